@@ -17,10 +17,7 @@ fn main() {
     rouille::start_server("localhost:8000", move |request| {
         rouille::proxy::full_proxy(
             &request,
-            rouille::proxy::ProxyConfig {
-                addr: "example.com:80",
-                replace_host: Some("example.com".into()),
-            },
+            rouille::proxy::ProxyConfig::new("example.com:80").replace_host("example.com"),
         )
         .unwrap()
     });
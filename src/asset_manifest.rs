@@ -0,0 +1,247 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json;
+use sha1::Sha1;
+
+use Request;
+
+/// Where the hash of the content of an asset is put in its URL.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fingerprint {
+    /// In the name of the file, before the extension, for example `app-9f2c1a3b.css`.
+    Filename,
+    /// In the `v` parameter of the query string, for example `app.css?v=9f2c1a3b`.
+    Query,
+}
+
+/// Maps the assets of a directory to URLs that change whenever their content changes.
+///
+/// Since a fingerprinted URL always designates the same content, the browsers can cache it
+/// forever. When an `AssetManifest` is passed to
+/// [`match_assets_with_options`](fn.match_assets_with_options.html) through the `manifest`
+/// field of `AssetsOptions`, the requests for the fingerprinted URLs are answered with the
+/// original files and a far-future `Cache-Control` header.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use rouille::{AssetManifest, AssetsOptions, Fingerprint, Response};
+///
+/// let manifest = AssetManifest::from_directory("public", "/static", Fingerprint::Filename)
+///     .unwrap();
+/// let manifest = Arc::new(manifest);
+/// let options = AssetsOptions {
+///     manifest: Some(manifest.clone()),
+///     ..AssetsOptions::default()
+/// };
+///
+/// rouille::start_server("localhost:8000", move |request| {
+///     if let Some(request) = request.remove_prefix("/static") {
+///         return rouille::match_assets_with_options(&request, "public", &options);
+///     }
+///
+///     // For example `<link rel="stylesheet" href="/static/app-9f2c1a3b.css">`.
+///     Response::html(format!(
+///         r#"<link rel="stylesheet" href="{}">"#,
+///         manifest.asset_url("app.css")
+///     ))
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetManifest {
+    // Prepended to the URLs, without a trailing slash.
+    prefix: String,
+    // Path of an asset relative to the root, such as `css/app.css`, to its fingerprinted URL
+    // relative to the root, such as `css/app-9f2c1a3b.css` or `css/app.css?v=9f2c1a3b`.
+    urls: HashMap<String, String>,
+    // Fingerprinted path relative to the root, to the path of the file relative to the root.
+    files: HashMap<String, String>,
+}
+
+// Number of hexadecimal digits of the hash that are put in the URLs.
+const HASH_LEN: usize = 10;
+
+impl AssetManifest {
+    /// Builds a manifest by hashing the content of all the files in `directory` and its
+    /// subdirectories. The files whose name starts with a dot are ignored.
+    ///
+    /// `prefix` is the route under which the assets are served, such as `/static`.
+    ///
+    /// The manifest must be rebuilt whenever the files are modified.
+    pub fn from_directory<P>(
+        directory: P,
+        prefix: &str,
+        fingerprint: Fingerprint,
+    ) -> io::Result<AssetManifest>
+    where
+        P: AsRef<Path>,
+    {
+        let mut manifest = AssetManifest::empty(prefix);
+        let mut pending = vec![(directory.as_ref().to_path_buf(), String::new())];
+        while let Some((directory, relative)) = pending.pop() {
+            for entry in fs::read_dir(&directory)? {
+                let entry = entry?;
+                let name = match entry.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+                if name.starts_with('.') {
+                    continue;
+                }
+
+                let path = format!("{}{}", relative, name);
+                let metadata = fs::metadata(entry.path())?;
+                if metadata.is_dir() {
+                    pending.push((entry.path(), format!("{}/", path)));
+                } else if metadata.is_file() {
+                    let mut hash = Sha1::new();
+                    hash.update(&fs::read(entry.path())?);
+                    let hash = hash.digest().to_string();
+                    manifest.insert(path, &hash[..HASH_LEN], fingerprint);
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Builds a manifest from the JSON object that maps the original paths of the assets to
+    /// their fingerprinted paths, such as the manifests generated by the bundlers:
+    /// `{"app.css": "app-9f2c1a3b.css"}`.
+    ///
+    /// The fingerprinted files are expected to exist in the directory of the assets, under
+    /// their fingerprinted name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::AssetManifest;
+    ///
+    /// let manifest = AssetManifest::from_json(r#"{"app.css": "app-9f2c1.css"}"#, "/static")
+    ///     .unwrap();
+    /// assert_eq!(manifest.asset_url("app.css"), "/static/app-9f2c1.css");
+    /// assert_eq!(manifest.asset_url("unknown.js"), "/static/unknown.js");
+    /// ```
+    pub fn from_json(json: &str, prefix: &str) -> Result<AssetManifest, serde_json::Error> {
+        let entries: HashMap<String, String> = serde_json::from_str(json)?;
+        let mut manifest = AssetManifest::empty(prefix);
+        for (original, fingerprinted) in entries {
+            let original = original.trim_start_matches('/').to_owned();
+            let fingerprinted = fingerprinted.trim_start_matches('/').to_owned();
+            manifest
+                .files
+                .insert(fingerprinted.clone(), fingerprinted.clone());
+            manifest.urls.insert(original, fingerprinted);
+        }
+        Ok(manifest)
+    }
+
+    fn empty(prefix: &str) -> AssetManifest {
+        AssetManifest {
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            urls: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, path: String, hash: &str, fingerprint: Fingerprint) {
+        match fingerprint {
+            Fingerprint::Filename => {
+                let fingerprinted = fingerprinted_name(&path, hash);
+                self.files.insert(fingerprinted.clone(), path.clone());
+                self.urls.insert(path, fingerprinted);
+            }
+            Fingerprint::Query => {
+                let url = format!("{}?v={}", path, hash);
+                self.urls.insert(path, url);
+            }
+        }
+    }
+
+    /// Returns the fingerprinted URL of an asset, given its path relative to the directory of
+    /// the assets.
+    ///
+    /// Returns the URL of the asset without fingerprint if it isn't in the manifest, so that a
+    /// missing asset doesn't prevent a page from being rendered.
+    pub fn asset_url(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        let url = self.urls.get(path).map(|u| &u[..]).unwrap_or(path);
+        format!("{}/{}", self.prefix, url)
+    }
+
+    // Returns the URL of the file to serve for a request whose prefix has been removed, and
+    // true if the request is for a fingerprinted URL.
+    pub(crate) fn resolve(&self, request: &Request) -> (String, bool) {
+        let url = request.url();
+        let path = url.trim_start_matches('/');
+        if let Some(file) = self.files.get(path) {
+            return (format!("/{}", file), true);
+        }
+
+        let fingerprinted = match (self.urls.get(path), request.get_param("v")) {
+            (Some(expected), Some(version)) => {
+                expected.ends_with(&format!("?v={}", version)) && !version.is_empty()
+            }
+            _ => false,
+        };
+        (url, fingerprinted)
+    }
+}
+
+// Inserts the hash before the extension of the name of a file.
+fn fingerprinted_name(path: &str, hash: &str) -> String {
+    let name_start = path.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}-{}{}", &path[..dot], hash, &path[dot..])
+        }
+        _ => format!("{}-{}", path, hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fingerprinted_name;
+    use super::AssetManifest;
+    use Request;
+
+    #[test]
+    fn names() {
+        assert_eq!(fingerprinted_name("app.css", "abc"), "app-abc.css");
+        assert_eq!(
+            fingerprinted_name("js/app.min.js", "abc"),
+            "js/app.min-abc.js"
+        );
+        assert_eq!(
+            fingerprinted_name("v1.0/LICENSE", "abc"),
+            "v1.0/LICENSE-abc"
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let manifest = AssetManifest::from_json(r#"{"/app.css": "/app-abc.css"}"#, "/s/").unwrap();
+        assert_eq!(manifest.asset_url("/app.css"), "/s/app-abc.css");
+
+        let request = Request::fake_http("GET", "/app-abc.css", vec![], vec![]);
+        assert_eq!(
+            manifest.resolve(&request),
+            ("/app-abc.css".to_owned(), true)
+        );
+        let request = Request::fake_http("GET", "/app.css", vec![], vec![]);
+        assert_eq!(manifest.resolve(&request), ("/app.css".to_owned(), false));
+    }
+}
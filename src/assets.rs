@@ -7,12 +7,27 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
 
+use chrono::DateTime;
+use chrono::Utc;
 use filetime;
-use time;
+use percent_encoding::utf8_percent_encode;
+use percent_encoding::AsciiSet;
+use percent_encoding::CONTROLS;
 
+use content_encoding;
+use input::parse_accept_header;
+
+use AssetManifest;
 use Request;
 use Response;
 
@@ -77,9 +92,112 @@ use Response;
 /// In this example, a request made to `/static/test.txt` will return the file
 /// `public/test.txt` if it exists.
 ///
+/// # Source maps
+///
+/// When serving a `.js` or `.css` file, if a file with the same name plus `.map` exists in the
+/// same directory then a `SourceMap` header pointing to it is added to the response. See also
+/// [the `minify_assets` function](fn.minify_assets.html).
+///
 pub fn match_assets<P: ?Sized>(request: &Request, path: &P) -> Response
 where
     P: AsRef<Path>,
+{
+    match_assets_with_options(request, path, &AssetsOptions::default())
+}
+
+/// Options of [`match_assets_with_options`](fn.match_assets_with_options.html).
+///
+/// # Example
+///
+/// ```no_run
+/// use rouille::AssetsOptions;
+///
+/// let options = AssetsOptions {
+///     precompressed: true,
+///     ..AssetsOptions::default()
+/// };
+///
+/// rouille::start_server("localhost:8000", move |request| {
+///     rouille::match_assets_with_options(&request, "public", &options)
+/// });
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AssetsOptions {
+    /// If true, serves the `.br` or `.gz` file next to the requested file, if there is one and
+    /// the client accepts its encoding, with the corresponding `Content-Encoding` header.
+    /// Defaults to `false`.
+    ///
+    /// The compressed files must be kept up to date with the original ones, for example by
+    /// generating them when building the assets.
+    pub precompressed: bool,
+
+    /// If true, requests for a directory are answered with the list of the files that it
+    /// contains, as HTML or as JSON. Defaults to `false`.
+    ///
+    /// The `sort` parameter of the query string sorts the list by `name`, `size` or `modified`,
+    /// and `order=desc` reverses the order. The list is in JSON if the query string contains
+    /// `format=json`, or if the client prefers `application/json` to `text/html`.
+    pub directory_listing: bool,
+
+    /// If true, the directory listings include the files whose name starts with a dot.
+    /// Defaults to `false`.
+    ///
+    /// This only affects the listings. The hidden files can still be requested by their name.
+    pub show_hidden: bool,
+
+    /// If true, the `GET` and `HEAD` requests that don't match a file are answered with the
+    /// `index.html` at the root of the directory, without redirecting, so that a single-page
+    /// application can handle its routes on the client side. Defaults to `false`.
+    ///
+    /// The URLs whose last segment contains a dot, such as `/missing.js`, still get a `404`,
+    /// since they are requests for assets rather than routes.
+    pub single_page_app: bool,
+
+    /// Content types to use for the given extensions, instead of the built-in ones. The keys are
+    /// the extensions in lowercase and without the dot, for example `mjs`.
+    pub mime_types: HashMap<String, String>,
+
+    /// Content type of the files whose extension is unknown. Defaults to
+    /// `application/octet-stream` if `None`.
+    pub default_content_type: Option<String>,
+
+    /// If true, adds an `X-Content-Type-Options: nosniff` header to the responses, so that
+    /// browsers don't guess the type of the files. Defaults to `false`.
+    pub nosniff: bool,
+
+    /// If true, the files and directories whose name starts with a dot, such as `.git` or
+    /// `.env`, are never served nor listed. Defaults to `false`.
+    ///
+    /// Both the URL and the path of the file after resolving the symbolic links are checked.
+    pub deny_dotfiles: bool,
+
+    /// If true, the symbolic links that point outside of the directory are followed. Defaults
+    /// to `false`, in which case the requests that resolve to a file outside of the directory
+    /// get a `404`, whatever the links that they go through.
+    ///
+    /// Even when this is enabled, a URL can't go up from the target of a link with `..`.
+    ///
+    /// The precompressed files, the `index.html` of `single_page_app` and the entries of the
+    /// directory listings are checked in the same way as the requested files, and with
+    /// `deny_dotfiles`.
+    pub follow_external_symlinks: bool,
+
+    /// If set, the fingerprinted URLs of the manifest are answered with the original files and a
+    /// `Cache-Control: public, max-age=31536000, immutable` header. Defaults to `None`.
+    pub manifest: Option<Arc<AssetManifest>>,
+}
+
+// `Cache-Control` of the fingerprinted assets, which can be cached for a year.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Same as [`match_assets`](fn.match_assets.html), but with options.
+pub fn match_assets_with_options<P>(
+    request: &Request,
+    path: &P,
+    options: &AssetsOptions,
+) -> Response
+where
+    P: ?Sized + AsRef<Path>,
 {
     let path = path.as_ref();
     let path = match path.canonicalize() {
@@ -87,67 +205,437 @@ where
         Err(_) => return Response::empty_404(),
     };
 
+    let (url, fingerprinted) = match options.manifest {
+        Some(ref manifest) => manifest.resolve(request),
+        None => (request.url(), false),
+    };
+
     // The potential location of the file on the disk.
     let potential_file = {
         // Clippy erroneously identifies this transform as a redundant clone
         #[allow(clippy::redundant_clone)]
         let mut path = path.to_path_buf();
-        for component in request.url().split('/') {
+        for component in url.split('/') {
             path.push(component);
         }
         path
     };
 
+    if options.deny_dotfiles && url.split('/').any(is_dotfile) {
+        return Response::empty_404();
+    }
+
     // We try to canonicalize the file. If this fails, then the file doesn't exist.
     let potential_file = match potential_file.canonicalize() {
         Ok(f) => f,
-        Err(_) => return Response::empty_404(),
+        Err(_) => return fallback(request, &path, options),
     };
 
     // Check that we're still within `path`. This should eliminate security issues with
-    // requests like `GET /../private_file`.
-    if !potential_file.starts_with(path) {
+    // requests like `GET /../private_file`. If the file is outside of `path` because of a
+    // symbolic link, the URL itself must not leave `path`, otherwise a request could go up from
+    // the target of the link.
+    let external = options.follow_external_symlinks && !url.split('/').any(|c| c == "..");
+    if !is_allowed(&path, &potential_file, external, options) {
         return Response::empty_404();
     }
 
     // Check that it's a file and not a directory.
     match fs::metadata(&potential_file) {
         Ok(ref m) if m.is_file() => (),
-        _ => return Response::empty_404(),
+        Ok(ref m) if m.is_dir() && options.directory_listing => {
+            return directory_listing(request, &path, &potential_file, options);
+        }
+        _ => return fallback(request, &path, options),
     };
 
+    let response = serve_file(request, &path, &potential_file, options);
+    if fingerprinted && (response.is_success() || response.status_code == 304) {
+        response.with_unique_header("Cache-Control", IMMUTABLE)
+    } else {
+        response
+    }
+}
+
+// Returns true for the components of a path that are hidden files. `.` and `..` aren't names of
+// files, but they are resolved by the canonicalization anyway.
+fn is_dotfile(component: &str) -> bool {
+    component.starts_with('.') && component != "." && component != ".."
+}
+
+// Returns true if `file`, a canonical path, can be served from `root`. A file outside of `root`
+// is only allowed if `external` is true, and a file inside of it is denied if a component of its
+// path is a dotfile and `deny_dotfiles` is enabled.
+fn is_allowed(root: &Path, file: &Path, external: bool, options: &AssetsOptions) -> bool {
+    match file.strip_prefix(root) {
+        // A symbolic link with a regular name can point to a dotfile.
+        Ok(relative) => {
+            !options.deny_dotfiles
+                || !relative
+                    .components()
+                    .any(|c| is_dotfile(&c.as_os_str().to_string_lossy()))
+        }
+        Err(_) => external,
+    }
+}
+
+// Canonicalizes `file`, a path built by rouille rather than taken from the URL, such as the
+// `index.html` or a precompressed variant, and returns it if it is a file that can be served from
+// `root`. Otherwise a symbolic link with an innocent name could serve any file of the host.
+fn resolve_file(root: &Path, file: &Path, options: &AssetsOptions) -> Option<PathBuf> {
+    let file = file.canonicalize().ok()?;
+    if is_allowed(root, &file, options.follow_external_symlinks, options) && file.is_file() {
+        Some(file)
+    } else {
+        None
+    }
+}
+
+// Answers a request that doesn't match any file, with the `index.html` of `root` if
+// `single_page_app` is enabled and the URL looks like a route of the application.
+fn fallback(request: &Request, root: &Path, options: &AssetsOptions) -> Response {
+    if !options.single_page_app || (request.method() != "GET" && request.method() != "HEAD") {
+        return Response::empty_404();
+    }
+
+    // The URLs whose last segment has an extension are requests for missing assets, which
+    // shouldn't get the HTML page.
+    let url = request.url();
+    let last_segment = url.rsplit('/').next().unwrap_or("");
+    if last_segment.contains('.') {
+        return Response::empty_404();
+    }
+
+    match resolve_file(root, &root.join("index.html"), options) {
+        Some(index) => serve_file(request, root, &index, options),
+        None => Response::empty_404(),
+    }
+}
+
+// Answers a request with the content of a file, which has already been checked to be allowed
+// within `root`.
+fn serve_file(
+    request: &Request,
+    root: &Path,
+    potential_file: &Path,
+    options: &AssetsOptions,
+) -> Response {
     let extension = potential_file.extension().and_then(|s| s.to_str());
 
-    let file = match fs::File::open(&potential_file) {
+    // The compressed files that exist next to the requested one.
+    let variants: Vec<_> = if options.precompressed {
+        PRECOMPRESSED
+            .iter()
+            .filter_map(|&(encoding, suffix)| {
+                let mut name = potential_file.file_name()?.to_os_string();
+                name.push(suffix);
+                let variant = resolve_file(root, &potential_file.with_file_name(name), options)?;
+                Some((encoding, variant))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let available: Vec<&str> = variants.iter().map(|v| v.0).collect();
+    let encoding = content_encoding::preferred_encoding(request, &available);
+    let served = match encoding {
+        Some(encoding) => variants
+            .iter()
+            .find(|v| v.0 == encoding)
+            .unwrap()
+            .1
+            .as_path(),
+        None => potential_file,
+    };
+
+    let (file, metadata) = match fs::File::open(served).and_then(|f| {
+        let m = f.metadata()?;
+        Ok((f, m))
+    }) {
         Ok(f) => f,
         Err(_) => return Response::empty_404(),
     };
 
-    let now =
-        time::OffsetDateTime::try_now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
-    let etag: String = (fs::metadata(&potential_file)
-        .map(|meta| filetime::FileTime::from_last_modification_time(&meta).unix_seconds() as u64)
-        .unwrap_or(now.nanosecond() as u64)
-        ^ 0xd3f4_0305_c9f8_e911_u64)
-        .to_string();
-
-    Response::from_file(extension_to_mime_impl(extension), file)
-        .with_etag(request, etag)
-        .with_public_cache(3600) // TODO: is this a good idea? what if the file is private?
+    // The ETag changes whenever the file is modified or changes size, and is different for each
+    // encoding.
+    let modified = filetime::FileTime::from_last_modification_time(&metadata);
+    let etag = format!(
+        "\"{:x}-{:x}-{:x}{}\"",
+        modified.unix_seconds(),
+        modified.nanoseconds(),
+        metadata.len(),
+        encoding.map(|e| format!("-{}", e)).unwrap_or_default()
+    );
+
+    let modified = if modified.unix_seconds() >= 0 {
+        Some(UNIX_EPOCH + Duration::new(modified.unix_seconds() as u64, modified.nanoseconds()))
+    } else {
+        None
+    };
+
+    let content_type = extension
+        .and_then(|e| options.mime_types.get(&e.to_ascii_lowercase()))
+        .map(|t| Cow::Owned(t.clone()))
+        .or_else(|| extension_to_mime_impl(extension).map(Cow::Borrowed))
+        .or_else(|| options.default_content_type.clone().map(Cow::Owned))
+        .unwrap_or(Cow::Borrowed("application/octet-stream"));
+
+    let mut response = Response::from_range_with_validators(
+        request,
+        content_type,
+        file,
+        metadata.len(),
+        Some(&etag),
+        modified,
+    )
+    .with_etag(request, etag)
+    .with_public_cache(3600); // TODO: is this a good idea? what if the file is private?
+    if let Some(modified) = modified {
+        response = response.with_last_modified(request, modified);
+    }
+    if let Some(encoding) = encoding {
+        response = response.with_unique_header("Content-Encoding", encoding);
+    }
+    if !variants.is_empty() {
+        response = response.with_vary("Accept-Encoding");
+    }
+    if options.nosniff {
+        response = response.with_unique_header("X-Content-Type-Options", "nosniff");
+    }
+
+    // If there is a source map next to a script or stylesheet, point the browser to it.
+    match (
+        extension,
+        potential_file.file_name().and_then(|n| n.to_str()),
+    ) {
+        (Some("js"), Some(name)) | (Some("css"), Some(name)) => {
+            let map_name = format!("{}.map", name);
+            if potential_file.with_file_name(&map_name).is_file() {
+                response.with_unique_header("SourceMap", map_name)
+            } else {
+                response
+            }
+        }
+        _ => response,
+    }
 }
 
 /// Returns the mime type of a file based on its extension, or `application/octet-stream` if the
 /// extension is unknown.
 #[inline]
 pub fn extension_to_mime(extension: &str) -> &'static str {
-    extension_to_mime_impl(Some(extension))
+    extension_to_mime_impl(Some(extension)).unwrap_or("application/octet-stream")
+}
+
+// Characters of a file name that are percent-encoded in the links of a listing.
+const NAME_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Serialize)]
+struct DirectoryEntry {
+    name: String,
+    directory: bool,
+    // `None` for directories.
+    size: Option<u64>,
+    // Seconds since the UNIX epoch.
+    modified: Option<u64>,
+}
+
+// Answers a request for `directory` with the list of its entries that can be served from
+// `root`.
+fn directory_listing(
+    request: &Request,
+    root: &Path,
+    directory: &Path,
+    options: &AssetsOptions,
+) -> Response {
+    // The links of the listing are relative to the URL of the directory, which must end with a
+    // slash. The URL requested by the client is checked rather than the URL of `request`, whose
+    // prefix may have been removed. The redirection is relative as well.
+    let raw_url = request.received_raw_url();
+    let raw_path = raw_url.split('?').next().unwrap_or("");
+    if !raw_path.ends_with('/') {
+        let name = raw_path.rsplit('/').next().unwrap_or("");
+        return match request.raw_query_string() {
+            "" => Response::redirect_301(format!("{}/", name)),
+            query => Response::redirect_301(format!("{}/?{}", name, query)),
+        };
+    }
+
+    let read_dir = match fs::read_dir(directory) {
+        Ok(r) => r,
+        Err(_) => return Response::empty_404(),
+    };
+
+    let mut entries = Vec::new();
+    for entry in read_dir.filter_map(|e| e.ok()) {
+        // Names that aren't valid UTF-8 couldn't be requested anyway.
+        let name = match entry.file_name().into_string() {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+        if (!options.show_hidden || options.deny_dotfiles) && name.starts_with('.') {
+            continue;
+        }
+        // Follows the symbolic links, and skips the broken ones and the ones that point to files
+        // that couldn't be served.
+        let target = match entry.path().canonicalize() {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        if !is_allowed(root, &target, options.follow_external_symlinks, options) {
+            continue;
+        }
+        let metadata = match fs::metadata(&target) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        entries.push(DirectoryEntry {
+            name,
+            directory: metadata.is_dir(),
+            size: if metadata.is_dir() {
+                None
+            } else {
+                Some(metadata.len())
+            },
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+        });
+    }
+
+    // The directories always come first.
+    let sort = request.get_param("sort");
+    let descending = request.get_param("order").as_deref() == Some("desc");
+    entries.sort_by(|a, b| {
+        let order = match sort.as_deref() {
+            Some("size") => a.size.cmp(&b.size),
+            Some("modified") => a.modified.cmp(&b.modified),
+            _ => Ordering::Equal,
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        let order = if descending { order.reverse() } else { order };
+        b.directory.cmp(&a.directory).then(order)
+    });
+
+    let json = match request.get_param("format") {
+        Some(format) => format == "json",
+        None => request.header("Accept").map_or(false, |accept| {
+            let accept = parse_accept_header(accept);
+            accept.quality("application/json") > accept.quality("text/html")
+        }),
+    };
+
+    let response = if json {
+        Response::json(&entries)
+    } else {
+        Response::html(listing_html(request, &entries, sort.as_deref(), descending))
+    };
+    let response = response.with_vary("Accept");
+    if options.nosniff {
+        response.with_unique_header("X-Content-Type-Options", "nosniff")
+    } else {
+        response
+    }
+}
+
+fn listing_html(
+    request: &Request,
+    entries: &[DirectoryEntry],
+    sort: Option<&str>,
+    descending: bool,
+) -> String {
+    let title = format!("Index of {}", escape_html(&request.url()));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n\
+         <h1>{0}</h1>\n<table>\n<thead><tr>",
+        title
+    );
+
+    // Clicking on the current column reverses the order.
+    for &(column, label) in &[("name", "Name"), ("size", "Size"), ("modified", "Modified")] {
+        let current = sort.unwrap_or("name") == column;
+        let order = if current && !descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        html.push_str(&format!(
+            "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+            column, order, label
+        ));
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+
+    if request.url() != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let slash = if entry.directory { "/" } else { "" };
+        let size = entry.size.map(|s| s.to_string()).unwrap_or_default();
+        let modified = entry
+            .modified
+            .map(|m| {
+                DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(m))
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            utf8_percent_encode(&entry.name, NAME_ENCODE_SET),
+            slash,
+            escape_html(&entry.name),
+            slash,
+            size,
+            modified
+        ));
+    }
+
+    html.push_str("</tbody>\n</table>\n</body></html>\n");
+    html
 }
 
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The encodings of the precompressed files, and the suffixes of their names, by order of
+// preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
 /// Returns the mime type of a file based on its extension.
-fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
+// Returns `None` if the extension is unknown.
+fn extension_to_mime_impl(extension: Option<&str>) -> Option<&'static str> {
     // List taken from https://github.com/cybergeek94/mime_guess/blob/master/src/mime_types.rs,
     // itself taken from a dead link.
-    match extension {
+    let mime = match extension {
         Some("323") => "text/h323; charset=utf8",
         Some("3g2") => "video/3gpp2",
         Some("3gp") => "video/3gpp",
@@ -379,7 +867,7 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("mak") => "text/plain; charset=utf8",
         Some("man") => "application/x-troff-man",
         Some("manifest") => "application/x-ms-manifest",
-        Some("map") => "text/plain; charset=utf8",
+        Some("map") => "application/json",
         Some("master") => "application/xml",
         Some("mda") => "application/msaccess",
         Some("mdb") => "application/x-msaccess",
@@ -715,6 +1203,472 @@ fn extension_to_mime_impl(extension: Option<&str>) -> &'static str {
         Some("xwd") => "image/x-xwindowdump",
         Some("z") => "application/x-compress",
         Some("zip") => "application/zip",
-        _ => "application/octet-stream",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_assets;
+    use super::match_assets_with_options;
+    use super::AssetsOptions;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use Request;
+
+    fn header<'a>(response: &'a ::Response, name: &str) -> &'a str {
+        &response.headers.iter().find(|h| h.0 == name).unwrap().1
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let dir = env::temp_dir().join(format!("rouille-assets-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "hello").unwrap();
+
+        let get = |headers: Vec<(&str, &str)>| {
+            let headers = headers
+                .into_iter()
+                .map(|(n, v)| (n.to_owned(), v.to_owned()))
+                .collect();
+            match_assets(&Request::fake_http("GET", "/a.txt", headers, vec![]), &dir)
+        };
+
+        let response = get(vec![]);
+        assert_eq!(response.status_code, 200);
+        let etag = header(&response, "ETag").to_owned();
+        let modified = header(&response, "Last-Modified").to_owned();
+        assert!(etag.starts_with('"') && etag.ends_with("-5\""));
+
+        assert_eq!(get(vec![("If-None-Match", &etag)]).status_code, 304);
+        assert_eq!(get(vec![("If-None-Match", "\"other\"")]).status_code, 200);
+        assert_eq!(get(vec![("If-Modified-Since", &modified)]).status_code, 304);
+        let old = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(get(vec![("If-Modified-Since", old)]).status_code, 200);
+        let response = get(vec![
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", &modified),
+        ]);
+        assert_eq!(response.status_code, 200);
+
+        let response = get(vec![("Range", "bytes=1-2")]);
+        assert_eq!(response.status_code, 206);
+        assert_eq!(header(&response, "Content-Range"), "bytes 1-2/5");
+        assert_eq!(header(&response, "Accept-Ranges"), "bytes");
+        let mut body = String::new();
+        let (mut reader, _) = response.data.into_reader_and_size();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "el");
+
+        let resume = get(vec![("Range", "bytes=1-2"), ("If-Range", &etag)]);
+        assert_eq!(resume.status_code, 206);
+        let resume = get(vec![("Range", "bytes=1-2"), ("If-Range", &modified)]);
+        assert_eq!(resume.status_code, 206);
+        let changed = get(vec![("Range", "bytes=1-2"), ("If-Range", "\"other\"")]);
+        assert_eq!(changed.status_code, 200);
+        assert_eq!(get(vec![("Range", "bytes=9-")]).status_code, 416);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn precompressed() {
+        let dir = env::temp_dir().join(format!("rouille-precompressed-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.css"), "plain").unwrap();
+        fs::write(dir.join("a.css.gz"), "gzip").unwrap();
+        fs::write(dir.join("a.css.br"), "br").unwrap();
+        fs::write(dir.join("b.css"), "plain").unwrap();
+
+        let options = AssetsOptions {
+            precompressed: true,
+            ..AssetsOptions::default()
+        };
+        let get = |url: &str, accept_encoding: Option<&str>| {
+            let headers = accept_encoding
+                .map(|v| ("Accept-Encoding".to_owned(), v.to_owned()))
+                .into_iter()
+                .collect();
+            let request = Request::fake_http("GET", url, headers, vec![]);
+            let response = match_assets_with_options(&request, &dir, &options);
+            let encoding = response
+                .headers
+                .iter()
+                .find(|h| h.0 == "Content-Encoding")
+                .map(|h| h.1.to_string());
+            let mut body = String::new();
+            let (mut reader, _) = response.data.into_reader_and_size();
+            reader.read_to_string(&mut body).unwrap();
+            (encoding, body)
+        };
+
+        assert_eq!(get("/a.css", None), (None, "plain".to_owned()));
+        let gzip = (Some("gzip".to_owned()), "gzip".to_owned());
+        assert_eq!(get("/a.css", Some("gzip, deflate")), gzip);
+        assert_eq!(get("/a.css", Some("br;q=0.5, gzip")), gzip);
+        let br = (Some("br".to_owned()), "br".to_owned());
+        assert_eq!(get("/a.css", Some("gzip, br")), br);
+        assert_eq!(get("/a.css", Some("*")), br);
+        assert_eq!(
+            get("/a.css", Some("br;q=0, gzip;q=0")),
+            (None, "plain".to_owned())
+        );
+        assert_eq!(
+            get("/a.css", Some("gzip;q=0.5, identity")),
+            (None, "plain".to_owned())
+        );
+        assert_eq!(get("/a.css", Some("identity;q=0, *")), br);
+        assert_eq!(get("/b.css", Some("gzip, br")), (None, "plain".to_owned()));
+
+        let request = Request::fake_http(
+            "GET",
+            "/a.css",
+            vec![("Accept-Encoding".to_owned(), "gzip".to_owned())],
+            vec![],
+        );
+        let response = match_assets_with_options(&request, &dir, &options);
+        assert_eq!(header(&response, "Content-Type"), "text/css; charset=utf8");
+        assert_eq!(header(&response, "Vary"), "Accept-Encoding");
+        assert!(header(&response, "ETag").ends_with("-gzip\""));
+        let response = match_assets(&request, &dir);
+        assert!(response.headers.iter().all(|h| h.0 != "Content-Encoding"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_listing() {
+        let dir = env::temp_dir().join(format!("rouille-listing-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("big <b>.txt"), "hello world").unwrap();
+        fs::write(dir.join("small.txt"), "hi").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+
+        let options = AssetsOptions {
+            directory_listing: true,
+            ..AssetsOptions::default()
+        };
+        let get = |url: &str, accept: &str| {
+            let headers = vec![("Accept".to_owned(), accept.to_owned())];
+            let request = Request::fake_http("GET", url, headers, vec![]);
+            match_assets_with_options(&request, &dir, &options)
+        };
+        let body = |response: ::Response| {
+            let mut body = String::new();
+            let (mut reader, _) = response.data.into_reader_and_size();
+            reader.read_to_string(&mut body).unwrap();
+            body
+        };
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(match_assets(&request, &dir).status_code, 404);
+
+        let response = get("/sub?a=b", "*/*");
+        assert_eq!(response.status_code, 301);
+        assert_eq!(header(&response, "Location"), "sub/?a=b");
+
+        // The redirection is built from the URL requested by the client, whose last segment may
+        // have been removed as a prefix.
+        let request = Request::fake_http("GET", "/static", vec![], vec![]);
+        for stripped in &[
+            request.remove_prefix("/static").unwrap(),
+            request.strip_path_prefix("/static").unwrap(),
+        ] {
+            let response = match_assets_with_options(stripped, &dir, &options);
+            assert_eq!(response.status_code, 301);
+            assert_eq!(header(&response, "Location"), "static/");
+        }
+        let request = Request::fake_http("GET", "/static/", vec![], vec![]);
+        let stripped = request.remove_prefix("/static").unwrap();
+        assert_eq!(
+            match_assets_with_options(&stripped, &dir, &options).status_code,
+            200
+        );
+
+        let html = body(get("/", "text/html"));
+        assert!(html.contains("<a href=\"sub/\">sub/</a>"));
+        assert!(html.contains("<a href=\"big%20%3Cb%3E.txt\">big &lt;b&gt;.txt</a>"));
+        assert!(!html.contains("hidden"));
+        assert!(!html.contains("../"));
+        assert!(body(get("/sub/", "text/html")).contains("../"));
+
+        let json = body(get("/?sort=size&order=desc", "application/json"));
+        let names: Vec<_> = json
+            .match_indices("\"name\"")
+            .map(|(i, _)| &json[i..])
+            .collect();
+        assert_eq!(names.len(), 3);
+        assert!(names[0].starts_with("\"name\":\"sub\""));
+        assert!(names[1].starts_with("\"name\":\"big <b>.txt\""));
+        assert!(names[2].starts_with("\"name\":\"small.txt\""));
+        assert!(json.contains("\"size\":11"));
+        assert!(body(get("/?format=json", "text/html")).starts_with('['));
+
+        let options = AssetsOptions {
+            directory_listing: true,
+            show_hidden: true,
+            ..AssetsOptions::default()
+        };
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let html = body(match_assets_with_options(&request, &dir, &options));
+        assert!(html.contains(".hidden"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn single_page_app() {
+        let dir = env::temp_dir().join(format!("rouille-spa-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("assets")).unwrap();
+        fs::write(dir.join("index.html"), "<app>").unwrap();
+        fs::write(dir.join("assets/app.js"), "js").unwrap();
+
+        let options = AssetsOptions {
+            single_page_app: true,
+            ..AssetsOptions::default()
+        };
+        let get = |method: &str, url: &str| {
+            let request = Request::fake_http(method, url, vec![], vec![]);
+            let response = match_assets_with_options(&request, &dir, &options);
+            let status = response.status_code;
+            let mut body = String::new();
+            let (mut reader, _) = response.data.into_reader_and_size();
+            reader.read_to_string(&mut body).unwrap();
+            (status, body)
+        };
+
+        assert_eq!(get("GET", "/assets/app.js"), (200, "js".to_owned()));
+        assert_eq!(get("GET", "/users/12/edit"), (200, "<app>".to_owned()));
+        assert_eq!(get("GET", "/assets"), (200, "<app>".to_owned()));
+        assert_eq!(get("GET", "/assets/missing.js").0, 404);
+        assert_eq!(get("POST", "/users").0, 404);
+
+        let request = Request::fake_http("GET", "/users", vec![], vec![]);
+        assert_eq!(match_assets(&request, &dir).status_code, 404);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fingerprinted() {
+        use std::sync::Arc;
+        use AssetManifest;
+        use Fingerprint;
+
+        let dir = env::temp_dir().join(format!("rouille-manifest-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(dir.join("css/app.css"), "body {}").unwrap();
+        fs::write(dir.join(".secret"), "").unwrap();
+
+        let manifest =
+            AssetManifest::from_directory(&dir, "/static", Fingerprint::Filename).unwrap();
+        let url = manifest.asset_url("css/app.css");
+        assert!(url.starts_with("/static/css/app-") && url.ends_with(".css"));
+        assert_eq!(manifest.asset_url(".secret"), "/static/.secret");
+
+        let options = AssetsOptions {
+            manifest: Some(Arc::new(manifest)),
+            ..AssetsOptions::default()
+        };
+        let get = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            match_assets_with_options(&request, &dir, &options)
+        };
+
+        let response = get(&url["/static".len()..]);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(header(&response, "Content-Type"), "text/css; charset=utf8");
+        assert_eq!(header(&response, "Cache-Control"), super::IMMUTABLE);
+        let response = get("/css/app.css");
+        assert_eq!(header(&response, "Cache-Control"), "public, max-age=3600");
+        assert_eq!(get("/css/app-0000000000.css").status_code, 404);
+
+        let manifest = AssetManifest::from_directory(&dir, "/static", Fingerprint::Query).unwrap();
+        let url = manifest.asset_url("css/app.css");
+        assert!(url.starts_with("/static/css/app.css?v="));
+        let options = AssetsOptions {
+            manifest: Some(Arc::new(manifest)),
+            ..AssetsOptions::default()
+        };
+        let get = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            match_assets_with_options(&request, &dir, &options)
+        };
+        let response = get(&url["/static".len()..]);
+        assert_eq!(header(&response, "Cache-Control"), super::IMMUTABLE);
+        let response = get("/css/app.css?v=stale");
+        assert_eq!(header(&response, "Cache-Control"), "public, max-age=3600");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mime_types() {
+        let dir = env::temp_dir().join(format!("rouille-mime-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in &["a.mjs", "a.WASM", "a.unknownext", "a.css"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let mut options = AssetsOptions {
+            default_content_type: Some("text/plain".to_owned()),
+            nosniff: true,
+            ..AssetsOptions::default()
+        };
+        options
+            .mime_types
+            .insert("mjs".to_owned(), "text/javascript".to_owned());
+        options
+            .mime_types
+            .insert("wasm".to_owned(), "application/x-custom".to_owned());
+
+        let get = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            match_assets_with_options(&request, &dir, &options)
+        };
+
+        assert_eq!(header(&get("/a.mjs"), "Content-Type"), "text/javascript");
+        assert_eq!(
+            header(&get("/a.WASM"), "Content-Type"),
+            "application/x-custom"
+        );
+        assert_eq!(header(&get("/a.unknownext"), "Content-Type"), "text/plain");
+        assert_eq!(
+            header(&get("/a.css"), "Content-Type"),
+            "text/css; charset=utf8"
+        );
+        assert_eq!(header(&get("/a.css"), "X-Content-Type-Options"), "nosniff");
+
+        let request = Request::fake_http("GET", "/a.unknownext", vec![], vec![]);
+        let response = match_assets(&request, &dir);
+        assert_eq!(
+            header(&response, "Content-Type"),
+            "application/octet-stream"
+        );
+        assert!(response
+            .headers
+            .iter()
+            .all(|h| h.0 != "X-Content-Type-Options"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinks_and_dotfiles() {
+        use std::os::unix::fs::symlink;
+
+        let base = env::temp_dir().join(format!("rouille-symlinks-{}", ::std::process::id()));
+        let dir = base.join("public");
+        let outside = base.join("outside");
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::create_dir_all(outside.join("sub")).unwrap();
+        fs::write(dir.join(".git/config"), "secret").unwrap();
+        fs::write(dir.join(".env"), "secret").unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(outside.join("b.txt"), "b").unwrap();
+        fs::write(outside.join("sub/c.txt"), "c").unwrap();
+        symlink(&outside, dir.join("external")).unwrap();
+        symlink(dir.join(".env"), dir.join("env.txt")).unwrap();
+
+        let status = |url: &str, options: &AssetsOptions| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            match_assets_with_options(&request, &dir, options).status_code
+        };
+
+        let default = AssetsOptions::default();
+        assert_eq!(status("/a.txt", &default), 200);
+        assert_eq!(status("/.env", &default), 200);
+        assert_eq!(status("/external/b.txt", &default), 404);
+
+        let strict = AssetsOptions {
+            deny_dotfiles: true,
+            ..AssetsOptions::default()
+        };
+        assert_eq!(status("/a.txt", &strict), 200);
+        assert_eq!(status("/.env", &strict), 404);
+        assert_eq!(status("/.git/config", &strict), 404);
+        assert_eq!(status("/env.txt", &strict), 404);
+        assert_eq!(status("/.git/../a.txt", &strict), 404);
+
+        let external = AssetsOptions {
+            follow_external_symlinks: true,
+            ..AssetsOptions::default()
+        };
+        assert_eq!(status("/external/b.txt", &external), 200);
+        assert_eq!(status("/external/sub/c.txt", &external), 200);
+        assert_eq!(status("/external/sub/../b.txt", &external), 404);
+        assert_eq!(status("/../outside/b.txt", &external), 404);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinked_variants() {
+        use std::os::unix::fs::symlink;
+
+        let base = env::temp_dir().join(format!("rouille-variants-{}", ::std::process::id()));
+        let dir = base.join("public");
+        let outside = base.join("outside");
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        fs::write(dir.join(".env"), "secret").unwrap();
+        fs::write(dir.join("app.js"), "app").unwrap();
+        fs::write(dir.join("style.css"), "style").unwrap();
+        symlink(outside.join("secret"), dir.join("app.js.gz")).unwrap();
+        symlink(dir.join(".env"), dir.join("style.css.br")).unwrap();
+        symlink(outside.join("secret"), dir.join("index.html")).unwrap();
+        symlink(outside.join("secret"), dir.join("link.txt")).unwrap();
+
+        let get = |url: &str, headers: Vec<(&str, &str)>, options: &AssetsOptions| {
+            let headers = headers
+                .into_iter()
+                .map(|(n, v)| (n.to_owned(), v.to_owned()))
+                .collect();
+            let request = Request::fake_http("GET", url, headers, vec![]);
+            let response = match_assets_with_options(&request, &dir, options);
+            let status = response.status_code;
+            let mut body = String::new();
+            let (mut reader, _) = response.data.into_reader_and_size();
+            reader.read_to_string(&mut body).unwrap();
+            (status, body)
+        };
+
+        let options = AssetsOptions {
+            precompressed: true,
+            single_page_app: true,
+            directory_listing: true,
+            deny_dotfiles: true,
+            ..AssetsOptions::default()
+        };
+        let gzip = vec![("Accept-Encoding", "gzip, br")];
+        assert_eq!(
+            get("/app.js", gzip.clone(), &options),
+            (200, "app".to_owned())
+        );
+        let style = get("/style.css", gzip.clone(), &options);
+        assert_eq!(style, (200, "style".to_owned()));
+        assert_eq!(get("/route", vec![], &options).0, 404);
+        let listing = get("/", vec![("Accept", "application/json")], &options).1;
+        assert!(listing.contains("app.js"));
+        assert!(!listing.contains("link.txt"));
+        assert!(!listing.contains("app.js.gz"));
+        assert!(!listing.contains("style.css.br"));
+
+        let external = AssetsOptions {
+            follow_external_symlinks: true,
+            ..options
+        };
+        let app = get("/app.js", gzip, &external);
+        assert_eq!(app, (200, "secret".to_owned()));
+        assert_eq!(get("/route", vec![], &external), (200, "secret".to_owned()));
+        let listing = get("/", vec![("Accept", "application/json")], &external).1;
+        assert!(listing.contains("link.txt"));
+
+        fs::remove_dir_all(&base).unwrap();
     }
 }
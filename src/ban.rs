@@ -0,0 +1,657 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Banning abusive clients for a period of time.
+//!
+//! A [`BanList`](struct.BanList.html) keeps track of the IP addresses and networks that are not
+//! allowed to access the server. Bans expire automatically. When enough addresses of the same
+//! network are banned, they are replaced with a single ban of the whole network.
+//!
+//! The ban list can be fed from several places, for example by a
+//! [`RateLimiter`](../rate_limit/struct.RateLimiter.html#method.with_ban_list) or directly by your
+//! handlers, and is enforced by using it as a [`Middleware`](../middleware/trait.Middleware.html).
+//! The bans are stored in a [`BanStore`](trait.BanStore.html), which by default keeps them in
+//! memory.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use rouille::{Request, Response};
+//! use rouille::ban::{self, BanList};
+//! use rouille::middleware::Pipeline;
+//!
+//! let bans = Arc::new(BanList::new());
+//!
+//! let handler_bans = bans.clone();
+//! let pipeline = Pipeline::new(move |request: &Request| {
+//!     if let Some(request) = request.remove_prefix("/admin/bans") {
+//!         return ban::admin(&request, &handler_bans);
+//!     }
+//!
+//!     if request.url() == "/wp-login.php" {
+//!         let _ = handler_bans.ban(request.remote_addr().ip(), Duration::from_secs(3600),
+//!                                  "honeypot");
+//!         return Response::empty_404();
+//!     }
+//!
+//!     Response::text("hello world")
+//! })
+//! .with(bans);
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| pipeline.handle(request));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use middleware::Middleware;
+use Request;
+use Response;
+
+/// An IP network, in other words an address and a prefix length, such as `192.168.0.0/16`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::ban::IpNetwork;
+///
+/// let network: IpNetwork = "10.1.0.0/16".parse().unwrap();
+/// assert!(network.contains("10.1.2.3".parse().unwrap()));
+/// assert!(!network.contains("10.2.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Builds a network from an address and a prefix length. The bits of the address beyond the
+    /// prefix are set to zero.
+    ///
+    /// Returns `None` if the prefix is larger than 32 for IPv4 or 128 for IPv6.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<IpNetwork> {
+        let addr = match addr {
+            IpAddr::V4(addr) if prefix <= 32 => {
+                let mask = u32::max_value()
+                    .checked_shl(32 - u32::from(prefix))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(addr) & mask))
+            }
+            IpAddr::V6(addr) if prefix <= 128 => {
+                let mask = u128::max_value()
+                    .checked_shl(128 - u32::from(prefix))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask))
+            }
+            _ => return None,
+        };
+
+        Some(IpNetwork { addr, prefix })
+    }
+
+    /// Builds a network that contains a single address.
+    #[inline]
+    pub fn host(addr: IpAddr) -> IpNetwork {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        IpNetwork { addr, prefix }
+    }
+
+    /// Returns the first address of the network.
+    #[inline]
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Returns the length of the prefix.
+    #[inline]
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Returns true if the network contains a single address.
+    #[inline]
+    pub fn is_host(&self) -> bool {
+        *self == IpNetwork::host(self.addr)
+    }
+
+    /// Returns true if `addr` is part of the network.
+    #[inline]
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        IpNetwork::new(addr, self.prefix) == Some(*self)
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl From<IpAddr> for IpNetwork {
+    #[inline]
+    fn from(addr: IpAddr) -> IpNetwork {
+        IpNetwork::host(addr)
+    }
+}
+
+/// Error that can happen when parsing an `IpNetwork`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IpNetworkParseError;
+
+impl error::Error for IpNetworkParseError {}
+
+impl fmt::Display for IpNetworkParseError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "invalid IP network")
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = IpNetworkParseError;
+
+    /// Parses either an address followed with `/<prefix>`, or a single address.
+    fn from_str(s: &str) -> Result<IpNetwork, IpNetworkParseError> {
+        let mut split = s.splitn(2, '/');
+        let addr = match split.next().unwrap_or("").parse::<IpAddr>() {
+            Ok(a) => a,
+            Err(_) => return Err(IpNetworkParseError),
+        };
+
+        match split.next() {
+            None => Ok(IpNetwork::host(addr)),
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) => IpNetwork::new(addr, prefix).ok_or(IpNetworkParseError),
+                Err(_) => Err(IpNetworkParseError),
+            },
+        }
+    }
+}
+
+/// A banned address or network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    /// The banned network.
+    pub network: IpNetwork,
+    /// Why the network was banned, for example `rate-limit` or `honeypot`.
+    pub reason: String,
+    /// When the ban expires.
+    pub expires_at: SystemTime,
+}
+
+impl Ban {
+    /// Returns true if the ban has expired at time `now`.
+    #[inline]
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Storage for the bans of a `BanList`.
+///
+/// Implement this trait in order to share bans between multiple servers, or to keep them across
+/// restarts.
+pub trait BanStore: Send + Sync {
+    /// Stores a ban, replacing any existing ban of the same network.
+    fn insert(&self, ban: Ban) -> io::Result<()>;
+
+    /// Removes the ban of a network. Returns `false` if it wasn't banned.
+    fn remove(&self, network: &IpNetwork) -> io::Result<bool>;
+
+    /// Returns all the stored bans, including the expired ones that haven't been removed yet.
+    fn list(&self) -> io::Result<Vec<Ban>>;
+
+    /// Returns a ban whose network contains `addr`, if any.
+    ///
+    /// The default implementation calls `list`.
+    fn find(&self, addr: IpAddr) -> io::Result<Option<Ban>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .find(|ban| ban.network.contains(addr)))
+    }
+}
+
+/// A `BanStore` that keeps the bans in memory.
+#[derive(Debug, Default)]
+pub struct MemoryBanStore {
+    bans: Mutex<Vec<Ban>>,
+}
+
+impl MemoryBanStore {
+    /// Builds a new empty store.
+    #[inline]
+    pub fn new() -> MemoryBanStore {
+        MemoryBanStore::default()
+    }
+}
+
+impl BanStore for MemoryBanStore {
+    fn insert(&self, ban: Ban) -> io::Result<()> {
+        let mut bans = self.bans.lock().unwrap();
+        bans.retain(|b| b.network != ban.network);
+        bans.push(ban);
+        Ok(())
+    }
+
+    fn remove(&self, network: &IpNetwork) -> io::Result<bool> {
+        let mut bans = self.bans.lock().unwrap();
+        let len = bans.len();
+        bans.retain(|b| b.network != *network);
+        Ok(bans.len() != len)
+    }
+
+    fn list(&self) -> io::Result<Vec<Ban>> {
+        Ok(self.bans.lock().unwrap().clone())
+    }
+
+    fn find(&self, addr: IpAddr) -> io::Result<Option<Ban>> {
+        let bans = self.bans.lock().unwrap();
+        Ok(bans.iter().find(|ban| ban.network.contains(addr)).cloned())
+    }
+}
+
+/// Configuration of the aggregation of bans into network bans.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AggregationConfig {
+    /// Number of banned addresses of the same network above which the whole network is banned.
+    /// `0` disables aggregation. Defaults to `8`.
+    pub threshold: usize,
+    /// Prefix length of the networks of IPv4 addresses. Defaults to `24`.
+    pub ipv4_prefix: u8,
+    /// Prefix length of the networks of IPv6 addresses. Defaults to `64`.
+    pub ipv6_prefix: u8,
+}
+
+impl Default for AggregationConfig {
+    #[inline]
+    fn default() -> AggregationConfig {
+        AggregationConfig {
+            threshold: 8,
+            ipv4_prefix: 24,
+            ipv6_prefix: 64,
+        }
+    }
+}
+
+/// List of banned addresses and networks.
+///
+/// See [the module-level documentation](index.html) for an overview.
+pub struct BanList {
+    store: Box<dyn BanStore>,
+    aggregation: AggregationConfig,
+}
+
+impl BanList {
+    /// Builds a new `BanList` that stores the bans in memory.
+    #[inline]
+    pub fn new() -> BanList {
+        BanList::with_store(MemoryBanStore::new())
+    }
+
+    /// Builds a new `BanList` that stores the bans in `store`.
+    pub fn with_store<S>(store: S) -> BanList
+    where
+        S: BanStore + 'static,
+    {
+        BanList {
+            store: Box::new(store),
+            aggregation: AggregationConfig::default(),
+        }
+    }
+
+    /// Sets how bans of individual addresses are aggregated into bans of networks.
+    #[inline]
+    pub fn aggregation(mut self, config: AggregationConfig) -> BanList {
+        self.aggregation = config;
+        self
+    }
+
+    /// Bans an address or a network for the given duration.
+    ///
+    /// If the network is already banned, its ban is replaced, unless the existing ban lasts
+    /// longer.
+    pub fn ban<N, R>(&self, network: N, duration: Duration, reason: R) -> io::Result<()>
+    where
+        N: Into<IpNetwork>,
+        R: Into<String>,
+    {
+        let network = network.into();
+        let now = SystemTime::now();
+        let mut ban = Ban {
+            network,
+            reason: reason.into(),
+            expires_at: now + duration,
+        };
+
+        let bans = self.store.list()?;
+        if let Some(existing) = bans.iter().find(|b| b.network == network) {
+            if existing.expires_at > ban.expires_at {
+                ban.expires_at = existing.expires_at;
+            }
+        }
+        self.store.insert(ban)?;
+
+        if network.is_host() {
+            self.aggregate(network.addr(), now)?;
+        }
+
+        Ok(())
+    }
+
+    // Replaces the bans of the addresses of the network of `addr` with a single ban if there
+    // are enough of them.
+    fn aggregate(&self, addr: IpAddr, now: SystemTime) -> io::Result<()> {
+        if self.aggregation.threshold == 0 {
+            return Ok(());
+        }
+
+        let prefix = if addr.is_ipv4() {
+            self.aggregation.ipv4_prefix
+        } else {
+            self.aggregation.ipv6_prefix
+        };
+        let network = match IpNetwork::new(addr, prefix) {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+
+        let hosts = self
+            .store
+            .list()?
+            .into_iter()
+            .filter(|b| b.network.is_host() && !b.is_expired(now))
+            .filter(|b| network.contains(b.network.addr()))
+            .collect::<Vec<_>>();
+        if hosts.len() < self.aggregation.threshold {
+            return Ok(());
+        }
+
+        let expires_at = hosts.iter().map(|b| b.expires_at).max().unwrap_or(now);
+        self.store.insert(Ban {
+            network,
+            reason: format!("aggregated from {} addresses", hosts.len()),
+            expires_at,
+        })?;
+        for host in &hosts {
+            self.store.remove(&host.network)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the ban that applies to `addr`, if any.
+    pub fn find(&self, addr: IpAddr) -> io::Result<Option<Ban>> {
+        match self.store.find(addr)? {
+            Some(ref ban) if ban.is_expired(SystemTime::now()) => {
+                if !self.store.remove(&ban.network)? {
+                    return Ok(None);
+                }
+                // Another ban of a wider network may still apply.
+                self.find(addr)
+            }
+            ban => Ok(ban),
+        }
+    }
+
+    /// Returns true if `addr` is currently banned.
+    #[inline]
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.find(addr).ok().and_then(|b| b).is_some()
+    }
+
+    /// Lifts the ban of a network. Returns `false` if it wasn't banned.
+    #[inline]
+    pub fn unban<N>(&self, network: N) -> io::Result<bool>
+    where
+        N: Into<IpNetwork>,
+    {
+        self.store.remove(&network.into())
+    }
+
+    /// Returns the bans that haven't expired yet.
+    pub fn list(&self) -> io::Result<Vec<Ban>> {
+        let now = SystemTime::now();
+        Ok(self
+            .store
+            .list()?
+            .into_iter()
+            .filter(|b| !b.is_expired(now))
+            .collect())
+    }
+
+    /// Removes the expired bans from the store.
+    pub fn purge_expired(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+        for ban in self.store.list()? {
+            if ban.is_expired(now) {
+                self.store.remove(&ban.network)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for BanList {
+    #[inline]
+    fn default() -> BanList {
+        BanList::new()
+    }
+}
+
+impl fmt::Debug for BanList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BanList")
+            .field("aggregation", &self.aggregation)
+            .finish()
+    }
+}
+
+/// Answers `403 Forbidden` to the requests of banned clients.
+///
+/// If the store returns an error, the request is let through.
+impl Middleware for BanList {
+    fn before(&self, request: &Request) -> Option<Response> {
+        if self.is_banned(request.client_addr()) {
+            Some(Response::text("Forbidden").with_status_code(403))
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for Arc<BanList> {
+    #[inline]
+    fn before(&self, request: &Request) -> Option<Response> {
+        (**self).before(request)
+    }
+}
+
+#[derive(Serialize)]
+struct BanJson {
+    network: String,
+    reason: String,
+    expires_in_secs: u64,
+}
+
+/// Handles the requests of an administration API of the ban list.
+///
+/// - `GET /` returns the list of active bans as JSON.
+/// - `DELETE /?network=<network>` lifts the ban of a network, and returns `404` if it wasn't
+///   banned.
+///
+/// Use `Request::remove_prefix` in order to mount this API under a route, and make sure that
+/// only administrators can access it.
+pub fn admin(request: &Request, bans: &BanList) -> Response {
+    if request.url() != "/" && !request.url().is_empty() {
+        return Response::empty_404();
+    }
+
+    match request.method() {
+        "GET" => {
+            let now = SystemTime::now();
+            let list = match bans.list() {
+                Ok(l) => l,
+                Err(_) => return Response::text("").with_status_code(500),
+            };
+            let list = list
+                .into_iter()
+                .map(|ban| BanJson {
+                    network: ban.network.to_string(),
+                    reason: ban.reason,
+                    expires_in_secs: ban
+                        .expires_at
+                        .duration_since(now)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0),
+                })
+                .collect::<Vec<_>>();
+            Response::json(&list)
+        }
+        "DELETE" => {
+            let network = match request.get_param("network").map(|n| n.parse::<IpNetwork>()) {
+                Some(Ok(n)) => n,
+                _ => return Response::empty_400(),
+            };
+            match bans.unban(network) {
+                Ok(true) => Response::empty_204(),
+                Ok(false) => Response::empty_404(),
+                Err(_) => Response::text("").with_status_code(500),
+            }
+        }
+        _ => Response::text("").with_status_code(405),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::admin;
+    use super::AggregationConfig;
+    use super::BanList;
+    use super::IpNetwork;
+    use middleware::Pipeline;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use Request;
+    use Response;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn network_parse() {
+        let network: IpNetwork = "192.168.12.34/16".parse().unwrap();
+        assert_eq!(network.to_string(), "192.168.0.0/16");
+        assert!(network.contains(ip("192.168.200.1")));
+        assert!(!network.contains(ip("192.169.0.1")));
+        assert!(!network.contains(ip("::1")));
+
+        let network: IpNetwork = "2001:db8::1".parse().unwrap();
+        assert!(network.is_host());
+        assert_eq!(network.to_string(), "2001:db8::1/128");
+
+        let network: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(network.contains(ip("8.8.8.8")));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("hello".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn ban_and_expire() {
+        let bans = BanList::new();
+        bans.ban(ip("10.0.0.1"), Duration::from_secs(60), "test")
+            .unwrap();
+        bans.ban(ip("10.0.0.2"), Duration::from_secs(0), "test")
+            .unwrap();
+
+        assert!(bans.is_banned(ip("10.0.0.1")));
+        assert!(!bans.is_banned(ip("10.0.0.2")));
+        assert!(!bans.is_banned(ip("10.0.0.3")));
+        assert_eq!(bans.list().unwrap().len(), 1);
+
+        assert!(bans.unban(ip("10.0.0.1")).unwrap());
+        assert!(!bans.is_banned(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn aggregation() {
+        let bans = BanList::new().aggregation(AggregationConfig {
+            threshold: 3,
+            ..AggregationConfig::default()
+        });
+
+        for addr in &["10.0.0.1", "10.0.0.2", "10.0.1.1"] {
+            bans.ban(ip(addr), Duration::from_secs(60), "test").unwrap();
+        }
+        assert!(!bans.is_banned(ip("10.0.0.3")));
+
+        bans.ban(ip("10.0.0.4"), Duration::from_secs(60), "test")
+            .unwrap();
+        assert!(bans.is_banned(ip("10.0.0.3")));
+
+        let list = bans.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list
+            .iter()
+            .any(|b| b.network == "10.0.0.0/24".parse().unwrap()));
+    }
+
+    #[test]
+    fn middleware_and_admin() {
+        let bans = BanList::new();
+        bans.ban(ip("127.0.0.1"), Duration::from_secs(60), "test")
+            .unwrap();
+
+        let response = admin(&Request::fake_http("GET", "/", vec![], vec![]), &bans);
+        assert_eq!(response.status_code, 200);
+
+        let pipeline = Pipeline::new(|_: &Request| Response::text("ok")).with(bans);
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(pipeline.handle(&request).status_code, 403);
+
+        let other = Request::fake_http_from(
+            "127.0.0.2:1000".parse().unwrap(),
+            "GET",
+            "/",
+            vec![],
+            vec![],
+        );
+        assert_eq!(pipeline.handle(&other).status_code, 200);
+    }
+
+    #[test]
+    fn admin_unban() {
+        let bans = BanList::new();
+        bans.ban(
+            "10.0.0.0/8".parse::<IpNetwork>().unwrap(),
+            Duration::from_secs(60),
+            "test",
+        )
+        .unwrap();
+
+        let request = Request::fake_http("DELETE", "/?network=10.0.0.0%2F8", vec![], vec![]);
+        assert_eq!(admin(&request, &bans).status_code, 204);
+        assert_eq!(admin(&request, &bans).status_code, 404);
+        assert!(!bans.is_banned(ip("10.1.2.3")));
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Storage of binary objects identified by a key.
+//!
+//! The [`BlobStore`](trait.BlobStore.html) trait abstracts over where objects are stored, so that
+//! handlers such as the S3-compatible gateway of the `s3` module can be used with your own
+//! backend. [`MemoryBlobStore`](struct.MemoryBlobStore.html) is a simple implementation that
+//! keeps everything in memory.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Storage of binary objects identified by a key.
+///
+/// Keys are arbitrary strings. Slashes have no special meaning, but are usually used to group
+/// objects together, and can be used as a prefix when listing.
+pub trait BlobStore: Send + Sync {
+    /// Returns the content of an object, or `None` if it doesn't exist.
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Creates an object, or replaces its content if it already exists.
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()>;
+
+    /// Deletes an object. Returns `false` if it didn't exist.
+    fn delete(&self, key: &str) -> io::Result<bool>;
+
+    /// Returns the objects whose key starts with `prefix`, ordered by key.
+    fn list(&self, prefix: &str) -> io::Result<Vec<BlobInfo>>;
+}
+
+/// Information about an object of a `BlobStore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobInfo {
+    /// Key of the object.
+    pub key: String,
+    /// Size of the object in bytes.
+    pub size: u64,
+    /// When the object was last written.
+    pub last_modified: SystemTime,
+}
+
+/// A `BlobStore` that keeps objects in memory.
+///
+/// # Example
+///
+/// ```
+/// use rouille::blob::{BlobStore, MemoryBlobStore};
+///
+/// let store = MemoryBlobStore::new();
+/// store.put("hello.txt", b"hello world".to_vec()).unwrap();
+/// assert_eq!(store.get("hello.txt").unwrap(), Some(b"hello world".to_vec()));
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore {
+    objects: Mutex<BTreeMap<String, (Vec<u8>, SystemTime)>>,
+}
+
+impl MemoryBlobStore {
+    /// Builds a new empty store.
+    #[inline]
+    pub fn new() -> MemoryBlobStore {
+        MemoryBlobStore::default()
+    }
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects.get(key).map(|entry| entry.0.clone()))
+    }
+
+    fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        let mut objects = self.objects.lock().unwrap();
+        objects.insert(key.to_owned(), (data, SystemTime::now()));
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<bool> {
+        let mut objects = self.objects.lock().unwrap();
+        Ok(objects.remove(key).is_some())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<BlobInfo>> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects
+            .range(prefix.to_owned()..)
+            .take_while(|&(key, _)| key.starts_with(prefix))
+            .map(|(key, entry)| BlobInfo {
+                key: key.clone(),
+                size: entry.0.len() as u64,
+                last_modified: entry.1,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlobStore;
+    use super::MemoryBlobStore;
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryBlobStore::new();
+        store.put("a/1", b"one".to_vec()).unwrap();
+        store.put("a/2", b"two".to_vec()).unwrap();
+        store.put("b", b"three".to_vec()).unwrap();
+
+        let keys = store
+            .list("a/")
+            .unwrap()
+            .into_iter()
+            .map(|info| info.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["a/1", "a/2"]);
+
+        assert!(store.delete("a/1").unwrap());
+        assert!(!store.delete("a/1").unwrap());
+        assert_eq!(store.get("a/1").unwrap(), None);
+        assert_eq!(store.list("").unwrap().len(), 2);
+    }
+}
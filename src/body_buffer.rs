@@ -0,0 +1,62 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::sync::Arc;
+
+/// Error returned by [`Request::buffer_body`](struct.Request.html#method.buffer_body).
+#[derive(Debug)]
+pub enum BufferBodyError {
+    /// The body was extracted with `data()` before being buffered.
+    BodyAlreadyExtracted,
+
+    /// The body is larger than the limit. The part of the body that has been read is lost.
+    LimitExceeded,
+
+    /// Could not read the body from the request.
+    IoError(IoError),
+}
+
+impl From<IoError> for BufferBodyError {
+    fn from(err: IoError) -> BufferBodyError {
+        BufferBodyError::IoError(err)
+    }
+}
+
+impl error::Error for BufferBodyError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            BufferBodyError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BufferBodyError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            BufferBodyError::BodyAlreadyExtracted => {
+                "the body of the request was already extracted"
+            }
+            BufferBodyError::LimitExceeded => "the limit to the number of bytes has been exceeded",
+            BufferBodyError::IoError(_) => "could not read the body from the request",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+// Stored in the extensions of a request whose body has been buffered, so that the requests that
+// share them, such as the ones returned by `remove_prefix`, see the buffer as well.
+#[derive(Clone)]
+pub(crate) struct BufferedBody(pub(crate) Arc<[u8]>);
@@ -0,0 +1,351 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Building and parsing the `Cache-Control` header.
+//!
+//! The `Cache-Control` header, defined in [RFC 7234](https://tools.ietf.org/html/rfc7234),
+//! tells the browsers and the proxies whether and for how long they can store a response.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::Response;
+//! use rouille::cache_control::CacheControl;
+//!
+//! let response = Response::text("body { color: red }")
+//!     .with_cache_control(CacheControl::new().public().max_age(31536000).immutable());
+//!
+//! let response = Response::text("latest news")
+//!     .with_cache_control(CacheControl::new().public().max_age(60).stale_while_revalidate(600))
+//!     .with_vary("Accept-Language");
+//! ```
+
+use std::fmt;
+
+/// The directives of a `Cache-Control` header. The durations are in seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// Any cache can store the response, even if it would normally not be cacheable.
+    pub public: bool,
+    /// Only the cache of the client can store the response.
+    pub private: bool,
+    /// The caches must validate the response with the server before using it.
+    pub no_cache: bool,
+    /// The response must not be stored at all.
+    pub no_store: bool,
+    /// The proxies must not transform the body of the response.
+    pub no_transform: bool,
+    /// Once stale, the response must be validated with the server before being used.
+    pub must_revalidate: bool,
+    /// Same as `must_revalidate`, but only for the shared caches.
+    pub proxy_revalidate: bool,
+    /// The response never changes while it is fresh, so the client doesn't need to revalidate it
+    /// when the user reloads the page.
+    pub immutable: bool,
+    /// How long the response stays fresh.
+    pub max_age: Option<u64>,
+    /// How long the response stays fresh in the shared caches, overriding `max_age`.
+    pub s_maxage: Option<u64>,
+    /// How long a stale response can still be used while it is revalidated in the background.
+    pub stale_while_revalidate: Option<u64>,
+    /// How long a stale response can still be used if the server answers with an error.
+    pub stale_if_error: Option<u64>,
+    /// The other directives, with their values if they have one. Names are in lowercase.
+    pub extensions: Vec<(String, Option<String>)>,
+}
+
+impl CacheControl {
+    /// Builds a `CacheControl` without any directive.
+    #[inline]
+    pub fn new() -> CacheControl {
+        CacheControl::default()
+    }
+
+    /// Sets the `public` directive.
+    #[inline]
+    pub fn public(mut self) -> CacheControl {
+        self.public = true;
+        self
+    }
+
+    /// Sets the `private` directive.
+    #[inline]
+    pub fn private(mut self) -> CacheControl {
+        self.private = true;
+        self
+    }
+
+    /// Sets the `no-cache` directive.
+    #[inline]
+    pub fn no_cache(mut self) -> CacheControl {
+        self.no_cache = true;
+        self
+    }
+
+    /// Sets the `no-store` directive.
+    #[inline]
+    pub fn no_store(mut self) -> CacheControl {
+        self.no_store = true;
+        self
+    }
+
+    /// Sets the `no-transform` directive.
+    #[inline]
+    pub fn no_transform(mut self) -> CacheControl {
+        self.no_transform = true;
+        self
+    }
+
+    /// Sets the `must-revalidate` directive.
+    #[inline]
+    pub fn must_revalidate(mut self) -> CacheControl {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Sets the `proxy-revalidate` directive.
+    #[inline]
+    pub fn proxy_revalidate(mut self) -> CacheControl {
+        self.proxy_revalidate = true;
+        self
+    }
+
+    /// Sets the `immutable` directive.
+    #[inline]
+    pub fn immutable(mut self) -> CacheControl {
+        self.immutable = true;
+        self
+    }
+
+    /// Sets the `max-age` directive, in seconds.
+    #[inline]
+    pub fn max_age(mut self, seconds: u64) -> CacheControl {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Sets the `s-maxage` directive, in seconds.
+    #[inline]
+    pub fn s_maxage(mut self, seconds: u64) -> CacheControl {
+        self.s_maxage = Some(seconds);
+        self
+    }
+
+    /// Sets the `stale-while-revalidate` directive, in seconds.
+    #[inline]
+    pub fn stale_while_revalidate(mut self, seconds: u64) -> CacheControl {
+        self.stale_while_revalidate = Some(seconds);
+        self
+    }
+
+    /// Sets the `stale-if-error` directive, in seconds.
+    #[inline]
+    pub fn stale_if_error(mut self, seconds: u64) -> CacheControl {
+        self.stale_if_error = Some(seconds);
+        self
+    }
+
+    /// Adds another directive, with an optional value.
+    #[inline]
+    pub fn extension<N>(mut self, name: N, value: Option<String>) -> CacheControl
+    where
+        N: Into<String>,
+    {
+        self.extensions.push((name.into().to_lowercase(), value));
+        self
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let mut directives: Vec<String> = Vec::new();
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+        for &(set, name) in &flags {
+            if set {
+                directives.push(name.to_owned());
+            }
+        }
+
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+        for &(seconds, name) in &durations {
+            if let Some(seconds) = seconds {
+                directives.push(format!("{}={}", name, seconds));
+            }
+        }
+
+        for (name, value) in &self.extensions {
+            match value {
+                Some(value) if !value.is_empty() && value.bytes().all(is_token_char) => {
+                    directives.push(format!("{}={}", name, value))
+                }
+                Some(value) => directives.push(format!(
+                    "{}=\"{}\"",
+                    name,
+                    value.replace('\\', "\\\\").replace('"', "\\\"")
+                )),
+                None => directives.push(name.clone()),
+            }
+        }
+
+        write!(fmt, "{}", directives.join(", "))
+    }
+}
+
+/// Parses the value of a `Cache-Control` header.
+///
+/// Like the caches do, the parsing is lenient: the directives that can't be understood, such as
+/// a `max-age` that isn't a number, are ignored.
+///
+/// # Example
+///
+/// ```
+/// use rouille::cache_control;
+///
+/// let cache_control = cache_control::parse("public, max-age=3600, community=\"UCI\"");
+/// assert!(cache_control.public);
+/// assert_eq!(cache_control.max_age, Some(3600));
+/// assert_eq!(cache_control.extensions, vec![("community".to_owned(), Some("UCI".to_owned()))]);
+/// ```
+pub fn parse(header: &str) -> CacheControl {
+    let mut cache_control = CacheControl::new();
+
+    for directive in split_directives(header) {
+        let mut parts = directive.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let value = parts.next().map(|v| unquote(v.trim()));
+        let seconds = value.as_ref().and_then(|v| v.parse().ok());
+
+        match &name[..] {
+            "" => (),
+            "public" => cache_control.public = true,
+            "private" => cache_control.private = true,
+            "no-cache" => cache_control.no_cache = true,
+            "no-store" => cache_control.no_store = true,
+            "no-transform" => cache_control.no_transform = true,
+            "must-revalidate" => cache_control.must_revalidate = true,
+            "proxy-revalidate" => cache_control.proxy_revalidate = true,
+            "immutable" => cache_control.immutable = true,
+            "max-age" => cache_control.max_age = seconds,
+            "s-maxage" => cache_control.s_maxage = seconds,
+            "stale-while-revalidate" => cache_control.stale_while_revalidate = seconds,
+            "stale-if-error" => cache_control.stale_if_error = seconds,
+            _ => cache_control.extensions.push((name, value)),
+        }
+    }
+
+    cache_control
+}
+
+// Splits the directives at the commas that aren't inside a quoted string.
+fn split_directives(header: &str) -> Vec<&str> {
+    let mut directives = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+
+    for (pos, c) in header.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                directives.push(&header[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+    directives.push(&header[start..]);
+
+    directives
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return value.to_owned();
+    }
+
+    let mut unquoted = String::with_capacity(value.len());
+    let mut escaped = false;
+    for c in value[1..value.len() - 1].chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+        escaped = false;
+        unquoted.push(c);
+    }
+    unquoted
+}
+
+fn is_token_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use super::CacheControl;
+
+    #[test]
+    fn format() {
+        assert_eq!(CacheControl::new().to_string(), "");
+        assert_eq!(
+            CacheControl::new()
+                .public()
+                .immutable()
+                .max_age(60)
+                .s_maxage(30)
+                .stale_while_revalidate(600)
+                .to_string(),
+            "public, immutable, max-age=60, s-maxage=30, stale-while-revalidate=600"
+        );
+        assert_eq!(
+            CacheControl::new()
+                .no_store()
+                .extension("Community", Some("U \"C\"".to_owned()))
+                .extension("x", Some("y".to_owned()))
+                .to_string(),
+            r#"no-store, community="U \"C\"", x=y"#
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let cache_control = CacheControl::new()
+            .private()
+            .no_cache()
+            .must_revalidate()
+            .max_age(0)
+            .stale_if_error(86400)
+            .extension("community", Some("a, b".to_owned()));
+        assert_eq!(parse(&cache_control.to_string()), cache_control);
+    }
+
+    #[test]
+    fn lenient() {
+        let cache_control = parse("NO-STORE,, max-age=abc ,s-maxage=\"10\"");
+        assert!(cache_control.no_store);
+        assert_eq!(cache_control.max_age, None);
+        assert_eq!(cache_control.s_maxage, Some(10));
+        assert!(cache_control.extensions.is_empty());
+    }
+}
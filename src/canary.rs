@@ -0,0 +1,283 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Sending part of the traffic to a canary release.
+//!
+//! A [`Canary`](struct.Canary.html) dispatches each request either to a *stable* handler or to a
+//! *canary* handler. The canary receives a percentage of the requests, and the requests that
+//! carry a given header. With a sticky cookie, a client keeps being sent to the same handler.
+//!
+//! The canary handler can be a local handler, or forward the requests to another server with
+//! the [`proxy`](../proxy/index.html) module.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rouille::{Request, Response};
+//! use rouille::canary::Canary;
+//! use rouille::proxy::{self, ProxyConfig};
+//!
+//! let canary = Canary::new(
+//!     |request: &Request| Response::text("stable"),
+//!     |request: &Request| {
+//!         let config = ProxyConfig::new("canary.internal:80");
+//!         proxy::full_proxy(request, config)
+//!             .unwrap_or_else(|_| Response::text("bad gateway").with_status_code(502))
+//!     },
+//! )
+//! .percentage(5.0)
+//! .header("X-Canary", "1")
+//! .sticky("canary", Duration::from_secs(24 * 3600));
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| canary.handle(request));
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use input;
+use Request;
+use Response;
+
+/// The handler that a request was dispatched to by a `Canary`.
+///
+/// The canary inserts this value in the [extensions](../struct.Request.html#method.extensions)
+/// of each request that it processes, so that a logging or metrics middleware can record it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Variant {
+    /// The stable handler.
+    Stable,
+    /// The canary handler.
+    Canary,
+}
+
+impl Variant {
+    fn as_str(self) -> &'static str {
+        match self {
+            Variant::Stable => "stable",
+            Variant::Canary => "canary",
+        }
+    }
+}
+
+/// Dispatches requests to a stable or a canary handler.
+pub struct Canary<S, C> {
+    stable: S,
+    canary: C,
+    percentage: f64,
+    header: Option<(String, String)>,
+    sticky: Option<(String, Duration)>,
+}
+
+impl<S, C> Canary<S, C>
+where
+    S: Fn(&Request) -> Response,
+    C: Fn(&Request) -> Response,
+{
+    /// Builds a `Canary` that sends all the requests to `stable` until configured otherwise.
+    pub fn new(stable: S, canary: C) -> Canary<S, C> {
+        Canary {
+            stable,
+            canary,
+            percentage: 0.0,
+            header: None,
+            sticky: None,
+        }
+    }
+
+    /// Sets the percentage of the requests, between 0 and 100, sent to the canary handler.
+    ///
+    /// The requests are assigned with the source of randomness of the request, as returned by
+    /// [`Request::random`](../struct.Request.html#method.random).
+    #[inline]
+    pub fn percentage(mut self, percentage: f64) -> Canary<S, C> {
+        self.percentage = percentage;
+        self
+    }
+
+    /// Sends the requests whose header `name` is equal to `value` to the canary handler,
+    /// regardless of the percentage and of the sticky cookie.
+    #[inline]
+    pub fn header<N, V>(mut self, name: N, value: V) -> Canary<S, C>
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.header = Some((name.into(), value.into()));
+        self
+    }
+
+    /// Remembers the handler assigned to each client in a cookie named `cookie_name` that
+    /// expires after `max_age`.
+    ///
+    /// The cookie contains either `stable` or `canary`. Setting it yourself makes it possible to
+    /// opt a client in or out of the canary.
+    #[inline]
+    pub fn sticky<N>(mut self, cookie_name: N, max_age: Duration) -> Canary<S, C>
+    where
+        N: Into<String>,
+    {
+        self.sticky = Some((cookie_name.into(), max_age));
+        self
+    }
+
+    /// Returns the handler that `request` should be dispatched to, and whether the sticky
+    /// cookie needs to be set.
+    fn assign(&self, request: &Request) -> (Variant, bool) {
+        if let Some((ref name, ref value)) = self.header {
+            if request.header(name) == Some(value.as_str()) {
+                return (Variant::Canary, false);
+            }
+        }
+
+        if let Some((ref cookie_name, _)) = self.sticky {
+            let cookie = input::cookies(request).find(|c| c.0 == cookie_name.as_str());
+            match cookie.map(|c| c.1) {
+                Some("canary") => return (Variant::Canary, false),
+                Some("stable") => return (Variant::Stable, false),
+                _ => (),
+            }
+        }
+
+        let draw = request.random().next_u64() as f64 / u64::max_value() as f64;
+        let variant = if draw * 100.0 < self.percentage {
+            Variant::Canary
+        } else {
+            Variant::Stable
+        };
+        (variant, self.sticky.is_some())
+    }
+
+    /// Processes a request by dispatching it to the stable or canary handler.
+    pub fn handle(&self, request: &Request) -> Response {
+        let (variant, set_cookie) = self.assign(request);
+        request.extensions().insert(variant);
+
+        let mut response = match variant {
+            Variant::Stable => (self.stable)(request),
+            Variant::Canary => (self.canary)(request),
+        };
+
+        if set_cookie {
+            if let Some((ref cookie_name, max_age)) = self.sticky {
+                let cookie = format!(
+                    "{}={}; Max-Age={}; Path=/; HttpOnly",
+                    cookie_name,
+                    variant.as_str(),
+                    max_age.as_secs()
+                );
+                response.headers.push(("Set-Cookie".into(), cookie.into()));
+            }
+        }
+
+        response
+    }
+}
+
+impl<S, C> fmt::Debug for Canary<S, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Canary")
+            .field("percentage", &self.percentage)
+            .field("header", &self.header)
+            .field("sticky", &self.sticky)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Canary;
+    use super::Variant;
+    use random::Random;
+    use std::time::Duration;
+    use Request;
+    use Response;
+
+    type Handler = fn(&Request) -> Response;
+
+    fn canary() -> Canary<Handler, Handler> {
+        fn stable(_: &Request) -> Response {
+            Response::text("stable")
+        }
+        fn canary(_: &Request) -> Response {
+            Response::text("canary")
+        }
+        Canary::new(stable as Handler, canary as Handler)
+    }
+
+    fn variant(request: &Request) -> Variant {
+        *request.extensions().get::<Variant>().unwrap()
+    }
+
+    fn set_cookie(response: &Response) -> Option<String> {
+        response
+            .headers
+            .iter()
+            .find(|h| h.0 == "Set-Cookie")
+            .map(|h| h.1.to_string())
+    }
+
+    #[test]
+    fn percentage() {
+        let handle = |canary: &Canary<_, _>| {
+            let request = Request::fake_http("GET", "/", vec![], vec![]);
+            request.extensions().insert(Random::seeded(1));
+            canary.handle(&request);
+            variant(&request)
+        };
+
+        assert_eq!(handle(&canary()), Variant::Stable);
+        assert_eq!(handle(&canary().percentage(100.0)), Variant::Canary);
+
+        let split = canary().percentage(50.0);
+        let canaries = (0..200)
+            .filter(|_| {
+                let request = Request::fake_http("GET", "/", vec![], vec![]);
+                split.handle(&request);
+                variant(&request) == Variant::Canary
+            })
+            .count();
+        assert!(canaries > 50 && canaries < 150);
+    }
+
+    #[test]
+    fn header_and_sticky_cookie() {
+        let canary = canary()
+            .header("X-Canary", "1")
+            .sticky("release", Duration::from_secs(60));
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = canary.handle(&request);
+        assert_eq!(
+            set_cookie(&response),
+            Some("release=stable; Max-Age=60; Path=/; HttpOnly".to_owned())
+        );
+
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("X-Canary".to_owned(), "1".to_owned())],
+            vec![],
+        );
+        let response = canary.handle(&request);
+        assert_eq!(variant(&request), Variant::Canary);
+        assert_eq!(set_cookie(&response), None);
+
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Cookie".to_owned(), "release=canary".to_owned())],
+            vec![],
+        );
+        let response = canary.handle(&request);
+        assert_eq!(variant(&request), Variant::Canary);
+        assert_eq!(set_cookie(&response), None);
+    }
+}
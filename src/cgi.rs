@@ -36,15 +36,48 @@
 //! It is therefore appropriate to simply call `.unwrap()` on that result. Any panic will be turned
 //! into an error 500 and add an entry to the logs, which is probably what you want when your
 //! server is misconfigured.
+//!
+//! # Options
+//!
+//! The `start_cgi_with_options` method takes a `CgiOptions` that controls the environment and
+//! the working directory of the program, limits its execution time, and redirects its error
+//! output to a log.
+//!
+//! ```no_run
+//! use std::io;
+//! use std::process::Command;
+//! use std::time::Duration;
+//! use rouille::cgi::{CgiOptions, CgiRun};
+//!
+//! let options = CgiOptions::new()
+//!     .inherit_env(vec!["PATH"])
+//!     .env("PHPRC", "/etc/php/cgi")
+//!     .current_dir("/var/www")
+//!     .timeout(Duration::from_secs(30))
+//!     .log_stderr(io::stderr);
+//!
+//! rouille::start_server("localhost:8080", move |request| {
+//!     Command::new("php-cgi").start_cgi_with_options(request, &options).unwrap()
+//! });
+//! ```
 
+use std::env;
 use std::error;
+use std::ffi::OsString;
 use std::fmt;
 use std::io;
 use std::io::BufRead;
 use std::io::Error as IoError;
 use std::io::Read;
+use std::io::Write;
+use std::path::PathBuf;
 use std::process::Command;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use Request;
 use Response;
@@ -90,6 +123,99 @@ impl fmt::Display for CgiError {
     }
 }
 
+type StderrFn = dyn Fn() -> Box<dyn Write> + Send + Sync;
+
+/// Options of the execution of a CGI program. See
+/// [`CgiRun::start_cgi_with_options`](trait.CgiRun.html#tymethod.start_cgi_with_options).
+#[derive(Default, Clone)]
+pub struct CgiOptions {
+    inherited_env: Option<Vec<OsString>>,
+    env: Vec<(OsString, OsString)>,
+    current_dir: Option<PathBuf>,
+    timeout: Option<Duration>,
+    stderr: Option<Arc<StderrFn>>,
+}
+
+impl CgiOptions {
+    /// Builds options that run the program like `start_cgi` does.
+    #[inline]
+    pub fn new() -> CgiOptions {
+        CgiOptions::default()
+    }
+
+    /// Only passes the given variables of the environment of the server to the program, instead
+    /// of all of them. The program still receives the CGI variables and the ones set with `env`.
+    ///
+    /// The variables that were set on the `Command` itself are discarded as well.
+    pub fn inherit_env<I, S>(mut self, names: I) -> CgiOptions
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.inherited_env = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets a variable in the environment of the program. It takes precedence over the CGI
+    /// variables with the same name.
+    #[inline]
+    pub fn env<K, V>(mut self, name: K, value: V) -> CgiOptions
+    where
+        K: Into<OsString>,
+        V: Into<OsString>,
+    {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the working directory of the program.
+    #[inline]
+    pub fn current_dir<P>(mut self, dir: P) -> CgiOptions
+    where
+        P: Into<PathBuf>,
+    {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Kills the program if it is still running after `timeout`.
+    ///
+    /// If the program didn't return all the headers of its response in time, a response with the
+    /// status code 504 is returned instead. Otherwise the body of the response stops where the
+    /// program was killed.
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> CgiOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Writes each line of the error output of the program to the writer returned by `output`,
+    /// prefixed with the method and the URL of the request, instead of letting the program write
+    /// to the error output of the server.
+    ///
+    /// Like with [`Logger`](../struct.Logger.html), `output` is called for each line.
+    pub fn log_stderr<F, W>(mut self, output: F) -> CgiOptions
+    where
+        F: Fn() -> W + Send + Sync + 'static,
+        W: Write + 'static,
+    {
+        self.stderr = Some(Arc::new(move || Box::new(output()) as Box<dyn Write>));
+        self
+    }
+}
+
+impl fmt::Debug for CgiOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CgiOptions")
+            .field("inherited_env", &self.inherited_env)
+            .field("env", &self.env)
+            .field("current_dir", &self.current_dir)
+            .field("timeout", &self.timeout)
+            .field("log_stderr", &self.stderr.is_some())
+            .finish()
+    }
+}
+
 pub trait CgiRun {
     /// Dispatches a request to the process.
     ///
@@ -102,10 +228,35 @@ pub trait CgiRun {
     /// means that the child can continue running in the background and send data to the client,
     /// even after you have finished handling the request.
     fn start_cgi(self, request: &Request) -> Result<Response, CgiError>;
+
+    /// Same as `start_cgi`, but with options for the execution of the program.
+    fn start_cgi_with_options(
+        self,
+        request: &Request,
+        options: &CgiOptions,
+    ) -> Result<Response, CgiError>;
 }
 
 impl CgiRun for Command {
-    fn start_cgi(mut self, request: &Request) -> Result<Response, CgiError> {
+    #[inline]
+    fn start_cgi(self, request: &Request) -> Result<Response, CgiError> {
+        self.start_cgi_with_options(request, &CgiOptions::default())
+    }
+
+    fn start_cgi_with_options(
+        mut self,
+        request: &Request,
+        options: &CgiOptions,
+    ) -> Result<Response, CgiError> {
+        if let Some(ref names) = options.inherited_env {
+            self.env_clear();
+            for name in names {
+                if let Some(value) = env::var_os(name) {
+                    self.env(name, value);
+                }
+            }
+        }
+
         self.env("SERVER_SOFTWARE", "rouille")
             .env("SERVER_NAME", "localhost") // FIXME:
             .env("GATEWAY_INTERFACE", "CGI/1.1")
@@ -127,26 +278,83 @@ impl CgiRun for Command {
                 &request.header("Content-Length").unwrap_or(""),
             )
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
             .stdin(Stdio::piped());
+        if options.stderr.is_some() {
+            self.stderr(Stdio::piped());
+        } else {
+            self.stderr(Stdio::inherit());
+        }
+        for (name, value) in &options.env {
+            self.env(name, value);
+        }
+        if let Some(ref dir) = options.current_dir {
+            self.current_dir(dir);
+        }
 
         // TODO: `HTTP_` env vars with the headers
 
         let mut child = self.spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        if let Some(ref output) = options.stderr {
+            let stderr = child.stderr.take().unwrap();
+            let output = output.clone();
+            let prefix = format!("{} {}", request.method(), request.raw_url());
+            thread::spawn(move || {
+                for line in io::BufReader::new(stderr).split(b'\n') {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    let line = String::from_utf8_lossy(&line);
+                    let _ = writeln!(output(), "{} - {}", prefix, line.trim_end_matches('\r'));
+                }
+            });
+        }
+
+        // Kills the program once the timeout is reached, unless the body of the response has
+        // been dropped before, which drops `done`.
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let done = options.timeout.map(|timeout| {
+            let (done, stopped) = mpsc::channel::<()>();
+            let timed_out = timed_out.clone();
+            let child = Arc::new(Mutex::new(child));
+            thread::spawn(move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    let _ = child.lock().unwrap().kill();
+                }
+                let _ = child.lock().unwrap().wait();
+            });
+            done
+        });
+        let gateway_timeout = || Ok(Response::text("Gateway Time-out").with_status_code(504));
 
         if let Some(mut body) = request.data() {
-            io::copy(&mut body, child.stdin.as_mut().unwrap())?;
+            if let Err(err) = io::copy(&mut body, &mut stdin) {
+                if timed_out.load(Ordering::SeqCst) {
+                    return gateway_timeout();
+                }
+                return Err(err.into());
+            }
         } else {
             return Err(CgiError::BodyAlreadyExtracted);
         }
+        // Closing stdin so that the program sees the end of the body.
+        drop(stdin);
 
         let response = {
-            let mut stdout = io::BufReader::new(child.stdout.take().unwrap());
+            let mut stdout = io::BufReader::new(stdout);
 
             let mut headers = Vec::new();
             let mut status_code = 200;
             for header in stdout.by_ref().lines() {
-                let header = header?;
+                let header = match header {
+                    Ok(header) => header,
+                    Err(_) if timed_out.load(Ordering::SeqCst) => return gateway_timeout(),
+                    Err(err) => return Err(err.into()),
+                };
                 if header.is_empty() {
                     break;
                 }
@@ -165,10 +373,18 @@ impl CgiRun for Command {
                 }
             }
 
+            if timed_out.load(Ordering::SeqCst) {
+                return gateway_timeout();
+            }
+
+            let body = CgiBody {
+                stdout,
+                _done: done,
+            };
             Response {
                 status_code,
                 headers,
-                data: ResponseBody::from_reader(stdout),
+                data: ResponseBody::from_reader(body),
                 upgrade: None,
             }
         };
@@ -176,3 +392,101 @@ impl CgiRun for Command {
         Ok(response)
     }
 }
+
+// Output of the program, which stops its timeout once dropped.
+struct CgiBody<R> {
+    stdout: R,
+    _done: Option<mpsc::Sender<()>>,
+}
+
+impl<R> Read for CgiBody<R>
+where
+    R: Read,
+{
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::CgiOptions;
+    use super::CgiRun;
+    use Request;
+
+    // A writer shared with the test.
+    #[derive(Clone)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn script(script: &str) -> Command {
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[test]
+    fn environment() {
+        let request = Request::fake_http("GET", "/hello?a=b", vec![], vec![]);
+        let options = CgiOptions::new()
+            .inherit_env(vec!["PATH"])
+            .env("GREETING", "hi")
+            .env("QUERY_STRING", "overridden")
+            .current_dir("/");
+        let command = script(
+            r#"printf 'Status: 201 Created\nX-Test: yes\n\n'
+            echo "$GREETING $QUERY_STRING $REQUEST_METHOD ${HOME:-none}"; pwd"#,
+        );
+        let response = command.start_cgi_with_options(&request, &options).unwrap();
+        assert_eq!(response.status_code, 201);
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.0 == "X-Test" && h.1 == "yes"));
+
+        let (mut body, _) = response.data.into_reader_and_size();
+        let mut text = String::new();
+        body.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hi overridden GET none\n/\n");
+    }
+
+    #[test]
+    fn stderr_and_timeout() {
+        let request = Request::fake_http("POST", "/slow", vec![], b"body".to_vec());
+        let logs = Output(Arc::new(Mutex::new(Vec::new())));
+        let output = logs.clone();
+        let options = CgiOptions::new()
+            .timeout(Duration::from_millis(200))
+            .log_stderr(move || output.clone());
+
+        let start = Instant::now();
+        let response = script("echo oops >&2; exec sleep 5")
+            .start_cgi_with_options(&request, &options)
+            .unwrap();
+        assert_eq!(response.status_code, 504);
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        // The error output is read by another thread.
+        while logs.0.lock().unwrap().is_empty() && start.elapsed() < Duration::from_secs(4) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(&logs.0.lock().unwrap()[..], b"POST /slow - oops\n");
+    }
+}
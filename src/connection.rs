@@ -0,0 +1,34 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::net::SocketAddr;
+
+/// Information about the connection that a request was received on.
+///
+/// Use [`Request::connection_info`](struct.Request.html#method.connection_info) to obtain it.
+///
+/// More fields may be added in the future, which is why this struct can't be built or
+/// destructured outside of rouille.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConnectionInfo {
+    /// Address of the client. Same as `Request::remote_addr`.
+    pub peer_addr: SocketAddr,
+
+    /// Address of the listening socket that accepted the connection.
+    ///
+    /// If the server listens on an unspecified address such as `0.0.0.0`, this is that
+    /// unspecified address and not the address of the interface the client connected to.
+    ///
+    /// `None` for requests that were built with `Request::fake_http` and similar functions.
+    pub local_addr: Option<SocketAddr>,
+
+    /// True if the connection uses TLS.
+    pub secure: bool,
+}
@@ -7,22 +7,147 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use input::parse_accept_header;
+use middleware::Middleware;
+use mime::MediaType;
+use std::fmt;
 use std::str;
+use std::sync::Arc;
 use Request;
 use Response;
 
+/// Middleware that calls [`apply`](fn.apply.html) on every response.
+///
+/// Use [`EncodingOptions`](struct.EncodingOptions.html) as a middleware instead in order to
+/// configure the compression.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Request, Response};
+/// use rouille::content_encoding::ContentEncoding;
+/// use rouille::middleware::Pipeline;
+///
+/// let pipeline = Pipeline::new(|_: &Request| Response::text("hello world"))
+///     .with(ContentEncoding);
+/// ```
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ContentEncoding;
+
+impl Middleware for ContentEncoding {
+    #[inline]
+    fn after(&self, request: &Request, response: Response) -> Response {
+        apply(request, response)
+    }
+}
+
+/// Options of [`apply_with_options`](fn.apply_with_options.html).
+///
+/// The options can also be used as a middleware that calls `apply_with_options` on every
+/// response.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use rouille::{Request, Response};
+/// use rouille::content_encoding::EncodingOptions;
+/// use rouille::middleware::Pipeline;
+///
+/// let options = EncodingOptions {
+///     brotli_quality: 4,
+///     min_size: 1024,
+///     content_types: vec!["text/*".to_owned(), "application/json".to_owned()],
+///     exclude: Some(Arc::new(|request: &Request| request.url().starts_with("/downloads/"))),
+///     ..EncodingOptions::default()
+/// };
+/// let pipeline = Pipeline::new(|_: &Request| Response::text("hello world")).with(options);
+/// ```
+#[derive(Clone)]
+pub struct EncodingOptions {
+    /// Quality of the brotli compression, from 0 (fastest) to 11 (smallest). Defaults to `6`.
+    pub brotli_quality: u32,
+    /// Level of the zstd compression, from 1 (fastest) to 22 (smallest). Defaults to `3`.
+    pub zstd_level: i32,
+    /// Level of the gzip compression, from 0 (fastest) to 9 (smallest). The encoder only has
+    /// three levels, which correspond to the ranges 0 to 3, 4 to 6 and 7 to 9. Defaults to `6`.
+    pub gzip_level: u32,
+    /// Responses whose body is smaller than this number of bytes are sent without encoding,
+    /// since the encoding would make them bigger. Bodies whose size isn't known in advance are
+    /// always encoded. Defaults to `0`.
+    pub min_size: usize,
+    /// Content types of the responses to encode, for example `application/json`, or `text/*`
+    /// for all the subtypes of a type. The parameters of the `Content-Type`, such as the charset,
+    /// are ignored.
+    ///
+    /// If the list is empty, only the textual content types are encoded, as described in the
+    /// documentation of [`apply`](fn.apply.html). The binary formats such as the images, the videos
+    /// and the archives are usually already compressed, and aren't encoded. Defaults to an empty
+    /// list.
+    pub content_types: Vec<String>,
+    /// Returns true for the requests whose responses must never be encoded, for example the
+    /// routes that serve files which the client checksums. Defaults to `None`.
+    pub exclude: Option<Arc<ExcludeFn>>,
+}
+
+type ExcludeFn = dyn Fn(&Request) -> bool + Send + Sync;
+
+impl Default for EncodingOptions {
+    #[inline]
+    fn default() -> EncodingOptions {
+        EncodingOptions {
+            brotli_quality: 6,
+            zstd_level: 3,
+            gzip_level: 6,
+            min_size: 0,
+            content_types: Vec::new(),
+            exclude: None,
+        }
+    }
+}
+
+impl fmt::Debug for EncodingOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncodingOptions")
+            .field("brotli_quality", &self.brotli_quality)
+            .field("zstd_level", &self.zstd_level)
+            .field("gzip_level", &self.gzip_level)
+            .field("min_size", &self.min_size)
+            .field("content_types", &self.content_types)
+            .field("exclude", &self.exclude.is_some())
+            .finish()
+    }
+}
+
+impl Middleware for EncodingOptions {
+    #[inline]
+    fn after(&self, request: &Request, response: Response) -> Response {
+        apply_with_options(request, response, self)
+    }
+}
+
 /// Applies content encoding to the response.
 ///
-/// Analyzes the `Accept-Encoding` header of the request. If one of the encodings is recognized and
-/// supported by rouille, it adds a `Content-Encoding` header to the `Response` and encodes its
-/// body.
+/// Analyzes the `Accept-Encoding` header of the request, and picks the encoding supported by
+/// rouille that has the highest priority. It then adds a `Content-Encoding` header to the
+/// `Response` and encodes its body. When the client gives the same priority to several
+/// encodings, zstd is preferred to brotli, which is preferred to gzip. A `Vary: Accept-Encoding` header is added to the
+/// responses that could have been encoded.
 ///
-/// If the response already has a `Content-Encoding` header, this function is a no-op.
+/// If the response already has a `Content-Encoding` header, for example because it is the
+/// response of a proxied server, this function is a no-op. This function is also a no-op if the
+/// `Cache-Control` header of the response contains the `no-transform` directive.
 /// If the response has a `Content-Type` header that isn't textual content, this function is a
-/// no-op.
+/// no-op. Use [`Response::no_compression`](../struct.Response.html#method.no_compression) to
+/// prevent a response from being encoded, for example for server-sent events.
 ///
-/// The gzip encoding is supported only if you enable the `gzip` feature of rouille (which is
-/// enabled by default).
+/// The body is encoded as it is sent, which means that streaming bodies don't need to be
+/// entirely in memory.
+///
+/// The gzip and brotli encodings are supported only if you enable respectively the `gzip` and
+/// the `brotli` features of rouille (which are enabled by default). The zstd encoding, which
+/// browsers rarely support but which is faster than the others for the traffic between services,
+/// requires the `zstd-encoding` feature.
 ///
 /// # Example
 ///
@@ -35,12 +160,30 @@ use Response;
 ///     content_encoding::apply(request, Response::text("hello world"))
 /// }
 /// ```
+#[inline]
 pub fn apply(request: &Request, response: Response) -> Response {
-    // Only text should be encoded. Otherwise just return.
-    if !response_is_text(&response) {
+    apply_with_options(request, response, &EncodingOptions::default())
+}
+
+/// Same as [`apply`](fn.apply.html), but with options.
+///
+/// See [`EncodingOptions`](struct.EncodingOptions.html) for the responses that are skipped in
+/// addition to the ones that `apply` skips.
+pub fn apply_with_options(
+    request: &Request,
+    response: Response,
+    options: &EncodingOptions,
+) -> Response {
+    if response.data.no_compression || !should_encode(&response, options) {
         return response;
     }
 
+    if let Some(ref exclude) = options.exclude {
+        if exclude(request) {
+            return response;
+        }
+    }
+
     // If any of the response's headers is equal to `Content-Encoding`, ignore the function
     // call and return immediately.
     if response
@@ -51,51 +194,116 @@ pub fn apply(request: &Request, response: Response) -> Response {
         return response;
     }
 
-    // Put the response in an Option for later.
-    let mut response = Some(response);
+    // The `no-transform` directive forbids the intermediaries, which includes us, to change the
+    // body.
+    if response.headers.iter().any(|h| {
+        h.0.eq_ignore_ascii_case("Cache-Control")
+            && h.1
+                .split(',')
+                .any(|d| d.trim().eq_ignore_ascii_case("no-transform"))
+    }) {
+        return response;
+    }
 
-    // Now let's get the list of content encodings accepted by the request.
-    // The list should be ordered from the most desired to the list desired.
-    // TODO: use input::priority_header_preferred instead
-    for encoding in accepted_content_encodings(request) {
-        // Try the brotli encoding.
-        if brotli(encoding, &mut response) {
-            return response.take().unwrap();
-        }
+    let response = response.with_vary("Accept-Encoding");
+    match preferred_encoding(request, &supported_encodings()) {
+        Some("zstd") => zstd(response, options.zstd_level),
+        Some("br") => brotli(response, options.brotli_quality),
+        Some("gzip") => gzip(response, options.gzip_level),
+        _ => response,
+    }
+}
 
-        // Try the gzip encoding.
-        if gzip(encoding, &mut response) {
-            return response.take().unwrap();
-        }
+// Returns the encodings that rouille supports, by order of preference when the client gives
+// them the same priority.
+fn supported_encodings() -> Vec<&'static str> {
+    let mut encodings = Vec::new();
+    if cfg!(feature = "zstd-encoding") {
+        encodings.push("zstd");
+    }
+    if cfg!(feature = "brotli") {
+        encodings.push("br");
+    }
+    if cfg!(feature = "gzip") {
+        encodings.push("gzip");
+    }
+    encodings
+}
 
-        // The identity encoding is always supported.
-        if encoding.eq_ignore_ascii_case("identity") {
-            return response.take().unwrap();
+// Returns the encoding amongst `available` that the client prefers, or `None` if it prefers the
+// identity or doesn't accept any of them. `available` is sorted by order of preference when the
+// client gives several encodings the same priority.
+//
+// Also used by `match_assets` to choose between the precompressed files.
+pub(crate) fn preferred_encoding<'a>(request: &Request, available: &[&'a str]) -> Option<&'a str> {
+    let header = parse_accept_header(request.header("Accept-Encoding")?);
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for &encoding in available {
+        let priority = header.quality(encoding);
+        if priority > 0.0 && best.map_or(true, |b| priority > b.1) {
+            best = Some((encoding, priority));
         }
     }
 
-    // No encoding accepted, don't do anything.
-    response.take().unwrap()
+    // The identity is only preferred if the client explicitly says so.
+    let best = best?;
+    let identity = header
+        .items()
+        .iter()
+        .find(|item| item.value.eq_ignore_ascii_case("identity"));
+    match identity {
+        Some(identity) if identity.quality > best.1 => None,
+        _ => Some(best.0),
+    }
 }
 
-// Returns true if the Content-Type of the response is a type that should be encoded.
-// Since encoding is purely an optimisation, it's not a problem if the function sometimes has
-// false positives or false negatives.
-fn response_is_text(response: &Response) -> bool {
+// Returns true if the size and the Content-Type of the response allow encoding it.
+fn should_encode(response: &Response, options: &EncodingOptions) -> bool {
+    if let Some(len) = response.data.data_length {
+        if len < options.min_size {
+            return false;
+        }
+    }
+
     response.headers.iter().any(|&(ref key, ref value)| {
         if !key.eq_ignore_ascii_case("Content-Type") {
             return false;
         }
 
-        // TODO: perform case-insensitive comparison
-        value.starts_with("text/")
-            || value.contains("javascript")
-            || value.contains("json")
-            || value.contains("xml")
-            || value.contains("font")
+        let media_type = match MediaType::parse(value) {
+            Ok(m) => m,
+            Err(_) => return false,
+        };
+
+        if options.content_types.is_empty() {
+            is_text(&media_type)
+        } else {
+            options.content_types.iter().any(|allowed| {
+                let mut parts = allowed.splitn(2, '/');
+                let main_type = parts.next().unwrap_or("").trim();
+                let subtype = parts.next().unwrap_or("").trim();
+                main_type.eq_ignore_ascii_case(media_type.main_type())
+                    && (subtype == "*" || subtype.eq_ignore_ascii_case(media_type.subtype()))
+            })
+        }
     })
 }
 
+// Returns true if the media type is a type that should be encoded by default.
+// Since encoding is purely an optimisation, it's not a problem if the function sometimes has
+// false positives or false negatives.
+fn is_text(media_type: &MediaType) -> bool {
+    let subtype = media_type.subtype();
+
+    media_type.main_type() == "text"
+        || media_type.main_type() == "font"
+        || subtype.contains("javascript")
+        || subtype.contains("json")
+        || subtype.contains("xml")
+        || subtype.contains("font")
+}
+
 /// Returns an iterator of the list of content encodings accepted by the request.
 ///
 /// # Example
@@ -149,68 +357,217 @@ impl<'a> Iterator for AcceptedContentEncodingsIter<'a> {
 }
 
 #[cfg(feature = "gzip")]
-fn gzip(e: &str, response: &mut Option<Response>) -> bool {
-    use deflate::deflate_bytes_gzip;
-    use std::io;
-    use std::mem;
-    use ResponseBody;
-
-    if !e.eq_ignore_ascii_case("gzip") {
-        return false;
-    }
-
-    let response = response.as_mut().unwrap();
-    response
-        .headers
-        .push(("Content-Encoding".into(), "gzip".into()));
-    let previous_body = mem::replace(&mut response.data, ResponseBody::empty());
-    let (mut raw_data, size) = previous_body.into_reader_and_size();
-    let mut src = match size {
-        Some(size) => Vec::with_capacity(size),
-        None => Vec::new(),
+fn gzip(response: Response, level: u32) -> Response {
+    use deflate::write::GzEncoder;
+    use deflate::Compression;
+
+    let compression = match level {
+        0..=3 => Compression::Fast,
+        4..=6 => Compression::Default,
+        _ => Compression::Best,
     };
-    io::copy(&mut raw_data, &mut src).expect("Failed reading response body while gzipping");
-    let zipped = deflate_bytes_gzip(&src);
-    response.data = ResponseBody::from_data(zipped);
-    true
+    encode_body(response, "gzip", |output| {
+        GzEncoder::new(output, compression)
+    })
 }
 
 #[cfg(not(feature = "gzip"))]
 #[inline]
-fn gzip(e: &str, response: &mut Option<Response>) -> bool {
-    false
+fn gzip(response: Response, _: u32) -> Response {
+    response
 }
 
 #[cfg(feature = "brotli")]
-fn brotli(e: &str, response: &mut Option<Response>) -> bool {
-    use brotli2::read::BrotliEncoder;
+fn brotli(response: Response, quality: u32) -> Response {
+    use brotli2::write::BrotliEncoder;
+
+    encode_body(response, "br", |output| {
+        BrotliEncoder::new(output, quality.min(11))
+    })
+}
+
+#[cfg(not(feature = "brotli"))]
+#[inline]
+fn brotli(response: Response, _: u32) -> Response {
+    response
+}
+
+#[cfg(feature = "zstd-encoding")]
+fn zstd(response: Response, level: i32) -> Response {
+    use zstd::stream::write::Encoder;
+
+    encode_body(response, "zstd", |output| {
+        // Creating the encoder only fails if the level is invalid, which the clamping prevents.
+        Encoder::new(output, level.max(1).min(22)).expect("failed to initialize the zstd encoder")
+    })
+}
+
+#[cfg(not(feature = "zstd-encoding"))]
+#[inline]
+fn zstd(response: Response, _: i32) -> Response {
+    response
+}
+
+// Adds a `Content-Encoding` header to the response, and replaces its body with a reader that
+// encodes it with the encoder returned by `make_encoder`.
+#[cfg(any(feature = "gzip", feature = "brotli", feature = "zstd-encoding"))]
+fn encode_body<E, F>(mut response: Response, name: &'static str, make_encoder: F) -> Response
+where
+    E: encoding_reader::Encoder + Send + 'static,
+    F: FnOnce(encoding_reader::Output) -> E,
+{
     use std::mem;
     use ResponseBody;
 
-    if !e.eq_ignore_ascii_case("br") {
-        return false;
-    }
-
-    let response = response.as_mut().unwrap();
     response
         .headers
-        .push(("Content-Encoding".into(), "br".into()));
+        .push(("Content-Encoding".into(), name.into()));
     let previous_body = mem::replace(&mut response.data, ResponseBody::empty());
+    let unbuffered = previous_body.unbuffered;
     let (raw_data, _) = previous_body.into_reader_and_size();
-    response.data = ResponseBody::from_reader(BrotliEncoder::new(raw_data, 6));
-    true
+    let reader = encoding_reader::EncodingReader::new(raw_data, make_encoder);
+    response.data = ResponseBody::from_reader(reader);
+    response.data.unbuffered = unbuffered;
+    response
 }
 
-#[cfg(not(feature = "brotli"))]
-#[inline]
-fn brotli(e: &str, response: &mut Option<Response>) -> bool {
-    false
+#[cfg(any(feature = "gzip", feature = "brotli", feature = "zstd-encoding"))]
+mod encoding_reader {
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    // Size of the chunks read from the source.
+    const CHUNK_SIZE: usize = 8192;
+
+    /// An encoder that writes to an `Output`.
+    pub trait Encoder: Write {
+        /// Writes the end of the encoded data.
+        fn finish(self) -> io::Result<()>;
+    }
+
+    #[cfg(feature = "gzip")]
+    impl Encoder for ::deflate::write::GzEncoder<Output> {
+        #[inline]
+        fn finish(self) -> io::Result<()> {
+            ::deflate::write::GzEncoder::finish(self).map(|_| ())
+        }
+    }
+
+    #[cfg(feature = "brotli")]
+    impl Encoder for ::brotli2::write::BrotliEncoder<Output> {
+        #[inline]
+        fn finish(self) -> io::Result<()> {
+            ::brotli2::write::BrotliEncoder::finish(self).map(|_| ())
+        }
+    }
+
+    #[cfg(feature = "zstd-encoding")]
+    impl Encoder for ::zstd::stream::write::Encoder<'static, Output> {
+        #[inline]
+        fn finish(self) -> io::Result<()> {
+            ::zstd::stream::write::Encoder::finish(self).map(|_| ())
+        }
+    }
+
+    /// Encodes the data of a reader as it is being read, so that streaming bodies don't need to
+    /// be entirely buffered.
+    pub struct EncodingReader<R, E> {
+        source: R,
+        // `None` once the source is exhausted and the encoder has been finished.
+        encoder: Option<E>,
+        output: Output,
+        // Number of bytes of `output` that have already been returned.
+        position: usize,
+    }
+
+    /// The buffer that receives the encoded data. It is shared with the encoder, which doesn't
+    /// always give access to its writer.
+    #[derive(Clone, Default)]
+    pub struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        #[inline]
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<R, E> EncodingReader<R, E>
+    where
+        R: Read,
+        E: Encoder,
+    {
+        pub fn new<F>(source: R, make_encoder: F) -> EncodingReader<R, E>
+        where
+            F: FnOnce(Output) -> E,
+        {
+            let output = Output::default();
+            EncodingReader {
+                source,
+                encoder: Some(make_encoder(output.clone())),
+                output,
+                position: 0,
+            }
+        }
+    }
+
+    impl<R, E> Read for EncodingReader<R, E>
+    where
+        R: Read,
+        E: Encoder,
+    {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                {
+                    let mut output = self.output.0.lock().unwrap();
+                    if self.position < output.len() {
+                        let available = &output[self.position..];
+                        let len = available.len().min(buf.len());
+                        buf[..len].copy_from_slice(&available[..len]);
+                        self.position += len;
+                        if self.position == output.len() {
+                            output.clear();
+                            self.position = 0;
+                        }
+                        return Ok(len);
+                    }
+                }
+
+                let mut chunk = [0; CHUNK_SIZE];
+                let len = match self.encoder {
+                    Some(_) => self.source.read(&mut chunk)?,
+                    None => return Ok(0),
+                };
+
+                if len == 0 {
+                    self.encoder.take().unwrap().finish()?;
+                } else {
+                    let encoder = self.encoder.as_mut().unwrap();
+                    encoder.write_all(&chunk[..len])?;
+                    // A short read means that the source has no more data for now, which is
+                    // typical of streaming bodies. Flushing sends what we have to the client.
+                    if len < CHUNK_SIZE {
+                        encoder.flush()?;
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use content_encoding;
     use Request;
+    use Response;
 
     #[test]
     fn no_req_encodings() {
@@ -259,5 +616,242 @@ mod tests {
         assert_eq!(list.next(), None);
     }
 
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_streaming() {
+        use std::io::Read;
+        use ResponseBody;
+
+        let request = {
+            let h = vec![("Accept-Encoding".to_owned(), "gzip".to_owned())];
+            Request::fake_http("GET", "/", h, vec![])
+        };
+
+        // A body large enough to be compressed in several chunks.
+        let text = "hello world ".repeat(5000);
+        let mut response = Response::text("");
+        response.data = ResponseBody::from_reader(::std::io::Cursor::new(text.clone()));
+        let response = content_encoding::apply(&request, response);
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.0 == "Content-Encoding" && h.1 == "gzip"));
+
+        let (mut reader, size) = response.data.into_reader_and_size();
+        assert_eq!(size, None);
+        let mut compressed = Vec::new();
+        reader.read_to_end(&mut compressed).unwrap();
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+    }
+
+    #[test]
+    fn no_compression() {
+        let request = {
+            let h = vec![("Accept-Encoding".to_owned(), "gzip, br".to_owned())];
+            Request::fake_http("GET", "/", h, vec![])
+        };
+
+        let response = content_encoding::apply(&request, Response::text("hello").no_compression());
+        assert!(!response.headers.iter().any(|h| h.0 == "Content-Encoding"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "brotli"))]
+    fn negotiation() {
+        let encoding = |accept: &str| {
+            let h = vec![("Accept-Encoding".to_owned(), accept.to_owned())];
+            let request = Request::fake_http("GET", "/", h, vec![]);
+            let response = content_encoding::apply(&request, Response::text("hello"));
+            assert!(response
+                .headers
+                .iter()
+                .any(|h| h.0 == "Vary" && h.1 == "Accept-Encoding"));
+            response
+                .headers
+                .iter()
+                .find(|h| h.0 == "Content-Encoding")
+                .map(|h| h.1.to_string())
+        };
+
+        assert_eq!(encoding("gzip, br").as_deref(), Some("br"));
+        assert_eq!(encoding("gzip, deflate").as_deref(), Some("gzip"));
+        assert_eq!(encoding("br;q=0.5, gzip;q=0.8").as_deref(), Some("gzip"));
+        assert_eq!(encoding("br;q=0, gzip;q=0").as_deref(), None);
+        assert_eq!(encoding("*, zstd;q=0").as_deref(), Some("br"));
+        assert_eq!(
+            encoding("*;q=0.5, br;q=0, zstd;q=0").as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(encoding("identity, gzip;q=0.5").as_deref(), None);
+        assert_eq!(encoding("identity;q=0.5, gzip").as_deref(), Some("gzip"));
+        assert_eq!(encoding("deflate").as_deref(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn brotli_quality() {
+        use content_encoding::EncodingOptions;
+        use std::io::Read;
+
+        let h = vec![("Accept-Encoding".to_owned(), "br".to_owned())];
+        let request = Request::fake_http("GET", "/", h, vec![]);
+        let text = "hello world ".repeat(5000);
+        let compressed = |quality| {
+            let options = EncodingOptions {
+                brotli_quality: quality,
+                ..EncodingOptions::default()
+            };
+            let response = Response::text(text.clone());
+            let response = content_encoding::apply_with_options(&request, response, &options);
+            let mut out = Vec::new();
+            let (mut reader, _) = response.data.into_reader_and_size();
+            reader.read_to_end(&mut out).unwrap();
+            out
+        };
+
+        let fast = compressed(0);
+        let small = compressed(11);
+        assert!(small.len() < text.len() / 10);
+        assert!(small.len() <= fast.len());
+    }
+
+    #[test]
+    #[cfg(feature = "zstd-encoding")]
+    fn zstd() {
+        use std::io::Read;
+
+        let encoding = |accept: &str| {
+            let h = vec![("Accept-Encoding".to_owned(), accept.to_owned())];
+            let request = Request::fake_http("GET", "/", h, vec![]);
+            let text = "hello world ".repeat(5000);
+            content_encoding::apply(&request, Response::text(text))
+        };
+
+        let response = encoding("gzip, br, zstd");
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.0 == "Content-Encoding" && h.1 == "zstd"));
+        let mut compressed = Vec::new();
+        let (mut reader, _) = response.data.into_reader_and_size();
+        reader.read_to_end(&mut compressed).unwrap();
+        assert_eq!(&compressed[..4], &[0x28, 0xb5, 0x2f, 0xfd]);
+        assert!(compressed.len() < 60000 / 10);
+
+        let response = encoding("zstd;q=0.5, gzip");
+        assert!(!response
+            .headers
+            .iter()
+            .any(|h| h.0 == "Content-Encoding" && h.1 == "zstd"));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn options() {
+        use content_encoding::EncodingOptions;
+        use std::sync::Arc;
+
+        let encoded = |options: &EncodingOptions, url: &str, response: Response| {
+            let h = vec![("Accept-Encoding".to_owned(), "gzip".to_owned())];
+            let request = Request::fake_http("GET", url, h, vec![]);
+            let response = content_encoding::apply_with_options(&request, response, options);
+            response.headers.iter().any(|h| h.0 == "Content-Encoding")
+        };
+
+        let options = EncodingOptions {
+            min_size: 10,
+            ..EncodingOptions::default()
+        };
+        assert!(!encoded(&options, "/", Response::text("hello")));
+        assert!(encoded(&options, "/", Response::text("hello world")));
+
+        let image = || Response::from_data("image/svg+xml", "<svg></svg>");
+        let archive = || Response::from_data("application/zip", "PK");
+        assert!(encoded(&EncodingOptions::default(), "/", image()));
+        assert!(!encoded(&EncodingOptions::default(), "/", archive()));
+
+        let options = EncodingOptions {
+            content_types: vec!["application/*".to_owned()],
+            ..EncodingOptions::default()
+        };
+        assert!(!encoded(&options, "/", image()));
+        assert!(encoded(&options, "/", archive()));
+        assert!(!encoded(&options, "/", Response::text("hello")));
+
+        let options = EncodingOptions {
+            exclude: Some(Arc::new(|request: &Request| {
+                request.url().starts_with("/raw/")
+            })),
+            ..EncodingOptions::default()
+        };
+        assert!(!encoded(&options, "/raw/file", Response::text("hello")));
+        assert!(encoded(&options, "/file", Response::text("hello")));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn untouched_responses() {
+        let h = vec![("Accept-Encoding".to_owned(), "gzip".to_owned())];
+        let request = Request::fake_http("GET", "/", h, vec![]);
+        let encodings = |response: &Response| {
+            response
+                .headers
+                .iter()
+                .filter(|h| h.0.eq_ignore_ascii_case("Content-Encoding"))
+                .count()
+        };
+
+        let proxied = Response::text("hello").with_additional_header("Content-Encoding", "gzip");
+        let response = content_encoding::apply(&request, proxied);
+        assert_eq!(encodings(&response), 1);
+
+        let no_transform =
+            Response::text("hello").with_unique_header("Cache-Control", "public, No-Transform");
+        let response = content_encoding::apply(&request, no_transform);
+        assert_eq!(encodings(&response), 0);
+
+        let response =
+            content_encoding::apply(&request, Response::text("hello").with_vary("Cookie"));
+        assert_eq!(encodings(&response), 1);
+        let vary: Vec<_> = response.headers.iter().filter(|h| h.0 == "Vary").collect();
+        assert_eq!(vary.len(), 1);
+        assert_eq!(vary[0].1, "Cookie, Accept-Encoding");
+    }
+
+    #[test]
+    fn streaming_flushes() {
+        use std::io;
+        use std::io::Read;
+        use ResponseBody;
+
+        // A streaming body whose second chunk isn't available yet.
+        struct Source(bool);
+        impl Read for Source {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                assert!(!self.0, "read the body past the available data");
+                self.0 = true;
+                buf[..5].copy_from_slice(b"hello");
+                Ok(5)
+            }
+        }
+
+        for encoding in content_encoding::supported_encodings() {
+            let h = vec![("Accept-Encoding".to_owned(), encoding.to_owned())];
+            let request = Request::fake_http("GET", "/", h, vec![]);
+            let mut response = Response::text("");
+            response.data = ResponseBody::from_reader(Source(false));
+            let response = content_encoding::apply(&request, response);
+            assert!(response
+                .headers
+                .iter()
+                .any(|h| h.0 == "Content-Encoding" && h.1 == encoding));
+
+            let (mut reader, _) = response.data.into_reader_and_size();
+            let mut buf = [0; 1024];
+            assert!(reader.read(&mut buf).unwrap() > 0);
+        }
+    }
+
     // TODO: more tests for encoding stuff
 }
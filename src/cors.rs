@@ -0,0 +1,420 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Cross-origin resource sharing.
+//!
+//! Browsers only let the scripts of a website read the responses of another origin if these
+//! responses contain the `Access-Control-*` headers. Before sending a request that a form
+//! couldn't send, such as a `PUT` or a request with a custom header, browsers also send an
+//! `OPTIONS` *preflight* request to ask for permission.
+//!
+//! A [`Cors`](struct.Cors.html) declares which origins, methods and headers are allowed. It
+//! answers the preflight requests itself, and adds the headers to the other responses. It can
+//! wrap a handler with [`apply`](struct.Cors.html#method.apply), or be used as a
+//! [`Middleware`](../middleware/trait.Middleware.html).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::cors::Cors;
+//!
+//! let cors = Cors::new()
+//!     .allow_origin("https://example.com")
+//!     .allow_origin("https://*.example.com")
+//!     .allow_methods(&["GET", "POST", "DELETE"])
+//!     .allow_headers(&["Content-Type", "Authorization"])
+//!     .allow_credentials()
+//!     .max_age(Duration::from_secs(3600));
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     cors.apply(request, || Response::text("hello world"))
+//! });
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use middleware::Middleware;
+use Request;
+use Response;
+
+type OriginFn = dyn Fn(&str) -> bool + Send + Sync;
+
+/// Configuration of the cross-origin requests that are allowed.
+///
+/// See [the module-level documentation](index.html).
+#[derive(Clone)]
+pub struct Cors {
+    // Empty and without predicate if any origin is allowed.
+    origins: Vec<String>,
+    origin_fn: Option<Arc<OriginFn>>,
+    methods: Vec<String>,
+    // `None` if the requested headers are allowed.
+    headers: Option<Vec<String>>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Builds a configuration that allows the `GET`, `HEAD` and `POST` requests of any origin,
+    /// with any header, and without credentials.
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            origin_fn: None,
+            methods: vec!["GET".to_owned(), "HEAD".to_owned(), "POST".to_owned()],
+            headers: None,
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allows an origin, such as `https://example.com`. Can be called multiple times.
+    ///
+    /// A `*` in the origin matches any sequence of characters, so that
+    /// `https://*.example.com` allows all the subdomains of `example.com`. Once an origin is
+    /// allowed, the origins that don't match any of the allowed ones are rejected.
+    pub fn allow_origin<S>(mut self, origin: S) -> Cors
+    where
+        S: Into<String>,
+    {
+        self.origins.push(origin.into());
+        self
+    }
+
+    /// Allows the origins for which `predicate` returns true, in addition to the ones given to
+    /// `allow_origin`.
+    pub fn allow_origin_fn<F>(mut self, predicate: F) -> Cors
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origin_fn = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Sets the methods that are allowed. Defaults to `GET`, `HEAD` and `POST`.
+    pub fn allow_methods(mut self, methods: &[&str]) -> Cors {
+        self.methods = methods.iter().map(|m| (*m).to_owned()).collect();
+        self
+    }
+
+    /// Sets the request headers that are allowed. By default, the headers that the browser asks
+    /// for are allowed.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers = Some(headers.iter().map(|h| (*h).to_owned()).collect());
+        self
+    }
+
+    /// Sets the response headers that the scripts can read, in addition to the ones that
+    /// browsers always expose such as `Content-Type`.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Cors {
+        self.expose_headers = headers.iter().map(|h| (*h).to_owned()).collect();
+        self
+    }
+
+    /// Allows the requests to include the cookies and the credentials of the user.
+    ///
+    /// The responses then contain the origin of the request instead of `*`.
+    #[inline]
+    pub fn allow_credentials(mut self) -> Cors {
+        self.credentials = true;
+        self
+    }
+
+    /// Sets how long the browsers can cache the answer to a preflight request.
+    #[inline]
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Returns true if requests from `origin` are allowed.
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        if self.origins.is_empty() && self.origin_fn.is_none() {
+            return true;
+        }
+        self.origins.iter().any(|o| origin_matches(o, origin))
+            || self.origin_fn.as_ref().map_or(false, |f| f(origin))
+    }
+
+    /// Answers the preflight requests, and calls `handler` for the other requests and adds the
+    /// CORS headers to its response.
+    ///
+    /// The preflight requests whose origin isn't allowed get a `403 Forbidden`. The other
+    /// requests are passed to the handler unchanged, and the browser doesn't let the scripts
+    /// read the response.
+    pub fn apply<F>(&self, request: &Request, handler: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        let origin = match request.header("Origin") {
+            Some(origin) => origin,
+            None => return handler(),
+        };
+        let allowed = self.is_origin_allowed(origin);
+
+        if request.method() == "OPTIONS" {
+            if let Some(method) = request.header("Access-Control-Request-Method") {
+                if !allowed || !self.methods.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+                    return Response::empty_404().with_status_code(403);
+                }
+                return self.preflight(request, origin);
+            }
+        }
+
+        let response = handler();
+        if !allowed {
+            return response;
+        }
+        let mut response = self.with_origin(response, origin);
+        if !self.expose_headers.is_empty() {
+            response = response.with_unique_header(
+                "Access-Control-Expose-Headers",
+                self.expose_headers.join(", "),
+            );
+        }
+        response
+    }
+
+    fn preflight(&self, request: &Request, origin: &str) -> Response {
+        let mut response = Response::empty_204()
+            .with_unique_header("Access-Control-Allow-Methods", self.methods.join(", "));
+
+        let headers = match self.headers {
+            Some(ref headers) => headers.join(", "),
+            None => request
+                .header("Access-Control-Request-Headers")
+                .unwrap_or("")
+                .to_owned(),
+        };
+        if !headers.is_empty() {
+            response = response.with_unique_header("Access-Control-Allow-Headers", headers);
+        }
+        if self.headers.is_none() {
+            response = response.with_vary("Access-Control-Request-Headers");
+        }
+        if let Some(max_age) = self.max_age {
+            response = response
+                .with_unique_header("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+
+        self.with_origin(response, origin)
+    }
+
+    // Adds the headers that allow the origin.
+    fn with_origin(&self, response: Response, origin: &str) -> Response {
+        let any = self.origins.is_empty() && self.origin_fn.is_none();
+        let response = if any && !self.credentials {
+            response.with_unique_header("Access-Control-Allow-Origin", "*")
+        } else {
+            response
+                .with_unique_header("Access-Control-Allow-Origin", origin.to_owned())
+                .with_vary("Origin")
+        };
+
+        if self.credentials {
+            response.with_unique_header("Access-Control-Allow-Credentials", "true")
+        } else {
+            response
+        }
+    }
+}
+
+impl Default for Cors {
+    #[inline]
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl fmt::Debug for Cors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cors")
+            .field("origins", &self.origins)
+            .field("origin_fn", &self.origin_fn.is_some())
+            .field("methods", &self.methods)
+            .field("headers", &self.headers)
+            .field("expose_headers", &self.expose_headers)
+            .field("credentials", &self.credentials)
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl Middleware for Cors {
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        self.apply(request, || next(request))
+    }
+}
+
+// Matches an origin against a pattern in which `*` matches any sequence of characters.
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap();
+    if !starts_with_ignore_case(origin, first) {
+        return false;
+    }
+    let mut rest = &origin[first.len()..];
+
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.split_last() {
+        Some((last, middle)) => {
+            for part in middle {
+                match find_ignore_case(rest, part) {
+                    Some(pos) => rest = &rest[pos + part.len()..],
+                    None => return false,
+                }
+            }
+            last
+        }
+        None => return rest.is_empty(),
+    };
+
+    rest.len() >= last.len()
+        && rest.is_char_boundary(rest.len() - last.len())
+        && rest[rest.len() - last.len()..].eq_ignore_ascii_case(last)
+}
+
+fn starts_with_ignore_case(s: &str, prefix: &str) -> bool {
+    s.len() >= prefix.len()
+        && s.is_char_boundary(prefix.len())
+        && s[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find_ignore_case(s: &str, needle: &str) -> Option<usize> {
+    (0..=s.len().saturating_sub(needle.len()))
+        .find(|&pos| s.is_char_boundary(pos) && starts_with_ignore_case(&s[pos..], needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::origin_matches;
+    use super::Cors;
+    use std::time::Duration;
+    use Request;
+    use Response;
+
+    fn get(cors: &Cors, method: &str, headers: &[(&str, &str)]) -> Response {
+        let headers = headers
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect();
+        let request = Request::fake_http(method, "/", headers, vec![]);
+        cors.apply(&request, || Response::text("hello"))
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|h| h.0.eq_ignore_ascii_case(name))
+            .map(|h| &h.1[..])
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(origin_matches("https://example.com", "https://EXAMPLE.com"));
+        assert!(!origin_matches(
+            "https://example.com",
+            "https://example.com.evil"
+        ));
+        assert!(origin_matches(
+            "https://*.example.com",
+            "https://a.b.example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!origin_matches(
+            "https://*.example.com",
+            "http://a.example.com"
+        ));
+        assert!(origin_matches("*", "null"));
+    }
+
+    #[test]
+    fn any_origin() {
+        let cors = Cors::new().expose_headers(&["X-Total"]);
+        let response = get(&cors, "GET", &[("Origin", "https://a.com")]);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            header(&response, "Access-Control-Expose-Headers"),
+            Some("X-Total")
+        );
+
+        let response = get(&cors, "GET", &[]);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+    }
+
+    #[test]
+    fn preflight() {
+        let cors = Cors::new()
+            .allow_origin("https://*.example.com")
+            .allow_origin_fn(|origin| origin == "http://localhost:3000")
+            .allow_methods(&["GET", "PUT"])
+            .allow_credentials()
+            .max_age(Duration::from_secs(600));
+
+        let response = get(
+            &cors,
+            "OPTIONS",
+            &[
+                ("Origin", "https://app.example.com"),
+                ("Access-Control-Request-Method", "PUT"),
+                ("Access-Control-Request-Headers", "X-Custom"),
+            ],
+        );
+        assert_eq!(response.status_code, 204);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Headers"),
+            Some("X-Custom")
+        );
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("600"));
+
+        let deleted = get(
+            &cors,
+            "OPTIONS",
+            &[
+                ("Origin", "https://app.example.com"),
+                ("Access-Control-Request-Method", "DELETE"),
+            ],
+        );
+        assert_eq!(deleted.status_code, 403);
+
+        let response = get(&cors, "PUT", &[("Origin", "http://localhost:3000")]);
+        assert_eq!(
+            header(&response, "Access-Control-Allow-Origin"),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(header(&response, "Vary"), Some("Origin"));
+
+        let response = get(&cors, "PUT", &[("Origin", "https://evil.com")]);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+    }
+}
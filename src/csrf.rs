@@ -0,0 +1,416 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Protection against cross-site request forgery.
+//!
+//! A malicious website can make the browser of a user send requests to your server, along with
+//! the cookies of the user. To tell these requests apart, the pages of your server embed a token
+//! in their forms, or give it to their scripts, and the requests that change the state of the
+//! server must send the token back. The other websites can't read the token.
+//!
+//! There are two modes:
+//!
+//! - The token is tied to the [session](../session/index.html) of the client. Get one with
+//!   [`token`](fn.token.html), and protect the routes with
+//!   [`CsrfMiddleware::new`](struct.CsrfMiddleware.html#method.new).
+//! - The *double-submit cookie* mode, for the APIs that don't use sessions. The token is stored
+//!   in a cookie, which the scripts of the page read and send back in a header. Use
+//!   [`CsrfMiddleware::double_submit`](struct.CsrfMiddleware.html#method.double_submit).
+//!
+//! The middleware checks the requests whose method is not `GET`, `HEAD`, `OPTIONS` or `TRACE`,
+//! and answers with a `403 Forbidden` if the token is missing or invalid. The token is read from
+//! the [`X-CSRF-Token`](constant.HEADER_NAME.html) header, or from the
+//! [`csrf_token`](constant.FIELD_NAME.html) field of the forms encoded as
+//! `application/x-www-form-urlencoded`.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::{Request, Response};
+//! use rouille::csrf::{self, CsrfMiddleware};
+//! use rouille::middleware::Pipeline;
+//! use rouille::session::{self, SessionMiddleware};
+//!
+//! let pipeline = Pipeline::new(|request: &Request| {
+//!     if request.method() == "POST" {
+//!         return Response::text("comment posted");
+//!     }
+//!     session::with_current(|session| {
+//!         let token = csrf::token(request, session);
+//!         Response::html(format!(
+//!             "<form method=\"post\">{}<textarea name=\"comment\"></textarea></form>",
+//!             csrf::hidden_field(&token)
+//!         ))
+//!     })
+//!     .unwrap()
+//! })
+//! .with(SessionMiddleware::new("SID", 3600))
+//! .with(CsrfMiddleware::new());
+//! ```
+
+use std::fmt;
+use std::io::Read;
+
+use sha1::Sha1;
+use url::form_urlencoded;
+
+use input;
+use middleware::Middleware;
+use random::Random;
+use session;
+use session::Session;
+use util::{constant_time_eq, from_hex, to_hex};
+use Request;
+use Response;
+use SameSite;
+use SetCookie;
+
+/// Name of the header from which the token is read.
+pub const HEADER_NAME: &str = "X-CSRF-Token";
+
+/// Name of the form field from which the token is read.
+pub const FIELD_NAME: &str = "csrf_token";
+
+// The forms whose body is larger than this are not read, and their requests are rejected unless
+// the token is in the header.
+const MAX_FORM_SIZE: usize = 1024 * 1024;
+
+// Length of the secret stored in the double-submit cookie.
+const COOKIE_SECRET_LEN: usize = 32;
+
+/// Returns a token tied to the session, to embed in the pages that are sent to the client.
+///
+/// Each call returns a different string, which prevents attacks that guess the token from the
+/// size of compressed responses, but all the tokens of a session are valid until its ID
+/// changes.
+pub fn token(request: &Request, session: &Session) -> String {
+    let digest = digest(session.id());
+    let mut mask = [0; 20];
+    Random::of(request).fill_bytes(&mut mask);
+
+    let masked: Vec<u8> = digest.iter().zip(mask.iter()).map(|(b, m)| b ^ m).collect();
+    to_hex(&mask) + &to_hex(&masked)
+}
+
+/// Returns true if `token` was returned by [`token`](fn.token.html) for this session.
+///
+/// Always returns false if the client didn't send a session ID.
+pub fn verify(session: &Session, token: &str) -> bool {
+    if !session.client_has_sid() {
+        return false;
+    }
+    verify_with_key(session.key(), token)
+}
+
+fn verify_with_key(key: &str, token: &str) -> bool {
+    let token = match from_hex(token) {
+        Some(ref token) if token.len() == 40 => token.clone(),
+        _ => return false,
+    };
+    let (mask, masked) = token.split_at(20);
+    let unmasked: Vec<u8> = masked.iter().zip(mask).map(|(b, m)| b ^ m).collect();
+    constant_time_eq(&unmasked, &digest(key))
+}
+
+/// Returns a hidden `<input>` element that contains the token, to put in a `<form>`.
+#[inline]
+pub fn hidden_field(token: &str) -> String {
+    // The token only contains hexadecimal digits, which don't need to be escaped.
+    format!(
+        "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+        FIELD_NAME, token
+    )
+}
+
+/// Returns the token of the double-submit cookie mode for this request, or `None` if the
+/// request isn't handled by a [`CsrfMiddleware::double_submit`](struct.CsrfMiddleware.html#method.double_submit).
+///
+/// This is the value of the cookie, which the middleware sends to the client if it doesn't have
+/// one yet.
+pub fn double_submit_token(request: &Request) -> Option<String> {
+    request
+        .extensions()
+        .get::<CookieSecret>()
+        .map(|secret| secret.0.clone())
+}
+
+/// Returns the token sent in the [`X-CSRF-Token`](constant.HEADER_NAME.html) header of the
+/// request, if any.
+#[inline]
+pub fn submitted_token(request: &Request) -> Option<&str> {
+    request.header(HEADER_NAME).map(|token| token.trim())
+}
+
+// Value of the double-submit cookie, stored in the extensions of the request.
+#[derive(Clone)]
+struct CookieSecret(String);
+
+/// Middleware that rejects the requests that change the state of the server without sending a
+/// valid token.
+///
+/// See [the module-level documentation](index.html).
+pub struct CsrfMiddleware {
+    // `None` for the tokens tied to the session.
+    cookie_name: Option<String>,
+}
+
+impl CsrfMiddleware {
+    /// Checks the tokens returned by [`token`](fn.token.html).
+    ///
+    /// The session is the one of the [`SessionMiddleware`](../session/struct.SessionMiddleware.html)
+    /// that runs before this middleware. The requests are rejected if there is none.
+    #[inline]
+    pub fn new() -> CsrfMiddleware {
+        CsrfMiddleware { cookie_name: None }
+    }
+
+    /// Checks that the token sent with the request is the value of the cookie with the given
+    /// name.
+    ///
+    /// If the client doesn't have the cookie yet, the middleware generates one and adds it to
+    /// the response. The cookie isn't `HttpOnly`, so that the scripts of the page can read it,
+    /// and its `SameSite` attribute is `Strict`. The handlers can also get its value with
+    /// [`double_submit_token`](fn.double_submit_token.html).
+    #[inline]
+    pub fn double_submit<S>(cookie_name: S) -> CsrfMiddleware
+    where
+        S: Into<String>,
+    {
+        CsrfMiddleware {
+            cookie_name: Some(cookie_name.into()),
+        }
+    }
+
+    // Returns true if `token` is valid for this request.
+    fn is_valid(&self, request: &Request, token: &str) -> bool {
+        match self.cookie_name {
+            Some(ref name) => {
+                let cookie = input::cookies(request).find(|c| c.0 == name);
+                match cookie {
+                    Some((_, value)) if !value.is_empty() => {
+                        constant_time_eq(value.as_bytes(), token.as_bytes())
+                    }
+                    _ => false,
+                }
+            }
+            None => session::with_current(|session| verify(session, token)).unwrap_or(false),
+        }
+    }
+}
+
+impl Default for CsrfMiddleware {
+    #[inline]
+    fn default() -> CsrfMiddleware {
+        CsrfMiddleware::new()
+    }
+}
+
+impl fmt::Debug for CsrfMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CsrfMiddleware")
+            .field("cookie_name", &self.cookie_name)
+            .finish()
+    }
+}
+
+impl Middleware for CsrfMiddleware {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        let mut new_cookie = None;
+        if let Some(ref name) = self.cookie_name {
+            let secret = match input::cookies(request).find(|c| c.0 == name) {
+                Some((_, value)) if !value.is_empty() => value.to_owned(),
+                _ => {
+                    let secret = Random::of(request).alphanumeric(COOKIE_SECRET_LEN);
+                    new_cookie = Some(secret.clone());
+                    secret
+                }
+            };
+            request.extensions().insert(CookieSecret(secret));
+        }
+
+        let response = match request.method() {
+            "GET" | "HEAD" | "OPTIONS" | "TRACE" => next(request),
+            _ => {
+                if let Some(token) = submitted_token(request) {
+                    if self.is_valid(request, token) {
+                        next(request)
+                    } else {
+                        forbidden()
+                    }
+                } else if is_form(request) {
+                    // The body is read in order to find the field, and the handler receives a
+                    // copy of the request with the same body.
+                    let mut body = Vec::new();
+                    let read = request.data().map(|data| {
+                        data.take(MAX_FORM_SIZE as u64 + 1)
+                            .read_to_end(&mut body)
+                            .is_ok()
+                    });
+                    let field = if read == Some(true) && body.len() <= MAX_FORM_SIZE {
+                        form_urlencoded::parse(&body)
+                            .find(|f| f.0 == FIELD_NAME)
+                            .map(|f| f.1.into_owned())
+                    } else {
+                        None
+                    };
+                    match field {
+                        Some(ref token) if self.is_valid(request, token) => {
+                            next(&request.with_data(body, true))
+                        }
+                        _ => forbidden(),
+                    }
+                } else {
+                    forbidden()
+                }
+            }
+        };
+
+        match (new_cookie, self.cookie_name.as_ref()) {
+            (Some(secret), Some(name)) => {
+                let mut cookie = SetCookie::new(name.clone(), secret)
+                    .path("/")
+                    .same_site(SameSite::Strict);
+                if request.is_secure() {
+                    cookie = cookie.secure();
+                }
+                response.with_cookie(cookie)
+            }
+            _ => response,
+        }
+    }
+}
+
+fn is_form(request: &Request) -> bool {
+    request.header("Content-Type").map_or(false, |value| {
+        value
+            .split(';')
+            .next()
+            .unwrap()
+            .trim()
+            .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+    })
+}
+
+#[inline]
+fn forbidden() -> Response {
+    Response::text("Invalid CSRF token").with_status_code(403)
+}
+
+fn digest(key: &str) -> [u8; 20] {
+    let mut hash = Sha1::new();
+    hash.update(b"rouille-csrf:");
+    hash.update(key.as_bytes());
+    hash.digest().bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CsrfMiddleware;
+    use middleware::Pipeline;
+    use session::{self, SessionMiddleware};
+    use std::io::Read;
+    use test::TestResponse;
+    use Request;
+    use Response;
+
+    fn request(method: &str, headers: &[(&str, &str)], body: &str) -> Request {
+        let headers = headers
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect();
+        Request::fake_http(method, "/", headers, body.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn session_tokens() {
+        let pipeline = Pipeline::new(|request: &Request| {
+            if request.method() == "POST" {
+                let mut body = String::new();
+                request.data().unwrap().read_to_string(&mut body).unwrap();
+                return Response::text(body);
+            }
+            session::with_current(|session| Response::text(super::token(request, session))).unwrap()
+        })
+        .with(SessionMiddleware::new("SID", 3600))
+        .with(CsrfMiddleware::new());
+
+        let response = pipeline.handle(&request("GET", &[], ""));
+        let cookie = response.headers.iter().find(|h| h.0 == "Set-Cookie");
+        let cookie = cookie.unwrap().1.split(';').next().unwrap().to_owned();
+        let token = TestResponse::from(response).text();
+        let other =
+            TestResponse::from(pipeline.handle(&request("GET", &[("Cookie", &cookie)], ""))).text();
+        assert_ne!(token, other);
+
+        let header = [("Cookie", &cookie[..]), ("X-CSRF-Token", &other[..])];
+        assert_eq!(
+            pipeline.handle(&request("POST", &header, "")).status_code,
+            200
+        );
+
+        let form = format!("a=b&csrf_token={}", token);
+        let content_type = ("Content-Type", "application/x-www-form-urlencoded");
+        let response = pipeline.handle(&request(
+            "POST",
+            &[("Cookie", &cookie), content_type],
+            &form,
+        ));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(TestResponse::from(response).text(), form);
+
+        let missing = pipeline.handle(&request("POST", &[("Cookie", &cookie)], ""));
+        assert_eq!(missing.status_code, 403);
+        let header = [("Cookie", "SID=other"), ("X-CSRF-Token", &token[..])];
+        assert_eq!(
+            pipeline.handle(&request("POST", &header, "")).status_code,
+            403
+        );
+        let header = [("Cookie", &cookie[..]), ("X-CSRF-Token", "abcd")];
+        assert_eq!(
+            pipeline.handle(&request("DELETE", &header, "")).status_code,
+            403
+        );
+    }
+
+    #[test]
+    fn double_submit() {
+        let pipeline = Pipeline::new(|request: &Request| {
+            Response::text(super::double_submit_token(request).unwrap())
+        })
+        .with(CsrfMiddleware::double_submit("csrf"));
+
+        let response = pipeline.handle(&request("GET", &[], ""));
+        let cookie = response.headers.iter().find(|h| h.0 == "Set-Cookie");
+        let cookie = cookie.unwrap().1.to_string();
+        assert!(cookie.contains("SameSite=Strict"));
+        assert!(!cookie.contains("HttpOnly"));
+        let cookie = cookie.split(';').next().unwrap().to_owned();
+        let token = TestResponse::from(response).text();
+        assert_eq!(cookie, format!("csrf={}", token));
+
+        let response = pipeline.handle(&request(
+            "PUT",
+            &[("Cookie", &cookie), ("X-CSRF-Token", &token)],
+            "",
+        ));
+        assert_eq!(response.status_code, 200);
+        assert!(!response.headers.iter().any(|h| h.0 == "Set-Cookie"));
+
+        let header = [("X-CSRF-Token", &token[..])];
+        assert_eq!(
+            pipeline.handle(&request("PUT", &header, "")).status_code,
+            403
+        );
+        let header = [("Cookie", &cookie[..]), ("X-CSRF-Token", "other")];
+        assert_eq!(
+            pipeline.handle(&request("PUT", &header, "")).status_code,
+            403
+        );
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::time::Duration;
+use std::time::Instant;
+
+use Request;
+
+/// The instant before which a request should be answered.
+///
+/// The server inserts this value in the [extensions](struct.Request.html#method.extensions) of
+/// the requests when [`Server::request_timeout`](struct.Server.html#method.request_timeout) or
+/// [`Server::trust_timeout_header`](struct.Server.html#method.trust_timeout_header) is used.
+/// A middleware can also insert or replace it. Handlers read it with
+/// [`Request::deadline`](struct.Request.html#method.deadline), and
+/// [`proxy`](proxy/fn.proxy.html) uses it to bound its own timeouts and passes the time left to
+/// the upstream server in the `X-Request-Timeout` header.
+///
+/// Nothing interrupts a handler that runs past its deadline. It is up to the handler to give up,
+/// but [`Server::respond_at_deadline`](struct.Server.html#method.respond_at_deadline) answers the
+/// client in its place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Deadline(pub Instant);
+
+/// Name of the header that contains the time that the caller is willing to wait for the response,
+/// in milliseconds.
+pub(crate) const TIMEOUT_HEADER: &str = "X-Request-Timeout";
+
+// Signature of the function registered with `Server::trust_timeout_header`.
+pub(crate) type TrustFn = dyn Fn(&Request) -> bool + Send + Sync;
+
+// Returns the deadline of a request received at `start`, which is the earliest of the timeout of
+// the server and of the timeout header if it is trusted.
+pub(crate) fn deadline(
+    request: &Request,
+    start: Instant,
+    timeout: Option<Duration>,
+    trust_header: Option<&TrustFn>,
+) -> Option<Deadline> {
+    let header = match trust_header {
+        Some(trusted) if trusted(request) => request
+            .header(TIMEOUT_HEADER)
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_millis),
+        _ => None,
+    };
+
+    let timeout = match (timeout, header) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    timeout.map(|timeout| Deadline(start + timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::deadline;
+    use super::Deadline;
+    use std::time::Duration;
+    use std::time::Instant;
+    use Request;
+
+    #[test]
+    fn sources() {
+        let start = Instant::now();
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("X-Request-Timeout".to_owned(), "250".to_owned())],
+            vec![],
+        );
+        let secs = Some(Duration::from_secs(1));
+        let at = |ms| Some(Deadline(start + Duration::from_millis(ms)));
+
+        assert_eq!(deadline(&request, start, None, None), None);
+        assert_eq!(deadline(&request, start, secs, None), at(1000));
+        assert_eq!(deadline(&request, start, None, Some(&|_| false)), None);
+        assert_eq!(deadline(&request, start, None, Some(&|_| true)), at(250));
+        assert_eq!(deadline(&request, start, secs, Some(&|_| true)), at(250));
+
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("X-Request-Timeout".to_owned(), "soon".to_owned())],
+            vec![],
+        );
+        assert_eq!(deadline(&request, start, secs, Some(&|_| true)), at(1000));
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Shortening the edit-refresh loop during development.
+//!
+//! This module is only available with the `dev` feature, and isn't meant to be used in
+//! production.
+//!
+//! [`watch`](fn.watch.html) wraps a handler that depends on a state derived from files, such as
+//! templates, a configuration or an [`AssetManifest`](../struct.AssetManifest.html). Before each
+//! request, it checks whether the files have changed, and rebuilds the state if they have. The
+//! responses get no-cache headers so that the browser always shows the latest version, and a
+//! panic of the handler or of the rebuild is shown in the browser with its backtrace instead of
+//! an empty `500` response.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::fs;
+//! use rouille::dev;
+//! use rouille::Response;
+//! use rouille::Server;
+//!
+//! let handler = dev::watch(
+//!     vec!["templates"],
+//!     || fs::read_to_string("templates/index.html").unwrap(),
+//!     |template, request| Response::html(template.replace("{{url}}", &request.url())),
+//! );
+//!
+//! let server = Server::new("localhost:8000", handler).unwrap();
+//! server.run();
+//! ```
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::fs;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Once;
+use std::time::SystemTime;
+
+use assets::escape_html;
+use panic_message;
+use Request;
+use Response;
+
+/// Turns a handler that takes a state built from files into a regular handler, which rebuilds
+/// the state with `build` whenever one of the files changes.
+///
+/// `paths` are the files and the directories to watch. The directories are watched recursively.
+/// The files are compared by modification time and size, before each request.
+///
+/// If `build` panics, the requests are answered with an error page until the files change
+/// again.
+pub fn watch<P, S, B, H>(paths: P, build: B, handler: H) -> impl Fn(&Request) -> Response
+where
+    P: IntoIterator,
+    P::Item: Into<PathBuf>,
+    B: Fn() -> S,
+    H: Fn(&S, &Request) -> Response,
+{
+    install_hook();
+    let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+    let current: Mutex<Option<Watched<S>>> = Mutex::new(None);
+
+    move |request: &Request| {
+        let state = {
+            let mut current = match current.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let signature = signature(&paths);
+            let outdated = match *current {
+                Some(ref watched) => watched.signature != signature,
+                None => true,
+            };
+            if outdated {
+                *current = Some(Watched {
+                    signature,
+                    state: catch(&build).map(Arc::new),
+                });
+            }
+            match current.as_ref().unwrap().state {
+                Ok(ref state) => state.clone(),
+                Err(ref failure) => {
+                    return failure
+                        .response("The state couldn't be built")
+                        .with_no_cache();
+                }
+            }
+        };
+
+        match catch(|| handler(&state, request)) {
+            Ok(response) => response.with_no_cache(),
+            Err(failure) => failure.response("The handler panicked").with_no_cache(),
+        }
+    }
+}
+
+// The state built from the files, and the files that it was built from.
+struct Watched<S> {
+    signature: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+    state: Result<Arc<S>, Failure>,
+}
+
+// Returns the modification time and the size of the files in `paths`, in a stable order.
+fn signature(paths: &[PathBuf]) -> Vec<(PathBuf, Option<(SystemTime, u64)>)> {
+    let mut signature = Vec::new();
+    let mut pending = paths.to_vec();
+    while let Some(path) = pending.pop() {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                signature.push((path, None));
+                continue;
+            }
+        };
+
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()));
+            }
+        } else {
+            let modified = metadata.modified().ok();
+            signature.push((path, modified.map(|m| (m, metadata.len()))));
+        }
+    }
+    signature.sort();
+    signature
+}
+
+// A panic, with its backtrace.
+struct Failure {
+    message: String,
+    location: Option<String>,
+    backtrace: Option<String>,
+}
+
+impl Failure {
+    fn from_payload(payload: &(dyn Any + Send)) -> Failure {
+        let (location, backtrace) = PANIC.with(|p| p.borrow_mut().take()).unwrap_or_default();
+        Failure {
+            message: panic_message(payload).unwrap_or("Box<dyn Any>").to_owned(),
+            location,
+            backtrace,
+        }
+    }
+
+    // Builds the error page.
+    fn response(&self, title: &str) -> Response {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+             <body>\n<h1>{0}</h1>\n<p><code>{1}</code></p>\n",
+            escape_html(title),
+            escape_html(&self.message)
+        );
+        if let Some(ref location) = self.location {
+            html.push_str(&format!("<p>at {}</p>\n", escape_html(location)));
+        }
+        if let Some(ref backtrace) = self.backtrace {
+            html.push_str(&format!("<pre>{}</pre>\n", escape_html(backtrace)));
+        }
+        html.push_str("</body></html>\n");
+        Response::html(html).with_status_code(500)
+    }
+}
+
+thread_local! {
+    // True while `catch` runs a function on this thread, in which case the panic hook saves the
+    // backtrace.
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+    // Location and backtrace of the last panic caught by `catch` on this thread.
+    static PANIC: RefCell<Option<(Option<String>, Option<String>)>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+// Installs a panic hook that saves the backtraces of the panics caught by `catch`, then calls the
+// previous hook.
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CAPTURING.with(|c| c.get()) {
+                let location = info.location().map(|l| l.to_string());
+                let backtrace = Backtrace::force_capture().to_string();
+                PANIC.with(|p| *p.borrow_mut() = Some((location, Some(backtrace))));
+            }
+            previous(info);
+        }));
+    });
+}
+
+// Calls `f`, and turns a panic into a `Failure`.
+fn catch<T, F>(f: F) -> Result<T, Failure>
+where
+    F: FnOnce() -> T,
+{
+    let capturing = CAPTURING.with(|c| c.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CAPTURING.with(|c| c.set(capturing));
+    result.map_err(|payload| Failure::from_payload(&*payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::watch;
+    use test::TestResponse;
+    use Request;
+    use Response;
+
+    #[test]
+    fn rebuilds() {
+        let dir = env::temp_dir().join(format!("rouille-dev-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let file = dir.join("sub/template.txt");
+        fs::write(&file, "hello").unwrap();
+
+        let builds = AtomicUsize::new(0);
+        let handler = watch(
+            vec![dir.clone()],
+            || {
+                builds.fetch_add(1, Ordering::Relaxed);
+                let content = fs::read_to_string(&file).unwrap();
+                assert!(content != "broken");
+                content
+            },
+            |content, _| Response::text(content.clone()),
+        );
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+
+        let response = handler(&request);
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("no-cache, no-store, must-revalidate")
+        );
+        assert_eq!(TestResponse::from(response).text(), "hello");
+        assert_eq!(TestResponse::from(handler(&request)).text(), "hello");
+        assert_eq!(builds.load(Ordering::Relaxed), 1);
+
+        fs::write(&file, "hello world").unwrap();
+        assert_eq!(TestResponse::from(handler(&request)).text(), "hello world");
+        assert_eq!(builds.load(Ordering::Relaxed), 2);
+
+        fs::write(&file, "broken").unwrap();
+        let response = handler(&request);
+        assert_eq!(response.status_code, 500);
+        assert!(TestResponse::from(response)
+            .text()
+            .contains("The state couldn&#39;t be built"));
+        assert_eq!(handler(&request).status_code, 500);
+        assert_eq!(builds.load(Ordering::Relaxed), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn panic_page() {
+        let handler = watch(
+            Vec::<String>::new(),
+            || (),
+            |_, request| {
+                if request.url() == "/panic" {
+                    panic!("<oops>");
+                }
+                Response::text("ok")
+            },
+        );
+
+        let response = handler(&Request::fake_http("GET", "/panic", vec![], vec![]));
+        assert_eq!(response.status_code, 500);
+        assert_eq!(response.content_type(), Some("text/html; charset=utf-8"));
+        let page = TestResponse::from(response).text();
+        assert!(page.contains("<code>&lt;oops&gt;</code>"));
+        assert!(page.contains("src/dev.rs"));
+        assert!(page.contains("<pre>"));
+
+        let response = handler(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+    }
+}
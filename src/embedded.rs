@@ -0,0 +1,217 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+
+use sha1::Sha1;
+
+use assets::extension_to_mime;
+use Request;
+use Response;
+
+/// A file whose content is embedded in the binary.
+///
+/// The fields are public so that the list of files can be generated by other means than the
+/// [`embedded_assets!`](macro.embedded_assets.html) macro, for example from the files of a crate
+/// such as `include_dir`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EmbeddedAsset {
+    /// Path of the file relative to the root of the assets, with `/` as separator and without a
+    /// leading slash, for example `css/style.css`.
+    pub path: &'static str,
+    /// Content of the file.
+    pub data: &'static [u8],
+}
+
+/// A set of files embedded in the binary, served like
+/// [`match_assets`](fn.match_assets.html) serves the files of a directory.
+///
+/// The ETag of each file is computed once, when building the `EmbeddedAssets`.
+///
+/// # Example
+///
+/// ```ignore
+/// #[macro_use]
+/// extern crate rouille;
+///
+/// fn main() {
+///     let assets = embedded_assets![
+///         "index.html" => include_bytes!("../public/index.html"),
+///         "css/style.css" => include_bytes!("../public/css/style.css"),
+///     ];
+///
+///     rouille::start_server("localhost:8000", move |request| {
+///         if let Some(request) = request.remove_prefix("/static") {
+///             return assets.serve(&request);
+///         }
+///         rouille::Response::empty_404()
+///     });
+/// }
+/// ```
+pub struct EmbeddedAssets {
+    // Indexed by path. The second element is the ETag.
+    files: HashMap<&'static str, (&'static [u8], String)>,
+}
+
+impl EmbeddedAssets {
+    /// Builds the set from a list of files. If multiple files have the same path, the last one
+    /// is used.
+    pub fn new<I>(assets: I) -> EmbeddedAssets
+    where
+        I: IntoIterator<Item = EmbeddedAsset>,
+    {
+        let files = assets
+            .into_iter()
+            .map(|asset| {
+                let mut hash = Sha1::new();
+                hash.update(asset.data);
+                let etag = format!("\"{}\"", hash.digest());
+                (asset.path.trim_start_matches('/'), (asset.data, etag))
+            })
+            .collect();
+
+        EmbeddedAssets { files }
+    }
+
+    /// Returns the content of the file at the given path, if it exists.
+    #[inline]
+    pub fn get(&self, path: &str) -> Option<&'static [u8]> {
+        self.files
+            .get(path.trim_start_matches('/'))
+            .map(|&(data, _)| data)
+    }
+
+    /// Returns the number of files.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns true if there is no file.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Searches for the file that matches the URL of the request, and returns it with the same
+    /// headers as `match_assets`: the content type based on the extension, an ETag and a public
+    /// cache of one hour. The `Range` and the conditional headers are honored as well.
+    ///
+    /// Returns a 404 response if there is no such file.
+    pub fn serve(&self, request: &Request) -> Response {
+        let url = request.url();
+        let path = url.trim_start_matches('/');
+        let &(data, ref etag) = match self.files.get(path) {
+            Some(file) => file,
+            None => return Response::empty_404(),
+        };
+
+        let extension = match path.rfind('.') {
+            Some(pos) if !path[pos..].contains('/') => &path[pos + 1..],
+            _ => "",
+        };
+
+        Response::from_range_with_validators(
+            request,
+            extension_to_mime(extension),
+            Cursor::new(data),
+            data.len() as u64,
+            Some(etag),
+            None,
+        )
+        .with_etag(request, etag.clone())
+        .with_public_cache(3600)
+    }
+}
+
+impl fmt::Debug for EmbeddedAssets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut paths: Vec<_> = self.files.keys().collect();
+        paths.sort();
+        f.debug_struct("EmbeddedAssets")
+            .field("paths", &paths)
+            .finish()
+    }
+}
+
+/// Builds an [`EmbeddedAssets`](struct.EmbeddedAssets.html) from a list of paths and contents.
+///
+/// The contents are usually given with `include_bytes!`, whose path is relative to the file
+/// that contains the macro call.
+///
+/// # Example
+///
+/// ```ignore
+/// let assets = embedded_assets![
+///     "index.html" => include_bytes!("../public/index.html"),
+///     "app.js" => include_bytes!("../public/app.js"),
+/// ];
+/// ```
+#[macro_export]
+macro_rules! embedded_assets {
+    ($($path:expr => $data:expr),* $(,)*) => {
+        $crate::EmbeddedAssets::new(vec![
+            $($crate::EmbeddedAsset { path: $path, data: $data }),*
+        ])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use Request;
+
+    #[test]
+    fn serve() {
+        let assets = embedded_assets![
+            "index.html" => b"<html>",
+            "css/style.css" => b"body {}",
+        ];
+        assert_eq!(assets.len(), 2);
+        assert_eq!(assets.get("/index.html"), Some(&b"<html>"[..]));
+
+        let get = |url: &str, headers: Vec<(&str, &str)>| {
+            let headers = headers
+                .into_iter()
+                .map(|(n, v)| (n.to_owned(), v.to_owned()))
+                .collect();
+            assets.serve(&Request::fake_http("GET", url, headers, vec![]))
+        };
+        let header = |response: &::Response, name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|h| h.0 == name)
+                .map(|h| h.1.to_string())
+        };
+
+        let response = get("/css/style.css", vec![]);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            header(&response, "Content-Type").unwrap(),
+            "text/css; charset=utf8"
+        );
+        let etag = header(&response, "ETag").unwrap();
+
+        assert_eq!(
+            get("/css/style.css", vec![("If-None-Match", &etag)]).status_code,
+            304
+        );
+        assert_eq!(get("/missing.css", vec![]).status_code, 404);
+
+        let response = get("/css/style.css", vec![("Range", "bytes=0-3")]);
+        assert_eq!(response.status_code, 206);
+        let mut body = String::new();
+        let (mut reader, _) = response.data.into_reader_and_size();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "body");
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Container of values attached to a request, indexed by their type.
+///
+/// It contains at most one value of each type. Middlewares typically store information such as
+/// the authenticated user or an identifier of the request, which the handler then retrieves.
+///
+/// Use [`Request::extensions`](struct.Request.html#method.extensions) to access it.
+///
+/// # Example
+///
+/// ```
+/// use rouille::Extensions;
+///
+/// #[derive(Debug, PartialEq)]
+/// struct UserId(u32);
+///
+/// let mut extensions = Extensions::new();
+/// extensions.insert(UserId(5));
+/// assert_eq!(extensions.get::<UserId>(), Some(&UserId(5)));
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Builds a new empty container.
+    #[inline]
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Inserts a value. Returns the previous value of the same type, if any.
+    pub fn insert<T>(&mut self, value: T) -> Option<T>
+    where
+        T: Any + Send + Sync,
+    {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|prev| (prev as Box<dyn Any + Send>).downcast().ok())
+            .map(|prev| *prev)
+    }
+
+    /// Returns a reference to the value of type `T`, if any.
+    #[inline]
+    pub fn get<T>(&self) -> Option<&T>
+    where
+        T: Any + Send + Sync,
+    {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_ref())
+    }
+
+    /// Returns a mutable reference to the value of type `T`, if any.
+    #[inline]
+    pub fn get_mut<T>(&mut self) -> Option<&mut T>
+    where
+        T: Any + Send + Sync,
+    {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|v| v.downcast_mut())
+    }
+
+    /// Returns true if there is a value of type `T`.
+    #[inline]
+    pub fn contains<T>(&self) -> bool
+    where
+        T: Any + Send + Sync,
+    {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Removes the value of type `T` and returns it, if any.
+    pub fn remove<T>(&mut self) -> Option<T>
+    where
+        T: Any + Send + Sync,
+    {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|v| (v as Box<dyn Any + Send>).downcast().ok())
+            .map(|v| *v)
+    }
+
+    /// Removes all the values.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Extensions;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut extensions = Extensions::new();
+        assert_eq!(extensions.insert(5u32), None);
+        assert_eq!(extensions.insert(6u32), Some(5));
+        assert_eq!(extensions.insert("hello"), None);
+
+        assert_eq!(extensions.get::<u32>(), Some(&6));
+        assert_eq!(extensions.get::<&str>(), Some(&"hello"));
+        assert_eq!(extensions.get::<u64>(), None);
+
+        *extensions.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(extensions.remove::<u32>(), Some(7));
+        assert!(!extensions.contains::<u32>());
+        assert!(extensions.contains::<&str>());
+    }
+}
@@ -0,0 +1,780 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Dispatch a request to a FastCGI server, such as php-fpm.
+//!
+//! Contrary to the [`cgi`](../cgi/index.html) module, which starts a new process for each
+//! request, FastCGI talks to an application that keeps running in the background and listens on
+//! a socket. A new connection to the application is opened for each request.
+//!
+//! The `fastcgi()` function sends the request with the CGI variables, streams the body of the
+//! request to the application, and returns once the application has sent the headers of its
+//! response. The body of the response is streamed to the client as the application produces it.
+//!
+//! Only TCP addresses are supported, so php-fpm must be configured to listen on one, for example
+//! with `listen = 127.0.0.1:9000`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rouille::fastcgi;
+//! use rouille::fastcgi::FastCgiConfig;
+//!
+//! rouille::start_server("localhost:8080", move |request| {
+//!     let config = FastCgiConfig::new("127.0.0.1:9000")
+//!         .document_root("/var/www")
+//!         .script_filename("/var/www/index.php");
+//!     fastcgi::fastcgi(request, config).unwrap()
+//! });
+//! ```
+//!
+//! # About the Result returned by fastcgi
+//!
+//! Like with `cgi`, the `fastcgi()` function returns an error if and only if the application
+//! couldn't be reached or didn't follow the protocol. An error 404 returned by the application
+//! is an `Ok`.
+
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::Error as IoError;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use Request;
+use Response;
+use ResponseBody;
+
+/// Error that can happen when dispatching a request to a FastCGI application.
+#[derive(Debug)]
+pub enum FastCgiError {
+    /// Can't pass through the body of the request because it was already extracted.
+    BodyAlreadyExtracted,
+
+    /// Could not read the body from the request, or could not connect to the application, or
+    /// the connection to the application closed unexpectedly.
+    IoError(IoError),
+
+    /// The application didn't follow the FastCGI protocol, or returned invalid headers.
+    ProtocolError,
+}
+
+impl From<IoError> for FastCgiError {
+    fn from(err: IoError) -> FastCgiError {
+        FastCgiError::IoError(err)
+    }
+}
+
+impl error::Error for FastCgiError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            FastCgiError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FastCgiError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            FastCgiError::BodyAlreadyExtracted => "the body of the request was already extracted",
+            FastCgiError::IoError(_) => {
+                "could not read the body from the request, or could not connect to the \
+                 application, or the connection to the application closed unexpectedly"
+            }
+            FastCgiError::ProtocolError => "the application didn't follow the FastCGI protocol",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+type StderrFn = dyn Fn() -> Box<dyn Write> + Send + Sync;
+
+/// Configuration of the dispatch to a FastCGI application.
+///
+/// The script that the application runs is given by the `SCRIPT_FILENAME` variable. If
+/// `script_filename` is set, all the requests are handled by this script, which receives the
+/// path of the request in `PATH_INFO`. This is how the front controllers of most PHP frameworks
+/// work. Otherwise, the path of the request is looked up in the `document_root`, and requests
+/// with `..` in their path are answered with a 404 error.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use rouille::fastcgi::FastCgiConfig;
+///
+/// let config = FastCgiConfig::new("127.0.0.1:9000")
+///     .document_root("/var/www")
+///     .param("APP_ENV", "production")
+///     .timeout(Duration::from_secs(30));
+/// ```
+#[derive(Clone)]
+pub struct FastCgiConfig<A> {
+    /// The address of the application. For example `127.0.0.1:9000`. If it resolves to several
+    /// addresses, they are tried until one of them accepts the connection.
+    pub addr: A,
+    document_root: Option<PathBuf>,
+    script_filename: Option<PathBuf>,
+    params: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    stderr: Option<Arc<StderrFn>>,
+}
+
+impl<A> FastCgiConfig<A> {
+    /// Builds a configuration that sends the requests to the application at `addr`.
+    #[inline]
+    pub fn new(addr: A) -> FastCgiConfig<A> {
+        FastCgiConfig {
+            addr,
+            document_root: None,
+            script_filename: None,
+            params: Vec::new(),
+            timeout: None,
+            stderr: None,
+        }
+    }
+
+    /// Sets the directory that contains the scripts, as seen by the application. It is passed
+    /// in `DOCUMENT_ROOT`.
+    #[inline]
+    pub fn document_root<P>(mut self, root: P) -> FastCgiConfig<A>
+    where
+        P: Into<PathBuf>,
+    {
+        self.document_root = Some(root.into());
+        self
+    }
+
+    /// Handles all the requests with the script at `path`, as seen by the application.
+    #[inline]
+    pub fn script_filename<P>(mut self, path: P) -> FastCgiConfig<A>
+    where
+        P: Into<PathBuf>,
+    {
+        self.script_filename = Some(path.into());
+        self
+    }
+
+    /// Passes an additional variable to the application. It takes precedence over the CGI
+    /// variables with the same name.
+    #[inline]
+    pub fn param<N, V>(mut self, name: N, value: V) -> FastCgiConfig<A>
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the time that the application has to accept the connection, and then to receive or
+    /// to send each part of the request and of the response. Defaults to 60 seconds.
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> FastCgiConfig<A> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Writes each line of the error stream of the application to the writer returned by
+    /// `output`, prefixed with the method and the URL of the request. By default, the error
+    /// stream is discarded.
+    ///
+    /// Like with [`Logger`](../struct.Logger.html), `output` is called for each line.
+    pub fn log_stderr<F, W>(mut self, output: F) -> FastCgiConfig<A>
+    where
+        F: Fn() -> W + Send + Sync + 'static,
+        W: Write + 'static,
+    {
+        self.stderr = Some(Arc::new(move || Box::new(output()) as Box<dyn Write>));
+        self
+    }
+}
+
+impl<A> fmt::Debug for FastCgiConfig<A>
+where
+    A: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FastCgiConfig")
+            .field("addr", &self.addr)
+            .field("document_root", &self.document_root)
+            .field("script_filename", &self.script_filename)
+            .field("params", &self.params)
+            .field("timeout", &self.timeout)
+            .field("log_stderr", &self.stderr.is_some())
+            .finish()
+    }
+}
+
+// Types of the records, from the specification.
+const FCGI_BEGIN_REQUEST: u8 = 1;
+const FCGI_END_REQUEST: u8 = 3;
+const FCGI_PARAMS: u8 = 4;
+const FCGI_STDIN: u8 = 5;
+const FCGI_STDOUT: u8 = 6;
+const FCGI_STDERR: u8 = 7;
+const FCGI_RESPONDER: u16 = 1;
+
+// A connection only carries one request, whose identifier is always 1.
+const REQUEST_ID: u16 = 1;
+
+/// Sends the request to a FastCGI application, and returns its response once it has sent the
+/// headers.
+///
+/// The body of the returned `Response` holds the connection to the application, and is read as
+/// the application sends it.
+pub fn fastcgi<A>(request: &Request, config: FastCgiConfig<A>) -> Result<Response, FastCgiError>
+where
+    A: ToSocketAddrs,
+{
+    let params = match cgi_params(request, &config) {
+        Some(params) => params,
+        None => return Ok(Response::empty_404()),
+    };
+    let mut body = match request.data() {
+        Some(body) => body,
+        None => return Err(FastCgiError::BodyAlreadyExtracted),
+    };
+
+    let timeout = config.timeout.unwrap_or_else(|| Duration::from_secs(60));
+    let socket = connect(&config.addr, timeout)?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.set_write_timeout(Some(timeout))?;
+
+    {
+        let mut writer = io::BufWriter::new(&socket);
+        let mut begin = [0; 8];
+        begin[..2].copy_from_slice(&FCGI_RESPONDER.to_be_bytes());
+        write_record(&mut writer, FCGI_BEGIN_REQUEST, &begin)?;
+
+        let mut encoded = Vec::new();
+        for (name, value) in &params {
+            encode_param(&mut encoded, name.as_bytes(), value.as_bytes());
+        }
+        for chunk in encoded.chunks(0xffff) {
+            write_record(&mut writer, FCGI_PARAMS, chunk)?;
+        }
+        write_record(&mut writer, FCGI_PARAMS, &[])?;
+
+        let mut buffer = vec![0; 0x8000];
+        loop {
+            let n = match body.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            write_record(&mut writer, FCGI_STDIN, &buffer[..n])?;
+        }
+        write_record(&mut writer, FCGI_STDIN, &[])?;
+        writer.flush()?;
+    }
+
+    let mut stdout = io::BufReader::new(Stdout {
+        socket,
+        remaining: 0,
+        padding: 0,
+        ended: false,
+        stderr: config.stderr.map(|output| {
+            (
+                output,
+                format!("{} {}", request.method(), request.raw_url()),
+            )
+        }),
+    });
+
+    let mut status_code = None;
+    let mut headers: Vec<(Cow<'static, str>, Cow<'static, str>)> = Vec::new();
+    loop {
+        let mut line = String::new();
+        match stdout.read_line(&mut line) {
+            Ok(0) => return Err(FastCgiError::ProtocolError),
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+                return Err(FastCgiError::ProtocolError)
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+        if line.is_empty() {
+            break;
+        }
+
+        let mut split = line.splitn(2, ':');
+        let name = split.next().unwrap_or("").trim();
+        let value = match split.next() {
+            Some(value) => value.trim(),
+            None => return Err(FastCgiError::ProtocolError),
+        };
+        if name.eq_ignore_ascii_case("Status") {
+            let code = value.get(..3).and_then(|code| code.parse().ok());
+            match code {
+                Some(code) if (100..1000).contains(&code) => status_code = Some(code),
+                _ => return Err(FastCgiError::ProtocolError),
+            }
+        } else {
+            headers.push((name.to_owned().into(), value.to_owned().into()));
+        }
+    }
+
+    // A CGI script that redirects without a status asks for a 302.
+    let status_code = status_code.unwrap_or_else(|| {
+        if headers.iter().any(|h| h.0.eq_ignore_ascii_case("Location")) {
+            302
+        } else {
+            200
+        }
+    });
+
+    Ok(Response {
+        status_code,
+        headers,
+        data: ResponseBody::from_reader(stdout),
+        upgrade: None,
+    })
+}
+
+// Builds the variables of the request, or returns `None` if the path of the request tries to
+// leave the document root.
+fn cgi_params<A>(request: &Request, config: &FastCgiConfig<A>) -> Option<Vec<(String, String)>> {
+    let url = request.url();
+    let (script_name, script_filename, path_info) = match config.script_filename {
+        Some(ref script) => {
+            let name = script
+                .file_name()
+                .map(|name| format!("/{}", name.to_string_lossy()))
+                .unwrap_or_default();
+            (name, script.to_string_lossy().into_owned(), url.clone())
+        }
+        None => {
+            let relative = Path::new(url.trim_start_matches('/'));
+            if relative.components().any(|c| c == Component::ParentDir) {
+                return None;
+            }
+            let filename = match config.document_root {
+                Some(ref root) => root.join(relative),
+                None => relative.to_owned(),
+            };
+            (
+                url.clone(),
+                filename.to_string_lossy().into_owned(),
+                String::new(),
+            )
+        }
+    };
+
+    let host = request.header("Host").unwrap_or("localhost");
+    let (server_name, server_port) = match host.rfind(':') {
+        Some(pos) if !host.ends_with(']') => (&host[..pos], host[pos + 1..].to_owned()),
+        _ => (
+            host,
+            if request.is_secure() { "443" } else { "80" }.to_owned(),
+        ),
+    };
+
+    let mut params = vec![
+        ("GATEWAY_INTERFACE".to_owned(), "CGI/1.1".to_owned()),
+        ("SERVER_SOFTWARE".to_owned(), "rouille".to_owned()),
+        ("SERVER_PROTOCOL".to_owned(), "HTTP/1.1".to_owned()),
+        ("SERVER_NAME".to_owned(), server_name.to_owned()),
+        ("SERVER_PORT".to_owned(), server_port),
+        ("REQUEST_METHOD".to_owned(), request.method().to_owned()),
+        ("REQUEST_URI".to_owned(), request.raw_url().to_owned()),
+        (
+            "QUERY_STRING".to_owned(),
+            request.raw_query_string().to_owned(),
+        ),
+        ("SCRIPT_NAME".to_owned(), script_name),
+        ("SCRIPT_FILENAME".to_owned(), script_filename),
+        ("PATH_INFO".to_owned(), path_info),
+        ("REMOTE_ADDR".to_owned(), request.client_addr().to_string()),
+        (
+            "REMOTE_PORT".to_owned(),
+            request.remote_addr().port().to_string(),
+        ),
+        (
+            "CONTENT_TYPE".to_owned(),
+            request.header("Content-Type").unwrap_or("").to_owned(),
+        ),
+        (
+            "CONTENT_LENGTH".to_owned(),
+            request.header("Content-Length").unwrap_or("").to_owned(),
+        ),
+    ];
+    if let Some(ref root) = config.document_root {
+        params.push((
+            "DOCUMENT_ROOT".to_owned(),
+            root.to_string_lossy().into_owned(),
+        ));
+    }
+    if request.is_secure() {
+        params.push(("HTTPS".to_owned(), "on".to_owned()));
+    }
+
+    for (name, value) in request.headers() {
+        // `Content-Type` and `Content-Length` have their own variables, and `HTTP_PROXY` would
+        // be mistaken for the configuration of the application by many HTTP clients.
+        if name.eq_ignore_ascii_case("Content-Type")
+            || name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Proxy")
+        {
+            continue;
+        }
+        let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+        match params.iter_mut().find(|p| p.0 == name) {
+            // Headers that appear several times are joined like by a proxy.
+            Some(param) => {
+                param.1.push_str(", ");
+                param.1.push_str(value);
+            }
+            None => params.push((name, value.to_owned())),
+        }
+    }
+
+    for (name, value) in &config.params {
+        params.retain(|p| p.0 != *name);
+        params.push((name.clone(), value.clone()));
+    }
+
+    Some(params)
+}
+
+fn connect<A>(addr: &A, timeout: Duration) -> io::Result<TcpStream>
+where
+    A: ToSocketAddrs,
+{
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(socket) => return Ok(socket),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the address of the application didn't resolve to anything",
+        )
+    }))
+}
+
+fn write_record<W>(mut writer: W, ty: u8, content: &[u8]) -> io::Result<()>
+where
+    W: Write,
+{
+    debug_assert!(content.len() <= 0xffff);
+    // The content is padded to a multiple of 8 bytes, as recommended.
+    let padding = (8 - content.len() % 8) % 8;
+    let id = REQUEST_ID.to_be_bytes();
+    let len = (content.len() as u16).to_be_bytes();
+    writer.write_all(&[1, ty, id[0], id[1], len[0], len[1], padding as u8, 0])?;
+    writer.write_all(content)?;
+    writer.write_all(&[0; 7][..padding])
+}
+
+// Appends a name-value pair, whose lengths are encoded on one byte if they are below 128 and on
+// four bytes otherwise.
+fn encode_param(output: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    for len in &[name.len(), value.len()] {
+        if *len < 0x80 {
+            output.push(*len as u8);
+        } else {
+            output.extend_from_slice(&(*len as u32 | 0x8000_0000).to_be_bytes());
+        }
+    }
+    output.extend_from_slice(name);
+    output.extend_from_slice(value);
+}
+
+// Content of the `FCGI_STDOUT` records of the response, until the end of the request.
+struct Stdout {
+    socket: TcpStream,
+    // Bytes of content left in the current `FCGI_STDOUT` record.
+    remaining: usize,
+    // Padding of the current `FCGI_STDOUT` record.
+    padding: usize,
+    ended: bool,
+    stderr: Option<(Arc<StderrFn>, String)>,
+}
+
+impl Stdout {
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        let copied = io::copy(&mut (&self.socket).take(len as u64), &mut io::sink())?;
+        if copied != len as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl Read for Stdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.ended || buf.is_empty() {
+                return Ok(0);
+            }
+
+            if self.remaining != 0 {
+                let len = buf.len().min(self.remaining);
+                let n = self.socket.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.remaining -= n;
+                if self.remaining == 0 {
+                    let padding = self.padding;
+                    self.skip(padding)?;
+                }
+                return Ok(n);
+            }
+
+            let mut header = [0; 8];
+            self.socket.read_exact(&mut header)?;
+            if header[0] != 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "unsupported FastCGI version",
+                ));
+            }
+            let id = u16::from_be_bytes([header[2], header[3]]);
+            let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let padding = usize::from(header[6]);
+
+            match header[1] {
+                FCGI_STDOUT if id == REQUEST_ID => {
+                    self.remaining = len;
+                    self.padding = padding;
+                    if len == 0 {
+                        self.skip(padding)?;
+                    }
+                }
+                FCGI_STDERR if id == REQUEST_ID && self.stderr.is_some() => {
+                    let mut content = vec![0; len + padding];
+                    self.socket.read_exact(&mut content)?;
+                    content.truncate(len);
+                    let (ref output, ref prefix) = *self.stderr.as_ref().unwrap();
+                    let text = String::from_utf8_lossy(&content);
+                    for line in text.lines().filter(|line| !line.is_empty()) {
+                        let _ = writeln!(output(), "{} - {}", prefix, line);
+                    }
+                }
+                FCGI_END_REQUEST if id == REQUEST_ID => {
+                    self.skip(len + padding)?;
+                    self.ended = true;
+                }
+                // Other records, such as the management ones, are ignored.
+                _ => self.skip(len + padding)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::encode_param;
+    use super::fastcgi;
+    use super::write_record;
+    use super::FastCgiConfig;
+    use super::FCGI_END_REQUEST;
+    use super::FCGI_PARAMS;
+    use super::FCGI_STDERR;
+    use super::FCGI_STDIN;
+    use super::FCGI_STDOUT;
+    use Request;
+
+    // The records received by the fake application, by type.
+    type Received = HashMap<u8, Vec<u8>>;
+
+    // Reads the records of a request, then answers with `stdout`.
+    fn application(stdout: &'static [&'static [u8]], received: Arc<Mutex<Received>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            loop {
+                let mut header = [0; 8];
+                socket.read_exact(&mut header).unwrap();
+                let len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+                let mut content = vec![0; len + usize::from(header[6])];
+                socket.read_exact(&mut content).unwrap();
+                content.truncate(len);
+                let mut received = received.lock().unwrap();
+                received.entry(header[1]).or_default().extend(content);
+                if header[1] == FCGI_STDIN && len == 0 {
+                    break;
+                }
+            }
+
+            write_record(&mut socket, FCGI_STDERR, b"warning\n").unwrap();
+            for chunk in stdout {
+                write_record(&mut socket, FCGI_STDOUT, chunk).unwrap();
+            }
+            write_record(&mut socket, FCGI_STDOUT, b"").unwrap();
+            write_record(&mut socket, FCGI_END_REQUEST, &[0; 8]).unwrap();
+            let _ = socket.flush();
+            // Waiting for the client to close the connection.
+            let _ = socket.read(&mut [0]);
+        });
+        addr
+    }
+
+    fn decode_params(mut data: &[u8]) -> HashMap<String, String> {
+        fn len(data: &mut &[u8]) -> usize {
+            if data[0] < 0x80 {
+                let len = usize::from(data[0]);
+                *data = &data[1..];
+                len
+            } else {
+                let len = u32::from_be_bytes([data[0] & 0x7f, data[1], data[2], data[3]]);
+                *data = &data[4..];
+                len as usize
+            }
+        }
+
+        let mut params = HashMap::new();
+        while !data.is_empty() {
+            let name_len = len(&mut data);
+            let value_len = len(&mut data);
+            let name = String::from_utf8(data[..name_len].to_vec()).unwrap();
+            let value = String::from_utf8(data[name_len..name_len + value_len].to_vec()).unwrap();
+            data = &data[name_len + value_len..];
+            params.insert(name, value);
+        }
+        params
+    }
+
+    #[test]
+    fn param_encoding() {
+        let mut output = Vec::new();
+        let long = vec![b'a'; 200];
+        encode_param(&mut output, b"NAME", &long);
+        assert_eq!(&output[..5], &[4, 0x80, 0, 0, 200]);
+        assert_eq!(
+            decode_params(&output).get("NAME").unwrap().len(),
+            long.len()
+        );
+    }
+
+    #[test]
+    fn request_and_response() {
+        let received = Arc::new(Mutex::new(HashMap::new()));
+        let addr = application(
+            &[
+                b"Status: 201 Created\r\nContent-Type: text/plain\r\nX-Po",
+                b"wered-By: test\r\n\r\nhello ",
+                b"world",
+            ],
+            received.clone(),
+        );
+
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let output = logs.clone();
+        let config = FastCgiConfig::new(addr)
+            .document_root("/var/www")
+            .script_filename("/var/www/index.php")
+            .param("APP_ENV", "test")
+            .log_stderr(move || Shared(output.clone()));
+        let request = Request::fake_http(
+            "POST",
+            "/users?page=2",
+            vec![
+                ("Host".to_owned(), "example.com:8080".to_owned()),
+                ("X-Custom".to_owned(), "a".to_owned()),
+                ("Proxy".to_owned(), "evil".to_owned()),
+            ],
+            b"request body".to_vec(),
+        );
+
+        let response = fastcgi(&request, config).unwrap();
+        assert_eq!(response.status_code, 201);
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.0 == "X-Powered-By" && h.1 == "test"));
+        let (mut body, _) = response.data.into_reader_and_size();
+        let mut text = String::new();
+        body.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "hello world");
+        assert_eq!(&logs.lock().unwrap()[..], b"POST /users?page=2 - warning\n");
+
+        let received = received.lock().unwrap();
+        assert_eq!(&received[&FCGI_STDIN][..], b"request body");
+        let params = decode_params(&received[&FCGI_PARAMS]);
+        assert_eq!(params["SCRIPT_FILENAME"], "/var/www/index.php");
+        assert_eq!(params["SCRIPT_NAME"], "/index.php");
+        assert_eq!(params["PATH_INFO"], "/users");
+        assert_eq!(params["QUERY_STRING"], "page=2");
+        assert_eq!(params["REQUEST_METHOD"], "POST");
+        assert_eq!(params["SERVER_NAME"], "example.com");
+        assert_eq!(params["SERVER_PORT"], "8080");
+        assert_eq!(params["HTTP_X_CUSTOM"], "a");
+        assert_eq!(params["APP_ENV"], "test");
+        assert!(!params.contains_key("HTTP_PROXY"));
+    }
+
+    #[test]
+    fn document_root() {
+        let received = Arc::new(Mutex::new(HashMap::new()));
+        let addr = application(&[b"Location: /login\r\n\r\n"], received.clone());
+        let config = FastCgiConfig::new(addr).document_root("/var/www");
+        let request = Request::fake_http("GET", "/admin/index.php", vec![], vec![]);
+        let response = fastcgi(&request, config).unwrap();
+        assert_eq!(response.status_code, 302);
+        let params = decode_params(&received.lock().unwrap()[&FCGI_PARAMS]);
+        assert_eq!(params["SCRIPT_FILENAME"], "/var/www/admin/index.php");
+        assert_eq!(params["SCRIPT_NAME"], "/admin/index.php");
+
+        // The connection isn't even opened.
+        let config = FastCgiConfig::new("127.0.0.1:1").document_root("/var/www");
+        let request = Request::fake_http("GET", "/../etc/passwd", vec![], vec![]);
+        assert_eq!(fastcgi(&request, config).unwrap().status_code, 404);
+    }
+
+    #[test]
+    fn connection_refused() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert!(fastcgi(&request, FastCgiConfig::new(addr)).is_err());
+    }
+
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> ::std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
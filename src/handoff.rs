@@ -0,0 +1,254 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Replacing a running server with a new binary without closing its listening sockets.
+//!
+//! The running process starts its successor with [`Successor::spawn`](struct.Successor.html),
+//! which passes the listening sockets to the new process. The successor retrieves them with
+//! [`inherited`](fn.inherited.html), starts its server, and calls [`ready`](fn.ready.html).
+//! Once the successor is ready, the old process stops serving, waits for the requests in
+//! flight to finish, and exits. Since the listening sockets stay open during the whole
+//! process, no connection is refused.
+//!
+//! Only available on Unix platforms.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::net::TcpListener;
+//! use std::time::Duration;
+//! use rouille::{Response, Server};
+//! use rouille::handoff::{self, Successor};
+//!
+//! # fn upgrade_requested() -> bool { unimplemented!() }
+//! let listener = match handoff::inherited().unwrap().pop() {
+//!     Some(listener) => listener,
+//!     None => TcpListener::bind("0.0.0.0:80").unwrap(),
+//! };
+//! let handoff_listener = listener.try_clone().unwrap();
+//!
+//! let server = Server::from_listener(listener, |request| {
+//!     Response::text("hello world")
+//! }).unwrap();
+//! handoff::ready().unwrap();
+//!
+//! // Serve until someone asks for an upgrade, for example by replacing the binary and sending a
+//! // signal to the process.
+//! while !upgrade_requested() {
+//!     server.poll_timeout(Duration::from_millis(100));
+//! }
+//!
+//! let mut successor = Successor::spawn(&[&handoff_listener]).unwrap();
+//! successor.wait_ready(Duration::from_secs(30)).unwrap();
+//!
+//! // Process the connections that were already accepted, then the requests in flight.
+//! server.poll_timeout(Duration::from_millis(100));
+//! server.join();
+//! ```
+
+use socket2::SockRef;
+use std::env;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::io::RawFd;
+use std::os::unix::net::UnixStream;
+use std::process::Child;
+use std::process::Command;
+use std::time::Duration;
+
+// Comma-separated list of the file descriptors of the listening sockets.
+const LISTEN_FDS: &str = "ROUILLE_LISTEN_FDS";
+// File descriptor of the socket that the successor uses to signal that it is ready.
+const READY_FD: &str = "ROUILLE_READY_FD";
+
+/// Returns the listening sockets passed by the predecessor of this process, in the order in
+/// which they were passed to `Successor::spawn`.
+///
+/// Returns an empty list if the process wasn't started by `Successor::spawn`. Only the first
+/// call returns the sockets.
+pub fn inherited() -> io::Result<Vec<TcpListener>> {
+    let fds = match env::var(LISTEN_FDS) {
+        Ok(fds) => fds,
+        Err(_) => return Ok(Vec::new()),
+    };
+    env::remove_var(LISTEN_FDS);
+
+    parse_fds(&fds)?
+        .into_iter()
+        .map(|fd| {
+            let listener = from_inherited_fd::<TcpListener>(fd);
+            SockRef::from(&listener).set_cloexec(true)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+/// Tells the predecessor of this process that it is ready to serve, so that it can stop.
+///
+/// Does nothing if the process wasn't started by `Successor::spawn`.
+pub fn ready() -> io::Result<()> {
+    let fd = match env::var(READY_FD) {
+        Ok(fd) => fd,
+        Err(_) => return Ok(()),
+    };
+    env::remove_var(READY_FD);
+
+    let fd = parse_fds(&fd)?;
+    let mut stream = from_inherited_fd::<UnixStream>(fd[0]);
+    stream.write_all(b"1")
+}
+
+/// A new process, started to replace the current one.
+pub struct Successor {
+    child: Child,
+    ready: UnixStream,
+}
+
+impl Successor {
+    /// Starts the current executable again with the same arguments, and passes the listening
+    /// sockets to it.
+    pub fn spawn(listeners: &[&TcpListener]) -> io::Result<Successor> {
+        let mut command = Command::new(env::current_exe()?);
+        command.args(env::args_os().skip(1));
+        Successor::spawn_command(command, listeners)
+    }
+
+    /// Same as `spawn`, but runs the given command. Use this to start a binary from another path
+    /// or with other arguments.
+    pub fn spawn_command(
+        mut command: Command,
+        listeners: &[&TcpListener],
+    ) -> io::Result<Successor> {
+        // The sockets are duplicated so that only the duplicates are inherited by the child.
+        let mut inherited = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            let listener = listener.try_clone()?;
+            SockRef::from(&listener).set_cloexec(false)?;
+            inherited.push(listener);
+        }
+
+        let (ready, child_ready) = UnixStream::pair()?;
+        SockRef::from(&child_ready).set_cloexec(false)?;
+
+        let fds: Vec<_> = inherited
+            .iter()
+            .map(|l| l.as_raw_fd().to_string())
+            .collect();
+        command.env(LISTEN_FDS, fds.join(","));
+        command.env(READY_FD, child_ready.as_raw_fd().to_string());
+        let child = command.spawn()?;
+
+        Ok(Successor { child, ready })
+    }
+
+    /// Waits until the successor calls [`ready`](fn.ready.html).
+    ///
+    /// Returns an error if the successor exits before being ready, or if it isn't ready after
+    /// `timeout`. In that case the current process should keep serving.
+    pub fn wait_ready(&mut self, timeout: Duration) -> io::Result<()> {
+        self.ready.set_read_timeout(Some(timeout))?;
+        match self.ready.read(&mut [0]) {
+            Ok(0) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the successor exited before being ready",
+            )),
+            Ok(_) => Ok(()),
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the successor isn't ready",
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the process ID of the successor.
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Returns the underlying `Child`, for example to kill the successor if it isn't ready.
+    #[inline]
+    pub fn into_child(self) -> Child {
+        self.child
+    }
+}
+
+impl fmt::Debug for Successor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Successor")
+            .field("id", &self.child.id())
+            .finish()
+    }
+}
+
+pub(crate) fn parse_fds(fds: &str) -> io::Result<Vec<RawFd>> {
+    fds.split(',')
+        .map(|fd| {
+            fd.trim().parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid file descriptor in the environment",
+                )
+            })
+        })
+        .collect()
+}
+
+#[allow(unsafe_code)]
+pub(crate) fn from_inherited_fd<T: FromRawFd>(fd: RawFd) -> T {
+    // The predecessor passed this file descriptor to us, and the environment variable that
+    // contains it was removed, so nothing else takes ownership of it.
+    unsafe { T::from_raw_fd(fd) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_fds;
+    use super::Successor;
+    use std::net::TcpListener;
+    use std::process::Command;
+    use std::time::Duration;
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    #[test]
+    fn fds() {
+        assert_eq!(parse_fds("3").unwrap(), vec![3]);
+        assert_eq!(parse_fds("3, 4").unwrap(), vec![3, 4]);
+        assert!(parse_fds("").is_err());
+        assert!(parse_fds("a").is_err());
+    }
+
+    #[test]
+    fn ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let command = shell("test -n \"$ROUILLE_LISTEN_FDS\" && printf 1 >&$ROUILLE_READY_FD");
+        let mut successor = Successor::spawn_command(command, &[&listener]).unwrap();
+        successor.wait_ready(Duration::from_secs(10)).unwrap();
+        successor.into_child().wait().unwrap();
+    }
+
+    #[test]
+    fn exited_before_ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut successor = Successor::spawn_command(shell("exit 1"), &[&listener]).unwrap();
+        assert!(successor.wait_ready(Duration::from_secs(10)).is_err());
+        successor.into_child().wait().unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+/// Limits on the headers of the requests accepted by a `Server`.
+///
+/// Pass this struct to [`Server::header_limits`](struct.Server.html#method.header_limits).
+/// Requests that exceed one of the limits are answered with
+/// `431 Request Header Fields Too Large` without calling the handler.
+///
+/// The size of a header is the length of its name and value, plus the four bytes of the `: `
+/// separator and the line ending.
+///
+/// # Example
+///
+/// ```no_run
+/// use rouille::{HeaderLimits, Response, Server};
+///
+/// let limits = HeaderLimits {
+///     max_count: 50,
+///     .. HeaderLimits::default()
+/// };
+///
+/// let server = Server::new("0.0.0.0:8000", |request| {
+///     Response::text("hello world")
+/// }).unwrap().header_limits(limits);
+/// server.run();
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// Maximum number of headers. Defaults to `100`.
+    pub max_count: usize,
+
+    /// Maximum size of a single header, in bytes. Defaults to `8192`.
+    pub max_size: usize,
+
+    /// Maximum total size of the headers, in bytes. Defaults to `32768`.
+    pub max_total_size: usize,
+}
+
+impl Default for HeaderLimits {
+    #[inline]
+    fn default() -> HeaderLimits {
+        HeaderLimits {
+            max_count: 100,
+            max_size: 8 * 1024,
+            max_total_size: 32 * 1024,
+        }
+    }
+}
+
+impl HeaderLimits {
+    /// Limits that accept any headers.
+    #[inline]
+    pub fn unlimited() -> HeaderLimits {
+        HeaderLimits {
+            max_count: usize::max_value(),
+            max_size: usize::max_value(),
+            max_total_size: usize::max_value(),
+        }
+    }
+
+    // Returns true if the headers exceed one of the limits.
+    pub(crate) fn exceeded(&self, headers: &[(String, String)]) -> bool {
+        if headers.len() > self.max_count {
+            return true;
+        }
+
+        let mut total: usize = 0;
+        for (name, value) in headers {
+            let size = name.len() + value.len() + 4;
+            if size > self.max_size {
+                return true;
+            }
+            total = total.saturating_add(size);
+        }
+        total > self.max_total_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderLimits;
+
+    fn headers(count: usize, value_len: usize) -> Vec<(String, String)> {
+        (0..count)
+            .map(|n| (format!("X-{}", n), "a".repeat(value_len)))
+            .collect()
+    }
+
+    #[test]
+    fn limits() {
+        let limits = HeaderLimits {
+            max_count: 4,
+            max_size: 20,
+            max_total_size: 70,
+        };
+        assert!(!limits.exceeded(&headers(4, 10)));
+        assert!(limits.exceeded(&headers(5, 1)));
+        assert!(limits.exceeded(&headers(1, 14)));
+        assert!(limits.exceeded(&headers(4, 12)));
+        assert!(!HeaderLimits::unlimited().exceeded(&headers(200, 10000)));
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Liveness and readiness probes.
+//!
+//! Orchestrators and load balancers query two endpoints of a server: the liveness endpoint tells
+//! whether the process works at all and must be restarted otherwise, and the readiness endpoint
+//! tells whether it can receive traffic right now, for example whether its database is
+//! reachable.
+//!
+//! A [`Health`](struct.Health.html) holds the probes of both endpoints. The
+//! [`handler`](fn.handler.html) answers `GET /live` and `GET /ready` with a `200 OK` response
+//! if all the probes succeed, or a `503 Service Unavailable` response otherwise. The body is a
+//! JSON object with the result of each probe:
+//!
+//! ```json
+//! {"status": "unavailable", "checks": {"database": {"status": "fail", "error": "timed out"}}}
+//! ```
+//!
+//! Once [`Health::set_shutting_down`](struct.Health.html#method.set_shutting_down) has been
+//! called, the readiness endpoint fails so that no new traffic is routed to the server while it
+//! finishes its in-flight requests. Passing the `Health` to
+//! [`Server::health`](../struct.Server.html#method.health) does it automatically when
+//! [`Server::close_connections`](../struct.Server.html#method.close_connections) is called or
+//! when the server stops.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use rouille::health;
+//! use rouille::health::Health;
+//! use rouille::Response;
+//! use rouille::Server;
+//!
+//! let health = Arc::new(
+//!     Health::new()
+//!         .with_readiness_probe("database", || {
+//!             // Ping the database here.
+//!             Ok(())
+//!         }),
+//! );
+//! let probes = health.clone();
+//!
+//! let server = Server::new("0.0.0.0:80", move |request| {
+//!     if let Some(request) = request.remove_prefix("/health") {
+//!         return health::handler(&request, &probes);
+//!     }
+//!     Response::text("hello world")
+//! })
+//! .unwrap()
+//! .health(health);
+//! server.run();
+//! ```
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use Request;
+use Response;
+
+/// Error that a probe can return.
+pub type ProbeError = Box<dyn error::Error + Send + Sync>;
+
+type ProbeFn = Box<dyn Fn() -> Result<(), ProbeError> + Send + Sync>;
+
+/// The liveness and readiness probes of a server.
+pub struct Health {
+    liveness: Vec<(String, ProbeFn)>,
+    readiness: Vec<(String, ProbeFn)>,
+    shutting_down: AtomicBool,
+}
+
+impl Health {
+    /// Builds a `Health` without any probe, whose endpoints always succeed.
+    #[inline]
+    pub fn new() -> Health {
+        Health {
+            liveness: Vec::new(),
+            readiness: Vec::new(),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Adds a probe to the liveness endpoint.
+    ///
+    /// Liveness probes should only fail if the process can't recover by itself, for example if
+    /// a worker thread has died, since the orchestrator usually restarts it.
+    #[inline]
+    pub fn with_liveness_probe<S, F>(mut self, name: S, probe: F) -> Health
+    where
+        S: Into<String>,
+        F: Fn() -> Result<(), ProbeError> + Send + Sync + 'static,
+    {
+        self.liveness.push((name.into(), Box::new(probe)));
+        self
+    }
+
+    /// Adds a probe to the readiness endpoint, for example a ping of the database or a check of
+    /// the depth of a queue.
+    #[inline]
+    pub fn with_readiness_probe<S, F>(mut self, name: S, probe: F) -> Health
+    where
+        S: Into<String>,
+        F: Fn() -> Result<(), ProbeError> + Send + Sync + 'static,
+    {
+        self.readiness.push((name.into(), Box::new(probe)));
+        self
+    }
+
+    /// Makes the readiness endpoint fail from now on, because the server is shutting down.
+    #[inline]
+    pub fn set_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if `set_shutting_down` has been called.
+    #[inline]
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Runs the liveness probes and returns the response of the liveness endpoint.
+    #[inline]
+    pub fn liveness_response(&self) -> Response {
+        report(&self.liveness, false)
+    }
+
+    /// Runs the readiness probes and returns the response of the readiness endpoint.
+    ///
+    /// The probes aren't run if the server is shutting down.
+    #[inline]
+    pub fn readiness_response(&self) -> Response {
+        report(&self.readiness, self.is_shutting_down())
+    }
+}
+
+impl Default for Health {
+    #[inline]
+    fn default() -> Health {
+        Health::new()
+    }
+}
+
+impl fmt::Debug for Health {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let names = |probes: &[(String, ProbeFn)]| -> Vec<String> {
+            probes.iter().map(|p| p.0.clone()).collect()
+        };
+        fmt.debug_struct("Health")
+            .field("liveness", &names(&self.liveness))
+            .field("readiness", &names(&self.readiness))
+            .field("shutting_down", &self.is_shutting_down())
+            .finish()
+    }
+}
+
+/// Handles the requests of the health endpoints.
+///
+/// - `GET /live` returns the result of the liveness probes.
+/// - `GET /ready` returns the result of the readiness probes.
+///
+/// Use `Request::remove_prefix` in order to mount the endpoints under a route.
+pub fn handler(request: &Request, health: &Health) -> Response {
+    let response = match request.url().as_str() {
+        "/live" => health.liveness_response(),
+        "/ready" => health.readiness_response(),
+        _ => return Response::empty_404(),
+    };
+
+    match request.method() {
+        "GET" | "HEAD" => response,
+        _ => Response::method_not_allowed(&["GET", "HEAD"]),
+    }
+}
+
+#[derive(Serialize)]
+struct ReportJson {
+    status: &'static str,
+    checks: BTreeMap<String, CheckJson>,
+}
+
+#[derive(Serialize)]
+struct CheckJson {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Runs the probes and builds the response of an endpoint.
+fn report(probes: &[(String, ProbeFn)], shutting_down: bool) -> Response {
+    if shutting_down {
+        let json = ReportJson {
+            status: "shutting_down",
+            checks: BTreeMap::new(),
+        };
+        return Response::json(&json).with_status_code(503).with_no_cache();
+    }
+
+    let mut healthy = true;
+    let mut checks = BTreeMap::new();
+    for (name, probe) in probes {
+        // A probe that panics counts as failed.
+        let result = panic::catch_unwind(AssertUnwindSafe(probe))
+            .unwrap_or_else(|_| Err("the probe panicked".into()));
+        let check = match result {
+            Ok(()) => CheckJson {
+                status: "ok",
+                error: None,
+            },
+            Err(err) => {
+                healthy = false;
+                CheckJson {
+                    status: "fail",
+                    error: Some(err.to_string()),
+                }
+            }
+        };
+        checks.insert(name.clone(), check);
+    }
+
+    let json = ReportJson {
+        status: if healthy { "ok" } else { "unavailable" },
+        checks,
+    };
+    let status_code = if healthy { 200 } else { 503 };
+    Response::json(&json)
+        .with_status_code(status_code)
+        .with_no_cache()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handler;
+    use super::Health;
+    use test::TestResponse;
+    use Request;
+    use Response;
+
+    fn get(health: &Health, url: &str) -> Response {
+        handler(&Request::fake_http("GET", url, vec![], vec![]), health)
+    }
+
+    #[test]
+    fn probes() {
+        let health = Health::new()
+            .with_liveness_probe("threads", || Ok(()))
+            .with_readiness_probe("database", || Ok(()))
+            .with_readiness_probe("queue", || Err("too deep".into()));
+
+        let response = get(&health, "/live");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            TestResponse::from(response).text(),
+            r#"{"status":"ok","checks":{"threads":{"status":"ok"}}}"#
+        );
+
+        let response = get(&health, "/ready");
+        assert_eq!(response.status_code, 503);
+        assert_eq!(
+            TestResponse::from(response).text(),
+            r#"{"status":"unavailable","checks":{"database":{"status":"ok"},"queue":{"status":"fail","error":"too deep"}}}"#
+        );
+
+        assert_eq!(get(&health, "/other").status_code, 404);
+        let post = Request::fake_http("POST", "/live", vec![], vec![]);
+        assert_eq!(handler(&post, &health).status_code, 405);
+    }
+
+    #[test]
+    fn panicking_probe() {
+        let health = Health::new().with_liveness_probe("broken", || panic!("oops"));
+        let response = get(&health, "/live");
+        assert_eq!(response.status_code, 503);
+        assert!(TestResponse::from(response)
+            .text()
+            .contains("the probe panicked"));
+    }
+
+    #[test]
+    fn shutting_down() {
+        let health = Health::new();
+        assert_eq!(get(&health, "/ready").status_code, 200);
+
+        health.set_shutting_down();
+        let response = get(&health, "/ready");
+        assert_eq!(response.status_code, 503);
+        assert_eq!(
+            TestResponse::from(response).text(),
+            r#"{"status":"shutting_down","checks":{}}"#
+        );
+        assert_eq!(get(&health, "/live").status_code, 200);
+    }
+}
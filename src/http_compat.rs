@@ -0,0 +1,523 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Conversions between rouille's types and the types of the `http` crate.
+//!
+//! This module is only available if you enable the `http` feature of rouille. The `http` crate
+//! is re-exported as `rouille::http`.
+//!
+//! The [`from_http_request`](fn.from_http_request.html),
+//! [`to_http_request`](fn.to_http_request.html),
+//! [`from_http_response`](fn.from_http_response.html) and
+//! [`into_http_response`](fn.into_http_response.html) functions convert requests and responses in
+//! both directions. Request bodies are converted to and from a `Vec<u8>`, while response bodies
+//! stay a `ResponseBody` so that streamed responses aren't buffered.
+//!
+//! If you also enable the `tower` feature, [`RouilleService`](struct.RouilleService.html) exposes
+//! a rouille handler as a `tower::Service`, and [`from_service`](fn.from_service.html) turns a
+//! `tower::Service` into a rouille handler. Both are blocking: the handler runs inside
+//! `Service::call`, and the futures of the service are run to completion on the thread of the
+//! request.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::http_compat;
+//! use rouille::Response;
+//!
+//! let request = rouille::http::Request::get("/hello").body(Vec::new()).unwrap();
+//! let request = http_compat::from_http_request(request, "127.0.0.1:12345".parse().unwrap());
+//! assert_eq!(request.url(), "/hello");
+//!
+//! let response = http_compat::into_http_response(Response::text("hello")).unwrap();
+//! assert_eq!(response.status(), 200);
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::io::Read;
+use std::net::SocketAddr;
+
+use http;
+
+use Request;
+use Response;
+use ResponseBody;
+
+#[cfg(feature = "tower")]
+pub use self::service::{from_service, RouilleService};
+
+/// Error that can happen when converting a request or a response to the types of the `http`
+/// crate.
+#[derive(Debug)]
+pub enum ConversionError {
+    /// The body of the request has already been extracted.
+    BodyAlreadyExtracted,
+
+    /// Could not read the body of the request.
+    IoError(IoError),
+
+    /// The method, the URL or a header isn't valid for the `http` crate.
+    Http(http::Error),
+}
+
+impl From<IoError> for ConversionError {
+    fn from(err: IoError) -> ConversionError {
+        ConversionError::IoError(err)
+    }
+}
+
+impl From<http::Error> for ConversionError {
+    fn from(err: http::Error) -> ConversionError {
+        ConversionError::Http(err)
+    }
+}
+
+impl error::Error for ConversionError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ConversionError::IoError(ref e) => Some(e),
+            ConversionError::Http(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConversionError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            ConversionError::BodyAlreadyExtracted => {
+                "the body of the request was already extracted"
+            }
+            ConversionError::IoError(_) => {
+                "can't read the body of the request because of an I/O error"
+            }
+            ConversionError::Http(_) => {
+                "the request or the response isn't valid for the http crate"
+            }
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Builds a rouille `Request` from a request of the `http` crate.
+///
+/// The request is considered to use HTTPS if the scheme of its URI is `https`. If the URI contains
+/// an authority and the request has no `Host` header, the authority is added as the `Host`
+/// header. Header values that aren't valid UTF-8 are converted lossily.
+pub fn from_http_request(request: http::Request<Vec<u8>>, remote_addr: SocketAddr) -> Request {
+    let (parts, body) = request.into_parts();
+
+    let mut headers: Vec<(String, String)> = parts
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.as_str().to_owned(), value)
+        })
+        .collect();
+    if let Some(authority) = parts.uri.authority() {
+        if !parts.headers.contains_key(http::header::HOST) {
+            headers.push(("Host".to_owned(), authority.as_str().to_owned()));
+        }
+    }
+
+    let url = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/")
+        .to_owned();
+    let method = parts.method.as_str().to_owned();
+
+    if parts.uri.scheme_str() == Some("https") {
+        Request::fake_https_from(remote_addr, method, url, headers, body)
+    } else {
+        Request::fake_http_from(remote_addr, method, url, headers, body)
+    }
+}
+
+/// Builds a request of the `http` crate from a rouille `Request`.
+///
+/// This extracts the body of the request, just like `Request::data()`. The URI of the new request
+/// is absolute if the rouille request has a `Host` header. The address of the client is added to
+/// the extensions of the new request as a `SocketAddr`.
+pub fn to_http_request(request: &Request) -> Result<http::Request<Vec<u8>>, ConversionError> {
+    let mut builder = http::Request::builder().method(request.method());
+
+    builder = match request.header("Host") {
+        Some(host) => {
+            let scheme = if request.is_secure() { "https" } else { "http" };
+            builder.uri(format!("{}://{}{}", scheme, host, request.raw_url()))
+        }
+        None => builder.uri(request.raw_url()),
+    };
+
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+
+    let mut body = Vec::new();
+    request
+        .data()
+        .ok_or(ConversionError::BodyAlreadyExtracted)?
+        .read_to_end(&mut body)?;
+
+    Ok(builder.extension(*request.remote_addr()).body(body)?)
+}
+
+/// Builds a rouille `Response` from a response of the `http` crate.
+///
+/// Header values that aren't valid UTF-8 are converted lossily.
+pub fn from_http_response<B>(response: http::Response<B>) -> Response
+where
+    B: Into<ResponseBody>,
+{
+    let (parts, body) = response.into_parts();
+
+    let headers = parts
+        .headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.as_str().to_owned().into(), value.into())
+        })
+        .collect();
+
+    Response {
+        status_code: parts.status.as_u16(),
+        headers,
+        data: body.into(),
+        upgrade: None,
+    }
+}
+
+/// Builds a response of the `http` crate from a rouille `Response`.
+///
+/// The body isn't read, and the `upgrade` field of the response is dropped.
+pub fn into_http_response(
+    response: Response,
+) -> Result<http::Response<ResponseBody>, ConversionError> {
+    let mut builder = http::Response::builder().status(response.status_code);
+    for (name, value) in &response.headers {
+        builder = builder.header(&name[..], &value[..]);
+    }
+
+    Ok(builder.body(response.data)?)
+}
+
+// The `http` crate and `std::task::Wake` require a more recent Rust than the rest of rouille.
+#[cfg(feature = "tower")]
+#[clippy::msrv = "1.51"]
+mod service {
+    use std::future::{self, Future};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    use http;
+    use tower_service::Service;
+
+    use super::ConversionError;
+    use super::{from_http_request, from_http_response, into_http_response, to_http_request};
+    use Request;
+    use Response;
+    use ResponseBody;
+
+    /// Exposes a rouille handler as a blocking `tower::Service`.
+    ///
+    /// The handler is called inside `Service::call`, and the returned future is always ready.
+    /// The address of the client is taken from the `SocketAddr` in the extensions of the request
+    /// if there is one, and is `remote_addr` otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::http_compat::RouilleService;
+    /// use rouille::Response;
+    ///
+    /// let service = RouilleService::new(|_| Response::text("hello"));
+    /// ```
+    pub struct RouilleService<F> {
+        handler: Arc<F>,
+        remote_addr: SocketAddr,
+    }
+
+    impl<F> RouilleService<F>
+    where
+        F: Fn(&Request) -> Response,
+    {
+        /// Builds a new `RouilleService` that calls `handler`.
+        #[inline]
+        pub fn new(handler: F) -> RouilleService<F> {
+            RouilleService {
+                handler: Arc::new(handler),
+                remote_addr: ([0, 0, 0, 0], 0).into(),
+            }
+        }
+
+        /// Sets the address of the client for requests that don't have one in their extensions.
+        ///
+        /// The default is `0.0.0.0:0`.
+        #[inline]
+        pub fn remote_addr(mut self, remote_addr: SocketAddr) -> RouilleService<F> {
+            self.remote_addr = remote_addr;
+            self
+        }
+    }
+
+    impl<F> Clone for RouilleService<F> {
+        fn clone(&self) -> RouilleService<F> {
+            RouilleService {
+                handler: self.handler.clone(),
+                remote_addr: self.remote_addr,
+            }
+        }
+    }
+
+    impl<F> Service<http::Request<Vec<u8>>> for RouilleService<F>
+    where
+        F: Fn(&Request) -> Response,
+    {
+        type Response = http::Response<ResponseBody>;
+        type Error = ConversionError;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        #[inline]
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<Vec<u8>>) -> Self::Future {
+            let remote_addr = request
+                .extensions()
+                .get::<SocketAddr>()
+                .cloned()
+                .unwrap_or(self.remote_addr);
+            let request = from_http_request(request, remote_addr);
+            future::ready(into_http_response((self.handler)(&request)))
+        }
+    }
+
+    /// Turns a `tower::Service` into a rouille handler.
+    ///
+    /// Calls to the service are serialized, but the futures it returns run concurrently, each on
+    /// the thread of its request. The handler answers with a 400 error if the request can't be
+    /// converted, for example because its body was already extracted, and with a 500 error if the
+    /// service returns an error. Like the errors generated by rouille itself, these can be replaced
+    /// with `Server::error_handler`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::http_compat::{self, RouilleService};
+    /// use rouille::Response;
+    ///
+    /// let service = RouilleService::new(|_| Response::text("hello"));
+    /// rouille::start_server("0.0.0.0:80", http_compat::from_service(service));
+    /// ```
+    pub fn from_service<S, B>(service: S) -> impl Fn(&Request) -> Response + Send + Sync
+    where
+        S: Service<http::Request<Vec<u8>>, Response = http::Response<B>> + Send,
+        B: Into<ResponseBody>,
+    {
+        let service = Mutex::new(service);
+
+        move |request| {
+            let request = match to_http_request(request) {
+                Ok(r) => r,
+                Err(_) => return Response::empty_400(),
+            };
+
+            let future = {
+                let mut service = service.lock().unwrap_or_else(|err| err.into_inner());
+                if block_on(|cx| service.poll_ready(cx)).is_err() {
+                    return internal_error();
+                }
+                service.call(request)
+            };
+
+            let mut future = Box::pin(future);
+            match block_on(|cx| future.as_mut().poll(cx)) {
+                Ok(response) => from_http_response(response),
+                Err(_) => internal_error(),
+            }
+        }
+    }
+
+    fn internal_error() -> Response {
+        let mut response = Response::text("Internal Server Error").with_status_code(500);
+        response.data.default_error = true;
+        response
+    }
+
+    // Wakes up the thread that is blocked in `block_on`.
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Calls `poll` until it returns `Poll::Ready`, parking the current thread in between.
+    fn block_on<T, P>(mut poll: P) -> T
+    where
+        P: FnMut(&mut Context) -> Poll<T>,
+    {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(value) = poll(&mut cx) {
+                return value;
+            }
+            thread::park();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use http;
+
+    use super::ConversionError;
+    use super::{from_http_request, from_http_response, into_http_response, to_http_request};
+    use Request;
+    use Response;
+
+    #[test]
+    fn requests() {
+        let request = http::Request::post("https://example.com/hello?a=b")
+            .header("X-Foo", "bar")
+            .body(b"body".to_vec())
+            .unwrap();
+        let request = from_http_request(request, "10.0.0.1:80".parse().unwrap());
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.raw_url(), "/hello?a=b");
+        assert!(request.is_secure());
+        assert_eq!(request.header("x-foo"), Some("bar"));
+        assert_eq!(request.header("Host"), Some("example.com"));
+        assert_eq!(request.remote_addr().port(), 80);
+
+        let converted = to_http_request(&request).unwrap();
+        assert_eq!(converted.method(), "POST");
+        assert_eq!(converted.uri(), "https://example.com/hello?a=b");
+        assert_eq!(converted.headers()["x-foo"], "bar");
+        assert_eq!(converted.body(), b"body");
+        assert_eq!(converted.extensions().get(), Some(request.remote_addr()));
+
+        match to_http_request(&request) {
+            Err(ConversionError::BodyAlreadyExtracted) => (),
+            _ => panic!(),
+        }
+
+        let request = Request::fake_http("GET", "/", vec![("X\nFoo".into(), "".into())], vec![]);
+        match to_http_request(&request) {
+            Err(ConversionError::Http(_)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn responses() {
+        let response = into_http_response(Response::text("hello").with_status_code(201)).unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(response.body().len(), Some(5));
+
+        let response = from_http_response(response);
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.content_type(), Some("text/plain; charset=utf-8"));
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "hello");
+
+        let response = http::Response::builder()
+            .status(404)
+            .body("not found".to_owned())
+            .unwrap();
+        assert_eq!(from_http_response(response).status_code, 404);
+    }
+
+    #[cfg(feature = "tower")]
+    #[test]
+    fn services() {
+        use super::{from_service, RouilleService};
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use std::thread;
+        use std::time::Duration;
+        use tower_service::Service;
+
+        let handler = from_service(RouilleService::new(|request: &Request| {
+            Response::text(format!("{} {}", request.remote_addr(), request.url()))
+        }));
+        let response = handler(&Request::fake_http("GET", "/foo", vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "127.0.0.1:12345 /foo");
+
+        // A service whose future is woken up by another thread.
+        struct Delayed(bool);
+        impl Future for Delayed {
+            type Output = Result<http::Response<String>, ()>;
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                if self.0 {
+                    return Poll::Ready(Err(()));
+                }
+                self.0 = true;
+                let waker = cx.waker().clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+        }
+        struct Failing;
+        impl Service<http::Request<Vec<u8>>> for Failing {
+            type Response = http::Response<String>;
+            type Error = ();
+            type Future = Delayed;
+            fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), ()>> {
+                Poll::Ready(Ok(()))
+            }
+            fn call(&mut self, _: http::Request<Vec<u8>>) -> Delayed {
+                Delayed(false)
+            }
+        }
+
+        let handler = from_service(Failing);
+        let response = handler(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert_eq!(response.status_code, 500);
+        assert!(response.data.default_error);
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Parsing and formatting the dates of HTTP headers.
+//!
+//! Headers such as `Last-Modified`, `Expires`, `If-Modified-Since`, `Retry-After` or the
+//! `Expires` attribute of cookies contain dates in the format described in RFC 7231, for example
+//! `Sun, 06 Nov 1994 08:49:37 GMT`. The servers must send this format, called IMF-fixdate, but
+//! must also accept the obsolete RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime
+//! (`Sun Nov  6 08:49:37 1994`) formats.
+//!
+//! These dates have a precision of one second. An [`HttpDate`](struct.HttpDate.html) is a date
+//! truncated to the second, which makes it possible to compare the date of a header with the
+//! modification time of a file.
+//!
+//! The AWS APIs use ISO 8601 dates instead, which [`parse_amz_date`](fn.parse_amz_date.html)
+//! and [`format_iso8601`](fn.format_iso8601.html) handle.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use rouille::http_date::HttpDate;
+//!
+//! let date: HttpDate = "Sunday, 06-Nov-94 08:49:37 GMT".parse().unwrap();
+//! assert_eq!(date.to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+//!
+//! let modified = UNIX_EPOCH + Duration::from_millis(784111777_500);
+//! assert_eq!(HttpDate::from(modified), date);
+//! ```
+
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Formats a date in the IMF-fixdate format, for example `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// # Example
+///
+/// ```
+/// use std::time::UNIX_EPOCH;
+/// use rouille::http_date::format_http_date;
+///
+/// assert_eq!(format_http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+/// ```
+#[inline]
+pub fn format_http_date(date: SystemTime) -> String {
+    HttpDate::from(date).to_string()
+}
+
+/// Parses a date in one of the three formats allowed by HTTP. Returns `None` if the date is
+/// invalid.
+///
+/// # Example
+///
+/// ```
+/// use rouille::http_date::parse_http_date;
+///
+/// let date = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT");
+/// assert!(date.is_some());
+/// assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), date);
+/// assert_eq!(parse_http_date("yesterday"), None);
+/// ```
+#[inline]
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    date.parse::<HttpDate>().ok().map(SystemTime::from)
+}
+
+/// Parses a date in the basic ISO 8601 format of the `X-Amz-Date` header of AWS, for example
+/// `19941106T084937Z`. Returns `None` if the date is invalid.
+///
+/// # Example
+///
+/// ```
+/// use rouille::http_date::{parse_amz_date, parse_http_date};
+///
+/// let date = parse_amz_date("19941106T084937Z");
+/// assert_eq!(date, parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+/// assert_eq!(parse_amz_date("1994-11-06"), None);
+/// ```
+pub fn parse_amz_date(date: &str) -> Option<SystemTime> {
+    let date = NaiveDateTime::parse_from_str(date, "%Y%m%dT%H%M%SZ").ok()?;
+    Some(SystemTime::from(Utc.from_utc_datetime(&date)))
+}
+
+/// Formats a date in the extended ISO 8601 format with milliseconds, for example
+/// `1994-11-06T08:49:37.000Z`, as in the XML documents of S3.
+///
+/// # Example
+///
+/// ```
+/// use std::time::UNIX_EPOCH;
+/// use rouille::http_date::format_iso8601;
+///
+/// assert_eq!(format_iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+/// ```
+#[inline]
+pub fn format_iso8601(date: SystemTime) -> String {
+    DateTime::<Utc>::from(date)
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+/// A date of an HTTP header, with a precision of one second.
+///
+/// Converting a `SystemTime` to an `HttpDate` truncates it to the second. `Display` formats the
+/// date in the IMF-fixdate format, and `FromStr` accepts the three formats allowed by HTTP.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HttpDate {
+    // Number of seconds since the UNIX epoch.
+    timestamp: i64,
+}
+
+impl HttpDate {
+    /// Returns the current date.
+    #[inline]
+    pub fn now() -> HttpDate {
+        HttpDate::from(SystemTime::now())
+    }
+
+    // Returns the date as a chrono type.
+    fn to_chrono(self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.timestamp, 0)
+            .single()
+            .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap())
+    }
+}
+
+impl From<SystemTime> for HttpDate {
+    #[inline]
+    fn from(date: SystemTime) -> HttpDate {
+        HttpDate {
+            timestamp: DateTime::<Utc>::from(date).timestamp(),
+        }
+    }
+}
+
+impl From<HttpDate> for SystemTime {
+    #[inline]
+    fn from(date: HttpDate) -> SystemTime {
+        if date.timestamp >= 0 {
+            UNIX_EPOCH + Duration::from_secs(date.timestamp as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(0u64.wrapping_sub(date.timestamp as u64))
+        }
+    }
+}
+
+impl FromStr for HttpDate {
+    type Err = InvalidHttpDate;
+
+    fn from_str(date: &str) -> Result<HttpDate, InvalidHttpDate> {
+        let date = date.trim();
+        if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+            return Ok(HttpDate {
+                timestamp: date.timestamp(),
+            });
+        }
+
+        // The obsolete RFC 850 and asctime formats.
+        ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"]
+            .iter()
+            .filter_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+            .map(|date| HttpDate {
+                timestamp: Utc.from_utc_datetime(&date).timestamp(),
+            })
+            .next()
+            .ok_or(InvalidHttpDate)
+    }
+}
+
+impl fmt::Display for HttpDate {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{}",
+            self.to_chrono().format("%a, %d %b %Y %H:%M:%S GMT")
+        )
+    }
+}
+
+/// Error returned when parsing an `HttpDate` fails.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidHttpDate;
+
+impl error::Error for InvalidHttpDate {}
+
+impl fmt::Display for InvalidHttpDate {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Invalid HTTP date")
+    }
+}
+
+/// Value of a `Retry-After` header, which is either a number of seconds or a date.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use rouille::http_date::RetryAfter;
+///
+/// assert_eq!("120".parse(), Ok(RetryAfter::Delay(Duration::from_secs(120))));
+/// assert!("Sun, 06 Nov 1994 08:49:37 GMT".parse::<RetryAfter>().is_ok());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RetryAfter {
+    /// Number of seconds to wait. Sub-second durations are rounded up when formatted.
+    Delay(Duration),
+    /// Date after which to retry.
+    Date(HttpDate),
+}
+
+impl RetryAfter {
+    /// Returns the time after which to retry, for a response received at `now`.
+    #[inline]
+    pub fn retry_at(&self, now: SystemTime) -> SystemTime {
+        match *self {
+            RetryAfter::Delay(delay) => now + delay,
+            RetryAfter::Date(date) => SystemTime::from(date),
+        }
+    }
+}
+
+impl FromStr for RetryAfter {
+    type Err = InvalidHttpDate;
+
+    fn from_str(value: &str) -> Result<RetryAfter, InvalidHttpDate> {
+        let value = value.trim();
+        if !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) {
+            return value
+                .parse()
+                .map(|secs| RetryAfter::Delay(Duration::from_secs(secs)))
+                .map_err(|_| InvalidHttpDate);
+        }
+        value.parse().map(RetryAfter::Date)
+    }
+}
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RetryAfter::Delay(delay) => {
+                let secs = delay.as_secs() + if delay.subsec_nanos() > 0 { 1 } else { 0 };
+                write!(fmt, "{}", secs)
+            }
+            RetryAfter::Date(date) => write!(fmt, "{}", date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_http_date;
+    use super::parse_http_date;
+    use super::HttpDate;
+    use super::RetryAfter;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn formats() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+        let expected = Some(date);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(
+            parse_http_date(" Sunday, 06-Nov-94 08:49:37 GMT "),
+            expected
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_http_date("invalid"), None);
+        assert_eq!(parse_http_date(""), None);
+
+        assert_eq!(format_http_date(date), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            format_http_date(date + Duration::from_millis(999)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn truncated_to_the_second() {
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(
+            HttpDate::from(date),
+            HttpDate::from(date + Duration::from_millis(500))
+        );
+        assert!(HttpDate::from(date) < HttpDate::from(date + Duration::from_secs(1)));
+
+        let before_epoch = UNIX_EPOCH - Duration::from_secs(86400);
+        assert_eq!(
+            format_http_date(before_epoch),
+            "Wed, 31 Dec 1969 00:00:00 GMT"
+        );
+        assert_eq!(
+            parse_http_date("Wed, 31 Dec 1969 00:00:00 GMT"),
+            Some(before_epoch)
+        );
+    }
+
+    #[test]
+    fn retry_after() {
+        let delay: RetryAfter = "30".parse().unwrap();
+        assert_eq!(delay, RetryAfter::Delay(Duration::from_secs(30)));
+        assert_eq!(
+            delay.retry_at(UNIX_EPOCH),
+            UNIX_EPOCH + Duration::from_secs(30)
+        );
+        assert_eq!(
+            RetryAfter::Delay(Duration::from_millis(1500)).to_string(),
+            "2"
+        );
+
+        let date: RetryAfter = "Sun, 06 Nov 1994 08:49:37 GMT".parse().unwrap();
+        assert_eq!(date.to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!("-1".parse::<RetryAfter>().is_err());
+    }
+}
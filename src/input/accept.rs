@@ -22,6 +22,17 @@
 /// You can also use `*` in the MIME types you pass to the macro. The MIME `*/*` can be used as a
 /// default handler.
 ///
+/// # Languages and charsets
+///
+/// If the list is preceded with `language:`, the macro dispatches depending on the
+/// `Accept-Language` header instead, with the language tags matched as described in
+/// [`accept_language_preferred`](input/fn.accept_language_preferred.html). Similarly, `charset:`
+/// dispatches depending on the `Accept-Charset` header. If no language or charset matches the
+/// request, the first in the list is chosen, which makes it the default.
+///
+/// Since the macro is an expression, the dispatches can be nested in order to take several
+/// headers into account.
+///
 /// > **Note**: Using `|` just like in real match expressions is not yet supported because the
 /// > author didn't find a way to make it work with Rust macros.
 ///
@@ -41,6 +52,30 @@
 /// # fn main() {}
 /// ```
 ///
+/// # Example with languages
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn handle(request: &Request) -> Response {
+///     accept!(request, language:
+///         "en" => accept!(request,
+///             "text/html" => Response::html("<p>Hello world</p>"),
+///             "text/plain" => Response::text("Hello world"),
+///         ),
+///         "fr" => accept!(request,
+///             "text/html" => Response::html("<p>Bonjour le monde</p>"),
+///             "text/plain" => Response::text("Bonjour le monde"),
+///         ),
+///     )
+///     .with_vary("Accept")
+///     .with_vary("Accept-Language")
+/// }
+/// # fn main() {}
+/// ```
+///
 /// # Example with a default handler
 ///
 /// ```
@@ -59,19 +94,40 @@
 /// ```
 #[macro_export]
 macro_rules! accept {
-    ($request:expr, $($mime:expr => $val:expr),+ $(,)*) => ({
+    ($request:expr, language: $($lang:expr => $val:expr),+ $(,)*) => ({
         use $crate::input;
         use std::iter;
 
-        let header = $request.header("Accept").unwrap_or("*/*");
+        let header = $request.header("Accept-Language").unwrap_or("*");
 
         let handled = {
             let i = iter::empty();
-            $(let i = i.chain(iter::once($mime));)+
+            $(let i = i.chain(iter::once($lang));)+
             i
         };
 
-        let mut preferred = input::priority_header_preferred(header, handled).unwrap_or(0);
+        let preferred = input::accept_language_preferred(header, handled).unwrap_or(0);
+        accept!(__outcome preferred, $($val),+)
+    });
+
+    ($request:expr, charset: $($charset:expr => $val:expr),+ $(,)*) => ({
+        use $crate::input;
+        use std::iter;
+
+        let header = $request.header("Accept-Charset").unwrap_or("*");
+
+        let handled = {
+            let i = iter::empty();
+            $(let i = i.chain(iter::once($charset));)+
+            i
+        };
+
+        let preferred = input::priority_header_preferred(header, handled).unwrap_or(0);
+        accept!(__outcome preferred, $($val),+)
+    });
+
+    (__outcome $preferred:expr, $($val:expr),+) => ({
+        let mut preferred = $preferred;
 
         let mut outcome = None;
 
@@ -88,6 +144,22 @@ macro_rules! accept {
         outcome.unwrap()    // unwrap() can only panic if priority_header_preferred has a bug or
                             // if the list of mimes is empty (which can't happen)
     });
+
+    ($request:expr, $($mime:expr => $val:expr),+ $(,)*) => ({
+        use $crate::input;
+        use std::iter;
+
+        let header = $request.header("Accept").unwrap_or("*/*");
+
+        let handled = {
+            let i = iter::empty();
+            $(let i = i.chain(iter::once($mime));)+
+            i
+        };
+
+        let preferred = input::priority_header_preferred(header, handled).unwrap_or(0);
+        accept!(__outcome preferred, $($val),+)
+    });
 }
 
 #[cfg(test)]
@@ -176,6 +248,70 @@ mod tests {
         assert_eq!(result, 5);
     }
 
+    #[test]
+    fn language() {
+        let request = |header: &str| {
+            let h = vec![("Accept-Language".to_owned(), header.to_owned())];
+            Request::fake_http("GET", "/", h, vec![])
+        };
+
+        let swiss = request("fr-CH, en;q=0.5");
+        let result = accept!(&swiss, language:
+            "en" => 1,
+            "fr" => 2,
+        );
+        assert_eq!(result, 2);
+
+        let spanish = request("es");
+        let result = accept!(&spanish, language:
+            "en" => 1,
+            "fr" => 2,
+        );
+        assert_eq!(result, 1);
+
+        let no_header = Request::fake_http("GET", "/", vec![], vec![]);
+        let result = accept!(&no_header, language: "de" => 1, "en" => 2);
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn charset() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![(
+                "Accept-Charset".to_owned(),
+                "iso-8859-1;q=0.5, UTF-8".to_owned(),
+            )],
+            vec![],
+        );
+
+        let result = accept!(&request, charset:
+            "iso-8859-1" => 1,
+            "utf-8" => 2,
+        );
+        assert_eq!(result, 2);
+    }
+
+    #[test]
+    fn nested() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Accept".to_owned(), "application/json".to_owned()),
+                ("Accept-Language".to_owned(), "fr".to_owned()),
+            ],
+            vec![],
+        );
+
+        let result = accept!(&request, language:
+            "en" => accept!(&request, "text/html" => "en html", "application/json" => "en json"),
+            "fr" => accept!(&request, "text/html" => "fr html", "application/json" => "fr json"),
+        );
+        assert_eq!(result, "fr json");
+    }
+
     #[test]
     fn no_header_wildcard() {
         let request = Request::fake_http(
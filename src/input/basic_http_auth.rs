@@ -17,8 +17,15 @@
 //! - In order to read a plain text body, see
 //!   [the `plain_text_body` function](fn.plain_text_body.html).
 
+#[cfg(feature = "argon2-passwords")]
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use base64;
+#[cfg(feature = "bcrypt-passwords")]
+use bcrypt;
+use sha1::Sha1;
+use util::constant_time_eq;
 use Request;
+use Response;
 
 /// Credentials returned by `basic_http_auth`.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -29,6 +36,78 @@ pub struct HttpAuthCredentials {
     pub password: String,
 }
 
+impl HttpAuthCredentials {
+    /// Returns true if the credentials are `login` and `password`.
+    ///
+    /// The comparison takes the same time whatever the number of characters that match, so that
+    /// an attacker can't guess the password one character at a time by measuring the duration
+    /// of the requests. The SHA-1 digests of the credentials are compared rather than the
+    /// credentials themselves, so that the duration doesn't reveal the length of the password
+    /// either, apart from the number of 64-byte blocks that are hashed. The login and the
+    /// password are always both compared.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::input;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     match input::basic_http_auth(request) {
+    ///         Some(ref auth) if auth.verify("admin", "GT5GeKyLvKLxuc7mjF5h") => {
+    ///             Response::text("You are in a secret area")
+    ///         }
+    ///         _ => Response::basic_http_auth_login_required("realm"),
+    ///     }
+    /// }
+    /// ```
+    pub fn verify(&self, login: &str, password: &str) -> bool {
+        let login = digest_eq(&self.login, login);
+        let password = digest_eq(&self.password, password);
+        login & password
+    }
+
+    /// Returns true if the login is `login` and the password matches a bcrypt `hash`, such as
+    /// `$2b$12$...`.
+    ///
+    /// Returns false if the hash is invalid. This function is only available with the
+    /// `bcrypt-passwords` feature.
+    #[cfg(feature = "bcrypt-passwords")]
+    pub fn verify_bcrypt(&self, login: &str, hash: &str) -> bool {
+        let login = digest_eq(&self.login, login);
+        let password = bcrypt::verify(&self.password, hash).unwrap_or(false);
+        login & password
+    }
+
+    /// Returns true if the login is `login` and the password matches an Argon2 `hash` in the
+    /// PHC string format, such as `$argon2id$v=19$m=19456,t=2,p=1$...`.
+    ///
+    /// Returns false if the hash is invalid. This function is only available with the
+    /// `argon2-passwords` feature.
+    #[cfg(feature = "argon2-passwords")]
+    pub fn verify_argon2(&self, login: &str, hash: &str) -> bool {
+        let login = digest_eq(&self.login, login);
+        let password = PasswordHash::new(hash).map_or(false, |hash| {
+            Argon2::default()
+                .verify_password(self.password.as_bytes(), &hash)
+                .is_ok()
+        });
+        login & password
+    }
+}
+
+// Compares the digests of two credentials, in a time that depends neither on the number of bytes
+// that match nor on the length of `expected`, except through the number of blocks to hash.
+fn digest_eq(provided: &str, expected: &str) -> bool {
+    let digest = |value: &str| {
+        let mut hash = Sha1::new();
+        hash.update(value.as_bytes());
+        hash.digest().bytes()
+    };
+    constant_time_eq(&digest(provided), &digest(expected))
+}
+
 /// Attempts to parse a `Authorization` header with basic HTTP auth.
 ///
 /// If such a header is present and valid, a `HttpAuthCredentials` is returned.
@@ -100,9 +179,54 @@ pub fn basic_http_auth(request: &Request) -> Option<HttpAuthCredentials> {
     Some(HttpAuthCredentials { login, password })
 }
 
+/// Checks the basic HTTP auth credentials of the request with `check`.
+///
+/// Returns the credentials if `check` accepts them, and otherwise a `401 Unauthorized` response
+/// that asks the client for credentials, as returned by
+/// [`Response::basic_http_auth_login_required`](../struct.Response.html#method.basic_http_auth_login_required).
+///
+/// # Example
+///
+/// ```
+/// use rouille::input;
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// fn handle(request: &Request) -> Response {
+///     let auth = match input::basic_http_auth_required(request, "admin area", |auth| {
+///         auth.verify("admin", "GT5GeKyLvKLxuc7mjF5h")
+///     }) {
+///         Ok(auth) => auth,
+///         Err(response) => return response,
+///     };
+///
+///     Response::text(format!("Hello, {}", auth.login))
+/// }
+/// ```
+pub fn basic_http_auth_required<F>(
+    request: &Request,
+    realm: &str,
+    check: F,
+) -> Result<HttpAuthCredentials, Response>
+where
+    F: FnOnce(&HttpAuthCredentials) -> bool,
+{
+    match basic_http_auth(request) {
+        Some(auth) => {
+            if check(&auth) {
+                Ok(auth)
+            } else {
+                Err(Response::basic_http_auth_login_required(realm))
+            }
+        }
+        None => Err(Response::basic_http_auth_login_required(realm)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::basic_http_auth;
+    use super::basic_http_auth_required;
     use super::HttpAuthCredentials;
     use Request;
 
@@ -151,4 +275,85 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn verify() {
+        let auth = HttpAuthCredentials {
+            login: "Aladdin".to_owned(),
+            password: "open sesame".to_owned(),
+        };
+        assert!(auth.verify("Aladdin", "open sesame"));
+        assert!(!auth.verify("Aladdin", "open sesamf"));
+        assert!(!auth.verify("Aladdin", "open"));
+        assert!(!auth.verify("aladdin", "open sesame"));
+    }
+
+    #[test]
+    #[cfg(feature = "bcrypt-passwords")]
+    fn verify_bcrypt() {
+        use bcrypt;
+
+        let auth = HttpAuthCredentials {
+            login: "Aladdin".to_owned(),
+            password: "open sesame".to_owned(),
+        };
+        let hash = bcrypt::hash("open sesame", 4).unwrap();
+        assert!(auth.verify_bcrypt("Aladdin", &hash));
+        assert!(!auth.verify_bcrypt("Aladdin", &bcrypt::hash("open", 4).unwrap()));
+        assert!(!auth.verify_bcrypt("Bob", &hash));
+        assert!(!auth.verify_bcrypt("Aladdin", "invalid"));
+    }
+
+    #[test]
+    #[cfg(feature = "argon2-passwords")]
+    fn verify_argon2() {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+        use argon2::Argon2;
+
+        let auth = HttpAuthCredentials {
+            login: "Aladdin".to_owned(),
+            password: "open sesame".to_owned(),
+        };
+        let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap();
+        let hash = Argon2::default()
+            .hash_password(b"open sesame", &salt)
+            .unwrap()
+            .to_string();
+        assert!(auth.verify_argon2("Aladdin", &hash));
+        assert!(!auth.verify_argon2("Bob", &hash));
+        assert!(!auth.verify_argon2("Aladdin", "$argon2id$invalid"));
+    }
+
+    #[test]
+    fn basic_http_auth_required_challenge() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![(
+                "Authorization".to_owned(),
+                "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==".to_owned(),
+            )],
+            Vec::new(),
+        );
+        let auth = basic_http_auth_required(&request, "realm", |auth| {
+            auth.verify("Aladdin", "open sesame")
+        });
+        assert_eq!(auth.unwrap().login, "Aladdin");
+
+        let response = basic_http_auth_required(&request, "the \"realm\"", |auth| {
+            auth.verify("Aladdin", "wrong")
+        })
+        .unwrap_err();
+        assert_eq!(response.status_code, 401);
+        assert_eq!(
+            response.headers,
+            vec![(
+                "WWW-Authenticate".into(),
+                "Basic realm=\"the \\\"realm\\\"\", charset=\"UTF-8\"".into()
+            )]
+        );
+
+        let request = Request::fake_http("GET", "/", vec![], Vec::new());
+        assert!(basic_http_auth_required(&request, "realm", |_| true).is_err());
+    }
 }
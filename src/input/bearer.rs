@@ -0,0 +1,88 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use Request;
+
+/// Attempts to parse an `Authorization` header with a bearer token, as defined by RFC 6750.
+///
+/// Returns `None` if there is no such header, or if the token contains characters that aren't
+/// allowed. The token isn't checked, which is up to the server. Tokens that are JSON Web Tokens
+/// can be checked with [the `jwt` module](jwt/index.html).
+///
+/// # Example
+///
+/// ```
+/// use rouille::input;
+/// use rouille::Request;
+/// use rouille::Response;
+///
+/// # fn is_valid(_: &str) -> bool { true }
+/// fn handle(request: &Request) -> Response {
+///     match input::bearer_token(request) {
+///         Some(token) if is_valid(token) => Response::text("You are in a secret area"),
+///         _ => Response::text("Unauthorized")
+///             .with_status_code(401)
+///             .with_additional_header("WWW-Authenticate", "Bearer"),
+///     }
+/// }
+/// ```
+pub fn bearer_token(request: &Request) -> Option<&str> {
+    let header = request.header("Authorization")?.trim();
+    let mut split = header.splitn(2, ' ');
+    if !split.next()?.eq_ignore_ascii_case("Bearer") {
+        return None;
+    }
+
+    let token = split.next()?.trim_start();
+    let valid = !token.is_empty()
+        && token
+            .trim_end_matches('=')
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~+/".contains(&b));
+    if valid {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bearer_token;
+    use Request;
+
+    #[test]
+    fn tokens() {
+        let request = |value: &str| {
+            Request::fake_http(
+                "GET",
+                "/",
+                vec![("Authorization".to_owned(), value.to_owned())],
+                Vec::new(),
+            )
+        };
+
+        assert_eq!(
+            bearer_token(&request("Bearer mF_9.B5f-4.1JqM")),
+            Some("mF_9.B5f-4.1JqM")
+        );
+        assert_eq!(bearer_token(&request("bearer  abc+/==")), Some("abc+/=="));
+        assert_eq!(bearer_token(&request("Bearer")), None);
+        assert_eq!(bearer_token(&request("Bearer a=b")), None);
+        assert_eq!(bearer_token(&request("Bearer a b")), None);
+        assert_eq!(
+            bearer_token(&request("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==")),
+            None
+        );
+        assert_eq!(
+            bearer_token(&Request::fake_http("GET", "/", vec![], vec![])),
+            None
+        );
+    }
+}
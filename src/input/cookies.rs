@@ -17,13 +17,16 @@
 //! - In order to read a plain text body, see
 //!   [the `plain_text_body` function](fn.plain_text_body.html).
 
+use percent_encoding::percent_decode_str;
+use std::slice;
 use std::str::Split;
 use Request;
 
 /// Attempts to parse the list of cookies from the request.
 ///
 /// Returns an iterator that produces a pair of `(key, value)`. If the header is missing or
-/// malformed, an empty iterator is returned.
+/// malformed, an empty iterator is returned. The values are returned as they are in the header;
+/// see [`cookie_jar`](fn.cookie_jar.html) for a parser that decodes them.
 ///
 /// # Example
 ///
@@ -87,8 +90,137 @@ impl<'a> Iterator for CookiesIter<'a> {
     }
 }
 
+/// Parses the cookies of the request into a `CookieJar`.
+///
+/// Contrary to [`cookies`](fn.cookies.html), the values are percent-decoded, and all the `Cookie`
+/// headers of the request are taken into account. The malformed cookies are ignored.
+///
+/// # Example
+///
+/// ```
+/// use rouille::Request;
+/// use rouille::input;
+///
+/// let request = Request::fake_http("GET", "/", vec![
+///     ("Cookie".to_owned(), "theme=dark; name=J%C3%A9r%C3%B4me; theme=light".to_owned()),
+/// ], vec![]);
+///
+/// let jar = input::cookie_jar(&request);
+/// assert_eq!(jar.get("name"), Some("Jérôme"));
+/// assert_eq!(jar.get("theme"), Some("dark"));
+/// assert_eq!(jar.get_all("theme"), vec!["dark", "light"]);
+/// assert_eq!(jar.get("missing"), None);
+/// ```
+pub fn cookie_jar(request: &Request) -> CookieJar {
+    let mut cookies = Vec::new();
+    for (name, value) in request.headers() {
+        if !name.eq_ignore_ascii_case("Cookie") {
+            continue;
+        }
+
+        let iter = CookiesIter {
+            iter: value.split(';'),
+        };
+        for (name, value) in iter {
+            if name.is_empty() {
+                continue;
+            }
+            let value = match percent_decode_str(value).decode_utf8() {
+                Ok(decoded) => decoded.into_owned(),
+                Err(_) => value.to_owned(),
+            };
+            cookies.push((name.to_owned(), value));
+        }
+    }
+
+    CookieJar { cookies }
+}
+
+/// The cookies of a request. Created with [`cookie_jar`](fn.cookie_jar.html).
+///
+/// A client can send several cookies with the same name, for example when the cookies were set
+/// with different paths. The cookies are kept in the order of the request, in which the browsers
+/// put the cookies with the most specific path first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    /// Returns the value of the first cookie with the given name.
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.iter().find(|c| c.0 == name).map(|c| &c.1[..])
+    }
+
+    /// Returns the values of all the cookies with the given name.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.cookies
+            .iter()
+            .filter(|c| c.0 == name)
+            .map(|c| &c.1[..])
+            .collect()
+    }
+
+    /// Returns true if the request has a cookie with the given name.
+    #[inline]
+    pub fn contains(&self, name: &str) -> bool {
+        self.cookies.iter().any(|c| c.0 == name)
+    }
+
+    /// Returns the number of cookies, including the duplicates.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    /// Returns true if the request has no cookie.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// Returns an iterator to the names and values of the cookies, in the order of the request.
+    #[inline]
+    pub fn iter(&self) -> CookieJarIter<'_> {
+        CookieJarIter {
+            iter: self.cookies.iter(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a CookieJar {
+    type Item = (&'a str, &'a str);
+    type IntoIter = CookieJarIter<'a>;
+
+    #[inline]
+    fn into_iter(self) -> CookieJarIter<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator to the cookies of a `CookieJar`.
+pub struct CookieJarIter<'a> {
+    iter: slice::Iter<'a, (String, String)>,
+}
+
+impl<'a> Iterator for CookieJarIter<'a> {
+    type Item = (&'a str, &'a str);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|c| (&c.0[..], &c.1[..]))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use super::cookie_jar;
     use super::cookies;
     use Request;
 
@@ -112,4 +244,32 @@ mod test {
             vec![("a".into(), "b".into()), ("hello".into(), "world".into())]
         );
     }
+
+    #[test]
+    fn jar() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                (
+                    "Cookie".to_owned(),
+                    "a=1; b=\"x%20y\"; =c; d; e=%FF".to_owned(),
+                ),
+                ("cookie".to_owned(), "a=2".to_owned()),
+            ],
+            Vec::new(),
+        );
+
+        let jar = cookie_jar(&request);
+        assert_eq!(jar.len(), 4);
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get_all("a"), vec!["1", "2"]);
+        assert_eq!(jar.get("b"), Some("x y"));
+        assert_eq!(jar.get("e"), Some("%FF"));
+        assert!(!jar.contains("d"));
+        assert_eq!(
+            jar.iter().map(|c| c.0).collect::<Vec<_>>(),
+            vec!["a", "b", "e", "a"]
+        );
+    }
 }
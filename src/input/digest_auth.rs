@@ -0,0 +1,607 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use random::Random;
+use util::{constant_time_eq, to_hex};
+use Request;
+use Response;
+
+// Maximum number of nonces that a `DigestAuth` remembers. The oldest ones are forgotten first.
+const MAX_NONCES: usize = 10_000;
+
+/// Hash algorithm of HTTP Digest authentication.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DigestAlgorithm {
+    /// `MD5`, the only algorithm that most legacy clients support.
+    Md5,
+    /// `SHA-256`, defined by RFC 7616.
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// Returns the name of the algorithm in the `algorithm` parameter of the headers.
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    // Returns the hash of `data` in lowercase hexadecimal.
+    fn hash(&self, data: &str) -> String {
+        let digest = match *self {
+            DigestAlgorithm::Md5 => Md5::digest(data.as_bytes()).to_vec(),
+            DigestAlgorithm::Sha256 => Sha256::digest(data.as_bytes()).to_vec(),
+        };
+        to_hex(&digest)
+    }
+}
+
+/// Configuration and nonces of HTTP Digest authentication, as defined by RFC 7616.
+///
+/// Unlike basic authentication, the password isn't sent by the client. Instead, the server sends
+/// a random nonce in the challenge of a `401 Unauthorized` response, and the client answers with
+/// a hash of its password, the nonce and the request. Only the `auth` quality of protection is
+/// supported, which doesn't protect the body of the request.
+///
+/// The nonces are kept in memory for `nonce_lifetime`, and each one can be used for several
+/// requests. A request that reuses the nonce count of a previous one is rejected, so that a
+/// request that was intercepted can't be replayed.
+///
+/// The `DigestAuth` must be shared by all the requests of the server, for example in an `Arc`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::{self, DigestAuth, DigestAuthError};
+/// use rouille::{Request, Response};
+///
+/// fn handle(request: &Request, auth: &DigestAuth) -> Response {
+///     let credentials = match input::digest_auth(request, auth) {
+///         Some(c) => c,
+///         None => return auth.login_required(request, false),
+///     };
+///
+///     let password = match credentials.username.as_str() {
+///         "admin" => "GT5GeKyLvKLxuc7mjF5h",
+///         _ => return auth.login_required(request, false),
+///     };
+///
+///     match auth.verify(&credentials, password) {
+///         Ok(()) => Response::text("You are in a secret area"),
+///         // The client retries with the new nonce without asking the user again.
+///         Err(DigestAuthError::StaleNonce) => auth.login_required(request, true),
+///         Err(_) => auth.login_required(request, false),
+///     }
+/// }
+/// ```
+pub struct DigestAuth {
+    realm: String,
+    algorithms: Vec<DigestAlgorithm>,
+    nonce_lifetime: Duration,
+    nonces: Mutex<HashMap<String, Nonce>>,
+}
+
+struct Nonce {
+    created: Instant,
+    // The largest nonce count that has been accepted with this nonce.
+    last_count: u32,
+}
+
+impl DigestAuth {
+    /// Builds the configuration of a realm, which is the name that the browsers show to the
+    /// user.
+    ///
+    /// `SHA-256` and `MD5` are both offered, and the nonces are valid for five minutes.
+    pub fn new<R>(realm: R) -> DigestAuth
+    where
+        R: Into<String>,
+    {
+        DigestAuth {
+            realm: realm.into(),
+            algorithms: vec![DigestAlgorithm::Sha256, DigestAlgorithm::Md5],
+            nonce_lifetime: Duration::from_secs(5 * 60),
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the algorithms that the clients can use, in order of preference.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `algorithms` is empty.
+    pub fn algorithms(mut self, algorithms: &[DigestAlgorithm]) -> Self {
+        assert!(!algorithms.is_empty());
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Sets how long a nonce can be used after the challenge that contains it.
+    #[inline]
+    pub fn nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.nonce_lifetime = lifetime;
+        self
+    }
+
+    /// Returns the realm.
+    #[inline]
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// Builds a `401 Unauthorized` response with a challenge for each algorithm and a new
+    /// nonce.
+    ///
+    /// `stale` must be true when the credentials were right but the nonce had expired, so that
+    /// the client retries with the new nonce without asking the user for the password again.
+    pub fn login_required(&self, request: &Request, stale: bool) -> Response {
+        let nonce = Random::of(request).alphanumeric(32);
+        {
+            let mut nonces = self.nonces.lock().unwrap();
+            let lifetime = self.nonce_lifetime;
+            nonces.retain(|_, n| n.created.elapsed() < lifetime);
+            if nonces.len() >= MAX_NONCES {
+                let oldest = nonces
+                    .iter()
+                    .min_by_key(|&(_, n)| n.created)
+                    .map(|(nonce, _)| nonce.clone());
+                if let Some(oldest) = oldest {
+                    nonces.remove(&oldest);
+                }
+            }
+            nonces.insert(
+                nonce.clone(),
+                Nonce {
+                    created: Instant::now(),
+                    last_count: 0,
+                },
+            );
+        }
+
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut response = Response::text("Unauthorized").with_status_code(401);
+        for algorithm in &self.algorithms {
+            let mut challenge = format!(
+                "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"",
+                realm,
+                algorithm.name(),
+                nonce
+            );
+            if stale {
+                challenge.push_str(", stale=true");
+            }
+            response = response.with_additional_header("WWW-Authenticate", challenge);
+        }
+        response
+    }
+
+    /// Returns the hash of the username, realm and password, which can be stored instead of the
+    /// password and passed to `verify_ha1`.
+    pub fn ha1(&self, algorithm: DigestAlgorithm, username: &str, password: &str) -> String {
+        algorithm.hash(&format!("{}:{}:{}", username, self.realm, password))
+    }
+
+    /// Checks that the client knows `password`, and that the nonce can be used.
+    #[inline]
+    pub fn verify(
+        &self,
+        credentials: &DigestCredentials,
+        password: &str,
+    ) -> Result<(), DigestAuthError> {
+        let ha1 = self.ha1(credentials.algorithm, &credentials.username, password);
+        self.verify_ha1(credentials, &ha1)
+    }
+
+    /// Same as `verify`, but with the hash returned by `ha1` instead of the password.
+    pub fn verify_ha1(
+        &self,
+        credentials: &DigestCredentials,
+        ha1: &str,
+    ) -> Result<(), DigestAuthError> {
+        let algorithm = credentials.algorithm;
+        let ha2 = algorithm.hash(&format!("{}:{}", credentials.method, credentials.uri));
+        let expected = algorithm.hash(&format!(
+            "{}:{}:{:08x}:{}:auth:{}",
+            ha1.to_ascii_lowercase(),
+            credentials.nonce,
+            credentials.nonce_count,
+            credentials.cnonce,
+            ha2
+        ));
+        let response = credentials.response.to_ascii_lowercase();
+        if !constant_time_eq(expected.as_bytes(), response.as_bytes()) {
+            return Err(DigestAuthError::WrongCredentials);
+        }
+
+        let mut nonces = self.nonces.lock().unwrap();
+        let expired = match nonces.get_mut(&credentials.nonce) {
+            None => return Err(DigestAuthError::StaleNonce),
+            Some(ref nonce) if nonce.created.elapsed() >= self.nonce_lifetime => true,
+            Some(nonce) => {
+                if credentials.nonce_count <= nonce.last_count {
+                    return Err(DigestAuthError::Replayed);
+                }
+                nonce.last_count = credentials.nonce_count;
+                false
+            }
+        };
+        if expired {
+            nonces.remove(&credentials.nonce);
+            return Err(DigestAuthError::StaleNonce);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DigestAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DigestAuth")
+            .field("realm", &self.realm)
+            .field("algorithms", &self.algorithms)
+            .field("nonce_lifetime", &self.nonce_lifetime)
+            .field("nonces", &self.nonces.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// Credentials returned by `digest_auth`.
+///
+/// They must be checked with `DigestAuth::verify` or `DigestAuth::verify_ha1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigestCredentials {
+    /// Login provided by the client.
+    pub username: String,
+    /// Algorithm chosen by the client.
+    pub algorithm: DigestAlgorithm,
+    /// URL of the request, as given by the client.
+    pub uri: String,
+    nonce: String,
+    nonce_count: u32,
+    cnonce: String,
+    response: String,
+    method: String,
+}
+
+/// Error that can happen when verifying `DigestCredentials`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DigestAuthError {
+    /// The username or the password is wrong.
+    WrongCredentials,
+    /// The credentials are right, but the nonce has expired or is unknown. The client should
+    /// receive a new challenge with `stale` set to true.
+    StaleNonce,
+    /// The nonce count of the request isn't larger than the one of a previous request.
+    Replayed,
+}
+
+impl error::Error for DigestAuthError {}
+
+impl fmt::Display for DigestAuthError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            DigestAuthError::WrongCredentials => "the username or the password is wrong",
+            DigestAuthError::StaleNonce => "the nonce has expired",
+            DigestAuthError::Replayed => "the nonce count has already been used",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Attempts to parse an `Authorization` header with HTTP Digest authentication.
+///
+/// Returns `None` if there is no such header, if it is invalid, or if its realm, algorithm or URL
+/// doesn't match `auth` and the request. The credentials must then be checked with
+/// `DigestAuth::verify`. See [`DigestAuth`](struct.DigestAuth.html) for an example.
+pub fn digest_auth(request: &Request, auth: &DigestAuth) -> Option<DigestCredentials> {
+    let header = request.header("Authorization")?;
+    let mut split = header.trim_start().splitn(2, ' ');
+    if !split.next()?.eq_ignore_ascii_case("Digest") {
+        return None;
+    }
+    let params = parse_params(split.next()?)?;
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|&(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+
+    if param("realm")? != auth.realm {
+        return None;
+    }
+    if !param("qop")?.eq_ignore_ascii_case("auth") {
+        return None;
+    }
+    // Hashed and non-ASCII usernames aren't supported.
+    if param("userhash").map_or(false, |v| v.eq_ignore_ascii_case("true")) {
+        return None;
+    }
+    let algorithm_name = param("algorithm").unwrap_or_else(|| "MD5".to_owned());
+    let algorithm = *auth
+        .algorithms
+        .iter()
+        .find(|a| a.name().eq_ignore_ascii_case(&algorithm_name))?;
+    let uri = param("uri")?;
+    if uri != request.raw_url() {
+        return None;
+    }
+    let nonce_count = param("nc")?;
+    if nonce_count.len() != 8 {
+        return None;
+    }
+
+    Some(DigestCredentials {
+        username: param("username")?,
+        algorithm,
+        uri,
+        nonce: param("nonce")?,
+        nonce_count: u32::from_str_radix(&nonce_count, 16).ok()?,
+        cnonce: param("cnonce")?,
+        response: param("response")?,
+        method: request.method().to_owned(),
+    })
+}
+
+// Parses the comma-separated `name=value` parameters of the header, where the values are tokens
+// or quoted strings.
+fn parse_params(input: &str) -> Option<Vec<(String, String)>> {
+    let input = input.as_bytes();
+    let mut params = Vec::new();
+    let mut position = 0;
+    let skip_whitespace = |position: &mut usize| {
+        while *position < input.len() && (input[*position] == b' ' || input[*position] == b'\t') {
+            *position += 1;
+        }
+    };
+
+    loop {
+        skip_whitespace(&mut position);
+        if position >= input.len() {
+            return Some(params);
+        }
+
+        let start = position;
+        while position < input.len() && input[position] != b'=' {
+            position += 1;
+        }
+        let name = String::from_utf8(input[start..position].to_vec()).ok()?;
+        let name = name.trim().to_owned();
+        if name.is_empty() || position >= input.len() {
+            return None;
+        }
+        position += 1;
+        skip_whitespace(&mut position);
+
+        let mut value = Vec::new();
+        if input.get(position) == Some(&b'"') {
+            position += 1;
+            loop {
+                match *input.get(position)? {
+                    b'"' => break,
+                    b'\\' => {
+                        position += 1;
+                        value.push(*input.get(position)?);
+                    }
+                    b => value.push(b),
+                }
+                position += 1;
+            }
+            position += 1;
+        } else {
+            while position < input.len() && input[position] != b',' {
+                value.push(input[position]);
+                position += 1;
+            }
+            while value.last() == Some(&b' ') || value.last() == Some(&b'\t') {
+                value.pop();
+            }
+        }
+        params.push((name, String::from_utf8(value).ok()?));
+
+        skip_whitespace(&mut position);
+        match input.get(position) {
+            None => return Some(params),
+            Some(&b',') => position += 1,
+            Some(_) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::digest_auth;
+    use super::parse_params;
+    use super::DigestAlgorithm;
+    use super::DigestAuth;
+    use super::DigestAuthError;
+    use std::thread;
+    use std::time::Duration;
+    use Request;
+
+    // Returns the nonce of the first challenge of the response.
+    fn challenge_nonce(auth: &DigestAuth) -> String {
+        let response = auth.login_required(&Request::fake_http("GET", "/", vec![], vec![]), false);
+        let (_, challenge) = response
+            .headers
+            .iter()
+            .find(|&(name, _)| name == "WWW-Authenticate")
+            .unwrap();
+        let params = parse_params(&challenge["Digest ".len()..]).unwrap();
+        params.into_iter().find(|(n, _)| n == "nonce").unwrap().1
+    }
+
+    fn authorization(header: String) -> Request {
+        Request::fake_http(
+            "GET",
+            "/dir/index.html",
+            vec![("Authorization".to_owned(), header)],
+            vec![],
+        )
+    }
+
+    // Builds the header that a client would send.
+    fn client(
+        auth: &DigestAuth,
+        algorithm: DigestAlgorithm,
+        nonce: &str,
+        nc: u32,
+        password: &str,
+    ) -> Request {
+        let ha1 = auth.ha1(algorithm, "Mufasa", password);
+        let ha2 = algorithm.hash("GET:/dir/index.html");
+        let response = algorithm.hash(&format!(
+            "{}:{}:{:08x}:f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ:auth:{}",
+            ha1, nonce, nc, ha2
+        ));
+        authorization(format!(
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+             uri=\"/dir/index.html\", algorithm={}, nonce=\"{}\", nc={:08x}, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+             response=\"{}\"",
+            algorithm.name(),
+            nonce,
+            nc,
+            response
+        ))
+    }
+
+    #[test]
+    fn hashes() {
+        // The example of section 3.9.1 of RFC 7616.
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let header = |algorithm: &str, response: &str| {
+            authorization(format!(
+                "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", \
+                 uri=\"/dir/index.html\", algorithm={}, nonce=\"{}\", nc=00000001, \
+                 cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+                 response=\"{}\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+                algorithm, nonce, response
+            ))
+        };
+        let auth = DigestAuth::new("http-auth@example.org");
+        auth.nonces.lock().unwrap().insert(
+            nonce.to_owned(),
+            super::Nonce {
+                created: ::std::time::Instant::now(),
+                last_count: 0,
+            },
+        );
+
+        let request = header("MD5", "8ca523f5e9506fed4657c9700eebdbec");
+        let credentials = digest_auth(&request, &auth).unwrap();
+        assert_eq!(credentials.username, "Mufasa");
+        assert_eq!(credentials.algorithm, DigestAlgorithm::Md5);
+        assert_eq!(auth.verify(&credentials, "Circle of Life"), Ok(()));
+
+        let request = header(
+            "SHA-256",
+            "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+        );
+        let mut credentials = digest_auth(&request, &auth).unwrap();
+        credentials.nonce_count = 2;
+        assert_eq!(
+            auth.verify(&credentials, "Circle of Life"),
+            Err(DigestAuthError::WrongCredentials)
+        );
+        credentials.nonce_count = 1;
+        assert_eq!(
+            auth.verify(&credentials, "Circle of Life"),
+            Err(DigestAuthError::Replayed)
+        );
+    }
+
+    #[test]
+    fn nonces() {
+        let auth = DigestAuth::new("http-auth@example.org")
+            .algorithms(&[DigestAlgorithm::Sha256])
+            .nonce_lifetime(Duration::from_millis(200));
+        let nonce = challenge_nonce(&auth);
+        let sha256 = DigestAlgorithm::Sha256;
+
+        let credentials = digest_auth(&client(&auth, sha256, &nonce, 1, "pw"), &auth).unwrap();
+        assert_eq!(auth.verify(&credentials, "pw"), Ok(()));
+        assert_eq!(
+            auth.verify(&credentials, "pw"),
+            Err(DigestAuthError::Replayed)
+        );
+        let credentials = digest_auth(&client(&auth, sha256, &nonce, 2, "pw"), &auth).unwrap();
+        assert_eq!(
+            auth.verify(&credentials, "wrong"),
+            Err(DigestAuthError::WrongCredentials)
+        );
+        assert_eq!(auth.verify(&credentials, "pw"), Ok(()));
+
+        let credentials = digest_auth(&client(&auth, sha256, "unknown", 1, "pw"), &auth).unwrap();
+        assert_eq!(
+            auth.verify(&credentials, "pw"),
+            Err(DigestAuthError::StaleNonce)
+        );
+
+        thread::sleep(Duration::from_millis(250));
+        let credentials = digest_auth(&client(&auth, sha256, &nonce, 3, "pw"), &auth).unwrap();
+        assert_eq!(
+            auth.verify(&credentials, "pw"),
+            Err(DigestAuthError::StaleNonce)
+        );
+
+        // MD5 isn't offered.
+        let request = client(&auth, DigestAlgorithm::Md5, &nonce, 4, "pw");
+        assert_eq!(digest_auth(&request, &auth), None);
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let auth = DigestAuth::new("realm");
+        let check = |header: &str| digest_auth(&authorization(header.to_owned()), &auth);
+        assert_eq!(check("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="), None);
+        assert_eq!(check("Digest username="), None);
+        assert_eq!(
+            check(
+                "Digest username=\"a\", realm=\"other\", uri=\"/dir/index.html\", nonce=\"n\", \
+                 nc=00000001, cnonce=\"c\", qop=auth, response=\"r\""
+            ),
+            None
+        );
+        assert_eq!(
+            check(
+                "Digest username=\"a\", realm=\"realm\", uri=\"/other\", nonce=\"n\", \
+                 nc=00000001, cnonce=\"c\", qop=auth, response=\"r\""
+            ),
+            None
+        );
+        assert!(check(
+            "digest username=\"a\\\"b\", realm=realm, uri=\"/dir/index.html\", nonce=\"n\", \
+             nc=00000001, cnonce=\"c\", qop=auth, response=\"r\""
+        )
+        .is_some());
+
+        let response = auth.login_required(&authorization(String::new()), true);
+        assert_eq!(response.status_code, 401);
+        let challenges: Vec<_> = response
+            .headers
+            .iter()
+            .filter(|&(name, _)| name == "WWW-Authenticate")
+            .collect();
+        assert_eq!(challenges.len(), 2);
+        assert!(challenges[0].1.contains("algorithm=SHA-256"));
+        assert!(challenges[1].1.contains("algorithm=MD5"));
+        assert!(challenges[1].1.ends_with(", stale=true"));
+    }
+}
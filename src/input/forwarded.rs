@@ -0,0 +1,328 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use Request;
+
+/// One element of a `Forwarded` header, as described in
+/// [RFC 7239](https://tools.ietf.org/html/rfc7239). Each proxy adds an element about the
+/// request that it received.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedElement {
+    /// The `for` parameter: the client that sent the request to the proxy.
+    pub forwarded_for: Option<ForwardedNode>,
+    /// The `by` parameter: the interface of the proxy that received the request.
+    pub by: Option<ForwardedNode>,
+    /// The `host` parameter: the `Host` header of the request received by the proxy.
+    pub host: Option<String>,
+    /// The `proto` parameter: the scheme of the request received by the proxy, in lowercase.
+    pub proto: Option<String>,
+}
+
+/// A node of the `for` or `by` parameters of a `Forwarded` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardedNode {
+    /// The address or the identifier of the node.
+    pub name: ForwardedName,
+    /// The port of the node, if known.
+    pub port: Option<ForwardedPort>,
+}
+
+/// Address or identifier of a `ForwardedNode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardedName {
+    /// The IP address of the node.
+    Ip(IpAddr),
+    /// The proxy doesn't know the node, or doesn't want to reveal it.
+    Unknown,
+    /// An identifier chosen by the proxy to hide the address of the node, such as `_hidden`.
+    Obfuscated(String),
+}
+
+/// Port of a `ForwardedNode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardedPort {
+    /// The port number.
+    Port(u16),
+    /// An identifier chosen by the proxy to hide the port, such as `_abc`.
+    Obfuscated(String),
+}
+
+/// Parses the value of a `Forwarded` header.
+///
+/// Returns `None` if the header is malformed. Since a single proxy that doesn't follow the RFC
+/// makes the whole header unreliable, none of its elements are returned in that case.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::{parse_forwarded, ForwardedName};
+///
+/// let elements = parse_forwarded(r#"for=192.0.2.60;proto=http, for="[2001:db8::1]:4711""#)
+///     .unwrap();
+/// assert_eq!(elements.len(), 2);
+/// assert_eq!(elements[0].proto.as_ref().unwrap(), "http");
+/// let node = elements[1].forwarded_for.as_ref().unwrap();
+/// assert_eq!(node.name, ForwardedName::Ip("2001:db8::1".parse().unwrap()));
+/// ```
+pub fn parse_forwarded(header: &str) -> Option<Vec<ForwardedElement>> {
+    let mut parser = Parser {
+        input: header.as_bytes(),
+        position: 0,
+    };
+    let mut elements = Vec::new();
+    let mut element = ForwardedElement::default();
+
+    loop {
+        parser.skip_whitespace();
+        // Empty elements are allowed by the list syntax of HTTP.
+        if parser.eat(b',') {
+            continue;
+        }
+        if parser.at_end() {
+            break;
+        }
+
+        let name = parser.token()?.to_ascii_lowercase();
+        if !parser.eat(b'=') {
+            return None;
+        }
+        let value = if parser.peek() == Some(b'"') {
+            parser.quoted_string()?
+        } else {
+            parser.token()?
+        };
+
+        let duplicate = match &name[..] {
+            "for" => element.forwarded_for.replace(parse_node(&value)?).is_some(),
+            "by" => element.by.replace(parse_node(&value)?).is_some(),
+            "host" => element.host.replace(value).is_some(),
+            "proto" => element.proto.replace(value.to_ascii_lowercase()).is_some(),
+            // Extensions are ignored.
+            _ => false,
+        };
+        if duplicate {
+            return None;
+        }
+
+        parser.skip_whitespace();
+        if parser.eat(b',') || parser.at_end() {
+            elements.push(element);
+            element = ForwardedElement::default();
+        } else if !parser.eat(b';') {
+            return None;
+        } else {
+            parser.skip_whitespace();
+            if parser.at_end() || parser.peek() == Some(b',') {
+                return None;
+            }
+        }
+    }
+
+    Some(elements)
+}
+
+/// Parses all the `Forwarded` headers of a request, in the order in which the proxies added
+/// them.
+///
+/// Returns `None` if the request has no such header, or if one of them is malformed.
+pub fn forwarded(request: &Request) -> Option<Vec<ForwardedElement>> {
+    let mut elements = Vec::new();
+    let mut found = false;
+    for (name, value) in request.headers() {
+        if name.eq_ignore_ascii_case("Forwarded") {
+            found = true;
+            elements.extend(parse_forwarded(value)?);
+        }
+    }
+    if found {
+        Some(elements)
+    } else {
+        None
+    }
+}
+
+// Parses `nodename [ ":" node-port ]`.
+fn parse_node(value: &str) -> Option<ForwardedNode> {
+    let (name, port) = if value.starts_with('[') {
+        let end = value.find(']')?;
+        let ip: Ipv6Addr = value[1..end].parse().ok()?;
+        let port = match &value[end + 1..] {
+            "" => None,
+            rest if rest.starts_with(':') => Some(&rest[1..]),
+            _ => return None,
+        };
+        (ForwardedName::Ip(IpAddr::V6(ip)), port)
+    } else {
+        let mut split = value.splitn(2, ':');
+        let name = split.next().unwrap_or("");
+        let name = if name.eq_ignore_ascii_case("unknown") {
+            ForwardedName::Unknown
+        } else if is_obfuscated(name) {
+            ForwardedName::Obfuscated(name.to_owned())
+        } else {
+            let ip: Ipv4Addr = name.parse().ok()?;
+            ForwardedName::Ip(IpAddr::V4(ip))
+        };
+        (name, split.next())
+    };
+
+    let port = match port {
+        None => None,
+        Some(port) if is_obfuscated(port) => Some(ForwardedPort::Obfuscated(port.to_owned())),
+        Some(port) if !port.is_empty() && port.len() <= 5 => {
+            Some(ForwardedPort::Port(port.parse().ok()?))
+        }
+        Some(_) => return None,
+    };
+    Some(ForwardedNode { name, port })
+}
+
+// Returns true for `"_" 1*( ALPHA / DIGIT / "." / "_" / "-")`.
+fn is_obfuscated(value: &str) -> bool {
+    value.len() > 1
+        && value.starts_with('_')
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'_' || b == b'-')
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).cloned()
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.input.len()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek() == Some(b' ') || self.peek() == Some(b'\t') {
+            self.position += 1;
+        }
+    }
+
+    // Parses a non-empty token, as defined by RFC 7230.
+    fn token(&mut self) -> Option<String> {
+        let start = self.position;
+        while let Some(b) = self.peek() {
+            if b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b) {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        if self.position == start {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&self.input[start..self.position]).into_owned())
+    }
+
+    // Parses a quoted string, starting at its opening quote, and unescapes it.
+    fn quoted_string(&mut self) -> Option<String> {
+        self.position += 1;
+        let mut value = Vec::new();
+        loop {
+            let b = self.peek()?;
+            self.position += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    value.push(self.peek()?);
+                    self.position += 1;
+                }
+                b => value.push(b),
+            }
+        }
+        String::from_utf8(value).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_forwarded;
+    use super::ForwardedName;
+    use super::ForwardedNode;
+    use super::ForwardedPort;
+
+    #[test]
+    fn nodes() {
+        let elements = parse_forwarded(
+            r#"For="[2001:db8:cafe::17]:4711", for="192.0.2.43:_port";by=_hidden, for=unknown"#,
+        )
+        .unwrap();
+        let nodes: Vec<_> = elements
+            .iter()
+            .map(|e| e.forwarded_for.clone().unwrap())
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                ForwardedNode {
+                    name: ForwardedName::Ip("2001:db8:cafe::17".parse().unwrap()),
+                    port: Some(ForwardedPort::Port(4711)),
+                },
+                ForwardedNode {
+                    name: ForwardedName::Ip("192.0.2.43".parse().unwrap()),
+                    port: Some(ForwardedPort::Obfuscated("_port".to_owned())),
+                },
+                ForwardedNode {
+                    name: ForwardedName::Unknown,
+                    port: None,
+                },
+            ]
+        );
+        assert_eq!(
+            elements[1].by.as_ref().unwrap().name,
+            ForwardedName::Obfuscated("_hidden".to_owned())
+        );
+    }
+
+    #[test]
+    fn parameters() {
+        let elements =
+            parse_forwarded(r#"proto=HTTPS; host="example.com:8080";ext="a\"b", , for=10.0.0.1"#)
+                .unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].proto.as_ref().unwrap(), "https");
+        assert_eq!(elements[0].host.as_ref().unwrap(), "example.com:8080");
+        assert_eq!(elements[0].forwarded_for, None);
+        assert_eq!(parse_forwarded("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse_forwarded("for"), None);
+        assert_eq!(parse_forwarded("for=2001:db8::1"), None);
+        assert_eq!(parse_forwarded(r#"for="[2001:db8::1"#), None);
+        assert_eq!(parse_forwarded("for=1.2.3.4;for=5.6.7.8"), None);
+        assert_eq!(parse_forwarded("for=1.2.3.4 proto=http"), None);
+        assert_eq!(parse_forwarded(r#"for="1.2.3.4:123456""#), None);
+        assert_eq!(parse_forwarded("for=1.2.3.4:80"), None);
+        assert_eq!(parse_forwarded("for=1.2.3.4;"), None);
+        assert_eq!(parse_forwarded("for=_"), None);
+    }
+}
@@ -34,6 +34,7 @@
 //! ```
 //!
 
+use mime;
 use serde;
 use serde_json;
 use serde_json::Value;
@@ -161,11 +162,7 @@ where
 {
     // TODO: add an optional bytes limit
 
-    if let Some(header) = request.header("Content-Type") {
-        if !header.starts_with("application/json") {
-            return Err(JsonError::WrongContentType);
-        }
-    } else {
+    if !mime::content_type(request).map_or(false, |ct| ct.is_structured("json")) {
         return Err(JsonError::WrongContentType);
     }
 
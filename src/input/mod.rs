@@ -14,26 +14,78 @@
 //!
 //! - In order to parse JSON, see [the `json` module](json/index.html).
 //! - In order to parse input from HTML forms, see [the `post` module](post/index.html).
+//! - In order to read all the parameters of the query string, in order, see
+//!   [the `parse_query` function](fn.parse_query.html).
 //! - In order to read a plain text body, see
 //!   [the `plain_text_body` function](fn.plain_text_body.html).
+//! - In order to read the `Forwarded` header added by proxies, see
+//!   [the `parse_forwarded` function](fn.parse_forwarded.html).
+//! - In order to read a bearer token, see [the `bearer_token` function](fn.bearer_token.html),
+//!   and [the `jwt` module](jwt/index.html) behind the `jwt` feature to validate JSON Web
+//!   Tokens.
+//! - In order to authenticate clients with HTTP Digest authentication, see
+//!   [`DigestAuth`](struct.DigestAuth.html), behind the `digest-auth` feature.
+//! - In order to verify the signature of webhooks, see [the `webhook` module](webhook/index.html),
+//!   behind the `webhook` feature.
+//! - In order to accept uploads sent in several `PATCH` requests with a `Content-Range`, see
+//!   [the `range_upload` function](fn.range_upload.html).
 
 pub use self::basic_http_auth::basic_http_auth;
+pub use self::basic_http_auth::basic_http_auth_required;
 pub use self::basic_http_auth::HttpAuthCredentials;
+pub use self::bearer::bearer_token;
+pub use self::cookies::cookie_jar;
 pub use self::cookies::cookies;
+pub use self::cookies::CookieJar;
+pub use self::cookies::CookieJarIter;
 pub use self::cookies::CookiesIter;
+#[cfg(feature = "digest-auth")]
+pub use self::digest_auth::{
+    digest_auth, DigestAlgorithm, DigestAuth, DigestAuthError, DigestCredentials,
+};
+pub use self::forwarded::forwarded;
+pub use self::forwarded::parse_forwarded;
+pub use self::forwarded::ForwardedElement;
+pub use self::forwarded::ForwardedName;
+pub use self::forwarded::ForwardedNode;
+pub use self::forwarded::ForwardedPort;
 pub use self::json::json_input;
 pub use self::plain::plain_text_body;
 pub use self::plain::plain_text_body_with_limit;
+pub use self::preconditions::preconditions;
+pub use self::preconditions::Precondition;
+pub use self::priority_header::accept_language_preferred;
+pub use self::priority_header::parse_accept_header;
 pub use self::priority_header::parse_priority_header;
 pub use self::priority_header::priority_header_preferred;
+pub use self::priority_header::AcceptHeader;
+pub use self::priority_header::AcceptItem;
 pub use self::priority_header::PriorityHeaderIter;
+pub use self::query::parse_query;
+pub use self::query::QueryParams;
+pub use self::range_upload::parse_content_range;
+pub use self::range_upload::range_upload;
+pub use self::range_upload::ContentRange;
+pub use self::range_upload::RangeUpload;
+pub use self::range_upload::RangeUploadError;
 
 pub mod json;
+#[cfg(feature = "jwt")]
+pub mod jwt;
 pub mod multipart;
 pub mod post;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
 mod accept;
 mod basic_http_auth;
+mod bearer;
 mod cookies;
+#[cfg(feature = "digest-auth")]
+mod digest_auth;
+mod forwarded;
 mod plain;
+mod preconditions;
 mod priority_header;
+mod query;
+mod range_upload;
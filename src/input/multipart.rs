@@ -15,6 +15,7 @@
 use std::error;
 use std::fmt;
 
+use mime;
 use Request;
 use RequestBody;
 
@@ -85,20 +86,14 @@ impl<'a> Multipart<'a> {
 }
 
 fn multipart_boundary(request: &Request) -> Option<String> {
-    const BOUNDARY: &str = "boundary=";
-
-    let content_type = match request.header("Content-Type") {
+    let content_type = match mime::content_type(request) {
+        Some(ct) => ct,
         None => return None,
-        Some(c) => c,
     };
 
-    let start = match content_type.find(BOUNDARY) {
-        Some(pos) => pos + BOUNDARY.len(),
-        None => return None,
-    };
+    if content_type.main_type() != "multipart" {
+        return None;
+    }
 
-    let end = content_type[start..]
-        .find(';')
-        .map_or(content_type.len(), |end| start + end);
-    Some(content_type[start..end].to_owned())
+    content_type.param("boundary").map(|b| b.to_owned())
 }
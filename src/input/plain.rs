@@ -7,6 +7,7 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use mime;
 use std::error;
 use std::fmt;
 use std::io::Error as IoError;
@@ -105,11 +106,7 @@ pub fn plain_text_body_with_limit(
     // TODO: handle encoding ; return NotUtf8 if a non-utf8 charset is sent
     // if no encoding is specified by the client, the default is `US-ASCII` which is compatible with UTF8
 
-    if let Some(header) = request.header("Content-Type") {
-        if !header.starts_with("text/plain") {
-            return Err(PlainTextError::WrongContentType);
-        }
-    } else {
+    if !mime::content_type(request).map_or(false, |ct| ct.is("text/plain")) {
         return Err(PlainTextError::WrongContentType);
     }
 
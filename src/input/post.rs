@@ -101,6 +101,7 @@
 //! `from_file` method. You should return `PostFieldError::WrongFieldType` if you're
 //! expecting a file and `from_field` was called, or vice-versa.
 
+use mime;
 use Request;
 
 use std::borrow::Cow;
@@ -135,6 +136,10 @@ pub enum PostError {
         field: Cow<'static, str>,
         error: PostFieldError,
     },
+
+    /// There were errors with one or more fields. Only returned when `post_input!` is asked to
+    /// report `all_errors`.
+    Fields(Vec<FieldError>),
 }
 
 impl From<IoError> for PostError {
@@ -168,12 +173,49 @@ impl fmt::Display for PostError {
                 "the content-type encoding is not ASCII or UTF-8, or the body is not valid UTF-8"
             }
             PostError::Field { .. } => "failed to parse a requested field",
+            PostError::Fields(ref errors) => {
+                write!(fmt, "failed to parse the requested fields")?;
+                for (n, error) in errors.iter().enumerate() {
+                    let separator = if n == 0 { ": " } else { "; " };
+                    write!(fmt, "{}{}", separator, error)?;
+                }
+                return Ok(());
+            }
         };
 
         write!(fmt, "{}", description)
     }
 }
 
+/// Error with one of the fields, as reported by `PostError::Fields`.
+#[derive(Debug)]
+pub struct FieldError {
+    /// Name of the field.
+    pub path: Cow<'static, str>,
+    /// The type that the field was decoded into, as written in the `post_input!` macro.
+    pub expected: &'static str,
+    /// Why the field couldn't be decoded.
+    pub error: PostFieldError,
+}
+
+impl error::Error for FieldError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl fmt::Display for FieldError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "field `{}` (expected `{}`): {}",
+            self.path, self.expected, self.error
+        )
+    }
+}
+
 /// Error returned by the methods of [the `DecodePostField` trait](trait.DecodePostField.html).
 #[derive(Debug)]
 pub enum PostFieldError {
@@ -496,13 +538,48 @@ impl DecodePostField<()> for BufferedFile {
 
 /// Parse input from HTML forms. See [the `post` module](input/post/index.html) for general
 /// documentation.
+///
+/// By default the macro returns an error as soon as one field fails to decode. If you add
+/// `all_errors` after the list of fields, all the fields are decoded and the failures are
+/// returned together in a `PostError::Fields`, so that a client can fix all of them at once.
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use rouille::Request;
+/// use rouille::Response;
+/// use rouille::input::post::PostError;
+///
+/// fn handle_request(request: &Request) -> Response {
+///     let input = match post_input!(request, { age: u32, name: String }, all_errors) {
+///         Ok(input) => input,
+///         Err(PostError::Fields(errors)) => {
+///             let fields: Vec<_> = errors.iter().map(|e| e.path.to_string()).collect();
+///             return Response::text(format!("invalid fields: {}", fields.join(", ")))
+///                 .with_status_code(400);
+///         }
+///         Err(err) => return rouille::try_or_400::error_response(&err),
+///     };
+///
+///     Response::text(format!("hello {}", input.name))
+/// }
+/// # fn main() {}
+/// ```
 #[macro_export]
 macro_rules! post_input {
-    ($request:expr, {$($field:ident: $ty:ty $({$config:expr})*),*$(,)*}) => ({
+    ($request:expr, {$($field:ident: $ty:ty $({$config:expr})*),*$(,)*}) => (
+        post_input!(__impl $request, false, {$($field: $ty $({$config})*),*})
+    );
+
+    ($request:expr, {$($field:ident: $ty:ty $({$config:expr})*),*$(,)*}, all_errors) => (
+        post_input!(__impl $request, true, {$($field: $ty $({$config})*),*})
+    );
+
+    (__impl $request:expr, $collect:expr, {$($field:ident: $ty:ty $({$config:expr})*),*}) => ({
         use std::io::Read;
         use std::result::Result;
         use $crate::Request;
         use $crate::input::post::DecodePostField;
+        use $crate::input::post::FieldError;
         use $crate::input::post::PostFieldError;
         use $crate::input::post::PostError;
         use $crate::input::post::form_urlencoded;
@@ -530,14 +607,29 @@ macro_rules! post_input {
             Ok(())
         }
 
-        fn go(request: &Request) -> Result<PostInput, PostError> {
+        // Records the error if all the errors are collected, otherwise returns it.
+        fn fail(errors: &mut Option<Vec<FieldError>>, field: &'static str,
+                expected: &'static str, error: PostFieldError) -> Result<(), PostError>
+        {
+            match *errors {
+                Some(ref mut errors) => {
+                    errors.push(FieldError { path: field.into(), expected, error });
+                    Ok(())
+                },
+                None => Err(PostError::Field { field: field.into(), error }),
+            }
+        }
+
+        fn go(request: &Request, collect: bool) -> Result<PostInput, PostError> {
             $(
                 let mut $field: Option<$ty> = None;
             )*
 
+            let mut errors = if collect { Some(Vec::new()) } else { None };
+
             // TODO: handle if the same field is specified multiple times
 
-            if request.header("Content-Type").map(|ct| ct.starts_with("application/x-www-form-urlencoded")).unwrap_or(false) {
+            if $crate::mime::content_type(request).map_or(false, |ct| ct.is("application/x-www-form-urlencoded")) {
                 let body = {
                     // TODO: DDoSable server if body is too large?
                     let mut out = Vec::new();       // TODO: with_capacity()?
@@ -557,20 +649,13 @@ macro_rules! post_input {
                                 let config = $config;
                             )*
 
-                            let decoded = match DecodePostField::from_field(config, &value) {
-                                Ok(d) => d,
-                                Err(err) => return Err(PostError::Field {
-                                    field: stringify!($field).into(),
-                                    error: err,
-                                }),
-                            };
-
-                            match merge(&mut $field, decoded) {
-                                Ok(d) => d,
-                                Err(err) => return Err(PostError::Field {
-                                    field: stringify!($field).into(),
-                                    error: err,
-                                }),
+                            match DecodePostField::from_field(config, &value) {
+                                Ok(decoded) => if let Err(err) = merge(&mut $field, decoded) {
+                                    fail(&mut errors, stringify!($field), stringify!($ty), err)?;
+                                },
+                                Err(err) => {
+                                    fail(&mut errors, stringify!($field), stringify!($ty), err)?;
+                                },
                             };
                             continue;
                         }
@@ -596,74 +681,70 @@ macro_rules! post_input {
                                 let config = $config;
                             )*
 
-                            if multipart_entry.is_text() {
+                            let decoded = if multipart_entry.is_text() {
                                 let mut text = String::new();
                                 multipart_entry.data.read_to_string(&mut text)?;
-                                let decoded = match DecodePostField::from_field(config, &text) {
-                                    Ok(d) => d,
-                                    Err(err) => return Err(PostError::Field {
-                                        field: stringify!($field).into(),
-                                        error: err,
-                                    }),
-                                };
-                                match merge(&mut $field, decoded) {
-                                    Ok(d) => d,
-                                    Err(err) => return Err(PostError::Field {
-                                        field: stringify!($field).into(),
-                                        error: err,
-                                    }),
-                                };
+                                DecodePostField::from_field(config, &text)
                             } else {
                                 let name = multipart_entry.headers.filename.as_ref().map(|n| n.to_owned());
                                 let name = name.as_ref().map(|n| &n[..]);
                                 let mime = multipart_entry.headers.content_type
                                     .map(|m| m.to_string())
                                     .unwrap_or_else(String::new);
-                                let decoded = match DecodePostField::from_file(config, multipart_entry.data, name, &mime) {
-                                    Ok(d) => d,
-                                    Err(err) => return Err(PostError::Field {
-                                        field: stringify!($field).into(),
-                                        error: err,
-                                    }),
-                                };
-                                match merge(&mut $field, decoded) {
-                                    Ok(d) => d,
-                                    Err(err) => return Err(PostError::Field {
-                                        field: stringify!($field).into(),
-                                        error: err,
-                                    }),
-                                };
-                            }
+                                DecodePostField::from_file(config, multipart_entry.data, name, &mime)
+                            };
+
+                            match decoded {
+                                Ok(decoded) => if let Err(err) = merge(&mut $field, decoded) {
+                                    fail(&mut errors, stringify!($field), stringify!($ty), err)?;
+                                },
+                                Err(err) => {
+                                    fail(&mut errors, stringify!($field), stringify!($ty), err)?;
+                                },
+                            };
                             continue;
                         }
                     )*
                 }
             }
 
+            $(
+                let $field = match $field {
+                    Some(v) => Some(v),
+                    // A field that failed to decode is also absent, but only its first error
+                    // is relevant.
+                    None if errors.as_ref().map_or(false, |e| e.iter().any(|e| e.path == stringify!($field))) => None,
+                    None => {
+                        let config = ();
+                        $(
+                            let config = $config;
+                        )*
+
+                        match DecodePostField::not_found(config) {
+                            Ok(d) => Some(d),
+                            Err(err) => {
+                                fail(&mut errors, stringify!($field), stringify!($ty), err)?;
+                                None
+                            },
+                        }
+                    }
+                };
+            )*
+
+            if let Some(errors) = errors {
+                if !errors.is_empty() {
+                    return Err(PostError::Fields(errors));
+                }
+            }
+
             Ok(PostInput {
                 $(
-                    $field: match $field {
-                        Some(v) => v,
-                        None => {
-                            let config = ();
-                            $(
-                                let config = $config;
-                            )*
-
-                            match DecodePostField::not_found(config) {
-                                Ok(d) => d,
-                                Err(err) => return Err(PostError::Field {
-                                    field: stringify!($field).into(),
-                                    error: err,
-                                }),
-                            }
-                        }
-                    },
+                    $field: $field.unwrap(),
                 )*
             })
         }
 
-        go($request)
+        go($request, $collect)
     });
 }
 
@@ -674,11 +755,7 @@ macro_rules! post_input {
 /// Returns an error if the request's content-type is not related to POST data.
 // TODO: what to do with this function?
 pub fn raw_urlencoded_post_input(request: &Request) -> Result<Vec<(String, String)>, PostError> {
-    if request
-        .header("Content-Type")
-        .map(|ct| !ct.starts_with("application/x-www-form-urlencoded"))
-        .unwrap_or(true)
-    {
+    if !mime::content_type(request).map_or(false, |ct| ct.is("application/x-www-form-urlencoded")) {
         return Err(PostError::WrongContentType);
     }
 
@@ -1011,6 +1088,78 @@ mod tests {
         }
     }
 
+    #[test]
+    fn all_errors() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Host".to_owned(), "localhost".to_owned()),
+                (
+                    "Content-Type".to_owned(),
+                    "application/x-www-form-urlencoded".to_owned(),
+                ),
+            ],
+            b"a=x&b=1&b=2&c=ok&d=1.5".to_vec(),
+        );
+
+        let input = post_input!(&request, {
+            a: u32,
+            b: u32,
+            c: String,
+            d: f32,
+            e: String,
+        }, all_errors);
+
+        let errors = match input.map(|i| (i.a, i.b, i.c, i.d, i.e)) {
+            Err(PostError::Fields(errors)) => errors,
+            _ => panic!(),
+        };
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.path.to_string(), e.expected, e.error.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "a".to_owned(),
+                    "u32",
+                    "failed to parse an integer field".to_owned()
+                ),
+                (
+                    "b".to_owned(),
+                    "u32",
+                    "got multiple values for the same field while only one was expected".to_owned()
+                ),
+                (
+                    "e".to_owned(),
+                    "String",
+                    "the field is missing from the request's client".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_errors_success() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Host".to_owned(), "localhost".to_owned()),
+                (
+                    "Content-Type".to_owned(),
+                    "application/x-www-form-urlencoded".to_owned(),
+                ),
+            ],
+            b"field=12".to_vec(),
+        );
+
+        let input = post_input!(&request, { field: u32 }, all_errors).unwrap();
+        assert_eq!(input.field, 12);
+    }
+
     #[test]
     fn missing_field_option() {
         let request = Request::fake_http(
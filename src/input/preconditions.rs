@@ -0,0 +1,190 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use http_date::HttpDate;
+use std::time::SystemTime;
+use Request;
+use Response;
+
+/// Result of [`preconditions`](fn.preconditions.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// The preconditions of the request are satisfied.
+    Proceed,
+    /// The request has neither an `If-Match` nor an `If-Unmodified-Since` header.
+    Missing,
+    /// A precondition of the request isn't satisfied. The request must be answered with
+    /// `412 Precondition Failed`.
+    Failed,
+}
+
+impl Precondition {
+    /// Returns the response that answers the request instead of the handler, or `None` if the
+    /// handler can proceed.
+    ///
+    /// If `required` is true, the requests without preconditions are answered with
+    /// `428 Precondition Required`, which forces the clients to fetch the current version of the
+    /// resource before modifying it.
+    pub fn error_response(self, required: bool) -> Option<Response> {
+        match self {
+            Precondition::Proceed => None,
+            Precondition::Missing if !required => None,
+            Precondition::Missing => {
+                Some(Response::text("Precondition Required").with_status_code(428))
+            }
+            Precondition::Failed => {
+                Some(Response::text("Precondition Failed").with_status_code(412))
+            }
+        }
+    }
+}
+
+/// Evaluates the `If-Match` and `If-Unmodified-Since` headers of a request that modifies a
+/// resource, as described in RFC 7232.
+///
+/// `current_etag` and `last_modified` describe the current state of the resource, and are
+/// `None` if the resource doesn't exist or if they aren't known. The ETag can be quoted or not,
+/// like in [`Response::with_etag`](../struct.Response.html#method.with_etag).
+///
+/// `If-Match` uses the strong comparison, which means that weak ETags never match, except
+/// with `If-Match: *`, which matches if the resource exists. `If-Unmodified-Since` is ignored if
+/// the request has an `If-Match` header, if its date is invalid, or if `last_modified` is
+/// `None`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Request, Response};
+/// use rouille::input::preconditions;
+///
+/// fn update_document(request: &Request, version: u64) -> Response {
+///     let etag = format!("\"{}\"", version);
+///     if let Some(response) = preconditions(request, Some(&etag), None).error_response(true) {
+///         return response;
+///     }
+///
+///     // ... update the document ...
+///     Response::empty_204()
+/// }
+/// ```
+pub fn preconditions(
+    request: &Request,
+    current_etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> Precondition {
+    if let Some(header) = request.header("If-Match") {
+        let exists = current_etag.is_some() || last_modified.is_some();
+        let matches = if header.trim() == "*" {
+            exists
+        } else {
+            current_etag.map_or(false, |etag| {
+                header
+                    .split(',')
+                    .any(|candidate| strong_match(candidate, etag))
+            })
+        };
+
+        return if matches {
+            Precondition::Proceed
+        } else {
+            Precondition::Failed
+        };
+    }
+
+    if let Some(header) = request.header("If-Unmodified-Since") {
+        if let (Ok(date), Some(modified)) = (header.parse::<HttpDate>(), last_modified) {
+            // HTTP dates don't have a precision below the second.
+            if HttpDate::from(modified) > date {
+                return Precondition::Failed;
+            }
+        }
+        return Precondition::Proceed;
+    }
+
+    Precondition::Missing
+}
+
+// Returns true if two ETags are identical and neither of them is weak.
+fn strong_match(a: &str, b: &str) -> bool {
+    let (a, b) = (a.trim(), b.trim());
+    if a.starts_with("W/") || b.starts_with("W/") {
+        return false;
+    }
+    a.trim_matches('"') == b.trim_matches('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::preconditions;
+    use super::Precondition;
+    use std::time::Duration;
+    use std::time::SystemTime;
+    use std::time::UNIX_EPOCH;
+    use Request;
+
+    fn request(header: &str, value: &str) -> Request {
+        let headers = vec![(header.to_owned(), value.to_owned())];
+        Request::fake_http("PUT", "/", headers, vec![])
+    }
+
+    #[test]
+    fn if_match() {
+        let check = |value, etag| preconditions(&request("If-Match", value), etag, None);
+        assert_eq!(check("\"a\"", Some("\"a\"")), Precondition::Proceed);
+        assert_eq!(check("\"b\", \"a\"", Some("a")), Precondition::Proceed);
+        assert_eq!(check("\"b\"", Some("\"a\"")), Precondition::Failed);
+        assert_eq!(check("W/\"a\"", Some("\"a\"")), Precondition::Failed);
+        assert_eq!(check("*", Some("W/\"a\"")), Precondition::Proceed);
+        assert_eq!(check("*", None), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let date = UNIX_EPOCH + Duration::from_secs(784111777);
+        let check = |value, modified: SystemTime| {
+            preconditions(&request("If-Unmodified-Since", value), None, Some(modified))
+        };
+
+        let value = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(check(value, date), Precondition::Proceed);
+        assert_eq!(
+            check(value, date + Duration::from_millis(500)),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check(value, date + Duration::from_secs(1)),
+            Precondition::Failed
+        );
+        assert_eq!(
+            check("invalid", date + Duration::from_secs(1)),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check("Sun Nov  6 08:49:37 1994", date + Duration::from_secs(1)),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn missing() {
+        let request = Request::fake_http("PUT", "/", vec![], vec![]);
+        let result = preconditions(&request, Some("\"a\""), None);
+        assert_eq!(result, Precondition::Missing);
+        assert!(result.error_response(false).is_none());
+        assert_eq!(result.error_response(true).unwrap().status_code, 428);
+        assert_eq!(
+            Precondition::Failed
+                .error_response(false)
+                .unwrap()
+                .status_code,
+            412
+        );
+    }
+}
@@ -7,6 +7,7 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use std::cmp::Ordering;
 use std::f32;
 use std::str::FromStr;
 use std::str::Split;
@@ -16,7 +17,9 @@ use std::str::Split;
 /// This function takes the value of a priority header and a list of elements that can be handled
 /// by the server, and returns the index within that list of the element with the highest priority.
 ///
-/// If multiple elements in `handled` match and have the same priority, the first one is returned.
+/// The priority of an element is the one of the most specific value of the header that matches
+/// it, as described in [`parse_accept_header`](fn.parse_accept_header.html). If multiple elements
+/// in `handled` match and have the same priority, the first one is returned.
 ///
 /// # Example
 ///
@@ -31,60 +34,311 @@ pub fn priority_header_preferred<'a, I>(input: &'a str, elements: I) -> Option<u
 where
     I: Iterator<Item = &'a str>,
 {
+    let header = parse_accept_header(input);
     let mut result = (None, f32::NEG_INFINITY);
 
     for (index, req_elem) in elements.enumerate() {
-        for (header_elem, prio) in parse_priority_header(input) {
-            if prio <= result.1 {
-                continue;
+        let prio = header
+            .matching(req_elem)
+            .map(|item| item.quality)
+            .unwrap_or(f32::NEG_INFINITY);
+        if prio > result.1 {
+            result = (Some(index), prio);
+        }
+    }
+
+    result.0
+}
+
+/// Returns the language preferred by the client amongst a list of languages.
+///
+/// This function takes the value of an `Accept-Language` header and the language tags that the
+/// server supports, for example `en`, `en-GB` or `fr`, and returns the index within that list of
+/// the language with the highest priority. Returns `None` if no language is acceptable, in which
+/// case the server usually uses its default language.
+///
+/// The tags are compared case-insensitively, and a tag of the header matches the more specific
+/// tags: `en` matches `en-GB`. When the header only contains more specific tags than the
+/// supported ones, the tags of the header fall back to their prefix: `en-GB` matches `en`, or
+/// `en-US` if there is no `en`. The closest match wins amongst the languages that have the
+/// same priority, then the first one in the list.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::accept_language_preferred;
+///
+/// let supported = ["en", "fr", "de"];
+/// let preferred = accept_language_preferred("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5", supported.iter().cloned());
+/// assert_eq!(preferred, Some(1));
+///
+/// let preferred = accept_language_preferred("en-GB", supported.iter().cloned());
+/// assert_eq!(preferred, Some(0));
+///
+/// assert_eq!(accept_language_preferred("es", supported.iter().cloned()), None);
+/// ```
+pub fn accept_language_preferred<'a, I>(input: &str, supported: I) -> Option<usize>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let header = parse_accept_header(input);
+    let mut result: Option<(usize, f32, u8)> = None;
+
+    for (index, language) in supported.enumerate() {
+        // The most specific item of the header that matches the language determines its
+        // priority.
+        let mut best: Option<(f32, u8)> = None;
+        for item in header.items() {
+            let closeness = match language_closeness(item.value, language) {
+                Some(c) => c,
+                None => continue,
+            };
+            if best.map_or(true, |b| closeness > b.1) {
+                best = Some((item.quality, closeness));
             }
+        }
 
-            if req_elem == header_elem {
-                result = (Some(index), prio);
-                continue;
+        let (quality, closeness) = match best {
+            Some(b) if b.0 > 0.0 => b,
+            _ => continue,
+        };
+        let better = result.map_or(true, |r| {
+            quality > r.1 || (quality == r.1 && closeness > r.2)
+        });
+        if better {
+            result = Some((index, quality, closeness));
+        }
+    }
+
+    result.map(|r| r.0)
+}
+
+// Returns how closely a language range of the header matches a supported language tag, or
+// `None` if it doesn't match. Higher is closer.
+fn language_closeness(range: &str, language: &str) -> Option<u8> {
+    // Returns true if `prefix` is equal to `tag` or to one of its prefixes.
+    let is_prefix = |prefix: &str, tag: &str| {
+        tag.len() > prefix.len()
+            && tag.as_bytes()[prefix.len()] == b'-'
+            && tag[..prefix.len()].eq_ignore_ascii_case(prefix)
+    };
+
+    if range == "*" {
+        return Some(0);
+    }
+    if range.eq_ignore_ascii_case(language) {
+        return Some(4);
+    }
+    if is_prefix(range, language) {
+        return Some(3);
+    }
+
+    // Falls back to the primary language of the range, so that `en-GB` matches `en`.
+    let primary = range.split('-').next().unwrap_or(range);
+    if primary.len() < range.len() {
+        if primary.eq_ignore_ascii_case(language) {
+            return Some(2);
+        }
+        if is_prefix(primary, language) {
+            return Some(1);
+        }
+    }
+
+    None
+}
+
+/// Parses the value of an `Accept`, `Accept-Encoding`, `Accept-Charset` or `Accept-Language`
+/// header.
+///
+/// Contrary to [`parse_priority_header`](fn.parse_priority_header.html), the elements are sorted
+/// by preference, and the `quality` method takes into account the wildcards and the precedence of
+/// the most specific element. For example with `text/*;q=0.5, text/html`, the quality of
+/// `text/html` is 1 and the quality of `text/plain` is 0.5.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::parse_accept_header;
+///
+/// let header = parse_accept_header("text/*;q=0.5, text/html;level=1, */*;q=0.1");
+///
+/// let preferred = &header.items()[0];
+/// assert_eq!(preferred.value, "text/html");
+/// assert_eq!(preferred.params, vec![("level", "1")]);
+///
+/// assert_eq!(header.quality("text/html"), 1.0);
+/// assert_eq!(header.quality("text/plain"), 0.5);
+/// assert_eq!(header.quality("image/png"), 0.1);
+/// ```
+pub fn parse_accept_header<'a>(input: &'a str) -> AcceptHeader<'a> {
+    let mut items: Vec<_> = input.split(',').filter_map(parse_accept_item).collect();
+    // The sort is stable, which keeps the order of the header for the elements that have the same
+    // preference.
+    items.sort_by(|a, b| {
+        b.quality
+            .partial_cmp(&a.quality)
+            .unwrap_or(Ordering::Equal)
+            .then(b.specificity().cmp(&a.specificity()))
+    });
+    AcceptHeader { items }
+}
+
+fn parse_accept_item<'a>(elem: &'a str) -> Option<AcceptItem<'a>> {
+    let mut parts = elem.split(';');
+    let value = parts.next()?.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut quality = 1.0;
+    let mut params = Vec::new();
+    for param in parts {
+        let mut param = param.splitn(2, '=');
+        let name = param.next().unwrap_or("").trim();
+        let val = param.next().unwrap_or("").trim().trim_matches('"');
+        if name.eq_ignore_ascii_case("q") {
+            match val.parse::<f32>() {
+                Ok(q) if q.is_finite() => quality = q.max(0.0),
+                _ => (),
             }
+        } else if !name.is_empty() {
+            params.push((name, val));
+        }
+    }
 
-            let (req_elem_left, req_elem_right) = {
-                let mut parts = req_elem.split('/');
-                let left = parts.next();
-                let right = parts.next();
-                (left, right)
-            };
+    Some(AcceptItem {
+        value,
+        quality,
+        params,
+    })
+}
 
-            let (header_elem_left, header_elem_right) = {
-                let mut parts = header_elem.split('/');
-                let left = parts.next();
-                let right = parts.next();
-                (left, right)
-            };
+/// Elements of an `Accept-*` header, sorted by preference.
+///
+/// Created with [`parse_accept_header`](fn.parse_accept_header.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptHeader<'a> {
+    items: Vec<AcceptItem<'a>>,
+}
 
-            if req_elem_left == Some("*") || header_elem_left == Some("*") {
-                if req_elem_right == header_elem_right
-                    || req_elem_right == Some("*")
-                    || header_elem_right == Some("*")
-                {
-                    result = (Some(index), prio);
-                    continue;
-                }
+impl<'a> AcceptHeader<'a> {
+    /// Returns the elements of the header, by decreasing quality. The elements that have the
+    /// same quality are sorted from the most specific to the least specific, then in the order
+    /// of the header.
+    #[inline]
+    pub fn items(&self) -> &[AcceptItem<'a>] {
+        &self.items
+    }
+
+    /// Returns the quality that the header gives to `value`, or 0 if the header doesn't accept
+    /// it.
+    ///
+    /// The quality is the one of the most specific element that matches `value`. If `value`
+    /// contains a wildcard, such as `text/*`, the best quality of the elements that it matches
+    /// is returned.
+    #[inline]
+    pub fn quality(&self, value: &str) -> f32 {
+        self.matching(value).map_or(0.0, |item| item.quality)
+    }
+
+    /// Returns the index within `candidates` of the value with the highest quality, or `None`
+    /// if the header accepts none of them. If multiple values have the same quality, the first
+    /// one is returned.
+    pub fn preferred<'b, I>(&self, candidates: I) -> Option<usize>
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        let mut result = (None, 0.0);
+        for (index, candidate) in candidates.into_iter().enumerate() {
+            let quality = self.quality(candidate);
+            if quality > result.1 {
+                result = (Some(index), quality);
             }
+        }
+        result.0
+    }
 
-            if (req_elem_right == Some("*") || header_elem_right == Some("*"))
-                && (req_elem_left == header_elem_left
-                    || req_elem_left == Some("*")
-                    || header_elem_left == Some("*"))
-            {
-                result = (Some(index), prio);
+    // Returns the element that determines the quality of `value`.
+    fn matching(&self, value: &str) -> Option<&AcceptItem<'a>> {
+        // Thanks to the sorting, the first element of the highest specificity has the best
+        // quality.
+        let mut result: Option<&AcceptItem> = None;
+        for item in &self.items {
+            if !item.matches(value) && !pattern_matches(value, item.value) {
                 continue;
             }
+            if result.map_or(true, |r| item.specificity() > r.specificity()) {
+                result = Some(item);
+            }
         }
+        result
     }
+}
 
-    result.0
+/// An element of an `Accept-*` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptItem<'a> {
+    /// The media range, encoding, charset or language, for example `text/*` or `gzip`.
+    pub value: &'a str,
+    /// The value of the `q` parameter, or 1 if there is none.
+    pub quality: f32,
+    /// The parameters other than `q`, in the order of the header.
+    pub params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> AcceptItem<'a> {
+    /// Returns true if this element matches `value`. The comparison is case-insensitive, and
+    /// the wildcards `*`, `*/*` and `type/*` of the element are taken into account.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::input::parse_accept_header;
+    ///
+    /// let header = parse_accept_header("text/*");
+    /// assert!(header.items()[0].matches("text/html"));
+    /// assert!(!header.items()[0].matches("image/png"));
+    /// ```
+    #[inline]
+    pub fn matches(&self, value: &str) -> bool {
+        pattern_matches(self.value, value)
+    }
+
+    // Higher for the elements that match fewer values.
+    fn specificity(&self) -> u8 {
+        if self.value == "*" || self.value == "*/*" {
+            0
+        } else if self.value.ends_with("/*") {
+            1
+        } else if self.params.is_empty() {
+            2
+        } else {
+            3
+        }
+    }
+}
+
+// Returns true if `pattern`, which can contain wildcards, matches `value`.
+fn pattern_matches(pattern: &str, value: &str) -> bool {
+    if pattern == "*" || pattern == "*/*" || pattern.eq_ignore_ascii_case(value) {
+        return true;
+    }
+
+    match (pattern.find('/'), value.find('/')) {
+        (Some(p), Some(v)) => {
+            &pattern[p + 1..] == "*" && pattern[..p].eq_ignore_ascii_case(&value[..v])
+        }
+        _ => false,
+    }
 }
 
 /// Parses the value of a header that has values with priorities. Suitable for
 /// `Accept-*`, `TE`, etc.
 ///
+/// The elements are returned in the order of the header, without their parameters. See
+/// [`parse_accept_header`](fn.parse_accept_header.html) for a parser that sorts them by
+/// preference.
+///
 /// # Example
 ///
 /// ```
@@ -152,6 +406,8 @@ impl<'a> Iterator for PriorityHeaderIter<'a> {
 
 #[cfg(test)]
 mod tests {
+    use super::accept_language_preferred;
+    use super::parse_accept_header;
     use super::parse_priority_header;
     use super::priority_header_preferred;
 
@@ -211,6 +467,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn preferred_most_specific() {
+        let header = "*/*, image/png; q=0";
+        let handled = ["image/png", "image/gif"];
+        assert_eq!(
+            priority_header_preferred(header, handled.iter().cloned()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn accept_header_sorted() {
+        let header = parse_accept_header("*/*;q=0.1, text/*, text/html;level=1, gzip; Q=0.5,");
+        let values: Vec<_> = header.items().iter().map(|i| i.value).collect();
+        assert_eq!(values, vec!["text/html", "text/*", "gzip", "*/*"]);
+        assert_eq!(header.items()[0].params, vec![("level", "1")]);
+        assert_eq!(header.items()[2].quality, 0.5);
+    }
+
+    #[test]
+    fn accept_header_quality() {
+        let header = parse_accept_header("text/*;q=0.5, TEXT/HTML, */*;q=0.1, image/png;q=0");
+        assert_eq!(header.quality("text/html"), 1.0);
+        assert_eq!(header.quality("text/plain"), 0.5);
+        assert_eq!(header.quality("image/gif"), 0.1);
+        assert_eq!(header.quality("image/png"), 0.0);
+        assert_eq!(header.quality("text/*"), 1.0);
+        assert_eq!(
+            header.preferred(vec!["image/png", "text/plain", "text/html"]),
+            Some(2)
+        );
+
+        let header = parse_accept_header("gzip;q=0, *");
+        assert_eq!(header.quality("gzip"), 0.0);
+        assert_eq!(header.quality("br"), 1.0);
+        assert_eq!(header.preferred(vec!["gzip"]), None);
+    }
+
+    #[test]
+    fn accept_language() {
+        let preferred = |header, supported: &[&str]| {
+            accept_language_preferred(header, supported.iter().cloned())
+        };
+
+        assert_eq!(preferred("de, en;q=0.5", &["en", "de"]), Some(1));
+        assert_eq!(preferred("EN-gb", &["fr", "en-GB"]), Some(1));
+        assert_eq!(preferred("en", &["fr", "en-US"]), Some(1));
+        assert_eq!(preferred("en-GB", &["en-US", "en"]), Some(1));
+        assert_eq!(preferred("en-GB", &["fr", "en-US"]), Some(1));
+        assert_eq!(
+            preferred("en-GB, en-US;q=0.9", &["en-US", "en-GB"]),
+            Some(1)
+        );
+        assert_eq!(preferred("*, fr;q=0", &["fr", "de"]), Some(1));
+        assert_eq!(preferred("fr;q=0", &["fr"]), None);
+        assert_eq!(preferred("e", &["en"]), None);
+        assert_eq!(preferred("", &["en"]), None);
+    }
+
     #[test]
     fn preferred_empty() {
         let header = "*/*";
@@ -0,0 +1,100 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use percent_encoding::percent_decode_str;
+use std::borrow::Cow;
+use std::str::Split;
+
+/// Parses a query string, such as the one returned by
+/// [`Request::raw_query_string`](../struct.Request.html#method.raw_query_string), into its
+/// parameters.
+///
+/// The iterator returns the decoded names and values of the parameters in the order of the query
+/// string, including the ones that appear several times. The value is `None` if the parameter has
+/// no `=`, and an empty string if the `=` is followed by nothing.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::parse_query;
+///
+/// let mut iter = parse_query("tag=a&tag=b%20c&flag&empty=");
+/// assert_eq!(iter.next(), Some(("tag".into(), Some("a".into()))));
+/// assert_eq!(iter.next(), Some(("tag".into(), Some("b c".into()))));
+/// assert_eq!(iter.next(), Some(("flag".into(), None)));
+/// assert_eq!(iter.next(), Some(("empty".into(), Some("".into()))));
+/// assert_eq!(iter.next(), None);
+/// ```
+#[inline]
+pub fn parse_query<'a>(input: &'a str) -> QueryParams<'a> {
+    QueryParams {
+        iter: input.split('&'),
+    }
+}
+
+/// Iterator to the parameters of a query string.
+///
+/// Created with [`parse_query`](fn.parse_query.html) or
+/// [`Request::query_params`](../struct.Request.html#method.query_params).
+#[derive(Debug, Clone)]
+pub struct QueryParams<'a> {
+    iter: Split<'a, char>,
+}
+
+impl<'a> Iterator for QueryParams<'a> {
+    type Item = (Cow<'a, str>, Option<Cow<'a, str>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let param = self.iter.next()?;
+            if param.is_empty() {
+                continue;
+            }
+
+            let mut param = param.splitn(2, '=');
+            let name = decode(param.next().unwrap_or(""));
+            return Some((name, param.next().map(decode)));
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, len) = self.iter.size_hint();
+        (0, len)
+    }
+}
+
+// Decodes a name or a value of the query string, in which `+` is a space.
+fn decode<'a>(input: &'a str) -> Cow<'a, str> {
+    if input.contains('+') {
+        let input = input.replace('+', " ");
+        Cow::Owned(percent_decode_str(&input).decode_utf8_lossy().into_owned())
+    } else {
+        percent_decode_str(input).decode_utf8_lossy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_query;
+
+    #[test]
+    fn decoding() {
+        let params: Vec<_> = parse_query("a+b=c%2Bd&&%C3%A9=%ZZ&=x").collect();
+        assert_eq!(
+            params,
+            vec![
+                ("a b".into(), Some("c+d".into())),
+                ("é".into(), Some("%ZZ".into())),
+                ("".into(), Some("x".into())),
+            ]
+        );
+        assert_eq!(parse_query("").next(), None);
+    }
+}
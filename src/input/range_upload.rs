@@ -0,0 +1,354 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Error as IoError;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use Request;
+
+/// The value of a `Content-Range` header sent by a client.
+///
+/// Corresponds to `bytes <first>-<last>/<total>`, where `total` is `*` if the client doesn't
+/// know the final size of the object yet. Both `first` and `last` are inclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ContentRange {
+    /// Offset of the first byte of the body.
+    pub first: u64,
+    /// Offset of the last byte of the body.
+    pub last: u64,
+    /// Total size of the object, if known.
+    pub total: Option<u64>,
+}
+
+/// Parses the value of a `Content-Range` header.
+///
+/// Returns `None` if the header is malformed, if `first` is greater than `last`, or if `last` is
+/// not strictly inferior to `total`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::input::{parse_content_range, ContentRange};
+///
+/// let range = parse_content_range("bytes 0-499/1234").unwrap();
+/// assert_eq!(range, ContentRange { first: 0, last: 499, total: Some(1234) });
+/// ```
+pub fn parse_content_range(header: &str) -> Option<ContentRange> {
+    let header = header.trim();
+    if header.len() < 6 || !header[..6].eq_ignore_ascii_case("bytes ") {
+        return None;
+    }
+
+    let mut split = header[6..].trim_start().splitn(2, '/');
+    let range = split.next()?;
+    let total = split.next()?.trim();
+
+    let mut split = range.splitn(2, '-');
+    let first = split.next()?.trim().parse::<u64>().ok()?;
+    let last = split.next()?.trim().parse::<u64>().ok()?;
+    if first > last {
+        return None;
+    }
+
+    let total = if total == "*" {
+        None
+    } else {
+        let total = total.parse::<u64>().ok()?;
+        if last >= total {
+            return None;
+        }
+        Some(total)
+    };
+
+    Some(ContentRange { first, last, total })
+}
+
+/// Outcome of a successful call to `range_upload`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RangeUpload {
+    /// The range that was written.
+    pub range: ContentRange,
+    /// Size of the object after the write.
+    pub size: u64,
+    /// True if the client announced a total size and the object has now reached it.
+    pub complete: bool,
+}
+
+/// Error that can happen when applying a ranged upload.
+#[derive(Debug)]
+pub enum RangeUploadError {
+    /// The method of the request is not `PATCH`.
+    WrongMethod,
+
+    /// The request doesn't have a `Content-Range` header.
+    MissingContentRange,
+
+    /// The `Content-Range` header couldn't be parsed.
+    InvalidContentRange,
+
+    /// The range starts after the current end of the object, which would leave a hole in it.
+    /// You should answer with a `416 Range Not Satisfiable` status code.
+    UnsatisfiableRange,
+
+    /// The range or the announced total size exceeds the maximum size of the object.
+    TooLarge,
+
+    /// The number of bytes in the body doesn't match the length of the range.
+    ///
+    /// Note that the bytes that were received may already have been written to the target.
+    BodyLengthMismatch,
+
+    /// Can't read the body of the request because it was already extracted.
+    BodyAlreadyExtracted,
+
+    /// Could not read the body from the request, or could not write to the target.
+    IoError(IoError),
+}
+
+impl From<IoError> for RangeUploadError {
+    fn from(err: IoError) -> RangeUploadError {
+        RangeUploadError::IoError(err)
+    }
+}
+
+impl error::Error for RangeUploadError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RangeUploadError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RangeUploadError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            RangeUploadError::WrongMethod => "the method of the request is not PATCH",
+            RangeUploadError::MissingContentRange => "the request has no Content-Range header",
+            RangeUploadError::InvalidContentRange => "the Content-Range header is invalid",
+            RangeUploadError::UnsatisfiableRange => {
+                "the range starts after the current end of the object"
+            }
+            RangeUploadError::TooLarge => "the range exceeds the maximum size of the object",
+            RangeUploadError::BodyLengthMismatch => {
+                "the length of the body doesn't match the Content-Range header"
+            }
+            RangeUploadError::BodyAlreadyExtracted => {
+                "the body of the request was already extracted"
+            }
+            RangeUploadError::IoError(_) => {
+                "could not read the body from the request, or could not write to the target"
+            }
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Writes the body of a `PATCH` request at the offset given by its `Content-Range` header.
+///
+/// This is meant for clients that upload large objects in several chunks with plain ranged
+/// requests. `current_size` is the number of bytes of the object that have been stored so far,
+/// and `max_size` is the maximum size that you allow for the object.
+///
+/// A range may overwrite bytes that were already written, but it must not start after
+/// `current_size`, as that would leave a hole in the object. The body must contain exactly as
+/// many bytes as the range.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[macro_use] extern crate rouille;
+/// # use rouille::{Request, Response};
+/// # fn main() {}
+/// use std::fs::OpenOptions;
+/// use rouille::input::{range_upload, RangeUploadError};
+///
+/// fn handle_upload(request: &Request) -> Response {
+///     let mut file = OpenOptions::new().write(true).create(true).open("upload.bin").unwrap();
+///     let current_size = file.metadata().unwrap().len();
+///
+///     match range_upload(request, &mut file, current_size, 1024 * 1024 * 1024) {
+///         Ok(ref upload) if upload.complete => Response::text("upload complete"),
+///         Ok(_) => Response::empty_204(),
+///         Err(RangeUploadError::UnsatisfiableRange) => {
+///             Response::empty_400().with_status_code(416)
+///         }
+///         Err(_) => Response::empty_400(),
+///     }
+/// }
+/// ```
+pub fn range_upload<W>(
+    request: &Request,
+    target: &mut W,
+    current_size: u64,
+    max_size: u64,
+) -> Result<RangeUpload, RangeUploadError>
+where
+    W: Write + Seek,
+{
+    if request.method() != "PATCH" {
+        return Err(RangeUploadError::WrongMethod);
+    }
+
+    let range = match request.header("Content-Range") {
+        Some(h) => match parse_content_range(h) {
+            Some(r) => r,
+            None => return Err(RangeUploadError::InvalidContentRange),
+        },
+        None => return Err(RangeUploadError::MissingContentRange),
+    };
+
+    if range.last >= max_size || range.total.map_or(false, |t| t > max_size) {
+        return Err(RangeUploadError::TooLarge);
+    }
+
+    if range.first > current_size {
+        return Err(RangeUploadError::UnsatisfiableRange);
+    }
+
+    let expected = range.last - range.first + 1;
+    if let Some(len) = request.header("Content-Length") {
+        if len.trim().parse::<u64>().ok() != Some(expected) {
+            return Err(RangeUploadError::BodyLengthMismatch);
+        }
+    }
+
+    let mut body = match request.data() {
+        Some(b) => b,
+        None => return Err(RangeUploadError::BodyAlreadyExtracted),
+    };
+
+    target.seek(SeekFrom::Start(range.first))?;
+    let written = io::copy(&mut (&mut body).take(expected), target)?;
+    if written != expected || body.read(&mut [0])? != 0 {
+        return Err(RangeUploadError::BodyLengthMismatch);
+    }
+    target.flush()?;
+
+    let size = current_size.max(range.last + 1);
+    Ok(RangeUpload {
+        range,
+        size,
+        complete: range.total == Some(size),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_content_range;
+    use super::range_upload;
+    use super::ContentRange;
+    use super::RangeUploadError;
+    use std::io::Cursor;
+    use Request;
+
+    fn patch(range: &str, body: &[u8]) -> Request {
+        Request::fake_http(
+            "PATCH",
+            "/",
+            vec![("Content-Range".to_owned(), range.to_owned())],
+            body.to_vec(),
+        )
+    }
+
+    #[test]
+    fn parse_valid() {
+        assert_eq!(
+            parse_content_range("bytes 10-19/*"),
+            Some(ContentRange {
+                first: 10,
+                last: 19,
+                total: None
+            })
+        );
+        assert_eq!(
+            parse_content_range("Bytes 0-0/1"),
+            Some(ContentRange {
+                first: 0,
+                last: 0,
+                total: Some(1)
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse_content_range("bytes 5-4/10"), None);
+        assert_eq!(parse_content_range("bytes 0-10/10"), None);
+        assert_eq!(parse_content_range("bytes 0-10"), None);
+        assert_eq!(parse_content_range("bytes */10"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn chunks_in_order() {
+        let mut target = Cursor::new(Vec::new());
+
+        let upload = range_upload(&patch("bytes 0-4/10", b"hello"), &mut target, 0, 100).unwrap();
+        assert_eq!(upload.size, 5);
+        assert!(!upload.complete);
+
+        let request = patch("bytes 5-9/10", b"world");
+        let upload = range_upload(&request, &mut target, upload.size, 100).unwrap();
+        assert_eq!(upload.size, 10);
+        assert!(upload.complete);
+        assert_eq!(target.into_inner(), b"helloworld");
+    }
+
+    #[test]
+    fn hole_rejected() {
+        let mut target = Cursor::new(Vec::new());
+        match range_upload(&patch("bytes 5-9/*", b"world"), &mut target, 0, 100) {
+            Err(RangeUploadError::UnsatisfiableRange) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let mut target = Cursor::new(Vec::new());
+        match range_upload(&patch("bytes 0-4/1000", b"hello"), &mut target, 0, 100) {
+            Err(RangeUploadError::TooLarge) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn body_length_mismatch() {
+        let mut target = Cursor::new(Vec::new());
+        match range_upload(&patch("bytes 0-9/*", b"hello"), &mut target, 0, 100) {
+            Err(RangeUploadError::BodyLengthMismatch) => (),
+            _ => panic!(),
+        }
+
+        let mut target = Cursor::new(Vec::new());
+        match range_upload(&patch("bytes 0-1/*", b"hello"), &mut target, 0, 100) {
+            Err(RangeUploadError::BodyLengthMismatch) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn wrong_method() {
+        let request = Request::fake_http("PUT", "/", vec![], vec![]);
+        let mut target = Cursor::new(Vec::new());
+        match range_upload(&request, &mut target, 0, 100) {
+            Err(RangeUploadError::WrongMethod) => (),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Verifying the HMAC signatures of webhooks.
+//!
+//! Services that send webhooks sign the body of their requests with a secret shared with the
+//! server, and put the signature in a header. A `WebhookVerifier` reads the body, checks its
+//! signature and returns it, so that it can be parsed afterwards. The signatures are compared in
+//! constant time.
+//!
+//! This module is only available with the `webhook` feature.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate serde_json;
+//! # extern crate rouille;
+//! use rouille::input::webhook::WebhookVerifier;
+//! use rouille::{Request, Response};
+//!
+//! fn handle(request: &Request, verifier: &WebhookVerifier) -> Response {
+//!     let body = match verifier.verify(request) {
+//!         Ok(body) => body,
+//!         Err(_) => return Response::text("Invalid signature").with_status_code(401),
+//!     };
+//!
+//!     let event: serde_json::Value = match serde_json::from_slice(&body) {
+//!         Ok(event) => event,
+//!         Err(_) => return Response::empty_400(),
+//!     };
+//!     // ...
+//! # let _ = event;
+//!     Response::empty_204()
+//! }
+//!
+//! # fn main() {
+//! let verifier = WebhookVerifier::github(b"It's a Secret to Everybody");
+//! # let _ = verifier;
+//! # }
+//! ```
+
+use base64;
+use ring::hmac;
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use util::{constant_time_eq, from_hex};
+use Request;
+
+/// Error that can happen when verifying a webhook.
+#[derive(Debug)]
+pub enum WebhookError {
+    /// Can't read the body of the request because it was already extracted.
+    BodyAlreadyExtracted,
+
+    /// Could not read the body from the request.
+    IoError(IoError),
+
+    /// The body is larger than the limit of the verifier.
+    LimitExceeded,
+
+    /// The request doesn't have the header of the signature, or it is malformed.
+    MissingSignature,
+
+    /// The signature doesn't match the body.
+    InvalidSignature,
+
+    /// The timestamp of a Stripe signature is too far from the current time.
+    Expired,
+}
+
+impl From<IoError> for WebhookError {
+    fn from(err: IoError) -> WebhookError {
+        WebhookError::IoError(err)
+    }
+}
+
+impl error::Error for WebhookError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            WebhookError::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for WebhookError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            WebhookError::BodyAlreadyExtracted => "the body of the request was already extracted",
+            WebhookError::IoError(_) => "could not read the body from the request",
+            WebhookError::LimitExceeded => "the limit to the number of bytes has been exceeded",
+            WebhookError::MissingSignature => "the request has no valid signature header",
+            WebhookError::InvalidSignature => "the signature doesn't match the body",
+            WebhookError::Expired => "the timestamp of the signature is too old",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Hash function of an HMAC signature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HmacAlgorithm {
+    /// HMAC-SHA1, still used by older webhooks.
+    Sha1,
+    /// HMAC-SHA256.
+    Sha256,
+}
+
+impl HmacAlgorithm {
+    /// Computes the HMAC of `data` with `secret`.
+    pub fn sign(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        let algorithm = match *self {
+            HmacAlgorithm::Sha1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+            HmacAlgorithm::Sha256 => hmac::HMAC_SHA256,
+        };
+        hmac::sign(&hmac::Key::new(algorithm, secret), data)
+            .as_ref()
+            .to_vec()
+    }
+
+    /// Returns true if `signature` is the HMAC of `data` with `secret`. The comparison takes
+    /// the same time whatever the number of bytes that match.
+    pub fn verify(&self, secret: &[u8], data: &[u8], signature: &[u8]) -> bool {
+        constant_time_eq(&self.sign(secret, data), signature)
+    }
+
+    // Prefix of the signatures in the headers of GitHub, such as `sha256=`.
+    fn prefix(&self) -> &'static str {
+        match *self {
+            HmacAlgorithm::Sha1 => "sha1=",
+            HmacAlgorithm::Sha256 => "sha256=",
+        }
+    }
+}
+
+/// Checks the signature of the body of webhook requests.
+#[derive(Clone)]
+pub struct WebhookVerifier {
+    secret: Vec<u8>,
+    scheme: Scheme,
+    limit: usize,
+    tolerance: Duration,
+}
+
+#[derive(Debug, Clone)]
+enum Scheme {
+    // The HMAC of the body, in hexadecimal or base64, with an optional `sha256=` prefix.
+    Header(String, HmacAlgorithm),
+    // A header such as `t=1492774577,v1=5257a869...`, signing the timestamp and the body.
+    Stripe,
+}
+
+impl WebhookVerifier {
+    /// Builds a verifier of a header that contains the HMAC of the body, in hexadecimal or in
+    /// base64, optionally preceded by a prefix such as `sha256=`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::input::webhook::{HmacAlgorithm, WebhookVerifier};
+    ///
+    /// let verifier = WebhookVerifier::hmac(
+    ///     "X-Shopify-Hmac-Sha256",
+    ///     HmacAlgorithm::Sha256,
+    ///     b"secret",
+    /// );
+    /// ```
+    pub fn hmac<H>(header: H, algorithm: HmacAlgorithm, secret: &[u8]) -> WebhookVerifier
+    where
+        H: Into<String>,
+    {
+        WebhookVerifier::new(Scheme::Header(header.into(), algorithm), secret)
+    }
+
+    /// Builds a verifier of the `X-Hub-Signature-256` header of GitHub.
+    #[inline]
+    pub fn github(secret: &[u8]) -> WebhookVerifier {
+        WebhookVerifier::hmac("X-Hub-Signature-256", HmacAlgorithm::Sha256, secret)
+    }
+
+    /// Builds a verifier of the `Stripe-Signature` header of Stripe.
+    ///
+    /// The signed timestamp of the header must be less than five minutes away from the current
+    /// time, which can be changed with `tolerance`.
+    #[inline]
+    pub fn stripe(secret: &[u8]) -> WebhookVerifier {
+        WebhookVerifier::new(Scheme::Stripe, secret)
+    }
+
+    fn new(scheme: Scheme, secret: &[u8]) -> WebhookVerifier {
+        WebhookVerifier {
+            secret: secret.to_vec(),
+            scheme,
+            limit: 1024 * 1024,
+            tolerance: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Sets the maximum size of the body. Defaults to 1 MiB.
+    #[inline]
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets how far the timestamp of a Stripe signature can be from the current time.
+    #[inline]
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Reads the body of the request and returns it if its signature is valid.
+    pub fn verify(&self, request: &Request) -> Result<Vec<u8>, WebhookError> {
+        let body = request.data().ok_or(WebhookError::BodyAlreadyExtracted)?;
+        let mut out = Vec::new();
+        body.take(self.limit.saturating_add(1) as u64)
+            .read_to_end(&mut out)?;
+        if out.len() > self.limit {
+            return Err(WebhookError::LimitExceeded);
+        }
+
+        self.verify_body(request, &out)?;
+        Ok(out)
+    }
+
+    /// Checks the signature in the headers of the request against a body that was read
+    /// separately.
+    pub fn verify_body(&self, request: &Request, body: &[u8]) -> Result<(), WebhookError> {
+        self.verify_at(request, body, SystemTime::now())
+    }
+
+    fn verify_at(
+        &self,
+        request: &Request,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Result<(), WebhookError> {
+        match self.scheme {
+            Scheme::Header(ref header, algorithm) => {
+                let value = request
+                    .header(header)
+                    .ok_or(WebhookError::MissingSignature)?
+                    .trim();
+                let value = if value.len() >= algorithm.prefix().len()
+                    && value[..algorithm.prefix().len()].eq_ignore_ascii_case(algorithm.prefix())
+                {
+                    &value[algorithm.prefix().len()..]
+                } else {
+                    value
+                };
+                let signature = from_hex(value)
+                    .or_else(|| base64::decode(value).ok())
+                    .ok_or(WebhookError::MissingSignature)?;
+                if algorithm.verify(&self.secret, body, &signature) {
+                    Ok(())
+                } else {
+                    Err(WebhookError::InvalidSignature)
+                }
+            }
+
+            Scheme::Stripe => {
+                let header = request
+                    .header("Stripe-Signature")
+                    .ok_or(WebhookError::MissingSignature)?;
+                let mut timestamp = None;
+                let mut signatures = Vec::new();
+                for item in header.split(',') {
+                    let mut split = item.trim().splitn(2, '=');
+                    match (split.next(), split.next()) {
+                        (Some("t"), Some(t)) => timestamp = t.parse::<u64>().ok(),
+                        (Some("v1"), Some(v1)) => signatures.extend(from_hex(v1)),
+                        _ => (),
+                    }
+                }
+                let timestamp = timestamp.ok_or(WebhookError::MissingSignature)?;
+                if signatures.is_empty() {
+                    return Err(WebhookError::MissingSignature);
+                }
+
+                let mut signed = format!("{}.", timestamp).into_bytes();
+                signed.extend_from_slice(body);
+                let expected = HmacAlgorithm::Sha256.sign(&self.secret, &signed);
+                // All the signatures are compared, so that the time doesn't depend on the
+                // position of the valid one.
+                let valid = signatures
+                    .iter()
+                    .fold(false, |valid, sig| constant_time_eq(&expected, sig) | valid);
+                if !valid {
+                    return Err(WebhookError::InvalidSignature);
+                }
+
+                let now = now
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_else(|_| Duration::from_secs(0))
+                    .as_secs();
+                let age = if now > timestamp {
+                    now - timestamp
+                } else {
+                    timestamp - now
+                };
+                if age > self.tolerance.as_secs() {
+                    return Err(WebhookError::Expired);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for WebhookVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WebhookVerifier")
+            .field("scheme", &self.scheme)
+            .field("limit", &self.limit)
+            .field("tolerance", &self.tolerance)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HmacAlgorithm;
+    use super::WebhookError;
+    use super::WebhookVerifier;
+    use base64;
+    use std::time::{Duration, UNIX_EPOCH};
+    use util::to_hex;
+    use Request;
+
+    fn request(header: &str, value: &str, body: &[u8]) -> Request {
+        Request::fake_http(
+            "POST",
+            "/webhook",
+            vec![(header.to_owned(), value.to_owned())],
+            body.to_vec(),
+        )
+    }
+
+    #[test]
+    fn sha1() {
+        // The test cases 1 and 6 of RFC 2202.
+        assert_eq!(
+            to_hex(&HmacAlgorithm::Sha1.sign(&[0x0b; 20], b"Hi There")),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+        assert_eq!(
+            to_hex(&HmacAlgorithm::Sha1.sign(
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+    }
+
+    #[test]
+    fn github() {
+        // The example of the documentation of GitHub.
+        let verifier = WebhookVerifier::github(b"It's a Secret to Everybody");
+        let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let body = verifier
+            .verify(&request("X-Hub-Signature-256", signature, b"Hello, World!"))
+            .unwrap();
+        assert_eq!(body, b"Hello, World!");
+
+        match verifier.verify(&request("X-Hub-Signature-256", signature, b"Hello")) {
+            Err(WebhookError::InvalidSignature) => (),
+            r => panic!("{:?}", r),
+        }
+        match verifier.verify(&request("X-Hub-Signature", signature, b"Hello, World!")) {
+            Err(WebhookError::MissingSignature) => (),
+            r => panic!("{:?}", r),
+        }
+        match verifier.clone().limit(5).verify(&request(
+            "X-Hub-Signature-256",
+            signature,
+            b"Hello, World!",
+        )) {
+            Err(WebhookError::LimitExceeded) => (),
+            r => panic!("{:?}", r),
+        }
+
+        let verifier = WebhookVerifier::hmac("X-Signature", HmacAlgorithm::Sha1, b"key");
+        let signature = base64::encode(HmacAlgorithm::Sha1.sign(b"key", b"body"));
+        assert!(verifier
+            .verify(&request("X-Signature", &signature, b"body"))
+            .is_ok());
+    }
+
+    #[test]
+    fn stripe() {
+        let verifier = WebhookVerifier::stripe(b"whsec_test");
+        let body = br#"{"id":"evt_1"}"#;
+        let signed = [&b"1700000000."[..], &body[..]].concat();
+        let signature = to_hex(&HmacAlgorithm::Sha256.sign(b"whsec_test", &signed));
+        let header = format!("t=1700000000,v1={},v1=00,v0=ffff", signature);
+        let webhook = request("Stripe-Signature", &header, body);
+        let at =
+            |secs: u64| verifier.verify_at(&webhook, body, UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert!(at(1700000100).is_ok());
+        assert!(at(1699999900).is_ok());
+        match at(1700000301) {
+            Err(WebhookError::Expired) => (),
+            r => panic!("{:?}", r),
+        }
+        match verifier.verify_at(
+            &webhook,
+            b"{}",
+            UNIX_EPOCH + Duration::from_secs(1700000000),
+        ) {
+            Err(WebhookError::InvalidSignature) => (),
+            r => panic!("{:?}", r),
+        }
+        match verifier.verify_body(&request("Stripe-Signature", "v1=00", body), body) {
+            Err(WebhookError::MissingSignature) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use serde;
+use serde_json;
+use serde_json::ser::CharEscape;
+use serde_json::ser::CompactFormatter;
+use serde_json::ser::Formatter;
+use serde_json::ser::PrettyFormatter;
+use serde_json::Serializer;
+use serde_json::Value;
+use std::io;
+use std::io::Write;
+
+/// How `Response::try_json_with_options` serializes a value.
+///
+/// By default the output is the same as `Response::json`: compact, with the keys of the maps
+/// in the order in which the maps iterate over them, and with the non-ASCII characters written
+/// as is.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+/// use rouille::{JsonOptions, Response};
+///
+/// let mut map = HashMap::new();
+/// map.insert("ville", "Montréal");
+/// map.insert("pays", "Canada");
+///
+/// let options = JsonOptions::new().pretty(true).sort_keys(true).ascii_only(true);
+/// let response = Response::try_json_with_options(&map, &options).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonOptions {
+    indent: Option<String>,
+    sort_keys: bool,
+    ascii_only: bool,
+}
+
+impl JsonOptions {
+    /// Builds the default options.
+    #[inline]
+    pub fn new() -> JsonOptions {
+        JsonOptions::default()
+    }
+
+    /// If true, puts each value on its own line, indented by two spaces.
+    #[inline]
+    pub fn pretty(mut self, pretty: bool) -> JsonOptions {
+        self.indent = if pretty { Some("  ".to_owned()) } else { None };
+        self
+    }
+
+    /// Puts each value on its own line, indented by the given string.
+    #[inline]
+    pub fn indent<I>(mut self, indent: I) -> JsonOptions
+    where
+        I: Into<String>,
+    {
+        self.indent = Some(indent.into());
+        self
+    }
+
+    /// If true, writes the keys of all the objects in alphabetical order, so that the output
+    /// doesn't depend on the iteration order of the maps. The value is first converted to a
+    /// `serde_json::Value`, which means that it is held twice in memory.
+    #[inline]
+    pub fn sort_keys(mut self, sort_keys: bool) -> JsonOptions {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    /// If true, escapes the non-ASCII characters of the strings as `\uXXXX`.
+    #[inline]
+    pub fn ascii_only(mut self, ascii_only: bool) -> JsonOptions {
+        self.ascii_only = ascii_only;
+        self
+    }
+
+    pub(crate) fn to_vec<T>(&self, value: &T) -> Result<Vec<u8>, serde_json::Error>
+    where
+        T: serde::Serialize,
+    {
+        let mut output = Vec::new();
+
+        if self.sort_keys {
+            let value = sorted(serde_json::to_value(value)?);
+            self.write(&mut output, &value)?;
+        } else {
+            self.write(&mut output, value)?;
+        }
+
+        Ok(output)
+    }
+
+    fn write<W, T>(&self, output: W, value: &T) -> Result<(), serde_json::Error>
+    where
+        W: Write,
+        T: ?Sized + serde::Serialize,
+    {
+        match (&self.indent, self.ascii_only) {
+            (None, false) => value.serialize(&mut Serializer::new(output)),
+            (Some(indent), false) => {
+                let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+                value.serialize(&mut Serializer::with_formatter(output, formatter))
+            }
+            (None, true) => {
+                let formatter = AsciiFormatter(CompactFormatter);
+                value.serialize(&mut Serializer::with_formatter(output, formatter))
+            }
+            (Some(indent), true) => {
+                let formatter = AsciiFormatter(PrettyFormatter::with_indent(indent.as_bytes()));
+                value.serialize(&mut Serializer::with_formatter(output, formatter))
+            }
+        }
+    }
+}
+
+impl Default for JsonOptions {
+    #[inline]
+    fn default() -> JsonOptions {
+        JsonOptions {
+            indent: None,
+            sort_keys: false,
+            ascii_only: false,
+        }
+    }
+}
+
+/// How `Response::json_stream_iter` writes the items of an iterator.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JsonStreamFormat {
+    /// A JSON array, with the `application/json` content type.
+    Array,
+    /// One JSON document per line, with the `application/x-ndjson` content type. The client can
+    /// process each item as soon as it is received.
+    Lines,
+}
+
+// Rebuilds the objects with their keys inserted in alphabetical order, which is the order in
+// which they are serialized whether or not the `preserve_order` feature of serde_json is
+// enabled.
+fn sorted(value: Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(entries.into_iter().map(|(k, v)| (k, sorted(v))).collect())
+        }
+        Value::Array(array) => Value::Array(array.into_iter().map(sorted).collect()),
+        value => value,
+    }
+}
+
+// Wraps another formatter, and escapes the non-ASCII characters of the strings.
+struct AsciiFormatter<F>(F);
+
+impl<F> Formatter for AsciiFormatter<F>
+where
+    F: Formatter,
+{
+    fn write_string_fragment<W>(&mut self, writer: &mut W, fragment: &str) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        let mut start = 0;
+        for (pos, c) in fragment.char_indices() {
+            if c.is_ascii() {
+                continue;
+            }
+            writer.write_all(&fragment.as_bytes()[start..pos])?;
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                write!(writer, "\\u{:04x}", unit)?;
+            }
+            start = pos + c.len_utf8();
+        }
+        writer.write_all(&fragment.as_bytes()[start..])
+    }
+
+    #[inline]
+    fn write_char_escape<W>(&mut self, writer: &mut W, char_escape: CharEscape) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.write_char_escape(writer, char_escape)
+    }
+
+    #[inline]
+    fn begin_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.begin_array(writer)
+    }
+
+    #[inline]
+    fn end_array<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.end_array(writer)
+    }
+
+    #[inline]
+    fn begin_array_value<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.begin_array_value(writer, first)
+    }
+
+    #[inline]
+    fn end_array_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.end_array_value(writer)
+    }
+
+    #[inline]
+    fn begin_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.begin_object(writer)
+    }
+
+    #[inline]
+    fn end_object<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.end_object(writer)
+    }
+
+    #[inline]
+    fn begin_object_key<W>(&mut self, writer: &mut W, first: bool) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.begin_object_key(writer, first)
+    }
+
+    #[inline]
+    fn end_object_key<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.end_object_key(writer)
+    }
+
+    #[inline]
+    fn begin_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.begin_object_value(writer)
+    }
+
+    #[inline]
+    fn end_object_value<W>(&mut self, writer: &mut W) -> io::Result<()>
+    where
+        W: ?Sized + Write,
+    {
+        self.0.end_object_value(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonOptions;
+    use std::collections::HashMap;
+
+    fn to_string(options: JsonOptions, value: &HashMap<&str, Vec<&str>>) -> String {
+        String::from_utf8(options.to_vec(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn options() {
+        let mut map = HashMap::new();
+        map.insert("b", vec!["é😀\""]);
+        map.insert("a", vec![]);
+
+        let sorted = JsonOptions::new().sort_keys(true);
+        assert_eq!(
+            to_string(sorted.clone(), &map),
+            "{\"a\":[],\"b\":[\"é😀\\\"\"]}"
+        );
+        assert_eq!(
+            to_string(sorted.clone().ascii_only(true), &map),
+            "{\"a\":[],\"b\":[\"\\u00e9\\ud83d\\ude00\\\"\"]}"
+        );
+        assert_eq!(
+            to_string(sorted.pretty(true), &map),
+            "{\n  \"a\": [],\n  \"b\": [\n    \"é😀\\\"\"\n  ]\n}"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use Response;
+
+// Number of tracked connections above which the idle ones are forgotten.
+const PRUNE_THRESHOLD: usize = 1024;
+
+// Time after which an idle connection is forgotten if there is no keep-alive timeout.
+const DEFAULT_IDLE: Duration = Duration::from_secs(300);
+
+// Decides which connections must be closed after their response, for
+// `Server::max_requests_per_connection`, `Server::keep_alive_timeout` and
+// `Server::close_connections`.
+//
+// tiny-http doesn't tell us when a connection is closed, so the connections are identified by the
+// address of the client and forgotten once they have been idle for long enough.
+#[derive(Debug, Default)]
+pub(crate) struct KeepAlive {
+    pub(crate) max_requests: Option<usize>,
+    pub(crate) timeout: Option<Duration>,
+    closing: AtomicBool,
+    // Number of requests received on the connection, and time of the last one.
+    connections: Mutex<HashMap<SocketAddr, (usize, Instant)>>,
+}
+
+impl KeepAlive {
+    pub(crate) fn new(max_requests: Option<usize>, timeout: Option<Duration>) -> KeepAlive {
+        KeepAlive {
+            max_requests,
+            timeout,
+            ..KeepAlive::default()
+        }
+    }
+
+    // Makes all the following responses close their connection.
+    pub(crate) fn close_all(&self) {
+        self.closing.store(true, Ordering::Relaxed);
+    }
+
+    // Adds the `Connection` and `Keep-Alive` headers to the response of a request that was
+    // received at `received` from `addr`.
+    pub(crate) fn apply(
+        &self,
+        addr: SocketAddr,
+        received: Instant,
+        response: Response,
+    ) -> Response {
+        if self.closing.load(Ordering::Relaxed) {
+            return response.with_connection_close();
+        }
+        if self.max_requests.is_none() && self.timeout.is_none() {
+            return response;
+        }
+        if response.closes_connection() {
+            self.connections.lock().unwrap().remove(&addr);
+            return response;
+        }
+
+        let idle = self.timeout.unwrap_or(DEFAULT_IDLE);
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() > PRUNE_THRESHOLD {
+            connections
+                .retain(|_, &mut (_, last)| received.saturating_duration_since(last) <= idle);
+        }
+
+        let (count, expired) = match connections.get(&addr) {
+            Some(&(count, last)) => {
+                let expired = self.timeout.map_or(false, |timeout| {
+                    received.saturating_duration_since(last) > timeout
+                });
+                (count + 1, expired)
+            }
+            None => (1, false),
+        };
+
+        // A connection that was reused after the timeout is closed as well, since tiny-http
+        // can't close it while it is idle.
+        if expired || self.max_requests.map_or(false, |max| count >= max) {
+            connections.remove(&addr);
+            return response.with_connection_close();
+        }
+        connections.insert(addr, (count, received));
+
+        let mut params = Vec::new();
+        if let Some(timeout) = self.timeout {
+            params.push(format!("timeout={}", timeout.as_secs()));
+        }
+        if let Some(max) = self.max_requests {
+            params.push(format!("max={}", max - count));
+        }
+        response.with_unique_header("Keep-Alive", params.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeepAlive;
+    use std::time::Duration;
+    use std::time::Instant;
+    use Response;
+
+    #[test]
+    fn max_requests() {
+        let keep_alive = KeepAlive::new(Some(3), None);
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let other = "127.0.0.1:5001".parse().unwrap();
+        let now = Instant::now();
+
+        let response = keep_alive.apply(addr, now, Response::text(""));
+        assert_eq!(response.header("Keep-Alive"), Some("max=2"));
+        assert!(!response.closes_connection());
+        let response = keep_alive.apply(addr, now, Response::text(""));
+        assert_eq!(response.header("Keep-Alive"), Some("max=1"));
+        assert!(!keep_alive
+            .apply(other, now, Response::text(""))
+            .closes_connection());
+
+        let response = keep_alive.apply(addr, now, Response::text(""));
+        assert!(response.closes_connection());
+        assert_eq!(response.header("Keep-Alive"), None);
+
+        // The client opens a new connection, which may reuse the same port.
+        let response = keep_alive.apply(addr, now, Response::text(""));
+        assert_eq!(response.header("Keep-Alive"), Some("max=2"));
+    }
+
+    #[test]
+    fn timeout() {
+        let keep_alive = KeepAlive::new(None, Some(Duration::from_secs(5)));
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let now = Instant::now();
+
+        let response = keep_alive.apply(addr, now, Response::text(""));
+        assert_eq!(response.header("Keep-Alive"), Some("timeout=5"));
+        let later = now + Duration::from_secs(3);
+        assert!(!keep_alive
+            .apply(addr, later, Response::text(""))
+            .closes_connection());
+        let later = later + Duration::from_secs(6);
+        assert!(keep_alive
+            .apply(addr, later, Response::text(""))
+            .closes_connection());
+    }
+
+    #[test]
+    fn close_all() {
+        let keep_alive = KeepAlive::default();
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        let response = keep_alive.apply(addr, Instant::now(), Response::text(""));
+        assert!(!response.closes_connection());
+        assert_eq!(response.header("Keep-Alive"), None);
+
+        keep_alive.close_all();
+        let response = keep_alive.apply(addr, Instant::now(), Response::text(""));
+        assert!(response.closes_connection());
+    }
+}
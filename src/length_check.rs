@@ -0,0 +1,179 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::sync::Arc;
+
+/// A response whose body doesn't have the length that it declared. See
+/// [`Server::on_length_mismatch`](struct.Server.html#method.on_length_mismatch).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthMismatch {
+    /// The method of the request.
+    pub method: String,
+    /// The URL of the request, as returned by `Request::raw_url`.
+    pub url: String,
+    /// The length that the body declared, and that was sent in the `Content-Length` header.
+    pub declared: usize,
+    /// The number of bytes that the body contained. For a body that is longer than declared,
+    /// this is the number of bytes read before the server stopped reading it, which is
+    /// `declared + 1`.
+    pub actual: usize,
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let comparison = if self.actual < self.declared {
+            "shorter"
+        } else {
+            "longer"
+        };
+        write!(
+            fmt,
+            "the body of the response to {} {} is {} than its Content-Length of {} bytes",
+            self.method, self.url, comparison, self.declared
+        )
+    }
+}
+
+// Signature of the function registered with `Server::on_length_mismatch`.
+pub(crate) type MismatchFn = dyn Fn(&LengthMismatch) + Send + Sync;
+
+// Wraps the body of a response and checks that it has the declared length.
+//
+// A longer body is cut at the declared length, so that the bytes in excess aren't read by the
+// client as the beginning of the next response. A shorter body makes the read fail, so that the
+// connection is closed instead of the client waiting for the missing bytes.
+pub(crate) struct LengthCheck<R> {
+    inner: R,
+    method: String,
+    url: String,
+    declared: usize,
+    read: usize,
+    on_mismatch: Arc<MismatchFn>,
+}
+
+impl<R> LengthCheck<R> {
+    pub(crate) fn new(
+        inner: R,
+        declared: usize,
+        method: String,
+        url: String,
+        on_mismatch: Arc<MismatchFn>,
+    ) -> LengthCheck<R> {
+        LengthCheck {
+            inner,
+            method,
+            url,
+            declared,
+            read: 0,
+            on_mismatch,
+        }
+    }
+
+    fn mismatch(&self, actual: usize) {
+        let mismatch = LengthMismatch {
+            method: self.method.clone(),
+            url: self.url.clone(),
+            declared: self.declared,
+            actual,
+        };
+        (self.on_mismatch)(&mismatch);
+    }
+}
+
+impl<R> Read for LengthCheck<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let remaining = self.declared - self.read;
+        if remaining == 0 {
+            // Checks that the body ends here.
+            let mut extra = [0];
+            if self.inner.read(&mut extra)? != 0 {
+                self.mismatch(self.declared + 1);
+            }
+            return Ok(0);
+        }
+
+        let len = cmp::min(buf.len(), remaining);
+        let num = self.inner.read(&mut buf[..len])?;
+        if num == 0 {
+            self.mismatch(self.read);
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the body of the response is shorter than its Content-Length",
+            ));
+        }
+        self.read += num;
+        Ok(num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LengthCheck;
+    use super::LengthMismatch;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    fn check(body: &'static [u8], declared: usize) -> (Vec<u8>, bool, Vec<LengthMismatch>) {
+        let mismatches = Arc::new(Mutex::new(Vec::new()));
+        let on_mismatch = {
+            let mismatches = mismatches.clone();
+            Arc::new(move |m: &LengthMismatch| mismatches.lock().unwrap().push(m.clone()))
+        };
+        let mut reader = LengthCheck::new(
+            body,
+            declared,
+            "GET".to_owned(),
+            "/".to_owned(),
+            on_mismatch,
+        );
+        let mut data = Vec::new();
+        let ok = reader.read_to_end(&mut data).is_ok();
+        let mismatches = mismatches.lock().unwrap().clone();
+        (data, ok, mismatches)
+    }
+
+    #[test]
+    fn exact() {
+        assert_eq!(check(b"hello", 5), (b"hello".to_vec(), true, vec![]));
+        assert_eq!(check(b"", 0), (vec![], true, vec![]));
+    }
+
+    #[test]
+    fn longer() {
+        let (data, ok, mismatches) = check(b"hello world", 5);
+        assert_eq!(data, b"hello");
+        assert!(ok);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].actual, 6);
+        assert_eq!(
+            mismatches[0].to_string(),
+            "the body of the response to GET / is longer than its Content-Length of 5 bytes"
+        );
+    }
+
+    #[test]
+    fn shorter() {
+        let (data, ok, mismatches) = check(b"hel", 5);
+        assert_eq!(data, b"hel");
+        assert!(!ok);
+        assert_eq!(mismatches[0].actual, 3);
+    }
+}
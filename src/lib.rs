@@ -52,26 +52,70 @@
 
 #![deny(unsafe_code)]
 
+#[cfg(feature = "aes-gcm")]
+extern crate aes_gcm;
+#[cfg(feature = "argon2")]
+extern crate argon2;
 extern crate base64;
+#[cfg(feature = "bcrypt")]
+extern crate bcrypt;
 #[cfg(feature = "brotli2")]
 extern crate brotli2;
+#[cfg(feature = "bytes")]
+extern crate bytes;
 extern crate chrono;
+#[cfg(feature = "cbor")]
+extern crate ciborium;
 #[cfg(feature = "gzip")]
 extern crate deflate;
 extern crate filetime;
+#[cfg(feature = "flate2")]
+extern crate flate2;
+#[cfg(feature = "h2")]
+extern crate h2;
+#[cfg(feature = "hmac")]
+extern crate hmac;
+#[cfg(feature = "http")]
+pub extern crate http;
+#[cfg(feature = "md-5")]
+extern crate md5;
 extern crate multipart;
 extern crate rand;
+#[cfg(feature = "rustls")]
+extern crate rustls;
+#[cfg(feature = "rustls-pemfile")]
+extern crate rustls_pemfile;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate num_cpus;
 pub extern crate percent_encoding;
+#[cfg(feature = "markdown")]
+extern crate pulldown_cmark;
+#[cfg(feature = "ring")]
+extern crate ring;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
 extern crate serde_json;
 extern crate sha1;
+#[cfg(feature = "sha2")]
+extern crate sha2;
+extern crate socket2;
 extern crate threadpool;
-extern crate time;
-extern crate tiny_http;
+pub extern crate tiny_http;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "http2-tls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tower-service")]
+extern crate tower_service;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 pub extern crate url;
+#[cfg(feature = "webpki-roots")]
+extern crate webpki_roots;
+#[cfg(feature = "zstd")]
+extern crate zstd;
 
 // https://github.com/servo/rust-url/blob/e121d8d0aafd50247de5f5310a227ecb1efe6ffe/percent_encoding/lib.rs#L126
 pub const DEFAULT_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
@@ -85,44 +129,155 @@ pub const DEFAULT_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::C
     .add(b'{')
     .add(b'}');
 
+pub use asset_manifest::{AssetManifest, Fingerprint};
 pub use assets::extension_to_mime;
 pub use assets::match_assets;
-pub use log::{log, log_custom};
+pub use assets::match_assets_with_options;
+pub use assets::AssetsOptions;
+pub use body_buffer::BufferBodyError;
+pub use connection::ConnectionInfo;
+pub use deadline::Deadline;
+pub use embedded::{EmbeddedAsset, EmbeddedAssets};
+pub use extensions::Extensions;
+pub use header_limits::HeaderLimits;
+pub use json_options::{JsonOptions, JsonStreamFormat};
+pub use length_check::LengthMismatch;
+pub use log::{log, log_custom, log_json, log_with_format, log_with_handler};
+pub use log::{
+    HandlerLogger, JsonLogger, LogFormat, LogFormatError, LogHandler, LogSummary, Logger,
+};
+pub use minify::minify_assets;
 pub use response::{Response, ResponseBody};
+pub use response_builder::{ResponseBuilder, ResponseBuilderError, SameSite, SetCookie};
+pub use router::{AllowedMethods, AutomaticHead, CustomMethods};
+pub use server_builder::ServerBuilder;
+pub use socket::SocketOptions;
 pub use tiny_http::ReadWrite;
-
+pub use url_for::url_for;
+pub use vhost::{match_host, HostParams};
+
+use body_buffer::BufferedBody;
+use deadline::TrustFn;
+use health::Health;
+use keep_alive::KeepAlive;
+use length_check::LengthCheck;
+use length_check::MismatchFn;
+use std::any::Any;
+use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::io::Cursor;
 use std::io::Read;
 use std::io::Result as IoResult;
+use std::io::Write;
 use std::marker::PhantomData;
+use std::mem;
+use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::net::TcpListener;
 use std::net::ToSocketAddrs;
 use std::panic;
 use std::panic::AssertUnwindSafe;
 use std::slice::Iter as SliceIter;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::MutexGuard;
 use std::thread;
 use std::time::Duration;
-
+use std::time::Instant;
+
+use link::Link;
+use random::Random;
+use real_ip::{Client, RealIp};
+use subsystems::Subsystems;
+use upload_limit::UploadLimit;
+
+pub mod ban;
+pub mod blob;
+pub mod cache_control;
+pub mod canary;
 pub mod cgi;
 pub mod content_encoding;
+pub mod cors;
+pub mod csrf;
+#[cfg(feature = "dev")]
+#[clippy::msrv = "1.65"]
+pub mod dev;
+pub mod fastcgi;
+#[cfg(unix)]
+pub mod handoff;
+pub mod health;
+#[cfg(feature = "http2")]
+#[clippy::msrv = "1.70"]
+pub mod http2;
+#[cfg(feature = "http")]
+pub mod http_compat;
+pub mod http_date;
 pub mod input;
+pub mod link;
+pub mod mapping;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod middleware;
+pub mod mime;
+pub mod negotiate;
+pub mod policy;
+pub mod prelude;
 pub mod proxy;
+pub mod random;
+pub mod rate_limit;
+pub mod real_ip;
+pub mod response_cache;
+pub mod rules;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sampler;
+#[cfg(feature = "signed-cookies")]
+pub mod secure_cookies;
+pub mod security_headers;
 pub mod session;
+pub mod shadow;
+pub mod sse;
+pub mod subsystems;
+pub mod test;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod trailing_slash;
+pub mod upload_limit;
 pub mod websocket;
+#[cfg(unix)]
+pub mod workers;
 
+mod asset_manifest;
 mod assets;
+mod body_buffer;
+mod connection;
+mod deadline;
+mod embedded;
+mod extensions;
 mod find_route;
+mod header_limits;
+mod json_options;
+mod keep_alive;
+mod length_check;
 mod log;
+mod minify;
+mod mount;
 mod response;
+mod response_builder;
 mod router;
+mod server_builder;
+mod socket;
 #[doc(hidden)]
 pub mod try_or_400;
+mod url_for;
+mod util;
+mod vhost;
 
 /// This macro assumes that the current function returns a `Response` and takes a `Result`.
 /// If the expression you pass to the macro is an error, then a 404 response is returned.
@@ -247,6 +402,93 @@ where
     panic!("The server socket closed unexpectedly")
 }
 
+/// Identical to `start_server` but passes a shared state to the handler.
+///
+/// The handler receives a reference to `state` alongside each request. This is handy for
+/// sharing a database pool or a configuration between requests. See
+/// [`with_state`](fn.with_state.html) for more details.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use rouille::Response;
+///
+/// struct AppState {
+///     greeting: String,
+///     visits: AtomicUsize,
+/// }
+///
+/// let state = AppState {
+///     greeting: "hello".to_owned(),
+///     visits: AtomicUsize::new(0),
+/// };
+///
+/// rouille::start_server_with_state("localhost:80", state, |state, request| {
+///     let visits = state.visits.fetch_add(1, Ordering::Relaxed) + 1;
+///     Response::text(format!("{} visitor #{}", state.greeting, visits))
+/// });
+/// ```
+pub fn start_server_with_state<A, S, F>(addr: A, state: S, handler: F) -> !
+where
+    A: ToSocketAddrs,
+    S: Send + Sync + 'static,
+    F: Send + Sync + 'static + Fn(&S, &Request) -> Response,
+{
+    start_server(addr, with_state(state, handler))
+}
+
+/// Turns a handler that takes a shared state into a regular handler.
+///
+/// The returned handler owns `state` and passes a reference to it on each call, which avoids
+/// having to move clones of the state into the closure by hand. Since multiple requests can be
+/// processed simultaneously, any mutable part of the state must be protected with a `Mutex` or
+/// an atomic. If you need to access the state from elsewhere as well, pass an `Arc`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::{Arc, Mutex};
+/// use rouille::{Response, Server};
+///
+/// let names = Arc::new(Mutex::new(Vec::<String>::new()));
+///
+/// let handler = rouille::with_state(names.clone(), |names, request| {
+///     Response::text(format!("{} names", names.lock().unwrap().len()))
+/// });
+///
+/// let server = Server::new("localhost:0", handler).unwrap();
+/// ```
+pub fn with_state<S, F>(state: S, handler: F) -> impl Fn(&Request) -> Response
+where
+    F: Fn(&S, &Request) -> Response,
+{
+    move |request: &Request| handler(&state, request)
+}
+
+/// Returns the message of a panic, given its payload.
+///
+/// Returns `None` if the panic was not started with a string message, for example with
+/// `std::panic::panic_any`.
+///
+/// # Example
+///
+/// ```
+/// use std::panic;
+///
+/// let payload = panic::catch_unwind(|| panic!("oops {}", 5)).unwrap_err();
+/// assert_eq!(rouille::panic_message(&*payload), Some("oops 5"));
+/// ```
+pub fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        Some(message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        Some(message)
+    } else {
+        None
+    }
+}
+
 struct AtomicCounter(Arc<AtomicUsize>);
 
 impl AtomicCounter {
@@ -333,11 +575,73 @@ impl Default for Executor {
 /// server.run();
 /// ```
 pub struct Server<F> {
-    server: tiny_http::Server,
+    // Never empty. The first element is the listener that was created when building the server.
+    servers: Vec<Arc<tiny_http::Server>>,
+    // Requests of all the listeners, once `forwarded` has been called.
+    forwarded: Mutex<Option<mpsc::Receiver<Incoming>>>,
     handler: Arc<AssertUnwindSafe<F>>,
+    panic_handler: Option<Arc<PanicHandler>>,
+    error_handler: Option<Arc<ErrorHandler>>,
+    on_length_mismatch: Option<Arc<MismatchFn>>,
+    random: Option<Random>,
+    automatic_head: bool,
+    allow_trace: bool,
+    custom_methods: Vec<String>,
+    reject_unknown_methods: bool,
+    expect_continue: Option<Arc<ContinueFn>>,
+    header_limits: HeaderLimits,
+    header_rejections: Arc<AtomicUsize>,
+    request_timeout: Option<Duration>,
+    respond_at_deadline: bool,
+    trust_timeout_header: Option<Arc<TrustFn>>,
+    real_ip: Option<Arc<RealIp>>,
+    upload_limit: Option<UploadLimit>,
+    keep_alive: Arc<KeepAlive>,
+    health: Option<Arc<Health>>,
+    subsystems: Arc<Subsystems>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<metrics::Metrics>>,
     executor: Executor,
 }
 
+// A request received by one of the listeners, and the address of that listener.
+type Incoming = (tiny_http::Request, SocketAddr);
+
+// How long the threads of `Server::forwarded` wait for a request before checking whether the
+// server still exists.
+const FORWARD_INTERVAL: Duration = Duration::from_millis(100);
+
+// Signature of the function registered with `Server::panic_handler`.
+type PanicHandler = dyn Fn(&Request, &(dyn Any + Send)) -> Response + Send + Sync;
+
+// Signature of the function registered with `Server::error_handler`.
+type ErrorHandler = dyn Fn(&Request, Response) -> Response + Send + Sync;
+
+// Signature of the function registered with `Server::expect_continue`.
+type ContinueFn = dyn Fn(&Request) -> Option<Response> + Send + Sync;
+
+// The response sent when the handler panics and there is no panic handler.
+fn internal_server_error() -> Response {
+    let mut response = Response::html(
+        "<h1>Internal Server Error</h1>\
+         <p>An internal error has occurred on the server.</p>",
+    )
+    .with_status_code(500);
+    response.data.default_error = true;
+    response
+}
+
+// The type of the handler is only known when calling `ServerBuilder::build`, so `builder` is
+// defined for an arbitrary one.
+impl Server<fn(&Request) -> Response> {
+    /// Starts building a `Server` with a [`ServerBuilder`](struct.ServerBuilder.html), in
+    /// order to combine several ways of creating the listening sockets.
+    #[inline]
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+}
+
 impl<F> Server<F>
 where
     F: Send + Sync + 'static + Fn(&Request) -> Response,
@@ -353,11 +657,79 @@ where
         A: ToSocketAddrs,
     {
         let server = tiny_http::Server::http(addr)?;
-        Ok(Server {
-            server,
-            executor: Executor::default(),
-            handler: Arc::new(AssertUnwindSafe(handler)), // TODO: using AssertUnwindSafe here is wrong, but unwind safety has some usability problems in Rust in general
-        })
+        Ok(Server::from_tiny_http(server, handler))
+    }
+
+    /// Builds a new `Server` object whose listening socket is configured with the given
+    /// options.
+    ///
+    /// See [the `SocketOptions` struct](struct.SocketOptions.html) for the list of options.
+    ///
+    /// Returns an error if there was an error while creating the listening socket, for example if
+    /// the port is already in use or if an option isn't supported by the platform.
+    pub fn with_socket_options<A>(
+        addr: A,
+        options: &SocketOptions,
+        handler: F,
+    ) -> Result<Server<F>, Box<dyn Error + Send + Sync + 'static>>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = options.bind(addr)?;
+        Server::from_listener(listener, handler)
+    }
+
+    /// Builds a new `Server` object that accepts connections from an existing listening socket.
+    ///
+    /// This is useful if you've created the listener yourself, for example to set socket options
+    /// that rouille doesn't support or to receive a socket from systemd.
+    pub fn from_listener(
+        listener: TcpListener,
+        handler: F,
+    ) -> Result<Server<F>, Box<dyn Error + Send + Sync + 'static>> {
+        let server = tiny_http::Server::from_listener(listener, None)?;
+        Ok(Server::from_tiny_http(server, handler))
+    }
+
+    /// Builds a new `Server` object that listens on multiple addresses at once.
+    ///
+    /// All the requests received on any of the addresses are processed by the same handler. This
+    /// is useful for example to listen on both IPv4 and IPv6, or on a public port and an internal
+    /// administration port.
+    ///
+    /// Returns an error if `addrs` is empty, or if there was an error while creating one of the
+    /// listening sockets.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new_multi(&["0.0.0.0:80", "[::]:80"], |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap();
+    /// println!("Listening on {:?}", server.server_addrs());
+    /// server.run();
+    /// ```
+    pub fn new_multi<A>(
+        addrs: &[A],
+        handler: F,
+    ) -> Result<Server<F>, Box<dyn Error + Send + Sync + 'static>>
+    where
+        A: ToSocketAddrs,
+    {
+        let mut addrs = addrs.iter();
+        let mut server = match addrs.next() {
+            Some(addr) => Server::new(addr, handler)?,
+            None => return Err("no address to listen on".into()),
+        };
+
+        for addr in addrs {
+            server = server.add_listener(addr)?;
+        }
+
+        Ok(server)
     }
 
     /// Builds a new `Server` object with SSL support.
@@ -381,11 +753,73 @@ where
             private_key,
         };
         let server = tiny_http::Server::https(addr, ssl_config)?;
-        Ok(Server {
-            server,
+        Ok(Server::from_tiny_http(server, handler))
+    }
+
+    // Internal function that wraps around a `tiny_http::Server` that is already listening.
+    fn from_tiny_http(server: tiny_http::Server, handler: F) -> Server<F> {
+        Server {
+            servers: vec![Arc::new(server)],
+            forwarded: Mutex::new(None),
             executor: Executor::default(),
+            panic_handler: None,
+            error_handler: None,
+            on_length_mismatch: None,
+            random: None,
+            automatic_head: true,
+            allow_trace: false,
+            custom_methods: Vec::new(),
+            reject_unknown_methods: false,
+            expect_continue: None,
+            header_limits: HeaderLimits::default(),
+            header_rejections: Arc::new(AtomicUsize::new(0)),
+            request_timeout: None,
+            respond_at_deadline: false,
+            trust_timeout_header: None,
+            real_ip: None,
+            upload_limit: None,
+            keep_alive: Arc::new(KeepAlive::default()),
+            health: None,
+            subsystems: Arc::new(Subsystems::new()),
+            #[cfg(feature = "metrics")]
+            metrics: None,
             handler: Arc::new(AssertUnwindSafe(handler)), // TODO: using AssertUnwindSafe here is wrong, but unwind safety has some usability problems in Rust in general
-        })
+        }
+    }
+
+    /// Starts listening on an additional address. Requests received on this address are processed
+    /// by the same handler as the other addresses.
+    ///
+    /// Returns an error if there was an error while creating the listening socket, for example if
+    /// the port is already in use.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("0.0.0.0:80", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap().add_listener("127.0.0.1:8080").unwrap();
+    /// server.run();
+    /// ```
+    pub fn add_listener<A>(mut self, addr: A) -> Result<Self, Box<dyn Error + Send + Sync + 'static>>
+    where
+        A: ToSocketAddrs,
+    {
+        self.servers.push(Arc::new(tiny_http::Server::http(addr)?));
+        Ok(self)
+    }
+
+    /// Same as `add_listener`, but uses a listening socket that you have created yourself.
+    pub fn add_tcp_listener(
+        mut self,
+        listener: TcpListener,
+    ) -> Result<Self, Box<dyn Error + Send + Sync + 'static>> {
+        let server = tiny_http::Server::from_listener(listener, None)?;
+        self.servers.push(Arc::new(server));
+        Ok(self)
     }
 
     /// Use a `ThreadPool` of the given size to process requests
@@ -396,122 +830,651 @@ where
         self
     }
 
-    /// Returns the address of the listening socket.
+    /// Sets the function that builds the response when the handler panics.
+    ///
+    /// The function receives the request and the payload of the panic, and returns the response
+    /// to send to the client, for example a JSON error body containing an incident identifier.
+    /// Use [`panic_message`](fn.panic_message.html) to extract the message from the payload.
+    ///
+    /// If no panic handler is set, or if the panic handler panics as well, a bare
+    /// `500 Internal Server Error` response is sent.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     panic!("oops")
+    /// }).unwrap().panic_handler(|request, payload| {
+    ///     let message = rouille::panic_message(payload).unwrap_or("unknown error");
+    ///     eprintln!("{} {} panicked: {}", request.method(), request.raw_url(), message);
+    ///     Response::json(&"internal error").with_status_code(500)
+    /// });
+    /// server.run();
+    /// ```
+    pub fn panic_handler<P>(mut self, panic_handler: P) -> Self
+    where
+        P: Fn(&Request, &(dyn Any + Send)) -> Response + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(panic_handler));
+        self
+    }
+
+    /// Sets a function that can replace the error responses of the server.
+    ///
+    /// The function is called for each response with a status code of 400 or more that has an
+    /// empty body, or whose body was generated by rouille itself, such as the ones returned by
+    /// `try_or_400!` or sent after a panic when no panic handler is set. It receives the request
+    /// and the response, and returns the response to send instead. This makes it possible to
+    /// enforce a consistent format for error responses in one place.
+    ///
+    /// Note that errors that tiny-http answers by itself, for example when a request is
+    /// malformed, don't go through this function.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::empty_404()
+    /// }).unwrap().error_handler(|request, response| {
+    ///     let problem = format!(r#"{{"status":{}}}"#, response.status_code);
+    ///     Response::from_data("application/problem+json", problem)
+    ///         .with_status_code(response.status_code)
+    /// });
+    /// server.run();
+    /// ```
+    pub fn error_handler<E>(mut self, error_handler: E) -> Self
+    where
+        E: Fn(&Request, Response) -> Response + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Arc::new(error_handler));
+        self
+    }
+
+    /// Sets the limits on the headers of the requests. Requests that exceed them are answered
+    /// with `431 Request Header Fields Too Large` without calling the handler.
+    ///
+    /// Defaults to `HeaderLimits::default()`. See [`HeaderLimits`](struct.HeaderLimits.html).
     #[inline]
-    pub fn server_addr(&self) -> SocketAddr {
-        self.server.server_addr()
+    pub fn header_limits(mut self, limits: HeaderLimits) -> Self {
+        self.header_limits = limits;
+        self
     }
 
-    /// Runs the server forever, or until the listening socket is somehow force-closed by the
-    /// operating system.
+    /// Returns the counter of the requests that were rejected because their headers exceeded
+    /// the limits.
+    ///
+    /// The counter is shared with the server, so it can be kept to read the number of rejections
+    /// after the server has been moved into `run` or `stoppable`.
     #[inline]
-    pub fn run(self) {
-        for request in self.server.incoming_requests() {
-            self.process(request);
-        }
+    pub fn header_limit_rejections(&self) -> Arc<AtomicUsize> {
+        self.header_rejections.clone()
     }
 
-    /// Processes all the client requests waiting to be processed, then returns.
+    /// Returns the registry of the background tasks of the server. They are shut down when
+    /// `run` returns or when the server started with `stoppable` is stopped.
     ///
-    /// This function executes very quickly, as each client requests that needs to be processed
-    /// is processed in a separate thread.
+    /// See [the `subsystems` module](subsystems/index.html).
     #[inline]
-    pub fn poll(&self) {
-        while let Ok(Some(request)) = self.server.try_recv() {
-            self.process(request);
-        }
+    pub fn subsystems(&self) -> Arc<Subsystems> {
+        self.subsystems.clone()
     }
 
-    /// Creates a new thread for the server that can be gracefully stopped later.
+    /// Checks that the bodies of the responses have the length that they declared, and calls
+    /// `on_mismatch` when they don't.
     ///
-    /// This function returns a tuple of a `JoinHandle` and a `Sender`.
-    /// You must call `JoinHandle::join()` otherwise the server will not run until completion.
-    /// The server can be stopped at will by sending it an empty `()` message from another thread.
-    /// There may be a maximum of a 1 second delay between sending the stop message and the server
-    /// stopping. This delay may be shortened in future.
+    /// The length of a body is usually known by rouille, but a body built with
+    /// [`ResponseBody::from_reader_and_size`](struct.ResponseBody.html#method.from_reader_and_size),
+    /// or a file that is modified while being sent, can be shorter or longer than what was sent
+    /// in the `Content-Length` header. A longer body would be read by the client as the beginning
+    /// of the next response, and a shorter one would make it wait for the missing bytes. When
+    /// this is enabled, the server stops sending a body at its declared length, and closes the
+    /// connection if the body is shorter.
+    ///
+    /// # Example
     ///
     /// ```no_run
-    /// use std::thread;
-    /// use std::time::Duration;
     /// use rouille::Server;
     /// use rouille::Response;
     ///
     /// let server = Server::new("localhost:0", |request| {
     ///     Response::text("hello world")
-    /// }).unwrap();
-    /// println!("Listening on {:?}", server.server_addr());
-    /// let (handle, sender) = server.stoppable();
+    /// }).unwrap().on_length_mismatch(|mismatch| eprintln!("{}", mismatch));
+    /// server.run();
+    /// ```
+    pub fn on_length_mismatch<M>(mut self, on_mismatch: M) -> Self
+    where
+        M: Fn(&LengthMismatch) + Send + Sync + 'static,
+    {
+        self.on_length_mismatch = Some(Arc::new(on_mismatch));
+        self
+    }
+
+    /// Sets the source of the randomness used to generate identifiers, such as session IDs.
     ///
-    /// // Stop the server in 3 seconds
-    /// thread::spawn(move || {
-    ///     thread::sleep(Duration::from_secs(3));
-    ///     sender.send(()).unwrap();
-    /// });
+    /// The source is inserted in the extensions of each request, and is returned by
+    /// [`Request::random`](struct.Request.html#method.random). If this is not called, the
+    /// randomness of the operating system is used.
     ///
-    /// // Block the main thread until the server is stopped
-    /// handle.join().unwrap();
+    /// A seeded source makes the identifiers deterministic, which is useful to replay a bug or
+    /// in tests, but must never be used in production.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    /// use rouille::random::Random;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::text(request.random().alphanumeric(16))
+    /// }).unwrap().random(Random::seeded(42));
+    /// server.run();
     /// ```
-    #[inline]
-    pub fn stoppable(self) -> (thread::JoinHandle<()>, mpsc::Sender<()>) {
-        let (tx, rx) = mpsc::channel();
-        let handle = thread::spawn(move || {
-            while rx.try_recv().is_err() {
-                // In order to reduce CPU load wait 1s for a recv before looping again
-                while let Ok(Some(request)) = self.server.recv_timeout(Duration::from_secs(1)) {
-                    self.process(request);
-                }
-            }
-        });
+    pub fn random(mut self, random: Random) -> Self {
+        self.random = Some(random);
+        self
+    }
 
-        (handle, tx)
+    /// Gives each request a [`Deadline`](struct.Deadline.html) of `timeout` after it is received.
+    ///
+    /// The deadline is only advisory: handlers read it with
+    /// [`Request::deadline`](struct.Request.html#method.deadline) to bound their own work, but
+    /// the server doesn't interrupt them unless `respond_at_deadline` is used.
+    #[inline]
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
     }
 
-    /// Same as `poll()` but blocks for at most `duration` before returning.
+    /// Answers with `503 Service Unavailable` the requests whose handler hasn't returned by their
+    /// deadline, as set by `request_timeout` or `trust_timeout_header`.
     ///
-    /// This function can be used implement a custom server loop in a more CPU-efficient manner
-    /// than calling `poll`.
+    /// The handler keeps running, since threads can't be interrupted, but its response is
+    /// discarded, and reading the rest of the body of the request returns EOF. Handlers should
+    /// still check [`Request::remaining_time`](struct.Request.html#method.remaining_time) to stop
+    /// early. The error handler isn't called for this response.
+    ///
+    /// A thread waits for the deadline of each request that has one.
     ///
     /// # Example
     ///
     /// ```no_run
+    /// use std::time::Duration;
     /// use rouille::Server;
     /// use rouille::Response;
     ///
-    /// let server = Server::new("localhost:0", |request| {
+    /// let server = Server::new("0.0.0.0:80", |request| {
     ///     Response::text("hello world")
-    /// }).unwrap();
-    /// println!("Listening on {:?}", server.server_addr());
-    ///
-    /// loop {
-    ///     server.poll_timeout(std::time::Duration::from_millis(100));
-    /// }
+    /// })
+    /// .unwrap()
+    /// .request_timeout(Duration::from_secs(30))
+    /// .respond_at_deadline();
+    /// server.run();
     /// ```
     #[inline]
-    pub fn poll_timeout(&self, dur: std::time::Duration) {
-        while let Ok(Some(request)) = self.server.recv_timeout(dur) {
-            self.process(request);
-        }
+    pub fn respond_at_deadline(mut self) -> Self {
+        self.respond_at_deadline = true;
+        self
     }
 
-    /// Waits for all in-flight requests to be processed. This is useful for implementing a graceful
-    /// shutdown.
+    /// Reads the `X-Request-Timeout` header, a number of milliseconds, of the requests for which
+    /// `trusted` returns true, and uses it as their [`Deadline`](struct.Deadline.html). If
+    /// `request_timeout` is also set, the earliest deadline is used.
     ///
-    /// Note: new connections may still be accepted while we wait, and this function does not guarantee
-    /// to wait for those new requests. To implement a graceful shutdown or a clean rolling-update,
-    /// the following approach should be used:
+    /// Only trust the header when it comes from your own services, since any client can send it.
     ///
-    /// 1) Stop routing requests to this server. For a rolling update, requests should be routed
-    ///    to the new instance. This logic typically sits outside of your application.
+    /// # Example
     ///
-    /// 2) Drain the queue of incoming connections by calling `poll_timeout` with a short timeout.
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use rouille::Server;
+    /// use rouille::Response;
     ///
-    /// 3) Wait for in-flight requests to be processed by using this method.
+    /// let server = Server::new("0.0.0.0:80", |request| {
+    ///     Response::text(format!("{:?} left", request.remaining_time()))
+    /// })
+    /// .unwrap()
+    /// .request_timeout(Duration::from_secs(30))
+    /// .trust_timeout_header(|request| request.remote_addr().ip().is_loopback());
+    /// server.run();
+    /// ```
+    pub fn trust_timeout_header<T>(mut self, trusted: T) -> Self
+    where
+        T: Fn(&Request) -> bool + Send + Sync + 'static,
+    {
+        self.trust_timeout_header = Some(Arc::new(trusted));
+        self
+    }
+
+    /// Resolves the address of the clients behind the trusted proxies of `real_ip`, which
+    /// [`Request::client_addr`](struct.Request.html#method.client_addr) then returns. See
+    /// [the `real_ip` module](real_ip/index.html).
+    #[inline]
+    pub fn real_ip(mut self, real_ip: RealIp) -> Self {
+        self.real_ip = Some(Arc::new(real_ip));
+        self
+    }
+
+    /// Limits the rate at which the bodies of all the requests are read. See
+    /// [the `upload_limit` module](upload_limit/index.html).
     ///
     /// # Example
+    ///
     /// ```no_run
-    /// # use std::time::Duration;
-    /// # use rouille::Server;
-    /// #
+    /// use rouille::upload_limit::UploadLimit;
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("0.0.0.0:80", |request| {
+    ///     Response::text("hello world")
+    /// })
+    /// .unwrap()
+    /// .upload_limit(UploadLimit::new(1_000_000));
+    /// server.run();
+    /// ```
+    #[inline]
+    pub fn upload_limit(mut self, limit: UploadLimit) -> Self {
+        self.upload_limit = Some(limit);
+        self
+    }
+
+    /// Closes the connections after they have carried `max` requests, by adding a
+    /// `Connection: close` header to the last response. The other responses get a
+    /// `Keep-Alive: max=N` header with the number of requests that are left.
+    ///
+    /// Rotating the connections spreads the clients between the instances behind load balancers
+    /// that keep the connections open for a long time.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `max` is 0.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("0.0.0.0:80", |request| {
+    ///     Response::text("hello world")
+    /// })
+    /// .unwrap()
+    /// .max_requests_per_connection(1000)
+    /// .keep_alive_timeout(Duration::from_secs(60));
+    /// server.run();
+    /// ```
+    #[inline]
+    pub fn max_requests_per_connection(mut self, max: usize) -> Self {
+        assert!(max > 0, "the maximum number of requests must not be zero");
+        self.keep_alive = Arc::new(KeepAlive::new(Some(max), self.keep_alive.timeout));
+        self
+    }
+
+    /// Sets how long the connections are kept open between two requests. The timeout is sent to
+    /// the clients in a `Keep-Alive: timeout=N` header.
+    ///
+    /// tiny-http doesn't close the idle connections by itself, so a client that ignores the header
+    /// can keep its connection open. Its next request after the timeout is then answered with a
+    /// `Connection: close` header.
+    #[inline]
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive = Arc::new(KeepAlive::new(self.keep_alive.max_requests, Some(timeout)));
+        self
+    }
+
+    /// Adds a `Connection: close` header to all the responses from now on, so that the clients
+    /// stop sending requests on their current connections.
+    ///
+    /// Call this when a graceful shutdown starts, in order to move the clients to the other
+    /// instances while the in-flight requests are finishing.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use rouille::Server;
+    /// #
+    /// # let server = Server::new("", |_| unimplemented!()).unwrap();
+    /// # fn is_stopping() -> bool { unimplemented!() }
+    /// while !is_stopping() {
+    ///     server.poll_timeout(Duration::from_millis(100));
+    /// }
+    ///
+    /// server.close_connections();
+    /// server.poll_timeout(Duration::from_millis(100));
+    /// server.join();
+    /// ```
+    #[inline]
+    pub fn close_connections(&self) {
+        self.keep_alive.close_all();
+        if let Some(ref health) = self.health {
+            health.set_shutting_down();
+        }
+    }
+
+    /// Makes the readiness endpoint of `health` fail when `close_connections` is called or when
+    /// the server stops. See [the `health` module](health/index.html).
+    #[inline]
+    pub fn health(mut self, health: Arc<Health>) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Records every request in the given registry, including the ones that the server answers
+    /// by itself. See [the `metrics` module](metrics/index.html).
+    ///
+    /// This method is only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn metrics(mut self, metrics: Arc<metrics::Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets whether the `GET` routes of `router!` also answer `HEAD` requests. Defaults to
+    /// `true`. See [`AutomaticHead`](struct.AutomaticHead.html).
+    #[inline]
+    pub fn automatic_head(mut self, enabled: bool) -> Self {
+        self.automatic_head = enabled;
+        self
+    }
+
+    /// Sets whether `TRACE` requests are passed to the handler. Defaults to `false`, in which
+    /// case the server answers them with `405 Method Not Allowed`, because echoing a request
+    /// back can leak its cookies and other credentials to a malicious script.
+    #[inline]
+    pub fn allow_trace(mut self, allow: bool) -> Self {
+        self.allow_trace = allow;
+        self
+    }
+
+    /// Registers methods that the server implements in addition to the standard ones. See
+    /// [`CustomMethods`](struct.CustomMethods.html).
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[macro_use] extern crate rouille;
+    /// use rouille::{Response, Server};
+    ///
+    /// # fn main() {
+    /// let server = Server::new("localhost:0", |request| {
+    ///     router!(request,
+    ///         (GET) (/cache/{key: String}) => { Response::text(key) },
+    ///         (PURGE) (/cache/{key: String}) => { Response::empty_204() },
+    ///         _ => Response::empty_404_or_405(request)
+    ///     )
+    /// })
+    /// .unwrap()
+    /// .custom_methods(&["PURGE"])
+    /// .reject_unknown_methods(true);
+    /// server.run();
+    /// # }
+    /// ```
+    pub fn custom_methods<S>(mut self, methods: &[S]) -> Self
+    where
+        S: AsRef<str>,
+    {
+        self.custom_methods
+            .extend(methods.iter().map(|m| m.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets whether requests whose method is neither standard nor registered with
+    /// `custom_methods` are answered with `501 Not Implemented` without calling the handler.
+    /// Defaults to `false`.
+    #[inline]
+    pub fn reject_unknown_methods(mut self, reject: bool) -> Self {
+        self.reject_unknown_methods = reject;
+        self
+    }
+
+    /// Registers a function that decides whether to accept the body of the requests that have an
+    /// `Expect: 100-continue` header, before the handler is called.
+    ///
+    /// Clients such as curl send this header with large uploads, and wait for a `100 Continue`
+    /// interim response before sending the body. The server sends it automatically the first
+    /// time the body is read by the handler, for example with `request.data()`. If the function
+    /// returns a response instead of `None`, this response is sent without calling the handler,
+    /// so that the client doesn't upload a body that would be rejected anyway. A `417
+    /// Expectation Failed` response is the usual answer when the server can't meet the
+    /// expectation, but the function can return any error, such as `413 Payload Too Large` or
+    /// `401 Unauthorized`.
+    ///
+    /// Requests with any other expectation are answered with `417 Expectation Failed` by the
+    /// server.
+    ///
+    /// > **Note**: The connection is only reused once the body of the rejected request has been
+    /// > discarded, so the response is only sent after the client has stopped waiting and sent
+    /// > the body, or after it has closed the connection.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::{Response, Server};
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     // ...
+    /// # Response::text("")
+    /// })
+    /// .unwrap()
+    /// .expect_continue(|request| {
+    ///     let length = request.header("Content-Length")?.parse::<u64>().ok()?;
+    ///     if length > 10 * 1024 * 1024 {
+    ///         Some(Response::text("Payload Too Large").with_status_code(413))
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    /// server.run();
+    /// ```
+    pub fn expect_continue<C>(mut self, check: C) -> Self
+    where
+        C: Fn(&Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.expect_continue = Some(Arc::new(check));
+        self
+    }
+
+    /// Returns the address of the listening socket.
+    ///
+    /// If the server listens on multiple addresses, returns the first one.
+    #[inline]
+    pub fn server_addr(&self) -> SocketAddr {
+        self.servers[0].server_addr()
+    }
+
+    /// Returns the addresses of all the listening sockets, in the order they were added.
+    pub fn server_addrs(&self) -> Vec<SocketAddr> {
+        self.servers.iter().map(|s| s.server_addr()).collect()
+    }
+
+    /// Runs the server forever, or until the listening socket is somehow force-closed by the
+    /// operating system.
+    ///
+    /// If the server listens on multiple addresses, this function only returns once all the
+    /// listening sockets have been closed.
+    pub fn run(self) {
+        if self.servers.len() == 1 {
+            let local_addr = self.servers[0].server_addr();
+            for request in self.servers[0].incoming_requests() {
+                self.process(request, local_addr);
+            }
+            self.shut_down();
+            return;
+        }
+
+        while let Ok((request, local_addr)) = self.forwarded(|rx| rx.recv()) {
+            self.process(request, local_addr);
+        }
+        self.shut_down();
+    }
+
+    // Calls `f` with a channel that receives the requests of all the listeners, so that they
+    // can be waited for at the same time. The first call starts a thread per listener that
+    // forwards its requests to the channel. The threads stop when the `Server` is dropped or
+    // when their listener fails.
+    fn forwarded<R>(&self, f: impl FnOnce(&mpsc::Receiver<Incoming>) -> R) -> R {
+        let mut forwarded = self.forwarded.lock().unwrap();
+        if forwarded.is_none() {
+            let (tx, rx) = mpsc::channel();
+            for server in &self.servers {
+                let local_addr = server.server_addr();
+                // Only a weak reference is kept, so that the listener is closed once the
+                // `Server` is dropped.
+                let server = Arc::downgrade(server);
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    while let Some(server) = server.upgrade() {
+                        match server.recv_timeout(FORWARD_INTERVAL) {
+                            Ok(Some(request)) => {
+                                if tx.send((request, local_addr)).is_err() {
+                                    break;
+                                }
+                            }
+                            Ok(None) => (),
+                            Err(_) => break,
+                        }
+                    }
+                });
+            }
+            *forwarded = Some(rx);
+        }
+        f(forwarded.as_ref().unwrap())
+    }
+
+    /// Processes all the client requests waiting to be processed, then returns.
+    ///
+    /// This function executes very quickly, as each client requests that needs to be processed
+    /// is processed in a separate thread.
+    #[inline]
+    pub fn poll(&self) {
+        for server in &self.servers {
+            while let Ok(Some(request)) = server.try_recv() {
+                self.process(request, server.server_addr());
+            }
+        }
+
+        // The requests that the threads of `forwarded` have already taken from the listeners.
+        if self.servers.len() > 1 {
+            while let Ok((request, local_addr)) = self.forwarded(|rx| rx.try_recv()) {
+                self.process(request, local_addr);
+            }
+        }
+    }
+
+    /// Creates a new thread for the server that can be gracefully stopped later.
+    ///
+    /// This function returns a tuple of a `JoinHandle` and a `Sender`.
+    /// You must call `JoinHandle::join()` otherwise the server will not run until completion.
+    /// The server can be stopped at will by sending it an empty `()` message from another thread.
+    /// There may be a maximum of a 1 second delay between sending the stop message and the server
+    /// stopping. This delay may be shortened in future.
+    ///
+    /// ```no_run
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap();
+    /// println!("Listening on {:?}", server.server_addr());
+    /// let (handle, sender) = server.stoppable();
+    ///
+    /// // Stop the server in 3 seconds
+    /// thread::spawn(move || {
+    ///     thread::sleep(Duration::from_secs(3));
+    ///     sender.send(()).unwrap();
+    /// });
+    ///
+    /// // Block the main thread until the server is stopped
+    /// handle.join().unwrap();
+    /// ```
+    #[inline]
+    pub fn stoppable(self) -> (thread::JoinHandle<()>, mpsc::Sender<()>) {
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            while rx.try_recv().is_err() {
+                // In order to reduce CPU load wait 1s for a recv before looping again
+                while let Some((request, local_addr)) = self.recv_timeout(Duration::from_secs(1)) {
+                    self.process(request, local_addr);
+                }
+            }
+            self.shut_down();
+        });
+
+        (handle, tx)
+    }
+
+    /// Same as `poll()` but blocks for at most `duration` before returning.
+    ///
+    /// This function can be used implement a custom server loop in a more CPU-efficient manner
+    /// than calling `poll`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rouille::Server;
+    /// use rouille::Response;
+    ///
+    /// let server = Server::new("localhost:0", |request| {
+    ///     Response::text("hello world")
+    /// }).unwrap();
+    /// println!("Listening on {:?}", server.server_addr());
+    ///
+    /// loop {
+    ///     server.poll_timeout(std::time::Duration::from_millis(100));
+    /// }
+    /// ```
+    #[inline]
+    pub fn poll_timeout(&self, dur: std::time::Duration) {
+        while let Some((request, local_addr)) = self.recv_timeout(dur) {
+            self.process(request, local_addr);
+        }
+    }
+
+    // Internal function that waits for at most `dur` for a request on any of the listeners.
+    fn recv_timeout(&self, dur: Duration) -> Option<(tiny_http::Request, SocketAddr)> {
+        if self.servers.len() == 1 {
+            let server = &self.servers[0];
+            return server
+                .recv_timeout(dur)
+                .ok()
+                .and_then(|r| r)
+                .map(|r| (r, server.server_addr()));
+        }
+
+        self.forwarded(|rx| rx.recv_timeout(dur).ok())
+    }
+
+    /// Waits for all in-flight requests to be processed. This is useful for implementing a graceful
+    /// shutdown.
+    ///
+    /// Note: new connections may still be accepted while we wait, and this function does not guarantee
+    /// to wait for those new requests. To implement a graceful shutdown or a clean rolling-update,
+    /// the following approach should be used:
+    ///
+    /// 1) Stop routing requests to this server. For a rolling update, requests should be routed
+    ///    to the new instance. This logic typically sits outside of your application.
+    ///
+    /// 2) Drain the queue of incoming connections by calling `poll_timeout` with a short timeout.
+    ///
+    /// 3) Wait for in-flight requests to be processed by using this method.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use rouille::Server;
+    /// #
     /// # let server = Server::new("", |_| unimplemented!()).unwrap();
     /// # fn is_stopping() -> bool { unimplemented!() }
     ///
@@ -533,116 +1496,557 @@ where
         self.executor.join();
     }
 
+    // Internal function, called when the server stops.
+    fn shut_down(&self) {
+        if let Some(ref health) = self.health {
+            health.set_shutting_down();
+        }
+        self.subsystems.shutdown();
+    }
+
     // Internal function, called when we got a request from tiny-http that needs to be processed.
-    fn process(&self, request: tiny_http::Request) {
+    fn process(&self, request: tiny_http::Request, local_addr: SocketAddr) {
         // We spawn a thread so that requests are processed in parallel.
         let handler = self.handler.clone();
-        self.executor.execute(|| {
-            // Small helper struct that makes it possible to put
-            // a `tiny_http::Request` inside a `Box<Read>`.
-            struct RequestRead(Arc<Mutex<Option<tiny_http::Request>>>);
-            impl Read for RequestRead {
-                #[inline]
-                fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-                    self.0
-                        .lock()
-                        .unwrap()
-                        .as_mut()
-                        .unwrap()
-                        .as_reader()
-                        .read(buf)
+        let panic_handler = self.panic_handler.clone();
+        let error_handler = self.error_handler.clone();
+        let on_length_mismatch = self.on_length_mismatch.clone();
+        let random = self.random.clone();
+        let automatic_head = self.automatic_head;
+        let allow_trace = self.allow_trace;
+        let custom_methods = self.custom_methods.clone();
+        let reject_unknown_methods = self.reject_unknown_methods;
+        let expect_continue = self.expect_continue.clone();
+        let header_limits = self.header_limits;
+        let header_rejections = self.header_rejections.clone();
+        let request_timeout = self.request_timeout;
+        let respond_at_deadline = self.respond_at_deadline;
+        let trust_timeout_header = self.trust_timeout_header.clone();
+        let real_ip = self.real_ip.clone();
+        let upload_limit = self.upload_limit.clone();
+        let keep_alive = self.keep_alive.clone();
+        #[cfg(feature = "metrics")]
+        let metrics = self.metrics.clone();
+        let start = Instant::now();
+        self.executor.execute(move || {
+            // Building the `Request` object.
+            let (rouille_request, tiny_http_request) = {
+                let (rouille_request, tiny_http_request) =
+                    request_from_tiny_http(request, Some(local_addr));
+                if let Some(random) = random {
+                    rouille_request.extensions().insert(random);
+                }
+                if !automatic_head {
+                    rouille_request.extensions().insert(AutomaticHead(false));
+                }
+                if !custom_methods.is_empty() {
+                    rouille_request
+                        .extensions()
+                        .insert(CustomMethods(custom_methods));
+                }
+                if let Some(deadline) = deadline::deadline(
+                    &rouille_request,
+                    start,
+                    request_timeout,
+                    trust_timeout_header.as_deref(),
+                ) {
+                    rouille_request.extensions().insert(deadline);
+                }
+                if let Some(real_ip) = real_ip {
+                    let client = real_ip.client(&rouille_request);
+                    rouille_request.extensions().insert(client);
+                }
+                if let Some(ref upload_limit) = upload_limit {
+                    upload_limit.apply(&rouille_request);
+                }
+                (rouille_request, tiny_http_request)
+            };
+
+            // Dropped once the response has been sent.
+            let _watchdog = match rouille_request.deadline() {
+                Some(deadline) if respond_at_deadline => {
+                    Some(watch_deadline(tiny_http_request.clone(), deadline))
+                }
+                _ => None,
+            };
+
+            #[cfg(feature = "metrics")]
+            let pending = metrics.map(|metrics| metrics.begin(&rouille_request, start));
+
+            // Some methods are answered by the server itself, depending on its configuration.
+            let rejected = if header_limits.exceeded(&rouille_request.headers) {
+                header_rejections.fetch_add(1, Ordering::Relaxed);
+                Some(Response::text("Request Header Fields Too Large").with_status_code(431))
+            } else if !allow_trace && rouille_request.method() == "TRACE" {
+                Some(Response::method_not_allowed(&[
+                    "GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH",
+                ]))
+            } else if reject_unknown_methods && !CustomMethods::is_known(&rouille_request) {
+                Some(Response::text("Not Implemented").with_status_code(501))
+            } else {
+                match expect_continue {
+                    Some(ref check) if rouille_request.expects_continue() => {
+                        panic::catch_unwind(AssertUnwindSafe(|| check(&rouille_request)))
+                            .unwrap_or_else(|_| Some(internal_server_error()))
+                    }
+                    _ => None,
+                }
+            };
+
+            // Calling the handler ; this most likely takes a lot of time.
+            // If the handler panics, we build a response with the panic handler, or a dummy one.
+            let rouille_response = {
+                // The `rouille_request` is only passed to the panic handler after a panic, which
+                // can't observe any broken invariant of the request itself.
+                let res = match rejected {
+                    Some(mut response) => {
+                        response.data.default_error = true;
+                        Ok(response)
+                    }
+                    None => panic::catch_unwind(AssertUnwindSafe(|| handler(&rouille_request))),
+                };
+
+                match res {
+                    Ok(r) => r,
+                    Err(payload) => panic_handler
+                        .and_then(|panic_handler| {
+                            // If the panic handler panics as well, fall back to the dummy response.
+                            panic::catch_unwind(AssertUnwindSafe(|| {
+                                panic_handler(&rouille_request, &*payload)
+                            }))
+                            .ok()
+                        })
+                        .unwrap_or_else(internal_server_error),
+                }
+            };
+
+            let rouille_response = match upload_limit {
+                Some(ref upload_limit) => upload_limit.check(&rouille_request, rouille_response),
+                None => rouille_response,
+            };
+
+            // Giving the error handler a chance to replace error responses without a body.
+            let rouille_response = match error_handler {
+                Some(ref error_handler)
+                    if rouille_response.status_code >= 400
+                        && (rouille_response.data.is_empty()
+                            || rouille_response.data.default_error) =>
+                {
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        error_handler(&rouille_request, rouille_response)
+                    }))
+                    .unwrap_or_else(|_| internal_server_error())
+                }
+                _ => rouille_response,
+            };
+
+            let rouille_response =
+                keep_alive.apply(rouille_request.remote_addr, start, rouille_response);
+
+            #[cfg(feature = "metrics")]
+            let rouille_response = match pending {
+                Some(pending) => pending.finish(rouille_response),
+                None => rouille_response,
+            };
+
+            respond_tiny_http(
+                &tiny_http_request,
+                &rouille_request,
+                rouille_response,
+                on_length_mismatch,
+            );
+        });
+    }
+}
+
+// Connection of a request received by tiny-http, shared between the `Request`, its body and the
+// server.
+enum TinyHttpConnection {
+    // The request of tiny-http, through which the body is read and the response is sent.
+    // `body_read` is true once the whole body has been read.
+    Request {
+        request: tiny_http::Request,
+        body_read: bool,
+    },
+    // The writer of the request, after an interim response has been written to it. Getting the
+    // writer consumes the request of tiny-http, so the rest of the body is buffered beforehand.
+    Raw {
+        writer: Box<dyn Write + Send>,
+        body: Cursor<Vec<u8>>,
+        head: bool,
+    },
+    // The response has been sent.
+    Done,
+}
+
+impl TinyHttpConnection {
+    // Writes an interim response to the client. Returns `false` if the client doesn't support
+    // them, if the rest of the body is too large to be buffered, or if the response has already
+    // been sent.
+    fn send_interim(&mut self, status_code: u16, headers: &[(&str, &str)]) -> IoResult<bool> {
+        if let TinyHttpConnection::Request {
+            ref mut request,
+            body_read,
+        } = *self
+        {
+            let version = request.http_version();
+            if (version.0, version.1) < (1, 1) {
+                return Ok(false);
+            }
+
+            // The rest of the body is only buffered if its size is known to be small, otherwise
+            // a client could make the server hold a whole upload in memory.
+            let small = request
+                .body_length()
+                .map_or(false, |len| len <= INTERIM_BODY_LIMIT);
+            if !body_read && !small {
+                return Ok(false);
+            }
+
+            // Reading the body sends `100 Continue` first if the client expects it. Once the body
+            // has been read, the reader must not be used again: a chunked decoder would wait for
+            // another chunk from the client.
+            let mut body = Vec::new();
+            if !body_read {
+                request.as_reader().read_to_end(&mut body)?;
+            }
+            *self = match mem::replace(self, TinyHttpConnection::Done) {
+                TinyHttpConnection::Request { request, .. } => TinyHttpConnection::Raw {
+                    head: *request.method() == tiny_http::Method::Head,
+                    writer: request.into_writer(),
+                    body: Cursor::new(body),
+                },
+                _ => unreachable!(),
+            };
+        }
+
+        match *self {
+            TinyHttpConnection::Raw { ref mut writer, .. } => {
+                let reason = tiny_http::StatusCode(status_code).default_reason_phrase();
+                write!(writer, "HTTP/1.1 {} {}\r\n", status_code, reason)?;
+                for &(key, value) in headers {
+                    if tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes()).is_ok() {
+                        write!(writer, "{}: {}\r\n", key, value)?;
+                    }
+                }
+                writer.write_all(b"\r\n")?;
+                writer.flush()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+// Maximum size of the body that is buffered to send an interim response before the body has been
+// read.
+const INTERIM_BODY_LIMIT: usize = 64 * 1024;
+
+// Stored in the extensions of the requests received by the server, so that they can send interim
+// responses.
+struct InterimSender(Arc<Mutex<TinyHttpConnection>>);
+
+// Small helper struct that makes it possible to put a `tiny_http::Request` inside a `Box<Read>`.
+struct RequestRead(Arc<Mutex<TinyHttpConnection>>);
+
+impl Read for RequestRead {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match *self.0.lock().unwrap() {
+            TinyHttpConnection::Request {
+                ref mut request,
+                ref mut body_read,
+            } => {
+                let num = request.as_reader().read(buf)?;
+                if num == 0 && !buf.is_empty() {
+                    *body_read = true;
                 }
+                Ok(num)
             }
+            TinyHttpConnection::Raw { ref mut body, .. } => body.read(buf),
+            TinyHttpConnection::Done => Ok(0),
+        }
+    }
+}
+
+// Builds a `Request` from a request of tiny-http. The tiny-http request is moved to the returned
+// mutex, from which the body of the `Request` is read, and which `respond_tiny_http` uses to
+// answer it.
+fn request_from_tiny_http(
+    request: tiny_http::Request,
+    local_addr: Option<SocketAddr>,
+) -> (Request, Arc<Mutex<TinyHttpConnection>>) {
+    let url = request.url().to_owned();
+    let method = request.method().as_str().to_owned();
+    let headers = request
+        .headers()
+        .iter()
+        .map(|h| (h.field.to_string(), h.value.clone().into()))
+        .collect();
+    let remote_addr = *request.remote_addr();
+    let https = request.secure();
+
+    let body_read = request.body_length() == Some(0);
+    let tiny_http_request = Arc::new(Mutex::new(TinyHttpConnection::Request {
+        request,
+        body_read,
+    }));
+    let data = Arc::new(Mutex::new(Some(
+        Box::new(RequestRead(tiny_http_request.clone())) as Box<_>,
+    )));
+
+    let rouille_request = Request {
+        url,
+        method,
+        headers,
+        https,
+        data,
+        remote_addr,
+        local_addr,
+        extensions: Default::default(),
+    };
+    {
+        let mut extensions = rouille_request.extensions();
+        extensions.insert(InterimSender(tiny_http_request.clone()));
+        extensions.insert(BytesWritten(Arc::new(AtomicU64::new(0))));
+    }
+    (rouille_request, tiny_http_request)
+}
+
+// Sends the response to a request built with `request_from_tiny_http`.
+fn respond_tiny_http(
+    tiny_http_request: &Mutex<TinyHttpConnection>,
+    rouille_request: &Request,
+    rouille_response: Response,
+    on_length_mismatch: Option<Arc<MismatchFn>>,
+) {
+    // The bytes written on the connection are counted for the logs and the metrics.
+    let bytes_written = rouille_request.bytes_written_counter();
+    let counted = |writer: Box<dyn Write + Send>| -> Box<dyn Write + Send> {
+        match bytes_written {
+            Some(ref counter) => Box::new(CountedWrite {
+                inner: writer,
+                counter: counter.clone(),
+            }),
+            None => writer,
+        }
+    };
+
+    let tiny_http_request = match take_connection(tiny_http_request) {
+        TinyHttpConnection::Request { request, .. } => request,
+        TinyHttpConnection::Raw { writer, head, .. } => {
+            // An interim response was sent, so we have to write the final response ourselves.
+            // The connection can't be upgraded anymore.
+            let _ = send_raw(counted(writer), head, rouille_response);
+            return;
+        }
+        TinyHttpConnection::Done => return,
+    };
+
+    let version = tiny_http_request.http_version().clone();
+    let head = *tiny_http_request.method() == tiny_http::Method::Head;
+    if rouille_response.upgrade.is_none() && (version.0, version.1) >= (1, 1) {
+        if rouille_response.data.unbuffered && !head {
+            let writer = counted(tiny_http_request.into_writer());
+            let _ = send_unbuffered(writer, rouille_response, true);
+            return;
+        }
+        // tiny-http doesn't let us set the `Connection` header. It closes HTTP/1.0
+        // connections by itself.
+        if rouille_response.closes_connection() {
+            let writer = counted(tiny_http_request.into_writer());
+            let _ = send_raw(writer, head, rouille_response);
+            return;
+        }
+    }
 
-            // Building the `Request` object.
-            let tiny_http_request;
-            let rouille_request = {
-                let url = request.url().to_owned();
-                let method = request.method().as_str().to_owned();
-                let headers = request
-                    .headers()
-                    .iter()
-                    .map(|h| (h.field.to_string(), h.value.clone().into()))
-                    .collect();
-                let remote_addr = *request.remote_addr();
-
-                tiny_http_request = Arc::new(Mutex::new(Some(request)));
-                let data = Arc::new(Mutex::new(Some(
-                    Box::new(RequestRead(tiny_http_request.clone())) as Box<_>,
-                )));
-
-                Request {
-                    url,
-                    method,
-                    headers,
-                    https: false,
-                    data,
-                    remote_addr,
-                }
-            };
+    let upgrade_header = rouille_response
+        .headers
+        .iter()
+        .rev()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Upgrade"))
+        .map(|(_, value)| value.clone())
+        .unwrap_or_else(|| "".into());
+    let upgrade = rouille_response.upgrade;
+
+    // writing the response
+    let (mut res_data, res_len) = rouille_response.data.into_reader_and_size();
+    if let (Some(declared), Some(on_mismatch)) = (res_len, on_length_mismatch) {
+        res_data = Box::new(LengthCheck::new(
+            res_data,
+            declared,
+            rouille_request.method().to_owned(),
+            rouille_request.raw_url().to_owned(),
+            on_mismatch,
+        ));
+    }
+    let response = tiny_http_response(
+        rouille_response.status_code,
+        rouille_response.headers,
+        res_data,
+        res_len,
+    );
+
+    if let Some(mut upgrade) = upgrade {
+        let socket = tiny_http_request.upgrade(&upgrade_header, response);
+        upgrade.build(socket);
+    } else {
+        // Does the same as `respond`, but through a writer that counts the bytes. We don't
+        // really care if we fail to send the response to the client, as there's nothing we can
+        // do anyway.
+        let headers = tiny_http_request.headers().to_vec();
+        let mut writer = counted(tiny_http_request.into_writer());
+        let _ = response
+            .raw_print(&mut writer, version, &headers, head, None)
+            .and_then(|()| writer.flush());
+    }
+}
 
-            // Calling the handler ; this most likely takes a lot of time.
-            // If the handler panics, we build a dummy response.
-            let mut rouille_response = {
-                // We don't use the `rouille_request` anymore after the panic, so it's ok to assert
-                // it's unwind safe.
-                let rouille_request = AssertUnwindSafe(rouille_request);
-                let res = panic::catch_unwind(move || {
-                    let rouille_request = rouille_request;
-                    handler(&rouille_request)
-                });
+// Takes the connection of a request to answer it, leaving `TinyHttpConnection::Done` instead.
+fn take_connection(connection: &Mutex<TinyHttpConnection>) -> TinyHttpConnection {
+    // The mutex is poisoned if the handler panicked while reading the body, which doesn't
+    // prevent us from answering.
+    let mut connection = match connection.lock() {
+        Ok(rq) => rq,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    mem::replace(&mut *connection, TinyHttpConnection::Done)
+}
 
-                match res {
-                    Ok(r) => r,
-                    Err(_) => Response::html(
-                        "<h1>Internal Server Error</h1>\
-                                        <p>An internal error has occurred on the server.</p>",
-                    )
-                    .with_status_code(500),
-                }
-            };
+// Writes the final response of a request whose connection was taken from tiny-http.
+fn send_raw(writer: Box<dyn Write + Send>, head: bool, response: Response) -> IoResult<()> {
+    let body = !head && response.status_code != 204 && response.status_code != 304;
+    send_unbuffered(writer, response, body)
+}
 
-            // writing the response
-            let (res_data, res_len) = rouille_response.data.into_reader_and_size();
-            let mut response = tiny_http::Response::empty(rouille_response.status_code)
-                .with_data(res_data, res_len);
+// Answers the request with a 503 error if it hasn't been answered at `deadline`. Dropping the
+// returned sender stops the thread.
+fn watch_deadline(
+    connection: Arc<Mutex<TinyHttpConnection>>,
+    deadline: Instant,
+) -> mpsc::Sender<()> {
+    let (sender, receiver) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if receiver.recv_timeout(timeout) != Err(mpsc::RecvTimeoutError::Timeout) {
+            return;
+        }
 
-            let mut upgrade_header = "".into();
+        let response = Response::text("Service Unavailable").with_status_code(503);
+        match take_connection(&connection) {
+            TinyHttpConnection::Request { request: rq, .. } => {
+                let (data, len) = response.data.into_reader_and_size();
+                let response =
+                    tiny_http_response(response.status_code, response.headers, data, len);
+                let _ = rq.respond(response);
+            }
+            TinyHttpConnection::Raw { writer, head, .. } => {
+                let _ = send_raw(writer, head, response);
+            }
+            TinyHttpConnection::Done => (),
+        }
+    });
+    sender
+}
 
-            for (key, value) in rouille_response.headers {
-                if key.eq_ignore_ascii_case("Content-Length") {
-                    continue;
-                }
+// Builds a response of tiny-http. The `Content-Length` header is computed by tiny-http, and the
+// `Upgrade` header is passed separately when upgrading.
+pub(crate) fn tiny_http_response(
+    status_code: u16,
+    headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    data: Box<dyn Read + Send>,
+    data_length: Option<usize>,
+) -> tiny_http::ResponseBox {
+    let mut response = tiny_http::Response::empty(status_code).with_data(data, data_length);
+
+    for (key, value) in headers {
+        if key.eq_ignore_ascii_case("Content-Length") || key.eq_ignore_ascii_case("Upgrade") {
+            continue;
+        }
 
-                if key.eq_ignore_ascii_case("Upgrade") {
-                    upgrade_header = value;
-                    continue;
-                }
+        if let Ok(header) = tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes()) {
+            response.add_header(header);
+        } else {
+            // TODO: ?
+        }
+    }
 
-                if let Ok(header) = tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes())
-                {
-                    response.add_header(header);
-                } else {
-                    // TODO: ?
-                }
-            }
+    response
+}
 
-            if let Some(ref mut upgrade) = rouille_response.upgrade {
-                let trq = tiny_http_request.lock().unwrap().take().unwrap();
-                let socket = trq.upgrade(&upgrade_header, response);
-                upgrade.build(socket);
-            } else {
-                // We don't really care if we fail to send the response to the client, as there's
-                // nothing we can do anyway.
-                let _ = tiny_http_request
-                    .lock()
-                    .unwrap()
-                    .take()
-                    .unwrap()
-                    .respond(response);
-            }
-        });
+/// Answers a request received by a tiny-http server that isn't managed by rouille, by calling
+/// `handler` with the corresponding `Request` and sending its `Response`.
+///
+/// This makes it possible to use the routing, the input parsing and the middlewares of rouille
+/// with an existing tiny-http server or a custom accept loop. Contrary to `Server`, the handler
+/// is called on the current thread, panics are not caught, and the options of `Server` such as
+/// the header limits don't apply. `Request::local_addr` returns `None` for these requests.
+///
+/// # Example
+///
+/// ```no_run
+/// use rouille::Response;
+/// use rouille::tiny_http;
+///
+/// let server = tiny_http::Server::http("0.0.0.0:8000").unwrap();
+/// for request in server.incoming_requests() {
+///     rouille::handle_tiny_http(request, |request| {
+///         Response::text(format!("hello from {}", request.url()))
+///     });
+/// }
+/// ```
+pub fn handle_tiny_http<F>(request: tiny_http::Request, handler: F)
+where
+    F: FnOnce(&Request) -> Response,
+{
+    let (rouille_request, tiny_http_request) = request_from_tiny_http(request, None);
+    let response = handler(&rouille_request);
+    respond_tiny_http(&tiny_http_request, &rouille_request, response, None);
+}
+
+// Sends a response with chunked transfer encoding, flushing each chunk as soon as it has been
+// read from the body. tiny-http only sends a chunk once it has buffered 8 kiB of data. If `body`
+// is false, only the status line and the headers are sent.
+fn send_unbuffered(
+    mut writer: Box<dyn Write + Send>,
+    response: Response,
+    body: bool,
+) -> IoResult<()> {
+    let reason = tiny_http::StatusCode(response.status_code).default_reason_phrase();
+    write!(writer, "HTTP/1.1 {} {}\r\n", response.status_code, reason)?;
+    for (key, value) in &response.headers {
+        if key.eq_ignore_ascii_case("Content-Length")
+            || key.eq_ignore_ascii_case("Transfer-Encoding")
+            || tiny_http::Header::from_bytes(key.as_bytes(), value.as_bytes()).is_err()
+        {
+            continue;
+        }
+        write!(writer, "{}: {}\r\n", key, value)?;
+    }
+    if !body {
+        writer.write_all(b"\r\n")?;
+        return writer.flush();
     }
+    writer.write_all(b"Transfer-Encoding: chunked\r\n\r\n")?;
+    writer.flush()?;
+
+    let (mut data, _) = response.data.into_reader_and_size();
+    let mut buffer = vec![0; 8192];
+    loop {
+        let num = match data.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(num) => num,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        write!(writer, "{:x}\r\n", num)?;
+        writer.write_all(&buffer[..num])?;
+        writer.write_all(b"\r\n")?;
+        writer.flush()?;
+    }
+
+    writer.write_all(b"0\r\n\r\n")?;
+    writer.flush()
 }
 
 /// Trait for objects that can take ownership of a raw connection to the client data.
@@ -664,6 +2068,8 @@ pub struct Request {
     https: bool,
     data: Arc<Mutex<Option<Box<dyn Read + Send>>>>,
     remote_addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    extensions: Arc<Mutex<Extensions>>,
 }
 
 impl fmt::Debug for Request {
@@ -674,6 +2080,7 @@ impl fmt::Debug for Request {
             .field("headers", &self.headers)
             .field("https", &self.https)
             .field("remote_addr", &self.remote_addr)
+            .field("local_addr", &self.local_addr)
             .finish()
     }
 }
@@ -703,6 +2110,8 @@ impl Request {
             data,
             headers,
             remote_addr,
+            local_addr: None,
+            extensions: Default::default(),
         }
     }
 
@@ -727,6 +2136,8 @@ impl Request {
             data,
             headers,
             remote_addr: from,
+            local_addr: None,
+            extensions: Default::default(),
         }
     }
 
@@ -754,6 +2165,8 @@ impl Request {
             data,
             headers,
             remote_addr,
+            local_addr: None,
+            extensions: Default::default(),
         }
     }
 
@@ -778,6 +2191,8 @@ impl Request {
             data,
             headers,
             remote_addr: from,
+            local_addr: None,
+            extensions: Default::default(),
         }
     }
 
@@ -805,6 +2220,7 @@ impl Request {
 
         // TODO: url-encoded characters in the prefix are not implemented
         assert!(self.url.starts_with(prefix));
+        self.remember_received_url();
         Some(Request {
             method: self.method.clone(),
             url: self.url[prefix.len()..].to_owned(),
@@ -812,11 +2228,109 @@ impl Request {
             https: self.https,
             data: self.data.clone(),
             remote_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            extensions: self.extensions.clone(),
+        })
+    }
+
+    /// Same as `remove_prefix`, except that the prefix must be followed by a `/` or be the whole
+    /// path, and that the URL of the new request always starts with a `/`.
+    ///
+    /// For example with the prefix `/api`, the URL `/api/users` becomes `/users`, the URL `/api`
+    /// becomes `/`, and the URL `/apis` doesn't match.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/api?page=2", vec![], vec![]);
+    /// assert_eq!(request.strip_path_prefix("/api").unwrap().raw_url(), "/?page=2");
+    /// assert!(request.strip_path_prefix("/ap").is_none());
+    /// ```
+    pub fn strip_path_prefix(&self, prefix: &str) -> Option<Request> {
+        let prefix = prefix.trim_end_matches('/');
+        if !self.url.starts_with(prefix) {
+            return None;
+        }
+
+        let rest = &self.url[prefix.len()..];
+        let url = match rest.chars().next() {
+            Some('/') => rest.to_owned(),
+            None | Some('?') => format!("/{}", rest),
+            Some(_) => return None,
+        };
+
+        self.remember_received_url();
+        Some(Request {
+            method: self.method.clone(),
+            url,
+            headers: self.headers.clone(),
+            https: self.https,
+            data: self.data.clone(),
+            remote_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            extensions: self.extensions.clone(),
         })
     }
 
+    // Builds a copy of this request with another URL, sharing its body and its extensions.
+    pub(crate) fn with_raw_url(&self, url: String) -> Request {
+        self.remember_received_url();
+        Request {
+            method: self.method.clone(),
+            url,
+            headers: self.headers.clone(),
+            https: self.https,
+            data: self.data.clone(),
+            remote_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            extensions: self.extensions.clone(),
+        }
+    }
+
+    // Stores the URL of this request in the extensions before a copy with another URL is built,
+    // unless the URL of an earlier request was already stored.
+    fn remember_received_url(&self) {
+        let mut extensions = self.extensions();
+        if extensions.get::<ReceivedUrl>().is_none() {
+            extensions.insert(ReceivedUrl(self.url.clone()));
+        }
+    }
+
+    // Returns the raw URL requested by the client, before any prefix was removed or the URL was
+    // rewritten. The links that the browser resolves are relative to this URL.
+    pub(crate) fn received_raw_url(&self) -> String {
+        match self.extensions().get::<ReceivedUrl>() {
+            Some(url) => url.0.clone(),
+            None => self.url.clone(),
+        }
+    }
+
+    // Builds a copy of this request whose body is `data`. The extensions are shared with this
+    // request if `share_extensions` is true, and are empty otherwise.
+    pub(crate) fn with_data(&self, data: Vec<u8>, share_extensions: bool) -> Request {
+        Request {
+            method: self.method.clone(),
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            https: self.https,
+            data: Arc::new(Mutex::new(Some(Box::new(Cursor::new(data)) as Box<_>))),
+            remote_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            extensions: if share_extensions {
+                self.extensions.clone()
+            } else {
+                Default::default()
+            },
+        }
+    }
+
     /// Returns `true` if the request uses HTTPS, and `false` if it uses HTTP.
     ///
+    /// Behind a proxy of [`Server::real_ip`](struct.Server.html#method.real_ip) whose scheme is
+    /// trusted, this is the scheme that the client used to reach the proxy.
+    ///
     /// # Example
     ///
     /// ```
@@ -833,7 +2347,24 @@ impl Request {
     /// ```
     #[inline]
     pub fn is_secure(&self) -> bool {
-        self.https
+        match self
+            .extensions()
+            .get::<Client>()
+            .and_then(|client| client.secure)
+        {
+            Some(secure) => secure,
+            None => self.https,
+        }
+    }
+
+    /// Returns the scheme of the request, `"https"` or `"http"`, according to `is_secure`.
+    #[inline]
+    pub fn scheme(&self) -> &'static str {
+        if self.is_secure() {
+            "https"
+        } else {
+            "http"
+        }
     }
 
     /// Returns the method of the request (`GET`, `POST`, etc.).
@@ -905,6 +2436,167 @@ impl Request {
             .into_owned()
     }
 
+    /// Returns the absolute URL requested by the client, built from the scheme of the connection,
+    /// the `Host` header and the raw URL.
+    ///
+    /// Returns `None` if the request has no valid `Host` header, unless the client sent an
+    /// absolute URL in the request line, as it does when talking to a proxy.
+    ///
+    /// The returned `Url` gives access to the individual components of the URL, such as the port
+    /// or the query pairs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/a/b?c=d", vec![
+    ///     ("Host".to_owned(), "example.com:8080".to_owned())
+    /// ], vec![]);
+    ///
+    /// let url = request.absolute_url().unwrap();
+    /// assert_eq!(url.as_str(), "http://example.com:8080/a/b?c=d");
+    /// assert_eq!(url.port_or_known_default(), Some(8080));
+    /// assert_eq!(url.query_pairs().next().unwrap(), ("c".into(), "d".into()));
+    /// ```
+    pub fn absolute_url(&self) -> Option<url::Url> {
+        if !self.url.starts_with('/') {
+            return url::Url::parse(&self.url).ok();
+        }
+
+        let host = self.header("Host")?;
+        // Make sure that the `Host` header can't change the meaning of the rest of the URL.
+        if host.is_empty()
+            || host
+                .chars()
+                .any(|c| "/?#@\\".contains(c) || c.is_whitespace() || c.is_control())
+        {
+            return None;
+        }
+
+        url::Url::parse(&format!("{}://{}{}", self.scheme(), host, self.url)).ok()
+    }
+
+    /// Returns the host requested by the client, without the port.
+    ///
+    /// Internationalized domain names are decoded from punycode.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/", vec![
+    ///     ("Host".to_owned(), "xn--caf-dma.example:80".to_owned())
+    /// ], vec![]);
+    /// assert_eq!(request.host().unwrap(), "café.example");
+    /// ```
+    pub fn host(&self) -> Option<String> {
+        let url = self.absolute_url()?;
+        match url.host()? {
+            url::Host::Domain(domain) => Some(url::quirks::domain_to_unicode(domain)),
+            host => Some(host.to_string()),
+        }
+    }
+
+    /// Returns the value captured under `name` from the host by the pattern of
+    /// [`vhosts!`](macro.vhosts.html) that matched this request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{match_host, Request};
+    ///
+    /// let request = Request::fake_http("GET", "/", vec![], vec![]);
+    /// request.extensions().insert(match_host("{tenant}.example.com", "acme.example.com").unwrap());
+    /// assert_eq!(request.host_param("tenant"), Some("acme".to_owned()));
+    /// ```
+    pub fn host_param(&self, name: &str) -> Option<String> {
+        self.extensions()
+            .get::<HostParams>()
+            .and_then(|params| params.get(name))
+            .map(|value| value.to_owned())
+    }
+
+    /// Returns the decoded segments of the path of the URL.
+    ///
+    /// Contrary to splitting the result of `url()`, an encoded `/` such as in `a%2Fb` doesn't
+    /// count as a separator.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/files/a%2Fb/c%20d?e", vec![], vec![]);
+    /// assert_eq!(request.path_segments(), vec!["files", "a/b", "c d"]);
+    /// ```
+    pub fn path_segments(&self) -> Vec<String> {
+        let path = self.raw_url().split('?').next().unwrap_or("");
+        let path = if path.starts_with('/') {
+            &path[1..]
+        } else {
+            path
+        };
+        if path.is_empty() {
+            return Vec::new();
+        }
+
+        path.split('/')
+            .map(|s| {
+                percent_encoding::percent_decode(s.as_bytes())
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    /// Resolves a URL reference, such as `../foo` or `/bar?baz`, against the URL of the request.
+    ///
+    /// Returns `None` if `absolute_url()` returns `None` or if `reference` is invalid. Note that
+    /// the result can point to another website if `reference` is an absolute URL. Use
+    /// `resolve_local_url` when `reference` comes from the client.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/a/b", vec![
+    ///     ("Host".to_owned(), "example.com".to_owned())
+    /// ], vec![]);
+    /// let url = request.resolve_url("../c").unwrap();
+    /// assert_eq!(url.as_str(), "http://example.com/c");
+    /// ```
+    pub fn resolve_url(&self, reference: &str) -> Option<url::Url> {
+        self.absolute_url()?.join(reference).ok()
+    }
+
+    /// Same as `resolve_url`, but returns `None` if the resolved URL doesn't have the same origin
+    /// as the request. This makes it possible to redirect to a URL provided by the client, for
+    /// example after a login, without creating an open redirect.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/login?next=//evil.com", vec![
+    ///     ("Host".to_owned(), "example.com".to_owned())
+    /// ], vec![]);
+    /// assert!(request.resolve_local_url("/account").is_some());
+    /// assert!(request.resolve_local_url("//evil.com").is_none());
+    /// ```
+    pub fn resolve_local_url(&self, reference: &str) -> Option<url::Url> {
+        let base = self.absolute_url()?;
+        let resolved = base.join(reference).ok()?;
+        if resolved.origin() == base.origin() {
+            Some(resolved)
+        } else {
+            None
+        }
+    }
+
     /// Returns the value of a GET parameter.
     /// TODO: clumbsy
     pub fn get_param(&self, param_name: &str) -> Option<String> {
@@ -929,23 +2621,190 @@ impl Request {
         )
     }
 
+    /// Returns the decoded values of all the GET parameters named `param_name`, in the order of
+    /// the query string. A parameter without a value, such as `tag` in `?tag&page=2`, gives an
+    /// empty string.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/search?tag=a&page=2&tag=b+c", vec![], vec![]);
+    /// assert_eq!(request.get_params("tag"), vec!["a", "b c"]);
+    /// assert!(request.get_params("sort").is_empty());
+    /// ```
+    pub fn get_params<'a>(&'a self, param_name: &str) -> Vec<Cow<'a, str>> {
+        self.query_params()
+            .filter(|param| param.0 == param_name)
+            .map(|param| param.1.unwrap_or(Cow::Borrowed("")))
+            .collect()
+    }
+
+    /// Returns an iterator over the decoded names and values of the GET parameters, in the order
+    /// of the query string. See [`input::parse_query`](input/fn.parse_query.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/?flag&q=", vec![], vec![]);
+    /// let params: Vec<_> = request.query_params().collect();
+    /// assert_eq!(params, vec![("flag".into(), None), ("q".into(), Some("".into()))]);
+    /// ```
+    #[inline]
+    pub fn query_params<'a>(&'a self) -> input::QueryParams<'a> {
+        input::parse_query(self.raw_query_string())
+    }
+
     /// Returns the value of a header of the request.
     ///
-    /// Returns `None` if no such header could be found.
+    /// Returns `None` if no such header could be found.
+    #[inline]
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(key))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// Returns true if the client waits for a `100 Continue` interim response before sending the
+    /// body of the request, because of an `Expect: 100-continue` header.
+    ///
+    /// The interim response is sent when the body is read for the first time. See
+    /// [`Server::expect_continue`](struct.Server.html#method.expect_continue) to reject such
+    /// requests before their body is sent.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("PUT", "/upload",
+    ///                                  vec![("Expect".to_owned(), "100-continue".to_owned())],
+    ///                                  vec![]);
+    /// assert!(request.expects_continue());
+    /// ```
+    #[inline]
+    pub fn expects_continue(&self) -> bool {
+        self.header("Expect").map_or(false, |value| {
+            value.trim().eq_ignore_ascii_case("100-continue")
+        })
+    }
+
+    /// Sends an informational (`1xx`) response to the client, before the final response returned
+    /// by the handler.
+    ///
+    /// Returns `Ok(false)` if nothing was sent, because the request wasn't received by a rouille
+    /// server (for example it was built with `fake_http`), because the client uses HTTP/1.0,
+    /// because the body of the request hasn't been read and is too large, or because the final
+    /// response has already been sent.
+    ///
+    /// Sending an interim response requires taking the connection from tiny-http, so the rest of
+    /// the body of the request is read into memory first, and the final response is sent with
+    /// chunked transfer encoding. The connection can't be upgraded afterwards. To avoid holding
+    /// large uploads in memory, the body is only read this way if it has a `Content-Length` of at
+    /// most 64 kiB. Otherwise the handler must read the body before sending interim responses.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `status_code` isn't between 102 and 199. `100 Continue` is sent automatically
+    /// when the body is read, and `101 Switching Protocols` is sent when upgrading the connection.
+    pub fn send_informational(&self, status_code: u16, headers: &[(&str, &str)]) -> IoResult<bool> {
+        assert!((102..200).contains(&status_code));
+        let connection = match self.extensions().get::<InterimSender>() {
+            Some(sender) => sender.0.clone(),
+            None => return Ok(false),
+        };
+        let mut connection = connection.lock().unwrap_or_else(|err| err.into_inner());
+        connection.send_interim(status_code, headers)
+    }
+
+    /// Sends a `103 Early Hints` response with a `Link` header containing `links`, so that the
+    /// client can start fetching resources such as stylesheets while the handler produces the
+    /// final response.
+    ///
+    /// See `send_informational` for the cases where nothing is sent. The final response should
+    /// contain the same links.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::link::Link;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let style = Link::new("/style.css", "preload").param("as", "style");
+    ///     let _ = request.send_early_hints(std::slice::from_ref(&style));
+    ///
+    ///     // ... slow rendering ...
+    ///     Response::html("<link rel=\"stylesheet\" href=\"/style.css\">").with_link(style)
+    /// }
+    /// # let _ = handle(&Request::fake_http("GET", "/", vec![], vec![]));
+    /// ```
+    pub fn send_early_hints(&self, links: &[Link]) -> IoResult<bool> {
+        if links.is_empty() {
+            return Ok(false);
+        }
+        self.send_informational(103, &[("Link", &link::format(links))])
+    }
+
+    /// Returns a list of all the headers of the request.
+    #[inline]
+    pub fn headers(&self) -> HeadersIter {
+        HeadersIter {
+            iter: self.headers.iter(),
+        }
+    }
+
+    /// Gives access to the values attached to this request, indexed by their type.
+    ///
+    /// Middlewares can use this to pass data such as the authenticated user to the handler. The
+    /// values are shared with the requests returned by `remove_prefix`.
+    ///
+    /// > **Note**: The container is locked for as long as the returned guard is alive. Calling
+    /// > this method again before the guard is dropped will deadlock.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// struct RequestId(u64);
+    ///
+    /// let request = Request::fake_http("GET", "/", vec![], vec![]);
+    /// request.extensions().insert(RequestId(12));
+    ///
+    /// let id = request.extensions().get::<RequestId>().map(|id| id.0);
+    /// assert_eq!(id, Some(12));
+    /// ```
     #[inline]
-    pub fn header(&self, key: &str) -> Option<&str> {
-        self.headers
-            .iter()
-            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(key))
-            .map(|&(_, ref v)| &v[..])
+    pub fn extensions(&self) -> MutexGuard<'_, Extensions> {
+        match self.extensions.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
     }
 
-    /// Returns a list of all the headers of the request.
+    /// Returns the source of randomness to use to generate identifiers for this request.
+    ///
+    /// This is the source set with [`Server::random`](struct.Server.html#method.random), or
+    /// inserted in the extensions of the request, or else the randomness of the operating system.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/", vec![], vec![]);
+    /// let token = request.random().alphanumeric(32);
+    /// assert_eq!(token.len(), 32);
+    /// ```
     #[inline]
-    pub fn headers(&self) -> HeadersIter {
-        HeadersIter {
-            iter: self.headers.iter(),
-        }
+    pub fn random(&self) -> Random {
+        Random::of(self)
     }
 
     /// Returns the state of the `DNT` (Do Not Track) header.
@@ -976,10 +2835,31 @@ impl Request {
         }
     }
 
+    /// Returns the ID of the last server-sent event received by the client, when it reconnects
+    /// to an event stream.
+    ///
+    /// This is the value of the `Last-Event-ID` header. See [the `sse` module](sse/index.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("GET", "/events",
+    ///                                  vec![("Last-Event-ID".to_owned(), "42".to_owned())],
+    ///                                  vec![]);
+    /// assert_eq!(request.last_event_id(), Some("42"));
+    /// ```
+    #[inline]
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.header("Last-Event-ID")
+    }
+
     /// Returns the body of the request.
     ///
     /// The body can only be retrieved once. Returns `None` is the body has already been retreived
-    /// before.
+    /// before, unless it has been buffered with [`buffer_body`](#method.buffer_body), in which
+    /// case each call returns a new reader of the whole body.
     ///
     /// # Example
     ///
@@ -1004,13 +2884,120 @@ impl Request {
     /// }
     /// ```
     pub fn data(&self) -> Option<RequestBody> {
-        let reader = self.data.lock().unwrap().take();
+        let reader = self.take_body();
+        let reader = reader.or_else(|| {
+            let buffered = self.extensions().get::<BufferedBody>().cloned()?;
+            Some(Box::new(Cursor::new(buffered.0)) as Box<_>)
+        });
         reader.map(|r| RequestBody {
             body: r,
             marker: PhantomData,
         })
     }
 
+    /// Reads the body of the request into memory, so that it can be read several times with
+    /// `data()`, and returns it.
+    ///
+    /// This lets several layers of a server read the body, for example a middleware that logs
+    /// it, then a check of its signature, then `input::json_input`. Once the body is buffered,
+    /// calling this function again returns the same buffer, whatever `limit`.
+    ///
+    /// Returns an error if the body has been extracted with `data()` before, or if it is larger
+    /// than `limit` bytes, in which case the part of the body that has been read is lost.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::input::json_input;
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let body = match request.buffer_body(64 * 1024) {
+    ///         Ok(body) => body,
+    ///         Err(_) => return Response::text("Payload Too Large").with_status_code(413),
+    ///     };
+    ///     println!("received {} bytes", body.len());
+    ///
+    ///     let value: Result<Vec<u32>, _> = json_input(request);
+    ///     // ...
+    /// # let _ = value;
+    /// # Response::empty_204()
+    /// }
+    /// ```
+    pub fn buffer_body(&self, limit: usize) -> Result<Arc<[u8]>, BufferBodyError> {
+        if let Some(buffered) = self.extensions().get::<BufferedBody>() {
+            return Ok(buffered.0.clone());
+        }
+
+        let reader = self
+            .take_body()
+            .ok_or(BufferBodyError::BodyAlreadyExtracted)?;
+        let mut body = Vec::new();
+        reader
+            .take(limit.saturating_add(1) as u64)
+            .read_to_end(&mut body)?;
+        if body.len() > limit {
+            return Err(BufferBodyError::LimitExceeded);
+        }
+
+        let body: Arc<[u8]> = body.into();
+        self.extensions().insert(BufferedBody(body.clone()));
+        Ok(body)
+    }
+
+    /// Returns the number of bytes that have been read from the body of the request so far,
+    /// whatever the way it was read: `data()`, `buffer_body()` or one of the functions of the
+    /// `input` module.
+    ///
+    /// The bytes that are read again from a buffered body aren't counted twice. The bytes that
+    /// the server discards after the response, when the handler didn't read the whole body,
+    /// aren't counted.
+    ///
+    /// This is the size of the body once its transfer encoding has been decoded, not the number
+    /// of bytes received on the connection, which includes the headers.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Read;
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_http("POST", "/", vec![], b"hello world".to_vec());
+    /// request.data().unwrap().take(5).read_to_end(&mut Vec::new()).unwrap();
+    /// assert_eq!(request.body_bytes_read(), 5);
+    /// ```
+    #[inline]
+    pub fn body_bytes_read(&self) -> u64 {
+        self.body_bytes_counter().load(Ordering::Relaxed)
+    }
+
+    // Returns the counter of the bytes read from the body, which is shared by the copies of the
+    // request.
+    pub(crate) fn body_bytes_counter(&self) -> Arc<AtomicU64> {
+        let mut extensions = self.extensions();
+        if let Some(counter) = extensions.get::<BodyBytesRead>() {
+            return counter.0.clone();
+        }
+        let counter = Arc::new(AtomicU64::new(0));
+        extensions.insert(BodyBytesRead(counter.clone()));
+        counter
+    }
+
+    // Returns the counter of the bytes written on the connection for the response, if the
+    // request was received by the server.
+    pub(crate) fn bytes_written_counter(&self) -> Option<Arc<AtomicU64>> {
+        self.extensions().get::<BytesWritten>().map(|b| b.0.clone())
+    }
+
+    // Takes the body of the request, wrapped so that the bytes read from it are counted.
+    fn take_body(&self) -> Option<Box<dyn Read + Send>> {
+        let inner = self.data.lock().unwrap().take()?;
+        Some(Box::new(CountedRead {
+            inner,
+            counter: self.body_bytes_counter(),
+        }))
+    }
+
     /// Returns the address of the client that made this request.
     ///
     /// # Example
@@ -1026,6 +3013,73 @@ impl Request {
     pub fn remote_addr(&self) -> &SocketAddr {
         &self.remote_addr
     }
+
+    /// Returns the IP address of the client, which is the one of `remote_addr` unless the
+    /// request came through a trusted proxy of
+    /// [`Server::real_ip`](struct.Server.html#method.real_ip).
+    #[inline]
+    pub fn client_addr(&self) -> IpAddr {
+        match self.extensions().get::<Client>() {
+            Some(client) => client.addr,
+            None => self.remote_addr.ip(),
+        }
+    }
+
+    /// Returns the instant before which the request should be answered, if it has a
+    /// [`Deadline`](struct.Deadline.html).
+    #[inline]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.extensions().get::<Deadline>().map(|d| d.0)
+    }
+
+    /// Returns the time left before the deadline of the request, which is zero if it has passed,
+    /// or `None` if the request has no deadline.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let timeout = request.remaining_time().unwrap_or(Duration::from_secs(10));
+    ///     if timeout == Duration::from_secs(0) {
+    ///         return Response::text("Gateway Timeout").with_status_code(504);
+    ///     }
+    ///     // Call another service with `timeout`...
+    /// # Response::text("")
+    /// }
+    /// ```
+    pub fn remaining_time(&self) -> Option<Duration> {
+        let deadline = self.deadline()?;
+        let now = Instant::now();
+        Some(if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_secs(0)
+        })
+    }
+
+    /// Returns information about the connection that the request was received on.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    ///
+    /// let request = Request::fake_https("GET", "/", vec![], vec![]);
+    /// let info = request.connection_info();
+    /// assert!(info.secure);
+    /// assert_eq!(info.local_addr, None);
+    /// ```
+    #[inline]
+    pub fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            peer_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            secure: self.https,
+        }
+    }
 }
 
 /// Iterator to the list of headers in a request.
@@ -1065,9 +3119,579 @@ impl<'a> Read for RequestBody<'a> {
     }
 }
 
+// Raw URL of a request before it was changed by `remove_prefix`, `strip_path_prefix` or a
+// rewrite, in its extensions.
+struct ReceivedUrl(String);
+
+// Number of bytes read from the body of a request, in its extensions.
+struct BodyBytesRead(Arc<AtomicU64>);
+
+// Number of bytes written on the connection for the final response of a request received by the
+// server, in its extensions.
+struct BytesWritten(Arc<AtomicU64>);
+
+// Writer of a connection that counts the bytes written to it.
+struct CountedWrite {
+    inner: Box<dyn Write + Send>,
+    counter: Arc<AtomicU64>,
+}
+
+impl Write for CountedWrite {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let num = self.inner.write(buf)?;
+        self.counter.fetch_add(num as u64, Ordering::Relaxed);
+        Ok(num)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+// Body of a request that counts the bytes read from it.
+struct CountedRead {
+    inner: Box<dyn Read + Send>,
+    counter: Arc<AtomicU64>,
+}
+
+impl Read for CountedRead {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let num = self.inner.read(buf)?;
+        self.counter.fetch_add(num as u64, Ordering::Relaxed);
+        Ok(num)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use link::Link;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+    use tiny_http;
+    use BufferBodyError;
+    use HeaderLimits;
+    use LogSummary;
     use Request;
+    use Response;
+    use Server;
+
+    #[test]
+    fn multiple_listeners() {
+        let server = Server::new_multi(&["127.0.0.1:0", "127.0.0.1:0"], |request| {
+            Response::text(request.url())
+        })
+        .unwrap();
+
+        let addrs = server.server_addrs();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[0], server.server_addr());
+        assert!(addrs[0] != addrs[1]);
+
+        let (handle, stop) = server.stoppable();
+        for addr in &addrs {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            assert!(out.starts_with("HTTP/1.1 200"));
+            assert!(out.ends_with("/hello"));
+        }
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn poll_timeout_multiple_listeners() {
+        let server =
+            Server::new_multi(&["127.0.0.1:0", "127.0.0.1:0"], |_| Response::text("ok")).unwrap();
+        let addr = server.server_addrs()[1];
+        let handle = thread::spawn(move || server.poll_timeout(Duration::from_secs(2)));
+
+        // The second listener doesn't wait for the time given to the first one.
+        let start = Instant::now();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200"));
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn panic_handler() {
+        let server = Server::new("127.0.0.1:0", |request| {
+            if request.url() == "/panic" {
+                panic!("oops");
+            }
+            Response::text("ok")
+        })
+        .unwrap()
+        .panic_handler(|request, payload| {
+            let message = super::panic_message(payload).unwrap_or("");
+            Response::text(format!("{} failed: {}", request.url(), message)).with_status_code(503)
+        });
+
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        // Both requests are sent on the same connection, and the body of the first one is never
+        // read by the handler.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"POST /panic HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+                  GET /after HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 503"));
+        assert!(out.contains("/panic failed: oops"));
+        assert!(out.contains("HTTP/1.1 200"));
+        assert!(out.ends_with("ok"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn error_handler() {
+        let server = Server::new("127.0.0.1:0", |request| match &request.url()[..] {
+            "/empty" => Response::empty_404(),
+            "/body" => Response::text("custom").with_status_code(404),
+            "/try" => {
+                let err = std::io::Error::new(std::io::ErrorKind::Other, "bad input");
+                ::try_or_400::error_response(&err)
+            }
+            _ => Response::text("ok"),
+        })
+        .unwrap()
+        .error_handler(|request, response| {
+            Response::text(format!(
+                "error {} on {}",
+                response.status_code,
+                request.url()
+            ))
+            .with_status_code(response.status_code)
+        });
+
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let get = |url: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                url
+            )
+            .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+
+        let out = get("/empty");
+        assert!(out.starts_with("HTTP/1.1 404"));
+        assert!(out.ends_with("error 404 on /empty"));
+        let out = get("/try");
+        assert!(out.starts_with("HTTP/1.1 400"));
+        assert!(out.ends_with("error 400 on /try"));
+        assert!(get("/body").ends_with("custom"));
+        assert!(get("/").ends_with("ok"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn method_policy() {
+        let server = Server::new("127.0.0.1:0", |request| Response::text(request.method()))
+            .unwrap()
+            .custom_methods(&["PURGE"])
+            .reject_unknown_methods(true);
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let send = |method: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "{} / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                method
+            )
+            .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+
+        assert!(send("TRACE").starts_with("HTTP/1.1 405"));
+        assert!(send("PROPFIND").starts_with("HTTP/1.1 501"));
+        assert!(send("PURGE").ends_with("PURGE"));
+        assert!(send("GET").ends_with("GET"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn expect_continue() {
+        let server = Server::new("127.0.0.1:0", |request| {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+            Response::text(body)
+        })
+        .unwrap()
+        .expect_continue(|request| {
+            if request.header("Content-Length") == Some("5") {
+                None
+            } else {
+                Some(Response::text("Payload Too Large").with_status_code(413))
+            }
+        });
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let connect = |length: usize| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "PUT / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Length: {}\r\nExpect: 100-continue\r\n\r\n",
+                length
+            )
+            .unwrap();
+            stream
+        };
+
+        // The interim response is sent before the body.
+        let mut stream = connect(5);
+        let mut interim = Vec::new();
+        while !interim.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            interim.push(byte[0]);
+        }
+        assert!(interim.starts_with(b"HTTP/1.1 100 Continue\r\n"));
+        stream.write_all(b"hello").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200"));
+        assert!(out.ends_with("hello"));
+
+        // The client gives up waiting and sends the body, which is discarded.
+        let mut stream = connect(3);
+        thread::sleep(Duration::from_millis(50));
+        stream.write_all(b"foo").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 413"));
+        assert!(!out.contains("100 Continue"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn respond_at_deadline() {
+        let server = Server::new("127.0.0.1:0", |request| {
+            if request.url() == "/slow" {
+                thread::sleep(Duration::from_millis(500));
+            }
+            Response::text("hello")
+        })
+        .unwrap()
+        .request_timeout(Duration::from_millis(100))
+        .respond_at_deadline();
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let get = |url: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                url
+            )
+            .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+
+        let start = Instant::now();
+        let out = get("/slow");
+        assert!(out.starts_with("HTTP/1.1 503"));
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        let out = get("/");
+        assert!(out.starts_with("HTTP/1.1 200"));
+        assert!(out.ends_with("hello"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn early_hints() {
+        let server = Server::new("127.0.0.1:0", |request| {
+            let style = Link::new("/style.css", "preload").param("as", "style");
+            request.send_early_hints(&[style]).unwrap();
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+            Response::text(body)
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let send = |version: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST / HTTP/{}\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Length: 5\r\n\r\nhello",
+                version
+            )
+            .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+
+        let out = send("1.1");
+        assert!(out.starts_with(
+            "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=\"preload\"; as=\"style\"\r\n\r\n\
+             HTTP/1.1 200 OK\r\n"
+        ));
+        assert!(out.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+
+        let out = send("1.0");
+        assert!(out.starts_with("HTTP/1.0 200"));
+        assert!(out.ends_with("hello"));
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert!(!request.send_informational(103, &[]).unwrap());
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn early_hints_large_body() {
+        let server = Server::new("127.0.0.1:0", |request| {
+            let style = Link::new("/style.css", "preload");
+            let before = request
+                .send_early_hints(::std::slice::from_ref(&style))
+                .unwrap();
+            let mut body = Vec::new();
+            request.data().unwrap().read_to_end(&mut body).unwrap();
+            let after = request.send_early_hints(&[style]).unwrap();
+            Response::text(format!("{} {} {}", before, after, body.len()))
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n",
+            )
+            .unwrap();
+        let chunk = vec![b'a'; 0x10000];
+        for _ in 0..16 {
+            stream.write_all(b"10000\r\n").unwrap();
+            stream.write_all(&chunk).unwrap();
+            stream.write_all(b"\r\n").unwrap();
+        }
+        stream.write_all(b"0\r\n\r\n").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+
+        // The body isn't buffered to send the hints before it is read, but they can be sent
+        // afterwards.
+        assert!(out.starts_with("HTTP/1.1 103 Early Hints\r\n"));
+        assert!(out.contains("false true 1048576"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn header_limits() {
+        let server = Server::new("127.0.0.1:0", |_| Response::text("ok"))
+            .unwrap()
+            .header_limits(HeaderLimits {
+                max_count: 3,
+                ..HeaderLimits::default()
+            });
+        let rejections = server.header_limit_rejections();
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let send = |headers: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+                headers
+            )
+            .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+
+        assert!(send("X-A: a\r\n").ends_with("ok"));
+        assert!(send("X-A: a\r\nX-B: b\r\n").starts_with("HTTP/1.1 431"));
+        assert_eq!(rejections.load(Ordering::Relaxed), 1);
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn subsystems_stop_with_server() {
+        let server = Server::new("127.0.0.1:0", |_| Response::text("ok")).unwrap();
+        let subsystems = server.subsystems();
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        subsystems.spawn("task", ::std::time::Duration::from_secs(5), move |signal| {
+            signal.wait();
+            tx.send(()).unwrap();
+        });
+        let (handle, stop) = server.stoppable();
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+        assert!(rx.try_recv().is_ok());
+        assert!(subsystems.is_empty());
+    }
+
+    #[test]
+    fn server_builder() {
+        let server = Server::builder()
+            .addr("127.0.0.1:0")
+            .addr("127.0.0.1:0")
+            .pool_size(2)
+            .build(|_| Response::text("ok"))
+            .unwrap();
+        let addrs = server.server_addrs();
+        assert_eq!(addrs.len(), 2);
+        let (handle, stop) = server.stoppable();
+
+        for addr in addrs {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            assert!(out.ends_with("ok"));
+        }
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+
+        let empty = Server::builder().build(|_| Response::text("ok"));
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn max_requests_per_connection() {
+        use std::net::Shutdown;
+
+        let server = Server::new("127.0.0.1:0", |_| Response::text("ok"))
+            .unwrap()
+            .max_requests_per_connection(2);
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        )
+        .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        let second = out.rfind("HTTP/1.1 200").unwrap();
+        assert!(out[..second].contains("Keep-Alive: max=1\r\n"));
+        assert!(out[second..].contains("Connection: close\r\n"));
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn handle_tiny_http() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr();
+        let thread = thread::spawn(move || {
+            let request = server.recv().unwrap();
+            super::handle_tiny_http(request, |request| {
+                let body = ::input::plain_text_body(request).unwrap();
+                Response::text(format!("{} {}", request.url(), body))
+            });
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"POST /a HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                  Content-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello",
+            )
+            .unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200"));
+        assert!(out.ends_with("/a hello"));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn with_state() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let handler = super::with_state(AtomicUsize::new(0), |counter, _| {
+            let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            Response::text(count.to_string())
+        });
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        handler(&request);
+        let response = handler(&request);
+        let (mut body, _) = response.data.into_reader_and_size();
+        let mut out = String::new();
+        body.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "2");
+    }
+
+    #[test]
+    fn new_multi_no_address() {
+        let addrs: [&str; 0] = [];
+        assert!(Server::new_multi(&addrs, |_| Response::empty_404()).is_err());
+    }
 
     #[test]
     fn header() {
@@ -1087,6 +3711,13 @@ mod tests {
         assert_eq!(request.get_param("p"), Some("hello".to_owned()));
     }
 
+    #[test]
+    fn get_params() {
+        let request = Request::fake_http("GET", "/?tag=a&tag&tag=%C3%A9&other=b", vec![], vec![]);
+        assert_eq!(request.get_params("tag"), vec!["a", "", "é"]);
+        assert_eq!(request.query_params().count(), 4);
+    }
+
     #[test]
     fn body_twice() {
         let request = Request::fake_http("GET", "/", vec![], vec![62, 62, 62]);
@@ -1094,6 +3725,36 @@ mod tests {
         assert!(request.data().is_none());
     }
 
+    #[test]
+    fn buffer_body() {
+        let request = Request::fake_http("POST", "/a/b", vec![], b"hello".to_vec());
+        assert_eq!(&request.buffer_body(5).unwrap()[..], b"hello");
+        assert_eq!(&request.buffer_body(0).unwrap()[..], b"hello");
+        for _ in 0..2 {
+            let mut body = String::new();
+            request.data().unwrap().read_to_string(&mut body).unwrap();
+            assert_eq!(body, "hello");
+        }
+        let prefixed = request.remove_prefix("/a").unwrap();
+        assert!(prefixed.data().is_some());
+        assert_eq!(request.body_bytes_read(), 5);
+        assert_eq!(prefixed.body_bytes_read(), 5);
+
+        let request = Request::fake_http("POST", "/", vec![], b"hello".to_vec());
+        match request.buffer_body(4) {
+            Err(BufferBodyError::LimitExceeded) => (),
+            r => panic!("{:?}", r),
+        }
+        assert!(request.data().is_none());
+
+        let request = Request::fake_http("POST", "/", vec![], b"hello".to_vec());
+        let _ = request.data();
+        match request.buffer_body(5) {
+            Err(BufferBodyError::BodyAlreadyExtracted) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+
     #[test]
     fn url_strips_get_query() {
         let request = Request::fake_http("GET", "/?p=hello", vec![], vec![]);
@@ -1130,6 +3791,107 @@ mod tests {
         assert_eq!(request.url(), "/hello+world");
     }
 
+    #[test]
+    fn transport_byte_counts() {
+        let summaries = Arc::new(Mutex::new(Vec::<LogSummary>::new()));
+        let handler = {
+            let summaries = summaries.clone();
+            Arc::new(move |s: &LogSummary| summaries.lock().unwrap().push(s.clone()))
+        };
+        let server = Server::new("127.0.0.1:0", move |request| {
+            ::log_with_handler(request, handler.clone(), || {
+                request
+                    .data()
+                    .unwrap()
+                    .read_to_end(&mut Vec::new())
+                    .unwrap();
+                Response::text("hello")
+            })
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n4\r\ndefg\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        let mut out = Vec::new();
+        stream.read_to_end(&mut out).unwrap();
+
+        // The chunked body is counted once decoded, and the whole response is counted.
+        let summaries = summaries.lock().unwrap();
+        assert_eq!(summaries[0].bytes_read, 7);
+        assert_eq!(summaries[0].bytes_written, out.len() as u64);
+        assert!(out.len() > 5);
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn connection_info() {
+        let server = Server::new_multi(&["127.0.0.1:0", "127.0.0.1:0"], |request| {
+            let info = request.connection_info();
+            assert_eq!(info.peer_addr, *request.remote_addr());
+            assert!(!info.secure);
+            Response::text(info.local_addr.unwrap().to_string())
+        })
+        .unwrap();
+
+        let addrs = server.server_addrs();
+        let (handle, stop) = server.stoppable();
+        for addr in &addrs {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            assert!(out.starts_with("HTTP/1.1 200"));
+            assert!(out.ends_with(&addr.to_string()));
+        }
+
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn absolute_url() {
+        let host = |h: &str| vec![("Host".to_owned(), h.to_owned())];
+
+        let request = Request::fake_https("GET", "/a?b", host("Example.com"), vec![]);
+        let url = request.absolute_url().unwrap();
+        assert_eq!(url.as_str(), "https://example.com/a?b");
+        assert_eq!(url.port_or_known_default(), Some(443));
+
+        let request = Request::fake_http("GET", "/a", host("user@evil.com"), vec![]);
+        assert!(request.absolute_url().is_none());
+        let request = Request::fake_http("GET", "/a", host("evil.com/x"), vec![]);
+        assert!(request.absolute_url().is_none());
+        let request = Request::fake_http("GET", "/a", vec![], vec![]);
+        assert!(request.absolute_url().is_none());
+        assert!(request.resolve_url("/b").is_none());
+
+        let request = Request::fake_http("GET", "http://proxied.com/a", vec![], vec![]);
+        assert_eq!(request.host().unwrap(), "proxied.com");
+
+        let request = Request::fake_http("GET", "/a/b", host("example.com"), vec![]);
+        assert_eq!(
+            request.resolve_local_url("c?d").unwrap().as_str(),
+            "http://example.com/a/c?d"
+        );
+        assert!(request.resolve_local_url("https://example.com/").is_none());
+        assert!(request.resolve_local_url("/\\evil.com").is_none());
+        assert_eq!(
+            Request::fake_http("GET", "/", vec![], vec![]).path_segments(),
+            Vec::<String>::new()
+        );
+    }
+
     #[test]
     fn dnt() {
         let request =
@@ -0,0 +1,445 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Building and parsing the `Link` header.
+//!
+//! The `Link` header, defined in [RFC 8288](https://tools.ietf.org/html/rfc8288), indicates
+//! resources related to the response, for example the next page of a collection or a stylesheet
+//! that the client should preload.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::Response;
+//! use rouille::link::Link;
+//!
+//! let response = Response::text("page 2")
+//!     .with_link(Link::new("/items?page=3", "next"))
+//!     .with_link(Link::new("/style.css", "preload").param("as", "style"));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// A single value of a `Link` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The URI of the linked resource.
+    pub target: String,
+    /// The relation types of the link, such as `next` or `preload`. Can be empty.
+    pub rel: Vec<String>,
+    /// The URI that the link applies to, if it isn't the requested resource.
+    pub anchor: Option<String>,
+    /// The media type of the linked resource, from the `type` attribute.
+    pub media_type: Option<String>,
+    /// A human-readable title of the link.
+    pub title: Option<String>,
+    /// The other attributes of the link. Attribute names are in lowercase.
+    pub params: Vec<(String, String)>,
+}
+
+impl Link {
+    /// Builds a link to `target` with the relation type `rel`.
+    ///
+    /// `rel` can contain several relation types separated with spaces.
+    pub fn new<T, R>(target: T, rel: R) -> Link
+    where
+        T: Into<String>,
+        R: AsRef<str>,
+    {
+        Link {
+            target: target.into(),
+            rel: rel.as_ref().split_whitespace().map(str::to_owned).collect(),
+            anchor: None,
+            media_type: None,
+            title: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// Sets the `anchor` attribute.
+    #[inline]
+    pub fn anchor<S>(mut self, anchor: S) -> Link
+    where
+        S: Into<String>,
+    {
+        self.anchor = Some(anchor.into());
+        self
+    }
+
+    /// Sets the `type` attribute.
+    #[inline]
+    pub fn media_type<S>(mut self, media_type: S) -> Link
+    where
+        S: Into<String>,
+    {
+        self.media_type = Some(media_type.into());
+        self
+    }
+
+    /// Sets the `title` attribute.
+    #[inline]
+    pub fn title<S>(mut self, title: S) -> Link
+    where
+        S: Into<String>,
+    {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Adds another attribute, such as `as` or `hreflang`.
+    #[inline]
+    pub fn param<N, V>(mut self, name: N, value: V) -> Link
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.params.push((name.into().to_lowercase(), value.into()));
+        self
+    }
+
+    /// Returns true if `rel` is one of the relation types of the link.
+    ///
+    /// Relation types are compared case-insensitively.
+    #[inline]
+    pub fn has_rel(&self, rel: &str) -> bool {
+        self.rel.iter().any(|r| r.eq_ignore_ascii_case(rel))
+    }
+
+    /// Returns the value of an attribute from `params`.
+    #[inline]
+    pub fn get_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|p| p.0.eq_ignore_ascii_case(name))
+            .map(|p| &p.1[..])
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "<{}>", self.target)?;
+        if !self.rel.is_empty() {
+            write!(fmt, "; rel=")?;
+            write_quoted(fmt, &self.rel.join(" "))?;
+        }
+        if let Some(ref anchor) = self.anchor {
+            write!(fmt, "; anchor=")?;
+            write_quoted(fmt, anchor)?;
+        }
+        if let Some(ref media_type) = self.media_type {
+            write!(fmt, "; type=")?;
+            write_quoted(fmt, media_type)?;
+        }
+        if let Some(ref title) = self.title {
+            write!(fmt, "; title=")?;
+            write_quoted(fmt, title)?;
+        }
+        for param in &self.params {
+            write!(fmt, "; {}=", param.0)?;
+            write_quoted(fmt, &param.1)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Link {
+    type Err = LinkParseError;
+
+    /// Parses a header that contains exactly one link.
+    fn from_str(s: &str) -> Result<Link, LinkParseError> {
+        let mut links = parse(s)?;
+        if links.len() != 1 {
+            return Err(LinkParseError);
+        }
+        Ok(links.remove(0))
+    }
+}
+
+/// Error that can happen when parsing a `Link` header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LinkParseError;
+
+impl error::Error for LinkParseError {}
+
+impl fmt::Display for LinkParseError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "invalid Link header")
+    }
+}
+
+/// Formats a list of links as the value of a single `Link` header.
+///
+/// # Example
+///
+/// ```
+/// use rouille::link::{self, Link};
+///
+/// let value = link::format(&[Link::new("/?page=1", "prev"), Link::new("/?page=3", "next")]);
+/// assert_eq!(value, r#"</?page=1>; rel="prev", </?page=3>; rel="next""#);
+/// ```
+pub fn format(links: &[Link]) -> String {
+    links
+        .iter()
+        .map(|link| link.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parses the value of a `Link` header, which can contain multiple links separated by commas.
+///
+/// As required by RFC 8288, only the first occurrence of the `rel`, `anchor`, `type` and `title`
+/// attributes is taken into account. Other attributes are stored in `params`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::link;
+///
+/// let links = link::parse(r#"<https://example.com/?page=2>; rel="next", </style.css>; rel=preload; as=style"#).unwrap();
+/// assert_eq!(links.len(), 2);
+/// assert!(links[0].has_rel("next"));
+/// assert_eq!(links[1].get_param("as"), Some("style"));
+/// ```
+pub fn parse(header: &str) -> Result<Vec<Link>, LinkParseError> {
+    let mut parser = Parser {
+        input: header.as_bytes(),
+        pos: 0,
+    };
+    let mut links = Vec::new();
+
+    loop {
+        parser.skip_whitespace();
+        // Empty elements are allowed in comma-separated lists.
+        if parser.eat(b',') {
+            continue;
+        }
+        if parser.is_at_end() {
+            break;
+        }
+
+        links.push(parser.link()?);
+
+        parser.skip_whitespace();
+        if !parser.is_at_end() && !parser.eat(b',') {
+            return Err(LinkParseError);
+        }
+    }
+
+    Ok(links)
+}
+
+fn write_quoted(fmt: &mut fmt::Formatter, value: &str) -> Result<(), fmt::Error> {
+    write!(fmt, "\"")?;
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            write!(fmt, "\\")?;
+        }
+        write!(fmt, "{}", c)?;
+    }
+    write!(fmt, "\"")
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn is_at_end(&self) -> bool {
+        self.pos >= self.input.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn link(&mut self) -> Result<Link, LinkParseError> {
+        if !self.eat(b'<') {
+            return Err(LinkParseError);
+        }
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c != b'>') {
+            self.pos += 1;
+        }
+        let target = self.string(start, self.pos)?;
+        if !self.eat(b'>') {
+            return Err(LinkParseError);
+        }
+
+        let mut link = Link {
+            target,
+            rel: Vec::new(),
+            anchor: None,
+            media_type: None,
+            title: None,
+            params: Vec::new(),
+        };
+        let mut seen_rel = false;
+
+        loop {
+            self.skip_whitespace();
+            if !self.eat(b';') {
+                break;
+            }
+            self.skip_whitespace();
+
+            let name = self.token()?.to_lowercase();
+            self.skip_whitespace();
+            let value = if self.eat(b'=') {
+                self.skip_whitespace();
+                if self.peek() == Some(b'"') {
+                    self.quoted_string()?
+                } else {
+                    self.token()?
+                }
+            } else {
+                String::new()
+            };
+
+            match &name[..] {
+                "rel" if !seen_rel => {
+                    seen_rel = true;
+                    link.rel = value.split_whitespace().map(str::to_owned).collect();
+                }
+                "anchor" if link.anchor.is_none() => link.anchor = Some(value),
+                "type" if link.media_type.is_none() => link.media_type = Some(value),
+                "title" if link.title.is_none() => link.title = Some(value),
+                "rel" | "anchor" | "type" | "title" => (),
+                _ => link.params.push((name, value)),
+            }
+        }
+
+        Ok(link)
+    }
+
+    fn token(&mut self) -> Result<String, LinkParseError> {
+        let start = self.pos;
+        while self.peek().map_or(false, is_token_char) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(LinkParseError);
+        }
+        self.string(start, self.pos)
+    }
+
+    fn quoted_string(&mut self) -> Result<String, LinkParseError> {
+        debug_assert_eq!(self.peek(), Some(b'"'));
+        self.pos += 1;
+
+        let mut value = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(LinkParseError),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(c) => value.push(c),
+                        None => return Err(LinkParseError),
+                    }
+                }
+                Some(c) => value.push(c),
+            }
+            self.pos += 1;
+        }
+
+        String::from_utf8(value).map_err(|_| LinkParseError)
+    }
+
+    fn string(&self, start: usize, end: usize) -> Result<String, LinkParseError> {
+        String::from_utf8(self.input[start..end].to_vec()).map_err(|_| LinkParseError)
+    }
+}
+
+fn is_token_char(c: u8) -> bool {
+    match c {
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+        | b'`' | b'|' | b'~' => true,
+        c => c.is_ascii_alphanumeric(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+    use super::parse;
+    use super::Link;
+
+    #[test]
+    fn format_and_parse() {
+        let links = vec![
+            Link::new("https://example.com/?page=2", "next"),
+            Link::new("/en", "alternate")
+                .media_type("text/html")
+                .title("The \"English\" version")
+                .param("hreflang", "en"),
+        ];
+
+        let header = format(&links);
+        assert_eq!(
+            header,
+            "<https://example.com/?page=2>; rel=\"next\", \
+             </en>; rel=\"alternate\"; type=\"text/html\"; \
+             title=\"The \\\"English\\\" version\"; hreflang=\"en\""
+        );
+        assert_eq!(parse(&header).unwrap(), links);
+    }
+
+    #[test]
+    fn parse_forms() {
+        let links = parse(
+            "</a,b>; REL=\"prev  first\"; rel=ignored; anchor=#x,, \
+             </c>;crossorigin;as=font",
+        )
+        .unwrap();
+
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].target, "/a,b");
+        assert_eq!(links[0].rel, vec!["prev", "first"]);
+        assert!(links[0].has_rel("FIRST"));
+        assert_eq!(links[0].anchor.as_ref().map(|a| &a[..]), Some("#x"));
+        assert_eq!(links[1].get_param("crossorigin"), Some(""));
+        assert_eq!(links[1].get_param("as"), Some("font"));
+        assert!(links[1].rel.is_empty());
+
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("/no-brackets; rel=next").is_err());
+        assert!(parse("</a>; rel=\"unterminated").is_err());
+        assert!(parse("</a> </b>").is_err());
+        assert!(parse("</a>; =next").is_err());
+        assert!("</a>, </b>".parse::<Link>().is_err());
+        assert!("</a>; rel=next".parse::<Link>().is_ok());
+    }
+}
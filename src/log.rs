@@ -7,15 +7,29 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use std::any::Any;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Read;
 use std::io::Write;
+use std::mem;
+use std::net::SocketAddr;
 use std::panic;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
 use chrono;
+use serde_json;
+use serde_json::Value;
 
+use middleware::Middleware;
 use Request;
 use Response;
+use ResponseBody;
 
 /// Adds a log entry to the given writer for each request.
 ///
@@ -133,6 +147,620 @@ where
     }
 }
 
+/// Middleware that does the same as [`rouille::log`](fn.log.html).
+///
+/// The closure is called for each request in order to obtain the writer to log to.
+///
+/// # Example
+///
+/// ```
+/// use std::io;
+/// use rouille::{Logger, Request, Response};
+/// use rouille::middleware::Pipeline;
+///
+/// let pipeline = Pipeline::new(|_: &Request| Response::text("hello world"))
+///     .with(Logger::new(io::stdout));
+/// ```
+pub struct Logger<F> {
+    output: F,
+    format: Option<LogFormat>,
+}
+
+impl<F, W> Logger<F>
+where
+    F: Fn() -> W,
+    W: Write,
+{
+    /// Builds a new `Logger` that writes to the writers returned by `output`.
+    #[inline]
+    pub fn new(output: F) -> Logger<F> {
+        Logger {
+            output,
+            format: None,
+        }
+    }
+
+    /// Writes the lines in the given format, like
+    /// [`rouille::log_with_format`](fn.log_with_format.html).
+    #[inline]
+    pub fn with_format(mut self, format: LogFormat) -> Logger<F> {
+        self.format = Some(format);
+        self
+    }
+}
+
+impl<F, W> Middleware for Logger<F>
+where
+    F: Fn() -> W + Send + Sync,
+    W: Write,
+{
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        match self.format {
+            Some(ref format) => log_with_format(request, (self.output)(), format, || next(request)),
+            None => log(request, (self.output)(), || next(request)),
+        }
+    }
+}
+
+/// Same as [`rouille::log`](fn.log.html), but the lines are in the given format.
+///
+/// # Example
+///
+/// ```
+/// use std::io;
+/// use rouille::{LogFormat, Request, Response};
+///
+/// fn handle(request: &Request) -> Response {
+///     let format = LogFormat::parse(LogFormat::COMBINED).unwrap();
+///     rouille::log_with_format(request, io::stdout(), &format, || {
+///         Response::text("hello world")
+///     })
+/// }
+/// ```
+pub fn log_with_format<W, F>(rq: &Request, mut output: W, format: &LogFormat, f: F) -> Response
+where
+    W: Write,
+    F: FnOnce() -> Response,
+{
+    let start_instant = Instant::now();
+    let time = chrono::Utc::now();
+
+    let response = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    let elapsed = start_instant.elapsed();
+
+    let line = format.format(rq, time, elapsed, response.as_ref().ok());
+    let _ = writeln!(output, "{}", line);
+
+    match response {
+        Ok(response) => response,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Format of the lines written by [`log_with_format`](fn.log_with_format.html), with the tokens
+/// of the Apache and nginx servers.
+///
+/// The supported tokens are:
+///
+/// - `%h` and `%a`: the IP address of the client, as returned by `Request::client_addr`;
+/// - `%l` and `%u`: always `-`;
+/// - `%t`: the time at which the request was received, such as `[10/Oct/2000:13:55:36 +0000]`;
+/// - `%r`: the request line, such as `GET /index.html HTTP/1.1`. The version is always
+///   `HTTP/1.1`;
+/// - `%m`, `%U` and `%q`: the method, the path, and the query string with its `?`;
+/// - `%s` and `%>s`: the status code of the response, `500` if the handler panicked;
+/// - `%b` and `%B`: the length of the body of the response, `-` or `0` if it's unknown;
+/// - `%I`: the number of bytes that the handler read from the body of the request;
+/// - `%D` and `%T`: the duration of the handler in microseconds and in seconds;
+/// - `%{Name}i` and `%{Name}o`: the value of a header of the request or of the response, or `-`;
+/// - `%%`: a `%`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormat {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    RemoteIp,
+    Dash,
+    Time,
+    RequestLine,
+    Method,
+    Path,
+    Query,
+    Status,
+    BytesClf,
+    Bytes,
+    BytesRead,
+    Micros,
+    Secs,
+    RequestHeader(String),
+    ResponseHeader(String),
+}
+
+impl LogFormat {
+    /// The Common Log Format.
+    pub const COMMON: &'static str = "%h %l %u %t \"%r\" %>s %b";
+
+    /// The Combined Log Format, which adds the referer and the user agent to the common one.
+    pub const COMBINED: &'static str =
+        "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+    /// Parses a format string.
+    pub fn parse(format: &str) -> Result<LogFormat, LogFormatError> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.char_indices().peekable();
+
+        while let Some((pos, c)) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+
+            let mut directive = match chars.next() {
+                Some((_, c)) => c,
+                None => return Err(LogFormatError::UnfinishedToken(pos)),
+            };
+            if directive == '>' {
+                directive = match chars.next() {
+                    Some((_, 's')) => 's',
+                    _ => return Err(LogFormatError::UnknownToken(pos)),
+                };
+            }
+
+            let token = match directive {
+                '%' => {
+                    literal.push('%');
+                    continue;
+                }
+                'h' | 'a' => Token::RemoteIp,
+                'l' | 'u' => Token::Dash,
+                't' => Token::Time,
+                'r' => Token::RequestLine,
+                'm' => Token::Method,
+                'U' => Token::Path,
+                'q' => Token::Query,
+                's' => Token::Status,
+                'b' => Token::BytesClf,
+                'B' => Token::Bytes,
+                'I' => Token::BytesRead,
+                'D' => Token::Micros,
+                'T' => Token::Secs,
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, c)) => name.push(c),
+                            None => return Err(LogFormatError::UnfinishedToken(pos)),
+                        }
+                    }
+                    match chars.next() {
+                        Some((_, 'i')) => Token::RequestHeader(name),
+                        Some((_, 'o')) => Token::ResponseHeader(name),
+                        Some(_) => return Err(LogFormatError::UnknownToken(pos)),
+                        None => return Err(LogFormatError::UnfinishedToken(pos)),
+                    }
+                }
+                _ => return Err(LogFormatError::UnknownToken(pos)),
+            };
+
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(mem::take(&mut literal)));
+            }
+            tokens.push(token);
+        }
+
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        Ok(LogFormat { tokens })
+    }
+
+    fn format(
+        &self,
+        rq: &Request,
+        time: chrono::DateTime<chrono::Utc>,
+        elapsed: Duration,
+        response: Option<&Response>,
+    ) -> String {
+        let mut line = String::new();
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(ref literal) => line.push_str(literal),
+                Token::RemoteIp => line.push_str(&rq.client_addr().to_string()),
+                Token::Dash => line.push('-'),
+                Token::Time => {
+                    line.push_str(&time.format("[%d/%b/%Y:%H:%M:%S %z]").to_string());
+                }
+                Token::RequestLine => {
+                    line.push_str(&format!("{} {} HTTP/1.1", rq.method(), rq.raw_url()));
+                }
+                Token::Method => line.push_str(rq.method()),
+                Token::Path => line.push_str(rq.raw_url().split('?').next().unwrap()),
+                Token::Query => {
+                    if let Some(pos) = rq.raw_url().find('?') {
+                        line.push_str(&rq.raw_url()[pos..]);
+                    }
+                }
+                Token::Status => {
+                    let status = response.map_or(500, |r| r.status_code);
+                    line.push_str(&status.to_string());
+                }
+                Token::BytesClf | Token::Bytes => match response.and_then(|r| r.data.data_length) {
+                    Some(len) => line.push_str(&len.to_string()),
+                    None if *token == Token::Bytes => line.push('0'),
+                    None => line.push('-'),
+                },
+                Token::BytesRead => line.push_str(&rq.body_bytes_read().to_string()),
+                Token::Micros => {
+                    let micros = elapsed.as_secs() * 1_000_000 + u64::from(elapsed.subsec_micros());
+                    line.push_str(&micros.to_string());
+                }
+                Token::Secs => line.push_str(&elapsed.as_secs().to_string()),
+                Token::RequestHeader(ref name) => line.push_str(rq.header(name).unwrap_or("-")),
+                Token::ResponseHeader(ref name) => {
+                    let value = response.and_then(|r| {
+                        r.headers
+                            .iter()
+                            .find(|h| h.0.eq_ignore_ascii_case(name))
+                            .map(|h| &h.1[..])
+                    });
+                    line.push_str(value.unwrap_or("-"));
+                }
+            }
+        }
+        line
+    }
+}
+
+/// Error while parsing a [`LogFormat`](struct.LogFormat.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFormatError {
+    /// The token that starts at the given byte offset is not supported.
+    UnknownToken(usize),
+    /// The format ends in the middle of the token that starts at the given byte offset.
+    UnfinishedToken(usize),
+}
+
+impl fmt::Display for LogFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogFormatError::UnknownToken(pos) => write!(f, "unknown token at offset {}", pos),
+            LogFormatError::UnfinishedToken(pos) => {
+                write!(f, "unfinished token at offset {}", pos)
+            }
+        }
+    }
+}
+
+impl error::Error for LogFormatError {}
+
+/// Same as [`rouille::log`](fn.log.html), but writes one JSON object per line, which is easier to
+/// ingest by log collectors.
+///
+/// The object has the following fields:
+///
+/// - `time`: the time at which the request was received, in the RFC 3339 format and in UTC;
+/// - `method` and `path`: the method and the raw URL of the request;
+/// - `status`: the status code of the response, or `null` if the handler panicked, in which
+///   case a `panic` field is `true`;
+/// - `latency_ms`: the duration of the handler in milliseconds;
+/// - `bytes`: the length of the body of the response, or `null` if it's unknown;
+/// - `bytes_read`: the number of bytes that the handler read from the body of the request;
+/// - `remote_addr`: the address of the client;
+/// - `request_id`: the value of the `X-Request-Id` header, or `null`;
+/// - `user_agent`: the value of the `User-Agent` header, or `null`.
+///
+/// # Example
+///
+/// ```
+/// use std::io;
+/// use rouille::{Request, Response};
+///
+/// fn handle(request: &Request) -> Response {
+///     rouille::log_json(request, io::stdout(), || {
+///         Response::text("hello world")
+///     })
+/// }
+/// ```
+pub fn log_json<W, F>(rq: &Request, mut output: W, f: F) -> Response
+where
+    W: Write,
+    F: FnOnce() -> Response,
+{
+    let start_instant = Instant::now();
+    let time = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+
+    let response = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    let elapsed = start_instant.elapsed();
+
+    let entry = json_entry(rq, time, elapsed, response.as_ref().ok());
+    let _ = writeln!(output, "{}", entry);
+
+    match response {
+        Ok(response) => response,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+fn json_entry(rq: &Request, time: String, elapsed: Duration, response: Option<&Response>) -> Value {
+    let header = |name| rq.header(name).map_or(Value::Null, |v| v.into());
+    let latency_ms = elapsed.as_secs() as f64 * 1_000.0 + f64::from(elapsed.subsec_nanos()) / 1e6;
+
+    let mut entry = serde_json::Map::new();
+    entry.insert("time".to_owned(), time.into());
+    entry.insert("method".to_owned(), rq.method().into());
+    entry.insert("path".to_owned(), rq.raw_url().into());
+    entry.insert(
+        "status".to_owned(),
+        response.map_or(Value::Null, |r| r.status_code.into()),
+    );
+    if response.is_none() {
+        entry.insert("panic".to_owned(), true.into());
+    }
+    entry.insert("latency_ms".to_owned(), latency_ms.into());
+    entry.insert(
+        "bytes".to_owned(),
+        response
+            .and_then(|r| r.data.data_length)
+            .map_or(Value::Null, |len| (len as u64).into()),
+    );
+    entry.insert("bytes_read".to_owned(), rq.body_bytes_read().into());
+    entry.insert(
+        "remote_addr".to_owned(),
+        rq.remote_addr().to_string().into(),
+    );
+    entry.insert("request_id".to_owned(), header("X-Request-Id"));
+    entry.insert("user_agent".to_owned(), header("User-Agent"));
+    Value::Object(entry)
+}
+
+/// Middleware that does the same as [`rouille::log_json`](fn.log_json.html).
+///
+/// The closure is called for each request in order to obtain the writer to log to.
+pub struct JsonLogger<F> {
+    output: F,
+}
+
+impl<F, W> JsonLogger<F>
+where
+    F: Fn() -> W,
+    W: Write,
+{
+    /// Builds a new `JsonLogger` that writes to the writers returned by `output`.
+    #[inline]
+    pub fn new(output: F) -> JsonLogger<F> {
+        JsonLogger { output }
+    }
+}
+
+impl<F, W> Middleware for JsonLogger<F>
+where
+    F: Fn() -> W + Send + Sync,
+    W: Write,
+{
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        log_json(request, (self.output)(), || next(request))
+    }
+}
+
+/// Receives a summary of each request once its response has been sent.
+///
+/// This is implemented for the closures that take a `&LogSummary`, and makes it possible to
+/// send the logs to syslog, to a channel, or to metrics, without parsing text. See
+/// [`log_with_handler`](fn.log_with_handler.html).
+pub trait LogHandler: Send + Sync {
+    /// Called once per request.
+    fn log(&self, summary: &LogSummary);
+}
+
+impl<F> LogHandler for F
+where
+    F: Fn(&LogSummary) + Send + Sync,
+{
+    #[inline]
+    fn log(&self, summary: &LogSummary) {
+        self(summary)
+    }
+}
+
+/// Summary of a request and of its response, passed to a [`LogHandler`](trait.LogHandler.html).
+#[derive(Debug, Clone)]
+pub struct LogSummary {
+    /// Method of the request.
+    pub method: String,
+    /// Raw URL of the request.
+    pub url: String,
+    /// Address of the client.
+    pub remote_addr: SocketAddr,
+    /// Status code of the response, or `500` if the handler panicked.
+    pub status_code: u16,
+    /// Time spent in the handler.
+    pub handler_duration: Duration,
+    /// Time between the start of the handler and the moment the server finished sending the
+    /// response.
+    pub duration: Duration,
+    /// Number of bytes that the server wrote on the connection for the response, including the
+    /// status line, the headers and the transfer encoding of the body.
+    ///
+    /// If the request wasn't received by a rouille server (for example because it was built
+    /// with `Request::fake_http`), or if the response upgraded the connection, this is the number
+    /// of bytes of the body that the server read instead.
+    pub bytes_written: u64,
+    /// Number of bytes that were read from the body of the request, as returned by
+    /// `Request::body_bytes_read` once the response has been sent.
+    pub bytes_read: u64,
+    /// False if the server stopped sending the body of the response before its end, for example
+    /// because the client closed the connection.
+    pub complete: bool,
+    /// Message of the panic, if the handler panicked.
+    pub panic: Option<String>,
+}
+
+/// Calls `f`, then calls `handler` with a summary of the request once the body of the response
+/// has been sent.
+///
+/// If the handler panics, `handler` is called immediately and the panic is resumed.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use rouille::{LogHandler, LogSummary, Request, Response};
+///
+/// fn handle(request: &Request, handler: Arc<dyn LogHandler>) -> Response {
+///     rouille::log_with_handler(request, handler, || Response::text("hello world"))
+/// }
+///
+/// let handler = Arc::new(|summary: &LogSummary| {
+///     eprintln!("{} {} {} {}B", summary.method, summary.url, summary.status_code,
+///               summary.bytes_written);
+/// });
+/// # let _ = handle(&Request::fake_http("GET", "/", vec![], vec![]), handler);
+/// ```
+pub fn log_with_handler<F>(rq: &Request, handler: Arc<dyn LogHandler>, f: F) -> Response
+where
+    F: FnOnce() -> Response,
+{
+    let start_instant = Instant::now();
+    let response = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    let handler_duration = start_instant.elapsed();
+
+    let mut summary = LogSummary {
+        method: rq.method().to_owned(),
+        url: rq.raw_url().to_owned(),
+        remote_addr: *rq.remote_addr(),
+        status_code: 500,
+        handler_duration,
+        duration: handler_duration,
+        bytes_written: 0,
+        bytes_read: 0,
+        complete: false,
+        panic: None,
+    };
+
+    match response {
+        Ok(mut response) => {
+            summary.status_code = response.status_code;
+            let data = mem::replace(&mut response.data, ResponseBody::empty());
+            let bytes_read = rq.body_bytes_counter();
+            let bytes_written = rq.bytes_written_counter();
+            response.data = data.map_reader(|inner| SentBody {
+                inner,
+                handler,
+                summary: Some(summary),
+                bytes_read,
+                bytes_written,
+                start_instant,
+            });
+            response
+        }
+        Err(payload) => {
+            summary.panic = Some(panic_message(&*payload));
+            summary.bytes_read = rq.body_bytes_read();
+            handler.log(&summary);
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_owned()
+    }
+}
+
+// Body of a response that counts the bytes read by the server, and calls the handler when the
+// server drops it.
+struct SentBody {
+    inner: Box<dyn Read + Send>,
+    handler: Arc<dyn LogHandler>,
+    // Taken when dropped.
+    summary: Option<LogSummary>,
+    bytes_read: Arc<AtomicU64>,
+    // Counted by the server on the connection, if the request was received by the server.
+    bytes_written: Option<Arc<AtomicU64>>,
+    start_instant: Instant,
+}
+
+impl Read for SentBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num = self.inner.read(buf)?;
+        if let Some(ref mut summary) = self.summary {
+            summary.bytes_written += num as u64;
+            if num == 0 && !buf.is_empty() {
+                summary.complete = true;
+            }
+        }
+        Ok(num)
+    }
+}
+
+impl Drop for SentBody {
+    fn drop(&mut self) {
+        if let Some(mut summary) = self.summary.take() {
+            summary.duration = self.start_instant.elapsed();
+            summary.bytes_read = self.bytes_read.load(Ordering::Relaxed);
+            if let Some(ref bytes_written) = self.bytes_written {
+                let bytes_written = bytes_written.load(Ordering::Relaxed);
+                // Nothing is counted when tiny-http upgrades the connection.
+                if bytes_written != 0 {
+                    summary.bytes_written = bytes_written;
+                }
+            }
+            self.handler.log(&summary);
+        }
+    }
+}
+
+/// Middleware that does the same as [`rouille::log_with_handler`](fn.log_with_handler.html).
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use rouille::{HandlerLogger, LogSummary, Request, Response};
+/// use rouille::middleware::Pipeline;
+///
+/// let pipeline = Pipeline::new(|_: &Request| Response::text("hello world"))
+///     .with(HandlerLogger::new(Arc::new(|summary: &LogSummary| {
+///         eprintln!("{} {} {:?}", summary.method, summary.url, summary.duration);
+///     })));
+/// ```
+pub struct HandlerLogger {
+    handler: Arc<dyn LogHandler>,
+}
+
+impl HandlerLogger {
+    /// Builds a new `HandlerLogger` that passes the summaries to `handler`.
+    #[inline]
+    pub fn new(handler: Arc<dyn LogHandler>) -> HandlerLogger {
+        HandlerLogger { handler }
+    }
+}
+
+impl fmt::Debug for HandlerLogger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HandlerLogger").finish()
+    }
+}
+
+impl Middleware for HandlerLogger {
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        log_with_handler(request, self.handler.clone(), || next(request))
+    }
+}
+
 fn format_time(duration: Duration) -> String {
     let secs_part = match duration.as_secs().checked_mul(1_000_000_000) {
         Some(v) => v,
@@ -151,3 +779,139 @@ fn format_time(duration: Duration) -> String {
         format!("{:.1}s", duration_in_ns as f64 / 1_000_000_000.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::log_json;
+    use super::log_with_format;
+    use super::log_with_handler;
+    use super::LogFormat;
+    use super::LogFormatError;
+    use super::LogSummary;
+    use serde_json::Value;
+    use std::io::Read;
+    use std::panic;
+    use std::sync::{Arc, Mutex};
+    use Request;
+    use Response;
+
+    #[test]
+    fn json() {
+        let headers = vec![
+            ("X-Request-Id".to_owned(), "abc".to_owned()),
+            ("User-Agent".to_owned(), "curl".to_owned()),
+        ];
+        let request = Request::fake_http("GET", "/a?b", headers, vec![]);
+        let mut output = Vec::new();
+        log_json(&request, &mut output, || Response::text("hello"));
+
+        let entry: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/a?b");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes"], 5);
+        assert_eq!(entry["request_id"], "abc");
+        assert_eq!(entry["user_agent"], "curl");
+        assert!(entry["latency_ms"].is_f64());
+        assert!(entry.get("panic").is_none());
+        assert_eq!(output.last(), Some(&b'\n'));
+
+        let request = Request::fake_http("POST", "/", vec![], vec![]);
+        let mut output = Vec::new();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            log_json(&request, &mut output, || panic!("oops"))
+        }));
+        assert!(result.is_err());
+        let entry: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(entry["status"], Value::Null);
+        assert_eq!(entry["panic"], true);
+        assert_eq!(entry["request_id"], Value::Null);
+    }
+
+    #[test]
+    fn format() {
+        let headers = vec![("X-Forwarded-For".to_owned(), "10.0.0.1".to_owned())];
+        let request = Request::fake_http("GET", "/a?b=c", headers, vec![]);
+        let format = LogFormat::parse(
+            "%h %l \"%r\" %>s %b %m %U|%q %{X-Forwarded-For}i %{Content-Type}o %{Missing}i 100%%",
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        log_with_format(&request, &mut output, &format, || Response::text("hello"));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "127.0.0.1 - \"GET /a?b=c HTTP/1.1\" 200 5 GET /a|?b=c 10.0.0.1 \
+             text/plain; charset=utf-8 - 100%\n"
+        );
+
+        let format = LogFormat::parse("%t %D").unwrap();
+        let mut output = Vec::new();
+        log_with_format(&request, &mut output, &format, || Response::text(""));
+        let line = String::from_utf8(output).unwrap();
+        assert!(line.starts_with('[') && line.contains(" +0000] "));
+
+        assert!(LogFormat::parse(LogFormat::COMBINED).is_ok());
+        assert_eq!(
+            LogFormat::parse("a %z"),
+            Err(LogFormatError::UnknownToken(2))
+        );
+        assert_eq!(
+            LogFormat::parse("%{Host"),
+            Err(LogFormatError::UnfinishedToken(0))
+        );
+    }
+
+    #[test]
+    fn handler() {
+        let summaries = Arc::new(Mutex::new(Vec::<LogSummary>::new()));
+        let handler = {
+            let summaries = summaries.clone();
+            Arc::new(move |s: &LogSummary| summaries.lock().unwrap().push(s.clone()))
+        };
+
+        let request = Request::fake_http("GET", "/a", vec![], vec![]);
+        let response = log_with_handler(&request, handler.clone(), || Response::text("hello"));
+        assert!(summaries.lock().unwrap().is_empty());
+        let (mut body, len) = response.data.into_reader_and_size();
+        assert_eq!(len, Some(5));
+        body.read_to_end(&mut Vec::new()).unwrap();
+        drop(body);
+
+        let response = log_with_handler(&request, handler.clone(), || Response::text("hello"));
+        let (mut body, _) = response.data.into_reader_and_size();
+        body.read_exact(&mut [0; 2]).unwrap();
+        drop(body);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            log_with_handler(&request, handler.clone(), || panic!("oops"))
+        }));
+        assert!(result.is_err());
+
+        let request = Request::fake_http("POST", "/b", vec![], b"hello".to_vec());
+        let response = log_with_handler(&request, handler.clone(), || {
+            request.data().unwrap().read_exact(&mut [0; 3]).unwrap();
+            Response::empty_204()
+        });
+        drop(response);
+
+        let summaries = summaries.lock().unwrap();
+        assert_eq!(summaries.len(), 4);
+        assert_eq!(
+            (summaries[0].status_code, &summaries[0].url[..]),
+            (200, "/a")
+        );
+        assert_eq!(
+            (summaries[0].bytes_written, summaries[0].complete),
+            (5, true)
+        );
+        assert!(summaries[0].duration >= summaries[0].handler_duration);
+        assert_eq!(
+            (summaries[1].bytes_written, summaries[1].complete),
+            (2, false)
+        );
+        assert_eq!(summaries[2].status_code, 500);
+        assert_eq!(summaries[2].panic, Some("oops".to_owned()));
+        assert_eq!(summaries[0].bytes_read, 0);
+        assert_eq!(summaries[3].bytes_read, 3);
+    }
+}
@@ -0,0 +1,446 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Serving static files, proxying and redirecting according to a configuration file.
+//!
+//! A [`Mappings`](struct.Mappings.html) middleware maps path prefixes to a directory of static
+//! files, another server, or a redirection. The requests whose path doesn't start with any of
+//! the prefixes are passed to the handler. This makes it possible to change a simple setup
+//! without recompiling the server, by editing a JSON file that is reloaded when it changes:
+//!
+//! ```json
+//! [
+//!     { "prefix": "/static", "assets": "public" },
+//!     { "prefix": "/api", "host": "{tenant}.example.com", "proxy": "localhost:8000" },
+//!     { "prefix": "/blog", "redirect": "https://blog.example.com" }
+//! ]
+//! ```
+//!
+//! A prefix matches the paths that are equal to it or that continue with a `/`, so that `/api`
+//! matches `/api` and `/api/users` but not `/apis`. The `host` is an optional pattern with the
+//! syntax of [`match_host`](../fn.match_host.html). The mappings are evaluated in order, and the
+//! first one that matches is used.
+//!
+//! - `assets` serves the files of a directory with
+//!   [`match_assets`](../fn.match_assets.html), after removing the prefix from the path. If the
+//!   file doesn't exist, the request is passed to the handler.
+//! - `proxy` dispatches the request, with its full path, to another server with
+//!   [`proxy::gateway_proxy`](../proxy/fn.gateway_proxy.html).
+//! - `redirect` answers `302 Found`, with the prefix of the path replaced with the target.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rouille::{Request, Response};
+//! use rouille::mapping::Mappings;
+//! use rouille::middleware::Pipeline;
+//!
+//! let mappings = Mappings::load("mappings.json").unwrap().watch(Duration::from_secs(5));
+//!
+//! let pipeline = Pipeline::new(|request: &Request| Response::text("hello world"))
+//!     .with(mappings);
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| pipeline.handle(request));
+//! ```
+
+use serde_json;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use match_assets;
+use match_host;
+use middleware::Middleware;
+use proxy;
+use proxy::ProxyConfig;
+use Request;
+use Response;
+
+/// Where the requests that match a mapping go.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    /// Serves the static files of a directory.
+    Assets(PathBuf),
+    /// Dispatches the requests to the server at this address, such as `localhost:8000`.
+    Proxy(String),
+    /// Redirects to this URL.
+    Redirect(String),
+}
+
+/// A path prefix and the target of the requests that start with it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Mapping {
+    prefix: String,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(flatten)]
+    target: Target,
+}
+
+impl Mapping {
+    /// Builds a mapping of the requests whose path starts with `prefix`.
+    pub fn new<P>(prefix: P, target: Target) -> Mapping
+    where
+        P: Into<String>,
+    {
+        Mapping {
+            prefix: prefix.into(),
+            host: None,
+            target,
+        }
+    }
+
+    /// Only matches the requests whose host matches this pattern.
+    #[inline]
+    pub fn host<H>(mut self, pattern: H) -> Mapping
+    where
+        H: Into<String>,
+    {
+        self.host = Some(pattern.into());
+        self
+    }
+
+    /// Returns the target of the mapping.
+    #[inline]
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Returns true if the request matches the mapping.
+    pub fn matches(&self, request: &Request) -> bool {
+        if let Some(ref pattern) = self.host {
+            match request.host() {
+                Some(ref host) if match_host(pattern, host).is_some() => (),
+                _ => return false,
+            }
+        }
+
+        request.strip_path_prefix(&self.prefix).is_some()
+    }
+
+    // Answers a request that matches the mapping, or returns `None` to pass it to the handler.
+    fn respond(&self, request: &Request) -> Option<Response> {
+        match self.target {
+            Target::Assets(ref root) => {
+                let stripped = request.strip_path_prefix(&self.prefix)?;
+                let response = match_assets(&stripped, root);
+                if response.is_success() {
+                    Some(response)
+                } else {
+                    None
+                }
+            }
+            Target::Proxy(ref addr) => {
+                let config = ProxyConfig::new(&addr[..]);
+                Some(
+                    proxy::gateway_proxy(request, addr.clone(), config)
+                        .unwrap_or_else(|_| Response::text("Bad Gateway").with_status_code(502)),
+                )
+            }
+            Target::Redirect(ref target) => {
+                let prefix = self.prefix.trim_end_matches('/');
+                let rest = &request.raw_url()[prefix.len()..];
+                let target = format!("{}{}", target.trim_end_matches('/'), rest);
+                Some(Response::redirect_302(target))
+            }
+        }
+    }
+}
+
+/// Error that can happen when loading mappings.
+#[derive(Debug)]
+pub enum MappingsError {
+    /// The file couldn't be read.
+    IoError(io::Error),
+    /// The mappings aren't valid JSON, or don't have the expected format.
+    ParseError(serde_json::Error),
+}
+
+impl From<io::Error> for MappingsError {
+    #[inline]
+    fn from(err: io::Error) -> MappingsError {
+        MappingsError::IoError(err)
+    }
+}
+
+impl From<serde_json::Error> for MappingsError {
+    #[inline]
+    fn from(err: serde_json::Error) -> MappingsError {
+        MappingsError::ParseError(err)
+    }
+}
+
+impl error::Error for MappingsError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            MappingsError::IoError(ref e) => Some(e),
+            MappingsError::ParseError(ref e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for MappingsError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            MappingsError::IoError(_) => "could not read the mappings",
+            MappingsError::ParseError(_) => "could not parse the mappings",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// A list of mappings, evaluated in order before the handler.
+pub struct Mappings {
+    mappings: RwLock<Arc<Vec<Mapping>>>,
+    source: Option<Source>,
+}
+
+// The file that the mappings were loaded from.
+struct Source {
+    path: PathBuf,
+    watch: Option<Duration>,
+    // When the file was last checked, and its modification time then.
+    checked: Mutex<(Instant, Option<SystemTime>)>,
+}
+
+impl Mappings {
+    /// Builds a list of mappings.
+    pub fn new(mappings: Vec<Mapping>) -> Mappings {
+        Mappings {
+            mappings: RwLock::new(Arc::new(mappings)),
+            source: None,
+        }
+    }
+
+    /// Parses a JSON list of mappings.
+    pub fn from_json(json: &str) -> Result<Mappings, MappingsError> {
+        Ok(Mappings::new(serde_json::from_str(json)?))
+    }
+
+    /// Loads a JSON list of mappings from a file. Call [`reload`](#method.reload) or
+    /// [`watch`](#method.watch) to pick up the modifications of the file.
+    ///
+    /// The relative paths of the `assets` directories are relative to the current directory,
+    /// not to the file.
+    pub fn load<P>(path: P) -> Result<Mappings, MappingsError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_owned();
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let mappings = serde_json::from_str(&fs::read_to_string(&path)?)?;
+
+        Ok(Mappings {
+            mappings: RwLock::new(Arc::new(mappings)),
+            source: Some(Source {
+                path,
+                watch: None,
+                checked: Mutex::new((Instant::now(), modified)),
+            }),
+        })
+    }
+
+    /// Checks whether the file that the mappings were loaded from has been modified at most
+    /// once per `interval`, while processing a request, and reloads it if so.
+    ///
+    /// If the file can't be loaded, the previous mappings are kept.
+    #[inline]
+    pub fn watch(mut self, interval: Duration) -> Mappings {
+        if let Some(ref mut source) = self.source {
+            source.watch = Some(interval);
+        }
+        self
+    }
+
+    /// Loads the mappings from the file again. Does nothing if the mappings weren't loaded from
+    /// a file.
+    ///
+    /// If the file can't be loaded, the previous mappings are kept and the error is returned.
+    pub fn reload(&self) -> Result<(), MappingsError> {
+        let source = match self.source {
+            Some(ref source) => source,
+            None => return Ok(()),
+        };
+
+        let mappings: Vec<Mapping> = serde_json::from_str(&fs::read_to_string(&source.path)?)?;
+        self.replace(mappings);
+        Ok(())
+    }
+
+    /// Replaces the mappings.
+    pub fn replace(&self, mappings: Vec<Mapping>) {
+        let mut current = match self.mappings.write() {
+            Ok(m) => m,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *current = Arc::new(mappings);
+    }
+
+    /// Returns the current mappings.
+    pub fn mappings(&self) -> Arc<Vec<Mapping>> {
+        match self.mappings.read() {
+            Ok(m) => m.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn reload_if_modified(&self) {
+        let source = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+        let interval = match source.watch {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let mut checked = match source.checked.lock() {
+            Ok(c) => c,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if checked.0.elapsed() < interval {
+            return;
+        }
+        checked.0 = Instant::now();
+
+        let modified = fs::metadata(&source.path).and_then(|m| m.modified()).ok();
+        if modified != checked.1 && self.reload().is_ok() {
+            checked.1 = modified;
+        }
+    }
+}
+
+impl fmt::Debug for Mappings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Mappings")
+            .field("mappings", &self.mappings())
+            .field("path", &self.source.as_ref().map(|s| &s.path))
+            .finish()
+    }
+}
+
+impl Middleware for Mappings {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        self.reload_if_modified();
+
+        let mappings = self.mappings();
+        match mappings.iter().find(|m| m.matches(request)) {
+            Some(mapping) => mapping.respond(request).unwrap_or_else(|| next(request)),
+            None => next(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mapping;
+    use super::Mappings;
+    use super::Target;
+    use middleware::Pipeline;
+    use std::env;
+    use std::fs;
+    use test::TestResponse;
+    use Request;
+    use Response;
+
+    fn pipeline(mappings: Mappings) -> Pipeline<impl Fn(&Request) -> Response> {
+        Pipeline::new(|request: &Request| Response::text(request.raw_url().to_owned()))
+            .with(mappings)
+    }
+
+    #[test]
+    fn parse() {
+        let mappings = Mappings::from_json(
+            r#"[
+                { "prefix": "/static", "assets": "public" },
+                { "prefix": "/api", "host": "api.example.com", "proxy": "localhost:8000" },
+                { "prefix": "/old", "redirect": "/new" }
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            *mappings.mappings(),
+            vec![
+                Mapping::new("/static", Target::Assets("public".into())),
+                Mapping::new("/api", Target::Proxy("localhost:8000".into()))
+                    .host("api.example.com"),
+                Mapping::new("/old", Target::Redirect("/new".into())),
+            ]
+        );
+        assert!(Mappings::from_json(r#"[{ "prefix": "/" }]"#).is_err());
+    }
+
+    #[test]
+    fn redirect_and_host() {
+        let pipeline = pipeline(Mappings::new(vec![
+            Mapping::new("/old", Target::Redirect("/new/".into())),
+            Mapping::new("/api", Target::Redirect("http://api".into())).host("api.example.com"),
+        ]));
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/old/a?b=c", vec![], vec![]));
+        assert_eq!(response.status_code, 302);
+        assert_eq!(response.headers[0].1, "/new/a?b=c");
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/old", vec![], vec![]));
+        assert_eq!(response.headers[0].1, "/new");
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/oldest", vec![], vec![]));
+        assert_eq!(TestResponse::from(response).text(), "/oldest");
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/api/x", vec![], vec![]));
+        assert_eq!(TestResponse::from(response).text(), "/api/x");
+
+        let host = vec![("Host".to_owned(), "api.example.com".to_owned())];
+        let response = pipeline.handle(&Request::fake_http("GET", "/api/x", host, vec![]));
+        assert_eq!(response.status_code, 302);
+    }
+
+    #[test]
+    fn assets_and_reload() {
+        let dir = env::temp_dir().join(format!("rouille-mapping-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("public")).unwrap();
+        fs::write(dir.join("public").join("a.txt"), "file").unwrap();
+        let config = dir.join("mappings.json");
+        let public = dir.join("public");
+        fs::write(
+            &config,
+            format!(r#"[{{ "prefix": "/static", "assets": {:?} }}]"#, public),
+        )
+        .unwrap();
+
+        let mappings = Mappings::load(&config).unwrap();
+        fs::write(&config, "invalid").unwrap();
+        assert!(mappings.reload().is_err());
+        assert_eq!(mappings.mappings().len(), 1);
+
+        let pipeline = pipeline(mappings);
+        let handle = |url: &str| {
+            TestResponse::from(pipeline.handle(&Request::fake_http("GET", url, vec![], vec![])))
+                .text()
+        };
+        assert_eq!(handle("/static/a.txt"), "file");
+        assert_eq!(handle("/static/b.txt"), "/static/b.txt");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Rendering markdown documents to HTML.
+//!
+//! This module is only available if you enable the `markdown` feature of rouille.
+//!
+//! The main entry points are [`Response::markdown`](../struct.Response.html#method.markdown) and
+//! [`Response::markdown_with_options`](../struct.Response.html#method.markdown_with_options).
+//! You can also call [`render`](fn.render.html) directly if you only need the HTML.
+//!
+//! The output is sanitized: raw HTML found in the markdown source is escaped instead of being
+//! passed through, and links or images whose destination uses a scheme such as `javascript:`
+//! are neutralized.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::Response;
+//! use rouille::markdown::MarkdownOptions;
+//!
+//! let options = MarkdownOptions {
+//!     template: Some("<html><body>{{content}}</body></html>".into()),
+//!     .. MarkdownOptions::default()
+//! };
+//!
+//! let response = Response::markdown_with_options("# Hello world", &options);
+//! ```
+
+use std::borrow::Cow;
+use std::fmt;
+
+use pulldown_cmark::escape::escape_html;
+use pulldown_cmark::html;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+
+/// The string that is replaced with the rendered HTML in `MarkdownOptions::template`.
+pub const CONTENT_PLACEHOLDER: &str = "{{content}}";
+
+/// Signature of a function that highlights a code block.
+///
+/// The first parameter is the language of the block if one was specified after the opening
+/// fence, and the second parameter is the raw content of the block. The function must return
+/// HTML that will be inserted as-is in the output, so it is responsible for escaping the code.
+pub type CodeHighlighter = dyn Fn(Option<&str>, &str) -> String + Send + Sync;
+
+/// Configuration for rendering markdown.
+pub struct MarkdownOptions {
+    /// If `Some`, the rendered HTML is inserted into this template in place of the
+    /// `{{content}}` placeholder. Use this to wrap the document in a complete page with your own
+    /// `<head>` and stylesheets.
+    pub template: Option<Cow<'static, str>>,
+
+    /// If `Some`, called for each code block of the document in order to produce its HTML. If
+    /// `None`, code blocks are rendered as `<pre><code>` with a `language-*` class.
+    pub highlighter: Option<Box<CodeHighlighter>>,
+
+    /// Enables tables, footnotes, strikethrough and task lists. Enabled by default.
+    pub extensions: bool,
+}
+
+impl Default for MarkdownOptions {
+    #[inline]
+    fn default() -> MarkdownOptions {
+        MarkdownOptions {
+            template: None,
+            highlighter: None,
+            extensions: true,
+        }
+    }
+}
+
+impl fmt::Debug for MarkdownOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MarkdownOptions")
+            .field("template", &self.template)
+            .field("highlighter", &self.highlighter.is_some())
+            .field("extensions", &self.extensions)
+            .finish()
+    }
+}
+
+/// Renders a markdown document to sanitized HTML.
+///
+/// # Example
+///
+/// ```
+/// use rouille::markdown;
+///
+/// let html = markdown::render("*hello*", &Default::default());
+/// assert_eq!(html, "<p><em>hello</em></p>\n");
+/// ```
+pub fn render(source: &str, options: &MarkdownOptions) -> String {
+    let mut parser_options = Options::empty();
+    if options.extensions {
+        parser_options.insert(Options::ENABLE_TABLES);
+        parser_options.insert(Options::ENABLE_FOOTNOTES);
+        parser_options.insert(Options::ENABLE_STRIKETHROUGH);
+        parser_options.insert(Options::ENABLE_TASKLISTS);
+    }
+
+    let mut events = Vec::new();
+    // `Some` while we are inside a code block that must go through the highlighter.
+    let mut code_block: Option<(Option<String>, String)> = None;
+
+    for event in Parser::new_ext(source, parser_options) {
+        if let Some((_, ref mut code)) = code_block {
+            match event {
+                Event::Text(text) => {
+                    code.push_str(&text);
+                    continue;
+                }
+                Event::End(Tag::CodeBlock(_)) => (),
+                _ => continue,
+            }
+        }
+
+        let event = match event {
+            Event::Start(Tag::CodeBlock(kind)) if options.highlighter.is_some() => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(ref lang) if !lang.is_empty() => Some(lang.to_string()),
+                    _ => None,
+                };
+                code_block = Some((language, String::new()));
+                continue;
+            }
+            Event::End(Tag::CodeBlock(_)) if code_block.is_some() => {
+                let (language, code) = code_block.take().unwrap();
+                let highlighter = options.highlighter.as_ref().unwrap();
+                Event::Html(highlighter(language.as_ref().map(|l| &l[..]), &code).into())
+            }
+            // Raw HTML is escaped rather than passed through.
+            Event::Html(raw) => Event::Text(raw),
+            Event::Start(Tag::Link(ty, dest, title)) => {
+                Event::Start(Tag::Link(ty, sanitize_url(dest), title))
+            }
+            Event::Start(Tag::Image(ty, dest, title)) => {
+                Event::Start(Tag::Image(ty, sanitize_url(dest), title))
+            }
+            e => e,
+        };
+
+        events.push(event);
+    }
+
+    let mut output = String::with_capacity(source.len() * 3 / 2);
+    html::push_html(&mut output, events.into_iter());
+
+    match options.template {
+        Some(ref template) => template.replace(CONTENT_PLACEHOLDER, &output),
+        None => output,
+    }
+}
+
+/// Escapes some text so that it can be safely inserted in HTML.
+///
+/// This is handy for writing a `highlighter` that doesn't produce any markup by itself.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    // Writing to a `String` can't fail.
+    let _ = escape_html(&mut out, text);
+    out
+}
+
+// Replaces the destination of a link or image with `#` if it uses a scheme that could execute
+// code in the browser.
+fn sanitize_url(dest: CowStr) -> CowStr {
+    let scheme_end = match dest.find(':') {
+        Some(pos) => pos,
+        None => return dest,
+    };
+
+    let scheme = dest[..scheme_end].trim().to_ascii_lowercase();
+    if scheme == "javascript" || scheme == "vbscript" || scheme == "data" {
+        "#".into()
+    } else {
+        dest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use super::MarkdownOptions;
+
+    #[test]
+    fn basic() {
+        let html = render("# Title\n\nSome *text*.", &Default::default());
+        assert_eq!(html, "<h1>Title</h1>\n<p>Some <em>text</em>.</p>\n");
+    }
+
+    #[test]
+    fn raw_html_escaped() {
+        let html = render("<script>alert(1)</script>", &Default::default());
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn javascript_links_removed() {
+        let html = render("[click](javascript:alert(1))", &Default::default());
+        assert_eq!(html, "<p><a href=\"#\">click</a></p>\n");
+
+        let html = render("[click](JavaScript:alert(1))", &Default::default());
+        assert_eq!(html, "<p><a href=\"#\">click</a></p>\n");
+    }
+
+    #[test]
+    fn regular_links_kept() {
+        let html = render("[home](https://example.com)", &Default::default());
+        assert_eq!(html, "<p><a href=\"https://example.com\">home</a></p>\n");
+    }
+
+    #[test]
+    fn template() {
+        let options = MarkdownOptions {
+            template: Some("<body>{{content}}</body>".into()),
+            ..MarkdownOptions::default()
+        };
+
+        assert_eq!(render("hello", &options), "<body><p>hello</p>\n</body>");
+    }
+
+    #[test]
+    fn highlighter() {
+        let options = MarkdownOptions {
+            highlighter: Some(Box::new(|lang, code| {
+                format!("[{}|{}]", lang.unwrap_or("none"), super::escape(code))
+            })),
+            ..MarkdownOptions::default()
+        };
+
+        let html = render("```rust\nfn main() {}\n```\n\n    a < b\n", &options);
+        assert_eq!(html, "[rust|fn main() {}\n][none|a &lt; b\n]");
+    }
+}
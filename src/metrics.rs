@@ -0,0 +1,599 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Counting the requests and exposing them to Prometheus.
+//!
+//! This module is only available with the `metrics` feature.
+//!
+//! A [`Metrics`](struct.Metrics.html) registry collects, for each method, route and status code,
+//! the number of requests, the time it took to answer them and the size of their bodies, as well
+//! as the number of requests being processed. It is filled either by the server, with
+//! [`Server::metrics`](../struct.Server.html#method.metrics), or by a
+//! [`Pipeline`](../middleware/struct.Pipeline.html) since `Arc<Metrics>` is a middleware.
+//! [`Metrics::response`](struct.Metrics.html#method.response) renders the registry in the
+//! [text format of Prometheus](https://prometheus.io/docs/instrumenting/exposition_formats/),
+//! for a `/metrics` route.
+//!
+//! The URL of a request isn't used as a label, because each ID in the URLs would create a new
+//! series. Handlers name their route with [`set_route`](fn.set_route.html) instead, usually with
+//! the pattern of the route. Requests whose route isn't named are counted under `other`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use std::sync::Arc;
+//! use rouille::Response;
+//! use rouille::Server;
+//! use rouille::metrics;
+//! use rouille::metrics::Metrics;
+//!
+//! let registry = Arc::new(Metrics::new());
+//! let exposed = registry.clone();
+//!
+//! let server = Server::new("0.0.0.0:80", move |request| {
+//!     router!(request,
+//!         (GET) (/users/{id: u32}) => {
+//!             metrics::set_route(request, "/users/{id}");
+//!             Response::text(format!("user {}", id))
+//!         },
+//!         (GET) (/metrics) => {
+//!             metrics::set_route(request, "/metrics");
+//!             exposed.response()
+//!         },
+//!         _ => Response::empty_404()
+//!     )
+//! })
+//! .unwrap()
+//! .metrics(registry);
+//! server.run();
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use middleware::Middleware;
+use Extensions;
+use Request;
+use Response;
+use ResponseBody;
+
+/// Upper bounds, in seconds, of the buckets of the duration histogram by default.
+pub const DEFAULT_DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Upper bounds, in bytes, of the buckets of the body size histograms by default.
+pub const DEFAULT_SIZE_BUCKETS: &[f64] = &[
+    100.0,
+    1_000.0,
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    10_000_000.0,
+];
+
+/// Label of the requests whose route wasn't named with `set_route`.
+const OTHER_ROUTE: &str = "other";
+
+/// Content type of the text format of Prometheus.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Registry of the metrics of the requests.
+///
+/// The following metrics are collected:
+///
+/// - `http_requests_total`, a counter with the `method`, `route` and `status` labels;
+/// - `http_requests_in_flight`, a gauge of the requests whose response hasn't been sent yet;
+/// - `http_request_duration_seconds`, a histogram with the `method` and `route` labels of the
+///   time between the reception of the request and the end of the response;
+/// - `http_request_size_bytes`, a histogram of the number of bytes read from the bodies of the
+///   requests, as returned by `Request::body_bytes_read`, with the `route` label;
+/// - `http_response_size_bytes`, a histogram of the number of bytes written on the connection
+///   for the responses, with the `route` label. Like `LogSummary::bytes_written`, this is the
+///   size of the body when the request wasn't received by a rouille server.
+///
+/// A request is counted once its response has been sent, or once the server gives up sending
+/// it. A handler that panics is counted as a `500`.
+pub struct Metrics {
+    in_flight: AtomicUsize,
+    duration_buckets: Vec<f64>,
+    size_buckets: Vec<f64>,
+    series: Mutex<Series>,
+}
+
+#[derive(Default)]
+struct Series {
+    // Indexed by method, route and status code.
+    requests: BTreeMap<(String, String, u16), u64>,
+    // Indexed by method and route.
+    durations: BTreeMap<(String, String), Histogram>,
+    // Indexed by route.
+    request_sizes: BTreeMap<String, Histogram>,
+    response_sizes: BTreeMap<String, Histogram>,
+}
+
+struct Histogram {
+    // Number of observations in each bucket, not cumulated. The last element is the `+Inf`
+    // bucket.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(buckets: usize) -> Histogram {
+        Histogram {
+            counts: vec![0; buckets + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        let pos = bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(bounds.len());
+        self.counts[pos] += 1;
+        self.sum += value;
+    }
+}
+
+// Route of a request, inserted in its extensions by `set_route`.
+struct Route(String);
+
+/// Sets the `route` label of the metrics of the request, usually to the pattern of the route
+/// that matched it, such as `/users/{id}`.
+///
+/// The number of distinct routes should be bounded: don't use values that come from the
+/// request.
+#[inline]
+pub fn set_route<S>(request: &Request, route: S)
+where
+    S: Into<String>,
+{
+    request.extensions().insert(Route(route.into()));
+}
+
+impl Metrics {
+    /// Builds an empty registry with the default buckets.
+    #[inline]
+    pub fn new() -> Metrics {
+        Metrics {
+            in_flight: AtomicUsize::new(0),
+            duration_buckets: DEFAULT_DURATION_BUCKETS.to_vec(),
+            size_buckets: DEFAULT_SIZE_BUCKETS.to_vec(),
+            series: Mutex::new(Series::default()),
+        }
+    }
+
+    /// Sets the upper bounds, in seconds, of the buckets of the duration histogram.
+    ///
+    /// The bounds are sorted, and the `+Inf` bucket is always added.
+    #[inline]
+    pub fn with_duration_buckets(mut self, buckets: &[f64]) -> Metrics {
+        self.duration_buckets = sorted(buckets);
+        self
+    }
+
+    /// Sets the upper bounds, in bytes, of the buckets of the body size histograms.
+    ///
+    /// The bounds are sorted, and the `+Inf` bucket is always added.
+    #[inline]
+    pub fn with_size_buckets(mut self, buckets: &[f64]) -> Metrics {
+        self.size_buckets = sorted(buckets);
+        self
+    }
+
+    /// Returns the number of requests being processed.
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests answered with the given method, route and status code.
+    pub fn requests(&self, method: &str, route: &str, status_code: u16) -> u64 {
+        let series = self.series.lock().unwrap();
+        series
+            .requests
+            .get(&(method.to_owned(), route.to_owned(), status_code))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Renders the metrics in the text format of Prometheus.
+    pub fn render(&self) -> String {
+        let series = self.series.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Number of HTTP requests answered.",
+        );
+        for (&(ref method, ref route, status), count) in &series.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "http_requests_in_flight",
+            "gauge",
+            "Number of HTTP requests being processed.",
+        );
+        let _ = writeln!(out, "http_requests_in_flight {}", self.in_flight());
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time spent answering HTTP requests.",
+        );
+        for ((method, route), histogram) in &series.durations {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            write_histogram(
+                &mut out,
+                "http_request_duration_seconds",
+                &labels,
+                &self.duration_buckets,
+                histogram,
+            );
+        }
+
+        let sizes = vec![
+            (
+                "http_request_size_bytes",
+                "Size of the bodies of the HTTP requests.",
+                &series.request_sizes,
+            ),
+            (
+                "http_response_size_bytes",
+                "Number of bytes sent for the HTTP responses.",
+                &series.response_sizes,
+            ),
+        ];
+        for (name, help, histograms) in sizes {
+            header(&mut out, name, "histogram", help);
+            for (route, histogram) in histograms {
+                let labels = format!("route=\"{}\"", escape(route));
+                write_histogram(&mut out, name, &labels, &self.size_buckets, histogram);
+            }
+        }
+
+        out
+    }
+
+    /// Builds a response that contains the metrics in the text format of Prometheus.
+    #[inline]
+    pub fn response(&self) -> Response {
+        Response::from_data(CONTENT_TYPE, self.render()).with_no_cache()
+    }
+
+    // Starts counting a request that was received at `start`. The request is recorded when the
+    // returned value is finished or dropped.
+    pub(crate) fn begin(self: &Arc<Self>, request: &Request, start: Instant) -> Pending {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Pending {
+            metrics: self.clone(),
+            method: request.method().to_owned(),
+            extensions: request.extensions.clone(),
+            bytes_read: request.body_bytes_counter(),
+            bytes_written: request.bytes_written_counter(),
+            start,
+            recorded: false,
+        }
+    }
+}
+
+impl Default for Metrics {
+    #[inline]
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("in_flight", &self.in_flight())
+            .field("duration_buckets", &self.duration_buckets)
+            .field("size_buckets", &self.size_buckets)
+            .finish()
+    }
+}
+
+/// Answers the request with `handler` and records it in `metrics`.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Arc;
+/// use rouille::{Request, Response};
+/// use rouille::metrics::{self, Metrics};
+///
+/// let registry = Arc::new(Metrics::new());
+///
+/// fn handle(registry: &Arc<Metrics>, request: &Request) -> Response {
+///     metrics::instrument(registry, request, || {
+///         metrics::set_route(request, "/");
+///         Response::text("hello world")
+///     })
+/// }
+/// ```
+pub fn instrument<F>(metrics: &Arc<Metrics>, request: &Request, handler: F) -> Response
+where
+    F: FnOnce() -> Response,
+{
+    let pending = metrics.begin(request, Instant::now());
+    let response = handler();
+    pending.finish(response)
+}
+
+impl Middleware for Arc<Metrics> {
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        instrument(self, request, || next(request))
+    }
+}
+
+// A request being processed. If it's dropped without being finished, for example because the
+// handler panicked, the request is recorded as a `500`.
+pub(crate) struct Pending {
+    metrics: Arc<Metrics>,
+    method: String,
+    extensions: Arc<Mutex<Extensions>>,
+    bytes_read: Arc<AtomicU64>,
+    // Counted by the server on the connection, if the request was received by the server.
+    bytes_written: Option<Arc<AtomicU64>>,
+    start: Instant,
+    recorded: bool,
+}
+
+impl Pending {
+    // Wraps the body of the response so that the request is recorded once the body is
+    // dropped, with the number of bytes that were read from it.
+    pub(crate) fn finish(self, mut response: Response) -> Response {
+        let status_code = response.status_code;
+        let data = mem::replace(&mut response.data, ResponseBody::empty());
+        response.data = data.map_reader(|inner| CountedBody {
+            inner,
+            pending: self,
+            status_code,
+            bytes: 0,
+        });
+        response
+    }
+
+    fn record(&mut self, status_code: u16, response_size: u64) {
+        if self.recorded {
+            return;
+        }
+        self.recorded = true;
+
+        let metrics = &*self.metrics;
+        metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        let route = {
+            let extensions = match self.extensions.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            extensions
+                .get::<Route>()
+                .map_or_else(|| OTHER_ROUTE.to_owned(), |route| route.0.clone())
+        };
+        let request_size = self.bytes_read.load(Ordering::Relaxed);
+        // Nothing is counted on the connection when tiny-http upgrades it.
+        let response_size = self
+            .bytes_written
+            .as_ref()
+            .map(|bytes_written| bytes_written.load(Ordering::Relaxed))
+            .filter(|&bytes_written| bytes_written != 0)
+            .unwrap_or(response_size);
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        let mut series = metrics.series.lock().unwrap();
+        *series
+            .requests
+            .entry((self.method.clone(), route.clone(), status_code))
+            .or_insert(0) += 1;
+        series
+            .durations
+            .entry((self.method.clone(), route.clone()))
+            .or_insert_with(|| Histogram::new(metrics.duration_buckets.len()))
+            .observe(&metrics.duration_buckets, seconds);
+        series
+            .request_sizes
+            .entry(route.clone())
+            .or_insert_with(|| Histogram::new(metrics.size_buckets.len()))
+            .observe(&metrics.size_buckets, request_size as f64);
+        series
+            .response_sizes
+            .entry(route)
+            .or_insert_with(|| Histogram::new(metrics.size_buckets.len()))
+            .observe(&metrics.size_buckets, response_size as f64);
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.record(500, 0);
+    }
+}
+
+// Body of a response that counts the bytes read by the server, and records the request when the
+// server drops it.
+struct CountedBody {
+    inner: Box<dyn Read + Send>,
+    pending: Pending,
+    status_code: u16,
+    bytes: u64,
+}
+
+impl Read for CountedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num = self.inner.read(buf)?;
+        self.bytes += num as u64;
+        Ok(num)
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.pending.record(self.status_code, self.bytes);
+    }
+}
+
+fn sorted(buckets: &[f64]) -> Vec<f64> {
+    let mut buckets: Vec<f64> = buckets.iter().cloned().filter(|b| b.is_finite()).collect();
+    buckets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    buckets.dedup();
+    buckets
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    labels: &str,
+    bounds: &[f64],
+    histogram: &Histogram,
+) {
+    let mut cumulated = 0;
+    for (bound, count) in bounds.iter().zip(&histogram.counts) {
+        cumulated += count;
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"{}\"}} {}",
+            name, labels, bound, cumulated
+        );
+    }
+    cumulated += histogram.counts[bounds.len()];
+    let _ = writeln!(
+        out,
+        "{}_bucket{{{},le=\"+Inf\"}} {}",
+        name, labels, cumulated
+    );
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, cumulated);
+}
+
+// Escapes a label value of the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{instrument, set_route, Metrics};
+    use middleware::Pipeline;
+    use std::io::Read;
+    use std::panic;
+    use std::panic::AssertUnwindSafe;
+    use std::sync::Arc;
+    use Request;
+    use Response;
+
+    fn consume(response: Response) {
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body).unwrap();
+    }
+
+    #[test]
+    fn counts_requests() {
+        let metrics = Arc::new(Metrics::new());
+        // The size of the request is counted without a `Content-Length`, as with a chunked body.
+        let request = Request::fake_http("POST", "/users/12", vec![], b"hello".to_vec());
+
+        let response = instrument(&metrics, &request, || {
+            set_route(&request, "/users/{id}");
+            request
+                .data()
+                .unwrap()
+                .read_to_end(&mut Vec::new())
+                .unwrap();
+            Response::text("created").with_status_code(201)
+        });
+        assert_eq!(metrics.in_flight(), 1);
+        assert_eq!(metrics.requests("POST", "/users/{id}", 201), 0);
+        consume(response);
+        assert_eq!(metrics.in_flight(), 0);
+        assert_eq!(metrics.requests("POST", "/users/{id}", 201), 1);
+
+        let request = Request::fake_http("GET", "/missing", vec![], vec![]);
+        consume(instrument(&metrics, &request, Response::empty_404));
+        assert_eq!(metrics.requests("GET", "other", 404), 1);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "http_requests_total{method=\"POST\",route=\"/users/{id}\",status=\"201\"} 1\n"
+        ));
+        assert!(rendered.contains("http_requests_in_flight 0\n"));
+        assert!(rendered
+            .contains("http_request_size_bytes_bucket{route=\"/users/{id}\",le=\"100\"} 1\n"));
+        assert!(rendered.contains("http_request_size_bytes_sum{route=\"/users/{id}\"} 5\n"));
+        assert!(rendered.contains("http_response_size_bytes_sum{route=\"/users/{id}\"} 7\n"));
+        assert!(rendered
+            .contains("http_request_duration_seconds_count{method=\"GET\",route=\"other\"} 1\n"));
+        assert!(rendered.contains("# TYPE http_request_duration_seconds histogram\n"));
+    }
+
+    #[test]
+    fn panics_count_as_errors() {
+        let metrics = Arc::new(Metrics::new());
+        let pipeline =
+            Pipeline::new(|_: &Request| -> Response { panic!("oops") }).with(metrics.clone());
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| pipeline.handle(&request)));
+        assert!(result.is_err());
+        assert_eq!(metrics.in_flight(), 0);
+        assert_eq!(metrics.requests("GET", "other", 500), 1);
+    }
+
+    #[test]
+    fn buckets() {
+        let metrics = Arc::new(Metrics::new().with_size_buckets(&[10.0, 1.0]));
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        consume(instrument(&metrics, &request, || Response::text("hello")));
+        let rendered = metrics.render();
+        assert!(rendered.contains("http_response_size_bytes_bucket{route=\"other\",le=\"1\"} 0\n"));
+        assert!(rendered.contains("http_response_size_bytes_bucket{route=\"other\",le=\"10\"} 1\n"));
+        assert!(
+            rendered.contains("http_response_size_bytes_bucket{route=\"other\",le=\"+Inf\"} 1\n")
+        );
+    }
+}
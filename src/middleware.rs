@@ -0,0 +1,338 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Composing cross-cutting behaviour around a handler.
+//!
+//! A [`Middleware`](trait.Middleware.html) is code that runs before and/or after the handler of
+//! every request, such as authentication, logging or compression. A
+//! [`Pipeline`](struct.Pipeline.html) wraps a handler with a list of middlewares.
+//!
+//! Rouille provides the following middlewares:
+//!
+//! - [`Logger`](../struct.Logger.html) writes a line for each request, like
+//!   [`rouille::log`](../fn.log.html).
+//! - [`content_encoding::ContentEncoding`](../content_encoding/struct.ContentEncoding.html)
+//!   compresses responses, like [`content_encoding::apply`](../content_encoding/fn.apply.html).
+//! - [`session::SessionMiddleware`](../session/struct.SessionMiddleware.html) assigns a session
+//!   to each client, like [`session::session`](../session/fn.session.html).
+//! - [`policy::Policy`](../policy/struct.Policy.html) enforces the limits of a route, such as
+//!   the maximum size of the body.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::io;
+//! use rouille::{Request, Response};
+//! use rouille::content_encoding::ContentEncoding;
+//! use rouille::Logger;
+//! use rouille::middleware::{Middleware, Pipeline};
+//!
+//! struct RequireToken;
+//!
+//! impl Middleware for RequireToken {
+//!     fn before(&self, request: &Request) -> Option<Response> {
+//!         if request.header("X-Token") == Some("secret") {
+//!             None
+//!         } else {
+//!             Some(Response::text("forbidden").with_status_code(403))
+//!         }
+//!     }
+//! }
+//!
+//! let pipeline = Pipeline::new(|request: &Request| Response::text("hello world"))
+//!     .with(Logger::new(io::stdout))
+//!     .with(ContentEncoding)
+//!     .with(RequireToken);
+//!
+//! rouille::start_server("0.0.0.0:8000", move |request| pipeline.handle(request));
+//! ```
+
+use std::cell::RefCell;
+use std::fmt;
+
+use Request;
+use Response;
+
+/// Code that runs around the handler of a request.
+///
+/// Most middlewares only need to implement `before`, `after`, or both. Middlewares that need to
+/// keep some state for the duration of a request, or to run the rest of the pipeline within a
+/// specific context, can override `handle` instead.
+///
+/// A middleware can pass data to the handler, such as the authenticated user, by inserting it in
+/// [`Request::extensions`](../struct.Request.html#method.extensions).
+pub trait Middleware: Send + Sync {
+    /// Called before the handler. If this returns `Some`, the handler and the remaining
+    /// middlewares are skipped and the response is returned immediately.
+    ///
+    /// The default implementation returns `None`.
+    #[inline]
+    fn before(&self, request: &Request) -> Option<Response> {
+        let _ = request;
+        None
+    }
+
+    /// Called with the response produced by the handler, or by a middleware further down the
+    /// pipeline. Returns the response to send to the client.
+    ///
+    /// The default implementation returns the response unchanged.
+    #[inline]
+    fn after(&self, request: &Request, response: Response) -> Response {
+        let _ = request;
+        response
+    }
+
+    /// Processes a request. `next` runs the rest of the pipeline, including the handler.
+    ///
+    /// The default implementation calls `before`, then `next` if `before` returned `None`, then
+    /// `after`. Note that `after` is not called if `before` short-circuits.
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        if let Some(response) = self.before(request) {
+            return response;
+        }
+
+        let response = next(request);
+        self.after(request, response)
+    }
+}
+
+/// A handler wrapped with a list of middlewares.
+///
+/// Middlewares run in the order in which they are added: the first one sees the request first
+/// and the response last.
+pub struct Pipeline<F> {
+    handler: F,
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl<F> Pipeline<F>
+where
+    F: Fn(&Request) -> Response,
+{
+    /// Builds a new `Pipeline` around a handler, without any middleware.
+    #[inline]
+    pub fn new(handler: F) -> Pipeline<F> {
+        Pipeline {
+            handler,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Adds a middleware at the end of the pipeline, right before the handler.
+    #[inline]
+    pub fn with<M>(mut self, middleware: M) -> Pipeline<F>
+    where
+        M: Middleware + 'static,
+    {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Processes a request by running it through all the middlewares and the handler.
+    #[inline]
+    pub fn handle(&self, request: &Request) -> Response {
+        self.handle_from(0, request)
+    }
+
+    fn handle_from(&self, index: usize, request: &Request) -> Response {
+        match self.middlewares.get(index) {
+            Some(middleware) => {
+                middleware.handle(request, &|request| self.handle_from(index + 1, request))
+            }
+            None => (self.handler)(request),
+        }
+    }
+}
+
+impl<F> fmt::Debug for Pipeline<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("middlewares", &self.middlewares.len())
+            .finish()
+    }
+}
+
+/// Runs `handler` through a list of middlewares, the first one seeing the request first and the
+/// response last, like in a `Pipeline`.
+///
+/// This is what the guards of the [`router!`](../macro.router.html) macro use. Contrary to a
+/// `Pipeline`, the handler doesn't receive the request, and is called at most once.
+///
+/// # Panic
+///
+/// Panics if one of the middlewares calls `next` more than once.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Request, Response};
+/// use rouille::middleware::{self, Middleware};
+///
+/// struct RequireToken;
+///
+/// impl Middleware for RequireToken {
+///     fn before(&self, request: &Request) -> Option<Response> {
+///         if request.header("X-Token") == Some("secret") {
+///             None
+///         } else {
+///             Some(Response::text("forbidden").with_status_code(403))
+///         }
+///     }
+/// }
+///
+/// let request = Request::fake_http("GET", "/", vec![], vec![]);
+/// let response = middleware::guarded(&request, &[&RequireToken], || Response::text("hello"));
+/// assert_eq!(response.status_code, 403);
+/// ```
+pub fn guarded<F>(request: &Request, middlewares: &[&dyn Middleware], handler: F) -> Response
+where
+    F: FnOnce() -> Response,
+{
+    fn chain(
+        request: &Request,
+        middlewares: &[&dyn Middleware],
+        handler: &dyn Fn() -> Response,
+    ) -> Response {
+        match middlewares.split_first() {
+            Some((middleware, rest)) => {
+                middleware.handle(request, &|request| chain(request, rest, handler))
+            }
+            None => handler(),
+        }
+    }
+
+    let handler = RefCell::new(Some(handler));
+    chain(
+        request,
+        middlewares,
+        &|| match handler.borrow_mut().take() {
+            Some(handler) => handler(),
+            None => panic!("A middleware called `next` more than once"),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::guarded;
+    use super::Middleware;
+    use super::Pipeline;
+    use std::sync::Mutex;
+    use Request;
+    use Response;
+
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn after(&self, _: &Request, response: Response) -> Response {
+            response.with_additional_header("X-Tag", self.0)
+        }
+    }
+
+    struct Deny;
+
+    impl Middleware for Deny {
+        fn before(&self, request: &Request) -> Option<Response> {
+            if request.url() == "/denied" {
+                Some(Response::empty_404())
+            } else {
+                None
+            }
+        }
+    }
+
+    struct User(&'static str);
+
+    struct Authenticate;
+
+    impl Middleware for Authenticate {
+        fn before(&self, request: &Request) -> Option<Response> {
+            request.extensions().insert(User("alice"));
+            None
+        }
+    }
+
+    fn tags(response: &Response) -> Vec<String> {
+        response
+            .headers
+            .iter()
+            .filter(|h| h.0 == "X-Tag")
+            .map(|h| h.1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn empty_pipeline() {
+        let pipeline = Pipeline::new(|_: &Request| Response::text("hello"));
+        let response = pipeline.handle(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+    }
+
+    #[test]
+    fn order() {
+        let pipeline = Pipeline::new(|_: &Request| Response::text("hello"))
+            .with(Tag("outer"))
+            .with(Tag("inner"));
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert_eq!(tags(&response), vec!["inner", "outer"]);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let called = Mutex::new(0);
+        let pipeline = Pipeline::new(|_: &Request| {
+            *called.lock().unwrap() += 1;
+            Response::text("hello")
+        })
+        .with(Tag("outer"))
+        .with(Deny)
+        .with(Tag("inner"));
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/denied", vec![], vec![]));
+        assert_eq!(response.status_code, 404);
+        assert_eq!(tags(&response), vec!["outer"]);
+        assert_eq!(*called.lock().unwrap(), 0);
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(*called.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn extensions() {
+        let pipeline = Pipeline::new(|request: &Request| {
+            let user = request.extensions().get::<User>().map(|u| u.0);
+            Response::text(user.unwrap_or("anonymous"))
+        })
+        .with(Authenticate);
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = pipeline.handle(&request);
+        assert_eq!(response.status_code, 200);
+        assert!(request.extensions().contains::<User>());
+    }
+
+    #[test]
+    fn guarded_handler() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let body = String::from("hello");
+        let response = guarded(
+            &request,
+            &[&Tag("outer"), &Authenticate, &Tag("inner")],
+            || Response::text(body),
+        );
+        assert_eq!(tags(&response), vec!["inner", "outer"]);
+        assert!(request.extensions().contains::<User>());
+
+        let request = Request::fake_http("GET", "/denied", vec![], vec![]);
+        let response = guarded(&request, &[&Deny], || unreachable!());
+        assert_eq!(response.status_code, 404);
+    }
+}
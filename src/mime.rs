@@ -0,0 +1,405 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Parsing media types, as found in the `Content-Type` and `Accept` headers.
+//!
+//! Comparing the value of a `Content-Type` header with a string is error-prone: the header can
+//! contain parameters such as `charset`, and types are case-insensitive. The
+//! [`MediaType`](struct.MediaType.html) struct parses the value once and provides the checks that
+//! are usually needed.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::mime::MediaType;
+//!
+//! let media_type: MediaType = "Application/vnd.api+JSON; charset=UTF-8".parse().unwrap();
+//! assert!(media_type.is("application/vnd.api+json"));
+//! assert_eq!(media_type.suffix(), Some("json"));
+//! assert_eq!(media_type.param("charset"), Some("UTF-8"));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use Request;
+
+/// A parsed media type, such as `text/html; charset=utf-8`.
+///
+/// The type, the subtype and the names of the parameters are stored in lowercase. The values of
+/// the parameters are kept as they are, without their quotes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaType {
+    main_type: String,
+    subtype: String,
+    params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    /// Parses a media type.
+    pub fn parse(input: &str) -> Result<MediaType, MediaTypeParseError> {
+        let mut parts = input.splitn(2, ';');
+        let essence = parts.next().unwrap_or("").trim();
+
+        let mut essence = essence.splitn(2, '/');
+        let main_type = essence.next().unwrap_or("");
+        let subtype = essence.next().unwrap_or("");
+        if !is_token(main_type) || !is_token(subtype) {
+            return Err(MediaTypeParseError);
+        }
+
+        let params = match parts.next() {
+            Some(params) => parse_params(params)?,
+            None => Vec::new(),
+        };
+
+        Ok(MediaType {
+            main_type: main_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params,
+        })
+    }
+
+    /// Returns the type, for example `text` for `text/html`.
+    #[inline]
+    pub fn main_type(&self) -> &str {
+        &self.main_type
+    }
+
+    /// Returns the subtype, for example `html` for `text/html`.
+    #[inline]
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    /// Returns the structured syntax suffix of the subtype, for example `json` for
+    /// `application/vnd.api+json`.
+    #[inline]
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype.rfind('+').map(|pos| &self.subtype[pos + 1..])
+    }
+
+    /// Returns the value of a parameter. The name is case-insensitive.
+    #[inline]
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|p| p.0.eq_ignore_ascii_case(name))
+            .map(|p| &p.1[..])
+    }
+
+    /// Returns the list of parameters, in the order in which they appear.
+    #[inline]
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    /// Returns true if the type and subtype are equal to `essence`, which must be of the form
+    /// `type/subtype`. The comparison is case-insensitive and parameters are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::mime::MediaType;
+    ///
+    /// let media_type = MediaType::parse("text/plain; charset=utf-8").unwrap();
+    /// assert!(media_type.is("text/plain"));
+    /// assert!(!media_type.is("text/plainer"));
+    /// ```
+    pub fn is(&self, essence: &str) -> bool {
+        let mut split = essence.splitn(2, '/');
+        let main_type = split.next().unwrap_or("");
+        let subtype = split.next().unwrap_or("");
+        self.main_type.eq_ignore_ascii_case(main_type) && self.subtype.eq_ignore_ascii_case(subtype)
+    }
+
+    /// Returns true if the type is `application/<name>` or if the suffix of the subtype is
+    /// `<name>`. For example `is_structured("json")` is true for both `application/json` and
+    /// `application/problem+json`.
+    pub fn is_structured(&self, name: &str) -> bool {
+        (self.main_type == "application" && self.subtype.eq_ignore_ascii_case(name))
+            || self
+                .suffix()
+                .map_or(false, |s| s.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns true if one of the two media types matches the other, taking into account the
+    /// `*` wildcards of either. Parameters are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::mime::MediaType;
+    ///
+    /// let accepted = MediaType::parse("image/*").unwrap();
+    /// assert!(accepted.matches(&MediaType::parse("image/png").unwrap()));
+    /// assert!(!accepted.matches(&MediaType::parse("text/plain").unwrap()));
+    /// ```
+    pub fn matches(&self, other: &MediaType) -> bool {
+        if self.main_type == "*" || other.main_type == "*" {
+            return true;
+        }
+        if self.main_type != other.main_type {
+            return false;
+        }
+        self.subtype == "*" || other.subtype == "*" || self.subtype == other.subtype
+    }
+
+    /// Returns the value of the `q` parameter, as found in `Accept` headers. Returns `1.0` if
+    /// there is no such parameter, and `None` if it is not a number between 0 and 1.
+    pub fn quality(&self) -> Option<f32> {
+        match self.param("q") {
+            None => Some(1.0),
+            Some(q) => match q.parse::<f32>() {
+                Ok(q) if (0.0..=1.0).contains(&q) => Some(q),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = MediaTypeParseError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<MediaType, MediaTypeParseError> {
+        MediaType::parse(s)
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "{}/{}", self.main_type, self.subtype)?;
+        for param in &self.params {
+            if is_token(&param.1) {
+                write!(fmt, "; {}={}", param.0, param.1)?;
+            } else {
+                write!(
+                    fmt,
+                    "; {}=\"{}\"",
+                    param.0,
+                    param.1.replace('\\', "\\\\").replace('"', "\\\"")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error that can happen when parsing a media type.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MediaTypeParseError;
+
+impl error::Error for MediaTypeParseError {}
+
+impl fmt::Display for MediaTypeParseError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "invalid media type")
+    }
+}
+
+/// Parses the `Content-Type` header of a request.
+///
+/// Returns `None` if the header is missing or invalid.
+///
+/// # Example
+///
+/// ```
+/// use rouille::Request;
+/// use rouille::mime;
+///
+/// fn is_form(request: &Request) -> bool {
+///     mime::content_type(request).map_or(false, |ct| ct.is("application/x-www-form-urlencoded"))
+/// }
+/// ```
+#[inline]
+pub fn content_type(request: &Request) -> Option<MediaType> {
+    request
+        .header("Content-Type")
+        .and_then(|ct| MediaType::parse(ct).ok())
+}
+
+/// Parses the value of an `Accept` header.
+///
+/// The media types are returned in the order of the header. Elements that are invalid, including
+/// those with an invalid `q` parameter, are skipped.
+///
+/// # Example
+///
+/// ```
+/// use rouille::mime;
+///
+/// let accepted = mime::parse_accept("text/html, application/json;q=0.5, garbage");
+/// assert_eq!(accepted.len(), 2);
+/// assert_eq!(accepted[1].quality(), Some(0.5));
+/// ```
+pub fn parse_accept(header: &str) -> Vec<MediaType> {
+    split_list(header)
+        .into_iter()
+        .filter_map(|elem| MediaType::parse(elem).ok())
+        .filter(|m| m.quality().is_some())
+        .collect()
+}
+
+// Parses `name=value` parameters separated by `;`.
+fn parse_params(input: &str) -> Result<Vec<(String, String)>, MediaTypeParseError> {
+    let mut params = Vec::new();
+    let mut rest = input;
+
+    loop {
+        rest = rest.trim_start_matches(|c| c == ' ' || c == '\t');
+        if rest.is_empty() {
+            break;
+        }
+        // Tolerate empty parameters, such as in `text/html;`.
+        if rest.starts_with(';') {
+            rest = &rest[1..];
+            continue;
+        }
+
+        let eq = rest.find('=').ok_or(MediaTypeParseError)?;
+        let name = rest[..eq].trim_end();
+        if !is_token(name) {
+            return Err(MediaTypeParseError);
+        }
+        rest = &rest[eq + 1..];
+
+        let value = if rest.starts_with('"') {
+            let quoted = &rest[1..];
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next() {
+                    None => return Err(MediaTypeParseError),
+                    Some((pos, '"')) => break pos,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => value.push(c),
+                        None => return Err(MediaTypeParseError),
+                    },
+                    Some((_, c)) => value.push(c),
+                }
+            };
+            rest = &quoted[end + 1..];
+            value
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            let value = rest[..end].trim_end();
+            if !is_token(value) {
+                return Err(MediaTypeParseError);
+            }
+            rest = &rest[end..];
+            value.to_owned()
+        };
+
+        rest = rest.trim_start_matches(|c| c == ' ' || c == '\t');
+        if !rest.is_empty() && !rest.starts_with(';') {
+            return Err(MediaTypeParseError);
+        }
+
+        params.push((name.to_ascii_lowercase(), value));
+    }
+
+    Ok(params)
+}
+
+// Splits a comma-separated list, ignoring the commas inside quoted strings.
+fn split_list(input: &str) -> Vec<&str> {
+    let mut elems = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (pos, c) in input.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                elems.push(&input[start..pos]);
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+    elems.push(&input[start..]);
+
+    elems
+        .into_iter()
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes().all(|c| match c {
+            b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => true,
+            c => c.is_ascii_alphanumeric(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_accept;
+    use super::MediaType;
+
+    #[test]
+    fn parse() {
+        let m = MediaType::parse("Multipart/Form-Data ; Boundary=\"a;b\\\"c\" ;charset=utf-8;")
+            .unwrap();
+        assert_eq!(m.main_type(), "multipart");
+        assert_eq!(m.subtype(), "form-data");
+        assert_eq!(m.suffix(), None);
+        assert_eq!(m.param("boundary"), Some("a;b\"c"));
+        assert_eq!(m.param("CHARSET"), Some("utf-8"));
+        assert_eq!(
+            m.to_string(),
+            "multipart/form-data; boundary=\"a;b\\\"c\"; charset=utf-8"
+        );
+        assert_eq!(MediaType::parse(&m.to_string()).unwrap(), m);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(MediaType::parse("").is_err());
+        assert!(MediaType::parse("text").is_err());
+        assert!(MediaType::parse("text/").is_err());
+        assert!(MediaType::parse("text/plain/x").is_err());
+        assert!(MediaType::parse("text/plain; charset").is_err());
+        assert!(MediaType::parse("text/plain; charset=\"utf-8").is_err());
+        assert!(MediaType::parse("text/plain; charset=\"utf-8\" x").is_err());
+        assert!(MediaType::parse("text/plain; charset=a b").is_err());
+    }
+
+    #[test]
+    fn structured() {
+        let json = MediaType::parse("application/json").unwrap();
+        let problem = MediaType::parse("application/problem+json").unwrap();
+        let patch = MediaType::parse("application/json-patch").unwrap();
+        assert!(json.is_structured("json"));
+        assert!(problem.is_structured("JSON"));
+        assert!(!patch.is_structured("json"));
+        assert!(!patch.is("application/json"));
+    }
+
+    #[test]
+    fn accept() {
+        let accepted = parse_accept("text/*;q=0.3, text/html;level=\"1,2\";q=0.7, , */*;q=2");
+        assert_eq!(accepted.len(), 2);
+        assert_eq!(accepted[0].quality(), Some(0.3));
+        assert_eq!(accepted[1].param("level"), Some("1,2"));
+
+        let html = MediaType::parse("text/html").unwrap();
+        assert!(accepted.iter().all(|m| m.matches(&html)));
+        assert!(!accepted[0].matches(&MediaType::parse("image/png").unwrap()));
+    }
+}
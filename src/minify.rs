@@ -0,0 +1,453 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Minifies all the CSS and JavaScript files inside `root` and writes the result in `cache_dir`.
+///
+/// This function is meant to be called once when your server starts. The directory structure
+/// of `root` is reproduced inside `cache_dir`, but only the files with a `.css` or `.js`
+/// extension are written. Files whose name already ends with `.min.css` or `.min.js` are left
+/// alone, and so are the files that come with a source map (a `.css.map` or `.js.map` file next
+/// to them, or a `sourceMappingURL` comment), since minifying them would make the map point at
+/// the wrong positions.
+///
+/// Returns the number of files that have been minified.
+///
+/// The minification is conservative: comments are removed and whitespace is collapsed, but the
+/// code itself is never rewritten. Line breaks are kept in JavaScript files so that automatic
+/// semicolon insertion isn't affected. Comments starting with `/*!` (usually licenses) are
+/// preserved.
+///
+/// The minified files keep their name. They aren't fingerprinted by
+/// [`AssetManifest`](struct.AssetManifest.html).
+///
+/// # Example
+///
+/// Serve the minified files first, and fall back to the original directory for everything else.
+///
+/// ```no_run
+/// # #[macro_use] extern crate rouille;
+/// # fn main() {
+/// rouille::minify_assets("public", "target/public-min").unwrap();
+///
+/// rouille::start_server("localhost:8000", move |request| {
+///     find_route!(
+///         rouille::match_assets(&request, "target/public-min"),
+///         rouille::match_assets(&request, "public")
+///     )
+/// });
+/// # }
+/// ```
+pub fn minify_assets<P, Q>(root: &P, cache_dir: &Q) -> io::Result<usize>
+where
+    P: ?Sized + AsRef<Path>,
+    Q: ?Sized + AsRef<Path>,
+{
+    minify_dir(root.as_ref(), cache_dir.as_ref())
+}
+
+fn minify_dir(src: &Path, dest: &Path) -> io::Result<usize> {
+    let mut count = 0;
+
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            count += minify_dir(&path, &dest.join(entry.file_name()))?;
+            continue;
+        }
+
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n,
+            None => continue,
+        };
+
+        let minify: fn(&str) -> String = if name.ends_with(".min.css") || name.ends_with(".min.js")
+        {
+            continue;
+        } else if name.ends_with(".css") {
+            minify_css
+        } else if name.ends_with(".js") {
+            minify_js
+        } else {
+            continue;
+        };
+
+        // The file was produced by a build tool, and the map would no longer match.
+        if path.with_file_name(format!("{}.map", name)).is_file() {
+            continue;
+        }
+        let source = fs::read_to_string(&path)?;
+        if source.contains("sourceMappingURL=") {
+            continue;
+        }
+
+        fs::create_dir_all(dest)?;
+        fs::write(dest.join(name), minify(&source))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Minifies CSS by removing comments and unnecessary whitespace.
+pub(crate) fn minify_css(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut out = String::with_capacity(source.len());
+    let mut i = 0;
+    // True if we skipped whitespace since the last character that was written.
+    let mut pending_space = false;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let end = find_seq(&chars, i + 2, &['*', '/']).map_or(chars.len(), |e| e + 2);
+            let comment: String = chars[i..end].iter().collect();
+            if is_preserved_comment(&comment) {
+                flush_space(&mut out, &mut pending_space, None);
+                out.push_str(&comment);
+            } else {
+                pending_space = true;
+            }
+            i = end;
+            continue;
+        }
+
+        if c == '"' || c == '\'' {
+            let end = skip_string(&chars, i, c);
+            flush_space(&mut out, &mut pending_space, Some(c));
+            out.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+
+        if "{};,".contains(c) {
+            pending_space = false;
+            if c == '}' && out.ends_with(';') {
+                out.pop();
+            }
+            out.push(c);
+            // Whitespace after these characters is never significant.
+            i += 1;
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            continue;
+        }
+
+        flush_space(&mut out, &mut pending_space, Some(c));
+        out.push(c);
+        if c == ':' {
+            // Whitespace after a colon is never significant either, including in selectors
+            // where a pseudo-class name always immediately follows.
+            while i + 1 < chars.len() && chars[i + 1].is_whitespace() {
+                i += 1;
+            }
+        }
+        i += 1;
+    }
+
+    out.trim().to_owned()
+}
+
+/// Minifies JavaScript by removing comments, indentation and blank lines.
+pub(crate) fn minify_js(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut out = String::with_capacity(source.len());
+    let mut i = 0;
+    let mut pending_space = false;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            let end = chars[i..]
+                .iter()
+                .position(|&c| c == '\n')
+                .map_or(chars.len(), |e| i + e);
+            let comment: String = chars[i..end].iter().collect();
+            if is_preserved_comment(&comment) {
+                flush_space(&mut out, &mut pending_space, None);
+                out.push_str(&comment);
+            }
+            i = end;
+            continue;
+        }
+
+        if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let end = find_seq(&chars, i + 2, &['*', '/']).map_or(chars.len(), |e| e + 2);
+            let comment: String = chars[i..end].iter().collect();
+            if is_preserved_comment(&comment) {
+                flush_space(&mut out, &mut pending_space, None);
+                out.push_str(&comment);
+            } else if comment.contains('\n') {
+                // A comment containing a line break counts as a line break for automatic
+                // semicolon insertion.
+                pending_space = false;
+                push_newline(&mut out);
+            } else {
+                pending_space = true;
+            }
+            i = end;
+            continue;
+        }
+
+        if c == '"' || c == '\'' || c == '`' {
+            let end = skip_string(&chars, i, c);
+            flush_space(&mut out, &mut pending_space, Some(c));
+            out.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+
+        if c == '/' && regex_allowed(&out) {
+            let end = skip_regex(&chars, i);
+            flush_space(&mut out, &mut pending_space, Some(c));
+            out.extend(&chars[i..end]);
+            i = end;
+            continue;
+        }
+
+        if c == '\n' || c == '\r' {
+            pending_space = false;
+            push_newline(&mut out);
+            i += 1;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+
+        flush_space(&mut out, &mut pending_space, Some(c));
+        out.push(c);
+        i += 1;
+    }
+
+    let mut out = out.trim().to_owned();
+    out.push('\n');
+    out
+}
+
+// Writes a single space if whitespace was skipped and if it could be significant before `next`.
+fn flush_space(out: &mut String, pending_space: &mut bool, next: Option<char>) {
+    if !*pending_space {
+        return;
+    }
+    *pending_space = false;
+
+    // A comment already separates the tokens around it.
+    if out.is_empty() || out.ends_with('\n') || out.ends_with(' ') || out.ends_with("*/") {
+        return;
+    }
+
+    if let Some(next) = next {
+        let prev = out.chars().last().unwrap();
+        if "{};,".contains(prev) || (next != '-' && next != '+' && "{};,)".contains(next)) {
+            return;
+        }
+    }
+
+    out.push(' ');
+}
+
+fn push_newline(out: &mut String) {
+    if out.ends_with(' ') {
+        out.pop();
+    }
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn is_preserved_comment(comment: &str) -> bool {
+    comment.starts_with("/*!")
+        || comment.contains("# sourceMappingURL=")
+        || comment.contains("@ sourceMappingURL=")
+}
+
+// Returns the index of the first occurrence of `seq` in `chars` starting at `start`.
+fn find_seq(chars: &[char], start: usize, seq: &[char]) -> Option<usize> {
+    if start > chars.len() {
+        return None;
+    }
+    chars[start..]
+        .windows(seq.len())
+        .position(|w| w == seq)
+        .map(|p| p + start)
+}
+
+// Returns the index right after the end of the string starting at `start`.
+fn skip_string(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            // Unterminated string ; only template literals can span multiple lines.
+            '\n' if quote != '`' => return i,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+// Returns the index right after the end of the regex literal starting at `start`.
+fn skip_regex(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    let mut in_class = false;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => {
+                i += 1;
+                // Flags.
+                while i < chars.len() && chars[i].is_alphabetic() {
+                    i += 1;
+                }
+                return i;
+            }
+            '\n' => return i,
+            _ => (),
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+// Returns true if a `/` following what has been written so far starts a regex literal rather
+// than being a division.
+fn regex_allowed(out: &str) -> bool {
+    let trimmed = out.trim_end();
+    let last = match trimmed.chars().last() {
+        Some(c) => c,
+        None => return true,
+    };
+
+    if "(,=:[!&|?{};+-*%<>~^\n".contains(last) {
+        return true;
+    }
+
+    let word_start = trimmed
+        .rfind(|c: char| !c.is_alphanumeric() && c != '_' && c != '$')
+        .map_or(0, |p| p + 1);
+    match &trimmed[word_start..] {
+        "return" | "typeof" | "instanceof" | "in" | "of" | "new" | "delete" | "void" | "throw"
+        | "case" | "do" | "else" | "yield" | "await" => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minify_assets, minify_css, minify_js};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn skips_files_with_source_maps() {
+        let dir = env::temp_dir().join(format!("rouille-minify-{}", ::std::process::id()));
+        let src = dir.join("src");
+        let dest = dir.join("dest");
+        fs::create_dir_all(src.join("css")).unwrap();
+        fs::write(src.join("app.js"), "foo( 1 );\n").unwrap();
+        fs::write(src.join("app.js.map"), "{}").unwrap();
+        fs::write(
+            src.join("inline.js"),
+            "foo();\n//# sourceMappingURL=data:x\n",
+        )
+        .unwrap();
+        fs::write(src.join("css/style.css"), "a { color: red; }").unwrap();
+
+        assert_eq!(minify_assets(&src, &dest).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(dest.join("css/style.css")).unwrap(),
+            "a{color:red}"
+        );
+        assert!(!dest.join("app.js").exists());
+        assert!(!dest.join("app.js.map").exists());
+        assert!(!dest.join("inline.js").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn css_basic() {
+        let css = "/* header */\nbody {\n    color: red;\n    margin: 0 auto;\n}\n\na:hover, a:focus { color: blue ; }\n";
+        assert_eq!(
+            minify_css(css),
+            "body{color:red;margin:0 auto}a:hover,a:focus{color:blue}"
+        );
+    }
+
+    #[test]
+    fn css_keeps_significant_spaces() {
+        assert_eq!(minify_css("div :first-child {}"), "div :first-child{}");
+        assert_eq!(
+            minify_css("a { width: calc(100% - 2px); }"),
+            "a{width:calc(100% - 2px)}"
+        );
+        assert_eq!(
+            minify_css("a { content: \"  /* x */  \"; }"),
+            "a{content:\"  /* x */  \"}"
+        );
+    }
+
+    #[test]
+    fn css_preserved_comments() {
+        let css = "/*! license */\na { color: red; }\n/*# sourceMappingURL=a.css.map */\n";
+        assert_eq!(
+            minify_css(css),
+            "/*! license */a{color:red}/*# sourceMappingURL=a.css.map */"
+        );
+    }
+
+    #[test]
+    fn js_basic() {
+        let js = "// comment\nfunction foo(a, b) {\n    /* inline */ return a + b; // trailing\n}\n\n\nfoo(1, 2)\n";
+        assert_eq!(
+            minify_js(js),
+            "function foo(a,b){\nreturn a + b;\n}\nfoo(1,2)\n"
+        );
+    }
+
+    #[test]
+    fn js_strings_and_regexes() {
+        let js = "var a = \"// not a comment\";\nvar b = /\\/\\/[/]x/g;\nvar c = `line\n   // kept`;\nvar d = 4 / 2 / 1;\n";
+        assert_eq!(minify_js(js), js);
+    }
+
+    #[test]
+    fn js_source_map_comment_kept() {
+        let js = "foo();\n//# sourceMappingURL=app.js.map\n";
+        assert_eq!(minify_js(js), "foo();\n//# sourceMappingURL=app.js.map\n");
+    }
+
+    #[test]
+    fn js_multiline_comment_is_newline() {
+        assert_eq!(minify_js("a = b\n/*\n*/c()"), "a = b\nc()\n");
+        assert_eq!(minify_js("a = b/*\n*/c()"), "a = b\nc()\n");
+    }
+}
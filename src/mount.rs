@@ -0,0 +1,105 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+/// Dispatches the request to sub-handlers depending on the prefix of its path.
+///
+/// Each parameter after the request must be of the form `prefix => handler`, where `handler` is
+/// something that can be called with a `&Request`, such as a function or a closure. The first
+/// handler whose prefix matches is called, with a request whose URL no longer contains the
+/// prefix. If no prefix matches, the default expression after `_` is returned.
+///
+/// The prefixes are matched with
+/// [`Request::strip_path_prefix`](struct.Request.html#method.strip_path_prefix), which means
+/// that `/api` matches `/api` and `/api/users` but not `/apis`.
+///
+/// This makes it possible to define routes in the module they belong to, instead of having one
+/// big `router!` for the whole application.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// # fn main() {
+/// use rouille::{Request, Response};
+///
+/// mod api {
+///     use rouille::{Request, Response};
+///
+///     pub fn handle(request: &Request) -> Response {
+///         router!(request,
+///             // Matches `GET /api/v1/users`.
+///             (GET) (/users) => { Response::text("list of users") },
+///             _ => Response::empty_404()
+///         )
+///     }
+/// }
+///
+/// fn handle(request: &Request) -> Response {
+///     mount!(request,
+///         "/api/v1" => api::handle,
+///         "/static" => |request: &Request| rouille::match_assets(request, "public"),
+///         _ => Response::empty_404()
+///     )
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! mount {
+    ($request:expr, $($prefix:expr => $handler:expr,)* _ => $default:expr $(,)*) => ({
+        let request = &$request;
+        let mut response = None;
+        $(
+            if response.is_none() {
+                if let Some(request) = request.strip_path_prefix($prefix) {
+                    response = Some(($handler)(&request));
+                }
+            }
+        )*
+        match response {
+            Some(response) => response,
+            None => $default,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use Request;
+    use Response;
+
+    fn api(request: &Request) -> Response {
+        Response::text(format!("api {}", request.raw_url()))
+    }
+
+    #[test]
+    fn mount() {
+        let handle = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            let response = mount!(request,
+                "/api/v1" => api,
+                "/api/" => |r: &Request| Response::text(format!("other {}", r.raw_url())),
+                _ => Response::text("default")
+            );
+            let mut body = String::new();
+            response
+                .data
+                .into_reader_and_size()
+                .0
+                .read_to_string(&mut body)
+                .unwrap();
+            body
+        };
+
+        assert_eq!(handle("/api/v1/users?a=b"), "api /users?a=b");
+        assert_eq!(handle("/api/v1"), "api /");
+        assert_eq!(handle("/api/v10"), "other /v10");
+        assert_eq!(handle("/api"), "other /");
+        assert_eq!(handle("/apis"), "default");
+    }
+}
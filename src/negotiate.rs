@@ -0,0 +1,341 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Serializing a value in the format preferred by the client.
+//!
+//! [`Response::negotiate`](../struct.Response.html#method.negotiate) looks at the `Accept` header
+//! of the request and serializes a value with the matching encoder of the default
+//! [`Encoders`](struct.Encoders.html). They contain JSON, CBOR if you enable the `cbor` feature of
+//! rouille, and MessagePack if you enable the `msgpack` feature. Build your own `Encoders` in
+//! order to support other formats.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::negotiate::Encoders;
+//! use rouille::Request;
+//! use rouille::Response;
+//!
+//! fn handle(request: &Request, encoders: &Encoders) -> Response {
+//!     let value = vec!["hello", "world"];
+//!     encoders.negotiate(request, &value).unwrap()
+//! }
+//!
+//! let encoders = Encoders::new().with_encoder("text/csv", |value| {
+//!     let items: Vec<String> = value.as_array().into_iter().flatten()
+//!         .map(|item| item.to_string())
+//!         .collect();
+//!     Ok(items.join(",").into_bytes())
+//! });
+//!
+//! let request = Request::fake_http("GET", "/", vec![("Accept".to_owned(), "text/csv".to_owned())],
+//!                                  Vec::new());
+//! let response = handle(&request, &encoders);
+//! assert_eq!(response.content_type(), Some("text/csv"));
+//! ```
+
+use std::error;
+use std::fmt;
+
+#[cfg(feature = "cbor")]
+use ciborium;
+#[cfg(feature = "msgpack")]
+use rmp_serde;
+use serde;
+use serde_json;
+use serde_json::Value;
+
+use input;
+use Request;
+use Response;
+
+/// Error that an encoder can return.
+pub type EncodeError = Box<dyn error::Error + Send + Sync>;
+
+type EncodeFn = Box<dyn Fn(&Value) -> Result<Vec<u8>, EncodeError> + Send + Sync>;
+
+/// The formats in which `negotiate` can serialize a value.
+///
+/// The values are first converted to a `serde_json::Value`, which is then passed to the encoder
+/// of the format preferred by the client.
+pub struct Encoders {
+    // Media type, `Content-Type` of the response, encoder.
+    encoders: Vec<(String, String, EncodeFn)>,
+}
+
+impl Encoders {
+    /// Builds the default encoders: JSON, followed by CBOR and MessagePack if the `cbor` and
+    /// `msgpack` features are enabled.
+    ///
+    /// JSON comes first, which means that it is chosen when the request has no `Accept` header.
+    pub fn new() -> Encoders {
+        let encoders = Encoders::empty().with_encoder_and_content_type(
+            "application/json",
+            "application/json; charset=utf-8",
+            |value| Ok(serde_json::to_vec(value)?),
+        );
+
+        #[cfg(feature = "cbor")]
+        let encoders = encoders.with_encoder("application/cbor", |value| {
+            let mut data = Vec::new();
+            ciborium::ser::into_writer(value, &mut data)?;
+            Ok(data)
+        });
+
+        #[cfg(feature = "msgpack")]
+        let encoders = encoders
+            .with_encoder("application/msgpack", |value| {
+                Ok(rmp_serde::to_vec_named(value)?)
+            })
+            .with_encoder("application/vnd.msgpack", |value| {
+                Ok(rmp_serde::to_vec_named(value)?)
+            })
+            .with_encoder("application/x-msgpack", |value| {
+                Ok(rmp_serde::to_vec_named(value)?)
+            });
+
+        encoders
+    }
+
+    /// Builds an `Encoders` without any format.
+    #[inline]
+    pub fn empty() -> Encoders {
+        Encoders {
+            encoders: Vec::new(),
+        }
+    }
+
+    /// Adds an encoder for a media type, such as `application/yaml`, which is also the
+    /// `Content-Type` of the responses.
+    ///
+    /// When the client accepts several formats with the same quality, the one that was added
+    /// first is chosen.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the media type contains a wildcard or parameters.
+    #[inline]
+    pub fn with_encoder<F>(self, media_type: &str, encoder: F) -> Encoders
+    where
+        F: Fn(&Value) -> Result<Vec<u8>, EncodeError> + Send + Sync + 'static,
+    {
+        self.with_encoder_and_content_type(media_type, media_type, encoder)
+    }
+
+    /// Same as `with_encoder`, but the `Content-Type` of the responses is different from the
+    /// media type, for example `text/plain; charset=utf-8` for `text/plain`.
+    pub fn with_encoder_and_content_type<F>(
+        mut self,
+        media_type: &str,
+        content_type: &str,
+        encoder: F,
+    ) -> Encoders
+    where
+        F: Fn(&Value) -> Result<Vec<u8>, EncodeError> + Send + Sync + 'static,
+    {
+        assert!(
+            !media_type.contains('*') && !media_type.contains(';'),
+            "invalid media type: {}",
+            media_type
+        );
+        self.encoders.push((
+            media_type.to_owned(),
+            content_type.to_owned(),
+            Box::new(encoder),
+        ));
+        self
+    }
+
+    /// Returns the media types that the encoders produce, in order.
+    #[inline]
+    pub fn media_types(&self) -> impl Iterator<Item = &str> {
+        self.encoders.iter().map(|e| &e.0[..])
+    }
+
+    /// Serializes `value` in the format that the `Accept` header of the request prefers.
+    ///
+    /// If the request has no `Accept` header, the first encoder is used. If the header accepts
+    /// none of the formats, returns an empty 406 response. The response always has a
+    /// `Vary: Accept` header.
+    ///
+    /// Returns an error if the value can't be serialized.
+    pub fn negotiate<T>(&self, request: &Request, value: &T) -> Result<Response, NegotiateError>
+    where
+        T: serde::Serialize,
+    {
+        let index = match request.header("Accept").filter(|h| !h.trim().is_empty()) {
+            Some(header) => input::parse_accept_header(header).preferred(self.media_types()),
+            None if self.encoders.is_empty() => None,
+            None => Some(0),
+        };
+
+        let (_, content_type, encoder) = match index {
+            Some(index) => &self.encoders[index],
+            None => return Ok(Response::empty_406().with_vary("Accept")),
+        };
+
+        let value = serde_json::to_value(value).map_err(NegotiateError::Serialize)?;
+        let data = encoder(&value).map_err(NegotiateError::Encode)?;
+        Ok(Response::from_data(content_type.clone(), data).with_vary("Accept"))
+    }
+}
+
+impl Default for Encoders {
+    #[inline]
+    fn default() -> Encoders {
+        Encoders::new()
+    }
+}
+
+impl fmt::Debug for Encoders {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Encoders")
+            .field("media_types", &self.media_types().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Error that can happen when serializing a value with `negotiate`.
+#[derive(Debug)]
+pub enum NegotiateError {
+    /// The value couldn't be converted to a `serde_json::Value`.
+    Serialize(serde_json::Error),
+
+    /// The encoder returned an error.
+    Encode(EncodeError),
+}
+
+impl error::Error for NegotiateError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            NegotiateError::Serialize(ref e) => Some(e),
+            NegotiateError::Encode(ref e) => Some(&**e),
+        }
+    }
+}
+
+impl fmt::Display for NegotiateError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            NegotiateError::Serialize(_) => "The value couldn't be serialized",
+            NegotiateError::Encode(_) => "The encoder failed to encode the value",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::Encoders;
+    use Request;
+    use Response;
+
+    fn request(accept: Option<&str>) -> Request {
+        let headers = accept
+            .map(|a| vec![("Accept".to_owned(), a.to_owned())])
+            .unwrap_or_default();
+        Request::fake_http("GET", "/", headers, Vec::new())
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        let mut data = Vec::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    fn text_encoders() -> Encoders {
+        Encoders::new().with_encoder("text/plain", |value| Ok(value.to_string().into_bytes()))
+    }
+
+    #[test]
+    fn default_is_json() {
+        let response = Response::negotiate(&request(None), &vec![1, 2]);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.content_type(),
+            Some("application/json; charset=utf-8")
+        );
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        assert_eq!(body(response), b"[1,2]");
+    }
+
+    #[test]
+    fn preferred_format() {
+        let encoders = text_encoders();
+        let response = encoders
+            .negotiate(
+                &request(Some("application/json;q=0.5, text/plain")),
+                &"hello",
+            )
+            .unwrap();
+        assert_eq!(response.content_type(), Some("text/plain"));
+        assert_eq!(body(response), b"\"hello\"");
+
+        let response = encoders
+            .negotiate(&request(Some("text/*;q=0.1, */*;q=0.5")), &"hello")
+            .unwrap();
+        assert_eq!(
+            response.content_type(),
+            Some("application/json; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn not_acceptable() {
+        let encoders = text_encoders();
+        let response = encoders
+            .negotiate(&request(Some("image/png, text/plain;q=0")), &"hello")
+            .unwrap();
+        assert_eq!(response.status_code, 406);
+        assert_eq!(response.header("Vary"), Some("Accept"));
+
+        let response = Encoders::empty().negotiate(&request(None), &1).unwrap();
+        assert_eq!(response.status_code, 406);
+    }
+
+    #[test]
+    fn encoder_error() {
+        let encoders = Encoders::empty().with_encoder("text/plain", |_| Err("failed".into()));
+        assert!(encoders.negotiate(&request(None), &1).is_err());
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor() {
+        use ciborium;
+        use serde_json::Value;
+
+        let response = Response::negotiate(&request(Some("application/cbor")), &vec!["a"]);
+        assert_eq!(response.content_type(), Some("application/cbor"));
+        let value: Value = ciborium::de::from_reader(&body(response)[..]).unwrap();
+        assert_eq!(value, Value::Array(vec![Value::String("a".to_owned())]));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        use rmp_serde;
+        use serde_json::Value;
+
+        let response = Response::negotiate(&request(Some("application/x-msgpack")), &vec!["a"]);
+        assert_eq!(response.content_type(), Some("application/x-msgpack"));
+        let value: Value = rmp_serde::from_slice(&body(response)).unwrap();
+        assert_eq!(value, Value::Array(vec![Value::String("a".to_owned())]));
+    }
+}
@@ -0,0 +1,357 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Declaring the limits of a route next to its pattern.
+//!
+//! A [`Policy`](struct.Policy.html) groups the requirements that a request must meet before the
+//! handler runs: the maximum size of the body, the accepted content types, the authentication
+//! scheme and the rate limit. A policy is a [`Middleware`](../middleware/trait.Middleware.html),
+//! so it's attached to a route as a guard of the [`router!`](../macro.router.html) macro. Since
+//! the policies are declared with the routes, the limits of the whole application can be
+//! reviewed in one place, and `Display` prints them for an audit log.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::policy::Policy;
+//! use rouille::rate_limit::RateLimiter;
+//!
+//! let uploads = Arc::new(RateLimiter::new(10, Duration::from_secs(60)));
+//! let upload = Policy::new()
+//!     .max_body_size(10 * 1024 * 1024)
+//!     .content_types(&["image/png", "image/jpeg"])
+//!     .auth_scheme("Bearer")
+//!     .rate_limit(uploads, 1);
+//! let api = Policy::new().max_body_size(64 * 1024).content_types(&["application/json"]);
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     router!(request,
+//!         (POST) (/images) [upload] => {
+//!             Response::text("uploaded")
+//!         },
+//!         (POST) (/comments) [api] => {
+//!             Response::text("posted")
+//!         },
+//!         _ => Response::empty_404()
+//!     )
+//! });
+//! # }
+//! ```
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::sync::Arc;
+
+use middleware::Middleware;
+use rate_limit::RateLimiter;
+use rate_limit::RequestCost;
+use Request;
+use Response;
+
+/// Requirements that a request must meet before reaching the handler of a route.
+///
+/// The requirements are checked in this order, and the first one that fails determines the
+/// response:
+///
+/// - the `Authorization` header must use the scheme given to `auth_scheme`, otherwise the
+///   response is a `401 Unauthorized` with a `WWW-Authenticate` header;
+/// - the client must have enough budget in the `RateLimiter` given to `rate_limit`, otherwise
+///   the response is a `429 Too Many Requests`;
+/// - a request with a body must have one of the types given to `content_types`, otherwise the
+///   response is a `415 Unsupported Media Type`;
+/// - the `Content-Length` must not exceed `max_body_size`, otherwise the response is a
+///   `413 Payload Too Large`.
+///
+/// The policy only checks that the scheme is the expected one. Validating the credentials is up
+/// to the handler or to another guard.
+#[derive(Default)]
+pub struct Policy {
+    max_body_size: Option<u64>,
+    content_types: Vec<String>,
+    auth_scheme: Option<String>,
+    rate_limit: Option<(Arc<RateLimiter>, u32)>,
+}
+
+impl Policy {
+    /// Builds a policy that accepts every request.
+    #[inline]
+    pub fn new() -> Policy {
+        Policy::default()
+    }
+
+    /// Limits the size of the body of the requests.
+    ///
+    /// Requests whose `Content-Length` exceeds the limit are rejected. The body of the chunked
+    /// requests, which don't declare their size, returns an error when reading past the limit.
+    #[inline]
+    pub fn max_body_size(mut self, size: u64) -> Policy {
+        self.max_body_size = Some(size);
+        self
+    }
+
+    /// Sets the content types that the requests with a body can have. The parameters of the
+    /// `Content-Type` header, such as the charset, are ignored when comparing.
+    pub fn content_types(mut self, types: &[&str]) -> Policy {
+        self.content_types = types.iter().map(|t| (*t).to_owned()).collect();
+        self
+    }
+
+    /// Requires the `Authorization` header of the requests to use the given scheme, such as
+    /// `Basic` or `Bearer`.
+    #[inline]
+    pub fn auth_scheme<S>(mut self, scheme: S) -> Policy
+    where
+        S: Into<String>,
+    {
+        self.auth_scheme = Some(scheme.into());
+        self
+    }
+
+    /// Spends `cost` from the budget of the client in `limiter` for each request.
+    ///
+    /// Routes that share the same `RateLimiter` share the budget of the clients, which makes it
+    /// possible to declare classes of routes such as uploads or searches.
+    #[inline]
+    pub fn rate_limit(mut self, limiter: Arc<RateLimiter>, cost: u32) -> Policy {
+        self.rate_limit = Some((limiter, cost));
+        self
+    }
+
+    // Returns the response to send if the request doesn't meet the policy.
+    fn check(&self, request: &Request) -> Option<Response> {
+        if let Some(ref scheme) = self.auth_scheme {
+            let matches = request
+                .header("Authorization")
+                .and_then(|value| value.split_whitespace().next())
+                .map_or(false, |s| s.eq_ignore_ascii_case(scheme));
+            if !matches {
+                return Some(
+                    Response::text("Unauthorized")
+                        .with_status_code(401)
+                        .with_unique_header("WWW-Authenticate", scheme.clone()),
+                );
+            }
+        }
+
+        if let Some((ref limiter, cost)) = self.rate_limit {
+            request.extensions().insert(RequestCost(cost));
+            if let Err(retry_after) = limiter.acquire_for(request, cost) {
+                return Some(Response::too_many_requests(retry_after));
+            }
+        }
+
+        let has_body = request.header("Transfer-Encoding").is_some()
+            || request
+                .header("Content-Length")
+                .map_or(false, |len| len.trim() != "0");
+
+        if !self.content_types.is_empty() && has_body {
+            let mime = request
+                .header("Content-Type")
+                .and_then(|value| value.split(';').next())
+                .map(|mime| mime.trim());
+            let allowed = mime.map_or(false, |mime| {
+                self.content_types
+                    .iter()
+                    .any(|t| t.eq_ignore_ascii_case(mime))
+            });
+            if !allowed {
+                return Some(Response::text("Unsupported Media Type").with_status_code(415));
+            }
+        }
+
+        if let Some(max) = self.max_body_size {
+            let declared = request
+                .header("Content-Length")
+                .and_then(|len| len.trim().parse::<u64>().ok());
+            match declared {
+                Some(len) if len > max => {
+                    return Some(Response::text("Payload Too Large").with_status_code(413));
+                }
+                Some(_) => (),
+                None => limit_body(request, max),
+            }
+        }
+
+        None
+    }
+}
+
+impl Middleware for Policy {
+    #[inline]
+    fn before(&self, request: &Request) -> Option<Response> {
+        self.check(request)
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rules = Vec::new();
+        if let Some(ref scheme) = self.auth_scheme {
+            rules.push(format!("auth scheme {}", scheme));
+        }
+        if let Some((ref limiter, cost)) = self.rate_limit {
+            rules.push(format!(
+                "rate limit cost {} of {} per {:?}",
+                cost,
+                limiter.budget(),
+                limiter.window()
+            ));
+        }
+        if !self.content_types.is_empty() {
+            rules.push(format!("content types {}", self.content_types.join(", ")));
+        }
+        if let Some(max) = self.max_body_size {
+            rules.push(format!("max body size {} bytes", max));
+        }
+
+        if rules.is_empty() {
+            write!(f, "no restriction")
+        } else {
+            write!(f, "{}", rules.join("; "))
+        }
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Policy")
+            .field("max_body_size", &self.max_body_size)
+            .field("content_types", &self.content_types)
+            .field("auth_scheme", &self.auth_scheme)
+            .field("rate_limit", &self.rate_limit)
+            .finish()
+    }
+}
+
+// Replaces the body of the request with a reader that fails after `max` bytes.
+fn limit_body(request: &Request, max: u64) {
+    let mut data = request.data.lock().unwrap();
+    if let Some(body) = data.take() {
+        *data = Some(Box::new(LimitedBody {
+            inner: body,
+            remaining: max,
+        }));
+    }
+}
+
+struct LimitedBody {
+    inner: Box<dyn Read + Send>,
+    remaining: u64,
+}
+
+impl Read for LimitedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Reads one byte more than allowed, in order to distinguish a body that is exactly at
+        // the limit from a body that is over it.
+        let max = (self.remaining + 1).min(buf.len() as u64) as usize;
+        let num = self.inner.read(&mut buf[..max])?;
+        if num as u64 > self.remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the request body exceeds the maximum size",
+            ));
+        }
+        self.remaining -= num as u64;
+        Ok(num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Policy;
+    use middleware::guarded;
+    use rate_limit::RateLimiter;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use Request;
+    use Response;
+
+    fn status(policy: &Policy, headers: &[(&str, &str)]) -> u16 {
+        let headers = headers
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect();
+        let request = Request::fake_http("POST", "/", headers, b"hello".to_vec());
+        guarded(&request, &[policy], || Response::text("ok")).status_code
+    }
+
+    #[test]
+    fn auth_scheme() {
+        let policy = Policy::new().auth_scheme("Bearer");
+        assert_eq!(status(&policy, &[("Authorization", "bearer abc")]), 200);
+        assert_eq!(status(&policy, &[("Authorization", "Basic abc")]), 401);
+        assert_eq!(status(&policy, &[]), 401);
+    }
+
+    #[test]
+    fn content_types() {
+        let policy = Policy::new().content_types(&["application/json"]);
+        let json = ("Content-Type", "application/json; charset=utf-8");
+        assert_eq!(status(&policy, &[json, ("Content-Length", "5")]), 200);
+        let text = ("Content-Type", "text/plain");
+        assert_eq!(status(&policy, &[text, ("Content-Length", "5")]), 415);
+        assert_eq!(status(&policy, &[("Content-Length", "5")]), 415);
+        assert_eq!(status(&policy, &[text]), 200);
+    }
+
+    #[test]
+    fn max_body_size() {
+        let policy = Policy::new().max_body_size(4);
+        assert_eq!(status(&policy, &[("Content-Length", "4")]), 200);
+        assert_eq!(status(&policy, &[("Content-Length", "5")]), 413);
+
+        // Without a `Content-Length`, the limit applies when reading the body.
+        let request = Request::fake_http("POST", "/", vec![], b"hello".to_vec());
+        let response = guarded(&request, &[&policy], || {
+            let mut body = Vec::new();
+            match request.data().unwrap().read_to_end(&mut body) {
+                Ok(_) => Response::text("ok"),
+                Err(_) => Response::empty_400(),
+            }
+        });
+        assert_eq!(response.status_code, 400);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let limiter = Arc::new(RateLimiter::new(2, Duration::from_secs(60)));
+        let policy = Policy::new().rate_limit(limiter, 1);
+        assert_eq!(status(&policy, &[]), 200);
+        assert_eq!(status(&policy, &[]), 200);
+        assert_eq!(status(&policy, &[]), 429);
+    }
+
+    #[test]
+    fn display() {
+        let limiter = Arc::new(RateLimiter::new(10, Duration::from_secs(60)));
+        let policy = Policy::new()
+            .max_body_size(1024)
+            .content_types(&["application/json"])
+            .auth_scheme("Bearer")
+            .rate_limit(limiter, 2);
+        assert_eq!(
+            policy.to_string(),
+            "auth scheme Bearer; rate limit cost 2 of 10 per 60s; \
+             content types application/json; max body size 1024 bytes"
+        );
+        assert_eq!(Policy::new().to_string(), "no restriction");
+    }
+}
@@ -0,0 +1,48 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! The items that most servers need, to be imported all at once.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use rouille::prelude::*;
+//!
+//! let server = Server::builder()
+//!     .addr("0.0.0.0:80")
+//!     .build(|request: &Request| {
+//!         router!(request,
+//!             (GET) (/) => { Response::text("hello world") },
+//!             _ => Response::empty_404()
+//!         )
+//!     })
+//!     .unwrap();
+//! server.run();
+//! # }
+//! ```
+//!
+//! With the 2018 edition, the macros are imported as well and the `#[macro_use]` attribute is
+//! not needed.
+
+pub use input;
+pub use middleware::Middleware;
+pub use middleware::Pipeline;
+pub use Request;
+pub use Response;
+pub use ResponseBody;
+pub use Server;
+pub use ServerBuilder;
+
+pub use assert_or_400;
+pub use find_route;
+pub use router;
+pub use try_or_400;
+pub use try_or_404;
@@ -15,6 +15,19 @@
 //! This function call will return immediately after the remote server has finished sending its
 //! headers. The socket to the remote will be stored in the `ResponseBody` of the response.
 //!
+//! Neither of the bodies is held in memory: the body of the request is streamed to the remote
+//! server, and the body of the response is streamed to the client as the server sends it. The
+//! `Content-Length` of the response is passed through, and a chunked response is decoded and
+//! chunked again by rouille. The hop-by-hop headers, such as `Connection` or `Keep-Alive`, are
+//! not forwarded in either direction.
+//!
+//! A new connection is opened for each request, unless the configuration has a
+//! [`ConnectionPool`](struct.ConnectionPool.html) from which idle connections are reused.
+//!
+//! A request can be dispatched to several servers: they are tried in turn according to the
+//! [`RetryPolicy`](struct.RetryPolicy.html) of the configuration, and a
+//! [`Balancer`](struct.Balancer.html) spreads the requests over them.
+//!
 //! # Proxy() vs full_proxy()
 //!
 //! The difference between `proxy()` and `full_proxy()` is that if the target server fails to
@@ -38,17 +51,11 @@
 //! fn handle_request(request: &Request) -> Response {
 //!     let config = match request.header("Host") {
 //!         Some(h) if h == "domain1.com" => {
-//!             proxy::ProxyConfig {
-//!                 addr: "domain1.handler.localnetwork",
-//!                 replace_host: None,
-//!             }
+//!             proxy::ProxyConfig::new("domain1.handler.localnetwork")
 //!         },
 //!
 //!         Some(h) if h == "domain2.com" => {
-//!             proxy::ProxyConfig {
-//!                 addr: "domain2.handler.localnetwork",
-//!                 replace_host: None,
-//!             }
+//!             proxy::ProxyConfig::new("domain2.handler.localnetwork")
 //!         },
 //!
 //!         _ => return Response::empty_404()
@@ -59,6 +66,9 @@
 //! ```
 
 use std::borrow::Cow;
+use std::collections::HashMap;
+#[cfg(feature = "proxy-tls")]
+use std::convert::TryFrom;
 use std::error;
 use std::fmt;
 use std::io;
@@ -66,10 +76,22 @@ use std::io::BufRead;
 use std::io::Error as IoError;
 use std::io::Read;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::net::TcpStream;
 use std::net::ToSocketAddrs;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
+use std::time::Instant;
+#[cfg(feature = "proxy-tls")]
+use std::time::SystemTime;
 
+use content_encoding;
+use deadline::TIMEOUT_HEADER;
+#[cfg(feature = "trace")]
+use trace;
 use Request;
 use Response;
 use ResponseBody;
@@ -121,146 +143,2199 @@ impl fmt::Display for ProxyError {
 }
 
 /// Configuration for the reverse proxy.
-#[derive(Debug, Clone)]
+///
+/// # Example
+///
+/// ```
+/// use rouille::proxy::ProxyConfig;
+///
+/// let config = ProxyConfig::new("example.com:80").replace_host("example.com");
+/// ```
+#[derive(Clone)]
 pub struct ProxyConfig<A> {
-    /// The address to connect to. For example `example.com:80`.
+    /// The address to connect to. For example `example.com:80`. If it resolves to several
+    /// addresses, they are tried until one of them accepts the connection.
     pub addr: A,
     /// If `Some`, the `Host` header will be replaced with this value.
     pub replace_host: Option<Cow<'static, str>>,
+    #[cfg(feature = "proxy-tls")]
+    tls: Option<UpstreamTls>,
+    pool: Option<Arc<ConnectionPool>>,
+    retry: Option<RetryPolicy>,
+    balancer: Option<Arc<Balancer>>,
+    rewrite: Option<Rewrite>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    on_error: Option<Arc<ErrorFn>>,
 }
 
-/// Sends the request to another HTTP server using the configuration.
+type ErrorFn = dyn Fn(&Request, GatewayErrorKind) -> Response + Send + Sync;
+
+impl<A> ProxyConfig<A> {
+    /// Builds a configuration that sends the requests to `addr` in plain HTTP, without modifying
+    /// their `Host` header.
+    #[inline]
+    pub fn new(addr: A) -> ProxyConfig<A> {
+        ProxyConfig {
+            addr,
+            replace_host: None,
+            #[cfg(feature = "proxy-tls")]
+            tls: None,
+            pool: None,
+            retry: None,
+            balancer: None,
+            rewrite: None,
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            on_error: None,
+        }
+    }
+
+    /// Replaces the `Host` header of the requests with `host`.
+    #[inline]
+    pub fn replace_host<H>(mut self, host: H) -> ProxyConfig<A>
+    where
+        H: Into<Cow<'static, str>>,
+    {
+        self.replace_host = Some(host.into());
+        self
+    }
+
+    /// Sets the time that the server has to accept the connection. Defaults to the timeout of
+    /// the [`RetryPolicy`](struct.RetryPolicy.html), or 60 seconds.
+    ///
+    /// If the request has a deadline that comes first, the timeouts are shortened to it.
+    #[inline]
+    pub fn connect_timeout(mut self, timeout: Duration) -> ProxyConfig<A> {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the time that the server has to send each part of the response, including the body.
+    /// Defaults to the timeout of the [`RetryPolicy`](struct.RetryPolicy.html), or 60 seconds.
+    #[inline]
+    pub fn read_timeout(mut self, timeout: Duration) -> ProxyConfig<A> {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the time that the server has to receive each part of the request. Defaults to the
+    /// timeout of the [`RetryPolicy`](struct.RetryPolicy.html), or 60 seconds.
+    #[inline]
+    pub fn write_timeout(mut self, timeout: Duration) -> ProxyConfig<A> {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Builds the responses of `full_proxy` and `gateway_proxy` when the server fails with
+    /// `handler`, instead of their default ones. The status code of the response should
+    /// usually be the one of the kind of error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// use rouille::proxy::ProxyConfig;
+    ///
+    /// let config = ProxyConfig::new("localhost:8000").on_error(|_request, kind| {
+    ///     Response::html("<h1>The service is unavailable</h1>")
+    ///         .with_status_code(kind.status_code())
+    /// });
+    /// ```
+    #[inline]
+    pub fn on_error<F>(mut self, handler: F) -> ProxyConfig<A>
+    where
+        F: Fn(&Request, GatewayErrorKind) -> Response + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(handler));
+        self
+    }
+
+    /// Rewrites the headers of the responses that refer to the server. See
+    /// [`Rewrite`](struct.Rewrite.html).
+    #[inline]
+    pub fn rewrite(mut self, rewrite: Rewrite) -> ProxyConfig<A> {
+        self.rewrite = Some(rewrite);
+        self
+    }
+
+    /// Reuses the idle connections of `pool`, and puts the connections back in it once the
+    /// responses have been read. See [`ConnectionPool`](struct.ConnectionPool.html).
+    #[inline]
+    pub fn pool(mut self, pool: Arc<ConnectionPool>) -> ProxyConfig<A> {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Tries the other addresses of the servers when the first ones fail. See
+    /// [`RetryPolicy`](struct.RetryPolicy.html).
+    #[inline]
+    pub fn retry(mut self, retry: RetryPolicy) -> ProxyConfig<A> {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Chooses the order in which the addresses of the servers are tried with `balancer`, which
+    /// spreads the requests over them. See [`Balancer`](struct.Balancer.html).
+    #[inline]
+    pub fn balancer(mut self, balancer: Arc<Balancer>) -> ProxyConfig<A> {
+        self.balancer = Some(balancer);
+        self
+    }
+
+    /// Connects to the server with TLS. See [`UpstreamTls`](struct.UpstreamTls.html).
+    ///
+    /// This method is only available with the `proxy-tls` feature.
+    #[cfg(feature = "proxy-tls")]
+    #[inline]
+    pub fn tls(mut self, tls: UpstreamTls) -> ProxyConfig<A> {
+        self.tls = Some(tls);
+        self
+    }
+}
+
+impl<A> fmt::Debug for ProxyConfig<A>
+where
+    A: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("ProxyConfig");
+        debug
+            .field("addr", &self.addr)
+            .field("replace_host", &self.replace_host);
+        #[cfg(feature = "proxy-tls")]
+        debug.field("tls", &self.tls);
+        debug
+            .field("pool", &self.pool)
+            .field("retry", &self.retry)
+            .field("balancer", &self.balancer)
+            .field("rewrite", &self.rewrite)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+/// TLS configuration of the connections to the server, built with
+/// [rustls](https://github.com/rustls/rustls).
 ///
-/// If the function fails to get a response from the target, an error is returned. If you want
-/// to instead return a response with a status code such as 502 (`Bad Gateway`) or 504
-/// (`Gateway Time-out`), see `full_proxy`.
+/// This struct is only available with the `proxy-tls` feature.
 ///
-/// > **Note**: Implementation is very hacky for the moment.
+/// By default, the certificate of the server must be valid for the name given to `new` and must
+/// be issued by one of the certificate authorities trusted by Mozilla. The name is also sent to
+/// the server with SNI.
 ///
-/// > **Note**: SSL is not supported.
-// TODO: ^
-pub fn proxy<A>(request: &Request, config: ProxyConfig<A>) -> Result<Response, ProxyError>
-where
-    A: ToSocketAddrs,
-{
-    let mut socket = TcpStream::connect(config.addr)?;
-    socket.set_read_timeout(Some(Duration::from_secs(60)))?;
-    socket.set_write_timeout(Some(Duration::from_secs(60)))?;
+/// # Example
+///
+/// ```no_run
+/// use rouille::proxy::{ProxyConfig, UpstreamTls};
+///
+/// let ca = std::fs::read("internal-ca.pem").unwrap();
+/// let tls = UpstreamTls::new("billing.internal")
+///     .unwrap()
+///     .without_default_roots()
+///     .add_root_certificates_pem(&ca)
+///     .unwrap();
+/// let config = ProxyConfig::new("10.0.0.12:443").tls(tls);
+/// ```
+#[cfg(feature = "proxy-tls")]
+#[derive(Clone)]
+pub struct UpstreamTls {
+    server_name: rustls::ServerName,
+    default_roots: bool,
+    custom_roots: Vec<rustls::Certificate>,
+    insecure: bool,
+    // Built from the other fields.
+    config: Arc<rustls::ClientConfig>,
+}
 
-    let mut data = match request.data() {
-        Some(d) => d,
-        None => return Err(ProxyError::BodyAlreadyExtracted),
-    };
+#[cfg(feature = "proxy-tls")]
+impl UpstreamTls {
+    /// Builds the configuration for a server whose certificate is valid for `server_name`, a DNS
+    /// name or an IP address.
+    ///
+    /// Returns an error if `server_name` is neither.
+    pub fn new(server_name: &str) -> io::Result<UpstreamTls> {
+        let server_name = rustls::ServerName::try_from(server_name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid TLS server name"))?;
+        Ok(UpstreamTls {
+            server_name,
+            default_roots: true,
+            custom_roots: Vec::new(),
+            insecure: false,
+            config: Arc::new(tls_config(true, &[], false)),
+        })
+    }
 
-    socket
-        .write_all(format!("{} {} HTTP/1.1\r\n", request.method(), request.raw_url()).as_bytes())?;
-    for (header, value) in request.headers() {
-        let value = if header == "Host" {
-            if let Some(ref replace) = config.replace_host {
-                &**replace
-            } else {
-                value
-            }
-        } else {
-            value
-        };
-        if header == "Connection" {
-            continue;
+    /// Also trusts the certificate authorities of `pem`, which contains one or more certificates
+    /// in the PEM format.
+    ///
+    /// Returns an error if `pem` doesn't contain any certificate, or if one of them is invalid.
+    pub fn add_root_certificates_pem(mut self, pem: &[u8]) -> io::Result<UpstreamTls> {
+        let certificates = rustls_pemfile::certs(&mut &pem[..])?;
+        if certificates.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no certificate in the PEM data",
+            ));
         }
+        for certificate in certificates {
+            let certificate = rustls::Certificate(certificate);
+            rustls::RootCertStore::empty()
+                .add(&certificate)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid certificate"))?;
+            self.custom_roots.push(certificate);
+        }
+        self.rebuild();
+        Ok(self)
+    }
 
-        socket.write_all(format!("{}: {}\r\n", header, value).as_bytes())?;
+    /// Stops trusting the certificate authorities of Mozilla, so that only the ones added with
+    /// `add_root_certificates_pem` are trusted.
+    #[inline]
+    pub fn without_default_roots(mut self) -> UpstreamTls {
+        self.default_roots = false;
+        self.rebuild();
+        self
     }
-    socket.write_all(b"Connection: close\r\n\r\n")?;
-    io::copy(&mut data, &mut socket)?;
 
-    let mut socket = io::BufReader::new(socket);
+    /// Accepts any certificate, without checking its issuer, its validity dates or the name of
+    /// the server.
+    ///
+    /// The connection is still encrypted, but anyone who can intercept it can impersonate the
+    /// server. This is only meant for internal servers with a self-signed certificate, on a
+    /// network that you trust.
+    #[inline]
+    pub fn danger_accept_invalid_certs(mut self) -> UpstreamTls {
+        self.insecure = true;
+        self.rebuild();
+        self
+    }
 
-    let mut headers = Vec::new();
-    let status_code;
-    {
-        let mut lines = socket.by_ref().lines();
+    fn rebuild(&mut self) {
+        let config = tls_config(self.default_roots, &self.custom_roots, self.insecure);
+        self.config = Arc::new(config);
+    }
 
-        {
-            let line = match lines.next() {
-                Some(l) => l,
-                None => return Err(ProxyError::HttpParseError),
-            }?;
-            let mut splits = line.splitn(3, ' ');
-            let _ = splits.next();
-            let status_str = match splits.next() {
-                Some(l) => l,
-                None => return Err(ProxyError::HttpParseError),
-            };
-            status_code = match status_str.parse() {
-                Ok(s) => s,
-                Err(_) => return Err(ProxyError::HttpParseError),
+    // Starts a TLS session over `socket`. The handshake happens during the first write.
+    fn connect(&self, socket: TcpStream) -> io::Result<Upstream> {
+        let connection =
+            rustls::ClientConnection::new(self.config.clone(), self.server_name.clone())
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        let stream = rustls::StreamOwned::new(connection, socket);
+        Ok(Upstream::Tls(Box::new(stream)))
+    }
+}
+
+#[cfg(feature = "proxy-tls")]
+impl fmt::Debug for UpstreamTls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UpstreamTls")
+            .field("server_name", &self.server_name)
+            .field("default_roots", &self.default_roots)
+            .field("custom_roots", &self.custom_roots.len())
+            .field("insecure", &self.insecure)
+            .finish()
+    }
+}
+
+#[cfg(feature = "proxy-tls")]
+fn tls_config(
+    default_roots: bool,
+    custom_roots: &[rustls::Certificate],
+    insecure: bool,
+) -> rustls::ClientConfig {
+    let mut roots = rustls::RootCertStore::empty();
+    if default_roots {
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+    }
+    for certificate in custom_roots {
+        // The certificates were checked when they were added.
+        let _ = roots.add(certificate);
+    }
+
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    if insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AcceptAnyCertificate));
+    }
+    config
+}
+
+// Verifier of `UpstreamTls::danger_accept_invalid_certs`.
+#[cfg(feature = "proxy-tls")]
+struct AcceptAnyCertificate;
+
+#[cfg(feature = "proxy-tls")]
+impl rustls::client::ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::Certificate,
+        _: &[rustls::Certificate],
+        _: &rustls::ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+// Connection to the server.
+enum Upstream {
+    Plain(TcpStream),
+    #[cfg(feature = "proxy-tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Upstream {
+    #[inline]
+    fn tcp(&self) -> &TcpStream {
+        match *self {
+            Upstream::Plain(ref socket) => socket,
+            #[cfg(feature = "proxy-tls")]
+            Upstream::Tls(ref stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Upstream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Upstream::Plain(ref mut socket) => socket.read(buf),
+            #[cfg(feature = "proxy-tls")]
+            Upstream::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Upstream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Upstream::Plain(ref mut socket) => socket.write(buf),
+            #[cfg(feature = "proxy-tls")]
+            Upstream::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Upstream::Plain(ref mut socket) => socket.flush(),
+            #[cfg(feature = "proxy-tls")]
+            Upstream::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+/// Idle connections to the servers, kept open to send the next requests without connecting
+/// again. Attached to a configuration with
+/// [`ProxyConfig::pool`](struct.ProxyConfig.html#method.pool).
+///
+/// The connections are indexed by the address of the server and its TLS configuration, so a
+/// single pool can be shared by the configurations of several servers. A connection is put back
+/// in the pool once the body of the response has been entirely read, if the server allows it.
+/// Connections whose response was dropped before its end are closed.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use rouille::proxy::{self, ConnectionPool, ProxyConfig};
+///
+/// let pool = Arc::new(
+///     ConnectionPool::new()
+///         .max_idle_per_host(16)
+///         .idle_timeout(Duration::from_secs(30)),
+/// );
+///
+/// rouille::start_server("0.0.0.0:80", move |request| {
+///     let config = ProxyConfig::new("localhost:8000").pool(pool.clone());
+///     proxy::full_proxy(request, config).unwrap()
+/// });
+/// ```
+pub struct ConnectionPool {
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+    // The most recently used connections are at the end.
+    idle: Mutex<HashMap<String, Vec<(Upstream, Instant)>>>,
+}
+
+impl ConnectionPool {
+    /// Builds an empty pool that keeps up to 8 idle connections per server, during 90 seconds.
+    #[inline]
+    pub fn new() -> ConnectionPool {
+        ConnectionPool {
+            max_idle_per_host: 8,
+            idle_timeout: Duration::from_secs(90),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the maximum number of idle connections kept for each server. The oldest ones are
+    /// closed first.
+    #[inline]
+    pub fn max_idle_per_host(mut self, max: usize) -> ConnectionPool {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// Sets the time after which an idle connection is closed. It should be shorter than the
+    /// keep-alive timeout of the servers.
+    #[inline]
+    pub fn idle_timeout(mut self, timeout: Duration) -> ConnectionPool {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Returns the number of idle connections, for all the servers.
+    pub fn idle_connections(&self) -> usize {
+        let idle = self.idle.lock().unwrap();
+        idle.values().map(|connections| connections.len()).sum()
+    }
+
+    // Returns the most recently used connection that is still open.
+    fn take(&self, key: &str) -> Option<Upstream> {
+        loop {
+            let (socket, idle_since) = {
+                let mut idle = self.idle.lock().unwrap();
+                idle.get_mut(key)?.pop()?
             };
+            if idle_since.elapsed() < self.idle_timeout && is_open(socket.tcp()) {
+                return Some(socket);
+            }
         }
+    }
 
-        for header in lines {
-            let header = header?;
-            if header.is_empty() {
-                break;
+    fn put(&self, key: String, socket: io::BufReader<Upstream>) {
+        // Bytes after the end of the response mean that the connection was misused.
+        if !socket.buffer().is_empty() || self.max_idle_per_host == 0 {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        let timeout = self.idle_timeout;
+        idle.retain(|_, connections| {
+            connections.retain(|&(_, idle_since)| idle_since.elapsed() < timeout);
+            !connections.is_empty()
+        });
+
+        let connections = idle.entry(key).or_default();
+        if connections.len() >= self.max_idle_per_host {
+            connections.remove(0);
+        }
+        connections.push((socket.into_inner(), Instant::now()));
+    }
+}
+
+impl Default for ConnectionPool {
+    #[inline]
+    fn default() -> ConnectionPool {
+        ConnectionPool::new()
+    }
+}
+
+impl fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("max_idle_per_host", &self.max_idle_per_host)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("idle_connections", &self.idle_connections())
+            .finish()
+    }
+}
+
+// Returns false if the server closed the connection, or sent something while it was idle.
+fn is_open(socket: &TcpStream) -> bool {
+    if socket.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let open = match socket.peek(&mut [0]) {
+        Err(ref err) => err.kind() == io::ErrorKind::WouldBlock,
+        Ok(_) => false,
+    };
+    socket.set_nonblocking(false).is_ok() && open
+}
+
+// Key of the connections to a server in a `ConnectionPool`.
+fn pool_key<A>(addr: &SocketAddr, config: &ProxyConfig<A>) -> String {
+    #[cfg(feature = "proxy-tls")]
+    {
+        if let Some(ref tls) = config.tls {
+            return format!("{} {:?}", addr, tls);
+        }
+    }
+    let _ = config;
+    addr.to_string()
+}
+
+// Body of a response whose connection goes back to the pool once it has been read.
+struct PooledBody {
+    // `None` once the connection is back in the pool.
+    body: Option<Framed>,
+    pool: Arc<ConnectionPool>,
+    key: String,
+}
+
+enum Framed {
+    Chunked(ChunkedReader<io::BufReader<Upstream>>),
+    Sized(io::Take<io::BufReader<Upstream>>),
+}
+
+impl Read for PooledBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (num, finished) = match self.body {
+            Some(Framed::Chunked(ref mut body)) => {
+                let num = body.read(buf)?;
+                (num, body.finished)
+            }
+            Some(Framed::Sized(ref mut body)) => {
+                let num = body.read(buf)?;
+                if num == 0 && body.limit() != 0 && !buf.is_empty() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                (num, body.limit() == 0)
             }
+            None => return Ok(0),
+        };
 
-            let mut splits = header.splitn(2, ':');
-            let header = match splits.next() {
-                Some(v) => v,
-                None => return Err(ProxyError::HttpParseError),
-            };
-            let val = match splits.next() {
-                Some(v) => v,
-                None => return Err(ProxyError::HttpParseError),
+        if finished {
+            let socket = match self.body.take() {
+                Some(Framed::Chunked(body)) => body.inner,
+                Some(Framed::Sized(body)) => body.into_inner(),
+                None => unreachable!(),
             };
-            let val = &val[1..];
+            self.pool.put(self.key.clone(), socket);
+        }
+        Ok(num)
+    }
+}
+
+/// How many servers a request is sent to before giving up. Attached to a configuration with
+/// [`ProxyConfig::retry`](struct.ProxyConfig.html#method.retry).
+///
+/// The servers are the addresses that the `addr` of the configuration resolves to, for example a
+/// `&[SocketAddr]` or a host name with several DNS records. They are tried one after the other,
+/// in the order given by the [`Balancer`](struct.Balancer.html) of the configuration if it has
+/// one.
+///
+/// A request is sent to the next server if it couldn't connect to the previous one, since
+/// nothing was sent yet. If the request was sent but the server failed to answer, it is sent
+/// again only if its method is idempotent (`GET`, `HEAD`, `OPTIONS`, `TRACE`, `PUT` or `DELETE`)
+/// and it has no body, which can't be read twice. The error of the last server is returned once
+/// all of them failed.
+///
+/// Without a retry policy, a request is sent to the first server that accepts the connection,
+/// which has 60 seconds to answer.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::SocketAddr;
+/// use std::time::Duration;
+/// use rouille::proxy::{self, ProxyConfig, RetryPolicy};
+///
+/// let backends: Vec<SocketAddr> = vec![
+///     "10.0.0.1:8000".parse().unwrap(),
+///     "10.0.0.2:8000".parse().unwrap(),
+/// ];
+///
+/// rouille::start_server("0.0.0.0:80", move |request| {
+///     let retry = RetryPolicy::new(2).attempt_timeout(Duration::from_secs(5));
+///     let config = ProxyConfig::new(&backends[..]).retry(retry);
+///     proxy::full_proxy(request, config).unwrap()
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    attempts: usize,
+    attempt_timeout: Duration,
+}
 
-            headers.push((header.to_owned().into(), val.to_owned().into()));
+impl RetryPolicy {
+    /// Builds a policy that tries up to `attempts` servers, each of them having 60 seconds to
+    /// answer. At least one server is always tried.
+    #[inline]
+    pub fn new(attempts: usize) -> RetryPolicy {
+        RetryPolicy {
+            attempts: attempts.max(1),
+            attempt_timeout: Duration::from_secs(60),
         }
     }
 
-    Ok(Response {
-        status_code,
-        headers,
-        data: ResponseBody::from_reader(socket),
-        upgrade: None,
-    })
+    /// Sets the time that each server has to accept the connection, and then for each read and
+    /// write of the exchange. It is shortened if the request has a deadline that comes first.
+    #[inline]
+    pub fn attempt_timeout(mut self, timeout: Duration) -> RetryPolicy {
+        self.attempt_timeout = timeout;
+        self
+    }
 }
 
-/// Error that can happen when calling `full_proxy`.
-#[derive(Debug)]
-pub enum FullProxyError {
-    /// Can't pass through the body of the request because it was already extracted.
-    BodyAlreadyExtracted,
+/// Order in which the servers of a configuration are tried, shared between the requests.
+/// Attached to a configuration with
+/// [`ProxyConfig::balancer`](struct.ProxyConfig.html#method.balancer).
+///
+/// Without a balancer, the servers are always tried in the order of the addresses, so the first
+/// one receives all the requests while it is up.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use rouille::proxy::{self, Balancer, ProxyConfig, RetryPolicy};
+///
+/// let backends: Vec<SocketAddr> = vec![
+///     "10.0.0.1:8000".parse().unwrap(),
+///     "10.0.0.2:8000".parse().unwrap(),
+/// ];
+/// let balancer = Arc::new(Balancer::least_failures());
+///
+/// rouille::start_server("0.0.0.0:80", move |request| {
+///     let config = ProxyConfig::new(&backends[..])
+///         .balancer(balancer.clone())
+///         .retry(RetryPolicy::new(2));
+///     proxy::full_proxy(request, config).unwrap()
+/// });
+/// ```
+pub struct Balancer {
+    strategy: Strategy,
+    next: AtomicUsize,
+    // Number of consecutive failures of each server. Servers that never failed aren't in it.
+    failures: Mutex<HashMap<SocketAddr, usize>>,
 }
 
-impl error::Error for FullProxyError {}
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Strategy {
+    RoundRobin,
+    LeastFailures,
+}
 
-impl fmt::Display for FullProxyError {
+impl Balancer {
+    /// Starts each request with the server that follows the one the previous request started
+    /// with.
     #[inline]
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let description = match *self {
-            FullProxyError::BodyAlreadyExtracted => "the body of the request was already extracted",
-        };
+    pub fn round_robin() -> Balancer {
+        Balancer::with_strategy(Strategy::RoundRobin)
+    }
 
-        write!(fmt, "{}", description)
+    /// Starts each request with the server that failed the fewest times in a row. Servers that
+    /// failed as many times are taken in round-robin order.
+    #[inline]
+    pub fn least_failures() -> Balancer {
+        Balancer::with_strategy(Strategy::LeastFailures)
+    }
+
+    fn with_strategy(strategy: Strategy) -> Balancer {
+        Balancer {
+            strategy,
+            next: AtomicUsize::new(0),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the number of times in a row that the server at `addr` failed to answer. It goes
+    /// back to 0 as soon as the server answers.
+    pub fn failures(&self, addr: &SocketAddr) -> usize {
+        let failures = self.failures.lock().unwrap();
+        failures.get(addr).cloned().unwrap_or(0)
+    }
+
+    // Returns the servers in the order in which they should be tried.
+    fn order(&self, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+        if addrs.is_empty() {
+            return Vec::new();
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % addrs.len();
+        let mut order: Vec<SocketAddr> = addrs[start..]
+            .iter()
+            .chain(&addrs[..start])
+            .cloned()
+            .collect();
+        if self.strategy == Strategy::LeastFailures {
+            // The sort is stable, which keeps the round-robin order between equal servers.
+            let failures = self.failures.lock().unwrap();
+            order.sort_by_key(|addr| failures.get(addr).cloned().unwrap_or(0));
+        }
+        order
+    }
+
+    fn record(&self, addr: &SocketAddr, answered: bool) {
+        let mut failures = self.failures.lock().unwrap();
+        if answered {
+            failures.remove(addr);
+        } else {
+            *failures.entry(*addr).or_insert(0) += 1;
+        }
     }
 }
 
-/// Sends the request to another HTTP server using the configuration.
+impl fmt::Debug for Balancer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Balancer")
+            .field("strategy", &self.strategy)
+            .field("failures", &*self.failures.lock().unwrap())
+            .finish()
+    }
+}
+
+// Returns true for the methods whose requests can be sent again without changing their effect.
+fn is_idempotent(method: &str) -> bool {
+    ["GET", "HEAD", "OPTIONS", "TRACE", "PUT", "DELETE"]
+        .iter()
+        .any(|m| method.eq_ignore_ascii_case(m))
+}
+
+// Opens a connection to a server, with TLS if the configuration says so.
+fn connect<A>(
+    addr: &SocketAddr,
+    timeout: Duration,
+    config: &ProxyConfig<A>,
+) -> io::Result<Upstream> {
+    let socket = TcpStream::connect_timeout(addr, timeout)?;
+    #[cfg(feature = "proxy-tls")]
+    {
+        if let Some(ref tls) = config.tls {
+            return tls.connect(socket);
+        }
+    }
+    let _ = config;
+    Ok(Upstream::Plain(socket))
+}
+
+/// Rewriting of the headers of the responses that refer to the server itself. Attached to a
+/// configuration with [`ProxyConfig::rewrite`](struct.ProxyConfig.html#method.rewrite).
 ///
-/// Contrary to `proxy`, if the server fails to return a proper response then a response is
-/// generated with the status code 502 or 504.
+/// A server behind a proxy usually doesn't know under which host and path it is reachable by
+/// the clients, and builds its redirections and cookies with its own address. A `Rewrite`
+/// replaces them with the public ones:
 ///
-/// The only possible remaining error is if the body of the request was already extracted. Since
-/// this would be a logic error, it is acceptable to unwrap it.
-pub fn full_proxy<A>(request: &Request, config: ProxyConfig<A>) -> Result<Response, FullProxyError>
-where
-    A: ToSocketAddrs,
-{
-    match proxy(request, config) {
-        Ok(r) => Ok(r),
-        Err(ProxyError::IoError(_)) => Ok(Response::text("Gateway Time-out").with_status_code(504)),
-        Err(ProxyError::HttpParseError) => Ok(Response::text("Bad Gateway").with_status_code(502)),
-        Err(ProxyError::BodyAlreadyExtracted) => Err(FullProxyError::BodyAlreadyExtracted),
+/// - The `Location` and `Content-Location` headers starting with a prefix given to `location`.
+/// - The `Domain` attribute of the `Set-Cookie` headers given to `cookie_domain`, compared
+///   without case and leading dot.
+/// - The `Path` attribute of the `Set-Cookie` headers starting with a prefix given to
+///   `cookie_path`.
+///
+/// For each header, only the first matching rule is applied, in the order in which they were
+/// added.
+///
+/// # Example
+///
+/// ```
+/// use rouille::proxy::{ProxyConfig, Rewrite};
+///
+/// let rewrite = Rewrite::new()
+///     .location("http://10.0.0.1:8000/", "https://example.com/billing/")
+///     .location("/", "/billing/")
+///     .cookie_domain("10.0.0.1", "example.com")
+///     .cookie_path("/", "/billing/");
+/// let config = ProxyConfig::new("10.0.0.1:8000").rewrite(rewrite);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Rewrite {
+    locations: Vec<(String, String)>,
+    cookie_domains: Vec<(String, String)>,
+    cookie_paths: Vec<(String, String)>,
+}
+
+impl Rewrite {
+    /// Builds a `Rewrite` that doesn't modify anything.
+    #[inline]
+    pub fn new() -> Rewrite {
+        Rewrite::default()
+    }
+
+    /// Replaces `from` with `to` at the start of the `Location` and `Content-Location` headers.
+    #[inline]
+    pub fn location<F, T>(mut self, from: F, to: T) -> Rewrite
+    where
+        F: Into<String>,
+        T: Into<String>,
+    {
+        self.locations.push((from.into(), to.into()));
+        self
+    }
+
+    /// Replaces the `Domain` attribute of the cookies set for `from` with `to`.
+    #[inline]
+    pub fn cookie_domain<F, T>(mut self, from: F, to: T) -> Rewrite
+    where
+        F: Into<String>,
+        T: Into<String>,
+    {
+        let from = from.into();
+        let from = from.trim_start_matches('.').to_owned();
+        self.cookie_domains.push((from, to.into()));
+        self
+    }
+
+    /// Replaces `from` with `to` at the start of the `Path` attribute of the cookies.
+    #[inline]
+    pub fn cookie_path<F, T>(mut self, from: F, to: T) -> Rewrite
+    where
+        F: Into<String>,
+        T: Into<String>,
+    {
+        self.cookie_paths.push((from.into(), to.into()));
+        self
+    }
+
+    fn apply(&self, headers: &mut Headers) {
+        for header in headers.iter_mut() {
+            let rewritten = if header.0.eq_ignore_ascii_case("Location")
+                || header.0.eq_ignore_ascii_case("Content-Location")
+            {
+                replace_prefix(&self.locations, &header.1)
+            } else if header.0.eq_ignore_ascii_case("Set-Cookie") {
+                self.rewrite_cookie(&header.1)
+            } else {
+                None
+            };
+            if let Some(value) = rewritten {
+                header.1 = value.into();
+            }
+        }
+    }
+
+    // Returns `None` if the cookie is left unchanged.
+    fn rewrite_cookie(&self, cookie: &str) -> Option<String> {
+        let mut changed = false;
+        // The first attribute is the name and the value of the cookie.
+        let attributes = cookie.split(';').enumerate().map(|(n, attribute)| {
+            if n == 0 {
+                return attribute.to_owned();
+            }
+            let mut split = attribute.splitn(2, '=');
+            let name = split.next().unwrap_or("").trim();
+            let value = match split.next() {
+                Some(value) => value.trim(),
+                None => return attribute.to_owned(),
+            };
+
+            let rewritten = if name.eq_ignore_ascii_case("Domain") {
+                let domain = value.trim_start_matches('.');
+                self.cookie_domains
+                    .iter()
+                    .find(|rule| rule.0.eq_ignore_ascii_case(domain))
+                    .map(|rule| rule.1.clone())
+            } else if name.eq_ignore_ascii_case("Path") {
+                replace_prefix(&self.cookie_paths, value)
+            } else {
+                None
+            };
+            match rewritten {
+                Some(value) => {
+                    changed = true;
+                    format!(" {}={}", name, value)
+                }
+                None => attribute.to_owned(),
+            }
+        });
+        let cookie = attributes.collect::<Vec<_>>().join(";");
+        if changed {
+            Some(cookie)
+        } else {
+            None
+        }
+    }
+}
+
+// Applies the first rule whose prefix matches `value`.
+fn replace_prefix(rules: &[(String, String)], value: &str) -> Option<String> {
+    rules
+        .iter()
+        .find(|rule| value.starts_with(&rule.0[..]))
+        .map(|rule| format!("{}{}", rule.1, &value[rule.0.len()..]))
+}
+
+/// Sends the request to another HTTP server using the configuration.
+///
+/// If the function fails to get a response from the target, an error is returned. If you want
+/// to instead return a response with a status code such as 502 (`Bad Gateway`) or 504
+/// (`Gateway Time-out`), see `full_proxy`.
+///
+/// With the `trace` feature, the `traceparent` header sent to the target is replaced with the
+/// [trace context](../trace/struct.TraceContext.html) of the request, if it has one.
+///
+/// The connection uses TLS if it was enabled with
+/// [`ProxyConfig::tls`](struct.ProxyConfig.html#method.tls), which requires the `proxy-tls`
+/// feature.
+///
+/// > **Note**: Implementation is very hacky for the moment.
+pub fn proxy<A>(request: &Request, config: ProxyConfig<A>) -> Result<Response, ProxyError>
+where
+    A: ToSocketAddrs,
+{
+    // The timeouts are bounded by the deadline of the request, if any.
+    let deadline_passed = || {
+        ProxyError::IoError(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the deadline of the request has passed",
+        ))
+    };
+    let remaining = request.remaining_time();
+    if remaining == Some(Duration::from_secs(0)) {
+        return Err(deadline_passed());
+    }
+
+    let addrs: Vec<SocketAddr> = config.addr.to_socket_addrs()?.collect();
+    let addrs = match config.balancer {
+        Some(ref balancer) => balancer.order(&addrs),
+        None => addrs,
+    };
+
+    let mut data = match request.data() {
+        Some(d) => d,
+        None => return Err(ProxyError::BodyAlreadyExtracted),
+    };
+
+    // The upstream server continues the trace of the span of the request, if any.
+    #[cfg(feature = "trace")]
+    let trace_context = trace::TraceContext::current(request);
+
+    // The body of the request was already decoded by the server if it was chunked, in which case
+    // it is encoded again while being sent.
+    let chunked_body = request.header("Transfer-Encoding").is_some();
+    let has_body = chunked_body
+        || request
+            .header("Content-Length")
+            .map_or(false, |len| len.trim() != "0");
+    let request_tokens = connection_tokens(request.headers());
+
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method(), request.raw_url());
+    for (header, value) in request.headers() {
+        let value = if header == "Host" {
+            if let Some(ref replace) = config.replace_host {
+                &**replace
+            } else {
+                value
+            }
+        } else {
+            value
+        };
+        // The body is sent without waiting for a `100 Continue`, which the server already sent
+        // to the client when the body was read. A `Content-Length` sent along with a
+        // `Transfer-Encoding` is ignored by the server, and forwarding it would let the upstream
+        // server read the rest of the chunked body as another request.
+        if is_hop_by_hop(header, &request_tokens)
+            || header.eq_ignore_ascii_case(TIMEOUT_HEADER)
+            || header.eq_ignore_ascii_case("Expect")
+            || (chunked_body && header.eq_ignore_ascii_case("Content-Length"))
+        {
+            continue;
+        }
+        #[cfg(feature = "trace")]
+        {
+            if header.eq_ignore_ascii_case(trace::HEADER_NAME) && trace_context.is_some() {
+                continue;
+            }
+        }
+
+        head.push_str(&format!("{}: {}\r\n", header, value));
+    }
+    if let Some(remaining) = remaining {
+        let millis = remaining.as_secs() * 1000 + u64::from(remaining.subsec_millis());
+        head.push_str(&format!("{}: {}\r\n", TIMEOUT_HEADER, millis));
+    }
+    #[cfg(feature = "trace")]
+    {
+        if let Some(context) = trace_context {
+            head.push_str(&format!("{}: {}\r\n", trace::HEADER_NAME, context));
+        }
+    }
+    if chunked_body {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    if config.pool.is_some() {
+        head.push_str("\r\n");
+    } else {
+        head.push_str("Connection: close\r\n\r\n");
+    }
+
+    let (attempts, attempt_timeout) = match config.retry {
+        Some(ref retry) => (retry.attempts, retry.attempt_timeout),
+        None => (addrs.len(), Duration::from_secs(60)),
+    };
+    let resend = config.retry.is_some() && !has_body && is_idempotent(request.method());
+
+    let mut last_error = None;
+    let mut exchanged = None;
+    for addr in addrs.iter().take(attempts) {
+        let remaining = request.remaining_time();
+        if remaining == Some(Duration::from_secs(0)) {
+            return Err(last_error.unwrap_or_else(deadline_passed));
+        }
+        let bounded = |timeout: Option<Duration>| {
+            let timeout = timeout.unwrap_or(attempt_timeout);
+            remaining.map_or(timeout, |remaining| timeout.min(remaining))
+        };
+        let timeouts = (bounded(config.read_timeout), bounded(config.write_timeout));
+        let pool = config
+            .pool
+            .as_ref()
+            .map(|pool| (pool, pool_key(addr, &config)));
+
+        // An idle connection may have been closed by the server just after it was checked, in
+        // which case the request is sent again on a new connection if nothing was read from its
+        // body.
+        let reused = pool.as_ref().and_then(|&(pool, ref key)| pool.take(key));
+        let result = match reused {
+            Some(socket) => match exchange(socket, timeouts, &head, &mut data, chunked_body) {
+                Ok(exchanged) => Some(Ok(exchanged)),
+                Err(ref err)
+                    if !has_body
+                        && GatewayErrorKind::from_proxy_error(err)
+                            != Some(GatewayErrorKind::Timeout) =>
+                {
+                    None
+                }
+                Err(err) => Some(Err(err)),
+            },
+            None => None,
+        };
+        let result = match result {
+            Some(result) => result,
+            None => match connect(addr, bounded(config.connect_timeout), &config) {
+                Ok(socket) => exchange(socket, timeouts, &head, &mut data, chunked_body),
+                Err(err) => {
+                    // Nothing was sent, so the next server can always be tried.
+                    if let Some(ref balancer) = config.balancer {
+                        balancer.record(addr, false);
+                    }
+                    last_error = Some(ProxyError::IoError(err));
+                    continue;
+                }
+            },
+        };
+
+        if let Some(ref balancer) = config.balancer {
+            balancer.record(addr, result.is_ok());
+        }
+        match result {
+            Ok(result) => {
+                exchanged = Some((result, pool));
+                break;
+            }
+            Err(err) => {
+                last_error = Some(err);
+                if !resend {
+                    break;
+                }
+            }
+        }
+    }
+
+    let ((socket, keep_alive, status_code, mut headers), pool) = match exchanged {
+        Some(exchanged) => exchanged,
+        None => {
+            return Err(last_error.unwrap_or_else(|| {
+                ProxyError::IoError(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the address of the server didn't resolve to anything",
+                ))
+            }))
+        }
+    };
+
+    // Responses to `HEAD` requests, and some status codes, never have a body, whatever their
+    // headers say.
+    let no_body = request.method().eq_ignore_ascii_case("HEAD")
+        || (100..200).contains(&status_code)
+        || status_code == 204
+        || status_code == 304;
+    let chunked = headers
+        .iter()
+        .any(|h: &(Cow<'static, str>, Cow<'static, str>)| {
+            h.0.eq_ignore_ascii_case("Transfer-Encoding")
+                && h.1.to_ascii_lowercase().contains("chunked")
+        });
+    let length = headers
+        .iter()
+        .find(|h| h.0.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|h| h.1.trim().parse::<usize>().ok());
+
+    // The framing of the body is chosen again by the server when sending the response, so the
+    // hop-by-hop headers of the upstream server are dropped.
+    let response_tokens = connection_tokens(headers.iter().map(|h| (&*h.0, &*h.1)));
+    headers.retain(|h| !is_hop_by_hop(&h.0, &response_tokens));
+    if let Some(ref rewrite) = config.rewrite {
+        rewrite.apply(&mut headers);
+    }
+
+    // The connection goes back to the pool once the body has been read, if its end can be
+    // found without the server closing the connection.
+    let release = match pool {
+        Some((pool, key))
+            if keep_alive
+                && !response_tokens.iter().any(|t| t == "close")
+                && (no_body || chunked || length.is_some()) =>
+        {
+            Some((pool.clone(), key))
+        }
+        _ => None,
+    };
+
+    let data = if no_body {
+        if let Some((pool, key)) = release {
+            pool.put(key, socket);
+        }
+        ResponseBody::empty()
+    } else if chunked {
+        let body = ChunkedReader::new(socket);
+        match release {
+            Some((pool, key)) => ResponseBody::from_reader(PooledBody {
+                body: Some(Framed::Chunked(body)),
+                pool,
+                key,
+            }),
+            None => ResponseBody::from_reader(body),
+        }
+    } else if let Some(length) = length {
+        let body = socket.take(length as u64);
+        match release {
+            Some((pool, key)) => ResponseBody::from_reader_and_size(
+                PooledBody {
+                    body: Some(Framed::Sized(body)),
+                    pool,
+                    key,
+                },
+                length,
+            ),
+            None => ResponseBody::from_reader_and_size(body, length),
+        }
+    } else {
+        ResponseBody::from_reader(socket)
+    };
+
+    Ok(Response {
+        status_code,
+        headers,
+        data,
+        upgrade: None,
+    })
+}
+
+// Sends the head and the body of a request, and reads the head of the response. Returns whether
+// the server supports persistent connections, the status code and the headers.
+fn exchange<R>(
+    mut socket: Upstream,
+    (read_timeout, write_timeout): (Duration, Duration),
+    head: &str,
+    data: &mut R,
+    chunked: bool,
+) -> Result<(io::BufReader<Upstream>, bool, u16, Headers), ProxyError>
+where
+    R: Read + ?Sized,
+{
+    socket.tcp().set_read_timeout(Some(read_timeout))?;
+    socket.tcp().set_write_timeout(Some(write_timeout))?;
+
+    {
+        let mut writer = io::BufWriter::new(&mut socket);
+        writer.write_all(head.as_bytes())?;
+        if chunked {
+            write_chunked(data, &mut writer)?;
+        } else {
+            io::copy(data, &mut writer)?;
+        }
+        writer.flush()?;
+    }
+
+    let mut socket = io::BufReader::new(socket);
+    let (keep_alive, status_code, headers) = read_response_head(&mut socket)?;
+    Ok((socket, keep_alive, status_code, headers))
+}
+
+// Headers of a response received from the remote server.
+type Headers = Vec<(Cow<'static, str>, Cow<'static, str>)>;
+
+// Reads the status line and the headers of the final response, skipping the interim responses
+// such as `100 Continue` or `103 Early Hints`. The boolean is true if the server supports
+// persistent connections, which is the default from HTTP/1.1.
+fn read_response_head<R>(socket: &mut R) -> Result<(bool, u16, Headers), ProxyError>
+where
+    R: BufRead,
+{
+    loop {
+        let head = read_head(socket)?;
+        match head.1 {
+            // `101 Switching Protocols` is the final response of an upgrade.
+            100..=199 if head.1 != 101 => continue,
+            _ => return Ok(head),
+        }
+    }
+}
+
+// Reads the status line and the headers of a response, final or interim.
+fn read_head<R>(socket: &mut R) -> Result<(bool, u16, Headers), ProxyError>
+where
+    R: BufRead,
+{
+    let mut lines = socket.lines();
+
+    let (keep_alive, status_code) = {
+        let line = match lines.next() {
+            Some(l) => l,
+            None => return Err(ProxyError::HttpParseError),
+        }?;
+        let mut splits = line.splitn(3, ' ');
+        let version = splits.next().unwrap_or("");
+        let status_str = match splits.next() {
+            Some(l) => l,
+            None => return Err(ProxyError::HttpParseError),
+        };
+        let status_code = match status_str.parse() {
+            Ok(s) => s,
+            Err(_) => return Err(ProxyError::HttpParseError),
+        };
+        (version != "HTTP/1.0", status_code)
+    };
+
+    let mut headers = Vec::new();
+    for header in lines {
+        let header = header?;
+        if header.is_empty() {
+            break;
+        }
+
+        let mut splits = header.splitn(2, ':');
+        let header = match splits.next() {
+            Some(v) => v,
+            None => return Err(ProxyError::HttpParseError),
+        };
+        let val = match splits.next() {
+            Some(v) => v,
+            None => return Err(ProxyError::HttpParseError),
+        };
+        let val = val.trim();
+
+        headers.push((header.to_owned().into(), val.to_owned().into()));
+    }
+
+    Ok((keep_alive, status_code, headers))
+}
+
+// Returns the lowercase names listed in the `Connection` headers, which are hop-by-hop as well.
+fn connection_tokens<'a, I>(headers: I) -> Vec<String>
+where
+    I: Iterator<Item = (&'a str, &'a str)>,
+{
+    headers
+        .filter(|&(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+// Returns true if the header only applies to a single connection, and must not be forwarded.
+fn is_hop_by_hop(name: &str, connection_tokens: &[String]) -> bool {
+    const HOP_BY_HOP: &[&str] = &[
+        "Connection",
+        "Keep-Alive",
+        "Proxy-Connection",
+        "TE",
+        "Trailer",
+        "Transfer-Encoding",
+        "Upgrade",
+    ];
+
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+        || connection_tokens
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(name))
+}
+
+// Copies `data` to `out` with the chunked transfer encoding.
+fn write_chunked<R, W>(data: &mut R, out: &mut W) -> io::Result<()>
+where
+    R: Read + ?Sized,
+    W: Write,
+{
+    let mut buf = vec![0; 8 * 1024];
+    loop {
+        let num = match data.read(&mut buf) {
+            Ok(0) => break,
+            Ok(num) => num,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        write!(out, "{:x}\r\n", num)?;
+        out.write_all(&buf[..num])?;
+        out.write_all(b"\r\n")?;
+    }
+    out.write_all(b"0\r\n\r\n")
+}
+
+// Decodes a body that uses the chunked transfer encoding. The trailers are discarded.
+struct ChunkedReader<R> {
+    inner: R,
+    // Number of bytes left in the current chunk, or `None` before reading the size of the next
+    // chunk.
+    remaining: Option<u64>,
+    finished: bool,
+}
+
+impl<R> ChunkedReader<R>
+where
+    R: BufRead,
+{
+    fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: None,
+            finished: false,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line)
+    }
+}
+
+impl<R> Read for ChunkedReader<R>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let line = self.read_line()?;
+                let size = line.split(';').next().unwrap_or("").trim();
+                let size = u64::from_str_radix(size, 16).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size")
+                })?;
+                if size == 0 {
+                    // Skipping the trailers.
+                    while self.read_line()?.trim_end() != "" {}
+                    self.finished = true;
+                    return Ok(0);
+                }
+                size
+            }
+        };
+
+        let max = remaining.min(buf.len() as u64) as usize;
+        let num = self.inner.read(&mut buf[..max])?;
+        if num == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if num as u64 == remaining {
+            // The chunk is followed by a CRLF.
+            self.read_line()?;
+            self.remaining = None;
+        } else {
+            self.remaining = Some(remaining - num as u64);
+        }
+        Ok(num)
+    }
+}
+
+/// Error that can happen when calling `full_proxy`.
+#[derive(Debug)]
+pub enum FullProxyError {
+    /// Can't pass through the body of the request because it was already extracted.
+    BodyAlreadyExtracted,
+}
+
+impl error::Error for FullProxyError {}
+
+impl fmt::Display for FullProxyError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            FullProxyError::BodyAlreadyExtracted => "the body of the request was already extracted",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// Sends the request to another HTTP server using the configuration.
+///
+/// Contrary to `proxy`, if the server fails to return a proper response then a response is
+/// generated with the status code 502 or 504: 504 if the server didn't answer in time, and 502
+/// otherwise, for example if it refused the connection. This response can be replaced with
+/// [`ProxyConfig::on_error`](struct.ProxyConfig.html#method.on_error).
+///
+/// The only possible remaining error is if the body of the request was already extracted. Since
+/// this would be a logic error, it is acceptable to unwrap it.
+pub fn full_proxy<A>(request: &Request, config: ProxyConfig<A>) -> Result<Response, FullProxyError>
+where
+    A: ToSocketAddrs,
+{
+    let on_error = config.on_error.clone();
+    match proxy(request, config) {
+        Ok(r) => Ok(r),
+        Err(err) => match (GatewayErrorKind::from_proxy_error(&err), on_error) {
+            (Some(kind), Some(on_error)) => Ok(on_error(request, kind)),
+            (Some(GatewayErrorKind::Timeout), None) => {
+                Ok(Response::text("Gateway Time-out").with_status_code(504))
+            }
+            (Some(_), None) => Ok(Response::text("Bad Gateway").with_status_code(502)),
+            (None, _) => Err(FullProxyError::BodyAlreadyExtracted),
+        },
+    }
+}
+
+/// Same as `full_proxy`, but the responses generated when the server fails are built with
+/// `GatewayError`, so that they tell the client what went wrong in a machine-readable way.
+///
+/// `upstream` is the name of the server, as reported to the client.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Request, Response};
+/// use rouille::proxy::{self, ProxyConfig};
+///
+/// fn handle(request: &Request) -> Response {
+///     let config = ProxyConfig::new("billing.localnetwork:80");
+///     proxy::gateway_proxy(request, "billing", config).unwrap()
+/// }
+/// ```
+pub fn gateway_proxy<U, A>(
+    request: &Request,
+    upstream: U,
+    config: ProxyConfig<A>,
+) -> Result<Response, FullProxyError>
+where
+    U: Into<Cow<'static, str>>,
+    A: ToSocketAddrs,
+{
+    let on_error = config.on_error.clone();
+    match proxy(request, config) {
+        Ok(r) => Ok(r),
+        Err(err) => match (GatewayErrorKind::from_proxy_error(&err), on_error) {
+            (Some(kind), Some(on_error)) => Ok(on_error(request, kind)),
+            (Some(kind), None) => Ok(GatewayError::new(request, kind, upstream).into_response()),
+            (None, _) => Err(FullProxyError::BodyAlreadyExtracted),
+        },
+    }
+}
+
+/// Reason why a request couldn't be dispatched to another server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GatewayErrorKind {
+    /// Couldn't connect to the server, or the connection was closed unexpectedly. Reported with
+    /// the status code 502.
+    Connect,
+    /// The server didn't produce compliant HTTP. Reported with the status code 502.
+    Protocol,
+    /// The server didn't answer in time. Reported with the status code 504.
+    Timeout,
+    /// The request wasn't sent because a circuit breaker considers that the server is down.
+    /// Reported with the status code 503.
+    CircuitOpen,
+}
+
+impl GatewayErrorKind {
+    /// Determines the kind of a `ProxyError`. Returns `None` for `BodyAlreadyExtracted`, which
+    /// isn't the fault of the server.
+    pub fn from_proxy_error(err: &ProxyError) -> Option<GatewayErrorKind> {
+        match *err {
+            ProxyError::BodyAlreadyExtracted => None,
+            ProxyError::IoError(ref err) => match err.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+                    Some(GatewayErrorKind::Timeout)
+                }
+                _ => Some(GatewayErrorKind::Connect),
+            },
+            ProxyError::HttpParseError => Some(GatewayErrorKind::Protocol),
+        }
+    }
+
+    /// Returns the status code of the response that reports this kind of error.
+    #[inline]
+    pub fn status_code(&self) -> u16 {
+        match *self {
+            GatewayErrorKind::Connect | GatewayErrorKind::Protocol => 502,
+            GatewayErrorKind::Timeout => 504,
+            GatewayErrorKind::CircuitOpen => 503,
+        }
+    }
+
+    // Identifier of the error in the body of the response.
+    fn code(&self) -> &'static str {
+        match *self {
+            GatewayErrorKind::Connect => "upstream_connect_error",
+            GatewayErrorKind::Protocol => "upstream_protocol_error",
+            GatewayErrorKind::Timeout => "upstream_timeout",
+            GatewayErrorKind::CircuitOpen => "upstream_circuit_open",
+        }
+    }
+}
+
+/// Response reporting that a request couldn't be dispatched to another server.
+///
+/// The body of the response is a JSON object such as:
+///
+/// ```json
+/// {
+///     "error": "upstream_timeout",
+///     "upstream": "billing",
+///     "correlation_id": "Xk2f9QbLr0aZ3mPc"
+/// }
+/// ```
+///
+/// The correlation ID is also put in the `X-Correlation-Id` header of the response, so that it
+/// can be found in the logs when the client reports the error. The name of the server, or the
+/// correlation ID, can be left out of the response if they shouldn't be disclosed.
+///
+/// # Example
+///
+/// ```
+/// use rouille::Request;
+/// use rouille::proxy::{GatewayError, GatewayErrorKind};
+///
+/// # let request = Request::fake_http("GET", "/", vec![], vec![]);
+/// let response = GatewayError::new(&request, GatewayErrorKind::CircuitOpen, "billing")
+///     .redact_upstream()
+///     .into_response();
+/// assert_eq!(response.status_code, 503);
+/// ```
+#[derive(Debug, Clone)]
+pub struct GatewayError {
+    kind: GatewayErrorKind,
+    upstream: Option<Cow<'static, str>>,
+    correlation_id: Option<String>,
+}
+
+impl GatewayError {
+    /// Builds the error for the given request.
+    ///
+    /// The correlation ID is the value of the `X-Request-Id` header of the request if there is
+    /// one, otherwise it is randomly generated.
+    pub fn new<U>(request: &Request, kind: GatewayErrorKind, upstream: U) -> GatewayError
+    where
+        U: Into<Cow<'static, str>>,
+    {
+        let correlation_id = match request.header("X-Request-Id") {
+            Some(id) => id.to_owned(),
+            None => request.random().alphanumeric(16),
+        };
+
+        GatewayError {
+            kind,
+            upstream: Some(upstream.into()),
+            correlation_id: Some(correlation_id),
+        }
+    }
+
+    /// Returns the kind of the error.
+    #[inline]
+    pub fn kind(&self) -> GatewayErrorKind {
+        self.kind
+    }
+
+    /// Returns the correlation ID, unless it was redacted.
+    #[inline]
+    pub fn get_correlation_id(&self) -> Option<&str> {
+        self.correlation_id.as_ref().map(|id| &id[..])
+    }
+
+    /// Replaces the correlation ID.
+    #[inline]
+    pub fn correlation_id<I>(mut self, id: I) -> GatewayError
+    where
+        I: Into<String>,
+    {
+        self.correlation_id = Some(id.into());
+        self
+    }
+
+    /// Leaves the name of the server out of the response.
+    #[inline]
+    pub fn redact_upstream(mut self) -> GatewayError {
+        self.upstream = None;
+        self
+    }
+
+    /// Leaves the correlation ID out of the response.
+    #[inline]
+    pub fn redact_correlation_id(mut self) -> GatewayError {
+        self.correlation_id = None;
+        self
+    }
+
+    /// Builds the response.
+    pub fn into_response(self) -> Response {
+        let status_code = self.kind.status_code();
+        let body = GatewayErrorJson {
+            error: self.kind.code(),
+            upstream: self.upstream.as_ref().map(|u| &u[..]),
+            correlation_id: self.correlation_id.as_ref().map(|id| &id[..]),
+        };
+        let response = Response::json(&body).with_status_code(status_code);
+
+        match self.correlation_id {
+            Some(id) => response.with_unique_header("X-Correlation-Id", id),
+            None => response,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GatewayErrorJson<'a> {
+    error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<&'a str>,
+}
+
+/// Decodes the body of a response received from `proxy` or `full_proxy` if it uses a
+/// `Content-Encoding` that the client doesn't accept, then encodes it again with
+/// [`content_encoding::apply`](../content_encoding/fn.apply.html).
+///
+/// Responses whose encoding is accepted by the client, or that aren't encoded, are returned
+/// unchanged. The `Content-Length` of the new response is determined from its new body.
+///
+/// Only the `br` encoding can be decoded, and only if the `brotli` feature of rouille is enabled.
+/// Responses that use another encoding that the client doesn't accept are replaced with a
+/// `502 Bad Gateway` response, as the client couldn't read them.
+///
+/// # Example
+///
+/// ```no_run
+/// use rouille::{Request, Response};
+/// use rouille::proxy::{self, ProxyConfig};
+///
+/// fn handle(request: &Request) -> Response {
+///     let config = ProxyConfig::new("localhost:8000");
+///     let response = proxy::full_proxy(request, config).unwrap();
+///     proxy::transcode(request, response)
+/// }
+/// ```
+pub fn transcode(request: &Request, response: Response) -> Response {
+    let encoding = match response
+        .headers
+        .iter()
+        .find(|h| h.0.eq_ignore_ascii_case("Content-Encoding"))
+    {
+        Some(h) => h.1.trim().to_ascii_lowercase(),
+        None => return response,
+    };
+
+    if encoding.is_empty() || encoding == "identity" {
+        return response;
+    }
+
+    let accepted = content_encoding::accepted_content_encodings(request).any(|accepted| {
+        let name = accepted.split(';').next().unwrap_or("").trim();
+        name == "*" || name.eq_ignore_ascii_case(&encoding)
+    });
+    if accepted {
+        return response;
+    }
+
+    match decode(&encoding, response) {
+        Some(response) => content_encoding::apply(request, response),
+        None => Response::text("Bad Gateway").with_status_code(502),
+    }
+}
+
+// Removes the `Content-Encoding` of a response by decoding its body, or returns `None` if the
+// encoding isn't supported.
+#[cfg(feature = "brotli")]
+fn decode(encoding: &str, response: Response) -> Option<Response> {
+    use brotli2::read::BrotliDecoder;
+
+    if encoding != "br" {
+        return None;
+    }
+
+    let mut response = response.without_header("Content-Encoding");
+    let (data, _) = response.data.into_reader_and_size();
+    response.data = ResponseBody::from_reader(BrotliDecoder::new(data));
+    Some(response)
+}
+
+#[cfg(not(feature = "brotli"))]
+#[inline]
+fn decode(_: &str, _: Response) -> Option<Response> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::full_proxy;
+    use super::proxy;
+    use super::transcode;
+    use super::Balancer;
+    use super::ConnectionPool;
+    use super::GatewayError;
+    use super::GatewayErrorKind;
+    use super::ProxyConfig;
+    use super::ProxyError;
+    use super::RetryPolicy;
+    use super::Rewrite;
+    use std::io;
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+    use test::TestResponse;
+    use Deadline;
+    use Request;
+    use Response;
+
+    fn request(accept_encoding: &str) -> Request {
+        let headers = vec![("Accept-Encoding".to_owned(), accept_encoding.to_owned())];
+        Request::fake_http("GET", "/", headers, vec![])
+    }
+
+    #[test]
+    fn accepted_or_unknown_encoding() {
+        let upstream = || Response::text("abc").with_unique_header("Content-Encoding", "gzip");
+
+        let response = transcode(&request("gzip, br"), upstream());
+        assert_eq!(response.status_code, 200);
+        assert!(response.headers.iter().any(|h| h.0 == "Content-Encoding"));
+
+        let response = transcode(&request("compress"), upstream());
+        assert_eq!(response.status_code, 502);
+
+        let response = transcode(&request(""), Response::text("abc"));
+        assert_eq!(response.status_code, 200);
+    }
+
+    #[test]
+    fn deadline_propagation() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            head
+        });
+
+        let headers = vec![("X-Request-Timeout".to_owned(), "999999".to_owned())];
+        let request = Request::fake_http("GET", "/", headers, vec![]);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        request.extensions().insert(Deadline(deadline));
+        let config = ProxyConfig::new(addr);
+        assert_eq!(proxy(&request, config).unwrap().status_code, 204);
+
+        let head = upstream.join().unwrap();
+        let timeout = head
+            .lines()
+            .find(|l| l.starts_with("X-Request-Timeout: "))
+            .unwrap();
+        let millis: u64 = timeout["X-Request-Timeout: ".len()..].parse().unwrap();
+        assert!(millis > 0 && millis <= 5000);
+        assert_eq!(head.matches("X-Request-Timeout").count(), 1);
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        request.extensions().insert(Deadline(Instant::now()));
+        let config = ProxyConfig::new(addr);
+        match proxy(&request, config) {
+            Err(ProxyError::IoError(err)) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+            _ => panic!(),
+        }
+    }
+
+    // Starts a server that answers a single request with `response`, and returns the request
+    // that it received.
+    fn upstream(response: &'static [u8]) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let thread = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut received = String::new();
+            while !received.ends_with("\r\n\r\n") {
+                reader.read_line(&mut received).unwrap();
+            }
+            if received.contains("Transfer-Encoding: chunked") {
+                while !received.ends_with("\r\n0\r\n\r\n") {
+                    reader.read_line(&mut received).unwrap();
+                }
+            }
+            reader.get_mut().write_all(response).unwrap();
+            received
+        });
+        (addr, thread)
+    }
+
+    #[test]
+    fn streams_chunked_bodies() {
+        let (addr, thread) = upstream(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nKeep-Alive: timeout=5\r\n\
+              X-Custom: a\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: b\r\n\r\n",
+        );
+        let headers = vec![
+            ("Transfer-Encoding".to_owned(), "chunked".to_owned()),
+            ("Connection".to_owned(), "X-Hop".to_owned()),
+            ("X-Hop".to_owned(), "1".to_owned()),
+        ];
+        let request = Request::fake_http("POST", "/upload", headers, b"request body".to_vec());
+        let config = ProxyConfig::new(addr);
+        let response = proxy(&request, config).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.data.data_length, None);
+        let names: Vec<_> = response.headers.iter().map(|h| h.0.to_string()).collect();
+        assert_eq!(names, vec!["X-Custom"]);
+        assert_eq!(TestResponse::from(response).text(), "hello world");
+
+        let received = thread.join().unwrap();
+        assert!(received.ends_with("\r\n\r\nc\r\nrequest body\r\n0\r\n\r\n"));
+        assert_eq!(received.matches("Transfer-Encoding").count(), 1);
+        assert!(!received.contains("X-Hop"));
+    }
+
+    #[test]
+    fn chunked_body_drops_content_length() {
+        let (addr, thread) = upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let headers = vec![
+            ("Content-Length".to_owned(), "3".to_owned()),
+            ("Transfer-Encoding".to_owned(), "chunked".to_owned()),
+        ];
+        let request = Request::fake_http("POST", "/", headers, b"abc".to_vec());
+        let config = ProxyConfig::new(addr);
+        let response = proxy(&request, config).unwrap();
+        assert_eq!(TestResponse::from(response).text(), "ok");
+
+        let received = thread.join().unwrap();
+        assert!(!received.contains("Content-Length"));
+        assert_eq!(
+            received.matches("Transfer-Encoding: chunked\r\n").count(),
+            1
+        );
+        assert!(received.ends_with("\r\n\r\n3\r\nabc\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn passes_content_length() {
+        let (addr, thread) =
+            upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, and more");
+        let headers = vec![("Content-Length".to_owned(), "3".to_owned())];
+        let request = Request::fake_http("PUT", "/", headers, b"abc".to_vec());
+        let config = ProxyConfig::new(addr);
+        let response = proxy(&request, config).unwrap();
+        assert_eq!(response.data.data_length, Some(5));
+        assert_eq!(TestResponse::from(response).text(), "hello");
+
+        let received = thread.join().unwrap();
+        assert!(received.contains("Content-Length: 3\r\n"));
+    }
+
+    #[test]
+    fn skips_interim_responses() {
+        let (addr, thread) = upstream(
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        );
+        let headers = vec![
+            ("Content-Length".to_owned(), "3".to_owned()),
+            ("Expect".to_owned(), "100-continue".to_owned()),
+        ];
+        let request = Request::fake_http("PUT", "/", headers, b"abc".to_vec());
+        let config = ProxyConfig::new(addr);
+        let response = proxy(&request, config).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert!(response.headers.iter().all(|h| h.0 != "Link"));
+        assert_eq!(TestResponse::from(response).text(), "ok");
+
+        let received = thread.join().unwrap();
+        assert!(!received.contains("Expect"));
+    }
+
+    #[test]
+    #[cfg(feature = "proxy-tls")]
+    fn tls_handshake_failure() {
+        use super::UpstreamTls;
+
+        assert!(UpstreamTls::new("not a name").is_err());
+        let tls = UpstreamTls::new("localhost").unwrap();
+        assert!(tls.clone().add_root_certificates_pem(b"garbage").is_err());
+
+        // A server that doesn't speak TLS.
+        let (addr, _thread) = upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let config = ProxyConfig::new(addr).tls(tls.danger_accept_invalid_certs());
+        match proxy(&request, config) {
+            Err(ProxyError::IoError(_)) => (),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn connection_pool() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream = thread::spawn(move || {
+            // Both requests are received on the same connection.
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut heads = Vec::new();
+            for response in &[
+                &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst"[..],
+                &b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 6\r\n\r\nsecond"[..],
+            ] {
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+                reader.get_mut().write_all(response).unwrap();
+                heads.push(head);
+            }
+            heads
+        });
+
+        let pool = Arc::new(ConnectionPool::new());
+        let send = || {
+            let request = Request::fake_http("GET", "/", vec![], vec![]);
+            let config = ProxyConfig::new(addr).pool(pool.clone());
+            TestResponse::from(proxy(&request, config).unwrap()).text()
+        };
+
+        assert_eq!(send(), "first");
+        assert_eq!(pool.idle_connections(), 1);
+        assert_eq!(send(), "second");
+        // The server asked to close the connection.
+        assert_eq!(pool.idle_connections(), 0);
+
+        let heads = upstream.join().unwrap();
+        assert!(heads.iter().all(|head| !head.contains("Connection: close")));
+    }
+
+    #[test]
+    fn balancer_order() {
+        let addrs: Vec<SocketAddr> = (1..4)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect();
+
+        let balancer = Balancer::round_robin();
+        assert_eq!(balancer.order(&addrs), addrs);
+        assert_eq!(balancer.order(&addrs), vec![addrs[1], addrs[2], addrs[0]]);
+
+        let balancer = Balancer::least_failures();
+        balancer.record(&addrs[0], false);
+        balancer.record(&addrs[0], false);
+        balancer.record(&addrs[1], false);
+        assert_eq!(balancer.order(&addrs), vec![addrs[2], addrs[1], addrs[0]]);
+        balancer.record(&addrs[0], true);
+        assert_eq!(balancer.failures(&addrs[0]), 0);
+        assert_eq!(balancer.order(&addrs), vec![addrs[2], addrs[0], addrs[1]]);
+    }
+
+    #[test]
+    fn failover() {
+        // An address on which nothing listens.
+        let down = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        // A server that closes the connections without answering.
+        let broken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broken_addr = broken.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in broken.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") && reader.read_line(&mut head).unwrap_or(0) != 0 {
+                }
+            }
+        });
+
+        let balancer = Arc::new(Balancer::round_robin());
+        let (up, thread) = upstream(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        let addrs = [down, broken_addr, up];
+        let send = |method, body: &[u8]| {
+            let request = Request::fake_http(method, "/", vec![], body.to_vec());
+            let config = ProxyConfig::new(&addrs[..])
+                .balancer(balancer.clone())
+                .retry(RetryPolicy::new(3).attempt_timeout(Duration::from_secs(5)));
+            proxy(&request, config)
+        };
+
+        // Not sent again, since the server may have processed it.
+        match send("POST", b"") {
+            Err(ProxyError::HttpParseError) | Err(ProxyError::IoError(_)) => (),
+            _ => panic!(),
+        }
+        assert_eq!(balancer.failures(&down), 1);
+        assert_eq!(balancer.failures(&broken_addr), 1);
+
+        // Starts with the second server, which fails again.
+        assert_eq!(TestResponse::from(send("GET", b"").unwrap()).text(), "ok");
+        assert_eq!(balancer.failures(&down), 1);
+        assert_eq!(balancer.failures(&broken_addr), 2);
+        assert_eq!(balancer.failures(&up), 0);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn timeouts_and_error_handler() {
+        // A server that accepts the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _thread = thread::spawn(move || {
+            let _stream = listener.accept();
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let config = ProxyConfig::new(addr).read_timeout(Duration::from_millis(100));
+        let response = full_proxy(&request, config).unwrap();
+        assert_eq!(response.status_code, 504);
+
+        let down = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = ProxyConfig::new(down).on_error(|_, kind| {
+            Response::text(format!("{:?}", kind)).with_status_code(kind.status_code())
+        });
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = full_proxy(&request, config).unwrap();
+        assert_eq!(response.status_code, 502);
+        assert_eq!(TestResponse::from(response).text(), "Connect");
+    }
+
+    #[test]
+    fn rewrite_headers() {
+        let rewrite = Rewrite::new()
+            .location("http://10.0.0.1:8000/", "https://example.com/app/")
+            .location("/", "/app/")
+            .cookie_domain(".10.0.0.1", "example.com")
+            .cookie_path("/", "/app/");
+        let mut headers = vec![
+            (
+                "Location".into(),
+                "http://10.0.0.1:8000/login?next=/".into(),
+            ),
+            ("content-location".into(), "/items/1".into()),
+            ("Location".into(), "https://other.com/".into()),
+            (
+                "Set-Cookie".into(),
+                "Path=abc; Domain=10.0.0.1; path=/admin; Secure".into(),
+            ),
+            ("Set-Cookie".into(), "id=1; Domain=other.com".into()),
+        ];
+        rewrite.apply(&mut headers);
+
+        let values: Vec<_> = headers.iter().map(|h| &h.1[..]).collect();
+        assert_eq!(
+            values,
+            vec![
+                "https://example.com/app/login?next=/",
+                "/app/items/1",
+                "https://other.com/",
+                "Path=abc; Domain=example.com; path=/app/admin; Secure",
+                "id=1; Domain=other.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn gateway_error_kinds() {
+        let timeout = ProxyError::IoError(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+        let refused = ProxyError::IoError(io::ErrorKind::ConnectionRefused.into());
+        assert_eq!(
+            GatewayErrorKind::from_proxy_error(&timeout),
+            Some(GatewayErrorKind::Timeout)
+        );
+        assert_eq!(
+            GatewayErrorKind::from_proxy_error(&refused),
+            Some(GatewayErrorKind::Connect)
+        );
+        assert_eq!(
+            GatewayErrorKind::from_proxy_error(&ProxyError::HttpParseError),
+            Some(GatewayErrorKind::Protocol)
+        );
+        assert_eq!(
+            GatewayErrorKind::from_proxy_error(&ProxyError::BodyAlreadyExtracted),
+            None
+        );
+    }
+
+    #[test]
+    fn gateway_error_response() {
+        let headers = vec![("X-Request-Id".to_owned(), "abc".to_owned())];
+        let request = Request::fake_http("GET", "/", headers, vec![]);
+
+        let response =
+            GatewayError::new(&request, GatewayErrorKind::Timeout, "billing").into_response();
+        assert_eq!(response.status_code, 504);
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.0 == "X-Correlation-Id" && h.1 == "abc"));
+        assert_eq!(
+            TestResponse::from(response).text(),
+            r#"{"error":"upstream_timeout","upstream":"billing","correlation_id":"abc"}"#
+        );
+
+        let response = GatewayError::new(&request, GatewayErrorKind::CircuitOpen, "billing")
+            .redact_upstream()
+            .redact_correlation_id()
+            .into_response();
+        assert_eq!(response.status_code, 503);
+        assert!(!response.headers.iter().any(|h| h.0 == "X-Correlation-Id"));
+        assert_eq!(
+            TestResponse::from(response).text(),
+            r#"{"error":"upstream_circuit_open"}"#
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "brotli")]
+    fn decode_brotli() {
+        use brotli2::read::BrotliEncoder;
+
+        let mut compressed = Vec::new();
+        BrotliEncoder::new(&b"hello world"[..], 6)
+            .read_to_end(&mut compressed)
+            .unwrap();
+        let upstream = Response::from_data("text/plain", compressed)
+            .with_unique_header("Content-Encoding", "br");
+
+        let response = transcode(&request("identity"), upstream);
+        assert!(!response.headers.iter().any(|h| h.0 == "Content-Encoding"));
+        assert_eq!(TestResponse::from(response).text(), "hello world");
     }
 }
@@ -0,0 +1,211 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Source of the randomness used to generate identifiers.
+//!
+//! Rouille generates identifiers, such as session IDs, from the [`Random`](struct.Random.html)
+//! source attached to the request. By default this is the randomness of the operating system,
+//! but a server can be given another source with
+//! [`Server::random`](../struct.Server.html#method.random). This is useful to make tests or the
+//! replay of a bug deterministic.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::Request;
+//! use rouille::random::Random;
+//!
+//! let request = Request::fake_http("GET", "/", vec![], vec![]);
+//! request.extensions().insert(Random::seeded(42));
+//! let first = request.random().alphanumeric(16);
+//!
+//! let request = Request::fake_http("GET", "/", vec![], vec![]);
+//! request.extensions().insert(Random::seeded(42));
+//! assert_eq!(request.random().alphanumeric(16), first);
+//! ```
+//!
+//! > **Warning**: Never use a seeded source in production. Anyone who knows the seed can
+//! > predict all the identifiers, including session IDs.
+
+use rand::rngs::OsRng;
+use rand::rngs::StdRng;
+use rand::RngCore;
+use rand::SeedableRng;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use Request;
+
+/// Generator of random bytes.
+///
+/// Implement this trait to provide your own source of randomness to
+/// [`Random::new`](struct.Random.html#method.new).
+pub trait RandomSource: Send + Sync {
+    /// Fills `dest` with random bytes.
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// Randomness of the operating system. This is the default source.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct OsRandom;
+
+impl RandomSource for OsRandom {
+    #[inline]
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest)
+    }
+}
+
+/// Deterministic source that always produces the same bytes for the same seed.
+pub struct SeededRandom {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededRandom {
+    /// Builds a source from a seed.
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        match self.rng.lock() {
+            Ok(mut rng) => rng.fill_bytes(dest),
+            Err(poisoned) => poisoned.into_inner().fill_bytes(dest),
+        }
+    }
+}
+
+impl fmt::Debug for SeededRandom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SeededRandom").finish()
+    }
+}
+
+/// A shared source of randomness.
+///
+/// Cloning a `Random` is cheap, and the clones share the same source. Inserting a `Random` in
+/// the [extensions](../struct.Request.html#method.extensions) of a request makes
+/// [`Request::random`](../struct.Request.html#method.random) return it.
+#[derive(Clone)]
+pub struct Random {
+    source: Arc<dyn RandomSource>,
+}
+
+impl Random {
+    /// Wraps around a source of randomness.
+    #[inline]
+    pub fn new<R>(source: R) -> Random
+    where
+        R: RandomSource + 'static,
+    {
+        Random {
+            source: Arc::new(source),
+        }
+    }
+
+    /// Returns the randomness of the operating system.
+    #[inline]
+    pub fn os() -> Random {
+        Random::new(OsRandom)
+    }
+
+    /// Returns a deterministic source. See [`SeededRandom`](struct.SeededRandom.html).
+    #[inline]
+    pub fn seeded(seed: u64) -> Random {
+        Random::new(SeededRandom::new(seed))
+    }
+
+    /// Returns the source attached to `request`, or the randomness of the operating system if
+    /// there is none. Same as [`Request::random`](../struct.Request.html#method.random).
+    pub fn of(request: &Request) -> Random {
+        request
+            .extensions()
+            .get::<Random>()
+            .cloned()
+            .unwrap_or_else(Random::os)
+    }
+
+    /// Fills `dest` with random bytes.
+    #[inline]
+    pub fn fill_bytes(&self, dest: &mut [u8]) {
+        self.source.fill_bytes(dest)
+    }
+
+    /// Returns a random `u64`.
+    pub fn next_u64(&self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        bytes
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+    }
+
+    /// Returns a string of `len` ASCII letters and digits.
+    ///
+    /// The string doesn't contain any punctuation or character that could need to be escaped.
+    pub fn alphanumeric(&self, len: usize) -> String {
+        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+        let mut output = String::with_capacity(len);
+        let mut bytes = [0; 64];
+        while output.len() < len {
+            self.fill_bytes(&mut bytes);
+            // Rejecting the bytes above the largest multiple of the charset length avoids biasing
+            // the first characters.
+            for &byte in bytes.iter() {
+                if usize::from(byte) < 256 - 256 % CHARSET.len() && output.len() < len {
+                    output.push(char::from(CHARSET[usize::from(byte) % CHARSET.len()]));
+                }
+            }
+        }
+        output
+    }
+}
+
+impl Default for Random {
+    #[inline]
+    fn default() -> Random {
+        Random::os()
+    }
+}
+
+impl fmt::Debug for Random {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Random").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Random;
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let a = Random::seeded(7);
+        let b = Random::seeded(7);
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert_eq!(a.alphanumeric(100), b.alphanumeric(100));
+        assert_ne!(
+            Random::seeded(8).alphanumeric(32),
+            Random::seeded(7).alphanumeric(32)
+        );
+    }
+
+    #[test]
+    fn alphanumeric() {
+        let id = Random::os().alphanumeric(200);
+        assert_eq!(id.len(), 200);
+        assert!(id.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}
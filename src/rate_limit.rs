@@ -0,0 +1,492 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Limiting the rate of requests of each client.
+//!
+//! A [`RateLimiter`](struct.RateLimiter.html) gives each client (identified by its IP address,
+//! as returned by [`Request::client_addr`](../struct.Request.html#method.client_addr)) a budget
+//! that is spent by its requests and that refills continuously over a time window. Each
+//! request has a *cost*, so that expensive routes such as searches can consume more of the budget
+//! than cheap reads. Requests that exceed the budget get a `429 Too Many Requests` response with
+//! a `Retry-After` header.
+//!
+//! The clients can be identified by something else than their IP address, such as an API key,
+//! with [`RateLimiter::with_key`](struct.RateLimiter.html#method.with_key). The budgets are kept
+//! in memory by default. Implement [`RateLimitStore`](trait.RateLimitStore.html) in order to
+//! share them between several instances of the server, for example with Redis.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::rate_limit::RateLimiter;
+//!
+//! // Each client can spend 100 per minute.
+//! let limiter = RateLimiter::new(100, Duration::from_secs(60));
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     router!(request,
+//!         (GET) (/items) => {
+//!             limiter.limit(request, 1, || Response::text("list of items"))
+//!         },
+//!         (GET) (/search) => {
+//!             limiter.limit(request, 20, || Response::text("search results"))
+//!         },
+//!         _ => Response::empty_404()
+//!     )
+//! });
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use ban::BanList;
+use middleware::Middleware;
+use Request;
+use Response;
+
+/// The cost of a request, as computed by a `RateLimiter`.
+///
+/// The rate limiter inserts this value in the [extensions](../struct.Request.html#method.extensions)
+/// of each request that it processes, so that a logging or metrics middleware can record it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RequestCost(pub u32);
+
+/// Limits the cost of the requests of each client over a time window.
+///
+/// It can be used either by calling [`limit`](#method.limit) from within a route, or as a
+/// [`Middleware`](../middleware/trait.Middleware.html), in which case the cost of each request is
+/// given by the function passed to [`with_cost`](#method.with_cost).
+pub struct RateLimiter {
+    budget: u32,
+    window: Duration,
+    cost: Option<Box<CostFn>>,
+    key: Option<Box<KeyFn>>,
+    ban: Option<(Arc<BanList>, Duration)>,
+    store: Arc<dyn RateLimitStore>,
+}
+
+type CostFn = dyn Fn(&Request) -> u32 + Send + Sync;
+type KeyFn = dyn Fn(&Request) -> String + Send + Sync;
+
+/// Storage of the budgets of the clients of a `RateLimiter`.
+///
+/// The budget of each client is a token bucket that holds at most `budget` and refills at the
+/// rate of `budget` per `window`.
+pub trait RateLimitStore: Send + Sync {
+    /// Spends `cost` from the bucket of the client identified by `key`, which is full the first
+    /// time the client is seen.
+    ///
+    /// Returns `Ok(None)` if the cost was spent, and `Ok(Some(retry_after))` with the time after
+    /// which the bucket will hold enough if it currently holds less than `cost`. This must be
+    /// atomic, so that concurrent requests of the same client can't spend the same budget.
+    fn acquire(
+        &self,
+        key: &str,
+        cost: u32,
+        budget: u32,
+        window: Duration,
+    ) -> io::Result<Option<Duration>>;
+}
+
+/// A `RateLimitStore` that keeps the budgets in memory. This is the store of the `RateLimiter`s
+/// built with `new`.
+///
+/// The clients whose budget is full are forgotten when the number of clients grows large.
+#[derive(Debug, Default)]
+pub struct MemoryRateLimitStore {
+    clients: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    last_update: Instant,
+}
+
+// Number of tracked clients above which we remove the ones whose budget is full.
+const PRUNE_THRESHOLD: usize = 1024;
+
+impl MemoryRateLimitStore {
+    /// Builds a new empty store.
+    #[inline]
+    pub fn new() -> MemoryRateLimitStore {
+        MemoryRateLimitStore::default()
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn acquire(
+        &self,
+        key: &str,
+        cost: u32,
+        budget: u32,
+        window: Duration,
+    ) -> io::Result<Option<Duration>> {
+        let now = Instant::now();
+        let budget = f64::from(budget);
+        let window = duration_to_secs(window);
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, bucket| {
+                let elapsed = duration_to_secs(now.duration_since(bucket.last_update));
+                bucket.available + elapsed * budget / window < budget
+            });
+        }
+
+        let bucket = clients.entry(key.to_owned()).or_insert(Bucket {
+            available: budget,
+            last_update: now,
+        });
+
+        let elapsed = duration_to_secs(now.duration_since(bucket.last_update));
+        bucket.available = (bucket.available + elapsed * budget / window).min(budget);
+        bucket.last_update = now;
+
+        let cost = f64::from(cost);
+        if bucket.available >= cost {
+            bucket.available -= cost;
+            Ok(None)
+        } else {
+            let missing = cost - bucket.available;
+            Ok(Some(secs_to_duration(missing * window / budget)))
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Builds a new `RateLimiter` that allows each client to spend `budget` over `window`.
+    ///
+    /// The budget refills continuously: a client that spent all of it can spend `budget / 2`
+    /// again after `window / 2`.
+    pub fn new(budget: u32, window: Duration) -> RateLimiter {
+        RateLimiter {
+            budget,
+            window,
+            cost: None,
+            key: None,
+            ban: None,
+            store: Arc::new(MemoryRateLimitStore::new()),
+        }
+    }
+
+    /// Sets the function that identifies the client of a request, for example by its API key.
+    /// If this is not called, the clients are identified by their IP address.
+    ///
+    /// The requests for which the function returns the same key share the same budget.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use rouille::rate_limit::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::new(1000, Duration::from_secs(3600)).with_key(|request| {
+    ///     match request.header("X-Api-Key") {
+    ///         Some(key) => format!("key:{}", key),
+    ///         None => format!("ip:{}", request.remote_addr().ip()),
+    ///     }
+    /// });
+    /// ```
+    pub fn with_key<F>(mut self, key: F) -> RateLimiter
+    where
+        F: Fn(&Request) -> String + Send + Sync + 'static,
+    {
+        self.key = Some(Box::new(key));
+        self
+    }
+
+    /// Keeps the budgets of the clients in `store` instead of in memory.
+    ///
+    /// If the store returns an error, the request is allowed. Rate limiters that share a store
+    /// must identify their clients with different keys.
+    #[inline]
+    pub fn with_store(mut self, store: Arc<dyn RateLimitStore>) -> RateLimiter {
+        self.store = store;
+        self
+    }
+
+    /// Sets the function that determines the cost of a request when the rate limiter is used as
+    /// a middleware. If this is not called, each request costs `1`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use rouille::rate_limit::RateLimiter;
+    ///
+    /// let limiter = RateLimiter::new(100, Duration::from_secs(60))
+    ///     .with_cost(|request| if request.url().starts_with("/search") { 20 } else { 1 });
+    /// ```
+    pub fn with_cost<F>(mut self, cost: F) -> RateLimiter
+    where
+        F: Fn(&Request) -> u32 + Send + Sync + 'static,
+    {
+        self.cost = Some(Box::new(cost));
+        self
+    }
+
+    /// Bans the clients whose requests are rejected for `duration`, by adding them to `bans`.
+    ///
+    /// The rate limiter itself doesn't enforce the bans. Use the `BanList` as a middleware placed
+    /// before the rate limiter for that.
+    pub fn with_ban_list(mut self, bans: Arc<BanList>, duration: Duration) -> RateLimiter {
+        self.ban = Some((bans, duration));
+        self
+    }
+
+    /// Returns the budget of each client.
+    #[inline]
+    pub fn budget(&self) -> u32 {
+        self.budget
+    }
+
+    /// Returns the time window over which the budget refills.
+    #[inline]
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Spends `cost` from the budget of `client`.
+    ///
+    /// Returns an error containing the time after which the client will have enough budget if
+    /// it is currently too low. A cost greater than the total budget is always rejected.
+    pub fn try_acquire(&self, client: IpAddr, cost: u32) -> Result<(), Duration> {
+        self.try_acquire_key(&client.to_string(), cost)
+    }
+
+    /// Same as `try_acquire`, but for the client identified by `key`, as returned by the
+    /// function passed to `with_key`.
+    pub fn try_acquire_key(&self, key: &str, cost: u32) -> Result<(), Duration> {
+        if cost > self.budget {
+            return Err(self.window);
+        }
+
+        match self.store.acquire(key, cost, self.budget, self.window) {
+            Ok(Some(retry_after)) => Err(retry_after),
+            Ok(None) | Err(_) => Ok(()),
+        }
+    }
+
+    /// Spends `cost` from the budget of the client that sent `request`, then calls `handler` if
+    /// the budget was sufficient. Otherwise returns a `429 Too Many Requests` response.
+    pub fn limit<F>(&self, request: &Request, cost: u32, handler: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        request.extensions().insert(RequestCost(cost));
+
+        match self.acquire_for(request, cost) {
+            Ok(()) => handler(),
+            Err(retry_after) => Response::too_many_requests(retry_after),
+        }
+    }
+
+    // Same as `try_acquire`, but for the client of a request, and bans it if necessary.
+    pub(crate) fn acquire_for(&self, request: &Request, cost: u32) -> Result<(), Duration> {
+        let client = request.client_addr();
+        let result = match self.key {
+            Some(ref key) => self.try_acquire_key(&key(request), cost),
+            None => self.try_acquire(client, cost),
+        };
+
+        if result.is_err() {
+            if let Some((ref bans, duration)) = self.ban {
+                // Failing to store the ban shouldn't prevent answering the request.
+                let _ = bans.ban(client, duration, "rate-limit");
+            }
+        }
+
+        result
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn before(&self, request: &Request) -> Option<Response> {
+        let cost = match self.cost {
+            Some(ref cost) => cost(request),
+            None => 1,
+        };
+
+        request.extensions().insert(RequestCost(cost));
+
+        match self.acquire_for(request, cost) {
+            Ok(()) => None,
+            Err(retry_after) => Some(Response::too_many_requests(retry_after)),
+        }
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("budget", &self.budget)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+fn secs_to_duration(secs: f64) -> Duration {
+    let whole = secs.trunc();
+    Duration::new(whole as u64, ((secs - whole) * 1_000_000_000.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryRateLimitStore;
+    use super::RateLimitStore;
+    use super::RateLimiter;
+    use super::RequestCost;
+    use ban::BanList;
+    use middleware::Pipeline;
+    use std::io;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use Request;
+    use Response;
+
+    #[test]
+    fn budget_spent_by_cost() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60));
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.try_acquire(client, 4).is_ok());
+        assert!(limiter.try_acquire(client, 4).is_ok());
+        let retry_after = limiter.try_acquire(client, 4).unwrap_err();
+        assert!(retry_after > Duration::from_secs(11));
+        assert!(retry_after <= Duration::from_secs(12));
+
+        assert!(limiter.try_acquire(client, 2).is_ok());
+        assert!(limiter.try_acquire(other, 10).is_ok());
+        assert!(limiter.try_acquire(other, 11).is_err());
+    }
+
+    #[test]
+    fn limit() {
+        let limiter = RateLimiter::new(5, Duration::from_secs(60));
+        let request = Request::fake_http("GET", "/search", vec![], vec![]);
+
+        let response = limiter.limit(&request, 5, || Response::text("ok"));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            request.extensions().get::<RequestCost>(),
+            Some(&RequestCost(5))
+        );
+
+        let response = limiter.limit(&request, 1, || Response::text("ok"));
+        assert_eq!(response.status_code, 429);
+        assert!(response.headers.iter().any(|h| h.0 == "Retry-After"));
+    }
+
+    #[test]
+    fn middleware() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60)).with_cost(|request| {
+            if request.url() == "/search" {
+                8
+            } else {
+                1
+            }
+        });
+        let pipeline = Pipeline::new(|_: &Request| Response::text("ok")).with(limiter);
+
+        let search = Request::fake_http("GET", "/search", vec![], vec![]);
+        let read = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(pipeline.handle(&search).status_code, 200);
+        assert_eq!(pipeline.handle(&read).status_code, 200);
+        assert_eq!(pipeline.handle(&read).status_code, 200);
+        assert_eq!(pipeline.handle(&read).status_code, 429);
+    }
+
+    #[test]
+    fn ban_on_reject() {
+        let bans = Arc::new(BanList::new());
+        let limiter = RateLimiter::new(1, Duration::from_secs(60))
+            .with_ban_list(bans.clone(), Duration::from_secs(60));
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+
+        assert_eq!(
+            limiter
+                .limit(&request, 1, || Response::text("ok"))
+                .status_code,
+            200
+        );
+        assert!(!bans.is_banned(request.remote_addr().ip()));
+        assert_eq!(
+            limiter
+                .limit(&request, 1, || Response::text("ok"))
+                .status_code,
+            429
+        );
+        assert!(bans.is_banned(request.remote_addr().ip()));
+    }
+
+    #[test]
+    fn custom_key() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60))
+            .with_key(|request| request.header("X-Api-Key").unwrap_or("").to_owned());
+        let request = |key: &str| {
+            let headers = vec![("X-Api-Key".to_owned(), key.to_owned())];
+            Request::fake_http("GET", "/", headers, vec![])
+        };
+        let status = |request: &Request| {
+            limiter
+                .limit(request, 1, || Response::text("ok"))
+                .status_code
+        };
+
+        assert_eq!(status(&request("a")), 200);
+        assert_eq!(status(&request("a")), 429);
+        assert_eq!(status(&request("b")), 200);
+    }
+
+    #[test]
+    fn custom_store() {
+        struct Failing;
+        impl RateLimitStore for Failing {
+            fn acquire(
+                &self,
+                _: &str,
+                _: u32,
+                _: u32,
+                _: Duration,
+            ) -> io::Result<Option<Duration>> {
+                Err(io::Error::new(io::ErrorKind::Other, "unavailable"))
+            }
+        }
+
+        let limiter = RateLimiter::new(1, Duration::from_secs(60)).with_store(Arc::new(Failing));
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        assert!(limiter.try_acquire(client, 1).is_ok());
+        assert!(limiter.try_acquire(client, 1).is_ok());
+        assert!(limiter.try_acquire(client, 2).is_err());
+
+        let store = Arc::new(MemoryRateLimitStore::new());
+        let first = RateLimiter::new(2, Duration::from_secs(60)).with_store(store.clone());
+        let second = RateLimiter::new(2, Duration::from_secs(60)).with_store(store);
+        assert!(first.try_acquire(client, 2).is_ok());
+        assert!(second.try_acquire(client, 1).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Finding the address of the client behind reverse proxies and load balancers.
+//!
+//! When the server is behind a proxy, the address of the connection is the one of the proxy, and
+//! the address of the client is in the `Forwarded`, `X-Forwarded-For` or `X-Real-Ip` headers
+//! that the proxy adds. These headers can be sent by anyone, so they are only read when the connection comes
+//! from a trusted proxy.
+//!
+//! A [`RealIp`](struct.RealIp.html) lists the trusted proxies. Once it is passed to
+//! [`Server::real_ip`](../struct.Server.html#method.real_ip),
+//! [`Request::client_addr`](../struct.Request.html#method.client_addr) returns the address of
+//! the client, which the logs, the rate limiters and the ban lists use instead of the address of
+//! the connection. With [`RealIp::trust_proto`](struct.RealIp.html#method.trust_proto),
+//! [`Request::is_secure`](../struct.Request.html#method.is_secure) also tells whether the client
+//! used HTTPS to reach the proxy.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rouille::Response;
+//! use rouille::Server;
+//! use rouille::real_ip::RealIp;
+//!
+//! let real_ip = RealIp::new()
+//!     .trust("10.0.0.0/8".parse().unwrap())
+//!     .trust("::1".parse().unwrap())
+//!     .trust_proto();
+//!
+//! let server = Server::new("0.0.0.0:80", |request| {
+//!     Response::text(format!("Your IP is {}", request.client_addr()))
+//! })
+//! .unwrap()
+//! .real_ip(real_ip);
+//! server.run();
+//! ```
+
+use std::error;
+use std::fmt;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use input;
+use input::ForwardedName;
+use Request;
+
+/// A range of IP addresses, such as `10.0.0.0/8` or `fd00::/8`.
+///
+/// A single address, without a prefix length, is also a range that only contains it.
+///
+/// # Example
+///
+/// ```
+/// use rouille::real_ip::IpRange;
+///
+/// let range: IpRange = "192.168.0.0/16".parse().unwrap();
+/// assert!(range.contains("192.168.1.20".parse().unwrap()));
+/// assert!(!range.contains("10.0.0.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// Returns true if `ip` is in the range. IPv4 addresses mapped to IPv6, such as
+    /// `::ffff:10.0.0.1`, are compared as IPv4 addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, unmap(ip)) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                mask_v4(u32::from(ip), self.prefix) == u32::from(range)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                mask_v6(u128::from(ip), self.prefix) == u128::from(range)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = IpRangeError;
+
+    fn from_str(s: &str) -> Result<IpRange, IpRangeError> {
+        let mut split = s.trim().splitn(2, '/');
+        let addr: IpAddr = split
+            .next()
+            .unwrap_or("")
+            .parse()
+            .map_err(|_| IpRangeError)?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match split.next() {
+            Some(prefix) => prefix.parse().map_err(|_| IpRangeError)?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(IpRangeError);
+        }
+
+        // The bits after the prefix are ignored.
+        let addr = match addr {
+            IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(mask_v4(u32::from(addr), prefix))),
+            IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(mask_v6(u128::from(addr), prefix))),
+        };
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+fn mask_v4(addr: u32, prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        addr & (!0 << (32 - u32::from(prefix)))
+    }
+}
+
+fn mask_v6(addr: u128, prefix: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        addr & (!0 << (128 - u32::from(prefix)))
+    }
+}
+
+// Turns `::ffff:a.b.c.d` into `a.b.c.d`.
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, high, low] => {
+                IpAddr::V4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+            }
+            _ => ip,
+        },
+        ip => ip,
+    }
+}
+
+/// Error returned when parsing an invalid `IpRange`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IpRangeError;
+
+impl error::Error for IpRangeError {}
+
+impl fmt::Display for IpRangeError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "invalid IP address range")
+    }
+}
+
+/// The proxies whose `Forwarded`, `X-Forwarded-For` and `X-Real-Ip` headers are trusted.
+///
+/// Each proxy appends the address that it received the request from to `X-Forwarded-For`, so the
+/// header is read from right to left, skipping the addresses of the trusted proxies. The first
+/// address that isn't trusted is the one of the client, since anything before it may have been
+/// made up by the client.
+///
+/// The standard `Forwarded` header is read the same way, from its `for` parameters, and is
+/// preferred over the other ones. `X-Real-Ip` is only read if there is no `X-Forwarded-For`
+/// header.
+///
+/// If your proxies don't set the `Forwarded` header, make sure that they remove it from the
+/// requests of the clients, or use `without_forwarded`, since it would otherwise be passed
+/// through and preferred.
+///
+/// See [the module documentation](index.html).
+#[derive(Debug, Clone, Default)]
+pub struct RealIp {
+    trusted: Vec<IpRange>,
+    without_forwarded: bool,
+    trust_proto: bool,
+}
+
+impl RealIp {
+    /// Builds a `RealIp` that trusts no proxy.
+    #[inline]
+    pub fn new() -> RealIp {
+        RealIp::default()
+    }
+
+    /// Trusts the proxies whose address is in `range`.
+    #[inline]
+    pub fn trust(mut self, range: IpRange) -> RealIp {
+        self.trusted.push(range);
+        self
+    }
+
+    /// Ignores the `Forwarded` header, and only reads `X-Forwarded-For` and `X-Real-Ip`.
+    #[inline]
+    pub fn without_forwarded(mut self) -> RealIp {
+        self.without_forwarded = true;
+        self
+    }
+
+    /// Also trusts the scheme of the requests of the clients, from the `proto` parameter of the
+    /// `Forwarded` header or from `X-Forwarded-Proto`. This is needed when a proxy accepts HTTPS
+    /// connections and sends the requests to the server with plain HTTP, so that
+    /// [`Request::is_secure`](../struct.Request.html#method.is_secure) and
+    /// [`Request::scheme`](../struct.Request.html#method.scheme) are correct.
+    #[inline]
+    pub fn trust_proto(mut self) -> RealIp {
+        self.trust_proto = true;
+        self
+    }
+
+    /// Returns true if `ip` is the address of a trusted proxy.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|range| range.contains(ip))
+    }
+
+    /// Returns the address of the client that sent `request`.
+    ///
+    /// This is the address of the connection if it doesn't come from a trusted proxy, or if the
+    /// proxy didn't add any header.
+    #[inline]
+    pub fn resolve(&self, request: &Request) -> IpAddr {
+        self.client(request).addr
+    }
+
+    /// Returns true if the client sent `request` with HTTPS.
+    ///
+    /// If `trust_proto` is used and the connection comes from a trusted proxy, this is the scheme
+    /// of the request that the proxy received from the client. Otherwise this is whether the
+    /// connection uses HTTPS.
+    #[inline]
+    pub fn is_secure(&self, request: &Request) -> bool {
+        self.client(request).secure.unwrap_or(request.https)
+    }
+
+    pub(crate) fn client(&self, request: &Request) -> Client {
+        let peer = request.remote_addr().ip();
+        if !self.is_trusted(peer) {
+            return Client {
+                addr: peer,
+                secure: None,
+            };
+        }
+
+        if !self.without_forwarded && request.header("Forwarded").is_some() {
+            // A malformed header is as garbled as an address that can't be parsed.
+            let elements = match input::forwarded(request) {
+                Some(elements) => elements,
+                None => {
+                    return Client {
+                        addr: peer,
+                        secure: None,
+                    }
+                }
+            };
+            let mut client = peer;
+            let mut proto = None;
+            for element in elements.iter().rev() {
+                if element.proto.is_some() {
+                    proto = element.proto.as_ref();
+                }
+                client = match element.forwarded_for.as_ref().map(|node| &node.name) {
+                    Some(&ForwardedName::Ip(ip)) => unmap(ip),
+                    // The client hid its address.
+                    _ => break,
+                };
+                if !self.is_trusted(client) {
+                    break;
+                }
+            }
+            return Client {
+                addr: client,
+                secure: self.secure(proto.map(|proto| &proto[..])),
+            };
+        }
+
+        let forwarded: Vec<&str> = request
+            .headers()
+            .filter(|&(name, _)| name.eq_ignore_ascii_case("X-Forwarded-For"))
+            .flat_map(|(_, value)| value.split(','))
+            .collect();
+        let mut client = if forwarded.is_empty() {
+            request
+                .header("X-Real-Ip")
+                .and_then(parse_addr)
+                .unwrap_or(peer)
+        } else {
+            peer
+        };
+        let mut hops = 0;
+        for addr in forwarded.iter().rev() {
+            // A garbled address can't be trusted, and neither can the ones before it.
+            client = match parse_addr(addr) {
+                Some(addr) => addr,
+                None => break,
+            };
+            hops += 1;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+
+        // Each proxy appends to both headers, so the scheme used by the client is at the same
+        // position as its address. A single value is the one of the first proxy.
+        let protos: Vec<&str> = request
+            .headers()
+            .filter(|&(name, _)| name.eq_ignore_ascii_case("X-Forwarded-Proto"))
+            .flat_map(|(_, value)| value.split(','))
+            .collect();
+        let proto = if protos.is_empty() {
+            None
+        } else {
+            Some(protos[protos.len().saturating_sub(hops.max(1))].trim())
+        };
+        Client {
+            addr: client,
+            secure: self.secure(proto),
+        }
+    }
+
+    // Interprets the scheme sent by a proxy.
+    fn secure(&self, proto: Option<&str>) -> Option<bool> {
+        if !self.trust_proto {
+            return None;
+        }
+        match proto {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => Some(true),
+            Some(proto) if proto.eq_ignore_ascii_case("http") => Some(false),
+            _ => None,
+        }
+    }
+}
+
+// Parses an address of `X-Forwarded-For`, which may have a port.
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim();
+    addr.parse()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .map(unmap)
+}
+
+// Extension of the requests whose client was resolved by `Server::real_ip`.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Client {
+    pub(crate) addr: IpAddr,
+    // `None` if the scheme of the connection is the one of the client.
+    pub(crate) secure: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IpRange;
+    use super::RealIp;
+    use std::net::IpAddr;
+    use Request;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges() {
+        let range: IpRange = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(range.to_string(), "10.0.0.0/8");
+        assert!(range.contains(ip("10.255.0.1")));
+        assert!(range.contains(ip("::ffff:10.0.0.1")));
+        assert!(!range.contains(ip("11.0.0.1")));
+
+        let range: IpRange = "fd00::/8".parse().unwrap();
+        assert!(range.contains(ip("fd12::1")));
+        assert!(!range.contains(ip("fe80::1")));
+
+        let any: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("1.2.3.4")));
+        let single: IpRange = "::1".parse().unwrap();
+        assert!(single.contains(ip("::1")));
+        assert!(!single.contains(ip("::2")));
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+        assert!("10.0.0.0/a".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn resolve() {
+        let real_ip = RealIp::new().trust("10.0.0.0/8".parse().unwrap());
+        let request = |from: &str, headers: &[(&str, &str)]| {
+            let headers = headers
+                .iter()
+                .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                .collect();
+            Request::fake_http_from(from.parse().unwrap(), "GET", "/", headers, vec![])
+        };
+
+        // Not from a trusted proxy.
+        let rq = request("1.2.3.4:80", &[("X-Forwarded-For", "5.6.7.8")]);
+        assert_eq!(real_ip.resolve(&rq), ip("1.2.3.4"));
+
+        let rq = request(
+            "10.0.0.1:80",
+            &[
+                ("X-Forwarded-For", "9.9.9.9, 5.6.7.8"),
+                ("X-Forwarded-For", "10.0.0.2"),
+            ],
+        );
+        assert_eq!(real_ip.resolve(&rq), ip("5.6.7.8"));
+
+        let rq = request("10.0.0.1:80", &[("X-Forwarded-For", "[2001:db8::1]:443")]);
+        assert_eq!(real_ip.resolve(&rq), ip("2001:db8::1"));
+
+        let rq = request("10.0.0.1:80", &[("X-Forwarded-For", "5.6.7.8, garbage")]);
+        assert_eq!(real_ip.resolve(&rq), ip("10.0.0.1"));
+
+        let rq = request("10.0.0.1:80", &[("X-Real-Ip", "5.6.7.8")]);
+        assert_eq!(real_ip.resolve(&rq), ip("5.6.7.8"));
+
+        let rq = request("10.0.0.1:80", &[]);
+        assert_eq!(real_ip.resolve(&rq), ip("10.0.0.1"));
+
+        // `Forwarded` is preferred.
+        let headers = [
+            ("X-Forwarded-For", "5.6.7.8"),
+            (
+                "Forwarded",
+                r#"for=9.9.9.9, for="[2001:db8::1]:80";proto=https"#,
+            ),
+            ("Forwarded", "for=10.0.0.2"),
+        ];
+        let rq = request("10.0.0.1:80", &headers);
+        assert_eq!(real_ip.resolve(&rq), ip("2001:db8::1"));
+        let legacy = real_ip.clone().without_forwarded();
+        assert_eq!(legacy.resolve(&rq), ip("5.6.7.8"));
+
+        let rq = request("10.0.0.1:80", &[("Forwarded", "for=_hidden, for=10.0.0.2")]);
+        assert_eq!(real_ip.resolve(&rq), ip("10.0.0.2"));
+        let rq = request("10.0.0.1:80", &[("Forwarded", "for")]);
+        assert_eq!(real_ip.resolve(&rq), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn scheme() {
+        let real_ip = RealIp::new().trust("10.0.0.0/8".parse().unwrap());
+        let trusting = real_ip.clone().trust_proto();
+        let request = |from: &str, headers: &[(&str, &str)]| {
+            let headers = headers
+                .iter()
+                .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                .collect();
+            Request::fake_http_from(from.parse().unwrap(), "GET", "/", headers, vec![])
+        };
+
+        let rq = request("10.0.0.1:80", &[("X-Forwarded-Proto", "https")]);
+        assert!(trusting.is_secure(&rq));
+        assert!(!real_ip.is_secure(&rq));
+        let rq = request("1.2.3.4:80", &[("X-Forwarded-Proto", "https")]);
+        assert!(!trusting.is_secure(&rq));
+
+        let headers = [
+            ("X-Forwarded-For", "5.6.7.8, 10.0.0.2"),
+            ("X-Forwarded-Proto", "https, http"),
+        ];
+        assert!(trusting.is_secure(&request("10.0.0.1:80", &headers)));
+        let headers = [
+            ("X-Forwarded-For", "5.6.7.8, 10.0.0.2"),
+            ("X-Forwarded-Proto", "https"),
+        ];
+        assert!(trusting.is_secure(&request("10.0.0.1:80", &headers)));
+
+        let headers = [(
+            "Forwarded",
+            "for=5.6.7.8;proto=https, for=10.0.0.2;proto=http",
+        )];
+        assert!(trusting.is_secure(&request("10.0.0.1:80", &headers)));
+        let headers = [("Forwarded", "for=5.6.7.8, for=10.0.0.2;proto=https")];
+        assert!(trusting.is_secure(&request("10.0.0.1:80", &headers)));
+    }
+}
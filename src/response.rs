@@ -7,16 +7,43 @@
 // notice may not be copied, modified, or distributed except
 // according to those terms.
 
+use cache_control::CacheControl;
+use http_date::format_http_date;
+use http_date::HttpDate;
+use http_date::RetryAfter;
+use link::Link;
+use negotiate::Encoders;
 use percent_encoding;
+use security_headers::SecurityHeaders;
 use serde;
 use serde_json;
+use sha1::Sha1;
+use sse;
+use sse::EventSender;
 use std::borrow::Cow;
+use std::cmp;
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::Cursor;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+use tiny_http;
+use tiny_http_response;
+use AllowedMethods;
+use CustomMethods;
+use JsonOptions;
+use JsonStreamFormat;
 use Request;
+use ResponseBuilder;
+use SetCookie;
 use Upgrade;
 
 /// Contains a prototype of a response.
@@ -32,7 +59,7 @@ pub struct Response {
     /// The value of the following headers will be ignored from this list, even if present:
     ///
     /// - Accept-Ranges
-    /// - Connection
+    /// - Connection, unless it is `Connection: close`
     /// - Content-Length
     /// - Content-Range
     /// - Trailer
@@ -56,9 +83,9 @@ pub struct Response {
 
     /// If set, rouille will give ownership of the client socket to the `Upgrade` object.
     ///
-    /// In all circumstances, the value of the `Connection` header is managed by the framework and
-    /// cannot be customized. If this value is set, the response will automatically contain
-    /// `Connection: Upgrade`.
+    /// Apart from `with_connection_close`, the value of the `Connection` header is managed by the
+    /// framework and cannot be customized. If this value is set, the response will automatically
+    /// contain `Connection: Upgrade`.
     pub upgrade: Option<Box<dyn Upgrade + Send>>,
 }
 
@@ -72,6 +99,20 @@ impl fmt::Debug for Response {
 }
 
 impl Response {
+    /// Starts building a `Response` with a [`ResponseBuilder`](struct.ResponseBuilder.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::builder().status(404).text("not found").build().unwrap();
+    /// assert_eq!(response.status_code, 404);
+    /// ```
+    #[inline]
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder::new()
+    }
+
     /// Returns true if the status code of this `Response` indicates success.
     ///
     /// This is the range [200-399].
@@ -102,6 +143,17 @@ impl Response {
         !self.is_success()
     }
 
+    /// Turns the response into a response of tiny-http, to send it with a tiny-http server that
+    /// isn't managed by rouille.
+    ///
+    /// The `Upgrade` header and the `upgrade` field of the response are dropped, since tiny-http
+    /// upgrades connections with `Request::upgrade`, and streamed bodies are buffered by
+    /// tiny-http. Prefer [`handle_tiny_http`](fn.handle_tiny_http.html), which handles both.
+    pub fn into_tiny_http(self) -> tiny_http::ResponseBox {
+        let (data, data_length) = self.data.into_reader_and_size();
+        tiny_http_response(self.status_code, self.headers, data, data_length)
+    }
+
     /// Builds a `Response` that redirects the user to another URL with a 301 status code. This
     /// semantically means a permanent redirect.
     ///
@@ -289,6 +341,152 @@ impl Response {
         }
     }
 
+    /// Builds a `Response` with the parts of a body requested by the `Range` header of the
+    /// request.
+    ///
+    /// `length` is the total length of `data`. If the request is a `GET` with a single satisfiable
+    /// byte range, the response is a `206 Partial Content` with the requested bytes and a
+    /// `Content-Range` header. With multiple ranges, the response is a `206` whose body is a
+    /// `multipart/byteranges` document with one part per range. Overlapping and adjacent ranges
+    /// are merged. If none of the ranges can be satisfied, the response is a
+    /// `416 Range Not Satisfiable`. Otherwise, the response is a `200` with the whole body. All the
+    /// responses have an `Accept-Ranges: bytes` header.
+    ///
+    /// Requests with an `If-Range` header get the whole body, since the validator can't be
+    /// checked here, and so do requests with more than 32 ranges. See `from_range_with_validators`
+    /// to support `If-Range`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let file = File::open("video.mp4").unwrap();
+    ///     let length = file.metadata().unwrap().len();
+    ///     Response::from_range(request, "video/mp4", file, length)
+    /// }
+    /// ```
+    #[inline]
+    pub fn from_range<C, R>(request: &Request, content_type: C, data: R, length: u64) -> Response
+    where
+        C: Into<Cow<'static, str>>,
+        R: Read + Seek + Send + 'static,
+    {
+        Response::from_range_with_validators(request, content_type, data, length, None, None)
+    }
+
+    /// Same as `from_range`, but also honors the `If-Range` header of the request by comparing it
+    /// with the `ETag` and the modification date of the body.
+    ///
+    /// The ranges are only served if `If-Range` contains the same strong `ETag` as `etag`, or
+    /// exactly the same date as `last_modified`. Otherwise, the whole body is served, since it has
+    /// changed since the client received the parts that it has.
+    ///
+    /// This function doesn't add the `ETag` and `Last-Modified` headers to the response. Use
+    /// `with_etag` and `with_last_modified` for that.
+    pub fn from_range_with_validators<C, R>(
+        request: &Request,
+        content_type: C,
+        mut data: R,
+        length: u64,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+    ) -> Response
+    where
+        C: Into<Cow<'static, str>>,
+        R: Read + Seek + Send + 'static,
+    {
+        let if_range = match request.header("If-Range") {
+            None => true,
+            Some(validator) => if_range(validator, etag, last_modified),
+        };
+        let ranges = match request.header("Range") {
+            Some(range) if request.method() == "GET" && if_range => parse_range(range, length),
+            _ => None,
+        };
+
+        let mut response = match ranges {
+            None => Response {
+                status_code: 200,
+                headers: vec![("Content-Type".into(), content_type.into())],
+                data: ResponseBody::from_reader_and_size(data, length as usize),
+                upgrade: None,
+            },
+            Some(ref ranges) if ranges.is_empty() => Response {
+                status_code: 416,
+                headers: vec![("Content-Range".into(), format!("bytes */{}", length).into())],
+                data: ResponseBody::empty(),
+                upgrade: None,
+            },
+            Some(ref ranges) if ranges.len() == 1 => {
+                let (first, last) = ranges[0];
+                if data.seek(SeekFrom::Start(first)).is_err() {
+                    return Response::text("Internal Server Error").with_status_code(500);
+                }
+                let size = last - first + 1;
+                let mut body = ResponseBody::from_reader_and_size(data.take(size), size as usize);
+                // Encoding the body would make the range meaningless.
+                body.no_compression = true;
+                Response {
+                    status_code: 206,
+                    headers: vec![
+                        ("Content-Type".into(), content_type.into()),
+                        (
+                            "Content-Range".into(),
+                            format!("bytes {}-{}/{}", first, last, length).into(),
+                        ),
+                    ],
+                    data: body,
+                    upgrade: None,
+                }
+            }
+            Some(ranges) => {
+                let boundary = request.random().alphanumeric(32);
+                let content_type = content_type.into();
+                let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+                let mut size = 0;
+                for (first, last) in ranges {
+                    let header = format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, content_type, first, last, length
+                    );
+                    size += header.len() as u64 + last - first + 1;
+                    parts.push(Part::Data(Cursor::new(header.into_bytes())));
+                    parts.push(Part::Range(first, last - first + 1));
+                }
+                let end = format!("\r\n--{}--\r\n", boundary);
+                size += end.len() as u64;
+                parts.push(Part::Data(Cursor::new(end.into_bytes())));
+                parts.reverse();
+
+                let reader = ByteRangesReader {
+                    data,
+                    parts,
+                    remaining: 0,
+                };
+                let mut body = ResponseBody::from_reader_and_size(reader, size as usize);
+                body.no_compression = true;
+                Response {
+                    status_code: 206,
+                    headers: vec![(
+                        "Content-Type".into(),
+                        format!("multipart/byteranges; boundary={}", boundary).into(),
+                    )],
+                    data: body,
+                    upgrade: None,
+                }
+            }
+        };
+
+        response
+            .headers
+            .push(("Accept-Ranges".into(), "bytes".into()));
+        response
+    }
+
     /// Builds a `Response` that outputs HTML.
     ///
     /// # Example
@@ -331,6 +529,53 @@ impl Response {
         }
     }
 
+    /// Builds a `Response` that outputs HTML rendered from a markdown document.
+    ///
+    /// Raw HTML inside the markdown is escaped. See [the `markdown` module](markdown/index.html)
+    /// for more details.
+    ///
+    /// This function is only available if you enable the `markdown` feature of rouille.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::markdown("# Hello\n\nThis is *markdown*.");
+    /// ```
+    #[cfg(feature = "markdown")]
+    #[inline]
+    pub fn markdown<D>(content: D) -> Response
+    where
+        D: AsRef<str>,
+    {
+        Response::markdown_with_options(content, &Default::default())
+    }
+
+    /// Same as `markdown`, but allows you to pass a template and a highlighter for code blocks.
+    ///
+    /// This function is only available if you enable the `markdown` feature of rouille.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// use rouille::markdown::MarkdownOptions;
+    ///
+    /// let options = MarkdownOptions {
+    ///     template: Some("<html><body>{{content}}</body></html>".into()),
+    ///     .. MarkdownOptions::default()
+    /// };
+    ///
+    /// let response = Response::markdown_with_options("# Hello", &options);
+    /// ```
+    #[cfg(feature = "markdown")]
+    pub fn markdown_with_options<D>(content: D, options: &::markdown::MarkdownOptions) -> Response
+    where
+        D: AsRef<str>,
+    {
+        Response::html(::markdown::render(content.as_ref(), options))
+    }
+
     /// Builds a `Response` that outputs plain text.
     ///
     /// # Example
@@ -373,12 +618,166 @@ impl Response {
     /// // The Response will contain something like `{ field1: "hello", field2: 5 }`
     /// # }
     /// ```
+    ///
+    /// # Panic
+    ///
+    /// Panics if the value can't be serialized, for example if it is a map whose keys aren't
+    /// strings. Use [`try_json`](#method.try_json) to handle this case.
     #[inline]
     pub fn json<T>(content: &T) -> Response
     where
         T: serde::Serialize,
     {
-        let data = serde_json::to_string(content).unwrap();
+        Response::try_json(content).unwrap()
+    }
+
+    /// Builds a `Response` that outputs JSON, or returns an error if the value can't be
+    /// serialized.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use rouille::Response;
+    ///
+    /// let mut map = HashMap::new();
+    /// map.insert(vec![1], "keys must be strings");
+    /// let response = Response::try_json(&map)
+    ///     .unwrap_or_else(|_| Response::text("serialization failed").with_status_code(500));
+    /// assert_eq!(response.status_code, 500);
+    /// ```
+    #[inline]
+    pub fn try_json<T>(content: &T) -> Result<Response, serde_json::Error>
+    where
+        T: serde::Serialize,
+    {
+        Response::try_json_with_content_type("application/json; charset=utf-8", content)
+    }
+
+    /// Builds a `Response` that outputs indented JSON, which is easier to read for a human.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::json_pretty(&vec!["a", "b"]);
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Panics if the value can't be serialized.
+    #[inline]
+    pub fn json_pretty<T>(content: &T) -> Response
+    where
+        T: serde::Serialize,
+    {
+        Response::try_json_with_options(content, &JsonOptions::new().pretty(true)).unwrap()
+    }
+
+    /// Same as `try_json`, but serializes the value according to the options.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{JsonOptions, Response};
+    ///
+    /// let options = JsonOptions::new().indent("\t").ascii_only(true);
+    /// let response = Response::try_json_with_options(&vec!["café"], &options).unwrap();
+    /// ```
+    pub fn try_json_with_options<T>(
+        content: &T,
+        options: &JsonOptions,
+    ) -> Result<Response, serde_json::Error>
+    where
+        T: serde::Serialize,
+    {
+        let data = options.to_vec(content)?;
+        Ok(Response::from_data("application/json; charset=utf-8", data))
+    }
+
+    /// Same as `try_json`, but with another `Content-Type`, such as `application/problem+json`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate serde_json;
+    /// # extern crate rouille;
+    /// use rouille::Response;
+    ///
+    /// # fn main() {
+    /// let problem = json!({ "title": "Out of stock", "status": 409 });
+    /// let response = Response::try_json_with_content_type("application/problem+json", &problem)
+    ///     .unwrap()
+    ///     .with_status_code(409);
+    /// # }
+    /// ```
+    pub fn try_json_with_content_type<C, T>(
+        content_type: C,
+        content: &T,
+    ) -> Result<Response, serde_json::Error>
+    where
+        C: Into<Cow<'static, str>>,
+        T: serde::Serialize,
+    {
+        let data = serde_json::to_vec(content)?;
+        Ok(Response::from_data(content_type, data))
+    }
+
+    /// Builds a `Response` that serializes the value in the format preferred by the client,
+    /// according to the `Accept` header of the request.
+    ///
+    /// The value is serialized as JSON, or as CBOR and MessagePack if the `cbor` and `msgpack`
+    /// features are enabled. If the request has no `Accept` header, JSON is used. If it accepts
+    /// none of the formats, returns an empty 406 response. See [the `negotiate`
+    /// module](negotiate/index.html) in order to support other formats.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     Response::negotiate(request, &vec![1, 2, 3])
+    /// }
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Panics if the value can't be serialized. Use
+    /// [`Encoders::negotiate`](negotiate/struct.Encoders.html#method.negotiate) to handle this
+    /// case.
+    #[inline]
+    pub fn negotiate<T>(request: &Request, content: &T) -> Response
+    where
+        T: serde::Serialize,
+    {
+        Encoders::new().negotiate(request, content).unwrap()
+    }
+
+    /// Builds a `Response` that outputs JSON, without serializing it in memory first.
+    ///
+    /// Contrary to [`json`](#method.json), the value is serialized while the body is sent to
+    /// the client, with [`ResponseBody::from_writer`](struct.ResponseBody.html#method.from_writer).
+    /// This keeps the memory usage low for large documents, but the value must be owned. If the
+    /// serialization fails, the response is interrupted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let rows: Vec<(u32, String)> = (0..100000).map(|n| (n, n.to_string())).collect();
+    /// let response = Response::json_stream(rows);
+    /// ```
+    pub fn json_stream<T>(content: T) -> Response
+    where
+        T: serde::Serialize + Send + 'static,
+    {
+        let data = ResponseBody::from_writer(move |writer| {
+            serde_json::to_writer(writer, &content).map_err(io::Error::from)
+        });
 
         Response {
             status_code: 200,
@@ -386,11 +785,86 @@ impl Response {
                 "Content-Type".into(),
                 "application/json; charset=utf-8".into(),
             )],
-            data: ResponseBody::from_data(data),
+            data,
+            upgrade: None,
+        }
+    }
+
+    /// Builds a `Response` that serializes the items of an iterator while the body is sent to the
+    /// client, either as a JSON array or as newline-delimited JSON.
+    ///
+    /// The iterator runs on another thread, and only a few kilobytes ahead of what the client
+    /// has received. This makes it possible to export a large number of rows without holding
+    /// them in memory. If the serialization of an item fails, the response is interrupted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{JsonStreamFormat, Response};
+    ///
+    /// let rows = (0..1000000).map(|n| (n, n * n));
+    /// let response = Response::json_stream_iter(rows, JsonStreamFormat::Lines);
+    /// ```
+    pub fn json_stream_iter<I>(items: I, format: JsonStreamFormat) -> Response
+    where
+        I: IntoIterator + Send + 'static,
+        I::IntoIter: Send,
+        I::Item: serde::Serialize,
+    {
+        let data = ResponseBody::from_writer(move |writer| {
+            let items = items.into_iter();
+            match format {
+                JsonStreamFormat::Array => {
+                    writer.write_all(b"[")?;
+                    for (index, item) in items.enumerate() {
+                        if index != 0 {
+                            writer.write_all(b",")?;
+                        }
+                        serde_json::to_writer(&mut *writer, &item)?;
+                    }
+                    writer.write_all(b"]")
+                }
+                JsonStreamFormat::Lines => {
+                    for item in items {
+                        serde_json::to_writer(&mut *writer, &item)?;
+                        writer.write_all(b"\n")?;
+                    }
+                    Ok(())
+                }
+            }
+        });
+
+        let content_type = match format {
+            JsonStreamFormat::Array => "application/json; charset=utf-8",
+            JsonStreamFormat::Lines => "application/x-ndjson",
+        };
+
+        Response {
+            status_code: 200,
+            headers: vec![("Content-Type".into(), content_type.into())],
+            data,
             upgrade: None,
         }
     }
 
+    /// Builds a `Response` that streams server-sent events, and the `EventSender` that pushes
+    /// the events. See [the `sse` module](sse/index.html).
+    ///
+    /// A keep-alive comment is sent every 15 seconds when no event is sent. Use
+    /// [`sse::event_stream`](sse/fn.event_stream.html) to choose another interval.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let (response, sender) = Response::event_stream();
+    /// sender.send_data("hello").unwrap();
+    /// ```
+    #[inline]
+    pub fn event_stream() -> (Response, EventSender) {
+        sse::event_stream(Some(Duration::from_secs(15)))
+    }
+
     /// Builds a `Response` that returns a `401 Not Authorized` status
     /// and a `WWW-Authenticate` header.
     ///
@@ -402,12 +876,12 @@ impl Response {
     /// ```
     #[inline]
     pub fn basic_http_auth_login_required(realm: &str) -> Response {
-        // TODO: escape the realm
+        let realm = realm.replace('\\', "\\\\").replace('"', "\\\"");
         Response {
             status_code: 401,
             headers: vec![(
                 "WWW-Authenticate".into(),
-                format!("Basic realm=\"{}\"", realm).into(),
+                format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm).into(),
             )],
             data: ResponseBody::empty(),
             upgrade: None,
@@ -468,6 +942,126 @@ impl Response {
         }
     }
 
+    /// Builds a `Response` with a 405 status code, meaning that the URL exists but doesn't
+    /// support the method of the request, and an `Allow` header containing the list of methods
+    /// that it supports.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::method_not_allowed(&["GET", "HEAD"]);
+    /// ```
+    pub fn method_not_allowed<S>(allowed: &[S]) -> Response
+    where
+        S: AsRef<str>,
+    {
+        let allowed = allowed
+            .iter()
+            .map(|m| m.as_ref())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Response::text("Method Not Allowed")
+            .with_status_code(405)
+            .with_unique_header("Allow", allowed)
+    }
+
+    /// Builds a `405 Method Not Allowed` response if the `router!` macro found routes that match
+    /// the URL of the request but not its method, or an empty 404 response otherwise.
+    ///
+    /// If the request is an `OPTIONS` request whose URL matches some routes, returns the
+    /// response of [`options`](#method.options) instead. If the method of the request is neither
+    /// standard nor one of the [`CustomMethods`](struct.CustomMethods.html) of the server,
+    /// returns `501 Not Implemented`.
+    ///
+    /// It is meant to be used as the default expression of `router!`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate rouille;
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     router!(request,
+    ///         (GET) (/) => { Response::text("hello") },
+    ///         // `POST /` returns a 405 with `Allow: GET, HEAD`, and `OPTIONS /` returns a 204
+    ///         // with `Allow: GET, HEAD, OPTIONS`.
+    ///         _ => Response::empty_404_or_405(request)
+    ///     )
+    /// }
+    /// # fn main() {}
+    /// ```
+    #[inline]
+    pub fn empty_404_or_405(request: &Request) -> Response {
+        Response::empty_404_or_405_with(request, |_, allowed| Response::options(&allowed.0))
+    }
+
+    /// Same as [`empty_404_or_405`](#method.empty_404_or_405), but `OPTIONS` requests whose URL
+    /// matches some routes are answered by `options`, which receives the methods of these
+    /// routes. This is the place to answer CORS preflight requests.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use] extern crate rouille;
+    /// use rouille::{Request, Response};
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     router!(request,
+    ///         (GET) (/) => { Response::text("hello") },
+    ///         _ => Response::empty_404_or_405_with(request, |request, allowed| {
+    ///             let response = Response::options(&allowed.0);
+    ///             if request.header("Access-Control-Request-Method").is_some() {
+    ///                 response
+    ///                     .with_unique_header("Access-Control-Allow-Origin", "*")
+    ///                     .with_unique_header("Access-Control-Allow-Methods", allowed.0.join(", "))
+    ///             } else {
+    ///                 response
+    ///             }
+    ///         })
+    ///     )
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn empty_404_or_405_with<F>(request: &Request, options: F) -> Response
+    where
+        F: FnOnce(&Request, &AllowedMethods) -> Response,
+    {
+        if !CustomMethods::is_known(request) {
+            return Response::text("Not Implemented").with_status_code(501);
+        }
+
+        let allowed = request.extensions().get::<AllowedMethods>().cloned();
+        match allowed {
+            Some(ref allowed) if request.method() == "OPTIONS" => options(request, allowed),
+            Some(allowed) => Response::method_not_allowed(&allowed.0),
+            None => Response::empty_404(),
+        }
+    }
+
+    /// Builds a `204 No Content` response to an `OPTIONS` request, with an `Allow` header that
+    /// contains the given methods and `OPTIONS`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::options(&["GET", "POST"]);
+    /// assert_eq!(response.status_code, 204);
+    /// ```
+    pub fn options<S>(allowed: &[S]) -> Response
+    where
+        S: AsRef<str>,
+    {
+        let mut methods = allowed.iter().map(|m| m.as_ref()).collect::<Vec<_>>();
+        if !methods.contains(&"OPTIONS") {
+            methods.push("OPTIONS");
+        }
+
+        Response::empty_204().with_unique_header("Allow", methods.join(", "))
+    }
+
     /// Builds an empty `Response` with a 406 status code.
     ///
     /// # Example
@@ -486,25 +1080,196 @@ impl Response {
         }
     }
 
-    /// Changes the status code of the response.
+    /// Builds an empty `Response` with a 204 status code, meaning that the request succeeded
+    /// and that there is no content to return.
+    ///
+    /// This is the same as `empty_204`.
     ///
     /// # Example
     ///
     /// ```
     /// use rouille::Response;
-    /// let response = Response::text("hello world").with_status_code(500);
+    /// let response = Response::no_content();
     /// ```
     #[inline]
-    pub fn with_status_code(mut self, code: u16) -> Response {
-        self.status_code = code;
-        self
+    pub fn no_content() -> Response {
+        Response::empty_204()
     }
 
-    /// Removes all headers from the response that match `header`.
-    pub fn without_header(mut self, header: &str) -> Response {
-        self.headers
-            .retain(|&(ref h, _)| !h.eq_ignore_ascii_case(header));
-        self
+    /// Builds an empty `Response` with a 201 status code, meaning that a resource has been
+    /// created at the given location.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::created("/users/5");
+    /// ```
+    #[inline]
+    pub fn created<S>(location: S) -> Response
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        Response {
+            status_code: 201,
+            headers: vec![("Location".into(), location.into())],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
+    /// Builds an empty `Response` with a 202 status code, meaning that the request has been
+    /// accepted but not processed yet.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::accepted();
+    /// ```
+    #[inline]
+    pub fn accepted() -> Response {
+        Response {
+            status_code: 202,
+            headers: vec![],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
+    /// Builds an empty `Response` with a 304 status code, meaning that the client can use its
+    /// cached version of the resource.
+    ///
+    /// > **Note**: You should add the `ETag` or `Cache-Control` headers that a `200` response
+    /// > would have contained. See also the `with_etag` method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::not_modified();
+    /// ```
+    #[inline]
+    pub fn not_modified() -> Response {
+        Response {
+            status_code: 304,
+            headers: vec![],
+            data: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
+    /// Builds a `Response` with a 429 status code, meaning that the client sent too many
+    /// requests, and a `Retry-After` header.
+    ///
+    /// The `Retry-After` header is in whole seconds, therefore `retry_after` is rounded up.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use rouille::Response;
+    /// let response = Response::too_many_requests(Duration::from_secs(30));
+    /// ```
+    #[inline]
+    pub fn too_many_requests(retry_after: Duration) -> Response {
+        Response::text("Too Many Requests")
+            .with_status_code(429)
+            .with_unique_header("Retry-After", RetryAfter::Delay(retry_after).to_string())
+    }
+
+    /// Builds a `Response` with a 503 status code, meaning that the server is temporarily
+    /// unable to handle the request. If `retry_after` is `Some`, a `Retry-After` header is added.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use rouille::Response;
+    /// let response = Response::service_unavailable(Some(Duration::from_secs(120)));
+    /// ```
+    pub fn service_unavailable(retry_after: Option<Duration>) -> Response {
+        let response = Response::text("Service Unavailable").with_status_code(503);
+        match retry_after {
+            Some(retry_after) => response
+                .with_unique_header("Retry-After", RetryAfter::Delay(retry_after).to_string()),
+            None => response,
+        }
+    }
+
+    /// Changes the status code of the response.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::text("hello world").with_status_code(500);
+    /// ```
+    #[inline]
+    pub fn with_status_code(mut self, code: u16) -> Response {
+        self.status_code = code;
+        self
+    }
+
+    /// Prevents [`content_encoding::apply`](content_encoding/fn.apply.html) from compressing
+    /// the body of the response.
+    ///
+    /// This is useful for bodies that are already compressed, or for streams such as
+    /// server-sent events, which must reach the client as soon as possible. Note that replacing
+    /// the `data` of the response afterwards cancels this.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::text("data: hello\n\n").no_compression();
+    /// ```
+    #[inline]
+    pub fn no_compression(mut self) -> Response {
+        self.data.no_compression = true;
+        self
+    }
+
+    /// Returns the value of the first header of the response whose name is `name`, compared
+    /// case-insensitively.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::text("hello").with_additional_header("X-Foo", "bar");
+    /// assert_eq!(response.header("x-foo"), Some("bar"));
+    /// assert_eq!(response.header("X-Bar"), None);
+    /// ```
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| &header.1[..])
+    }
+
+    /// Returns the `Content-Type` header of the response, if any.
+    ///
+    /// This is useful for middlewares that only want to touch some kinds of responses, for
+    /// example to rewrite HTML.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// let response = Response::html("<p>hello</p>");
+    /// assert_eq!(response.content_type(), Some("text/html; charset=utf-8"));
+    /// ```
+    #[inline]
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+    }
+
+    /// Removes all headers from the response that match `header`.
+    pub fn without_header(mut self, header: &str) -> Response {
+        self.headers
+            .retain(|&(ref h, _)| !h.eq_ignore_ascii_case(header));
+        self
     }
 
     /// Adds an additional header to the response.
@@ -518,6 +1283,49 @@ impl Response {
         self
     }
 
+    /// Adds a `Link` header to the response.
+    ///
+    /// Each call adds a separate header, which clients treat the same as a single header
+    /// containing all the links.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// use rouille::link::Link;
+    ///
+    /// let response = Response::text("hello world")
+    ///     .with_link(Link::new("/style.css", "preload").param("as", "style"));
+    /// ```
+    #[inline]
+    pub fn with_link(self, link: Link) -> Response {
+        self.with_additional_header("Link", link.to_string())
+    }
+
+    /// Adds a `Set-Cookie` header to the response.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the cookie is invalid, as described in
+    /// [`SetCookie::validate`](struct.SetCookie.html#method.validate). Use
+    /// [`ResponseBuilder::cookie`](struct.ResponseBuilder.html#method.cookie) in order to get an
+    /// error instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{Response, SameSite, SetCookie};
+    ///
+    /// let response = Response::text("hello world")
+    ///     .with_cookie(SetCookie::new("theme", "dark").path("/").same_site(SameSite::Lax));
+    /// ```
+    pub fn with_cookie(self, cookie: SetCookie) -> Response {
+        if let Err(err) = cookie.validate() {
+            panic!("{}", err);
+        }
+        self.with_additional_header("Set-Cookie", cookie.to_string())
+    }
+
     /// Removes all headers from the response whose names are `header`, and replaces them .
     pub fn with_unique_header<H, V>(mut self, header: H, value: V) -> Response
     where
@@ -589,24 +1397,68 @@ impl Response {
         self.with_etag_keep(etag).simplify_if_etag_match(request)
     }
 
+    /// Same as `with_etag`, but the ETag is a hash of the body of the response. Use this when
+    /// the response is cheap to build but expensive to send, and there is no version number or
+    /// modification date to build the ETag from.
+    ///
+    /// The body is read in memory. Responses whose status code isn't `2xx`, and streaming
+    /// responses such as event streams, are returned unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     Response::json(&vec![1, 2, 3]).with_body_etag(request)
+    /// }
+    /// ```
+    pub fn with_body_etag(mut self, request: &Request) -> Response {
+        if !self.is_success() || self.data.unbuffered || self.upgrade.is_some() {
+            return self;
+        }
+
+        let mut data = Vec::new();
+        let default_error = self.data.default_error;
+        let no_compression = self.data.no_compression;
+        let (mut reader, _) =
+            mem::replace(&mut self.data, ResponseBody::empty()).into_reader_and_size();
+        if reader.read_to_end(&mut data).is_err() {
+            return Response::text("Internal Server Error").with_status_code(500);
+        }
+
+        let mut hash = Sha1::new();
+        hash.update(&data);
+        let etag = format!("\"{}\"", hash.digest());
+
+        self.data = ResponseBody::from_data(data);
+        self.data.default_error = default_error;
+        self.data.no_compression = no_compression;
+        self.with_etag(request, etag)
+    }
+
     /// Turns the response into an empty 304 response if the `ETag` that is stored in it matches a
     /// `If-None-Match` header of the request.
+    ///
+    /// The header can contain a list of ETags, or `*`. The ETags are compared with the weak
+    /// comparison of RFC 7232, which means that a `W/` prefix is ignored. For compatibility, the
+    /// ETags are also allowed to not be quoted.
     pub fn simplify_if_etag_match(mut self, request: &Request) -> Response {
         if self.status_code < 200 || self.status_code >= 300 {
             return self;
         }
 
-        let mut not_modified = false;
-        for &(ref key, ref etag) in &self.headers {
-            if !key.eq_ignore_ascii_case("ETag") {
-                continue;
-            }
-
-            not_modified = request
-                .header("If-None-Match")
-                .map(|header| header == etag)
-                .unwrap_or(false);
-        }
+        let not_modified = match (
+            request.header("If-None-Match"),
+            self.headers
+                .iter()
+                .rev()
+                .find(|h| h.0.eq_ignore_ascii_case("ETag")),
+        ) {
+            (Some(header), Some(etag)) => if_none_match(header, &etag.1),
+            _ => false,
+        };
 
         if not_modified {
             self.data = ResponseBody::empty();
@@ -669,6 +1521,138 @@ impl Response {
         self
     }
 
+    /// Adds or replaces the `Cache-Control` header of the response.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// use rouille::cache_control::CacheControl;
+    ///
+    /// let response = Response::text("hello world")
+    ///     .with_cache_control(CacheControl::new().public().max_age(600).s_maxage(60));
+    /// ```
+    #[inline]
+    pub fn with_cache_control(self, cache_control: CacheControl) -> Response {
+        self.with_unique_header("Cache-Control", cache_control.to_string())
+    }
+
+    /// Adds the security headers of `headers` to the response, except for those that it already
+    /// contains.
+    ///
+    /// The request is used to generate the nonce of the content security policy. See
+    /// [the `security_headers` module](security_headers/index.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Request;
+    /// use rouille::Response;
+    /// use rouille::security_headers::SecurityHeaders;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     Response::html("<p>hello</p>").with_security_headers(request, &SecurityHeaders::new())
+    /// }
+    /// # let _ = handle(&Request::fake_http("GET", "/", vec![], vec![]));
+    /// ```
+    #[inline]
+    pub fn with_security_headers(self, request: &Request, headers: &SecurityHeaders) -> Response {
+        headers.apply(request, self)
+    }
+
+    /// Adds or replaces an `Expires` header, which contains the date after which the response
+    /// is stale. The `max-age` directive of `Cache-Control` has priority over it for the clients
+    /// that understand it.
+    #[inline]
+    pub fn with_expires(self, date: SystemTime) -> Response {
+        self.with_unique_header("Expires", format_http_date(date))
+    }
+
+    /// Adds or replaces a `Last-Modified` header, then turns the response into an empty 304
+    /// response if the resource wasn't modified since the date in the `If-Modified-Since` header
+    /// of the request.
+    ///
+    /// As required by HTTP, `If-Modified-Since` is ignored if the request has an `If-None-Match`
+    /// header, which is handled by `with_etag`, or if its method isn't `GET` or `HEAD`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs;
+    /// use rouille::Request;
+    /// use rouille::Response;
+    ///
+    /// fn handle(request: &Request) -> Response {
+    ///     let modified = fs::metadata("report.txt").unwrap().modified().unwrap();
+    ///     let file = fs::File::open("report.txt").unwrap();
+    ///     Response::from_file("text/plain", file).with_last_modified(request, modified)
+    /// }
+    /// ```
+    pub fn with_last_modified(mut self, request: &Request, date: SystemTime) -> Response {
+        self = self.with_last_modified_keep(date);
+        if self.status_code < 200 || self.status_code >= 300 {
+            return self;
+        }
+        if request.header("If-None-Match").is_some()
+            || (request.method() != "GET" && request.method() != "HEAD")
+        {
+            return self;
+        }
+
+        let since = match request
+            .header("If-Modified-Since")
+            .and_then(|since| since.parse::<HttpDate>().ok())
+        {
+            Some(since) => since,
+            None => return self,
+        };
+        // HTTP dates don't have a precision below the second.
+        if HttpDate::from(date) <= since {
+            self.data = ResponseBody::empty();
+            self.status_code = 304;
+        }
+        self
+    }
+
+    /// Adds or replaces a `Last-Modified` header.
+    ///
+    /// > **Note**: Contrary to `with_last_modified`, this function doesn't try to turn the
+    /// > response into a 304 response.
+    #[inline]
+    pub fn with_last_modified_keep(self, date: SystemTime) -> Response {
+        self.with_unique_header("Last-Modified", format_http_date(date))
+    }
+
+    /// Adds a header name to the `Vary` header of the response, creating it if necessary. This
+    /// tells the caches that the response depends on the value of this header of the request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::text("bonjour").with_vary("Accept-Language").with_vary("Cookie");
+    /// assert!(response.headers.contains(&("Vary".into(), "Accept-Language, Cookie".into())));
+    /// ```
+    pub fn with_vary(mut self, header: &str) -> Response {
+        if let Some(vary) = self
+            .headers
+            .iter_mut()
+            .find(|h| h.0.eq_ignore_ascii_case("Vary"))
+        {
+            let present = vary
+                .1
+                .split(',')
+                .any(|h| h.trim() == "*" || h.trim().eq_ignore_ascii_case(header));
+            if !present {
+                vary.1 = format!("{}, {}", vary.1, header).into();
+            }
+            return self;
+        }
+
+        self.with_additional_header("Vary", header.to_owned())
+    }
+
     /// Adds or replaces a `Cache-Control` header that specifies that the resource is public and
     /// can be cached for the given number of seconds.
     ///
@@ -710,12 +1694,42 @@ impl Response {
             .with_unique_header("Expires", "0")
             .with_unique_header("Pragma", "no-cache")
     }
+
+    /// Adds a `Connection: close` header, which asks the client to close the connection once it
+    /// has received the response instead of sending other requests on it.
+    ///
+    /// This is useful to move clients to other instances, for example while draining a server or
+    /// behind load balancers that don't spread long-lived connections.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    ///
+    /// let response = Response::text("goodbye").with_connection_close();
+    /// assert!(response.closes_connection());
+    /// ```
+    #[inline]
+    pub fn with_connection_close(self) -> Response {
+        self.with_unique_header("Connection", "close")
+    }
+
+    /// Returns true if the response has a `Connection: close` header.
+    pub fn closes_connection(&self) -> bool {
+        self.headers.iter().any(|(key, value)| {
+            key.eq_ignore_ascii_case("Connection")
+                && value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("close"))
+        })
+    }
 }
 
 /// An opaque type that represents the body of a response.
 ///
 /// You can't access the inside of this struct, but you can build one by using one of the provided
-/// constructors.
+/// constructors. Middlewares can query the declared length with `len()`, and look at or modify
+/// the data as it is sent with `inspect()` and `wrap()`, without reading the body in advance.
 ///
 /// # Example
 ///
@@ -725,7 +1739,15 @@ impl Response {
 /// ```
 pub struct ResponseBody {
     data: Box<dyn Read + Send>,
-    data_length: Option<usize>,
+    pub(crate) data_length: Option<usize>,
+    // True if this is the body of an error response generated by rouille itself, which the error
+    // handler of the server is allowed to replace.
+    pub(crate) default_error: bool,
+    // True if `content_encoding::apply` must not encode this body.
+    pub(crate) no_compression: bool,
+    // True if the server must send the data to the client as soon as it is read, instead of
+    // buffering it.
+    pub(crate) unbuffered: bool,
 }
 
 impl ResponseBody {
@@ -742,9 +1764,102 @@ impl ResponseBody {
         ResponseBody {
             data: Box::new(io::empty()),
             data_length: Some(0),
+            default_error: false,
+            no_compression: false,
+            unbuffered: false,
         }
     }
 
+    /// Returns true if the body is known to be empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::ResponseBody;
+    /// assert!(ResponseBody::empty().is_empty());
+    /// assert!(!ResponseBody::from_string("hello").is_empty());
+    /// ```
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data_length == Some(0)
+    }
+
+    /// Returns the length of the body in bytes, or `None` if it isn't known in advance.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::ResponseBody;
+    /// use std::io::Cursor;
+    ///
+    /// assert_eq!(ResponseBody::from_string("hello").len(), Some(5));
+    /// assert_eq!(ResponseBody::from_reader(Cursor::new(vec![0u8; 5])).len(), None);
+    /// ```
+    #[inline]
+    pub fn len(&self) -> Option<usize> {
+        self.data_length
+    }
+
+    /// Replaces the reader of the body with the one returned by `f`, which receives the current
+    /// reader.
+    ///
+    /// Since the new reader can return different data, the length of the body becomes unknown. If
+    /// you only need to look at the data, use `inspect()`, which keeps the length.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::ResponseBody;
+    /// use std::io::Read;
+    ///
+    /// let body = ResponseBody::from_string("hello world")
+    ///     .wrap(|reader| reader.chain(&b"!"[..]));
+    /// assert_eq!(body.len(), None);
+    ///
+    /// let mut data = String::new();
+    /// body.into_reader_and_size().0.read_to_string(&mut data).unwrap();
+    /// assert_eq!(data, "hello world!");
+    /// ```
+    pub fn wrap<F, R>(self, f: F) -> ResponseBody
+    where
+        F: FnOnce(Box<dyn Read + Send>) -> R,
+        R: Read + Send + 'static,
+    {
+        let mut body = self.map_reader(f);
+        body.data_length = None;
+        body
+    }
+
+    /// Calls `f` with each chunk of data as it is read from the body, for example to measure its
+    /// size or to hash it.
+    ///
+    /// The data itself isn't modified, and the length of the body is kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::ResponseBody;
+    /// use std::io::Read;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let sent = Arc::new(AtomicUsize::new(0));
+    /// let body = ResponseBody::from_string("hello world").inspect({
+    ///     let sent = sent.clone();
+    ///     move |chunk| { sent.fetch_add(chunk.len(), Ordering::Relaxed); }
+    /// });
+    /// assert_eq!(body.len(), Some(11));
+    ///
+    /// body.into_reader_and_size().0.read_to_end(&mut Vec::new()).unwrap();
+    /// assert_eq!(sent.load(Ordering::Relaxed), 11);
+    /// ```
+    pub fn inspect<F>(self, f: F) -> ResponseBody
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.map_reader(|reader| InspectReader { reader, f })
+    }
+
     /// Builds a new `ResponseBody` that will read the data from a `Read`.
     ///
     /// Note that this is suboptimal compared to other constructors because the length
@@ -767,6 +1882,9 @@ impl ResponseBody {
         ResponseBody {
             data: Box::new(data),
             data_length: None,
+            default_error: false,
+            no_compression: false,
+            unbuffered: false,
         }
     }
 
@@ -793,6 +1911,9 @@ impl ResponseBody {
         ResponseBody {
             data: Box::new(data),
             data_length: Some(size),
+            default_error: false,
+            no_compression: false,
+            unbuffered: false,
         }
     }
 
@@ -815,6 +1936,9 @@ impl ResponseBody {
         ResponseBody {
             data: Box::new(Cursor::new(data)),
             data_length: Some(len),
+            default_error: false,
+            no_compression: false,
+            unbuffered: false,
         }
     }
 
@@ -836,9 +1960,47 @@ impl ResponseBody {
         ResponseBody {
             data: Box::new(file),
             data_length: len,
+            default_error: false,
+            no_compression: false,
+            unbuffered: false,
         }
     }
 
+    /// Builds a new `ResponseBody` whose data is generated by a function that writes it.
+    ///
+    /// The function runs in a separate thread when the body starts being sent, and the data is
+    /// sent to the client as it is written. The writes block while the client is slower than the
+    /// function, and fail once the client has disconnected. If the function returns an error,
+    /// the response is interrupted.
+    ///
+    /// Since the length isn't known in advance, the body is sent with chunked transfer
+    /// encoding.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// use rouille::ResponseBody;
+    ///
+    /// let body = ResponseBody::from_writer(|w| {
+    ///     for n in 0..1000 {
+    ///         writeln!(w, "{},{}", n, n * n)?;
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    #[inline]
+    pub fn from_writer<F>(write: F) -> ResponseBody
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        ResponseBody::from_reader(WriterReader {
+            write: Some(Box::new(write)),
+            receiver: None,
+            current: Cursor::new(Vec::new()),
+        })
+    }
+
     /// Builds a new `ResponseBody` that returns an UTF-8 string.
     ///
     /// # Example
@@ -863,11 +2025,351 @@ impl ResponseBody {
     pub fn into_reader_and_size(self) -> (Box<dyn Read + Send>, Option<usize>) {
         (self.data, self.data_length)
     }
+
+    // Replaces the reader of the body with `f(reader)`, which must produce the same data. The
+    // length and the flags of the body are kept.
+    pub(crate) fn map_reader<F, R>(self, f: F) -> ResponseBody
+    where
+        F: FnOnce(Box<dyn Read + Send>) -> R,
+        R: Read + Send + 'static,
+    {
+        ResponseBody {
+            data: Box::new(f(self.data)),
+            ..self
+        }
+    }
+}
+
+// Reader returned by `ResponseBody::inspect`.
+struct InspectReader<F> {
+    reader: Box<dyn Read + Send>,
+    f: F,
+}
+
+impl<F> Read for InspectReader<F>
+where
+    F: FnMut(&[u8]),
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num = self.reader.read(buf)?;
+        if num != 0 {
+            (self.f)(&buf[..num]);
+        }
+        Ok(num)
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    #[inline]
+    fn from(data: Vec<u8>) -> ResponseBody {
+        ResponseBody::from_data(data)
+    }
+}
+
+impl From<String> for ResponseBody {
+    #[inline]
+    fn from(data: String) -> ResponseBody {
+        ResponseBody::from_string(data)
+    }
+}
+
+type WriteFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+// Number of chunks that the writer of `from_writer` can produce ahead of the client.
+const WRITER_CHUNKS: usize = 4;
+const WRITER_CHUNK_SIZE: usize = 16 * 1024;
+
+// Reads the data produced by the function passed to `ResponseBody::from_writer`.
+struct WriterReader {
+    // The function, until the first read starts it.
+    write: Option<WriteFn>,
+    receiver: Option<mpsc::Receiver<io::Result<Vec<u8>>>>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl Read for WriterReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let num = self.current.read(buf)?;
+            if num != 0 || buf.is_empty() {
+                return Ok(num);
+            }
+
+            if let Some(write) = self.write.take() {
+                let (sender, receiver) = mpsc::sync_channel(WRITER_CHUNKS);
+                self.receiver = Some(receiver);
+                thread::spawn(move || {
+                    let mut writer = ChannelWriter {
+                        sender,
+                        buffer: Vec::with_capacity(WRITER_CHUNK_SIZE),
+                    };
+                    let result = write(&mut writer).and_then(|_| writer.flush());
+                    if let Err(err) = result {
+                        let _ = writer.sender.send(Err(err));
+                    }
+                });
+            }
+
+            let chunk = match self.receiver {
+                Some(ref receiver) => receiver.recv(),
+                None => return Ok(0),
+            };
+            match chunk {
+                Ok(Ok(chunk)) => self.current = Cursor::new(chunk),
+                Ok(Err(err)) => return Err(err),
+                // The function has returned.
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+// Sends what is written to a `WriterReader` by chunks.
+struct ChannelWriter {
+    sender: mpsc::SyncSender<io::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= WRITER_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = mem::replace(&mut self.buffer, Vec::with_capacity(WRITER_CHUNK_SIZE));
+        self.sender.send(Ok(chunk)).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the response is no longer being sent",
+            )
+        })
+    }
+}
+
+// Returns true if the validator of an `If-Range` header matches the current version of the body.
+fn if_range(validator: &str, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    let validator = validator.trim();
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        // Weak ETags never match.
+        return match etag {
+            Some(etag) => !etag.starts_with("W/") && validator == etag.trim(),
+            None => false,
+        };
+    }
+
+    match (validator.parse::<HttpDate>(), last_modified) {
+        (Ok(date), Some(modified)) => HttpDate::from(modified) == date,
+        _ => false,
+    }
+}
+
+// Maximum number of ranges in a `Range` header. Requests with more ranges get the whole body.
+const MAX_RANGES: usize = 32;
+
+// Parses the value of a `Range` header for a body of `length` bytes. Returns `None` if the header
+// must be ignored, and otherwise the offsets of the first and last bytes of the satisfiable
+// ranges, both inclusive, sorted and merged. An empty list means that no range can be satisfied.
+fn parse_range(header: &str, length: u64) -> Option<Vec<(u64, u64)>> {
+    let header = header.trim();
+    if header.len() < 6 || !header[..6].eq_ignore_ascii_case("bytes=") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for (index, range) in header[6..].split(',').enumerate() {
+        if index >= MAX_RANGES {
+            return None;
+        }
+
+        let mut split = range.trim().splitn(2, '-');
+        let first = split.next()?.trim();
+        let last = split.next()?.trim();
+
+        if first.is_empty() {
+            // A suffix range, for the last bytes of the body.
+            let suffix = last.parse::<u64>().ok()?;
+            if suffix != 0 && length != 0 {
+                ranges.push((length.saturating_sub(suffix), length - 1));
+            }
+            continue;
+        }
+
+        let first = first.parse::<u64>().ok()?;
+        let last = if last.is_empty() {
+            u64::max_value()
+        } else {
+            last.parse::<u64>().ok()?
+        };
+        if first > last {
+            return None;
+        }
+        if first < length {
+            ranges.push((first, last.min(length - 1)));
+        }
+    }
+
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1 + 1 => previous.1 = previous.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    Some(merged)
+}
+
+// A part of the body of a `multipart/byteranges` response.
+enum Part {
+    // Delimiter and headers of a part, or the final delimiter.
+    Data(Cursor<Vec<u8>>),
+    // Offset and length of a range of the body.
+    Range(u64, u64),
+}
+
+// Reads the parts of a `multipart/byteranges` response, seeking to each range when reaching it.
+struct ByteRangesReader<R> {
+    data: R,
+    // In reverse order, so that the next part can be popped.
+    parts: Vec<Part>,
+    // Number of bytes left in the current range.
+    remaining: u64,
+}
+
+impl<R> Read for ByteRangesReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+
+            if self.remaining > 0 {
+                let len = cmp::min(buf.len() as u64, self.remaining) as usize;
+                let num = self.data.read(&mut buf[..len])?;
+                if num == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the body is shorter than its declared length",
+                    ));
+                }
+                self.remaining -= num as u64;
+                return Ok(num);
+            }
+
+            match self.parts.last_mut() {
+                None => return Ok(0),
+                Some(Part::Data(data)) => {
+                    let num = data.read(buf)?;
+                    if num != 0 {
+                        return Ok(num);
+                    }
+                }
+                Some(Part::Range(first, len)) => {
+                    self.data.seek(SeekFrom::Start(*first))?;
+                    self.remaining = *len;
+                }
+            }
+            self.parts.pop();
+        }
+    }
+}
+
+// Returns true if the value of an `If-None-Match` header matches an ETag, with the weak
+// comparison.
+fn if_none_match(header: &str, etag: &str) -> bool {
+    fn opaque(etag: &str) -> &str {
+        let etag = etag.trim();
+        let etag = if etag.starts_with("W/") {
+            &etag[2..]
+        } else {
+            etag
+        };
+        etag.trim_matches('"')
+    }
+
+    let header = header.trim();
+    if header == "*" {
+        return true;
+    }
+
+    let etag = opaque(etag);
+    header.split(',').any(|candidate| opaque(candidate) == etag)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::if_none_match;
+    use super::if_range;
+    use super::parse_range;
+    use std::io;
+    use std::io::Read;
+    use std::time::Duration;
+    use Request;
     use Response;
+    use ResponseBody;
+
+    #[test]
+    fn if_none_match_comparison() {
+        assert!(if_none_match("\"a\"", "\"a\""));
+        assert!(if_none_match("W/\"a\"", "\"a\""));
+        assert!(if_none_match("\"b\", W/\"a\"", "W/\"a\""));
+        assert!(if_none_match("*", "\"a\""));
+        assert!(if_none_match("a", "a"));
+        assert!(!if_none_match("\"b\", \"c\"", "\"a\""));
+    }
+
+    #[test]
+    fn caching_headers() {
+        let date = ::std::time::UNIX_EPOCH + Duration::from_secs(784111777);
+        let response = Response::text("")
+            .with_expires(date)
+            .with_additional_header("Vary", "Accept")
+            .with_vary("accept")
+            .with_vary("Cookie");
+        assert!(response
+            .headers
+            .contains(&("Expires".into(), "Sun, 06 Nov 1994 08:49:37 GMT".into())));
+        assert!(response
+            .headers
+            .contains(&("Vary".into(), "Accept, Cookie".into())));
+    }
+
+    #[test]
+    fn body_etag() {
+        let response =
+            Response::text("hello").with_body_etag(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+        let etag = response
+            .headers
+            .iter()
+            .find(|h| h.0 == "ETag")
+            .unwrap()
+            .1
+            .clone()
+            .into_owned();
+        assert_eq!(etag, "\"aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d\"");
+
+        let headers = vec![("If-None-Match".to_owned(), format!("W/{}", etag))];
+        let request = Request::fake_http("GET", "/", headers, vec![]);
+        let response = Response::text("hello").with_body_etag(&request);
+        assert_eq!(response.status_code, 304);
+
+        let response = Response::text("hello")
+            .with_status_code(404)
+            .with_body_etag(&request);
+        assert!(!response.headers.iter().any(|h| h.0 == "ETag"));
+    }
 
     #[test]
     fn unique_header_adds() {
@@ -912,4 +2414,242 @@ mod tests {
         assert_eq!(r.headers.len(), 1);
         assert_eq!(r.headers[0], ("foo".into(), "Bar".into()));
     }
+
+    #[test]
+    fn created() {
+        let r = Response::created("/users/5");
+        assert_eq!(r.status_code, 201);
+        assert_eq!(r.headers, vec![("Location".into(), "/users/5".into())]);
+    }
+
+    #[test]
+    fn retry_after_rounded_up() {
+        let r = Response::too_many_requests(Duration::from_millis(1500));
+        assert_eq!(r.status_code, 429);
+        assert_eq!(
+            r.headers.iter().find(|h| h.0 == "Retry-After").unwrap().1,
+            "2"
+        );
+
+        let r = Response::service_unavailable(Some(Duration::from_secs(3)));
+        assert_eq!(r.status_code, 503);
+        assert_eq!(
+            r.headers.iter().find(|h| h.0 == "Retry-After").unwrap().1,
+            "3"
+        );
+
+        let r = Response::service_unavailable(None);
+        assert!(!r.headers.iter().any(|h| h.0 == "Retry-After"));
+    }
+
+    #[test]
+    fn json_stream() {
+        let response = Response::json_stream(vec![(1, "a"), (2, "b")]);
+        let mut data = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, r#"[[1,"a"],[2,"b"]]"#);
+    }
+
+    #[test]
+    fn json_stream_iter() {
+        use JsonStreamFormat;
+
+        let body = |format| {
+            let response = Response::json_stream_iter((1..4).map(|n| vec![n; n]), format);
+            let mut data = String::new();
+            response
+                .data
+                .into_reader_and_size()
+                .0
+                .read_to_string(&mut data)
+                .unwrap();
+            data
+        };
+
+        assert_eq!(body(JsonStreamFormat::Array), "[[1],[2,2],[3,3,3]]");
+        assert_eq!(body(JsonStreamFormat::Lines), "[1]\n[2,2]\n[3,3,3]\n");
+    }
+
+    #[test]
+    fn from_writer() {
+        let body = ResponseBody::from_writer(|w| {
+            for n in 0..10000 {
+                write!(w, "{},", n)?;
+            }
+            Ok(())
+        });
+
+        let (mut reader, size) = body.into_reader_and_size();
+        assert_eq!(size, None);
+        let mut data = String::new();
+        reader.read_to_string(&mut data).unwrap();
+        assert!(data.starts_with("0,1,2,"));
+        assert!(data.ends_with(",9999,"));
+    }
+
+    #[test]
+    fn from_writer_error() {
+        let body = ResponseBody::from_writer(|w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::new(io::ErrorKind::Other, "failed"))
+        });
+
+        let mut reader = body.into_reader_and_size().0;
+        let mut data = Vec::new();
+        assert!(reader.read_to_end(&mut data).is_err());
+    }
+
+    #[test]
+    fn from_writer_disconnected() {
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        let body = ResponseBody::from_writer(move |w| {
+            let result = (0..).try_for_each(|_| w.write_all(&[0; 1024]));
+            sender.send(result.unwrap_err().kind()).unwrap();
+            Ok(())
+        });
+
+        let mut reader = body.into_reader_and_size().0;
+        reader.read_exact(&mut [0; 10]).unwrap();
+        drop(reader);
+        assert_eq!(receiver.recv().unwrap(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn range_parsing() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some(vec![(0, 4)]));
+        assert_eq!(parse_range("bytes=5-", 10), Some(vec![(5, 9)]));
+        assert_eq!(parse_range("bytes=5-100", 10), Some(vec![(5, 9)]));
+        assert_eq!(parse_range("bytes=-3", 10), Some(vec![(7, 9)]));
+        assert_eq!(parse_range("bytes=-30", 10), Some(vec![(0, 9)]));
+        assert_eq!(parse_range("bytes=10-", 10), Some(vec![]));
+        assert_eq!(parse_range("bytes=-0", 10), Some(vec![]));
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("items=0-4", 10), None);
+        assert_eq!(parse_range("bytes=a-", 10), None);
+
+        assert_eq!(
+            parse_range("bytes=6-7, 0-1, 20-", 10),
+            Some(vec![(0, 1), (6, 7)])
+        );
+        assert_eq!(
+            parse_range("bytes=0-2,3-4,4-6,-1", 10),
+            Some(vec![(0, 6), (9, 9)])
+        );
+        let many = vec!["0-0"; 33].join(",");
+        assert_eq!(parse_range(&format!("bytes={}", many), 10), None);
+    }
+
+    #[test]
+    fn if_range_validators() {
+        let date = ::std::time::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert!(if_range("\"a\"", Some("\"a\""), None));
+        assert!(!if_range("\"a\"", Some("\"b\""), None));
+        assert!(!if_range("W/\"a\"", Some("W/\"a\""), None));
+        assert!(!if_range("\"a\"", None, Some(date)));
+        assert!(if_range("Sun, 06 Nov 1994 08:49:37 GMT", None, Some(date)));
+        assert!(!if_range("Sun, 06 Nov 1994 08:49:38 GMT", None, Some(date)));
+        assert!(!if_range(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            Some("\"a\""),
+            None
+        ));
+    }
+
+    #[test]
+    fn from_range() {
+        let body = |response: Response| {
+            let mut data = String::new();
+            let (mut reader, _) = response.data.into_reader_and_size();
+            reader.read_to_string(&mut data).unwrap();
+            data
+        };
+        let data = || io::Cursor::new(b"0123456789".to_vec());
+        let range = |value: &str| {
+            Request::fake_http("GET", "/", vec![("Range".into(), value.into())], vec![])
+        };
+
+        let response = Response::from_range(&range("bytes=2-4"), "text/plain", data(), 10);
+        assert_eq!(response.status_code, 206);
+        assert!(response
+            .headers
+            .contains(&("Content-Range".into(), "bytes 2-4/10".into())));
+        assert!(response
+            .headers
+            .contains(&("Accept-Ranges".into(), "bytes".into())));
+        assert_eq!(body(response), "234");
+
+        let response = Response::from_range(&range("bytes=20-"), "text/plain", data(), 10);
+        assert_eq!(response.status_code, 416);
+        assert!(response
+            .headers
+            .contains(&("Content-Range".into(), "bytes */10".into())));
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = Response::from_range(&request, "text/plain", data(), 10);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(body(response), "0123456789");
+
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![
+                ("Range".into(), "bytes=2-4".into()),
+                ("If-Range".into(), "\"a\"".into()),
+            ],
+            vec![],
+        );
+        let response = Response::from_range(&request, "text/plain", data(), 10);
+        assert_eq!(response.status_code, 200);
+
+        let response = Response::from_range(&range("bytes=0-1,8-"), "text/plain", data(), 10);
+        assert_eq!(response.status_code, 206);
+        let content_type = response.headers[0].1.clone();
+        assert!(content_type.starts_with("multipart/byteranges; boundary="));
+        let boundary = content_type["multipart/byteranges; boundary=".len()..].to_owned();
+        let (mut reader, size) = response.data.into_reader_and_size();
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(size, Some(body.len()));
+        let body = body.replace(&boundary, "B");
+        assert_eq!(
+            body,
+            "\r\n--B\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--B\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--B--\r\n"
+        );
+    }
+
+    #[test]
+    fn inspect_and_wrap_keep_flags() {
+        let response = Response::text("hello").no_compression();
+        assert_eq!(response.content_type(), Some("text/plain; charset=utf-8"));
+
+        let mut seen = Vec::new();
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+        let body = response
+            .data
+            .inspect(move |chunk| sender.send(chunk.to_vec()).unwrap());
+        assert_eq!(body.len(), Some(5));
+        assert!(body.no_compression);
+
+        let body = body.wrap(|reader| reader.chain(io::Cursor::new(" world")));
+        assert_eq!(body.len(), None);
+        assert!(body.no_compression);
+
+        let mut data = String::new();
+        body.into_reader_and_size()
+            .0
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "hello world");
+        for chunk in receiver.try_iter() {
+            seen.extend(chunk);
+        }
+        assert_eq!(seen, b"hello");
+    }
 }
@@ -0,0 +1,583 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use serde;
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use http_date::format_http_date;
+use Response;
+use ResponseBody;
+
+/// Builds a `Response` step by step. Created with
+/// [`Response::builder`](struct.Response.html#method.builder).
+///
+/// Contrary to the `with_*` methods of `Response`, the builder checks that the names and values
+/// of the headers are valid. The first invalid one is reported when calling `build()`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Response, SetCookie};
+///
+/// let response = Response::builder()
+///     .status(201)
+///     .header("Location", "/items/5")
+///     .append_header("Vary", "Accept")
+///     .append_header("Vary", "Accept-Encoding")
+///     .cookie(SetCookie::new("last", "5").path("/").http_only())
+///     .text("created")
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(response.status_code, 201);
+/// assert!(Response::builder().header("X-Bad", "a\r\nb").build().is_err());
+/// ```
+pub struct ResponseBuilder {
+    response: Response,
+    error: Option<ResponseBuilderError>,
+}
+
+impl ResponseBuilder {
+    #[inline]
+    pub(crate) fn new() -> ResponseBuilder {
+        ResponseBuilder {
+            response: Response {
+                status_code: 200,
+                headers: Vec::new(),
+                data: ResponseBody::empty(),
+                upgrade: None,
+            },
+            error: None,
+        }
+    }
+
+    /// Sets the status code of the response. The default is `200`.
+    #[inline]
+    pub fn status(mut self, status_code: u16) -> ResponseBuilder {
+        if self.error.is_none() && !(100..1000).contains(&status_code) {
+            self.error = Some(ResponseBuilderError::InvalidStatusCode(status_code));
+        }
+        self.response.status_code = status_code;
+        self
+    }
+
+    /// Sets a header, replacing all the existing headers with the same name.
+    pub fn header<H, V>(mut self, header: H, value: V) -> ResponseBuilder
+    where
+        H: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        let header = header.into();
+        self.response
+            .headers
+            .retain(|h| !h.0.eq_ignore_ascii_case(&header));
+        self.append_header(header, value)
+    }
+
+    /// Adds a header, keeping the existing headers with the same name.
+    pub fn append_header<H, V>(mut self, header: H, value: V) -> ResponseBuilder
+    where
+        H: Into<Cow<'static, str>>,
+        V: Into<Cow<'static, str>>,
+    {
+        let header = header.into();
+        let value = value.into();
+
+        if self.error.is_none() {
+            if !is_token(&header) {
+                self.error = Some(ResponseBuilderError::InvalidHeaderName(header.to_string()));
+            } else if !is_header_value(&value) {
+                self.error = Some(ResponseBuilderError::InvalidHeaderValue(header.to_string()));
+            }
+        }
+
+        self.response.headers.push((header, value));
+        self
+    }
+
+    /// Adds a `Set-Cookie` header.
+    pub fn cookie(mut self, cookie: SetCookie) -> ResponseBuilder {
+        if self.error.is_none() {
+            if let Err(err) = cookie.validate() {
+                self.error = Some(err);
+            }
+        }
+
+        self.response
+            .headers
+            .push(("Set-Cookie".into(), cookie.to_string().into()));
+        self
+    }
+
+    /// Sets the body of the response, without modifying the headers.
+    #[inline]
+    pub fn body(mut self, body: ResponseBody) -> ResponseBuilder {
+        self.response.data = body;
+        self
+    }
+
+    /// Sets the body of the response and its `Content-Type`.
+    pub fn data<C, D>(self, content_type: C, data: D) -> ResponseBuilder
+    where
+        C: Into<Cow<'static, str>>,
+        D: Into<Vec<u8>>,
+    {
+        self.header("Content-Type", content_type)
+            .body(ResponseBody::from_data(data))
+    }
+
+    /// Sets a plain text body. Same as [`Response::text`](struct.Response.html#method.text).
+    #[inline]
+    pub fn text<S>(self, text: S) -> ResponseBuilder
+    where
+        S: Into<String>,
+    {
+        self.data("text/plain; charset=utf-8", text.into())
+    }
+
+    /// Sets an HTML body. Same as [`Response::html`](struct.Response.html#method.html).
+    #[inline]
+    pub fn html<D>(self, content: D) -> ResponseBuilder
+    where
+        D: Into<String>,
+    {
+        self.data("text/html; charset=utf-8", content.into())
+    }
+
+    /// Sets a JSON body. Same as [`Response::json`](struct.Response.html#method.json).
+    pub fn json<T>(mut self, content: &T) -> ResponseBuilder
+    where
+        T: serde::Serialize,
+    {
+        match serde_json::to_vec(content) {
+            Ok(data) => self.data("application/json; charset=utf-8", data),
+            Err(err) => {
+                if self.error.is_none() {
+                    self.error = Some(ResponseBuilderError::InvalidJson(err.to_string()));
+                }
+                self
+            }
+        }
+    }
+
+    /// Returns the response, or the first error that was encountered.
+    #[inline]
+    pub fn build(self) -> Result<Response, ResponseBuilderError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.response),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseBuilder")
+            .field("status_code", &self.response.status_code)
+            .field("headers", &self.response.headers)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+/// Error that can happen when building a response with a `ResponseBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResponseBuilderError {
+    /// The status code doesn't have three digits.
+    InvalidStatusCode(u16),
+    /// The name of a header is empty or contains a character that isn't allowed.
+    InvalidHeaderName(String),
+    /// The value of the header with this name contains a control character, such as a line feed.
+    InvalidHeaderValue(String),
+    /// The cookie with this name has an invalid name, value or attribute.
+    InvalidCookie(String),
+    /// The body couldn't be serialized to JSON.
+    InvalidJson(String),
+}
+
+impl error::Error for ResponseBuilderError {}
+
+impl fmt::Display for ResponseBuilderError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            ResponseBuilderError::InvalidStatusCode(code) => {
+                write!(fmt, "invalid status code {}", code)
+            }
+            ResponseBuilderError::InvalidHeaderName(ref name) => {
+                write!(fmt, "invalid header name {:?}", name)
+            }
+            ResponseBuilderError::InvalidHeaderValue(ref name) => {
+                write!(fmt, "invalid value for header {:?}", name)
+            }
+            ResponseBuilderError::InvalidCookie(ref name) => {
+                write!(fmt, "invalid cookie {:?}", name)
+            }
+            ResponseBuilderError::InvalidJson(ref err) => {
+                write!(fmt, "failed to serialize the body to JSON: {}", err)
+            }
+        }
+    }
+}
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SameSite {
+    /// The cookie is only sent with requests from the same site.
+    Strict,
+    /// The cookie is also sent when navigating to the site from another site.
+    Lax,
+    /// The cookie is sent with all requests. Browsers require the cookie to be `Secure`.
+    None,
+}
+
+/// A cookie to add to a response with
+/// [`ResponseBuilder::cookie`](struct.ResponseBuilder.html#method.cookie) or
+/// [`Response::with_cookie`](struct.Response.html#method.with_cookie).
+///
+/// Its `Display` implementation produces the value of the `Set-Cookie` header.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use rouille::{SameSite, SetCookie};
+///
+/// let cookie = SetCookie::new("session", "abc")
+///     .max_age(Duration::from_secs(3600))
+///     .secure()
+///     .same_site(SameSite::Lax);
+/// assert_eq!(cookie.to_string(), "session=abc; Max-Age=3600; Secure; SameSite=Lax");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetCookie {
+    pub(crate) name: String,
+    pub(crate) value: String,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    /// Builds a cookie without any attribute.
+    pub fn new<N, V>(name: N, value: V) -> SetCookie
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        SetCookie {
+            name: name.into(),
+            value: value.into(),
+            max_age: None,
+            expires: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Builds a cookie that removes the cookie `name` from the client.
+    ///
+    /// The path and domain must be the same as the ones of the cookie being removed.
+    #[inline]
+    pub fn removal<N>(name: N) -> SetCookie
+    where
+        N: Into<String>,
+    {
+        SetCookie::new(name, "").max_age(Duration::from_secs(0))
+    }
+
+    /// Sets the duration after which the client discards the cookie.
+    #[inline]
+    pub fn max_age(mut self, max_age: Duration) -> SetCookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets the date after which the client discards the cookie.
+    ///
+    /// `Max-Age` takes precedence over this attribute, but some old clients only support
+    /// `Expires`.
+    #[inline]
+    pub fn expires(mut self, expires: SystemTime) -> SetCookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Sets the domain that the cookie is sent to.
+    #[inline]
+    pub fn domain<D>(mut self, domain: D) -> SetCookie
+    where
+        D: Into<String>,
+    {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets the path prefix of the requests that the cookie is sent with.
+    #[inline]
+    pub fn path<P>(mut self, path: P) -> SetCookie
+    where
+        P: Into<String>,
+    {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Only sends the cookie over HTTPS.
+    #[inline]
+    pub fn secure(mut self) -> SetCookie {
+        self.secure = true;
+        self
+    }
+
+    /// Hides the cookie from JavaScript.
+    #[inline]
+    pub fn http_only(mut self) -> SetCookie {
+        self.http_only = true;
+        self
+    }
+
+    /// Sets the `SameSite` attribute.
+    #[inline]
+    pub fn same_site(mut self, same_site: SameSite) -> SetCookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Checks that the cookie can be sent.
+    ///
+    /// The name must be a token, the value must only contain the characters allowed by RFC 6265,
+    /// optionally between double quotes, and the domain and the path must not contain control
+    /// characters or semicolons. A cookie with `SameSite=None` must also be `Secure`, otherwise
+    /// browsers reject it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::{SameSite, SetCookie};
+    ///
+    /// assert!(SetCookie::new("id", "abc").validate().is_ok());
+    /// assert!(SetCookie::new("id", "a b").validate().is_err());
+    /// assert!(SetCookie::new("id", "abc").same_site(SameSite::None).validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), ResponseBuilderError> {
+        // See the grammar of RFC 6265.
+        let value =
+            if self.value.len() >= 2 && self.value.starts_with('"') && self.value.ends_with('"') {
+                &self.value[1..self.value.len() - 1]
+            } else {
+                &self.value[..]
+            };
+        let valid_value = value
+            .bytes()
+            .all(|b| b > 0x20 && b < 0x7f && b != b'"' && b != b',' && b != b';' && b != b'\\');
+        let valid_attribute = |a: &Option<String>| {
+            a.as_ref()
+                .map_or(true, |a| is_header_value(a) && !a.contains(';'))
+        };
+
+        let valid_same_site = self.same_site != Some(SameSite::None) || self.secure;
+
+        if is_token(&self.name)
+            && valid_value
+            && valid_attribute(&self.domain)
+            && valid_attribute(&self.path)
+            && valid_same_site
+        {
+            Ok(())
+        } else {
+            Err(ResponseBuilderError::InvalidCookie(self.name.clone()))
+        }
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Returns true if `s` is a `token` as defined by RFC 7230, which is the syntax of header names.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// Returns true if `s` doesn't contain any control character other than tabs.
+fn is_header_value(s: &str) -> bool {
+    s.bytes().all(|b| b == b'\t' || (b >= 0x20 && b != 0x7f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseBuilderError;
+    use super::SetCookie;
+    use Response;
+
+    #[test]
+    fn headers() {
+        let response = Response::builder()
+            .header("X-A", "1")
+            .header("x-a", "2")
+            .append_header("X-B", "1")
+            .append_header("X-B", "2")
+            .build()
+            .unwrap();
+
+        let headers: Vec<_> = response
+            .headers
+            .iter()
+            .map(|h| format!("{}: {}", h.0, h.1))
+            .collect();
+        assert_eq!(headers, vec!["x-a: 2", "X-B: 1", "X-B: 2"]);
+    }
+
+    #[test]
+    fn typed_body() {
+        let response = Response::builder()
+            .header("Content-Type", "text/plain")
+            .json(&vec![1, 2])
+            .build()
+            .unwrap();
+        assert_eq!(
+            response.headers,
+            vec![(
+                "Content-Type".into(),
+                "application/json; charset=utf-8".into()
+            )]
+        );
+
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "[1,2]");
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(
+            Response::builder()
+                .header("X A", "1")
+                .header("X-B", "\n")
+                .build()
+                .unwrap_err(),
+            ResponseBuilderError::InvalidHeaderName("X A".to_owned())
+        );
+        assert_eq!(
+            Response::builder()
+                .header("X-B", "a\rb")
+                .build()
+                .unwrap_err(),
+            ResponseBuilderError::InvalidHeaderValue("X-B".to_owned())
+        );
+        assert_eq!(
+            Response::builder().status(20).build().unwrap_err(),
+            ResponseBuilderError::InvalidStatusCode(20)
+        );
+        assert_eq!(
+            Response::builder()
+                .cookie(SetCookie::new("id", "a;b"))
+                .build()
+                .unwrap_err(),
+            ResponseBuilderError::InvalidCookie("id".to_owned())
+        );
+        assert!(Response::builder()
+            .cookie(SetCookie::new("id", "\"abc\"").path("/a b"))
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn cookie_attributes() {
+        use std::time::{Duration, UNIX_EPOCH};
+        use SameSite;
+
+        let cookie = SetCookie::new("a", "1")
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure()
+            .same_site(SameSite::None);
+        assert_eq!(
+            cookie.to_string(),
+            "a=1; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; SameSite=None"
+        );
+
+        let response = Response::text("").with_cookie(cookie);
+        assert!(response.headers.iter().any(|h| h.0 == "Set-Cookie"));
+
+        let not_secure = SetCookie::new("a", "1").same_site(SameSite::None);
+        assert_eq!(
+            not_secure.validate(),
+            Err(ResponseBuilderError::InvalidCookie("a".to_owned()))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_invalid_cookie() {
+        let _ = Response::text("").with_cookie(SetCookie::new("a", "\r\nX-Injected: 1"));
+    }
+
+    #[test]
+    fn cookies() {
+        let response = Response::builder()
+            .cookie(SetCookie::new("a", "1").path("/").http_only())
+            .cookie(SetCookie::removal("b").domain("example.com"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            response.headers,
+            vec![
+                ("Set-Cookie".into(), "a=1; Path=/; HttpOnly".into()),
+                (
+                    "Set-Cookie".into(),
+                    "b=; Max-Age=0; Domain=example.com".into()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,702 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Caching responses in memory.
+//!
+//! A [`ResponseCache`](struct.ResponseCache.html) stores the responses to `GET` and `HEAD`
+//! requests, and returns them to the following identical requests without calling the handler.
+//! This is useful for expensive routes whose result rarely changes, in deployments that don't
+//! have a caching proxy in front of the server.
+//!
+//! Responses are identified by the method, the `Host` header, the URL (including the query
+//! string), and the values of the request headers listed in the `Vary` header of the response. They are kept for the
+//! duration given to `ResponseCache::new`, unless their `Cache-Control` header has a `s-maxage`
+//! or `max-age` directive, and the least recently used responses are removed when the cache is
+//! full.
+//!
+//! A response is not stored if:
+//!
+//! - Its status code is not `200`.
+//! - Its `Cache-Control` header contains `no-store`, `no-cache` or `private`, or its `Vary`
+//!   header is `*`.
+//! - It has a `Set-Cookie` header.
+//! - The request has an `Authorization` or `Cookie` header, unless the `Cache-Control` header
+//!   of the response contains `public` or `s-maxage`.
+//! - It is streamed, or its body is larger than the maximum size of an entry.
+//!
+//! Requests with a `Cache-Control: no-cache` header skip the cache but their response can be
+//! stored, and requests with `Cache-Control: no-store` don't use the cache at all. Requests with
+//! an `Authorization` or `Cookie` header only receive the stored responses that contain `public`
+//! or `s-maxage`, so that the response to a user is never sent to another one.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::response_cache::ResponseCache;
+//!
+//! let cache = ResponseCache::new(Duration::from_secs(60)).max_entries(100);
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     router!(request,
+//!         (GET) (/report) => {
+//!             cache.cache(request, || Response::text("expensive report"))
+//!         },
+//!         _ => Response::empty_404()
+//!     )
+//! });
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+use std::io::Read;
+use std::mem;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use cache_control;
+use middleware::Middleware;
+use Request;
+use Response;
+use ResponseBody;
+
+/// Stores responses in memory. See [the module-level documentation](index.html).
+///
+/// It can be used either by calling [`cache`](#method.cache) from within a route, or as a
+/// [`Middleware`](../middleware/trait.Middleware.html).
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    max_size: usize,
+    max_entry_size: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // The responses for each method, host and URL, with one entry per combination of varying
+    // headers.
+    entries: HashMap<Key, Vec<Entry>>,
+    // Number of entries and total size of their bodies.
+    len: usize,
+    size: usize,
+    // Incremented on each access, to find the least recently used entry.
+    clock: u64,
+}
+
+// The method, the `Host` header and the raw URL of a request.
+type Key = (String, Option<String>, String);
+
+struct Entry {
+    // Whether the response can be sent to requests that have credentials.
+    shared: bool,
+    // Names of the headers listed in `Vary`, and their values in the request.
+    vary: Vec<(String, Option<String>)>,
+    status_code: u16,
+    headers: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    data: Vec<u8>,
+    no_compression: bool,
+    stored: Instant,
+    expires: Instant,
+    last_used: u64,
+}
+
+impl ResponseCache {
+    /// Builds a new empty cache that keeps responses during `ttl`.
+    ///
+    /// By default, the cache holds at most 1024 responses and 64 MiB of data, and doesn't store
+    /// bodies larger than 1 MiB.
+    pub fn new(ttl: Duration) -> ResponseCache {
+        ResponseCache {
+            ttl,
+            max_entries: 1024,
+            max_size: 64 * 1024 * 1024,
+            max_entry_size: 1024 * 1024,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Sets the maximum number of responses in the cache.
+    #[inline]
+    pub fn max_entries(mut self, max_entries: usize) -> ResponseCache {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Sets the maximum total size of the bodies in the cache, in bytes.
+    #[inline]
+    pub fn max_size(mut self, max_size: usize) -> ResponseCache {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the size in bytes above which a body is not stored.
+    #[inline]
+    pub fn max_entry_size(mut self, max_entry_size: usize) -> ResponseCache {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    /// Returns the stored response to `request` if there is one, otherwise calls `handler` and
+    /// stores its response if it can be cached.
+    pub fn cache<F>(&self, request: &Request, handler: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        let (lookup, store) = match request.method() {
+            "GET" | "HEAD" => {
+                let directives =
+                    cache_control::parse(request.header("Cache-Control").unwrap_or(""));
+                (
+                    !directives.no_store && !directives.no_cache,
+                    !directives.no_store,
+                )
+            }
+            _ => (false, false),
+        };
+
+        if lookup {
+            if let Some(response) = self.get(request) {
+                return response;
+            }
+        }
+
+        let response = handler();
+        if store {
+            self.store(request, response)
+        } else {
+            response
+        }
+    }
+
+    /// Returns the number of responses in the cache, including the expired ones that haven't
+    /// been removed yet.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().len
+    }
+
+    /// Returns true if the cache is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all the responses from the cache.
+    pub fn clear(&self) {
+        *self.state.lock().unwrap() = State::default();
+    }
+
+    fn get(&self, request: &Request) -> Option<Response> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+
+        let key = key_of(request);
+        let credentials = has_credentials(request);
+        let entry = state.entries.get_mut(&key)?.iter_mut().find(|entry| {
+            entry.expires > now && (entry.shared || !credentials) && entry.matches(request)
+        })?;
+        entry.last_used = clock;
+
+        let mut headers = entry.headers.clone();
+        let age = now.duration_since(entry.stored).as_secs();
+        headers.push(("Age".into(), age.to_string().into()));
+
+        let mut data = ResponseBody::from_data(entry.data.clone());
+        data.no_compression = entry.no_compression;
+        Some(Response {
+            status_code: entry.status_code,
+            headers,
+            data,
+            upgrade: None,
+        })
+    }
+
+    fn store(&self, request: &Request, mut response: Response) -> Response {
+        let (ttl, shared) = match self.ttl_of(&response) {
+            Some(ttl) => ttl,
+            None => return response,
+        };
+        if !shared && has_credentials(request) {
+            return response;
+        }
+
+        let vary = match vary_of(request, &response) {
+            Some(vary) => vary,
+            None => return response,
+        };
+
+        // Reads the body, but gives it back untouched if it's too large to be stored.
+        let no_compression = response.data.no_compression;
+        let (mut reader, length) =
+            mem::replace(&mut response.data, ResponseBody::empty()).into_reader_and_size();
+        if length.map_or(false, |length| length > self.max_entry_size) {
+            response.data = restore_body(Vec::new(), reader, length, no_compression);
+            return response;
+        }
+        let mut data = Vec::new();
+        let limit = self.max_entry_size as u64 + 1;
+        if reader.by_ref().take(limit).read_to_end(&mut data).is_err() {
+            return Response::text("Internal Server Error").with_status_code(500);
+        }
+        if data.len() > self.max_entry_size {
+            response.data = restore_body(data, reader, length, no_compression);
+            return response;
+        }
+
+        let now = Instant::now();
+        let entry = Entry {
+            shared,
+            vary,
+            status_code: response.status_code,
+            headers: response.headers.clone(),
+            data: data.clone(),
+            no_compression,
+            stored: now,
+            expires: now + ttl,
+            last_used: 0,
+        };
+        self.insert(request, entry, now);
+
+        response.data = ResponseBody::from_data(data);
+        response.data.no_compression = no_compression;
+        response
+    }
+
+    // Returns how long `response` can be stored and whether it can be sent to requests that have
+    // credentials, or `None` if it can't be stored.
+    fn ttl_of(&self, response: &Response) -> Option<(Duration, bool)> {
+        if response.status_code != 200 || response.data.unbuffered || response.upgrade.is_some() {
+            return None;
+        }
+
+        let mut directives = cache_control::CacheControl::new();
+        for (name, value) in &response.headers {
+            if name.eq_ignore_ascii_case("Set-Cookie") {
+                return None;
+            }
+            if name.eq_ignore_ascii_case("Cache-Control") {
+                directives = cache_control::parse(value);
+            }
+        }
+
+        if directives.no_store || directives.no_cache || directives.private {
+            return None;
+        }
+        let shared = directives.public || directives.s_maxage.is_some();
+        match directives.s_maxage.or(directives.max_age) {
+            Some(0) => None,
+            Some(seconds) => Some((Duration::from_secs(seconds), shared)),
+            None => Some((self.ttl, shared)),
+        }
+    }
+
+    fn insert(&self, request: &Request, mut entry: Entry, now: Instant) {
+        if entry.data.len() > self.max_size || self.max_entries == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        entry.last_used = state.clock;
+
+        // Replaces the previous response to the same request, if any.
+        let key = key_of(request);
+        let removed = match state.entries.get_mut(&key) {
+            Some(variants) => {
+                let before = variants.len();
+                let mut size = 0;
+                variants.retain(|variant| {
+                    let same = variant.vary == entry.vary;
+                    if same {
+                        size += variant.data.len();
+                    }
+                    !same
+                });
+                (before - variants.len(), size)
+            }
+            None => (0, 0),
+        };
+        state.len -= removed.0;
+        state.size -= removed.1;
+
+        state.remove_expired(now);
+        while state.len >= self.max_entries || state.size + entry.data.len() > self.max_size {
+            state.remove_least_recently_used();
+        }
+
+        state.len += 1;
+        state.size += entry.data.len();
+        state.entries.entry(key).or_default().push(entry);
+    }
+}
+
+impl Middleware for ResponseCache {
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        self.cache(request, || next(request))
+    }
+}
+
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .field("max_entries", &self.max_entries)
+            .field("max_size", &self.max_size)
+            .field("max_entry_size", &self.max_entry_size)
+            .finish()
+    }
+}
+
+impl State {
+    fn remove_expired(&mut self, now: Instant) {
+        let mut len = 0;
+        let mut size = 0;
+        for variants in self.entries.values_mut() {
+            variants.retain(|entry| {
+                let expired = entry.expires <= now;
+                if expired {
+                    len += 1;
+                    size += entry.data.len();
+                }
+                !expired
+            });
+        }
+        self.entries.retain(|_, variants| !variants.is_empty());
+        self.len -= len;
+        self.size -= size;
+    }
+
+    fn remove_least_recently_used(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .flat_map(|(key, variants)| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, entry)| (entry.last_used, key, index))
+            })
+            .min()
+            .map(|(_, key, index)| (key.clone(), index));
+
+        if let Some((key, index)) = oldest {
+            let variants = self.entries.get_mut(&key).unwrap();
+            let entry = variants.remove(index);
+            if variants.is_empty() {
+                self.entries.remove(&key);
+            }
+            self.len -= 1;
+            self.size -= entry.data.len();
+        }
+    }
+}
+
+impl Entry {
+    fn matches(&self, request: &Request) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request.header(name) == value.as_ref().map(|v| &v[..]))
+    }
+}
+
+fn key_of(request: &Request) -> Key {
+    (
+        request.method().to_owned(),
+        request.header("Host").map(str::to_ascii_lowercase),
+        request.raw_url().to_owned(),
+    )
+}
+
+// Returns true if the response to `request` may be specific to a user, in which case it can only
+// be stored if it is explicitly allowed in shared caches (RFC 9111, section 3.5).
+fn has_credentials(request: &Request) -> bool {
+    request.header("Authorization").is_some() || request.header("Cookie").is_some()
+}
+
+// Returns the headers of `request` that `response` varies on, or `None` if it varies on `*`.
+fn vary_of(request: &Request, response: &Response) -> Option<Vec<(String, Option<String>)>> {
+    let mut vary = Vec::new();
+    for (_, value) in response
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Vary"))
+    {
+        for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name == "*" {
+                return None;
+            }
+            let name = name.to_ascii_lowercase();
+            if vary.iter().all(|(n, _)| *n != name) {
+                let value = request.header(&name).map(str::to_owned);
+                vary.push((name, value));
+            }
+        }
+    }
+    vary.sort();
+    Some(vary)
+}
+
+// Rebuilds a body from the bytes that were already read from it and the rest of the reader.
+fn restore_body(
+    read: Vec<u8>,
+    rest: Box<dyn Read + Send>,
+    length: Option<usize>,
+    no_compression: bool,
+) -> ResponseBody {
+    let reader = Cursor::new(read).chain(rest);
+    let mut data = match length {
+        Some(length) => ResponseBody::from_reader_and_size(reader, length),
+        None => ResponseBody::from_reader(reader),
+    };
+    data.no_compression = no_compression;
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseCache;
+    use middleware::Pipeline;
+    use std::cell::Cell;
+    use std::time::Duration;
+    use test::TestResponse;
+    use Request;
+    use Response;
+    use ResponseBody;
+
+    fn get(url: &str, headers: Vec<(String, String)>) -> Request {
+        Request::fake_http("GET", url, headers, vec![])
+    }
+
+    #[test]
+    fn hit_skips_handler() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let calls = Cell::new(0);
+        let handler = || {
+            calls.set(calls.get() + 1);
+            Response::text(format!("call {}", calls.get()))
+        };
+
+        assert_eq!(
+            TestResponse::from(cache.cache(&get("/a", vec![]), handler)).text(),
+            "call 1"
+        );
+        let hit = cache.cache(&get("/a", vec![]), handler);
+        assert!(hit.headers.iter().any(|h| h.0 == "Age"));
+        assert_eq!(TestResponse::from(hit).text(), "call 1");
+        assert_eq!(
+            TestResponse::from(cache.cache(&get("/a?b", vec![]), handler)).text(),
+            "call 2"
+        );
+        assert_eq!(calls.get(), 2);
+        assert_eq!(cache.len(), 2);
+
+        let post = Request::fake_http("POST", "/a", vec![], vec![]);
+        assert_eq!(
+            TestResponse::from(cache.cache(&post, handler)).text(),
+            "call 3"
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn expiration() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let calls = Cell::new(0);
+        let handler = || {
+            calls.set(calls.get() + 1);
+            Response::text("hello").with_public_cache(0)
+        };
+
+        cache.cache(&get("/", vec![]), handler);
+        cache.cache(&get("/", vec![]), handler);
+        assert_eq!(calls.get(), 2);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn not_stored() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let request = get("/", vec![]);
+        cache.cache(&request, || Response::text("a").with_no_cache());
+        cache.cache(&request, || Response::text("a").with_private_cache(60));
+        cache.cache(&request, || Response::text("a").with_status_code(404));
+        cache.cache(&request, || {
+            Response::text("a").with_additional_header("Set-Cookie", "a=b")
+        });
+        cache.cache(&request, || Response::text("a").with_vary("*"));
+        assert!(cache.is_empty());
+
+        let no_store = get("/", vec![("Cache-Control".into(), "no-store".into())]);
+        cache.cache(&no_store, || Response::text("a"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn request_no_cache_refreshes() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let refresh = get("/", vec![("Cache-Control".into(), "no-cache".into())]);
+        cache.cache(&get("/", vec![]), || Response::text("old"));
+        assert_eq!(
+            TestResponse::from(cache.cache(&refresh, || Response::text("new"))).text(),
+            "new"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&get("/", vec![]), || unreachable!())).text(),
+            "new"
+        );
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn vary() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let handler = |request: &Request| {
+            let language = request.header("Accept-Language").unwrap_or("en").to_owned();
+            Response::text(language).with_vary("Accept-Language")
+        };
+        let en = get("/", vec![("Accept-Language".into(), "en".into())]);
+        let fr = get("/", vec![("Accept-Language".into(), "fr".into())]);
+
+        assert_eq!(
+            TestResponse::from(cache.cache(&en, || handler(&en))).text(),
+            "en"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&fr, || handler(&fr))).text(),
+            "fr"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&en, || unreachable!())).text(),
+            "en"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&fr, || unreachable!())).text(),
+            "fr"
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn host_in_key() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let a = get("/", vec![("Host".into(), "a.example".into())]);
+        let b = get("/", vec![("Host".into(), "b.example".into())]);
+        let a_upper = get("/", vec![("Host".into(), "A.example".into())]);
+
+        assert_eq!(
+            TestResponse::from(cache.cache(&a, || Response::text("a"))).text(),
+            "a"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&b, || Response::text("b"))).text(),
+            "b"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&a_upper, || unreachable!())).text(),
+            "a"
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn credentials() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let anonymous = get("/", vec![]);
+        let authorized = get("/", vec![("Authorization".into(), "Basic YTpi".into())]);
+        let cookie = get("/", vec![("Cookie".into(), "session=a".into())]);
+
+        // The responses to requests with credentials are not stored, and these requests don't
+        // receive the stored responses.
+        cache.cache(&authorized, || Response::text("authorized"));
+        cache.cache(&cookie, || Response::text("cookie"));
+        assert!(cache.is_empty());
+        cache.cache(&anonymous, || Response::text("anonymous"));
+        assert_eq!(
+            TestResponse::from(cache.cache(&cookie, || Response::text("cookie"))).text(),
+            "cookie"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&anonymous, || unreachable!())).text(),
+            "anonymous"
+        );
+
+        // Unless the response allows it explicitly.
+        cache.clear();
+        cache.cache(&authorized, || {
+            Response::text("public").with_public_cache(60)
+        });
+        assert_eq!(
+            TestResponse::from(cache.cache(&cookie, || unreachable!())).text(),
+            "public"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&anonymous, || unreachable!())).text(),
+            "public"
+        );
+
+        cache.clear();
+        cache.cache(&cookie, || {
+            Response::text("shared").with_additional_header("Cache-Control", "s-maxage=60")
+        });
+        assert_eq!(
+            TestResponse::from(cache.cache(&authorized, || unreachable!())).text(),
+            "shared"
+        );
+    }
+
+    #[test]
+    fn size_bounds() {
+        let cache = ResponseCache::new(Duration::from_secs(60))
+            .max_entries(2)
+            .max_entry_size(4);
+
+        let large = cache.cache(&get("/large", vec![]), || Response {
+            data: ResponseBody::from_reader(&b"too large"[..]),
+            ..Response::text("")
+        });
+        assert_eq!(TestResponse::from(large).text(), "too large");
+        assert!(cache.is_empty());
+
+        cache.cache(&get("/a", vec![]), || Response::text("a"));
+        cache.cache(&get("/b", vec![]), || Response::text("b"));
+        cache.cache(&get("/a", vec![]), || unreachable!());
+        cache.cache(&get("/c", vec![]), || Response::text("c"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            TestResponse::from(cache.cache(&get("/a", vec![]), || unreachable!())).text(),
+            "a"
+        );
+        assert_eq!(
+            TestResponse::from(cache.cache(&get("/b", vec![]), || Response::text("new"))).text(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn middleware() {
+        let pipeline = Pipeline::new(|request: &Request| Response::text(request.url()))
+            .with(ResponseCache::new(Duration::from_secs(60)));
+        assert_eq!(
+            TestResponse::from(pipeline.handle(&get("/a", vec![]))).text(),
+            "/a"
+        );
+        let hit = pipeline.handle(&get("/a", vec![]));
+        assert!(hit.headers.iter().any(|h| h.0 == "Age"));
+    }
+}
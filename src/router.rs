@@ -59,6 +59,76 @@
 /// },
 /// ```
 ///
+/// Custom types can be used as well, as long as they implement `FromStr`.
+///
+/// Ignoring the route is the default, rather than answering with a `400 Bad Request`, because the
+/// bodies of a `router!` don't have to return a `Response`, and because routers commonly rely on
+/// a failed parse to try the next route, for example `/{id: u32}` followed by `/{name: String}`.
+/// Instead of ignoring the route, you can respond with an error if the parsing fails by adding
+/// `| <status code>` after the type, usually `| 400`. The rest of the URL, the method and the
+/// guards of the route still have to match for the error to be returned, otherwise the next
+/// routes are tried. The body of the route must then be a `Response`:
+///
+/// ```ignore
+/// (GET) (/users/{id: u64 | 400}) => {
+///     // `GET /users/abc` returns an empty `400 Bad Request` response.
+///     ...
+/// },
+/// ```
+///
+/// In order to restrict the values that a parameter accepts beyond what its type allows, you can
+/// add `where` followed by a validator, which is a function or a closure that takes a reference
+/// to the parsed value and returns a `bool`. The route is ignored if the validator returns
+/// `false`:
+///
+/// ```ignore
+/// fn is_slug(s: &str) -> bool {
+///     !s.is_empty() && s.bytes().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-')
+/// }
+///
+/// (GET) (/posts/{slug: String where is_slug}) => {
+///     ...
+/// },
+/// ```
+///
+/// The last segment of the pattern can be written `{*name}` in order to capture the rest of the
+/// path, slashes included, as a percent-decoded `String`. Use `{*name: &str}` to get the raw
+/// part of the URL instead, or any other type to parse it through `FromStr`:
+///
+/// ```ignore
+/// (GET) (/files/{*path}) => {
+///     // `GET /files/docs/a%20b.txt` gives `path == "docs/a b.txt"`.
+///     ...
+/// },
+/// ```
+///
+///
+/// A route can have guards, written inside `[]` between the pattern and the `=>`. A guard is a
+/// value that implements [`Middleware`](middleware/trait.Middleware.html), and runs around the
+/// body of this route only, like in a [`Pipeline`](middleware/struct.Pipeline.html): the first
+/// guard sees the request first and the response last. A guard that returns a response from
+/// `before` prevents the body from running. The body of a route with guards must be a
+/// `Response`, and can't use `return` to return from the surrounding function:
+///
+/// ```ignore
+/// (GET) (/admin/users) [require_auth, log_slow] => {
+///     // Only runs if `require_auth` lets the request through.
+///     ...
+/// },
+/// ```
+///
+/// The limits of a route, such as the maximum size of the body or the accepted content types,
+/// can be declared as a guard with a [`policy::Policy`](policy/struct.Policy.html).
+///
+/// If none of the routes is a `HEAD` route, the `GET` routes also handle the `HEAD` requests, and
+/// the server sends their response without the body. See
+/// [`AutomaticHead`](struct.AutomaticHead.html) to disable this.
+///
+/// If no route matches the request, but some routes match its URL with another method, the
+/// macro stores these methods as an [`AllowedMethods`](struct.AllowedMethods.html) in the
+/// extensions of the request. Use `Response::empty_404_or_405(request)` as the default route in
+/// order to answer with a `405 Method Not Allowed` and the `Allow` header in that situation.
+///
 ///
 /// # Alternative syntax (**string-style**)
 ///
@@ -100,7 +170,7 @@ macro_rules! router {
     // --- New style ---
     // -----------------
     ($request:expr,
-     $(($method:ident) [$url_pattern:expr $(, $param:ident: $param_type:ty)*] => $handle:expr,)*
+     $(($method:ident) [$url_pattern:expr $(, $param:ident: $param_type:ty)*] $([$($guard:expr),+ $(,)*])? => $handle:expr,)*
      _ => $default:expr $(,)*) => {
         {
             let request = &$request;
@@ -112,21 +182,53 @@ macro_rules! router {
                 &request_url[..pos]
             };
 
+            let automatic_head = $crate::AutomaticHead::enabled(
+                request, false $(|| stringify!($method) == "HEAD")*);
+            let head_as_get = automatic_head && request.method() == "HEAD";
+
             let mut ret = None;
+            let mut allowed = Vec::new();
             $({
-                if ret.is_none() && request.method() == stringify!($method) {
-                    ret = router!(__param_dispatch request_url, $url_pattern => $handle ; $($param: $param_type),*);
+                if ret.is_none() {
+                    // The URL is checked even if the method doesn't match, in order to know the
+                    // methods that are allowed for this URL.
+                    #[allow(clippy::diverging_sub_expression)]
+                    let route = router!(__param_dispatch request_url, $url_pattern => {
+                        if request.method() == stringify!($method)
+                            || (head_as_get && stringify!($method) == "GET")
+                        {
+                            let value = router!(__guarded request $handle $(; [$($guard),+])?);
+                            Ok(value)
+                        } else {
+                            Err(stringify!($method))
+                        }
+                    } ; $($param: $param_type),*);
+                    match route {
+                        Some(Ok(value)) => ret = Some(value),
+                        Some(Err(method)) => allowed.push(method),
+                        None => (),
+                    }
                 }
             })+
 
             if let Some(ret) = ret {
                 ret
             } else {
+                $crate::AllowedMethods::record(request, allowed, automatic_head);
                 $default
             }
         }
     };
 
+    // Evaluates the body of a route, through its guards if it has any.
+    (__guarded $request:ident $value:expr) => {
+        $value
+    };
+
+    (__guarded $request:ident $value:expr ; [$($guard:expr),+]) => {
+        $crate::middleware::guarded($request, &[$(&$guard as &dyn $crate::middleware::Middleware),+], || $value)
+    };
+
     // No url parameters, just check the url and evaluate the `$handle`
     (__param_dispatch $request_url:ident, $url_pattern:expr => $handle:expr ; ) => {
         router!(__check_url_match $request_url, $url_pattern => $handle)
@@ -247,7 +349,7 @@ macro_rules! router {
     // -----------------
     // --- Old style ---
     // -----------------
-    ($request:expr, $(($method:ident) ($($pat:tt)+) => $value:block,)* _ => $def:expr $(,)*) => {
+    ($request:expr, $(($method:ident) ($($pat:tt)+) $([$($guard:expr),+ $(,)*])? => $value:block,)* _ => $def:expr $(,)*) => {
         {
             let request = &$request;
 
@@ -258,29 +360,65 @@ macro_rules! router {
                 &request_url[..pos]
             };
 
+            let automatic_head = $crate::AutomaticHead::enabled(
+                request, false $(|| stringify!($method) == "HEAD")*);
+            let head_as_get = automatic_head && request.method() == "HEAD";
+
             let mut ret = None;
+            let mut allowed = Vec::new();
 
             $({
-                if ret.is_none() && request.method() == stringify!($method) {
-                    ret = router!(__check_pattern request_url $value $($pat)+);
+                if ret.is_none() {
+                    // The URL is checked even if the method doesn't match, in order to know the
+                    // methods that are allowed for this URL.
+                    // Answer of the route when a parameter declared with `| <status code>`
+                    // fails to parse. The method and the guards must pass as well.
+                    #[allow(unused_variables)]
+                    let error = |status: u16| -> Result<$crate::Response, &'static str> {
+                        if request.method() == stringify!($method)
+                            || (head_as_get && stringify!($method) == "GET")
+                        {
+                            let response = $crate::Response::empty_400().with_status_code(status);
+                            Ok(router!(__guarded request response $(; [$($guard),+])?))
+                        } else {
+                            Err(stringify!($method))
+                        }
+                    };
+                    #[allow(clippy::diverging_sub_expression)]
+                    let route = router!(__check_pattern request_url error {
+                        if request.method() == stringify!($method)
+                            || (head_as_get && stringify!($method) == "GET")
+                        {
+                            let value = router!(__guarded request $value $(; [$($guard),+])?);
+                            Ok(value)
+                        } else {
+                            Err(stringify!($method))
+                        }
+                    } $($pat)+);
+                    match route {
+                        Some(Ok(value)) => ret = Some(value),
+                        Some(Err(method)) => allowed.push(method),
+                        None => (),
+                    }
                 }
             })+
 
             if let Some(ret) = ret {
                 ret
             } else {
+                $crate::AllowedMethods::record(request, allowed, automatic_head);
                 $def
             }
         }
     };
 
-    (__check_pattern $url:ident $value:block /{$p:ident} $($rest:tt)*) => (
+    (__check_pattern $url:ident $error:ident $value:block /{$p:ident} $($rest:tt)*) => (
         if let Some(url) = $url.strip_prefix('/') {
             let pat_end = url.find('/').unwrap_or(url.len());
             let rest_url = &url[pat_end..];
 
             if let Ok($p) = url[0 .. pat_end].parse() {
-                router!(__check_pattern rest_url $value $($rest)*)
+                router!(__check_pattern rest_url $error $value $($rest)*)
             } else {
                 None
             }
@@ -289,7 +427,80 @@ macro_rules! router {
         }
     );
 
-    (__check_pattern $url:ident $value:block /{$p:ident: $t:ty} $($rest:tt)*) => (
+    // Rest-of-path segments, which must be the last element of the pattern.
+    (__check_pattern $url:ident $error:ident $value:block /{*$p:ident}) => (
+        if let Some(url) = $url.strip_prefix('/') {
+            let $p = $crate::percent_encoding::percent_decode(url.as_bytes())
+                .decode_utf8_lossy()
+                .into_owned();
+            Some($value)
+        } else {
+            None
+        }
+    );
+
+    (__check_pattern $url:ident $error:ident $value:block /{*$p:ident: &str}) => (
+        if let Some(url) = $url.strip_prefix('/') {
+            let $p: &str = url;
+            Some($value)
+        } else {
+            None
+        }
+    );
+
+    (__check_pattern $url:ident $error:ident $value:block /{*$p:ident: $t:ty}) => (
+        if let Some(url) = $url.strip_prefix('/') {
+            match $crate::percent_encoding::percent_decode(url.as_bytes())
+                .decode_utf8_lossy().parse::<$t>() {
+                Ok($p) => Some($value),
+                Err(_) => None,
+            }
+        } else {
+            None
+        }
+    );
+
+    // Same as the typed parameter below, but the route is also skipped if the validator returns
+    // false for the parsed value.
+    (__check_pattern $url:ident $error:ident $value:block /{$p:ident: $t:ty where $validator:expr} $($rest:tt)*) => (
+        if let Some(url) = $url.strip_prefix('/') {
+            let pat_end = url.find('/').unwrap_or(url.len());
+            let rest_url = &url[pat_end..];
+
+            match $crate::percent_encoding::percent_decode(url[0 .. pat_end].as_bytes())
+                .decode_utf8_lossy().parse::<$t>() {
+                Ok($p) => if ($validator)(&$p) {
+                    router!(__check_pattern rest_url $error $value $($rest)*)
+                } else {
+                    None
+                },
+                Err(_) => None,
+            }
+        } else {
+            None
+        }
+    );
+
+    // Same as below, but returns a response with the given status code if the parameter fails to
+    // parse while the rest of the URL matches.
+    (__check_pattern $url:ident $error:ident $value:block /{$p:ident: $t:ty | $status:expr} $($rest:tt)*) => (
+        if let Some(url) = $url.strip_prefix('/') {
+            let pat_end = url.find('/').unwrap_or(url.len());
+            let rest_url = &url[pat_end..];
+
+            match $crate::percent_encoding::percent_decode(url[0 .. pat_end].as_bytes())
+                .decode_utf8_lossy().parse::<$t>() {
+                Ok($p) => router!(__check_pattern rest_url $error $value $($rest)*),
+                Err(_) => router!(__check_pattern rest_url $error {
+                    $error($status)
+                } $($rest)*),
+            }
+        } else {
+            None
+        }
+    );
+
+    (__check_pattern $url:ident $error:ident $value:block /{$p:ident: $t:ty} $($rest:tt)*) => (
         if let Some(url) = $url.strip_prefix('/') {
             let pat_end = url.find('/').unwrap_or(url.len());
             let rest_url = &url[pat_end..];
@@ -297,7 +508,7 @@ macro_rules! router {
             if let Ok($p) = $crate::percent_encoding::percent_decode(url[0 .. pat_end].as_bytes())
                 .decode_utf8_lossy().parse() {
                 let $p: $t = $p;
-                router!(__check_pattern rest_url $value $($rest)*)
+                router!(__check_pattern rest_url $error $value $($rest)*)
             } else {
                 None
             }
@@ -306,40 +517,40 @@ macro_rules! router {
         }
     );
 
-    (__check_pattern $url:ident $value:block /$p:ident $($rest:tt)*) => (
+    (__check_pattern $url:ident $error:ident $value:block /$p:ident $($rest:tt)*) => (
         {
             let required = concat!("/", stringify!($p));
             if let Some(rest_url) = $url.strip_prefix(required) {
-                router!(__check_pattern rest_url $value $($rest)*)
+                router!(__check_pattern rest_url $error $value $($rest)*)
             } else {
                 None
             }
         }
     );
 
-    (__check_pattern $url:ident $value:block - $($rest:tt)*) => (
+    (__check_pattern $url:ident $error:ident $value:block - $($rest:tt)*) => (
         {
             if let Some(rest_url) = $url.strip_prefix('-') {
-                router!(__check_pattern rest_url $value $($rest)*)
+                router!(__check_pattern rest_url $error $value $($rest)*)
             } else {
                 None
             }
         }
     );
 
-    (__check_pattern $url:ident $value:block) => (
+    (__check_pattern $url:ident $error:ident $value:block) => (
         if $url.len() == 0 { Some($value) } else { None }
     );
 
-    (__check_pattern $url:ident $value:block /) => (
+    (__check_pattern $url:ident $error:ident $value:block /) => (
         if $url == "/" { Some($value) } else { None }
     );
 
-    (__check_pattern $url:ident $value:block $p:ident $($rest:tt)*) => (
+    (__check_pattern $url:ident $error:ident $value:block $p:ident $($rest:tt)*) => (
         {
             let required = stringify!($p);
             if let Some(rest_url) = $url.strip_prefix(required) {
-                router!(__check_pattern rest_url $value $($rest)*)
+                router!(__check_pattern rest_url $error $value $($rest)*)
             } else {
                 None
             }
@@ -347,6 +558,98 @@ macro_rules! router {
     );
 }
 
+use Request;
+
+/// The methods allowed for the URL of a request, as found by the `router!` macro.
+///
+/// When none of its routes matches a request, but some of them would have matched with another
+/// method, `router!` stores the methods of these routes in the
+/// [extensions](struct.Request.html#method.extensions) of the request before evaluating the
+/// default expression. Use
+/// [`Response::empty_404_or_405`](struct.Response.html#method.empty_404_or_405) as the default
+/// expression to answer with a `405 Method Not Allowed` in that situation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedMethods(pub Vec<&'static str>);
+
+impl AllowedMethods {
+    // Called by `router!` when none of its routes match.
+    #[doc(hidden)]
+    pub fn record(request: &Request, methods: Vec<&'static str>, automatic_head: bool) {
+        let mut unique = Vec::with_capacity(methods.len() + 1);
+        for method in methods {
+            if !unique.contains(&method) {
+                unique.push(method);
+                if method == "GET" && automatic_head {
+                    unique.push("HEAD");
+                }
+            }
+        }
+
+        let mut extensions = request.extensions();
+        if unique.is_empty() {
+            extensions.remove::<AllowedMethods>();
+        } else {
+            extensions.insert(AllowedMethods(unique));
+        }
+    }
+}
+
+/// The methods that the server implements in addition to the standard ones, such as `PURGE`
+/// or `PROPFIND`.
+///
+/// `router!` accepts routes with any method, for example `(PURGE) (/cache/{key: String})`.
+/// However [`Response::empty_404_or_405`](struct.Response.html#method.empty_404_or_405) answers
+/// `501 Not Implemented` to the requests whose method is neither standard nor one of these
+/// methods, and [`Server::reject_unknown_methods`](struct.Server.html#method.reject_unknown_methods)
+/// answers them before calling the handler.
+///
+/// Calling [`Server::custom_methods`](struct.Server.html#method.custom_methods) inserts a
+/// `CustomMethods` in the [extensions](struct.Request.html#method.extensions) of each request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CustomMethods(pub Vec<String>);
+
+// The methods of RFC 7231 and RFC 5789.
+const STANDARD_METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+impl CustomMethods {
+    /// Returns true if the method of the request is a standard method, or one of the
+    /// `CustomMethods` in the extensions of the request.
+    pub fn is_known(request: &Request) -> bool {
+        let method = request.method();
+        STANDARD_METHODS.contains(&method)
+            || request
+                .extensions()
+                .get::<CustomMethods>()
+                .map_or(false, |custom| custom.0.iter().any(|m| m == method))
+    }
+}
+
+/// Controls whether `router!` answers `HEAD` requests with its `GET` routes.
+///
+/// By default, if none of the routes of a `router!` is a `HEAD` route, a `HEAD` request is
+/// handled by the `GET` route that matches its URL. The server then sends the headers of the
+/// response, including `Content-Length`, but not its body.
+///
+/// Inserting `AutomaticHead(false)` in the [extensions](struct.Request.html#method.extensions)
+/// of a request, or calling
+/// [`Server::automatic_head(false)`](struct.Server.html#method.automatic_head), disables this.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AutomaticHead(pub bool);
+
+impl AutomaticHead {
+    // Called by `router!` to know whether its `GET` routes also handle `HEAD` requests.
+    #[doc(hidden)]
+    pub fn enabled(request: &Request, router_has_head: bool) -> bool {
+        !router_has_head
+            && request
+                .extensions()
+                .get::<AutomaticHead>()
+                .map_or(true, |automatic| automatic.0)
+    }
+}
+
 #[allow(unused_variables)]
 #[cfg(test)]
 mod tests {
@@ -428,6 +731,305 @@ mod tests {
         );
     }
 
+    #[test]
+    fn old_style_param_error() {
+        use std::str::FromStr;
+        use Response;
+
+        #[derive(Debug)]
+        struct Even(u32);
+        impl FromStr for Even {
+            type Err = ();
+            fn from_str(s: &str) -> Result<Even, ()> {
+                match s.parse::<u32>() {
+                    Ok(n) if n % 2 == 0 => Ok(Even(n)),
+                    _ => Err(()),
+                }
+            }
+        }
+
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) (/users/{id: u64 | 400}) => { Response::text(id.to_string()) },
+                (GET) (/even/{n: Even | 422}/{m: Even | 400}) => {
+                    Response::text((n.0 + m.0).to_string())
+                },
+                (GET) (/even/{n: Even}) => { Response::text(n.0.to_string()) },
+                _ => Response::empty_404()
+            )
+        };
+
+        assert_eq!(route("/users/5").status_code, 200);
+        assert_eq!(route("/users/abc").status_code, 400);
+        assert_eq!(route("/users/abc/posts").status_code, 404);
+        assert_eq!(route("/even/2/4").status_code, 200);
+        assert_eq!(route("/even/3/4").status_code, 422);
+        assert_eq!(route("/even/2/5").status_code, 400);
+        assert_eq!(route("/even/2").status_code, 200);
+        assert_eq!(route("/even/3").status_code, 404);
+    }
+
+    #[test]
+    #[allow(irrefutable_let_patterns)]
+    fn old_style_param_error_other_method() {
+        use Response;
+
+        let route = |method: &str| {
+            let request = Request::fake_http(method, "/users/abc", vec![], vec![]);
+            router!(request,
+                (GET) (/users/{id: u64 | 400}) => { Response::text(id.to_string()) },
+                (DELETE) (/users/{name: String}) => { Response::text(name) },
+                _ => Response::empty_404_or_405(&request)
+            )
+        };
+
+        assert_eq!(route("GET").status_code, 400);
+        assert_eq!(route("DELETE").status_code, 200);
+        assert_eq!(route("POST").status_code, 405);
+    }
+
+    #[test]
+    fn old_style_rest_of_path() {
+        use std::path::PathBuf;
+
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) (/files/{*path}) => { format!("decoded {}", path) },
+                (GET) (/raw/{*path: &str}) => { format!("raw {}", path) },
+                (GET) (/id/{id: u32}/{*path: PathBuf}) => {
+                    format!("{} {}", id, path.components().count())
+                },
+                _ => String::from("none")
+            )
+        };
+
+        assert_eq!(route("/files/docs/a%20b.txt?x=1"), "decoded docs/a b.txt");
+        assert_eq!(route("/files/"), "decoded ");
+        assert_eq!(route("/files"), "none");
+        assert_eq!(route("/raw/docs/a%20b.txt"), "raw docs/a%20b.txt");
+        assert_eq!(route("/id/5/a/b/c"), "5 3");
+        assert_eq!(route("/id/x/a/b/c"), "none");
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        use super::AllowedMethods;
+        use Response;
+
+        let route = |method: &str, url: &str| {
+            let request = Request::fake_http(method, url, vec![], vec![]);
+            let response = router!(request,
+                (GET) (/users/{id: u32}) => { Response::text(id.to_string()) },
+                (DELETE) (/users/{_id: u32}) => { Response::empty_204() },
+                (GET) (/users/{_id: u32}/{_other: u32}) => { Response::text("other") },
+                (PUT) (/users/{_id: u32}) => { Response::empty_204() },
+                (DELETE) (/users/{_id: u32}) => { Response::empty_204() },
+                _ => Response::empty_404_or_405(&request)
+            );
+            let allowed = request.extensions().get::<AllowedMethods>().cloned();
+            (response, allowed)
+        };
+
+        let (response, allowed) = route("POST", "/users/5");
+        assert_eq!(response.status_code, 405);
+        assert_eq!(
+            response.headers.iter().find(|h| h.0 == "Allow").unwrap().1,
+            "GET, HEAD, DELETE, PUT"
+        );
+        assert_eq!(
+            allowed,
+            Some(AllowedMethods(vec!["GET", "HEAD", "DELETE", "PUT"]))
+        );
+
+        assert_eq!(route("GET", "/users/5").0.status_code, 200);
+        assert_eq!(route("POST", "/users/abc").0.status_code, 404);
+        assert_eq!(route("POST", "/users/abc").1, None);
+
+        let request = Request::fake_http("POST", "/a", vec![], vec![]);
+        let methods = router!(request,
+            (GET) ["/a"] => { vec![] },
+            (HEAD) ["/{x}", x: String] => { vec![if x == "a" { "HEAD" } else { "" }] },
+            _ => request.extensions().get::<AllowedMethods>().unwrap().0.clone()
+        );
+        assert_eq!(methods, vec!["GET", "HEAD"]);
+    }
+
+    #[test]
+    fn automatic_head() {
+        use super::AutomaticHead;
+        use Response;
+
+        let route = |request: &Request| {
+            router!(request,
+                (GET) (/a) => { Response::text("a") },
+                (POST) (/b) => { Response::text("b") },
+                _ => Response::empty_404_or_405(request)
+            )
+        };
+
+        let request = Request::fake_http("HEAD", "/a", vec![], vec![]);
+        assert_eq!(route(&request).status_code, 200);
+        let request = Request::fake_http("HEAD", "/b", vec![], vec![]);
+        assert_eq!(route(&request).status_code, 405);
+
+        let request = Request::fake_http("HEAD", "/a", vec![], vec![]);
+        request.extensions().insert(AutomaticHead(false));
+        assert_eq!(route(&request).status_code, 405);
+
+        // A router with an explicit `HEAD` route doesn't use its `GET` routes for `HEAD`.
+        let request = Request::fake_http("HEAD", "/a", vec![], vec![]);
+        let response = router!(request,
+            (GET) ["/a"] => Response::text("a"),
+            (HEAD) ["/b"] => Response::text("b"),
+            _ => Response::empty_404()
+        );
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn custom_methods() {
+        use super::CustomMethods;
+        use Response;
+
+        let route = |method: &str, url: &str, custom: bool| {
+            let request = Request::fake_http(method, url, vec![], vec![]);
+            if custom {
+                request
+                    .extensions()
+                    .insert(CustomMethods(vec!["PURGE".to_owned()]));
+            }
+            router!(request,
+                (GET) (/a) => { Response::text("a") },
+                (PURGE) (/a) => { Response::text("purged") },
+                _ => Response::empty_404_or_405(&request)
+            )
+            .status_code
+        };
+
+        assert_eq!(route("PURGE", "/a", false), 200);
+        assert_eq!(route("PURGE", "/b", true), 404);
+        assert_eq!(route("PURGE", "/b", false), 501);
+        assert_eq!(route("PROPFIND", "/a", true), 501);
+        assert_eq!(route("POST", "/a", false), 405);
+    }
+
+    #[test]
+    fn automatic_options() {
+        use Response;
+
+        let route = |request: &Request| {
+            router!(request,
+                (GET) (/a) => { Response::text("a") },
+                (POST) (/a) => { Response::text("a") },
+                _ => Response::empty_404_or_405_with(request, |_, allowed| {
+                    Response::options(&allowed.0).with_unique_header("X-Preflight", "yes")
+                })
+            )
+        };
+
+        let request = Request::fake_http("OPTIONS", "/a", vec![], vec![]);
+        let response = route(&request);
+        assert_eq!(response.status_code, 204);
+        assert_eq!(
+            response.headers.iter().find(|h| h.0 == "Allow").unwrap().1,
+            "GET, HEAD, POST, OPTIONS"
+        );
+        assert!(response.headers.iter().any(|h| h.0 == "X-Preflight"));
+
+        let request = Request::fake_http("OPTIONS", "/b", vec![], vec![]);
+        assert_eq!(route(&request).status_code, 404);
+    }
+
+    #[test]
+    fn old_style_validator() {
+        fn is_slug(s: &str) -> bool {
+            !s.is_empty()
+                && s.bytes()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-')
+        }
+
+        let route = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            router!(request,
+                (GET) (/posts/{id: u32 where |id: &u32| *id > 0}) => { format!("id {}", id) },
+                (GET) (/posts/{slug: String where is_slug}/edit) => { format!("edit {}", slug) },
+                (GET) (/posts/{slug: String where is_slug}) => { format!("slug {}", slug) },
+                _ => String::from("none")
+            )
+        };
+
+        assert_eq!(route("/posts/12"), "id 12");
+        assert_eq!(route("/posts/0"), "slug 0");
+        assert_eq!(route("/posts/hello-world"), "slug hello-world");
+        assert_eq!(route("/posts/hello-world/edit"), "edit hello-world");
+        assert_eq!(route("/posts/Hello%20World"), "none");
+    }
+
+    #[test]
+    fn guards() {
+        use middleware::Middleware;
+        use Response;
+
+        struct RequireAuth;
+        impl Middleware for RequireAuth {
+            fn before(&self, request: &Request) -> Option<Response> {
+                if request.header("Authorization").is_some() {
+                    None
+                } else {
+                    Some(Response::empty_404().with_status_code(401))
+                }
+            }
+        }
+
+        struct Tag;
+        impl Middleware for Tag {
+            fn after(&self, _: &Request, response: Response) -> Response {
+                response.with_additional_header("X-Tag", "tagged")
+            }
+        }
+
+        let route = |url: &str, authorized: bool| {
+            let headers = if authorized {
+                vec![("Authorization".to_owned(), "yes".to_owned())]
+            } else {
+                vec![]
+            };
+            let request = Request::fake_http("GET", url, headers, vec![]);
+            router!(request,
+                (GET) (/admin/{id: u32}) [Tag, RequireAuth] => {
+                    Response::text(format!("admin {}", id))
+                },
+                (GET) (/public) [Tag] => { Response::text("public") },
+                (GET) (/users/{id: u32 | 400}) [RequireAuth] => {
+                    Response::text(id.to_string())
+                },
+                (GET) (/) => { Response::text("index") },
+                _ => Response::empty_404()
+            )
+        };
+
+        let response = route("/admin/3", false);
+        assert_eq!(response.status_code, 401);
+        assert!(response.headers.iter().any(|h| h.0 == "X-Tag"));
+        assert_eq!(route("/admin/3", true).status_code, 200);
+        assert!(route("/public", false)
+            .headers
+            .iter()
+            .any(|h| h.0 == "X-Tag"));
+        assert!(!route("/", false).headers.iter().any(|h| h.0 == "X-Tag"));
+        assert_eq!(route("/users/abc", false).status_code, 401);
+        assert_eq!(route("/users/abc", true).status_code, 400);
+
+        let request = Request::fake_http("GET", "/admin", vec![], vec![]);
+        let response = router!(request,
+            (GET) ["/admin"] [RequireAuth] => Response::text("admin"),
+            _ => Response::empty_404()
+        );
+        assert_eq!(response.status_code, 401);
+    }
+
     // -- new-style tests --
     #[test]
     fn multiple_params() {
@@ -0,0 +1,601 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Filtering requests with a list of rules before they reach the handler.
+//!
+//! A [`Rules`](struct.Rules.html) middleware evaluates each request against a list of
+//! [`Rule`](struct.Rule.html)s. A rule matches on the method, the path, the headers, the size of
+//! the body and the address of the client, and has an [`Action`](enum.Action.html): block the
+//! request, answer it slowly, rewrite its URL, or tag it for the handler and the logs.
+//!
+//! The rules can be written in code, or loaded from a JSON file that is reloaded when it
+//! changes:
+//!
+//! ```json
+//! [
+//!     { "path": "/wp-admin*", "action": "block" },
+//!     { "headers": [{ "name": "User-Agent", "pattern": "*sqlmap*" }], "action": { "tarpit": 5000 } },
+//!     { "method": "POST", "body_larger_than": 1048576, "client": "10.0.0.0/8", "action": { "tag": "upload" } },
+//!     { "path": "/old/*", "action": { "rewrite": "/new" } }
+//! ]
+//! ```
+//!
+//! The patterns of the paths and of the header values are case-sensitive glob patterns, where
+//! `*` matches any sequence of characters, including `/`, and `?` matches one character.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rouille::{Request, Response};
+//! use rouille::middleware::Pipeline;
+//! use rouille::rules::{self, Rules};
+//!
+//! let rules = Rules::load("rules.json").unwrap().watch(Duration::from_secs(5));
+//!
+//! let pipeline = Pipeline::new(|request: &Request| {
+//!     if rules::tags(request).iter().any(|t| t == "upload") {
+//!         // ...
+//!     }
+//!     Response::text("hello world")
+//! })
+//! .with(rules);
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| pipeline.handle(request));
+//! ```
+
+use serde::de;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_json;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use ban::IpNetwork;
+use middleware::Middleware;
+use Request;
+use Response;
+
+/// What to do with a request that matches a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Answers `403 Forbidden`.
+    Block,
+    /// Waits for the given number of milliseconds, then answers `403 Forbidden`. This slows
+    /// down scanners, but keeps a thread of the server busy.
+    Tarpit(u64),
+    /// Replaces the URL of the request, including its query string if the new URL has one.
+    Rewrite(String),
+    /// Adds a tag to the request, which the handler retrieves with [`tags`](fn.tags.html).
+    Tag(String),
+}
+
+/// A rule that matches requests. A request matches if it satisfies all the conditions of the
+/// rule. A rule without conditions matches all the requests.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    headers: Vec<HeaderRule>,
+    #[serde(default)]
+    body_larger_than: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_network")]
+    client: Option<IpNetwork>,
+    action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct HeaderRule {
+    name: String,
+    pattern: String,
+}
+
+fn deserialize_network<'de, D>(deserializer: D) -> Result<Option<IpNetwork>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let network = String::deserialize(deserializer)?;
+    network
+        .parse()
+        .map(Some)
+        .map_err(|_| de::Error::custom(format!("invalid network `{}`", network)))
+}
+
+impl Rule {
+    /// Builds a rule that matches all the requests.
+    pub fn new(action: Action) -> Rule {
+        Rule {
+            method: None,
+            path: None,
+            headers: Vec::new(),
+            body_larger_than: None,
+            client: None,
+            action,
+        }
+    }
+
+    /// Only matches the requests with this method.
+    #[inline]
+    pub fn method<M>(mut self, method: M) -> Rule
+    where
+        M: Into<String>,
+    {
+        self.method = Some(method.into());
+        self
+    }
+
+    /// Only matches the requests whose path matches this pattern.
+    #[inline]
+    pub fn path<P>(mut self, pattern: P) -> Rule
+    where
+        P: Into<String>,
+    {
+        self.path = Some(pattern.into());
+        self
+    }
+
+    /// Only matches the requests that have a header named `name` whose value matches `pattern`.
+    #[inline]
+    pub fn header<N, P>(mut self, name: N, pattern: P) -> Rule
+    where
+        N: Into<String>,
+        P: Into<String>,
+    {
+        self.headers.push(HeaderRule {
+            name: name.into(),
+            pattern: pattern.into(),
+        });
+        self
+    }
+
+    /// Only matches the requests whose `Content-Length` is larger than `size`.
+    #[inline]
+    pub fn body_larger_than(mut self, size: u64) -> Rule {
+        self.body_larger_than = Some(size);
+        self
+    }
+
+    /// Only matches the requests whose client address is in this network.
+    #[inline]
+    pub fn client(mut self, network: IpNetwork) -> Rule {
+        self.client = Some(network);
+        self
+    }
+
+    /// Returns true if the request matches the rule.
+    pub fn matches(&self, request: &Request) -> bool {
+        if let Some(ref method) = self.method {
+            if !request.method().eq_ignore_ascii_case(method) {
+                return false;
+            }
+        }
+
+        if let Some(ref pattern) = self.path {
+            let url = request.raw_url();
+            let path = url.split('?').next().unwrap_or(url);
+            if !glob_match(pattern.as_bytes(), path.as_bytes()) {
+                return false;
+            }
+        }
+
+        for header in &self.headers {
+            match request.header(&header.name) {
+                Some(value) if glob_match(header.pattern.as_bytes(), value.as_bytes()) => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(size) = self.body_larger_than {
+            let length = request
+                .header("Content-Length")
+                .and_then(|l| l.trim().parse::<u64>().ok())
+                .unwrap_or(0);
+            if length <= size {
+                return false;
+            }
+        }
+
+        if let Some(network) = self.client {
+            if !network.contains(request.remote_addr().ip()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// The tags added by the rules that matched a request. See [`tags`](fn.tags.html).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleTags(pub Vec<String>);
+
+/// Returns the tags added to the request by `Action::Tag` rules.
+pub fn tags(request: &Request) -> Vec<String> {
+    request
+        .extensions()
+        .get::<RuleTags>()
+        .map(|t| t.0.clone())
+        .unwrap_or_default()
+}
+
+/// Error that can happen when loading rules.
+#[derive(Debug)]
+pub enum RulesError {
+    /// The file couldn't be read.
+    IoError(io::Error),
+    /// The rules aren't valid JSON, or don't have the expected format.
+    ParseError(serde_json::Error),
+}
+
+impl From<io::Error> for RulesError {
+    #[inline]
+    fn from(err: io::Error) -> RulesError {
+        RulesError::IoError(err)
+    }
+}
+
+impl From<serde_json::Error> for RulesError {
+    #[inline]
+    fn from(err: serde_json::Error) -> RulesError {
+        RulesError::ParseError(err)
+    }
+}
+
+impl error::Error for RulesError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            RulesError::IoError(ref e) => Some(e),
+            RulesError::ParseError(ref e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for RulesError {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let description = match *self {
+            RulesError::IoError(_) => "could not read the rules",
+            RulesError::ParseError(_) => "could not parse the rules",
+        };
+
+        write!(fmt, "{}", description)
+    }
+}
+
+/// A list of rules, evaluated in order for each request.
+///
+/// The `Tag` rules add their tag and the evaluation continues. The first `Rewrite` rule that
+/// matches rewrites the URL, and the evaluation continues with the original URL. The first
+/// `Block` or `Tarpit` rule that matches answers the request.
+pub struct Rules {
+    rules: RwLock<Arc<Vec<Rule>>>,
+    source: Option<Source>,
+}
+
+// The file that the rules were loaded from.
+struct Source {
+    path: PathBuf,
+    watch: Option<Duration>,
+    // When the file was last checked, and its modification time then.
+    checked: Mutex<(Instant, Option<SystemTime>)>,
+}
+
+impl Rules {
+    /// Builds a list of rules.
+    pub fn new(rules: Vec<Rule>) -> Rules {
+        Rules {
+            rules: RwLock::new(Arc::new(rules)),
+            source: None,
+        }
+    }
+
+    /// Parses a JSON list of rules.
+    pub fn from_json(json: &str) -> Result<Rules, RulesError> {
+        Ok(Rules::new(serde_json::from_str(json)?))
+    }
+
+    /// Loads a JSON list of rules from a file. Call [`reload`](#method.reload) or
+    /// [`watch`](#method.watch) to pick up the modifications of the file.
+    pub fn load<P>(path: P) -> Result<Rules, RulesError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_owned();
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        let rules = serde_json::from_str(&fs::read_to_string(&path)?)?;
+
+        Ok(Rules {
+            rules: RwLock::new(Arc::new(rules)),
+            source: Some(Source {
+                path,
+                watch: None,
+                checked: Mutex::new((Instant::now(), modified)),
+            }),
+        })
+    }
+
+    /// Checks whether the file that the rules were loaded from has been modified at most once
+    /// per `interval`, while processing a request, and reloads it if so.
+    ///
+    /// If the file can't be loaded, the previous rules are kept.
+    #[inline]
+    pub fn watch(mut self, interval: Duration) -> Rules {
+        if let Some(ref mut source) = self.source {
+            source.watch = Some(interval);
+        }
+        self
+    }
+
+    /// Loads the rules from the file again. Does nothing if the rules weren't loaded from a
+    /// file.
+    ///
+    /// If the file can't be loaded, the previous rules are kept and the error is returned.
+    pub fn reload(&self) -> Result<(), RulesError> {
+        let source = match self.source {
+            Some(ref source) => source,
+            None => return Ok(()),
+        };
+
+        let rules: Vec<Rule> = serde_json::from_str(&fs::read_to_string(&source.path)?)?;
+        self.replace(rules);
+        Ok(())
+    }
+
+    /// Replaces the rules.
+    pub fn replace(&self, rules: Vec<Rule>) {
+        let mut current = match self.rules.write() {
+            Ok(r) => r,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *current = Arc::new(rules);
+    }
+
+    /// Returns the current rules.
+    pub fn rules(&self) -> Arc<Vec<Rule>> {
+        match self.rules.read() {
+            Ok(r) => r.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn reload_if_modified(&self) {
+        let source = match self.source {
+            Some(ref source) => source,
+            None => return,
+        };
+        let interval = match source.watch {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let mut checked = match source.checked.lock() {
+            Ok(c) => c,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if checked.0.elapsed() < interval {
+            return;
+        }
+        checked.0 = Instant::now();
+
+        let modified = fs::metadata(&source.path).and_then(|m| m.modified()).ok();
+        if modified != checked.1 && self.reload().is_ok() {
+            checked.1 = modified;
+        }
+    }
+}
+
+impl fmt::Debug for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Rules")
+            .field("rules", &self.rules())
+            .field("path", &self.source.as_ref().map(|s| &s.path))
+            .finish()
+    }
+}
+
+impl Middleware for Rules {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        self.reload_if_modified();
+
+        let mut rewritten = None;
+        let mut tags = Vec::new();
+        for rule in self.rules().iter().filter(|r| r.matches(request)) {
+            match rule.action {
+                Action::Block => return Response::text("Forbidden").with_status_code(403),
+                Action::Tarpit(millis) => {
+                    thread::sleep(Duration::from_millis(millis));
+                    return Response::text("Forbidden").with_status_code(403);
+                }
+                Action::Rewrite(ref url) if rewritten.is_none() => {
+                    let query = request.raw_url().find('?').map(|p| &request.raw_url()[p..]);
+                    rewritten = Some(match query {
+                        Some(query) if !url.contains('?') => format!("{}{}", url, query),
+                        _ => url.clone(),
+                    });
+                }
+                Action::Rewrite(_) => (),
+                Action::Tag(ref tag) => tags.push(tag.clone()),
+            }
+        }
+
+        if !tags.is_empty() {
+            request.extensions().insert(RuleTags(tags));
+        }
+        match rewritten {
+            Some(url) => next(&request.with_raw_url(url)),
+            None => next(request),
+        }
+    }
+}
+
+// Matches `text` against a pattern where `*` matches any sequence and `?` any single byte.
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position in the pattern after the last `*`, and position in the text that it matched up to.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+    use super::tags;
+    use super::Action;
+    use super::Rule;
+    use super::Rules;
+    use middleware::Pipeline;
+    use std::env;
+    use std::fs;
+    use std::time::Duration;
+    use test::TestResponse;
+    use Request;
+    use Response;
+
+    fn pipeline(rules: Rules) -> Pipeline<impl Fn(&Request) -> Response> {
+        Pipeline::new(|request: &Request| {
+            Response::text(format!("{} {:?}", request.raw_url(), tags(request)))
+        })
+        .with(rules)
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match(b"/admin*", b"/admin/users"));
+        assert!(glob_match(b"*.php", b"/a/b.php"));
+        assert!(glob_match(b"/a?c", b"/abc"));
+        assert!(glob_match(b"*a*b*", b"xxaxxbxx"));
+        assert!(!glob_match(b"/admin", b"/admin/users"));
+        assert!(!glob_match(b"*.php", b"/a/b.php5"));
+        assert!(glob_match(b"", b""));
+    }
+
+    #[test]
+    fn conditions() {
+        let request = Request::fake_http_from(
+            "10.1.2.3:1234".parse().unwrap(),
+            "POST",
+            "/upload?x=1",
+            vec![
+                ("User-Agent".to_owned(), "curl/7.0".to_owned()),
+                ("Content-Length".to_owned(), "2000".to_owned()),
+            ],
+            vec![],
+        );
+        let rule = || Rule::new(Action::Block);
+
+        assert!(rule().matches(&request));
+        assert!(rule().method("post").matches(&request));
+        assert!(!rule().method("GET").matches(&request));
+        assert!(rule().path("/up*").matches(&request));
+        assert!(!rule().path("/upload?x*").matches(&request));
+        assert!(rule().header("user-agent", "curl/*").matches(&request));
+        assert!(!rule().header("Referer", "*").matches(&request));
+        assert!(rule().body_larger_than(1000).matches(&request));
+        assert!(!rule().body_larger_than(2000).matches(&request));
+        assert!(rule()
+            .client("10.0.0.0/8".parse().unwrap())
+            .matches(&request));
+        assert!(!rule()
+            .client("192.168.0.0/16".parse().unwrap())
+            .matches(&request));
+    }
+
+    #[test]
+    fn actions() {
+        let rules = Rules::from_json(
+            r#"[
+                { "path": "/admin*", "action": "block" },
+                { "path": "/slow", "action": { "tarpit": 1 } },
+                { "path": "/old/*", "action": { "rewrite": "/new" } },
+                { "method": "GET", "action": { "tag": "get" } },
+                { "client": "127.0.0.0/8", "action": { "tag": "local" } }
+            ]"#,
+        )
+        .unwrap();
+        let pipeline = pipeline(rules);
+        let handle = |url: &str| pipeline.handle(&Request::fake_http("GET", url, vec![], vec![]));
+
+        assert_eq!(handle("/admin/users").status_code, 403);
+        assert_eq!(handle("/slow").status_code, 403);
+        assert_eq!(
+            TestResponse::from(handle("/old/page?a=b")).text(),
+            "/new?a=b [\"get\", \"local\"]"
+        );
+        assert_eq!(
+            TestResponse::from(handle("/")).text(),
+            "/ [\"get\", \"local\"]"
+        );
+    }
+
+    #[test]
+    fn invalid_json() {
+        assert!(Rules::from_json(r#"[{ "action": "explode" }]"#).is_err());
+        assert!(Rules::from_json(r#"[{ "client": "nope", "action": "block" }]"#).is_err());
+    }
+
+    #[test]
+    fn hot_reload() {
+        let path = env::temp_dir().join(format!("rouille-rules-{}.json", ::std::process::id()));
+        fs::write(&path, r#"[{ "path": "/a", "action": "block" }]"#).unwrap();
+
+        let rules = Rules::load(&path).unwrap().watch(Duration::from_secs(0));
+        assert_eq!(rules.rules().len(), 1);
+
+        fs::write(&path, "invalid").unwrap();
+        assert!(rules.reload().is_err());
+        assert_eq!(rules.rules().len(), 1);
+
+        fs::write(&path, r#"[{ "path": "/b", "action": "block" }]"#).unwrap();
+        rules.reload().unwrap();
+        let pipeline = pipeline(rules);
+        let handle = |url: &str| pipeline.handle(&Request::fake_http("GET", url, vec![], vec![]));
+        assert_eq!(handle("/a").status_code, 200);
+        assert_eq!(handle("/b").status_code, 403);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Capturing the full requests and responses of a fraction of the traffic.
+//!
+//! A [`Sampler`](struct.Sampler.html) wraps a handler. For a configurable proportion of the
+//! requests that match its filters, it keeps a copy of the request and of the response,
+//! including their bodies, and passes it to a callback as a [`Capture`](struct.Capture.html).
+//! This makes it possible to investigate a bug that only happens from time to time in
+//! production without logging all the traffic.
+//!
+//! The values of sensitive headers, such as `Authorization` and `Cookie`, and the fields of
+//! JSON bodies that are registered with `redact_json_field` are replaced with `[redacted]`
+//! before the callback is called.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::fs::OpenOptions;
+//! use rouille::Response;
+//! use rouille::sampler::Sampler;
+//!
+//! let output = OpenOptions::new().create(true).append(true).open("captures.jsonl").unwrap();
+//!
+//! let sampler = Sampler::new(|request| Response::text("hello world"))
+//!     .sample_rate(0.01)
+//!     .path("/api/*")
+//!     .status(500..600)
+//!     .redact_json_field("password")
+//!     .record_to(output);
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| sampler.handle(request));
+//! ```
+
+use serde_json;
+use serde_json::Value;
+use std::fmt;
+use std::io::Read;
+use std::io::Write;
+use std::ops::Range;
+use std::sync::Mutex;
+
+use rules::glob_match;
+use Request;
+use Response;
+use ResponseBody;
+
+// Replaces the values that are redacted.
+const REDACTED: &str = "[redacted]";
+
+/// Wraps a handler and captures a sample of its requests and responses.
+pub struct Sampler<F> {
+    handler: F,
+    sample_rate: f64,
+    path: Option<String>,
+    status: Option<Range<u16>>,
+    headers: Vec<(String, String)>,
+    redacted_headers: Vec<String>,
+    redacted_fields: Vec<String>,
+    max_body_size: usize,
+    on_capture: Option<Box<CaptureFn>>,
+}
+
+// Signature of the function registered with `Sampler::on_capture`.
+type CaptureFn = dyn Fn(&Capture) + Send + Sync;
+
+impl<F> Sampler<F>
+where
+    F: Fn(&Request) -> Response,
+{
+    /// Builds a `Sampler` that captures all the requests.
+    ///
+    /// The `Authorization`, `Proxy-Authorization`, `Cookie` and `Set-Cookie` headers are
+    /// redacted.
+    pub fn new(handler: F) -> Sampler<F> {
+        Sampler {
+            handler,
+            sample_rate: 1.0,
+            path: None,
+            status: None,
+            headers: Vec::new(),
+            redacted_headers: vec![
+                "Authorization".to_owned(),
+                "Proxy-Authorization".to_owned(),
+                "Cookie".to_owned(),
+                "Set-Cookie".to_owned(),
+            ],
+            redacted_fields: Vec::new(),
+            max_body_size: 64 * 1024,
+            on_capture: None,
+        }
+    }
+
+    /// Sets the proportion of the matching requests, between 0 and 1, that are captured.
+    /// Defaults to 1.
+    ///
+    /// The requests are sampled with the source of randomness of the request, as returned by
+    /// [`Request::random`](../struct.Request.html#method.random).
+    #[inline]
+    pub fn sample_rate(mut self, sample_rate: f64) -> Sampler<F> {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Only captures the requests whose path matches a glob pattern, where `*` matches any
+    /// sequence of characters and `?` matches one character.
+    #[inline]
+    pub fn path<P>(mut self, pattern: P) -> Sampler<F>
+    where
+        P: Into<String>,
+    {
+        self.path = Some(pattern.into());
+        self
+    }
+
+    /// Only captures the requests whose response has a status code in the given range.
+    #[inline]
+    pub fn status(mut self, status: Range<u16>) -> Sampler<F> {
+        self.status = Some(status);
+        self
+    }
+
+    /// Only captures the requests that have a header whose value matches a glob pattern. Can be
+    /// called multiple times, in which case all the headers must match.
+    #[inline]
+    pub fn header<N, P>(mut self, name: N, pattern: P) -> Sampler<F>
+    where
+        N: Into<String>,
+        P: Into<String>,
+    {
+        self.headers.push((name.into(), pattern.into()));
+        self
+    }
+
+    /// Redacts the value of a header of the requests and of the responses.
+    #[inline]
+    pub fn redact_header<N>(mut self, name: N) -> Sampler<F>
+    where
+        N: Into<String>,
+    {
+        self.redacted_headers.push(name.into());
+        self
+    }
+
+    /// Redacts the values of the fields with the given name, at any depth, in the JSON bodies of
+    /// the requests and of the responses.
+    #[inline]
+    pub fn redact_json_field<N>(mut self, name: N) -> Sampler<F>
+    where
+        N: Into<String>,
+    {
+        self.redacted_fields.push(name.into());
+        self
+    }
+
+    /// Sets the maximum number of bytes of each body that is kept in the capture. Longer bodies
+    /// are truncated, and aren't redacted as JSON. Defaults to 64 kiB.
+    #[inline]
+    pub fn max_body_size(mut self, size: usize) -> Sampler<F> {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Sets the function called with each capture.
+    #[inline]
+    pub fn on_capture<C>(mut self, on_capture: C) -> Sampler<F>
+    where
+        C: Fn(&Capture) + Send + Sync + 'static,
+    {
+        self.on_capture = Some(Box::new(on_capture));
+        self
+    }
+
+    /// Writes each capture to `output` as a line of JSON, in the format of
+    /// [`Capture::to_json`](struct.Capture.html#method.to_json).
+    ///
+    /// Errors while writing are ignored.
+    pub fn record_to<W>(self, output: W) -> Sampler<F>
+    where
+        W: Write + Send + 'static,
+    {
+        let output = Mutex::new(output);
+        self.on_capture(move |capture| {
+            let mut line = capture.to_json();
+            line.push('\n');
+            if let Ok(mut output) = output.lock() {
+                let _ = output.write_all(line.as_bytes());
+                let _ = output.flush();
+            }
+        })
+    }
+
+    /// Processes a request.
+    ///
+    /// For the requests that are sampled, the body of the request and the body of the response
+    /// are read in memory.
+    pub fn handle(&self, request: &Request) -> Response {
+        if !self.matches_request(request) || !self.is_sampled(request) {
+            return (self.handler)(request);
+        }
+
+        let mut body = Vec::new();
+        if let Some(mut data) = request.data() {
+            // A body that can't be read is seen as empty by the handler.
+            let _ = data.read_to_end(&mut body);
+        }
+        let sampled_request = request.with_data(body.clone(), true);
+        let (response, response_body) = buffer((self.handler)(&sampled_request));
+
+        let status_matches = match self.status {
+            Some(ref status) => status.contains(&response.status_code),
+            None => true,
+        };
+        if status_matches {
+            if let Some(ref on_capture) = self.on_capture {
+                let request_headers = request
+                    .headers()
+                    .map(|(name, value)| self.redact_header_value(name, value))
+                    .collect();
+                let response_headers = response
+                    .headers
+                    .iter()
+                    .map(|(name, value)| self.redact_header_value(name, value))
+                    .collect();
+
+                on_capture(&Capture {
+                    method: request.method().to_owned(),
+                    url: request.raw_url().to_owned(),
+                    request_headers,
+                    request_body: self.redact_body(&body),
+                    status_code: response.status_code,
+                    response_headers,
+                    response_body: self.redact_body(&response_body),
+                });
+            }
+        }
+
+        response
+    }
+
+    fn matches_request(&self, request: &Request) -> bool {
+        if let Some(ref pattern) = self.path {
+            let path = request.raw_url().split('?').next().unwrap_or("");
+            if !glob_match(pattern.as_bytes(), path.as_bytes()) {
+                return false;
+            }
+        }
+
+        self.headers.iter().all(|(name, pattern)| {
+            request.header(name).map_or(false, |value| {
+                glob_match(pattern.as_bytes(), value.as_bytes())
+            })
+        })
+    }
+
+    fn is_sampled(&self, request: &Request) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        if self.sample_rate <= 0.0 {
+            return false;
+        }
+
+        (request.random().next_u64() as f64 / u64::max_value() as f64) < self.sample_rate
+    }
+
+    fn redact_header_value(&self, name: &str, value: &str) -> (String, String) {
+        let redacted = self
+            .redacted_headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name));
+        let value = if redacted { REDACTED } else { value };
+        (name.to_owned(), value.to_owned())
+    }
+
+    fn redact_body(&self, body: &[u8]) -> String {
+        if body.len() > self.max_body_size {
+            // The last character may be cut in the middle, in which case it is dropped.
+            let body = String::from_utf8_lossy(&body[..self.max_body_size]);
+            return body.trim_end_matches('\u{fffd}').to_owned();
+        }
+
+        if !self.redacted_fields.is_empty() {
+            if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+                redact_fields(&mut value, &self.redacted_fields);
+                return value.to_string();
+            }
+        }
+
+        String::from_utf8_lossy(body).into_owned()
+    }
+}
+
+impl<F> fmt::Debug for Sampler<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sampler")
+            .field("sample_rate", &self.sample_rate)
+            .field("path", &self.path)
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+/// A request and its response, captured by a `Sampler`.
+///
+/// The bodies are converted to UTF-8, which replaces the invalid sequences of bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Capture {
+    /// The method of the request.
+    pub method: String,
+    /// The URL of the request, as returned by `Request::raw_url`.
+    pub url: String,
+    /// The headers of the request, after redaction.
+    pub request_headers: Vec<(String, String)>,
+    /// The body of the request, after redaction and truncation.
+    pub request_body: String,
+    /// The status code of the response.
+    pub status_code: u16,
+    /// The headers of the response, after redaction.
+    pub response_headers: Vec<(String, String)>,
+    /// The body of the response, after redaction and truncation.
+    pub response_body: String,
+}
+
+impl Capture {
+    /// Serializes the capture as a JSON object, whose fields have the same names as the fields
+    /// of this struct, and whose headers are arrays of `[name, value]` pairs.
+    #[inline]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+fn redact_fields(value: &mut Value, fields: &[String]) {
+    match *value {
+        Value::Object(ref mut object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.iter().any(|f| f == key) {
+                    *value = Value::String(REDACTED.to_owned());
+                } else {
+                    redact_fields(value, fields);
+                }
+            }
+        }
+        Value::Array(ref mut array) => {
+            for value in array {
+                redact_fields(value, fields);
+            }
+        }
+        _ => (),
+    }
+}
+
+// Reads the body of a response in memory, and returns the response with an identical body.
+fn buffer(mut response: Response) -> (Response, Vec<u8>) {
+    let default_error = response.data.default_error;
+    let (mut reader, _) = response.data.into_reader_and_size();
+    let mut data = Vec::new();
+    let _ = reader.read_to_end(&mut data);
+
+    response.data = ResponseBody::from_data(data.clone());
+    response.data.default_error = default_error;
+    (response, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Capture;
+    use super::Sampler;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use Request;
+    use Response;
+
+    fn echo(request: &Request) -> Response {
+        let mut body = Vec::new();
+        request.data().unwrap().read_to_end(&mut body).unwrap();
+        Response::from_data("application/json", body)
+            .with_unique_header("Set-Cookie", "session=secret")
+    }
+
+    fn recorded<F>(sampler: Sampler<F>) -> (Sampler<F>, Arc<Mutex<Vec<Capture>>>)
+    where
+        F: Fn(&Request) -> Response,
+    {
+        let captures = Arc::new(Mutex::new(Vec::new()));
+        let sampler = {
+            let captures = captures.clone();
+            sampler.on_capture(move |capture| captures.lock().unwrap().push(capture.clone()))
+        };
+        (sampler, captures)
+    }
+
+    #[test]
+    fn captures_and_redacts() {
+        let (sampler, captures) = recorded(Sampler::new(echo).redact_json_field("password"));
+
+        let headers = vec![("Authorization".to_owned(), "Bearer secret".to_owned())];
+        let body = br#"{"user":"tom","password":"hunter2"}"#.to_vec();
+        let request = Request::fake_http("POST", "/login?next=/", headers, body);
+        let mut response = String::new();
+        let (mut data, _) = sampler.handle(&request).data.into_reader_and_size();
+        data.read_to_string(&mut response).unwrap();
+        assert_eq!(response, r#"{"user":"tom","password":"hunter2"}"#);
+
+        let captures = captures.lock().unwrap();
+        assert_eq!(captures.len(), 1);
+        let capture = &captures[0];
+        assert_eq!(capture.method, "POST");
+        assert_eq!(capture.url, "/login?next=/");
+        assert!(capture
+            .request_headers
+            .contains(&("Authorization".to_owned(), "[redacted]".to_owned())));
+        assert!(capture
+            .response_headers
+            .contains(&("Set-Cookie".to_owned(), "[redacted]".to_owned())));
+        assert_eq!(
+            capture.request_body,
+            r#"{"password":"[redacted]","user":"tom"}"#
+        );
+        assert_eq!(capture.request_body, capture.response_body);
+        assert_eq!(capture.status_code, 200);
+    }
+
+    #[test]
+    fn filters() {
+        let (sampler, captures) = recorded(
+            Sampler::new(|request: &Request| {
+                Response::text("").with_status_code(if request.url() == "/fail" {
+                    500
+                } else {
+                    200
+                })
+            })
+            .path("/f*")
+            .status(500..600)
+            .header("X-Debug", "?"),
+        );
+
+        let debug = || vec![("X-Debug".to_owned(), "1".to_owned())];
+        sampler.handle(&Request::fake_http("GET", "/fail", debug(), vec![]));
+        sampler.handle(&Request::fake_http("GET", "/fine", debug(), vec![]));
+        sampler.handle(&Request::fake_http("GET", "/fail", vec![], vec![]));
+        sampler.handle(&Request::fake_http("GET", "/other", debug(), vec![]));
+        assert_eq!(captures.lock().unwrap().len(), 1);
+
+        let sampler = Sampler::new(echo).sample_rate(0.0);
+        let (sampler, captures) = recorded(sampler);
+        sampler.handle(&Request::fake_http("GET", "/", vec![], vec![]));
+        assert!(captures.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncates() {
+        let (sampler, captures) = recorded(Sampler::new(echo).max_body_size(4));
+        let body = "abcdé".as_bytes().to_vec();
+        sampler.handle(&Request::fake_http("POST", "/", vec![], body));
+        assert_eq!(captures.lock().unwrap()[0].request_body, "abcd");
+
+        let (sampler, captures) = recorded(Sampler::new(echo).max_body_size(5));
+        let body = "abcdé".as_bytes().to_vec();
+        sampler.handle(&Request::fake_http("POST", "/", vec![], body));
+        assert_eq!(captures.lock().unwrap()[0].request_body, "abcd");
+    }
+}
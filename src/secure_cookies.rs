@@ -0,0 +1,368 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Storing data in cookies that the client can't tamper with.
+//!
+//! This module is only available if you enable the `signed-cookies` feature of rouille.
+//!
+//! - The values of the cookies signed with [`SignedCookies`](struct.SignedCookies.html) can be
+//!   read by the client, but any modification is detected.
+//! - The values of the cookies encrypted with [`PrivateCookies`](struct.PrivateCookies.html),
+//!   which requires the `private-cookies` feature, can neither be read nor modified by the
+//!   client.
+//!
+//! Both use a [`Key`](struct.Key.html) derived from a secret that must stay on the server. In
+//! order to change the secret without invalidating the existing cookies, pass the old key to
+//! `with_previous_key`: the cookies that use it are still accepted, while the new cookies use the
+//! new key.
+//!
+//! The signature covers the name and the value of a cookie, but not its attributes or its
+//! expiration. Put an expiration date in the value if the server must enforce it.
+//!
+//! # Example
+//!
+//! ```
+//! use rouille::{input, Request, Response, SetCookie};
+//! use rouille::secure_cookies::{Key, SignedCookies};
+//!
+//! let cookies = SignedCookies::new(Key::from_secret(b"a secret of at least thirty-two bytes"));
+//!
+//! fn handle(request: &Request, cookies: &SignedCookies) -> Response {
+//!     let jar = input::cookie_jar(request);
+//!     match cookies.get(&jar, "user") {
+//!         Some(user) => Response::text(format!("hello {}", user)),
+//!         None => Response::text("hello stranger")
+//!             .with_cookie(cookies.sign(SetCookie::new("user", "guest").http_only())),
+//!     }
+//! }
+//! # let _ = handle(&Request::fake_http("GET", "/", vec![], vec![]), &cookies);
+//! ```
+
+use std::fmt;
+
+use base64;
+use hmac::{Hmac, Mac, NewMac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+
+use input::CookieJar;
+use SetCookie;
+
+/// Length in bytes of the signatures.
+const SIGNATURE_LEN: usize = 32;
+
+/// The secret keys used to sign and encrypt the cookies.
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Key {
+    /// Derives the keys from a secret, for example read from the configuration of the server.
+    ///
+    /// The secret should be random. The same secret gives the same keys, so that the cookies
+    /// stay valid when the server restarts.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the secret is shorter than 32 bytes.
+    pub fn from_secret(secret: &[u8]) -> Key {
+        assert!(
+            secret.len() >= 32,
+            "the secret of the cookies must be at least 32 bytes long"
+        );
+
+        let derive = |label: &[u8]| {
+            let mut mac =
+                Hmac::<Sha256>::new_varkey(secret).expect("HMAC accepts keys of any size");
+            mac.update(label);
+            let mut key = [0; 32];
+            key.copy_from_slice(&mac.finalize().into_bytes());
+            key
+        };
+
+        Key {
+            signing: derive(b"rouille cookie signing"),
+            encryption: derive(b"rouille cookie encryption"),
+        }
+    }
+
+    /// Generates random keys. The cookies become invalid when the server restarts.
+    pub fn generate() -> Key {
+        let mut secret = [0; 64];
+        OsRng.fill_bytes(&mut secret);
+        Key::from_secret(&secret)
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Key").finish()
+    }
+}
+
+/// Signs the values of the cookies and checks their signatures.
+///
+/// The signature is appended to the value, after a dot.
+#[derive(Debug, Clone)]
+pub struct SignedCookies {
+    // The first key signs, all of them are accepted.
+    keys: Vec<Key>,
+}
+
+impl SignedCookies {
+    /// Builds a `SignedCookies` that signs with `key`.
+    #[inline]
+    pub fn new(key: Key) -> SignedCookies {
+        SignedCookies { keys: vec![key] }
+    }
+
+    /// Also accepts the cookies signed with `key`, without using it for the new cookies.
+    #[inline]
+    pub fn with_previous_key(mut self, key: Key) -> SignedCookies {
+        self.keys.push(key);
+        self
+    }
+
+    /// Appends the signature to the value of the cookie.
+    pub fn sign(&self, mut cookie: SetCookie) -> SetCookie {
+        let signature = signature(&self.keys[0], &cookie.name, &cookie.value).finalize();
+        let signature = base64::encode_config(signature.into_bytes(), base64::URL_SAFE_NO_PAD);
+        cookie.value = format!("{}.{}", cookie.value, signature);
+        cookie
+    }
+
+    /// Returns the value of the cookie with the given name if its signature is valid, without
+    /// the signature.
+    ///
+    /// If the request has several cookies with this name, the first one whose signature is
+    /// valid is returned.
+    pub fn get<'a>(&self, jar: &'a CookieJar, name: &str) -> Option<&'a str> {
+        jar.get_all(name)
+            .into_iter()
+            .filter_map(|value| self.verify(name, value))
+            .next()
+    }
+
+    /// Checks the signature of the value of a cookie, and returns the value without the
+    /// signature if it's valid.
+    pub fn verify<'a>(&self, name: &str, value: &'a str) -> Option<&'a str> {
+        let pos = value.rfind('.')?;
+        let tag = base64::decode_config(&value[pos + 1..], base64::URL_SAFE_NO_PAD).ok()?;
+        if tag.len() != SIGNATURE_LEN {
+            return None;
+        }
+
+        let value = &value[..pos];
+        let valid = self
+            .keys
+            .iter()
+            .any(|key| signature(key, name, value).verify(&tag).is_ok());
+        if valid {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+// Returns the HMAC of the name and the value of a cookie, ready to be finalized or verified.
+fn signature(key: &Key, name: &str, value: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(&key.signing).expect("HMAC accepts keys of any size");
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(value.as_bytes());
+    mac
+}
+
+/// Encrypts the values of the cookies and decrypts them.
+///
+/// This is only available if you enable the `private-cookies` feature of rouille. The values
+/// are encrypted with AES-256-GCM, which also detects any modification, and the name of the
+/// cookie is authenticated as well so that the value of a cookie can't be moved to another one.
+///
+/// Since the value is encrypted, it can contain any character, for example JSON.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{input, Request, SetCookie};
+/// use rouille::secure_cookies::{Key, PrivateCookies};
+///
+/// let cookies = PrivateCookies::new(Key::generate());
+/// let cookie = cookies.encrypt(SetCookie::new("flash", "{\"message\": \"saved\"}"));
+///
+/// let request = Request::fake_http("GET", "/", vec![
+///     ("Cookie".to_owned(), cookie.to_string()),
+/// ], vec![]);
+/// let jar = input::cookie_jar(&request);
+/// assert_eq!(cookies.get(&jar, "flash").unwrap(), "{\"message\": \"saved\"}");
+/// ```
+#[cfg(feature = "private-cookies")]
+#[derive(Debug, Clone)]
+pub struct PrivateCookies {
+    // The first key encrypts, all of them are accepted.
+    keys: Vec<Key>,
+}
+
+#[cfg(feature = "private-cookies")]
+impl PrivateCookies {
+    /// Builds a `PrivateCookies` that encrypts with `key`.
+    #[inline]
+    pub fn new(key: Key) -> PrivateCookies {
+        PrivateCookies { keys: vec![key] }
+    }
+
+    /// Also accepts the cookies encrypted with `key`, without using it for the new cookies.
+    #[inline]
+    pub fn with_previous_key(mut self, key: Key) -> PrivateCookies {
+        self.keys.push(key);
+        self
+    }
+
+    /// Replaces the value of the cookie with its encrypted version.
+    pub fn encrypt(&self, mut cookie: SetCookie) -> SetCookie {
+        use aes_gcm::aead::{Aead, NewAead, Payload};
+        use aes_gcm::Aes256Gcm;
+
+        let cipher =
+            Aes256Gcm::new_varkey(&self.keys[0].encryption).expect("the key has the right size");
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: cookie.value.as_bytes(),
+            aad: cookie.name.as_bytes(),
+        };
+        let encrypted = cipher
+            .encrypt(nonce[..].into(), payload)
+            .expect("failed to encrypt the cookie");
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&encrypted);
+        cookie.value = base64::encode_config(&data, base64::URL_SAFE_NO_PAD);
+        cookie
+    }
+
+    /// Returns the decrypted value of the cookie with the given name, if the cookie is valid.
+    ///
+    /// If the request has several cookies with this name, the first valid one is returned.
+    pub fn get(&self, jar: &CookieJar, name: &str) -> Option<String> {
+        jar.get_all(name)
+            .into_iter()
+            .filter_map(|value| self.decrypt(name, value))
+            .next()
+    }
+
+    /// Decrypts the value of a cookie, or returns `None` if it's not valid.
+    pub fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+        use aes_gcm::aead::{Aead, NewAead, Payload};
+        use aes_gcm::Aes256Gcm;
+
+        let data = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, encrypted) = data.split_at(NONCE_LEN);
+
+        self.keys.iter().find_map(|key| {
+            let cipher =
+                Aes256Gcm::new_varkey(&key.encryption).expect("the key has the right size");
+            let payload = Payload {
+                msg: encrypted,
+                aad: name.as_bytes(),
+            };
+            let decrypted = cipher.decrypt(nonce.into(), payload).ok()?;
+            String::from_utf8(decrypted).ok()
+        })
+    }
+}
+
+/// Length in bytes of the nonces of AES-GCM.
+#[cfg(feature = "private-cookies")]
+const NONCE_LEN: usize = 12;
+
+#[cfg(test)]
+mod tests {
+    use super::Key;
+    use super::SignedCookies;
+    use input;
+    use Request;
+    use SetCookie;
+
+    fn jar(cookie: &str) -> input::CookieJar {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![("Cookie".to_owned(), cookie.to_owned())],
+            vec![],
+        );
+        input::cookie_jar(&request)
+    }
+
+    #[test]
+    fn signed() {
+        let key = Key::from_secret(&[1; 32]);
+        let cookies = SignedCookies::new(key.clone());
+        let cookie = cookies.sign(SetCookie::new("user", "alice"));
+        assert!(cookie.to_string().starts_with("user=alice."));
+
+        assert_eq!(
+            cookies.get(&jar(&cookie.to_string()), "user"),
+            Some("alice")
+        );
+        let other_name = cookie.to_string().replacen("user", "admin", 1);
+        assert_eq!(cookies.get(&jar(&other_name), "admin"), None);
+        let tampered = cookie.to_string().replacen("alice", "bob", 1);
+        assert_eq!(cookies.get(&jar(&tampered), "user"), None);
+        assert_eq!(cookies.get(&jar("user=alice"), "user"), None);
+
+        // The cookies signed with the previous key are still accepted after a rotation.
+        let rotated = SignedCookies::new(Key::from_secret(&[2; 32])).with_previous_key(key);
+        assert_eq!(
+            rotated.get(&jar(&cookie.to_string()), "user"),
+            Some("alice")
+        );
+        let unknown = SignedCookies::new(Key::from_secret(&[3; 32]));
+        assert_eq!(unknown.get(&jar(&cookie.to_string()), "user"), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn short_secret() {
+        Key::from_secret(b"too short");
+    }
+
+    #[test]
+    #[cfg(feature = "private-cookies")]
+    fn private() {
+        use super::PrivateCookies;
+
+        let key = Key::generate();
+        let cookies = PrivateCookies::new(key.clone());
+        let cookie = cookies.encrypt(SetCookie::new("flash", "saved; 100%"));
+        assert!(cookie.validate().is_ok());
+        assert!(!cookie.to_string().contains("saved"));
+        assert_eq!(
+            cookies.get(&jar(&cookie.to_string()), "flash").as_deref(),
+            Some("saved; 100%")
+        );
+
+        let other_name = cookie.to_string().replacen("flash", "other", 1);
+        assert_eq!(cookies.get(&jar(&other_name), "other"), None);
+        assert_eq!(cookies.decrypt("flash", "garbage"), None);
+
+        let rotated = PrivateCookies::new(Key::generate()).with_previous_key(key);
+        assert!(rotated.get(&jar(&cookie.to_string()), "flash").is_some());
+        assert!(PrivateCookies::new(Key::generate())
+            .get(&jar(&cookie.to_string()), "flash")
+            .is_none());
+    }
+}
@@ -0,0 +1,636 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Headers that tell browsers to enable their security features.
+//!
+//! A [`SecurityHeaders`](struct.SecurityHeaders.html) groups the `Strict-Transport-Security`,
+//! `X-Content-Type-Options`, `X-Frame-Options`, `Referrer-Policy` and
+//! `Content-Security-Policy` headers. It is added to a response with
+//! [`Response::with_security_headers`](../struct.Response.html#method.with_security_headers), or
+//! to all the responses when used as a [`Middleware`](../middleware/trait.Middleware.html).
+//! Headers that the response already contains are left untouched, so that a handler can relax
+//! the policy for a single page.
+//!
+//! The [`ContentSecurityPolicy`](struct.ContentSecurityPolicy.html) can allow inline scripts and
+//! styles with a nonce. The nonce is generated once per request, and
+//! [`nonce`](fn.nonce.html) returns it so that templates can put it in the `nonce` attribute of
+//! their `<script>` and `<style>` elements.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rouille::Response;
+//! use rouille::security_headers::{self, ContentSecurityPolicy, SecurityHeaders, Source};
+//!
+//! let headers = SecurityHeaders::new().content_security_policy(
+//!     ContentSecurityPolicy::new()
+//!         .default_src(&[Source::SelfOrigin])
+//!         .script_src(&[Source::SelfOrigin, Source::Nonce])
+//!         .object_src(&[Source::None]),
+//! );
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     let html = format!(
+//!         "<script nonce=\"{}\">console.log('hello')</script>",
+//!         security_headers::nonce(request)
+//!     );
+//!     Response::html(html).with_security_headers(request, &headers)
+//! });
+//! ```
+
+use std::fmt;
+
+use middleware::Middleware;
+use random::Random;
+use Request;
+use Response;
+
+/// The security headers to add to the responses.
+///
+/// `SecurityHeaders::new()` enables HSTS for a year, `X-Content-Type-Options: nosniff`,
+/// `X-Frame-Options: DENY` and `Referrer-Policy: strict-origin-when-cross-origin`, without any
+/// content security policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    /// The `Strict-Transport-Security` header.
+    pub hsts: Option<Hsts>,
+    /// Adds `X-Content-Type-Options: nosniff`, so that browsers trust the `Content-Type` header.
+    pub nosniff: bool,
+    /// The `X-Frame-Options` header.
+    pub frame_options: Option<FrameOptions>,
+    /// The `Referrer-Policy` header.
+    pub referrer_policy: Option<ReferrerPolicy>,
+    /// The `Content-Security-Policy` header.
+    pub content_security_policy: Option<ContentSecurityPolicy>,
+}
+
+impl SecurityHeaders {
+    /// Builds the default security headers.
+    #[inline]
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders::default()
+    }
+
+    /// Builds a `SecurityHeaders` that doesn't add any header.
+    #[inline]
+    pub fn none() -> SecurityHeaders {
+        SecurityHeaders {
+            hsts: None,
+            nosniff: false,
+            frame_options: None,
+            referrer_policy: None,
+            content_security_policy: None,
+        }
+    }
+
+    /// Sets the `Strict-Transport-Security` header, or disables it with `None`.
+    ///
+    /// Browsers ignore this header if the response comes through plain HTTP, so it doesn't need
+    /// to be disabled for development servers.
+    #[inline]
+    pub fn hsts(mut self, hsts: Option<Hsts>) -> SecurityHeaders {
+        self.hsts = hsts;
+        self
+    }
+
+    /// Enables or disables `X-Content-Type-Options: nosniff`.
+    #[inline]
+    pub fn nosniff(mut self, nosniff: bool) -> SecurityHeaders {
+        self.nosniff = nosniff;
+        self
+    }
+
+    /// Sets the `X-Frame-Options` header, or disables it with `None`.
+    #[inline]
+    pub fn frame_options(mut self, frame_options: Option<FrameOptions>) -> SecurityHeaders {
+        self.frame_options = frame_options;
+        self
+    }
+
+    /// Sets the `Referrer-Policy` header, or disables it with `None`.
+    #[inline]
+    pub fn referrer_policy(mut self, referrer_policy: Option<ReferrerPolicy>) -> SecurityHeaders {
+        self.referrer_policy = referrer_policy;
+        self
+    }
+
+    /// Sets the `Content-Security-Policy` header.
+    #[inline]
+    pub fn content_security_policy(mut self, policy: ContentSecurityPolicy) -> SecurityHeaders {
+        self.content_security_policy = Some(policy);
+        self
+    }
+
+    /// Adds the headers to `response`, except for those that it already contains.
+    ///
+    /// Same as [`Response::with_security_headers`](../struct.Response.html#method.with_security_headers).
+    pub fn apply(&self, request: &Request, mut response: Response) -> Response {
+        let mut add = |name: &'static str, value: String| {
+            if response.header(name).is_none() {
+                response.headers.push((name.into(), value.into()));
+            }
+        };
+
+        if let Some(ref hsts) = self.hsts {
+            add("Strict-Transport-Security", hsts.to_string());
+        }
+        if self.nosniff {
+            add("X-Content-Type-Options", "nosniff".to_owned());
+        }
+        if let Some(frame_options) = self.frame_options {
+            add("X-Frame-Options", frame_options.to_string());
+        }
+        if let Some(referrer_policy) = self.referrer_policy {
+            add("Referrer-Policy", referrer_policy.to_string());
+        }
+        if let Some(ref policy) = self.content_security_policy {
+            add(policy.header_name(), policy.header_value(request));
+        }
+
+        response
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders {
+            hsts: Some(Hsts::new(365 * 24 * 3600)),
+            nosniff: true,
+            frame_options: Some(FrameOptions::Deny),
+            referrer_policy: Some(ReferrerPolicy::StrictOriginWhenCrossOrigin),
+            content_security_policy: None,
+        }
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    #[inline]
+    fn after(&self, request: &Request, response: Response) -> Response {
+        self.apply(request, response)
+    }
+}
+
+/// The `Strict-Transport-Security` header, which tells browsers to only connect to the website
+/// with HTTPS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    /// For how long the browser must remember the policy, in seconds.
+    pub max_age: u64,
+    /// The policy also applies to the subdomains.
+    pub include_subdomains: bool,
+    /// Asks to be included in the preload lists of the browsers.
+    pub preload: bool,
+}
+
+impl Hsts {
+    /// Builds a policy that lasts `max_age` seconds.
+    #[inline]
+    pub fn new(max_age: u64) -> Hsts {
+        Hsts {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// Sets the `includeSubDomains` directive.
+    #[inline]
+    pub fn include_subdomains(mut self) -> Hsts {
+        self.include_subdomains = true;
+        self
+    }
+
+    /// Sets the `preload` directive.
+    #[inline]
+    pub fn preload(mut self) -> Hsts {
+        self.preload = true;
+        self
+    }
+}
+
+impl fmt::Display for Hsts {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "max-age={}", self.max_age)?;
+        if self.include_subdomains {
+            write!(fmt, "; includeSubDomains")?;
+        }
+        if self.preload {
+            write!(fmt, "; preload")?;
+        }
+        Ok(())
+    }
+}
+
+/// The value of the `X-Frame-Options` header, which controls whether other pages can display the
+/// response in a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// The response can't be displayed in a frame.
+    Deny,
+    /// Only the pages of the same origin can display the response in a frame.
+    SameOrigin,
+}
+
+impl fmt::Display for FrameOptions {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let value = match *self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        };
+        write!(fmt, "{}", value)
+    }
+}
+
+/// The value of the `Referrer-Policy` header, which controls what the `Referer` header of the
+/// requests made from the page contains.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReferrerPolicy {
+    /// `no-referrer`
+    NoReferrer,
+    /// `no-referrer-when-downgrade`
+    NoReferrerWhenDowngrade,
+    /// `origin`
+    Origin,
+    /// `origin-when-cross-origin`
+    OriginWhenCrossOrigin,
+    /// `same-origin`
+    SameOrigin,
+    /// `strict-origin`
+    StrictOrigin,
+    /// `strict-origin-when-cross-origin`
+    StrictOriginWhenCrossOrigin,
+    /// `unsafe-url`
+    UnsafeUrl,
+}
+
+impl fmt::Display for ReferrerPolicy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let value = match *self {
+            ReferrerPolicy::NoReferrer => "no-referrer",
+            ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            ReferrerPolicy::Origin => "origin",
+            ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            ReferrerPolicy::SameOrigin => "same-origin",
+            ReferrerPolicy::StrictOrigin => "strict-origin",
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+            ReferrerPolicy::UnsafeUrl => "unsafe-url",
+        };
+        write!(fmt, "{}", value)
+    }
+}
+
+/// A source of a directive of a `ContentSecurityPolicy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// `'none'`, which doesn't allow anything.
+    None,
+    /// `'self'`, the origin of the page.
+    SelfOrigin,
+    /// `'unsafe-inline'`
+    UnsafeInline,
+    /// `'unsafe-eval'`
+    UnsafeEval,
+    /// `'strict-dynamic'`
+    StrictDynamic,
+    /// The nonce of the request, as returned by [`nonce`](fn.nonce.html).
+    Nonce,
+    /// The hash of an inline script or style, for example `sha256-<base64>`.
+    Hash(String),
+    /// Any URL with this scheme, for example `data` or `https`.
+    Scheme(String),
+    /// A host, optionally with a scheme, a port, a path or wildcards, for example
+    /// `https://*.example.com`.
+    Host(String),
+}
+
+impl Source {
+    fn write(&self, nonce: &str, out: &mut String) {
+        match *self {
+            Source::None => out.push_str("'none'"),
+            Source::SelfOrigin => out.push_str("'self'"),
+            Source::UnsafeInline => out.push_str("'unsafe-inline'"),
+            Source::UnsafeEval => out.push_str("'unsafe-eval'"),
+            Source::StrictDynamic => out.push_str("'strict-dynamic'"),
+            Source::Nonce => {
+                out.push_str("'nonce-");
+                out.push_str(nonce);
+                out.push('\'');
+            }
+            Source::Hash(ref hash) => {
+                out.push('\'');
+                out.push_str(hash);
+                out.push('\'');
+            }
+            Source::Scheme(ref scheme) => {
+                out.push_str(scheme);
+                out.push(':');
+            }
+            Source::Host(ref host) => out.push_str(host),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let value = match *self {
+            Source::Hash(ref v) | Source::Scheme(ref v) | Source::Host(ref v) => v,
+            _ => return true,
+        };
+        !value.is_empty()
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && b != b';' && b != b',' && b != b'\'')
+    }
+}
+
+/// Builder for a `Content-Security-Policy` header, which restricts the resources that the page
+/// can load and execute.
+///
+/// # Example
+///
+/// ```
+/// use rouille::Request;
+/// use rouille::security_headers::{ContentSecurityPolicy, Source};
+///
+/// let policy = ContentSecurityPolicy::new()
+///     .default_src(&[Source::SelfOrigin])
+///     .img_src(&[Source::SelfOrigin, Source::Scheme("data".to_owned())])
+///     .upgrade_insecure_requests();
+///
+/// let request = Request::fake_http("GET", "/", vec![], vec![]);
+/// assert_eq!(
+///     policy.header_value(&request),
+///     "default-src 'self'; img-src 'self' data:; upgrade-insecure-requests"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<Source>)>,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    /// Builds a policy without any directive.
+    #[inline]
+    pub fn new() -> ContentSecurityPolicy {
+        ContentSecurityPolicy::default()
+    }
+
+    /// Sets a directive, replacing the previous sources of this directive if any.
+    ///
+    /// A directive without sources, such as `upgrade-insecure-requests`, is written without
+    /// value.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the name isn't made of lowercase letters and dashes, or if a `Hash`, `Scheme` or
+    /// `Host` source is empty or contains spaces, commas, semicolons or quotes.
+    pub fn directive<N>(mut self, name: N, sources: &[Source]) -> ContentSecurityPolicy
+    where
+        N: Into<String>,
+    {
+        let name = name.into();
+        assert!(!name.is_empty() && name.bytes().all(|b| b.is_ascii_lowercase() || b == b'-'));
+        assert!(sources.iter().all(Source::is_valid));
+
+        self.directives.retain(|d| d.0 != name);
+        self.directives.push((name, sources.to_vec()));
+        self
+    }
+
+    /// Sets the `default-src` directive, which applies to the kinds of resources that don't have
+    /// their own directive.
+    #[inline]
+    pub fn default_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("default-src", sources)
+    }
+
+    /// Sets the `script-src` directive.
+    #[inline]
+    pub fn script_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("script-src", sources)
+    }
+
+    /// Sets the `style-src` directive.
+    #[inline]
+    pub fn style_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("style-src", sources)
+    }
+
+    /// Sets the `img-src` directive.
+    #[inline]
+    pub fn img_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("img-src", sources)
+    }
+
+    /// Sets the `connect-src` directive, which applies to `fetch`, `XMLHttpRequest`, WebSockets
+    /// and server-sent events.
+    #[inline]
+    pub fn connect_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("connect-src", sources)
+    }
+
+    /// Sets the `font-src` directive.
+    #[inline]
+    pub fn font_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("font-src", sources)
+    }
+
+    /// Sets the `object-src` directive.
+    #[inline]
+    pub fn object_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("object-src", sources)
+    }
+
+    /// Sets the `frame-src` directive.
+    #[inline]
+    pub fn frame_src(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("frame-src", sources)
+    }
+
+    /// Sets the `base-uri` directive.
+    #[inline]
+    pub fn base_uri(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("base-uri", sources)
+    }
+
+    /// Sets the `form-action` directive.
+    #[inline]
+    pub fn form_action(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("form-action", sources)
+    }
+
+    /// Sets the `frame-ancestors` directive, which supersedes `X-Frame-Options` in the browsers
+    /// that support it.
+    #[inline]
+    pub fn frame_ancestors(self, sources: &[Source]) -> ContentSecurityPolicy {
+        self.directive("frame-ancestors", sources)
+    }
+
+    /// Sets the `upgrade-insecure-requests` directive.
+    #[inline]
+    pub fn upgrade_insecure_requests(self) -> ContentSecurityPolicy {
+        self.directive("upgrade-insecure-requests", &[])
+    }
+
+    /// Sets the `report-uri` directive, to which browsers send the violations of the policy.
+    #[inline]
+    pub fn report_uri<S>(self, uri: S) -> ContentSecurityPolicy
+    where
+        S: Into<String>,
+    {
+        self.directive("report-uri", &[Source::Host(uri.into())])
+    }
+
+    /// Sends the policy as `Content-Security-Policy-Report-Only`, so that browsers only report the
+    /// violations instead of blocking the resources.
+    #[inline]
+    pub fn report_only(mut self) -> ContentSecurityPolicy {
+        self.report_only = true;
+        self
+    }
+
+    /// Returns the name of the header of the policy.
+    #[inline]
+    pub fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+
+    /// Returns the value of the header of the policy for `request`, in which `Source::Nonce` is
+    /// replaced with the nonce of the request.
+    pub fn header_value(&self, request: &Request) -> String {
+        let uses_nonce = self.directives.iter().any(|d| d.1.contains(&Source::Nonce));
+        let nonce = if uses_nonce {
+            nonce(request)
+        } else {
+            String::new()
+        };
+
+        let mut out = String::new();
+        for (name, sources) in &self.directives {
+            if !out.is_empty() {
+                out.push_str("; ");
+            }
+            out.push_str(name);
+            for source in sources {
+                out.push(' ');
+                source.write(&nonce, &mut out);
+            }
+        }
+        out
+    }
+}
+
+// Stored in the extensions of a request once its nonce has been generated.
+struct CspNonce(String);
+
+/// Returns the nonce of `request`, to put in the `nonce` attribute of the inline `<script>` and
+/// `<style>` elements allowed by `Source::Nonce`.
+///
+/// The nonce is generated with [`Random::of`](../random/struct.Random.html#method.of) the first
+/// time this function is called for a request, and the same nonce is returned afterwards.
+///
+/// # Example
+///
+/// ```
+/// use rouille::Request;
+/// use rouille::security_headers;
+///
+/// let request = Request::fake_http("GET", "/", vec![], vec![]);
+/// let nonce = security_headers::nonce(&request);
+/// assert_eq!(nonce.len(), 22);
+/// assert_eq!(security_headers::nonce(&request), nonce);
+/// ```
+pub fn nonce(request: &Request) -> String {
+    let random = Random::of(request);
+    let mut extensions = request.extensions();
+    if let Some(nonce) = extensions.get::<CspNonce>() {
+        return nonce.0.clone();
+    }
+
+    // 22 alphanumeric characters are more than 128 bits of randomness.
+    let nonce = random.alphanumeric(22);
+    extensions.insert(CspNonce(nonce.clone()));
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nonce, ContentSecurityPolicy, FrameOptions, Hsts, SecurityHeaders, Source};
+    use middleware::Middleware;
+    use Request;
+    use Response;
+
+    #[test]
+    fn default_headers() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let response = Response::text("hello")
+            .with_additional_header("X-Frame-Options", "SAMEORIGIN")
+            .with_security_headers(&request, &SecurityHeaders::new());
+        assert_eq!(
+            response.header("Strict-Transport-Security"),
+            Some("max-age=31536000")
+        );
+        assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(response.header("X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(
+            response.header("Referrer-Policy"),
+            Some("strict-origin-when-cross-origin")
+        );
+        assert_eq!(response.header("Content-Security-Policy"), None);
+
+        let headers = SecurityHeaders::none()
+            .hsts(Some(Hsts::new(60).include_subdomains().preload()))
+            .frame_options(Some(FrameOptions::Deny));
+        let response = headers.after(&request, Response::text("hello"));
+        assert_eq!(response.headers.len(), 3);
+        assert_eq!(
+            response.header("Strict-Transport-Security"),
+            Some("max-age=60; includeSubDomains; preload")
+        );
+        assert_eq!(response.header("X-Frame-Options"), Some("DENY"));
+    }
+
+    #[test]
+    fn content_security_policy() {
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        let policy = ContentSecurityPolicy::new()
+            .default_src(&[Source::None])
+            .script_src(&[Source::Nonce, Source::StrictDynamic])
+            .style_src(&[Source::Hash("sha256-abc=".to_owned())])
+            .default_src(&[Source::SelfOrigin])
+            .report_uri("/csp-reports")
+            .report_only();
+
+        let response = Response::text("hello").with_security_headers(
+            &request,
+            &SecurityHeaders::none().content_security_policy(policy),
+        );
+        assert_eq!(response.header("Content-Security-Policy"), None);
+        assert_eq!(
+            response.header("Content-Security-Policy-Report-Only"),
+            Some(&*format!(
+                "script-src 'nonce-{}' 'strict-dynamic'; style-src 'sha256-abc='; \
+                 default-src 'self'; report-uri /csp-reports",
+                nonce(&request)
+            ))
+        );
+
+        let other = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_ne!(nonce(&other), nonce(&request));
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_source() {
+        let _ = ContentSecurityPolicy::new().img_src(&[Source::Host("a.com; script-src *".into())]);
+    }
+}
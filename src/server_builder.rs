@@ -0,0 +1,193 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+
+use tiny_http;
+
+use Request;
+use Response;
+use Server;
+use SocketOptions;
+
+/// Builds a `Server` step by step. Created with
+/// [`Server::builder`](struct.Server.html#method.builder).
+///
+/// The builder gathers the options that determine how the listening sockets are created, which
+/// would otherwise need a different constructor for each combination. The other options, such
+/// as [`header_limits`](struct.Server.html#method.header_limits), are set on the `Server`
+/// returned by `build`.
+///
+/// # Example
+///
+/// ```no_run
+/// use rouille::{Response, Server, SocketOptions};
+///
+/// let server = Server::builder()
+///     .addr("0.0.0.0:80")
+///     .addr("[::]:80")
+///     .socket_options(SocketOptions { nodelay: true, ..SocketOptions::default() })
+///     .pool_size(8)
+///     .build(|request| Response::text("hello world"))
+///     .unwrap();
+/// server.run();
+/// ```
+pub struct ServerBuilder {
+    addrs: Vec<SocketAddr>,
+    listeners: Vec<TcpListener>,
+    socket_options: Option<SocketOptions>,
+    pool_size: Option<usize>,
+    #[cfg(feature = "ssl")]
+    tls: Option<tiny_http::SslConfig>,
+    // First error that happened while resolving an address.
+    error: Option<io::Error>,
+}
+
+impl ServerBuilder {
+    #[inline]
+    pub(crate) fn new() -> ServerBuilder {
+        ServerBuilder {
+            addrs: Vec::new(),
+            listeners: Vec::new(),
+            socket_options: None,
+            pool_size: None,
+            #[cfg(feature = "ssl")]
+            tls: None,
+            error: None,
+        }
+    }
+
+    /// Adds an address to listen on. Can be called multiple times, in which case the requests
+    /// received on all the addresses are processed by the same handler.
+    ///
+    /// If `addr` resolves to multiple addresses, only the first one is used. Resolution errors
+    /// are returned by `build`.
+    pub fn addr<A>(mut self, addr: A) -> ServerBuilder
+    where
+        A: ToSocketAddrs,
+    {
+        let resolved = addr.to_socket_addrs().and_then(|mut addrs| {
+            addrs
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address resolved"))
+        });
+        match resolved {
+            Ok(addr) => self.addrs.push(addr),
+            Err(err) => {
+                if self.error.is_none() {
+                    self.error = Some(err);
+                }
+            }
+        }
+        self
+    }
+
+    /// Adds a listening socket that you have created yourself.
+    ///
+    /// The socket options and the TLS configuration don't apply to this socket, except that
+    /// connections are encrypted if TLS is enabled.
+    #[inline]
+    pub fn listener(mut self, listener: TcpListener) -> ServerBuilder {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Configures the sockets created for the addresses passed to `addr`.
+    ///
+    /// See [the `SocketOptions` struct](struct.SocketOptions.html) for the list of options.
+    #[inline]
+    pub fn socket_options(mut self, options: SocketOptions) -> ServerBuilder {
+        self.socket_options = Some(options);
+        self
+    }
+
+    /// Processes the requests with a thread pool of the given size, like
+    /// [`Server::pool_size`](struct.Server.html#method.pool_size).
+    #[inline]
+    pub fn pool_size(mut self, pool_size: usize) -> ServerBuilder {
+        self.pool_size = Some(pool_size);
+        self
+    }
+
+    /// Serves HTTPS instead of HTTP on all the sockets, with the given certificate and private
+    /// key in the PEM format.
+    #[cfg(feature = "ssl")]
+    #[inline]
+    pub fn tls(mut self, certificate: Vec<u8>, private_key: Vec<u8>) -> ServerBuilder {
+        self.tls = Some(tiny_http::SslConfig {
+            certificate,
+            private_key,
+        });
+        self
+    }
+
+    /// Creates the listening sockets and builds the `Server`.
+    ///
+    /// Returns an error if an address couldn't be resolved, if there is no address to listen
+    /// on, or if there was an error while creating a listening socket, for example if the port
+    /// is already in use.
+    pub fn build<F>(self, handler: F) -> Result<Server<F>, Box<dyn Error + Send + Sync + 'static>>
+    where
+        F: Send + Sync + 'static + Fn(&Request) -> Response,
+    {
+        if let Some(err) = self.error {
+            return Err(err.into());
+        }
+
+        #[cfg(feature = "ssl")]
+        let tls = self.tls;
+        #[cfg(not(feature = "ssl"))]
+        let tls: Option<tiny_http::SslConfig> = None;
+
+        let mut listeners = Vec::with_capacity(self.addrs.len() + self.listeners.len());
+        for addr in &self.addrs {
+            let listener = match self.socket_options {
+                Some(ref options) => options.bind(addr)?,
+                None => TcpListener::bind(addr)?,
+            };
+            listeners.push(listener);
+        }
+        listeners.extend(self.listeners);
+
+        let mut servers = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            servers.push(tiny_http::Server::from_listener(listener, tls.clone())?);
+        }
+
+        let mut servers = servers.into_iter();
+        let mut server = match servers.next() {
+            Some(first) => Server::from_tiny_http(first, handler),
+            None => return Err("no address to listen on".into()),
+        };
+        server.servers.extend(servers.map(Arc::new));
+
+        if let Some(pool_size) = self.pool_size {
+            server = server.pool_size(pool_size);
+        }
+
+        Ok(server)
+    }
+}
+
+impl fmt::Debug for ServerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerBuilder")
+            .field("addrs", &self.addrs)
+            .field("listeners", &self.listeners)
+            .field("socket_options", &self.socket_options)
+            .field("pool_size", &self.pool_size)
+            .finish()
+    }
+}
@@ -32,56 +32,442 @@
 //!     })
 //! }
 //! ```
+//!
+//! # Storing data
+//!
+//! The data of the sessions is kept by a [`SessionStore`](trait.SessionStore.html), which can be
+//! passed to [`session_with_store`](fn.session_with_store.html) or to
+//! [`SessionMiddleware::with_store`](struct.SessionMiddleware.html#method.with_store).
+//! [`MemorySessionStore`](struct.MemorySessionStore.html) keeps the data in memory. Implement
+//! the trait in order to store the sessions in a database, so that they survive a restart of the
+//! server and are shared by several instances.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use rouille::Request;
+//! use rouille::Response;
+//! use rouille::session::{self, MemorySessionStore, SessionStore};
+//!
+//! fn handle_request(request: &Request, store: Arc<dyn SessionStore>) -> Response {
+//!     session::session_with_store(request, "SID", 3600, store, |session| {
+//!         let visits = match session.load().unwrap() {
+//!             Some(data) => String::from_utf8_lossy(&data).parse().unwrap_or(0),
+//!             None => 0,
+//!         } + 1;
+//!         session.save(visits.to_string().into_bytes()).unwrap();
+//!         Response::text(format!("{} visits", visits))
+//!     })
+//! }
+//! # let _ = handle_request(&Request::fake_http("GET", "/", vec![], vec![]),
+//! #                        Arc::new(MemorySessionStore::new()));
+//! ```
 
-use rand;
-use rand::distributions::Alphanumeric;
-use rand::Rng;
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use input;
+use middleware::Middleware;
+use random::Random;
 use Request;
 use Response;
 
+/// Assigns a session to the client, and calls `inner` with it.
+///
+/// The session expires after `timeout_s` seconds of inactivity: the `Max-Age` of the cookie is
+/// refreshed on each request of the client.
 pub fn session<'r, F>(request: &'r Request, cookie_name: &str, timeout_s: u64, inner: F) -> Response
 where
     F: FnOnce(&Session<'r>) -> Response,
 {
+    let expiry = Expiry::new(timeout_s, None);
+    let session = start_session(request, cookie_name, None, expiry);
+    let response = inner(&session);
+    finish_session(&session, cookie_name, response)
+}
+
+/// Same as [`session`](fn.session.html), but the data of the session can be loaded from and
+/// saved to `store`.
+///
+/// The sessions that are unknown to the store or that have expired are discarded when the
+/// client sends their ID, and the client is given a new ID instead.
+pub fn session_with_store<'r, F>(
+    request: &'r Request,
+    cookie_name: &str,
+    timeout_s: u64,
+    store: Arc<dyn SessionStore>,
+    inner: F,
+) -> Response
+where
+    F: FnOnce(&Session<'r>) -> Response,
+{
+    let expiry = Expiry::new(timeout_s, None);
+    let session = start_session(request, cookie_name, Some(store), expiry);
+    let response = inner(&session);
+    finish_session(&session, cookie_name, response)
+}
+
+// When the sessions expire.
+#[derive(Debug, Copy, Clone)]
+struct Expiry {
+    idle_timeout: Duration,
+    max_lifetime: Option<Duration>,
+}
+
+impl Expiry {
+    #[inline]
+    fn new(timeout_s: u64, max_lifetime: Option<Duration>) -> Expiry {
+        Expiry {
+            idle_timeout: Duration::from_secs(timeout_s),
+            max_lifetime,
+        }
+    }
+
+    // Returns true if a session created at `created` and last used at `accessed` has expired.
+    fn is_expired(&self, created: u64, accessed: u64, now: u64) -> bool {
+        if now.saturating_sub(accessed) >= self.idle_timeout.as_secs() {
+            return true;
+        }
+        match self.max_lifetime {
+            Some(max) => now.saturating_sub(created) >= max.as_secs(),
+            None => false,
+        }
+    }
+
+    // Returns how long a session created at `created` has left to live if it's used now.
+    fn remaining(&self, created: u64, now: u64) -> Duration {
+        let age = Duration::from_secs(now.saturating_sub(created));
+        match self.max_lifetime {
+            Some(max) => {
+                let left = max
+                    .checked_sub(age)
+                    .unwrap_or_else(|| Duration::from_secs(0));
+                left.min(self.idle_timeout)
+            }
+            None => self.idle_timeout,
+        }
+    }
+}
+
+fn start_session<'r>(
+    request: &'r Request,
+    cookie_name: &str,
+    store: Option<Arc<dyn SessionStore>>,
+    expiry: Expiry,
+) -> Session<'r> {
     let mut cookie = input::cookies(request);
     let cookie = cookie.find(|&(ref k, _)| k == &cookie_name);
     let cookie = cookie.map(|(_, v)| v);
 
-    let session = if let Some(cookie) = cookie {
-        Session {
-            key_was_retreived: AtomicBool::new(false),
-            key_was_given: true,
-            key: cookie.into(),
+    let now = unix_now();
+    let mut session = Session {
+        key_was_retreived: AtomicBool::new(false),
+        key_was_given: false,
+        key: Cow::Borrowed(""),
+        next_key: String::new(),
+        regenerated: AtomicBool::new(false),
+        store,
+        expiry,
+        created: now,
+        data: Mutex::new(None),
+        load_failed: AtomicBool::new(false),
+        saved: AtomicBool::new(false),
+        deleted: AtomicBool::new(false),
+        flashes_in: Mutex::new(Vec::new()),
+        flashes_out: Mutex::new(Vec::new()),
+    };
+
+    if let Some(cookie) = cookie {
+        let known = match session.store {
+            None => true,
+            Some(ref store) => match store.load(cookie) {
+                Ok(Some(record)) => match Record::decode(&record) {
+                    Some(ref r) if expiry.is_expired(r.created, r.accessed, now) => {
+                        let _ = store.delete(cookie);
+                        false
+                    }
+                    Some(record) => {
+                        session.created = record.created;
+                        *session.data.get_mut().unwrap() = Some(record.data.to_owned());
+                        *session.flashes_in.get_mut().unwrap() = record.flashes;
+                        true
+                    }
+                    None => false,
+                },
+                Ok(None) => false,
+                Err(_) => {
+                    // Keeps the ID, so that a temporary failure of the store doesn't log out
+                    // the client.
+                    session.load_failed.store(true, Ordering::Relaxed);
+                    true
+                }
+            },
+        };
+
+        if known {
+            session.key_was_given = true;
+            session.key = cookie.into();
         }
+    }
+
+    let random = request.random();
+    if !session.key_was_given {
+        session.key = generate_session_id_with(&random).into();
+    }
+    // Generated in advance, so that `regenerate_id` can replace the ID returned by `id`.
+    session.next_key = generate_session_id_with(&random);
+    session
+}
+
+fn finish_session(session: &Session, cookie_name: &str, mut response: Response) -> Response {
+    let active = session.key_was_retreived.load(Ordering::Relaxed) || session.key_was_given;
+    if !active {
+        return response;
+    }
+
+    let now = unix_now();
+    let max_age = if session.deleted.load(Ordering::Relaxed) {
+        0
     } else {
-        Session {
-            key_was_retreived: AtomicBool::new(false),
-            key_was_given: false,
-            key: generate_session_id().into(),
+        let ttl = session.expiry.remaining(session.created, now);
+        if let Some(ref store) = session.store {
+            // Saving the session on each request refreshes its last access, if the handler
+            // didn't already save it.
+            if !session.saved.load(Ordering::Relaxed)
+                && !session.load_failed.load(Ordering::Relaxed)
+            {
+                let data = session.data.lock().unwrap();
+                let data = data.as_ref().map_or(&[][..], |d| &d[..]);
+                let _ = session.write(&**store, session.key(), data);
+            }
         }
+        ttl.as_secs()
     };
 
-    let mut response = inner(&session);
+    // TODO: use `get_mut()`
+    // FIXME: correct interactions with existing headers
+    // TODO: allow setting domain
+    let header_value = format!(
+        "{}={}; Max-Age={}; Path=/; HttpOnly",
+        cookie_name,
+        session.key(),
+        max_age
+    );
+    response
+        .headers
+        .push(("Set-Cookie".into(), header_value.into()));
+
+    response
+}
+
+// What is saved to the store for each session.
+//
+// The record starts with the Unix times of the creation and of the last access of the session,
+// followed by the number of flash messages and by the messages, then by the data of the user.
+// The integers are in big-endian, and each string is preceded by its length.
+struct Record<'a> {
+    created: u64,
+    accessed: u64,
+    flashes: Vec<(String, String)>,
+    data: &'a [u8],
+}
 
-    if session.key_was_retreived.load(Ordering::Relaxed) {
-        // TODO: use `get_mut()`
-        // FIXME: correct interactions with existing headers
-        // TODO: allow setting domain
-        let header_value = format!(
-            "{}={}; Max-Age={}; Path=/; HttpOnly",
-            cookie_name, session.key, timeout_s
-        );
-        response
-            .headers
-            .push(("Set-Cookie".into(), header_value.into()));
+impl<'a> Record<'a> {
+    fn encode(&self) -> Vec<u8> {
+        let mut record = Vec::with_capacity(20 + self.data.len());
+        record.extend_from_slice(&self.created.to_be_bytes());
+        record.extend_from_slice(&self.accessed.to_be_bytes());
+        record.extend_from_slice(&(self.flashes.len() as u32).to_be_bytes());
+        for (name, message) in &self.flashes {
+            for string in &[name, message] {
+                record.extend_from_slice(&(string.len() as u32).to_be_bytes());
+                record.extend_from_slice(string.as_bytes());
+            }
+        }
+        record.extend_from_slice(self.data);
+        record
     }
 
-    response
+    fn decode(mut record: &'a [u8]) -> Option<Record<'a>> {
+        fn take<'a>(record: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if record.len() < len {
+                return None;
+            }
+            let (taken, rest) = record.split_at(len);
+            *record = rest;
+            Some(taken)
+        }
+        fn int(record: &mut &[u8]) -> Option<u64> {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(take(record, 8)?);
+            Some(u64::from_be_bytes(buf))
+        }
+        fn len(record: &mut &[u8]) -> Option<usize> {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(take(record, 4)?);
+            Some(u32::from_be_bytes(buf) as usize)
+        }
+        fn string(record: &mut &[u8]) -> Option<String> {
+            let len = len(record)?;
+            String::from_utf8(take(record, len)?.to_owned()).ok()
+        }
+
+        let created = int(&mut record)?;
+        let accessed = int(&mut record)?;
+        let num_flashes = len(&mut record)?;
+        let mut flashes = Vec::new();
+        for _ in 0..num_flashes {
+            let name = string(&mut record)?;
+            flashes.push((name, string(&mut record)?));
+        }
+
+        Some(Record {
+            created,
+            accessed,
+            flashes,
+            data: record,
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Middleware that does the same as the [`session`](fn.session.html) function.
+///
+/// Since the handler doesn't receive the `Session` as a parameter, call
+/// [`with_current`](fn.with_current.html) from within the handler in order to access it.
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Request, Response};
+/// use rouille::middleware::Pipeline;
+/// use rouille::session::{self, SessionMiddleware};
+///
+/// let pipeline = Pipeline::new(|_: &Request| {
+///     let id = session::with_current(|session| session.id().to_owned()).unwrap();
+///     Response::text(format!("Session ID: {}", id))
+/// })
+/// .with(SessionMiddleware::new("SID", 3600));
+/// ```
+#[derive(Clone)]
+pub struct SessionMiddleware {
+    cookie_name: String,
+    expiry: Expiry,
+    store: Option<Arc<dyn SessionStore>>,
+}
+
+impl SessionMiddleware {
+    /// Builds a new `SessionMiddleware`. The parameters have the same meaning as for the
+    /// [`session`](fn.session.html) function.
+    #[inline]
+    pub fn new<S>(cookie_name: S, timeout_s: u64) -> SessionMiddleware
+    where
+        S: Into<String>,
+    {
+        SessionMiddleware {
+            cookie_name: cookie_name.into(),
+            expiry: Expiry::new(timeout_s, None),
+            store: None,
+        }
+    }
+
+    /// Loads and saves the data of the sessions with `store`, like
+    /// [`session_with_store`](fn.session_with_store.html).
+    #[inline]
+    pub fn with_store(mut self, store: Arc<dyn SessionStore>) -> SessionMiddleware {
+        self.store = Some(store);
+        self
+    }
+
+    /// Limits the lifetime of the sessions, counted from their creation and regardless of the
+    /// activity of the client. The `Max-Age` of the cookie never exceeds the lifetime that is
+    /// left.
+    ///
+    /// The creation time of the sessions is kept by the store, therefore this limit is only
+    /// enforced if the middleware has a store.
+    #[inline]
+    pub fn with_max_lifetime(mut self, max_lifetime: Duration) -> SessionMiddleware {
+        self.expiry.max_lifetime = Some(max_lifetime);
+        self
+    }
+}
+
+impl fmt::Debug for SessionMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SessionMiddleware")
+            .field("cookie_name", &self.cookie_name)
+            .field("idle_timeout", &self.expiry.idle_timeout)
+            .field("max_lifetime", &self.expiry.max_lifetime)
+            .field("store", &self.store.is_some())
+            .finish()
+    }
+}
+
+impl Middleware for SessionMiddleware {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        let session = start_session(request, &self.cookie_name, self.store.clone(), self.expiry);
+        let session = Rc::new(session.into_owned());
+
+        let response = {
+            let _guard = CurrentSessionGuard::enter(session.clone());
+            next(request)
+        };
+
+        finish_session(&session, &self.cookie_name, response)
+    }
+}
+
+thread_local! {
+    static CURRENT_SESSION: RefCell<Option<Rc<Session<'static>>>> = RefCell::new(None);
+}
+
+// Makes a session the current one, and restores the previous one when dropped, including if the
+// handler panics.
+struct CurrentSessionGuard {
+    previous: Option<Rc<Session<'static>>>,
+}
+
+impl CurrentSessionGuard {
+    fn enter(session: Rc<Session<'static>>) -> CurrentSessionGuard {
+        let previous = CURRENT_SESSION.with(|c| c.replace(Some(session)));
+        CurrentSessionGuard { previous }
+    }
+}
+
+impl Drop for CurrentSessionGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_SESSION.with(|c| *c.borrow_mut() = previous);
+    }
+}
+
+/// Calls `f` with the session of the request being handled by a
+/// [`SessionMiddleware`](struct.SessionMiddleware.html).
+///
+/// Returns `None` if this is not called from within a handler wrapped by a `SessionMiddleware`.
+pub fn with_current<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&Session) -> R,
+{
+    let session = CURRENT_SESSION.with(|c| c.borrow().clone());
+    session.map(|s| f(&s))
 }
 
 /// Contains the ID of the session.
@@ -89,6 +475,27 @@ pub struct Session<'r> {
     key_was_retreived: AtomicBool,
     key_was_given: bool,
     key: Cow<'r, str>,
+    // Replaces `key` once `regenerate_id` has been called.
+    next_key: String,
+    regenerated: AtomicBool,
+    store: Option<Arc<dyn SessionStore>>,
+    expiry: Expiry,
+    // Unix time of the creation of the session.
+    created: u64,
+    // Data loaded from the store or saved with `save`.
+    data: Mutex<Option<Vec<u8>>>,
+    load_failed: AtomicBool,
+    saved: AtomicBool,
+    deleted: AtomicBool,
+    // Flash messages set during the previous request, and during this one.
+    flashes_in: Mutex<Vec<(String, String)>>,
+    flashes_out: Mutex<Vec<(String, String)>>,
+}
+
+// `Session` is shared by reference with the handler, and must stay `Sync`.
+fn _assert_sync<T: Sync>() {}
+fn _assert_session_is_sync() {
+    _assert_sync::<Session>();
 }
 
 impl<'r> Session<'r> {
@@ -104,15 +511,263 @@ impl<'r> Session<'r> {
     #[inline]
     pub fn id(&self) -> &str {
         self.key_was_retreived.store(true, Ordering::Relaxed);
-        &self.key
+        self.key()
+    }
+
+    #[inline]
+    pub(crate) fn key(&self) -> &str {
+        if self.regenerated.load(Ordering::Relaxed) {
+            &self.next_key
+        } else {
+            &self.key
+        }
+    }
+
+    /// Replaces the ID of the session with a new one, and sends it to the client.
+    ///
+    /// Call this after the user logs in or when their privileges change, so that an ID that
+    /// an attacker managed to give to the client beforehand becomes useless. The data in the
+    /// store is moved to the new ID.
+    ///
+    /// Returns an error if the store failed, or if the ID was already regenerated during this
+    /// request.
+    pub fn regenerate_id(&self) -> io::Result<()> {
+        if self.regenerated.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the session ID was already regenerated during this request",
+            ));
+        }
+
+        if let Some(ref store) = self.store {
+            if !self.deleted.load(Ordering::Relaxed) {
+                let data = self.load()?;
+                let data = data.as_ref().map_or(&[][..], |d| &d[..]);
+                self.write(&**store, &self.next_key, data)?;
+                if self.key_was_given {
+                    store.delete(&self.key)?;
+                }
+                self.saved.store(true, Ordering::Relaxed);
+            }
+        }
+
+        self.regenerated.store(true, Ordering::Relaxed);
+        self.key_was_retreived.store(true, Ordering::Relaxed);
+        Ok(())
     }
 
-    /*/// Generates a new id. This modifies the value returned by `id()`.
-    // TODO: implement
+    /// Returns the data of the session from the store, or `None` if the store has no data for
+    /// this session.
+    ///
+    /// The data is loaded when the session starts. Returns an error if the store failed to load
+    /// it, and always returns `None` if the session has no store.
+    pub fn load(&self) -> io::Result<Option<Vec<u8>>> {
+        if self.load_failed.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "failed to load the session from the store",
+            ));
+        }
+        Ok(self.data.lock().unwrap().clone())
+    }
+
+    /// Saves the data of the session to the store. The store discards it once the session
+    /// expires.
+    ///
+    /// Returns an error if the session has no store.
+    pub fn save(&self, data: Vec<u8>) -> io::Result<()> {
+        let store = match self.store {
+            Some(ref store) => store,
+            None => return Err(no_store()),
+        };
+        self.write(&**store, self.id(), &data)?;
+        *self.data.lock().unwrap() = Some(data);
+        self.load_failed.store(false, Ordering::Relaxed);
+        self.saved.store(true, Ordering::Relaxed);
+        self.deleted.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Deletes the data of the session from the store, for example when the user logs out. The
+    /// cookie of the client is removed as well.
+    ///
+    /// Returns an error if the session has no store.
+    pub fn delete(&self) -> io::Result<()> {
+        match self.store {
+            Some(ref store) => store.delete(self.key())?,
+            None => return Err(no_store()),
+        }
+        *self.data.lock().unwrap() = None;
+        self.saved.store(false, Ordering::Relaxed);
+        self.deleted.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Sets a flash message, which can be read once with `take_flash` during the next request
+    /// of the client, for example after a redirection. The flash messages that are not read
+    /// during the next request are discarded.
+    ///
+    /// Setting a message with the same name again replaces it. Flash messages are kept by the
+    /// store, and are ignored if the session has no store.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::Response;
+    /// use rouille::session::Session;
+    ///
+    /// fn save_settings(session: &Session) -> Response {
+    ///     // ...
+    ///     session.set_flash("notice", "Your changes were saved.");
+    ///     Response::redirect_303("/settings")
+    /// }
+    ///
+    /// fn show_settings(session: &Session) -> Response {
+    ///     let notice = session.take_flash("notice").unwrap_or_default();
+    ///     Response::html(format!("<p>{}</p>", notice))
+    /// }
+    /// ```
+    pub fn set_flash<N, M>(&self, name: N, message: M)
+    where
+        N: Into<String>,
+        M: Into<String>,
+    {
+        let name = name.into();
+        let mut flashes = self.flashes_out.lock().unwrap();
+        flashes.retain(|f| f.0 != name);
+        flashes.push((name, message.into()));
+        self.key_was_retreived.store(true, Ordering::Relaxed);
+        // The flash messages are saved at the end of the request.
+        self.saved.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns the flash message with the given name that was set during the previous request,
+    /// and removes it.
+    pub fn take_flash(&self, name: &str) -> Option<String> {
+        let mut flashes = self.flashes_in.lock().unwrap();
+        let pos = flashes.iter().position(|f| f.0 == name)?;
+        Some(flashes.remove(pos).1)
+    }
+
+    // Saves the data and the outgoing flash messages of the session under `key`.
+    fn write(&self, store: &dyn SessionStore, key: &str, data: &[u8]) -> io::Result<()> {
+        let now = unix_now();
+        let record = Record {
+            created: self.created,
+            accessed: now,
+            flashes: self.flashes_out.lock().unwrap().clone(),
+            data,
+        };
+        store.save(
+            key,
+            record.encode(),
+            self.expiry.remaining(self.created, now),
+        )
+    }
+
+    fn into_owned(self) -> Session<'static> {
+        Session {
+            key_was_retreived: self.key_was_retreived,
+            key_was_given: self.key_was_given,
+            key: Cow::Owned(self.key.into_owned()),
+            next_key: self.next_key,
+            regenerated: self.regenerated,
+            store: self.store,
+            expiry: self.expiry,
+            created: self.created,
+            data: self.data,
+            load_failed: self.load_failed,
+            saved: self.saved,
+            deleted: self.deleted,
+            flashes_in: self.flashes_in,
+            flashes_out: self.flashes_out,
+        }
+    }
+}
+
+fn no_store() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the session has no store")
+}
+
+/// Storage of the data of the sessions, identified by the session ID.
+///
+/// The data is opaque to the store. Implementations can use the `ttl` given to `save` to
+/// discard the sessions that are no longer used, for example with the expiration of the keys of
+/// Redis.
+pub trait SessionStore: Send + Sync {
+    /// Returns the data of a session, or `None` if there is none or if it has expired.
+    fn load(&self, id: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Creates or replaces the data of a session. The data can be discarded after `ttl`.
+    fn save(&self, id: &str, data: Vec<u8>, ttl: Duration) -> io::Result<()>;
+
+    /// Deletes the data of a session. Deleting a session that doesn't exist isn't an error.
+    fn delete(&self, id: &str) -> io::Result<()>;
+}
+
+/// A `SessionStore` that keeps the data in memory.
+///
+/// The expired sessions are removed when they are loaded. Call
+/// [`purge_expired`](#method.purge_expired) periodically, for example from a
+/// [subsystem](../subsystems/index.html), in order to remove the sessions that are never loaded
+/// again.
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, (Vec<u8>, Instant)>>,
+}
+
+impl MemorySessionStore {
+    /// Builds a new empty store.
+    #[inline]
+    pub fn new() -> MemorySessionStore {
+        MemorySessionStore::default()
+    }
+
+    /// Returns the number of sessions in the store, including the expired ones that haven't
+    /// been removed yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    /// Returns true if the store is empty.
     #[inline]
-    pub fn regenerate_id(&self) {
-        unimplemented!()
-    }*/
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes the expired sessions.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.sessions.lock().unwrap().retain(|_, s| s.1 > now);
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, id: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired = match sessions.get(id) {
+            Some(session) if session.1 > Instant::now() => return Ok(Some(session.0.clone())),
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            sessions.remove(id);
+        }
+        Ok(None)
+    }
+
+    fn save(&self, id: &str, data: Vec<u8>, ttl: Duration) -> io::Result<()> {
+        let expires = Instant::now() + ttl;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(id.to_owned(), (data, expires));
+        Ok(())
+    }
+
+    fn delete(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
 }
 
 /// Generates a string suitable for a session ID.
@@ -120,18 +775,250 @@ impl<'r> Session<'r> {
 /// The output string doesn't contain any punctuation or character such as quotes or brackets
 /// that could need to be escaped.
 pub fn generate_session_id() -> String {
+    generate_session_id_with(&Random::os())
+}
+
+/// Same as `generate_session_id`, but uses the given source of randomness.
+///
+/// The sessions of this module use the source of the request, as returned by
+/// [`Request::random`](../struct.Request.html#method.random).
+pub fn generate_session_id_with(random: &Random) -> String {
     // 5e+114 possibilities is reasonable.
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .map(|c| char::from(c))
-        .filter(|&c| {
-            ('a'..='z').contains(&c) || ('A'..='Z').contains(&c) || ('0'..='9').contains(&c)
-        })
-        .take(64)
-        .collect::<String>()
+    random.alphanumeric(64)
 }
 
 #[test]
 fn test_generate_session_id() {
     assert!(generate_session_id().len() >= 32);
 }
+
+#[test]
+fn test_seeded_session_id() {
+    let request = Request::fake_http("GET", "/", vec![], vec![]);
+    request.extensions().insert(Random::seeded(3));
+    let first = start_session(&request, "SID", None, Expiry::new(3600, None))
+        .id()
+        .to_owned();
+    let second = start_session(&request, "SID", None, Expiry::new(3600, None))
+        .id()
+        .to_owned();
+    assert_ne!(first, second);
+
+    let request = Request::fake_http("GET", "/", vec![], vec![]);
+    request.extensions().insert(Random::seeded(3));
+    assert_eq!(
+        start_session(&request, "SID", None, Expiry::new(3600, None)).id(),
+        first
+    );
+}
+
+#[test]
+fn test_session_middleware() {
+    use middleware::Pipeline;
+
+    let pipeline = Pipeline::new(|_: &Request| {
+        let id = with_current(|s| s.id().to_owned()).unwrap();
+        Response::text(id)
+    })
+    .with(SessionMiddleware::new("SID", 3600));
+
+    let request = Request::fake_http("GET", "/", vec![], vec![]);
+    let response = pipeline.handle(&request);
+    assert!(response
+        .headers
+        .iter()
+        .any(|h| h.0 == "Set-Cookie" && h.1.starts_with("SID=")));
+
+    let cookie = vec![("Cookie".to_owned(), "SID=abcdef".to_owned())];
+    let request = Request::fake_http("GET", "/", cookie, vec![]);
+    let response = pipeline.handle(&request);
+    assert!(response
+        .headers
+        .iter()
+        .any(|h| h.0 == "Set-Cookie" && h.1.starts_with("SID=abcdef;")));
+
+    assert!(with_current(|_| ()).is_none());
+}
+
+#[test]
+fn test_session_store() {
+    use middleware::Pipeline;
+
+    let store = Arc::new(MemorySessionStore::new());
+    let pipeline = Pipeline::new(|_: &Request| {
+        with_current(|session| {
+            let data = session.load().unwrap().unwrap_or_default();
+            let mut visits = String::from_utf8(data).unwrap();
+            visits.push('x');
+            session.save(visits.clone().into_bytes()).unwrap();
+            Response::text(visits)
+        })
+        .unwrap()
+    })
+    .with(SessionMiddleware::new("SID", 3600).with_store(store.clone()));
+
+    let body = |response: Response| {
+        let mut body = String::new();
+        let (mut reader, _) = response.data.into_reader_and_size();
+        io::Read::read_to_string(&mut reader, &mut body).unwrap();
+        body
+    };
+
+    let response = pipeline.handle(&Request::fake_http("GET", "/", vec![], vec![]));
+    let cookie = response
+        .headers
+        .iter()
+        .find(|h| h.0 == "Set-Cookie")
+        .map(|h| h.1.split(';').next().unwrap().to_owned())
+        .unwrap();
+    assert_eq!(body(response), "x");
+
+    let request = Request::fake_http("GET", "/", vec![("Cookie".to_owned(), cookie)], vec![]);
+    assert_eq!(body(pipeline.handle(&request)), "xx");
+    assert_eq!(store.len(), 1);
+
+    store.save("old", vec![1], Duration::from_secs(0)).unwrap();
+    assert_eq!(store.load("old").unwrap(), None);
+    store.save("old", vec![1], Duration::from_secs(0)).unwrap();
+    store.purge_expired();
+    assert_eq!(store.len(), 1);
+
+    let request = Request::fake_http("GET", "/", vec![], vec![]);
+    let session = start_session(&request, "SID", None, Expiry::new(3600, None));
+    assert_eq!(session.load().unwrap(), None);
+    assert!(session.save(vec![]).is_err());
+}
+
+#[test]
+fn test_session_expiry() {
+    use middleware::Pipeline;
+
+    let store = Arc::new(MemorySessionStore::new());
+    let pipeline = Pipeline::new(|request: &Request| {
+        with_current(|session| {
+            if request.url() == "/logout" {
+                session.delete().unwrap();
+            }
+            Response::text(session.id())
+        })
+        .unwrap()
+    })
+    .with(
+        SessionMiddleware::new("SID", 60)
+            .with_store(store.clone())
+            .with_max_lifetime(Duration::from_secs(100)),
+    );
+
+    let cookie = |url: &str, sid: &str| {
+        let headers = vec![("Cookie".to_owned(), format!("SID={}", sid))];
+        let response = pipeline.handle(&Request::fake_http("GET", url, headers, vec![]));
+        let cookie = response.headers.iter().find(|h| h.0 == "Set-Cookie");
+        cookie.unwrap().1.to_string()
+    };
+    let put = |sid: &str, created: u64, accessed: u64| {
+        let record = Record {
+            created,
+            accessed,
+            flashes: Vec::new(),
+            data: b"data",
+        };
+        let record = record.encode();
+        store.save(sid, record, Duration::from_secs(3600)).unwrap();
+    };
+    let now = unix_now();
+
+    // Sliding renewal.
+    put("active", now - 10, now - 10);
+    assert_eq!(
+        cookie("/", "active"),
+        "SID=active; Max-Age=60; Path=/; HttpOnly"
+    );
+    let record = store.load("active").unwrap().unwrap();
+    let record = Record::decode(&record).unwrap();
+    assert_eq!((record.created, record.data), (now - 10, &b"data"[..]));
+    assert!(record.accessed >= now);
+
+    // The cookie doesn't outlive the maximum lifetime.
+    put("old", now - 80, now - 10);
+    assert!(cookie("/", "old").starts_with("SID=old; Max-Age=2"));
+
+    // Expired or unknown sessions are replaced.
+    put("idle", now - 70, now - 70);
+    assert!(!cookie("/", "idle").starts_with("SID=idle;"));
+    assert_eq!(store.load("idle").unwrap(), None);
+    put("ancient", now - 200, now - 10);
+    assert!(!cookie("/", "ancient").starts_with("SID=ancient;"));
+    assert!(!cookie("/", "unknown").starts_with("SID=unknown;"));
+
+    // Deleting the session removes the cookie.
+    assert!(cookie("/logout", "active").starts_with("SID=active; Max-Age=0;"));
+    assert_eq!(store.load("active").unwrap(), None);
+}
+
+#[test]
+fn test_regenerate_id() {
+    let store = Arc::new(MemorySessionStore::new());
+    let headers = vec![("Cookie".to_owned(), "SID=fixed".to_owned())];
+    let request = Request::fake_http("POST", "/login", headers, vec![]);
+    let record = Record {
+        created: unix_now(),
+        accessed: unix_now(),
+        flashes: Vec::new(),
+        data: b"data",
+    };
+    store
+        .save("fixed", record.encode(), Duration::from_secs(60))
+        .unwrap();
+
+    let response = session_with_store(&request, "SID", 60, store.clone(), |session| {
+        assert_eq!(session.id(), "fixed");
+        session.regenerate_id().unwrap();
+        assert!(session.regenerate_id().is_err());
+        assert_ne!(session.id(), "fixed");
+        assert_eq!(session.load().unwrap(), Some(b"data".to_vec()));
+        Response::text(session.id())
+    });
+
+    let cookie = response.headers.iter().find(|h| h.0 == "Set-Cookie");
+    let cookie = cookie.unwrap().1.to_string();
+    let id = &cookie["SID=".len()..cookie.find(';').unwrap()];
+    assert_ne!(id, "fixed");
+    assert_eq!(store.load("fixed").unwrap(), None);
+    let record = store.load(id).unwrap().unwrap();
+    assert_eq!(Record::decode(&record).unwrap().data, b"data");
+}
+
+#[test]
+fn test_flash() {
+    let store: Arc<dyn SessionStore> = Arc::new(MemorySessionStore::new());
+    let handle = |cookie: Option<&str>, f: &dyn Fn(&Session)| {
+        let headers = cookie
+            .map(|c| vec![("Cookie".to_owned(), format!("SID={}", c))])
+            .unwrap_or_default();
+        let request = Request::fake_http("GET", "/", headers, vec![]);
+        session_with_store(&request, "SID", 60, store.clone(), |session| {
+            f(session);
+            Response::text(session.id())
+        })
+    };
+
+    let response = handle(None, &|session| {
+        session.set_flash("notice", "first");
+        session.set_flash("notice", "saved");
+        session.set_flash("error", "none");
+        session.save(b"data".to_vec()).unwrap();
+    });
+    let cookie = response.headers.iter().find(|h| h.0 == "Set-Cookie");
+    let cookie = cookie.unwrap().1.to_string();
+    let id = &cookie["SID=".len()..cookie.find(';').unwrap()];
+
+    handle(Some(id), &|session| {
+        assert_eq!(session.take_flash("notice"), Some("saved".to_owned()));
+        assert_eq!(session.take_flash("notice"), None);
+        assert_eq!(session.load().unwrap(), Some(b"data".to_vec()));
+    });
+    handle(Some(id), &|session| {
+        assert_eq!(session.take_flash("error"), None);
+        assert_eq!(session.load().unwrap(), Some(b"data".to_vec()));
+    });
+}
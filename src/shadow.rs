@@ -0,0 +1,377 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Comparing a new implementation of a handler with the current one.
+//!
+//! A [`Shadow`](struct.Shadow.html) runs a *primary* handler and a *shadow* handler on the same
+//! requests. The client always receives the response of the primary handler, and the
+//! differences between the two responses are reported to a callback. This makes it possible to
+//! check a rewrite of an endpoint against production traffic before switching to it.
+//!
+//! The bodies are compared structurally if both are JSON, so that for example the order of the
+//! keys of an object doesn't matter. Other bodies are compared byte by byte.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rouille::{Request, Response};
+//! use rouille::shadow::Shadow;
+//!
+//! fn old_handler(request: &Request) -> Response {
+//!     Response::json(&vec![1, 2, 3])
+//! }
+//!
+//! fn new_handler(request: &Request) -> Response {
+//!     Response::json(&vec![1, 2, 3])
+//! }
+//!
+//! let shadow = Shadow::new(old_handler, new_handler)
+//!     .sample_rate(0.05)
+//!     .on_diff(|request, diff| {
+//!         eprintln!("{} {} differs: {:?}", request.method(), request.raw_url(), diff);
+//!     });
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| shadow.handle(request));
+//! ```
+
+use serde_json::Value;
+use std::fmt;
+use std::io::Read;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use Request;
+use Response;
+use ResponseBody;
+
+/// Runs a shadow handler next to a primary handler and reports the differences between their
+/// responses.
+pub struct Shadow<P, S> {
+    primary: P,
+    shadow: S,
+    sample_rate: f64,
+    on_diff: Option<Box<DiffFn>>,
+}
+
+// Signature of the function registered with `Shadow::on_diff`.
+type DiffFn = dyn Fn(&Request, &ShadowDiff) + Send + Sync;
+
+impl<P, S> Shadow<P, S>
+where
+    P: Fn(&Request) -> Response,
+    S: Fn(&Request) -> Response,
+{
+    /// Builds a `Shadow` that runs `shadow` on every request next to `primary`.
+    pub fn new(primary: P, shadow: S) -> Shadow<P, S> {
+        Shadow {
+            primary,
+            shadow,
+            sample_rate: 1.0,
+            on_diff: None,
+        }
+    }
+
+    /// Sets the proportion of requests, between 0 and 1, for which the shadow handler runs.
+    /// Defaults to 1.
+    ///
+    /// The requests are sampled with the source of randomness of the request, as returned by
+    /// [`Request::random`](../struct.Request.html#method.random).
+    #[inline]
+    pub fn sample_rate(mut self, sample_rate: f64) -> Shadow<P, S> {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Sets the function called when the responses of the two handlers differ.
+    #[inline]
+    pub fn on_diff<F>(mut self, on_diff: F) -> Shadow<P, S>
+    where
+        F: Fn(&Request, &ShadowDiff) + Send + Sync + 'static,
+    {
+        self.on_diff = Some(Box::new(on_diff));
+        self
+    }
+
+    /// Processes a request, and returns the response of the primary handler.
+    ///
+    /// For the sampled requests, the body of the request and the bodies of the responses are
+    /// read in memory, and the shadow handler runs after the primary one, which adds its
+    /// duration to the latency of the request. The shadow handler receives a copy of the request
+    /// with empty [extensions](../struct.Request.html#method.extensions), and a panic in it is
+    /// reported as a `500` response.
+    pub fn handle(&self, request: &Request) -> Response {
+        if !self.is_sampled(request) {
+            return (self.primary)(request);
+        }
+
+        let mut body = Vec::new();
+        if let Some(mut data) = request.data() {
+            // A body that can't be read is seen as empty by both handlers.
+            let _ = data.read_to_end(&mut body);
+        }
+
+        let primary_request = request.with_data(body.clone(), true);
+        let (primary, primary_body) = buffer((self.primary)(&primary_request));
+
+        let shadow_request = request.with_data(body, false);
+        let shadow = panic::catch_unwind(AssertUnwindSafe(|| (self.shadow)(&shadow_request)))
+            .unwrap_or_else(|_| Response::empty_404().with_status_code(500));
+        let (shadow, shadow_body) = buffer(shadow);
+
+        let diff = ShadowDiff {
+            primary_status: primary.status_code,
+            shadow_status: shadow.status_code,
+            body: diff_bodies(&primary_body, &shadow_body),
+        };
+        if !diff.is_empty() {
+            if let Some(ref on_diff) = self.on_diff {
+                on_diff(request, &diff);
+            }
+        }
+
+        primary
+    }
+
+    fn is_sampled(&self, request: &Request) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        if self.sample_rate <= 0.0 {
+            return false;
+        }
+
+        (request.random().next_u64() as f64 / u64::max_value() as f64) < self.sample_rate
+    }
+}
+
+impl<P, S> fmt::Debug for Shadow<P, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shadow")
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
+
+/// The differences between the responses of the primary and shadow handlers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowDiff {
+    /// The status code of the response of the primary handler.
+    pub primary_status: u16,
+    /// The status code of the response of the shadow handler.
+    pub shadow_status: u16,
+    /// The differences between the bodies. Empty if the bodies are the same.
+    pub body: Vec<BodyDifference>,
+}
+
+impl ShadowDiff {
+    /// Returns true if the status codes and the bodies are the same.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.primary_status == self.shadow_status && self.body.is_empty()
+    }
+}
+
+/// A difference between the bodies of the primary and shadow responses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BodyDifference {
+    /// The location of the difference in the JSON documents, as a JSON pointer such as
+    /// `/users/0/name`. The empty string designates the whole body.
+    pub path: String,
+    /// The value in the primary response, or `None` if it doesn't exist. JSON values are
+    /// serialized as JSON.
+    pub primary: Option<String>,
+    /// The value in the shadow response, or `None` if it doesn't exist.
+    pub shadow: Option<String>,
+}
+
+// Reads the body of a response in memory, and returns the response with an identical body.
+fn buffer(mut response: Response) -> (Response, Vec<u8>) {
+    let default_error = response.data.default_error;
+    let (mut reader, _) = response.data.into_reader_and_size();
+    let mut data = Vec::new();
+    let _ = reader.read_to_end(&mut data);
+
+    response.data = ResponseBody::from_data(data.clone());
+    response.data.default_error = default_error;
+    (response, data)
+}
+
+fn diff_bodies(primary: &[u8], shadow: &[u8]) -> Vec<BodyDifference> {
+    let mut differences = Vec::new();
+
+    match (
+        serde_json::from_slice::<Value>(primary),
+        serde_json::from_slice::<Value>(shadow),
+    ) {
+        (Ok(primary), Ok(shadow)) => diff_values("", &primary, &shadow, &mut differences),
+        _ => {
+            if primary != shadow {
+                differences.push(BodyDifference {
+                    path: String::new(),
+                    primary: Some(String::from_utf8_lossy(primary).into_owned()),
+                    shadow: Some(String::from_utf8_lossy(shadow).into_owned()),
+                });
+            }
+        }
+    }
+
+    differences
+}
+
+fn diff_values(path: &str, primary: &Value, shadow: &Value, out: &mut Vec<BodyDifference>) {
+    match (primary, shadow) {
+        (Value::Object(primary), Value::Object(shadow)) => {
+            for (key, value) in primary {
+                let path = object_path(path, key);
+                match shadow.get(key) {
+                    Some(other) => diff_values(&path, value, other, out),
+                    None => push_difference(out, path, Some(value), None),
+                }
+            }
+            for (key, value) in shadow {
+                if !primary.contains_key(key) {
+                    push_difference(out, object_path(path, key), None, Some(value));
+                }
+            }
+        }
+        (Value::Array(primary), Value::Array(shadow)) => {
+            for index in 0..primary.len().max(shadow.len()) {
+                let path = format!("{}/{}", path, index);
+                match (primary.get(index), shadow.get(index)) {
+                    (Some(value), Some(other)) => diff_values(&path, value, other, out),
+                    (value, other) => push_difference(out, path, value, other),
+                }
+            }
+        }
+        (primary, shadow) => {
+            if primary != shadow {
+                push_difference(out, path.to_owned(), Some(primary), Some(shadow));
+            }
+        }
+    }
+}
+
+// Appends a key to a JSON pointer, escaping it as required by RFC 6901.
+fn object_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn push_difference(
+    out: &mut Vec<BodyDifference>,
+    path: String,
+    primary: Option<&Value>,
+    shadow: Option<&Value>,
+) {
+    out.push(BodyDifference {
+        path,
+        primary: primary.map(|v| v.to_string()),
+        shadow: shadow.map(|v| v.to_string()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BodyDifference;
+    use super::Shadow;
+    use super::ShadowDiff;
+    use std::io::Read;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use Request;
+    use Response;
+
+    #[test]
+    fn reports_json_differences() {
+        let diffs = Arc::new(Mutex::new(Vec::new()));
+        let shadow = {
+            let diffs = diffs.clone();
+            Shadow::new(
+                |request: &Request| {
+                    let mut body = String::new();
+                    request.data().unwrap().read_to_string(&mut body).unwrap();
+                    Response::from_data(
+                        "application/json",
+                        format!(r#"{{"echo":"{}","items":[1,2],"a/b":1}}"#, body),
+                    )
+                },
+                |request: &Request| {
+                    let mut body = String::new();
+                    request.data().unwrap().read_to_string(&mut body).unwrap();
+                    Response::from_data(
+                        "application/json",
+                        format!(r#"{{"items":[1,3,4],"echo":"{}"}}"#, body),
+                    )
+                    .with_status_code(201)
+                },
+            )
+            .on_diff(move |_, diff| diffs.lock().unwrap().push(diff.clone()))
+        };
+
+        let request = Request::fake_http("POST", "/", vec![], b"hi".to_vec());
+        let response = shadow.handle(&request);
+        assert_eq!(response.status_code, 200);
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, r#"{"echo":"hi","items":[1,2],"a/b":1}"#);
+
+        let diff = |path: &str, primary: Option<&str>, shadow: Option<&str>| BodyDifference {
+            path: path.to_owned(),
+            primary: primary.map(|s| s.to_owned()),
+            shadow: shadow.map(|s| s.to_owned()),
+        };
+        assert_eq!(
+            *diffs.lock().unwrap(),
+            vec![ShadowDiff {
+                primary_status: 200,
+                shadow_status: 201,
+                body: vec![
+                    diff("/a~1b", Some("1"), None),
+                    diff("/items/1", Some("2"), Some("3")),
+                    diff("/items/2", None, Some("4")),
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn identical_and_unsampled() {
+        let calls = Arc::new(Mutex::new(0));
+        let shadow = {
+            let calls = calls.clone();
+            Shadow::new(
+                |_: &Request| Response::text("a"),
+                |_: &Request| -> Response { panic!("shadow") },
+            )
+            .on_diff(move |_, diff| {
+                assert_eq!(diff.shadow_status, 500);
+                *calls.lock().unwrap() += 1;
+            })
+        };
+
+        let request = Request::fake_http("GET", "/", vec![], vec![]);
+        assert_eq!(shadow.handle(&request).status_code, 200);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        let shadow = shadow.sample_rate(0.0);
+        assert_eq!(shadow.handle(&request).status_code, 200);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        let same = Shadow::new(
+            |_: &Request| Response::json(&vec![1]),
+            |_: &Request| Response::from_data("application/json", " [ 1 ] "),
+        )
+        .on_diff(|_, _| panic!("no difference expected"));
+        assert_eq!(same.handle(&request).status_code, 200);
+    }
+}
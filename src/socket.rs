@@ -0,0 +1,173 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::io;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+
+/// Options applied to the listening socket of a `Server`.
+///
+/// Pass this struct to [`Server::with_socket_options`](struct.Server.html#method.with_socket_options).
+///
+/// The `nodelay` and `keepalive` options are set on the listening socket and are inherited by
+/// the connections it accepts on Linux, BSD and macOS.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use rouille::{Response, Server, SocketOptions};
+///
+/// let options = SocketOptions {
+///     nodelay: true,
+///     backlog: 1024,
+///     keepalive: Some(Duration::from_secs(60)),
+///     .. SocketOptions::default()
+/// };
+///
+/// let server = Server::with_socket_options("0.0.0.0:8000", &options, |request| {
+///     Response::text("hello world")
+/// }).unwrap();
+/// server.run();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// If true, sets `TCP_NODELAY` so that small responses are not delayed by Nagle's
+    /// algorithm. Defaults to `false`.
+    pub nodelay: bool,
+
+    /// If true, sets `SO_REUSEPORT` so that multiple processes can listen on the same port and
+    /// let the kernel balance connections between them. Defaults to `false`.
+    ///
+    /// Only supported on Unix platforms. Building a server with this option on other platforms
+    /// returns an error.
+    pub reuse_port: bool,
+
+    /// Maximum number of pending connections that haven't been accepted yet. Defaults to `128`,
+    /// which is what the standard library uses.
+    pub backlog: i32,
+
+    /// If `Some`, enables `SO_KEEPALIVE` with the given idle time before the first probe is
+    /// sent. Defaults to `None`.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for SocketOptions {
+    #[inline]
+    fn default() -> SocketOptions {
+        SocketOptions {
+            nodelay: false,
+            reuse_port: false,
+            backlog: 128,
+            keepalive: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Binds a listening socket to the first address of `addr` that works, and applies the
+    /// options to it.
+    pub fn bind<A>(&self, addr: A) -> io::Result<TcpListener>
+    where
+        A: ToSocketAddrs,
+    {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match self.bind_one(&addr) {
+                Ok(listener) => return Ok(listener),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
+    }
+
+    fn bind_one(&self, addr: &SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+
+        // This is what the standard library does as well.
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+
+        if self.reuse_port {
+            set_reuse_port(&socket)?;
+        }
+
+        if self.nodelay {
+            socket.set_nodelay(true)?;
+        }
+
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+
+        socket.bind(&(*addr).into())?;
+        socket.listen(self.backlog)?;
+        Ok(socket.into())
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn bind_default() {
+        let listener = SocketOptions::default().bind("127.0.0.1:0").unwrap();
+        assert!(listener.local_addr().unwrap().port() != 0);
+    }
+
+    #[test]
+    fn bind_all_options() {
+        let options = SocketOptions {
+            nodelay: true,
+            reuse_port: cfg!(unix),
+            backlog: 16,
+            keepalive: Some(Duration::from_secs(30)),
+        };
+
+        assert!(options.bind("127.0.0.1:0").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reuse_port_shares_address() {
+        let options = SocketOptions {
+            reuse_port: true,
+            ..SocketOptions::default()
+        };
+
+        let first = options.bind("127.0.0.1:0").unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(options.bind(addr).is_ok());
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Pushing events to the client with server-sent events.
+//!
+//! [`Response::event_stream`](../struct.Response.html#method.event_stream) returns a response
+//! and an [`EventSender`](struct.EventSender.html). The response keeps the connection open, and
+//! each event sent with the `EventSender` is immediately transmitted to the client. On the
+//! client side, the events are received with the `EventSource` JavaScript API.
+//!
+//! Since the response is only sent after the handler returns, the `EventSender` must be given
+//! to another thread. The stream ends when all the clones of the `EventSender` are dropped.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::thread;
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::sse::Event;
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     let (response, sender) = Response::event_stream();
+//!     let mut next_id: u64 = request.last_event_id().and_then(|id| id.parse().ok()).unwrap_or(0);
+//!
+//!     thread::spawn(move || loop {
+//!         next_id += 1;
+//!         let event = Event::new("tick").event("clock").id(next_id.to_string());
+//!         if sender.send(event).is_err() {
+//!             break;      // The client has disconnected.
+//!         }
+//!         thread::sleep(Duration::from_secs(1));
+//!     });
+//!
+//!     response
+//! });
+//! ```
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Cursor;
+use std::io::Read;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use Response;
+use ResponseBody;
+
+/// An event sent to the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    data: String,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Builds an event with the given data. The data can contain multiple lines.
+    pub fn new<D>(data: D) -> Event
+    where
+        D: Into<String>,
+    {
+        Event {
+            data: data.into(),
+            event: None,
+            id: None,
+            retry: None,
+        }
+    }
+
+    /// Sets the type of the event, which is `message` by default.
+    #[inline]
+    pub fn event<E>(mut self, event: E) -> Event
+    where
+        E: Into<String>,
+    {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the ID of the event. When reconnecting, the client passes the ID of the last event
+    /// that it received in the `Last-Event-ID` header, which is returned by
+    /// [`Request::last_event_id`](../struct.Request.html#method.last_event_id).
+    #[inline]
+    pub fn id<I>(mut self, id: I) -> Event
+    where
+        I: Into<String>,
+    {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the time that the client waits before reconnecting if the connection is lost.
+    #[inline]
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The type and the ID can't span multiple lines.
+        if let Some(ref event) = self.event {
+            writeln!(
+                f,
+                "event: {}",
+                event.replace(|c| c == '\r' || c == '\n', " ")
+            )?;
+        }
+        if let Some(ref id) = self.id {
+            writeln!(f, "id: {}", id.replace(|c| c == '\r' || c == '\n', " "))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.trim_end_matches('\r'))?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Sends events to the client of an event stream. Created with
+/// [`Response::event_stream`](../struct.Response.html#method.event_stream).
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: mpsc::Sender<String>,
+}
+
+impl EventSender {
+    /// Sends an event to the client.
+    ///
+    /// Returns an error if the client has disconnected. A disconnection is only noticed when
+    /// writing to the connection, so the error can come one event or one keep-alive late.
+    #[inline]
+    pub fn send(&self, event: Event) -> Result<(), Disconnected> {
+        self.sender
+            .send(event.to_string())
+            .map_err(|_| Disconnected)
+    }
+
+    /// Sends an event of type `message` with the given data.
+    #[inline]
+    pub fn send_data<D>(&self, data: D) -> Result<(), Disconnected>
+    where
+        D: Into<String>,
+    {
+        self.send(Event::new(data))
+    }
+
+    /// Sends a comment, which the client ignores.
+    pub fn comment(&self, comment: &str) -> Result<(), Disconnected> {
+        let mut text = String::new();
+        for line in comment.split('\n') {
+            text.push_str(": ");
+            text.push_str(line.trim_end_matches('\r'));
+            text.push('\n');
+        }
+        self.sender.send(text).map_err(|_| Disconnected)
+    }
+}
+
+/// Error returned by `EventSender` when the client has disconnected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl error::Error for Disconnected {}
+
+impl fmt::Display for Disconnected {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "the client has disconnected")
+    }
+}
+
+/// Builds an event stream response and the `EventSender` to push events to it.
+///
+/// If `keep_alive` is `Some`, a comment is sent whenever no event was sent during that time, so
+/// that proxies don't close the connection and disconnections are noticed.
+/// [`Response::event_stream`](../struct.Response.html#method.event_stream) sends one every 15
+/// seconds.
+pub fn event_stream(keep_alive: Option<Duration>) -> (Response, EventSender) {
+    let (sender, receiver) = mpsc::channel();
+
+    let mut data = ResponseBody::from_reader(EventReader {
+        receiver,
+        keep_alive,
+        current: Cursor::new(Vec::new()),
+    });
+    data.no_compression = true;
+    data.unbuffered = true;
+
+    let response = Response {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".into(), "text/event-stream".into()),
+            ("Cache-Control".into(), "no-cache".into()),
+            // Disables the buffering of nginx.
+            ("X-Accel-Buffering".into(), "no".into()),
+        ],
+        data,
+        upgrade: None,
+    };
+
+    (response, EventSender { sender })
+}
+
+struct EventReader {
+    receiver: mpsc::Receiver<String>,
+    keep_alive: Option<Duration>,
+    current: Cursor<Vec<u8>>,
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let num = self.current.read(buf)?;
+            if num != 0 || buf.is_empty() {
+                return Ok(num);
+            }
+
+            let next = match self.keep_alive {
+                Some(keep_alive) => match self.receiver.recv_timeout(keep_alive) {
+                    Ok(text) => text,
+                    Err(mpsc::RecvTimeoutError::Timeout) => ":\n".to_owned(),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+                },
+                None => match self.receiver.recv() {
+                    Ok(text) => text,
+                    Err(_) => return Ok(0),
+                },
+            };
+            self.current = Cursor::new(next.into_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::event_stream;
+    use super::Event;
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use Response;
+    use Server;
+
+    #[test]
+    fn format() {
+        let event = Event::new("a\r\nb")
+            .event("up\ndate")
+            .id("5")
+            .retry(Duration::from_secs(2));
+        assert_eq!(
+            event.to_string(),
+            "event: up date\nid: 5\nretry: 2000\ndata: a\ndata: b\n\n"
+        );
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+    }
+
+    #[test]
+    fn stream() {
+        let (response, sender) = event_stream(None);
+        sender.send_data("hello").unwrap();
+        sender.comment("ping").unwrap();
+        drop(sender);
+
+        let mut body = String::new();
+        response
+            .data
+            .into_reader_and_size()
+            .0
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "data: hello\n\n: ping\n");
+    }
+
+    #[test]
+    fn keep_alive_and_disconnection() {
+        let (response, sender) = event_stream(Some(Duration::from_millis(10)));
+        let mut reader = response.data.into_reader_and_size().0;
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b":\n");
+
+        drop(reader);
+        assert!(sender.send_data("hello").is_err());
+    }
+
+    #[test]
+    fn events_are_not_buffered() {
+        let (tx, rx) = mpsc::channel();
+        let tx = ::std::sync::Mutex::new(tx);
+        let server = Server::new("127.0.0.1:0", move |_| {
+            let (response, sender) = Response::event_stream();
+            tx.lock().unwrap().send(sender).unwrap();
+            response
+        })
+        .unwrap();
+        let addr = server.server_addr();
+        let (handle, stop) = server.stoppable();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let sender = rx.recv().unwrap();
+        sender.send_data("hello").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut lines = Vec::new();
+        while lines.last().map(|l: &String| l.as_str()) != Some("data: hello\n") {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            lines.push(line);
+        }
+        assert_eq!(lines[0], "HTTP/1.1 200 OK\r\n");
+        assert!(lines.contains(&"Content-Type: text/event-stream\r\n".to_owned()));
+        assert!(lines.contains(&"Transfer-Encoding: chunked\r\n".to_owned()));
+
+        drop(sender);
+        stop.send(()).unwrap();
+        handle.join().unwrap();
+    }
+}
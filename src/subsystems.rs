@@ -0,0 +1,269 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Stopping the background tasks of a server.
+//!
+//! A server often needs threads that run next to the handler, for example to purge the expired
+//! sessions or to flush metrics. The threads started with
+//! [`Subsystems::spawn`](struct.Subsystems.html#method.spawn) receive a `ShutdownSignal`, and
+//! [`Subsystems::shutdown`](struct.Subsystems.html#method.shutdown) signals them to stop and
+//! waits for them, so that they don't outlive the server.
+//!
+//! Each server has a registry, returned by
+//! [`Server::subsystems`](../struct.Server.html#method.subsystems), which is shut down when the
+//! server stops. A registry can also be created and shut down manually.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::Server;
+//!
+//! let server = Server::new("localhost:0", |request| {
+//!     Response::text("hello world")
+//! }).unwrap();
+//!
+//! server.subsystems().spawn_periodic(
+//!     "session-gc",
+//!     Duration::from_secs(60),
+//!     Duration::from_secs(5),
+//!     || {
+//!         // Purge the expired sessions here.
+//!     },
+//! );
+//!
+//! let (handle, stop) = server.stoppable();
+//! # stop.send(()).unwrap();
+//! // Stops the server, then the session GC.
+//! handle.join().unwrap();
+//! ```
+
+use std::fmt;
+use std::mem;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// A registry of background tasks that are stopped together.
+///
+/// The tasks are stopped in the reverse order of their registration, so that a task can rely on
+/// the tasks that were registered before it until it stops.
+pub struct Subsystems {
+    entries: Mutex<Vec<Entry>>,
+}
+
+struct Entry {
+    name: String,
+    timeout: Duration,
+    // Dropping the sender signals the shutdown.
+    stop: mpsc::Sender<()>,
+    // Disconnected when the thread has returned or panicked.
+    done: mpsc::Receiver<()>,
+}
+
+impl Subsystems {
+    /// Builds an empty registry.
+    #[inline]
+    pub fn new() -> Subsystems {
+        Subsystems {
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Starts a thread that runs `task`, and registers it under the given name.
+    ///
+    /// The task must return soon after its `ShutdownSignal` is triggered. When shutting down,
+    /// the registry waits for at most `timeout` for it, after which the thread is left running
+    /// and its name is reported by `shutdown`.
+    pub fn spawn<S, F>(&self, name: S, timeout: Duration, task: F)
+    where
+        S: Into<String>,
+        F: FnOnce(ShutdownSignal) + Send + 'static,
+    {
+        let (stop, receiver) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel::<()>();
+        let name = name.into();
+
+        thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                // Dropped at the end of the thread, even if the task panics.
+                let _done = done_sender;
+                task(ShutdownSignal { receiver })
+            })
+            .expect("failed to spawn a thread");
+
+        self.entries.lock().unwrap().push(Entry {
+            name,
+            timeout,
+            stop,
+            done,
+        });
+    }
+
+    /// Same as `spawn`, but calls `task` every `interval` until the shutdown.
+    pub fn spawn_periodic<S, F>(&self, name: S, interval: Duration, timeout: Duration, mut task: F)
+    where
+        S: Into<String>,
+        F: FnMut() + Send + 'static,
+    {
+        self.spawn(name, timeout, move |signal| {
+            while !signal.wait_timeout(interval) {
+                task();
+            }
+        });
+    }
+
+    /// Returns the number of registered tasks that haven't been shut down yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns true if no task is registered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Signals the registered tasks to stop, one after the other in the reverse order of their
+    /// registration, and waits for each of them for at most its timeout.
+    ///
+    /// Returns the names of the tasks that didn't stop in time. The registry is empty afterwards,
+    /// and can be used to start new tasks.
+    pub fn shutdown(&self) -> Vec<String> {
+        let entries = mem::take(&mut *self.entries.lock().unwrap());
+
+        let mut timed_out = Vec::new();
+        for entry in entries.into_iter().rev() {
+            drop(entry.stop);
+            if let Err(mpsc::RecvTimeoutError::Timeout) = entry.done.recv_timeout(entry.timeout) {
+                timed_out.push(entry.name);
+            }
+        }
+        timed_out
+    }
+}
+
+impl Default for Subsystems {
+    #[inline]
+    fn default() -> Subsystems {
+        Subsystems::new()
+    }
+}
+
+impl fmt::Debug for Subsystems {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entries = self.entries.lock().unwrap();
+        let names: Vec<_> = entries.iter().map(|e| &e.name).collect();
+        f.debug_struct("Subsystems").field("tasks", &names).finish()
+    }
+}
+
+/// Received by a task started with `Subsystems::spawn`, to know when it must stop.
+pub struct ShutdownSignal {
+    receiver: mpsc::Receiver<()>,
+}
+
+impl ShutdownSignal {
+    /// Returns true if the task must stop.
+    #[inline]
+    pub fn is_triggered(&self) -> bool {
+        match self.receiver.try_recv() {
+            Err(mpsc::TryRecvError::Empty) => false,
+            _ => true,
+        }
+    }
+
+    /// Waits for at most `timeout` for the shutdown. Returns true if the task must stop.
+    ///
+    /// This replaces `thread::sleep` in tasks that run periodically.
+    #[inline]
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        match self.receiver.recv_timeout(timeout) {
+            Err(mpsc::RecvTimeoutError::Timeout) => false,
+            _ => true,
+        }
+    }
+
+    /// Blocks until the shutdown.
+    #[inline]
+    pub fn wait(&self) {
+        let _ = self.receiver.recv();
+    }
+}
+
+impl fmt::Debug for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShutdownSignal")
+            .field("triggered", &self.is_triggered())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Subsystems;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reverse_order() {
+        let subsystems = Subsystems::new();
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+        for name in &["first", "second", "third"] {
+            let stopped = stopped.clone();
+            subsystems.spawn(*name, Duration::from_secs(5), move |signal| {
+                signal.wait();
+                stopped.lock().unwrap().push(*name);
+            });
+        }
+
+        assert_eq!(subsystems.len(), 3);
+        assert!(subsystems.shutdown().is_empty());
+        assert!(subsystems.is_empty());
+        assert_eq!(*stopped.lock().unwrap(), vec!["third", "second", "first"]);
+    }
+
+    #[test]
+    fn timeout() {
+        let subsystems = Subsystems::new();
+        subsystems.spawn("stuck", Duration::from_millis(10), |_| {
+            thread::sleep(Duration::from_secs(1));
+        });
+        subsystems.spawn("panics", Duration::from_secs(5), |_| panic!());
+        subsystems.spawn("fine", Duration::from_secs(5), |signal| signal.wait());
+
+        assert_eq!(subsystems.shutdown(), vec!["stuck".to_owned()]);
+    }
+
+    #[test]
+    fn periodic() {
+        let subsystems = Subsystems::new();
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        subsystems.spawn_periodic(
+            "tick",
+            Duration::from_millis(1),
+            Duration::from_secs(5),
+            move || {
+                let _ = tx.lock().unwrap().send(());
+            },
+        );
+
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        assert!(subsystems.shutdown().is_empty());
+    }
+}
@@ -0,0 +1,1006 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Utilities to test handlers and servers.
+//!
+//! # Testing a handler
+//!
+//! A [`TestRequest`](struct.TestRequest.html) builds a request with a JSON, form or multipart
+//! body, cookies and query parameters, and `call` passes it to a handler. The
+//! [`TestResponse`](struct.TestResponse.html) that it returns contains the whole body of the
+//! response, and has assertions on its status code and headers.
+//!
+//! ```
+//! # #[macro_use] extern crate rouille;
+//! # #[macro_use] extern crate serde_json;
+//! use rouille::input::json_input;
+//! use rouille::test::TestRequest;
+//! use rouille::{Request, Response};
+//!
+//! fn handler(request: &Request) -> Response {
+//!     let value: serde_json::Value = try_or_400!(json_input(request));
+//!     Response::json(&value["name"])
+//! }
+//!
+//! # fn main() {
+//! let response = TestRequest::new("POST", "/users")
+//!     .json(&json!({ "name": "alice" }))
+//!     .call(handler);
+//!
+//! response.assert_status(200);
+//! assert_eq!(response.json::<String>().unwrap(), "alice");
+//! # }
+//! ```
+//!
+//! # Testing a server
+//!
+//! A [`TestServer`](struct.TestServer.html) runs a real `Server` on a local port, and its
+//! [`TestClient`](struct.TestClient.html) sends requests over a connection that it keeps alive.
+//! The responses go through everything that the server does after the handler, such as the
+//! chunked transfer encoding and the compression, which `TestRequest::call` doesn't.
+//!
+//! ```
+//! use rouille::test::{TestRequest, TestServer};
+//! use rouille::Response;
+//!
+//! let server = TestServer::new(|request| Response::text(request.url()));
+//! let mut client = server.client();
+//! let response = client.send(&TestRequest::new("GET", "/hello")).unwrap();
+//! response.assert_status(200);
+//! assert_eq!(response.text(), "/hello");
+//! ```
+//!
+//! # Load testing
+//!
+//! The [`load`](fn.load.html) function starts a server on a local port, then makes several
+//! clients send requests to it concurrently over real TCP connections. It returns a
+//! [`LoadReport`](struct.LoadReport.html) containing the status code and latency of each
+//! request, which the test can then make assertions on.
+//!
+//! # Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::test::{self, Scenario};
+//!
+//! let scenario = Scenario::new(8, 10)
+//!     .request(3, "GET", "/")
+//!     .request(1, "GET", "/missing");
+//!
+//! let report = test::load(|request| {
+//!     if request.url() == "/" {
+//!         Response::text("hello")
+//!     } else {
+//!         Response::empty_404()
+//!     }
+//! }, &scenario);
+//!
+//! assert_eq!(report.total(), 80);
+//! assert_eq!(report.count_status(200), 60);
+//! assert_eq!(report.count_status(404), 20);
+//! assert!(report.latency_percentile(0.99) < Duration::from_secs(5));
+//! ```
+
+use serde;
+use serde_json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Barrier;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use url::form_urlencoded;
+
+use Request;
+use Response;
+use Server;
+
+/// Describes the clients of a load test and the requests that they send.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    clients: usize,
+    requests_per_client: usize,
+    timeout: Duration,
+    mix: Vec<(u32, TestRequest)>,
+}
+
+impl Scenario {
+    /// Builds a scenario where `clients` clients each send `requests_per_client` requests, one
+    /// after the other.
+    ///
+    /// Add the requests to send with [`request`](#method.request) or
+    /// [`request_with`](#method.request_with). If none is added, clients send `GET /`.
+    pub fn new(clients: usize, requests_per_client: usize) -> Scenario {
+        Scenario {
+            clients,
+            requests_per_client,
+            timeout: Duration::from_secs(30),
+            mix: Vec::new(),
+        }
+    }
+
+    /// Adds a request without headers or body to the mix.
+    ///
+    /// The `weight` is the relative frequency of the request. For example with two requests of
+    /// weights `3` and `1`, three quarters of the requests sent are the first one.
+    #[inline]
+    pub fn request<M, U>(self, weight: u32, method: M, url: U) -> Scenario
+    where
+        M: Into<String>,
+        U: Into<String>,
+    {
+        self.request_with(weight, TestRequest::new(method, url))
+    }
+
+    /// Adds a request to the mix. See [`request`](#method.request).
+    #[inline]
+    pub fn request_with(mut self, weight: u32, request: TestRequest) -> Scenario {
+        self.mix.push((weight, request));
+        self
+    }
+
+    /// Sets how long a client waits for a response before counting the request as failed.
+    /// Defaults to 30 seconds.
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Scenario {
+        self.timeout = timeout;
+        self
+    }
+
+    // Returns the request to send for the request number `index`. The requests follow the
+    // weights exactly and in a deterministic order.
+    fn pick(&self, index: usize) -> Option<&TestRequest> {
+        let total: u64 = self.mix.iter().map(|r| u64::from(r.0)).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut n = index as u64 % total;
+        for entry in &self.mix {
+            if n < u64::from(entry.0) {
+                return Some(&entry.1);
+            }
+            n -= u64::from(entry.0);
+        }
+
+        None
+    }
+}
+
+// Boundary of the multipart bodies built by `TestRequest`.
+const BOUNDARY: &str = "rouille-test-boundary";
+
+/// A request sent to a handler with `call`, or by the clients of a load test.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRequest {
+    /// The method of the request, such as `GET`.
+    pub method: String,
+    /// The URL of the request, such as `/hello?a=b`.
+    pub url: String,
+    /// Additional headers. `Host`, `Connection` and `Content-Length` are added automatically.
+    pub headers: Vec<(String, String)>,
+    /// The body of the request.
+    pub body: Vec<u8>,
+}
+
+impl TestRequest {
+    /// Builds a request without headers or body.
+    pub fn new<M, U>(method: M, url: U) -> TestRequest
+    where
+        M: Into<String>,
+        U: Into<String>,
+    {
+        TestRequest {
+            method: method.into(),
+            url: url.into(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header to the request.
+    #[inline]
+    pub fn with_header<H, V>(mut self, header: H, value: V) -> TestRequest
+    where
+        H: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push((header.into(), value.into()));
+        self
+    }
+
+    /// Sets the body of the request.
+    #[inline]
+    pub fn with_body<B>(mut self, body: B) -> TestRequest
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.body = body.into();
+        self
+    }
+
+    /// Appends parameters to the query string of the URL.
+    pub fn query<K, V>(mut self, params: &[(K, V)]) -> TestRequest
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for (key, value) in params {
+            serializer.append_pair(key.as_ref(), value.as_ref());
+        }
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        self.url.push(separator);
+        self.url.push_str(&serializer.finish());
+        self
+    }
+
+    /// Adds a cookie to the `Cookie` header of the request.
+    pub fn cookie<N, V>(mut self, name: N, value: V) -> TestRequest
+    where
+        N: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let cookie = format!("{}={}", name.as_ref(), value.as_ref());
+        match self
+            .headers
+            .iter_mut()
+            .find(|(h, _)| h.eq_ignore_ascii_case("Cookie"))
+        {
+            Some((_, value)) => {
+                value.push_str("; ");
+                value.push_str(&cookie);
+            }
+            None => self.headers.push(("Cookie".to_owned(), cookie)),
+        }
+        self
+    }
+
+    /// Sets the body of the request to the JSON representation of `value`, with an
+    /// `application/json` content type.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `value` can't be serialized.
+    pub fn json<T>(self, value: &T) -> TestRequest
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        let body = serde_json::to_vec(value).expect("Failed to serialize the JSON body");
+        self.with_content_type("application/json").with_body(body)
+    }
+
+    /// Sets the body of the request to an HTML form, with an
+    /// `application/x-www-form-urlencoded` content type.
+    pub fn form<K, V>(self, fields: &[(K, V)]) -> TestRequest
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        for (key, value) in fields {
+            serializer.append_pair(key.as_ref(), value.as_ref());
+        }
+        self.with_content_type("application/x-www-form-urlencoded")
+            .with_body(serializer.finish())
+    }
+
+    /// Adds a text field to the `multipart/form-data` body of the request.
+    #[inline]
+    pub fn multipart_field<N, V>(self, name: N, value: V) -> TestRequest
+    where
+        N: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        let disposition = format!("form-data; name=\"{}\"", name.as_ref());
+        self.multipart_part(&disposition, None, value.as_ref())
+    }
+
+    /// Adds a file to the `multipart/form-data` body of the request.
+    pub fn multipart_file<N, F, C, D>(
+        self,
+        name: N,
+        filename: F,
+        content_type: C,
+        data: D,
+    ) -> TestRequest
+    where
+        N: AsRef<str>,
+        F: AsRef<str>,
+        C: AsRef<str>,
+        D: AsRef<[u8]>,
+    {
+        let disposition = format!(
+            "form-data; name=\"{}\"; filename=\"{}\"",
+            name.as_ref(),
+            filename.as_ref()
+        );
+        self.multipart_part(&disposition, Some(content_type.as_ref()), data.as_ref())
+    }
+
+    // Appends a part before the closing delimiter of the multipart body.
+    fn multipart_part(
+        mut self,
+        disposition: &str,
+        content_type: Option<&str>,
+        data: &[u8],
+    ) -> TestRequest {
+        let multipart = format!("multipart/form-data; boundary={}", BOUNDARY);
+        let closing = format!("--{}--\r\n", BOUNDARY);
+        if self.header("Content-Type") != Some(&multipart) {
+            self.body.clear();
+        } else if self.body.ends_with(closing.as_bytes()) {
+            let len = self.body.len() - closing.len();
+            self.body.truncate(len);
+        }
+
+        let mut part = format!("--{}\r\nContent-Disposition: {}\r\n", BOUNDARY, disposition);
+        if let Some(content_type) = content_type {
+            part.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        part.push_str("\r\n");
+        self.body.extend_from_slice(part.as_bytes());
+        self.body.extend_from_slice(data);
+        self.body.extend_from_slice(b"\r\n");
+        self.body.extend_from_slice(closing.as_bytes());
+        self.with_content_type(&multipart)
+    }
+
+    // Replaces the `Content-Type` header.
+    fn with_content_type(mut self, content_type: &str) -> TestRequest {
+        self.headers
+            .retain(|(h, _)| !h.eq_ignore_ascii_case("Content-Type"));
+        self.with_header("Content-Type", content_type)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
+
+    /// Builds a `Request` that can be passed to a handler.
+    pub fn to_request(&self) -> Request {
+        Request::fake_http(
+            self.method.clone(),
+            self.url.clone(),
+            self.headers.clone(),
+            self.body.clone(),
+        )
+    }
+
+    /// Passes the request to `handler` and returns its response.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the body of the response can't be read.
+    pub fn call<F>(&self, handler: F) -> TestResponse
+    where
+        F: FnOnce(&Request) -> Response,
+    {
+        TestResponse::from(handler(&self.to_request()))
+    }
+
+    // Writes the request, with a `Connection: close` header if `close` is true.
+    fn write_to<W>(&self, mut stream: W, close: bool) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let mut data = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n",
+            self.method,
+            self.url,
+            self.body.len()
+        );
+        if close {
+            data.push_str("Connection: close\r\n");
+        }
+        for header in &self.headers {
+            data.push_str(&format!("{}: {}\r\n", header.0, header.1));
+        }
+        data.push_str("\r\n");
+
+        let mut data = data.into_bytes();
+        data.extend_from_slice(&self.body);
+        stream.write_all(&data)?;
+        stream.flush()
+    }
+
+    // Sends the request on a new connection and returns the status code of the response.
+    fn send(&self, addr: &SocketAddr, timeout: Duration) -> Option<u16> {
+        let mut stream = TcpStream::connect_timeout(addr, timeout).ok()?;
+        stream.set_read_timeout(Some(timeout)).ok()?;
+        stream.set_write_timeout(Some(timeout)).ok()?;
+        self.write_to(&mut stream, true).ok()?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).ok()?;
+
+        // The status line is of the form `HTTP/1.1 200 OK`.
+        let status_line = response.split(|&c| c == b'\r').next()?;
+        let status_line = String::from_utf8_lossy(status_line);
+        status_line.split(' ').nth(1)?.parse().ok()
+    }
+}
+
+/// A response whose body has been read, returned by `TestRequest::call`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResponse {
+    /// The status code of the response.
+    pub status_code: u16,
+    /// The headers of the response.
+    pub headers: Vec<(String, String)>,
+    /// The body of the response.
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Returns the value of a header of the response, which is the first one if there are
+    /// several.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+    }
+
+    /// Returns the body as a string, replacing the invalid UTF-8 sequences.
+    #[inline]
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Parses the body as JSON.
+    #[inline]
+    pub fn json<T>(&self) -> Result<T, serde_json::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        serde_json::from_slice(&self.body)
+    }
+
+    /// Panics if the status code isn't `status_code`, with the body in the message.
+    pub fn assert_status(&self, status_code: u16) -> &TestResponse {
+        assert!(
+            self.status_code == status_code,
+            "expected status {}, got {} with body {:?}",
+            status_code,
+            self.status_code,
+            self.text()
+        );
+        self
+    }
+
+    /// Panics if the response doesn't have a `name` header whose value is `value`.
+    pub fn assert_header(&self, name: &str, value: &str) -> &TestResponse {
+        let values: Vec<_> = self
+            .headers
+            .iter()
+            .filter(|(h, _)| h.eq_ignore_ascii_case(name))
+            .map(|(_, v)| &v[..])
+            .collect();
+        assert!(
+            values.contains(&value),
+            "expected header {}: {:?}, got {:?}",
+            name,
+            value,
+            values
+        );
+        self
+    }
+}
+
+impl From<Response> for TestResponse {
+    fn from(response: Response) -> TestResponse {
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut body = Vec::new();
+        reader
+            .read_to_end(&mut body)
+            .expect("Failed to read the body of the response");
+        TestResponse {
+            status_code: response.status_code,
+            headers: response
+                .headers
+                .into_iter()
+                .map(|(h, v)| (h.into_owned(), v.into_owned()))
+                .collect(),
+            body,
+        }
+    }
+}
+
+/// The results of a load test.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    // Status code, or `None` if the request failed, and the time it took.
+    results: Vec<(Option<u16>, Duration)>,
+}
+
+impl LoadReport {
+    /// Returns the number of requests that were sent.
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.results.len()
+    }
+
+    /// Returns the number of responses with the status code `status`.
+    pub fn count_status(&self, status: u16) -> usize {
+        self.results.iter().filter(|r| r.0 == Some(status)).count()
+    }
+
+    /// Returns the number of requests that didn't get a response, because of a connection error
+    /// or a timeout.
+    pub fn errors(&self) -> usize {
+        self.results.iter().filter(|r| r.0.is_none()).count()
+    }
+
+    /// Returns the number of responses for each status code.
+    pub fn statuses(&self) -> BTreeMap<u16, usize> {
+        let mut statuses = BTreeMap::new();
+        for status in self.results.iter().filter_map(|r| r.0) {
+            *statuses.entry(status).or_insert(0) += 1;
+        }
+        statuses
+    }
+
+    /// Returns the proportion of requests that got a `2xx` response, between 0 and 1.
+    pub fn success_ratio(&self) -> f64 {
+        if self.results.is_empty() {
+            return 0.0;
+        }
+
+        let successes = self
+            .results
+            .iter()
+            .filter(|r| r.0.map_or(false, |s| (200..300).contains(&s)))
+            .count();
+        successes as f64 / self.results.len() as f64
+    }
+
+    /// Returns the latency under which the given proportion of the requests completed. For
+    /// example `latency_percentile(0.99)` returns the 99th percentile.
+    ///
+    /// Failed requests are included. Returns zero if no request was sent.
+    pub fn latency_percentile(&self, percentile: f64) -> Duration {
+        let mut latencies = self.results.iter().map(|r| r.1).collect::<Vec<_>>();
+        if latencies.is_empty() {
+            return Duration::from_secs(0);
+        }
+        latencies.sort();
+
+        let percentile = percentile.max(0.0).min(1.0);
+        let index = (percentile * latencies.len() as f64).ceil() as usize;
+        latencies[index.max(1) - 1]
+    }
+
+    /// Returns the latency of the slowest request.
+    #[inline]
+    pub fn max_latency(&self) -> Duration {
+        self.latency_percentile(1.0)
+    }
+}
+
+/// Starts a server with `handler` on a local port and runs `scenario` against it.
+///
+/// # Panic
+///
+/// Panics if the server can't be started.
+pub fn load<F>(handler: F, scenario: &Scenario) -> LoadReport
+where
+    F: Send + Sync + 'static + Fn(&Request) -> Response,
+{
+    let server = Server::new("127.0.0.1:0", handler).expect("Failed to start the test server");
+    load_server(server, scenario)
+}
+
+/// Same as [`load`](fn.load.html), but with a server that you built yourself. This makes it
+/// possible to test the configuration of the server, such as its pool size.
+///
+/// The requests are sent to the first address of the server, which must therefore be reachable
+/// from the local machine.
+pub fn load_server<F>(server: Server<F>, scenario: &Scenario) -> LoadReport
+where
+    F: Send + Sync + 'static + Fn(&Request) -> Response,
+{
+    let addr = server.server_addr();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let server_thread = {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                server.poll_timeout(Duration::from_millis(10));
+            }
+            server.join();
+        })
+    };
+
+    // All the clients wait for each other so that they start at the same time.
+    let barrier = Arc::new(Barrier::new(scenario.clients));
+    let clients = (0..scenario.clients)
+        .map(|client| {
+            let barrier = barrier.clone();
+            let scenario = scenario.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                let default = TestRequest::new("GET", "/");
+                (0..scenario.requests_per_client)
+                    .map(|n| {
+                        let request = scenario
+                            .pick(n * scenario.clients + client)
+                            .unwrap_or(&default);
+                        let start = Instant::now();
+                        let status = request.send(&addr, scenario.timeout);
+                        (status, start.elapsed())
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = LoadReport::default();
+    for client in clients {
+        match client.join() {
+            Ok(results) => report.results.extend(results),
+            Err(_) => panic!("A client of the load test panicked"),
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    let _ = server_thread.join();
+
+    report
+}
+
+/// A server running on a local port, for integration tests. The server is stopped when the
+/// `TestServer` is dropped.
+///
+/// See [the module documentation](index.html#testing-a-server) for an example.
+pub struct TestServer {
+    addr: SocketAddr,
+    stop: mpsc::Sender<()>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Starts a server on a random port of `127.0.0.1`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the server can't be started.
+    pub fn new<F>(handler: F) -> TestServer
+    where
+        F: Send + Sync + 'static + Fn(&Request) -> Response,
+    {
+        let server = Server::new("127.0.0.1:0", handler).expect("Failed to start the test server");
+        TestServer::from_server(server)
+    }
+
+    /// Runs a server that you built yourself, in order to test its configuration. The clients
+    /// connect to its first address.
+    pub fn from_server<F>(server: Server<F>) -> TestServer
+    where
+        F: Send + Sync + 'static + Fn(&Request) -> Response,
+    {
+        let addr = server.server_addr();
+        let (thread, stop) = server.stoppable();
+        TestServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the address that the server listens on.
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Opens a new connection to the server.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the connection fails.
+    pub fn client(&self) -> TestClient {
+        let stream = TcpStream::connect(self.addr).expect("Failed to connect to the test server");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .expect("Failed to set the timeout of the connection");
+        TestClient {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// Sends a request on a new connection, which is closed afterwards.
+    pub fn send(&self, request: &TestRequest) -> io::Result<TestResponse> {
+        let mut client = self.client();
+        request.write_to(client.stream.get_mut(), true)?;
+        client.read_response(&request.method)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for TestServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TestServer")
+            .field("addr", &self.addr)
+            .finish()
+    }
+}
+
+/// A connection to a `TestServer`, on which the requests are sent one after the other.
+///
+/// The body of the responses is decoded if it uses the chunked transfer encoding, but isn't
+/// decompressed.
+#[derive(Debug)]
+pub struct TestClient {
+    stream: BufReader<TcpStream>,
+}
+
+impl TestClient {
+    /// Sends a request and waits for its response. The connection is kept alive unless the
+    /// server closes it.
+    #[inline]
+    pub fn send(&mut self, request: &TestRequest) -> io::Result<TestResponse> {
+        request.write_to(self.stream.get_mut(), false)?;
+        self.read_response(&request.method)
+    }
+
+    fn read_response(&mut self, method: &str) -> io::Result<TestResponse> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        // Interim responses such as `100 Continue` are skipped.
+        let (status_code, headers) = loop {
+            let status_line = self.read_line()?;
+            let status_code: u16 = status_line
+                .split(' ')
+                .nth(1)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| invalid("invalid status line"))?;
+
+            let mut headers = Vec::new();
+            loop {
+                let line = self.read_line()?;
+                if line.is_empty() {
+                    break;
+                }
+                let mut split = line.splitn(2, ':');
+                let name = split.next().unwrap_or("").trim().to_owned();
+                let value = split
+                    .next()
+                    .ok_or_else(|| invalid("invalid header"))?
+                    .trim()
+                    .to_owned();
+                headers.push((name, value));
+            }
+
+            if status_code >= 200 {
+                break (status_code, headers);
+            }
+        };
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(h, _): &&(String, String)| h.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.clone())
+        };
+        let mut body = Vec::new();
+        if method == "HEAD" || status_code == 204 || status_code == 304 {
+            // These responses never have a body, whatever their headers say.
+        } else if header("Transfer-Encoding").map_or(false, |te| te.contains("chunked")) {
+            loop {
+                let size = self.read_line()?;
+                let size = size.split(';').next().unwrap_or("").trim();
+                let size =
+                    usize::from_str_radix(size, 16).map_err(|_| invalid("invalid chunk size"))?;
+                if size == 0 {
+                    // Skipping the trailers.
+                    while !self.read_line()?.is_empty() {}
+                    break;
+                }
+                let start = body.len();
+                body.resize(start + size, 0);
+                self.stream.read_exact(&mut body[start..])?;
+                self.read_line()?;
+            }
+        } else if let Some(length) = header("Content-Length") {
+            let length = length
+                .parse()
+                .map_err(|_| invalid("invalid Content-Length"))?;
+            body.resize(length, 0);
+            self.stream.read_exact(&mut body)?;
+        } else {
+            self.stream.read_to_end(&mut body)?;
+        }
+
+        Ok(TestResponse {
+            status_code,
+            headers,
+            body,
+        })
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(&['\r', '\n'][..]).to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load;
+    use super::Scenario;
+    use super::TestRequest;
+    use super::TestServer;
+    use input;
+    use std::io::Cursor;
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+    use Response;
+    use ResponseBody;
+
+    #[test]
+    fn request_builder() {
+        let request = TestRequest::new("POST", "/form?a=1")
+            .query(&[("b", "x y")])
+            .cookie("session", "abc")
+            .cookie("theme", "dark")
+            .form(&[("name", "alice & bob")])
+            .to_request();
+        assert_eq!(request.raw_url(), "/form?a=1&b=x+y");
+        assert_eq!(request.get_param("b").unwrap(), "x y");
+        let cookies: Vec<_> = input::cookies(&request).collect();
+        assert_eq!(cookies, vec![("session", "abc"), ("theme", "dark")]);
+        let fields = input::post::raw_urlencoded_post_input(&request).unwrap();
+        assert_eq!(fields, vec![("name".to_owned(), "alice & bob".to_owned())]);
+
+        let request = TestRequest::new("POST", "/upload")
+            .json(&vec![1, 2])
+            .multipart_field("title", "holidays")
+            .multipart_file("photo", "a.jpg", "image/jpeg", b"\xff\xd8")
+            .to_request();
+        let mut multipart = input::multipart::get_multipart_input(&request).unwrap();
+        let mut names = Vec::new();
+        while let Some(mut field) = multipart.next() {
+            let mut data = Vec::new();
+            field.data.read_to_end(&mut data).unwrap();
+            names.push((field.headers.name.to_string(), field.headers.filename, data));
+        }
+        assert_eq!(
+            names,
+            vec![
+                ("title".to_owned(), None, b"holidays".to_vec()),
+                (
+                    "photo".to_owned(),
+                    Some("a.jpg".to_owned()),
+                    b"\xff\xd8".to_vec()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn call() {
+        let response = TestRequest::new("POST", "/")
+            .json(&vec!["a", "b"])
+            .call(|request| {
+                let value: Vec<String> = input::json_input(request).unwrap();
+                Response::json(&value.len()).with_additional_header("X-Count", "yes")
+            });
+        response
+            .assert_status(200)
+            .assert_header("x-count", "yes")
+            .assert_header("Content-Type", "application/json; charset=utf-8");
+        assert_eq!(response.json::<usize>().unwrap(), 2);
+        assert_eq!(response.header("X-Count"), Some("yes"));
+
+        let response = TestRequest::new("GET", "/").call(|_| Response::text("hello"));
+        assert_eq!(response.text(), "hello");
+    }
+
+    #[test]
+    fn test_server() {
+        let server = TestServer::new(|request| match request.url().as_str() {
+            // Bodies of unknown length are sent with the chunked encoding.
+            "/stream" => Response {
+                data: ResponseBody::from_reader(Cursor::new(b"streamed".to_vec())),
+                ..Response::text("")
+            },
+            _ => Response::text(format!("{} {}", request.method(), request.url())),
+        });
+
+        // Several requests on the same connection.
+        let mut client = server.client();
+        let response = client.send(&TestRequest::new("GET", "/a")).unwrap();
+        assert_eq!(response.text(), "GET /a");
+        let response = client.send(&TestRequest::new("HEAD", "/b")).unwrap();
+        response.assert_status(200);
+        assert!(response.body.is_empty());
+        let response = client.send(&TestRequest::new("GET", "/stream")).unwrap();
+        response.assert_header("Transfer-Encoding", "chunked");
+        assert_eq!(response.text(), "streamed");
+        let response = client.send(&TestRequest::new("POST", "/c")).unwrap();
+        assert_eq!(response.text(), "POST /c");
+
+        let response = server.send(&TestRequest::new("GET", "/")).unwrap();
+        assert_eq!(response.text(), "GET /");
+        let addr = server.addr();
+        drop(server);
+        assert!(::std::net::TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "expected status 200, got 404")]
+    fn assert_status() {
+        TestRequest::new("GET", "/")
+            .call(|_| Response::empty_404())
+            .assert_status(200);
+    }
+
+    #[test]
+    fn status_and_latency() {
+        let scenario = Scenario::new(4, 6)
+            .request(1, "GET", "/slow")
+            .request_with(2, TestRequest::new("POST", "/echo").with_body("hello"));
+
+        let report = load(
+            |request| {
+                if request.url() == "/slow" {
+                    thread::sleep(Duration::from_millis(50));
+                    return Response::empty_204();
+                }
+
+                let mut body = String::new();
+                request.data().unwrap().read_to_string(&mut body).unwrap();
+                if body == "hello" {
+                    Response::text(body)
+                } else {
+                    Response::empty_400()
+                }
+            },
+            &scenario,
+        );
+
+        assert_eq!(report.total(), 24);
+        assert_eq!(report.errors(), 0);
+        assert_eq!(report.count_status(204), 8);
+        assert_eq!(report.count_status(200), 16);
+        assert_eq!(report.statuses().len(), 2);
+        assert_eq!(report.success_ratio(), 1.0);
+        assert!(report.max_latency() >= Duration::from_millis(50));
+        assert!(report.latency_percentile(0.5) <= report.max_latency());
+    }
+}
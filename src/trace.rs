@@ -0,0 +1,506 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Spans of the `tracing` crate around the requests.
+//!
+//! This module is only available with the `trace` feature.
+//!
+//! [`traced`](fn.traced.html), or the [`Tracing`](struct.Tracing.html) middleware, runs the
+//! handler inside a `request` span with the following fields:
+//!
+//! - `method` and `path`, recorded when the span is created;
+//! - `trace_id` and `span_id`, the identifiers of the [trace context](struct.TraceContext.html)
+//!   of the request;
+//! - `status`, recorded when the handler returns;
+//! - `latency_ms`, recorded once the response has been sent.
+//!
+//! The span is closed once the response has been sent. An `ERROR` event is emitted in the span if
+//! the handler panics, and a `WARN` event if reading the body of the request or of the response
+//! fails, for example because the client went away.
+//!
+//! # Distributed tracing
+//!
+//! The trace context of a request is read from its
+//! [`traceparent`](https://www.w3.org/TR/trace-context/) header when there is one, so that the
+//! spans of this server are attached to the trace of the caller. Otherwise a new trace is
+//! started. The context of the span of the request is inserted in its extensions, where it's
+//! returned by [`TraceContext::current`](struct.TraceContext.html#method.current), and
+//! [`proxy`](../proxy/fn.proxy.html) passes it to the upstream server in the `traceparent`
+//! header.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rouille::Response;
+//! use rouille::trace;
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     trace::traced(request, || Response::text("hello world"))
+//! });
+//! ```
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tracing::field;
+use tracing::Span;
+
+use middleware::Middleware;
+use random::Random;
+use util::to_hex;
+use Request;
+use Response;
+use ResponseBody;
+
+/// Name of the header of the W3C trace context.
+pub const HEADER_NAME: &str = "traceparent";
+
+/// Position of a request in a distributed trace, as transmitted by the
+/// [`traceparent`](https://www.w3.org/TR/trace-context/#traceparent-header) header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// Identifier of the whole trace. Never all zeroes.
+    pub trace_id: [u8; 16],
+    /// Identifier of the span that the context designates. Never all zeroes.
+    pub span_id: [u8; 8],
+    /// True if the caller records the trace.
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parses the value of a `traceparent` header.
+    ///
+    /// Returns `None` if the value is malformed, if it uses the forbidden version `ff`, or if one
+    /// of the identifiers is all zeroes. The fields that future versions may append are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::trace::TraceContext;
+    ///
+    /// let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    /// let context = TraceContext::parse(header).unwrap();
+    /// assert!(context.sampled);
+    /// assert_eq!(context.to_string(), header);
+    /// ```
+    pub fn parse(header: &str) -> Option<TraceContext> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+
+        let version = parse_hex::<[u8; 1]>(version)?[0];
+        if version == 0xff || (version == 0 && parts.next().is_some()) {
+            return None;
+        }
+
+        let trace_id = parse_hex::<[u8; 16]>(trace_id)?;
+        let span_id = parse_hex::<[u8; 8]>(span_id)?;
+        let flags = parse_hex::<[u8; 1]>(flags)?[0];
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            sampled: flags & 1 != 0,
+        })
+    }
+
+    /// Parses the `traceparent` header of the request, if any.
+    #[inline]
+    pub fn from_request(request: &Request) -> Option<TraceContext> {
+        request.header(HEADER_NAME).and_then(TraceContext::parse)
+    }
+
+    /// Returns the context of the span of the request, inserted in its extensions by `traced` or
+    /// by the `Tracing` middleware.
+    #[inline]
+    pub fn current(request: &Request) -> Option<TraceContext> {
+        request.extensions().get::<TraceContext>().cloned()
+    }
+
+    /// Starts a new trace, which is sampled.
+    pub fn new_root(random: &Random) -> TraceContext {
+        let mut trace_id = [0; 16];
+        while trace_id == [0; 16] {
+            random.fill_bytes(&mut trace_id);
+        }
+        TraceContext {
+            trace_id,
+            span_id: new_span_id(random),
+            sampled: true,
+        }
+    }
+
+    /// Returns the context of a new span within the same trace, whose parent is this span.
+    #[inline]
+    pub fn child(&self, random: &Random) -> TraceContext {
+        TraceContext {
+            span_id: new_span_id(random),
+            ..*self
+        }
+    }
+
+    /// Returns the trace ID in hexadecimal.
+    #[inline]
+    pub fn trace_id_hex(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    /// Returns the span ID in hexadecimal.
+    #[inline]
+    pub fn span_id_hex(&self) -> String {
+        to_hex(&self.span_id)
+    }
+}
+
+/// Formats the context as the value of a `traceparent` header.
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{}",
+            self.trace_id_hex(),
+            self.span_id_hex(),
+            if self.sampled { "01" } else { "00" }
+        )
+    }
+}
+
+fn new_span_id(random: &Random) -> [u8; 8] {
+    let mut span_id = [0; 8];
+    while span_id == [0; 8] {
+        random.fill_bytes(&mut span_id);
+    }
+    span_id
+}
+
+// Parses lowercase hexadecimal digits that fill exactly `T`.
+fn parse_hex<T>(hex: &str) -> Option<T>
+where
+    T: Default + AsMut<[u8]>,
+{
+    let mut out = T::default();
+    if hex.len() != out.as_mut().len() * 2 {
+        return None;
+    }
+    for (byte, pair) in out.as_mut().iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digit = |c: u8| match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            _ => None,
+        };
+        *byte = (digit(pair[0])? << 4) | digit(pair[1])?;
+    }
+    Some(out)
+}
+
+// Signature of the function registered with `Tracing::extract`.
+type ExtractFn = dyn Fn(&Request) -> Option<TraceContext> + Send + Sync;
+
+// Signature of the function registered with `Tracing::inject`.
+type InjectFn = dyn Fn(&TraceContext, Response) -> Response + Send + Sync;
+
+/// Middleware that runs the rest of the pipeline inside a `request` span. See
+/// [the module documentation](index.html).
+///
+/// # Example
+///
+/// ```
+/// use rouille::{Request, Response};
+/// use rouille::middleware::Pipeline;
+/// use rouille::trace::{TraceContext, Tracing, HEADER_NAME};
+///
+/// // Only the internal services are trusted to continue a trace, and the context is returned
+/// // to the caller.
+/// let tracing = Tracing::new()
+///     .extract(|request: &Request| {
+///         if request.remote_addr().ip().is_loopback() {
+///             TraceContext::from_request(request)
+///         } else {
+///             None
+///         }
+///     })
+///     .inject(|context: &TraceContext, response: Response| {
+///         response.with_unique_header(HEADER_NAME, context.to_string())
+///     });
+///
+/// let pipeline = Pipeline::new(|_: &Request| Response::text("hello world")).with(tracing);
+/// ```
+pub struct Tracing {
+    extract: Arc<ExtractFn>,
+    inject: Option<Arc<InjectFn>>,
+}
+
+impl Tracing {
+    /// Builds a middleware that continues the trace of the `traceparent` header of the requests.
+    #[inline]
+    pub fn new() -> Tracing {
+        Tracing {
+            extract: Arc::new(TraceContext::from_request),
+            inject: None,
+        }
+    }
+
+    /// Sets the function that returns the trace context of the caller of a request. When it
+    /// returns `None`, a new trace is started.
+    #[inline]
+    pub fn extract<F>(mut self, extract: F) -> Tracing
+    where
+        F: Fn(&Request) -> Option<TraceContext> + Send + Sync + 'static,
+    {
+        self.extract = Arc::new(extract);
+        self
+    }
+
+    /// Sets a function called with the context of the span of each request and its response,
+    /// for example to return the context to the caller in a header.
+    #[inline]
+    pub fn inject<F>(mut self, inject: F) -> Tracing
+    where
+        F: Fn(&TraceContext, Response) -> Response + Send + Sync + 'static,
+    {
+        self.inject = Some(Arc::new(inject));
+        self
+    }
+
+    fn run<F>(&self, request: &Request, handler: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        let random = Random::of(request);
+        let context = match (self.extract)(request) {
+            Some(parent) => parent.child(&random),
+            None => TraceContext::new_root(&random),
+        };
+        request.extensions().insert(context);
+
+        let start = Instant::now();
+        let span = ::tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = %request.url(),
+            trace_id = %context.trace_id_hex(),
+            span_id = %context.span_id_hex(),
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        trace_request_body(request, &span);
+
+        let result = {
+            let _entered = span.enter();
+            panic::catch_unwind(AssertUnwindSafe(handler))
+        };
+        let mut response = match result {
+            Ok(response) => response,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| (*message).to_owned())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "Box<Any>".to_owned());
+                ::tracing::error!(parent: &span, panic = %message, "the handler panicked");
+                span.record("status", 500u64);
+                span.record("latency_ms", elapsed_ms(start));
+                panic::resume_unwind(payload);
+            }
+        };
+        span.record("status", u64::from(response.status_code));
+
+        if let Some(ref inject) = self.inject {
+            response = inject(&context, response);
+        }
+
+        let data = mem::replace(&mut response.data, ResponseBody::empty());
+        response.data = data.map_reader(|inner| TracedBody { inner, span, start });
+        response
+    }
+}
+
+impl Default for Tracing {
+    #[inline]
+    fn default() -> Tracing {
+        Tracing::new()
+    }
+}
+
+impl fmt::Debug for Tracing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracing")
+            .field("inject", &self.inject.is_some())
+            .finish()
+    }
+}
+
+impl Middleware for Tracing {
+    #[inline]
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        self.run(request, || next(request))
+    }
+}
+
+/// Runs `handler` inside a `request` span, with the default configuration of the
+/// [`Tracing`](struct.Tracing.html) middleware.
+#[inline]
+pub fn traced<F>(request: &Request, handler: F) -> Response
+where
+    F: FnOnce() -> Response,
+{
+    Tracing::new().run(request, handler)
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1e6
+}
+
+// Replaces the body of the request with a reader that emits an event in `span` when it fails.
+fn trace_request_body(request: &Request, span: &Span) {
+    let mut data = request.data.lock().unwrap();
+    if let Some(body) = data.take() {
+        *data = Some(Box::new(TracedRequestBody {
+            inner: body,
+            span: span.clone(),
+        }));
+    }
+}
+
+struct TracedRequestBody {
+    inner: Box<dyn Read + Send>,
+    span: Span,
+}
+
+impl Read for TracedRequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|err| {
+            ::tracing::warn!(parent: &self.span, error = %err, "failed to read the request body");
+            err
+        })
+    }
+}
+
+// Body of a response that records the latency in the span and closes it when the server drops
+// it.
+struct TracedBody {
+    inner: Box<dyn Read + Send>,
+    span: Span,
+    start: Instant,
+}
+
+impl Read for TracedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|err| {
+            ::tracing::warn!(parent: &self.span, error = %err, "failed to read the response body");
+            err
+        })
+    }
+}
+
+impl Drop for TracedBody {
+    fn drop(&mut self) {
+        self.span.record("latency_ms", elapsed_ms(self.start));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{traced, TraceContext, Tracing, HEADER_NAME};
+    use middleware::Pipeline;
+    use random::Random;
+    use Request;
+    use Response;
+
+    const HEADER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parse() {
+        let context = TraceContext::parse(HEADER).unwrap();
+        assert_eq!(context.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id_hex(), "00f067aa0ba902b7");
+        assert!(context.sampled);
+        assert_eq!(context.to_string(), HEADER);
+
+        let unsampled = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00";
+        assert!(!TraceContext::parse(unsampled).unwrap().sampled);
+
+        // Future versions may append fields.
+        let future = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra";
+        assert!(TraceContext::parse(future).is_some());
+
+        for invalid in &[
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn continues_trace() {
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![(HEADER_NAME.to_owned(), HEADER.to_owned())],
+            vec![],
+        );
+        request.extensions().insert(Random::seeded(1));
+        let response = traced(&request, || Response::text("hello"));
+        assert_eq!(response.status_code, 200);
+
+        let parent = TraceContext::parse(HEADER).unwrap();
+        let context = TraceContext::current(&request).unwrap();
+        assert_eq!(context.trace_id, parent.trace_id);
+        assert_ne!(context.span_id, parent.span_id);
+    }
+
+    #[test]
+    fn extract_and_inject() {
+        let pipeline = Pipeline::new(|_: &Request| Response::text("hello")).with(
+            Tracing::new().extract(|_: &Request| None).inject(
+                |context: &TraceContext, response: Response| {
+                    response.with_unique_header(HEADER_NAME, context.to_string())
+                },
+            ),
+        );
+        let request = Request::fake_http(
+            "GET",
+            "/",
+            vec![(HEADER_NAME.to_owned(), HEADER.to_owned())],
+            vec![],
+        );
+        let response = pipeline.handle(&request);
+
+        let context = TraceContext::current(&request).unwrap();
+        assert_ne!(
+            context.trace_id,
+            TraceContext::parse(HEADER).unwrap().trace_id
+        );
+        let sent = response
+            .headers
+            .iter()
+            .find(|h| h.0 == HEADER_NAME)
+            .map(|h| h.1.to_string());
+        assert_eq!(sent, Some(context.to_string()));
+    }
+}
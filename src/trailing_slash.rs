@@ -0,0 +1,184 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Treating `/foo` and `/foo/` as the same URL.
+//!
+//! The [`TrailingSlash`](struct.TrailingSlash.html) middleware chooses one of the two forms as
+//! the canonical one. Requests that use the other form are either rewritten before reaching the
+//! handler, so that the routes only need to be written once, or redirected to the canonical
+//! form. The path `/` is never modified.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use rouille::{Request, Response};
+//! use rouille::middleware::Pipeline;
+//! use rouille::trailing_slash::TrailingSlash;
+//!
+//! // `GET /hello/` is answered with a `308 Permanent Redirect` to `/hello`.
+//! let pipeline = Pipeline::new(|request: &Request| {
+//!     router!(request,
+//!         (GET) (/hello) => { Response::text("hello") },
+//!         _ => Response::empty_404()
+//!     )
+//! })
+//! .with(TrailingSlash::remove().redirect(308));
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| pipeline.handle(request));
+//! # }
+//! ```
+
+use middleware::Middleware;
+use Request;
+use Response;
+
+/// Middleware that normalizes the trailing slash of the path of the requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrailingSlash {
+    // True if the canonical form ends with a slash.
+    slash: bool,
+    // The status code of the redirection, or `None` to rewrite the request.
+    redirect: Option<u16>,
+}
+
+impl TrailingSlash {
+    /// The canonical form doesn't end with a slash: `/foo/` is handled as `/foo`.
+    #[inline]
+    pub fn remove() -> TrailingSlash {
+        TrailingSlash {
+            slash: false,
+            redirect: None,
+        }
+    }
+
+    /// The canonical form ends with a slash: `/foo` is handled as `/foo/`.
+    ///
+    /// Note that this applies to all paths, including the ones of files such as `/style.css`.
+    #[inline]
+    pub fn add() -> TrailingSlash {
+        TrailingSlash {
+            slash: true,
+            redirect: None,
+        }
+    }
+
+    /// Redirects the requests to the canonical form instead of rewriting them, with the given
+    /// status code. Use `301` or `308`; the latter guarantees that the client keeps the method
+    /// and the body of the request.
+    #[inline]
+    pub fn redirect(mut self, status_code: u16) -> TrailingSlash {
+        self.redirect = Some(status_code);
+        self
+    }
+
+    /// Returns the canonical form of a URL, or `None` if it is already canonical.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rouille::trailing_slash::TrailingSlash;
+    ///
+    /// let policy = TrailingSlash::remove();
+    /// assert_eq!(policy.canonical_url("/foo//?a=b"), Some("/foo?a=b".to_owned()));
+    /// assert_eq!(policy.canonical_url("/foo"), None);
+    /// assert_eq!(policy.canonical_url("/"), None);
+    /// ```
+    pub fn canonical_url(&self, raw_url: &str) -> Option<String> {
+        let (path, query) = match raw_url.find('?') {
+            Some(pos) => raw_url.split_at(pos),
+            None => (raw_url, ""),
+        };
+
+        if path == "/" || path.is_empty() {
+            return None;
+        }
+
+        let has_slash = path.ends_with('/');
+        if self.slash {
+            if has_slash {
+                None
+            } else {
+                Some(format!("{}/{}", path, query))
+            }
+        } else if has_slash {
+            let path = path.trim_end_matches('/');
+            let path = if path.is_empty() { "/" } else { path };
+            Some(format!("{}{}", path, query))
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for TrailingSlash {
+    fn handle(&self, request: &Request, next: &dyn Fn(&Request) -> Response) -> Response {
+        let canonical = match self.canonical_url(request.raw_url()) {
+            Some(url) => url,
+            None => return next(request),
+        };
+
+        match self.redirect {
+            Some(301) => Response::redirect_301(canonical),
+            Some(308) => Response::redirect_308(canonical),
+            Some(status_code) => Response::redirect_308(canonical).with_status_code(status_code),
+            None => next(&request.with_raw_url(canonical)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrailingSlash;
+    use middleware::Pipeline;
+    use test::TestResponse;
+    use Request;
+    use Response;
+
+    fn pipeline(policy: TrailingSlash) -> Pipeline<impl Fn(&Request) -> Response> {
+        Pipeline::new(|request: &Request| Response::text(request.raw_url().to_owned())).with(policy)
+    }
+
+    #[test]
+    fn rewrite() {
+        let remove = pipeline(TrailingSlash::remove());
+        let handle = |url: &str| {
+            TestResponse::from(remove.handle(&Request::fake_http("GET", url, vec![], vec![])))
+                .text()
+        };
+        assert_eq!(handle("/foo/"), "/foo");
+        assert_eq!(handle("/foo?a=b/"), "/foo?a=b/");
+        assert_eq!(handle("/foo/?a"), "/foo?a");
+        assert_eq!(handle("/"), "/");
+        assert_eq!(handle("//"), "/");
+
+        let add = pipeline(TrailingSlash::add());
+        let handle = |url: &str| {
+            TestResponse::from(add.handle(&Request::fake_http("GET", url, vec![], vec![]))).text()
+        };
+        assert_eq!(handle("/foo"), "/foo/");
+        assert_eq!(handle("/foo/"), "/foo/");
+        assert_eq!(handle("/foo?a"), "/foo/?a");
+    }
+
+    #[test]
+    fn redirect() {
+        let pipeline = pipeline(TrailingSlash::remove().redirect(301));
+        let response = pipeline.handle(&Request::fake_http("GET", "/foo/?a", vec![], vec![]));
+        assert_eq!(response.status_code, 301);
+        assert!(response
+            .headers
+            .iter()
+            .any(|h| h.0 == "Location" && h.1 == "/foo?a"));
+
+        let response = pipeline.handle(&Request::fake_http("GET", "/foo", vec![], vec![]));
+        assert_eq!(response.status_code, 200);
+    }
+}
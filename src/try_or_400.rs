@@ -12,6 +12,8 @@
 
 use std::error::Error;
 
+use Response;
+
 /// This macro assumes that the current function returns a `Response` and takes a `Result`.
 /// If the expression you pass to the macro is an error, then a 400 response is returned.
 ///
@@ -38,10 +40,7 @@ macro_rules! try_or_400 {
     ($result:expr) => {
         match $result {
             Ok(r) => r,
-            Err(err) => {
-                let json = $crate::try_or_400::ErrJson::from_err(&err);
-                return $crate::Response::json(&json).with_status_code(400);
-            }
+            Err(err) => return $crate::try_or_400::error_response(&err),
         }
     };
 }
@@ -61,3 +60,10 @@ impl ErrJson {
         }
     }
 }
+
+/// Builds the response returned by `try_or_400!`.
+pub fn error_response<E: ?Sized + Error>(err: &E) -> Response {
+    let mut response = Response::json(&ErrJson::from_err(err)).with_status_code(400);
+    response.data.default_error = true;
+    response
+}
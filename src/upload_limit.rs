@@ -0,0 +1,403 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Limiting the rate at which the bodies of the requests are read.
+//!
+//! An [`UploadLimit`](struct.UploadLimit.html) slows down the reading of the bodies so that they
+//! don't exceed a number of bytes per second. By default the limit applies to each connection,
+//! which means that the requests that a client sends on the same keep-alive connection share it,
+//! and a burst allowance lets small bodies through at full speed. Since the server then reads the
+//! socket more slowly, TCP slows down the client, and an upload at full link speed can't starve
+//! the disk or the CPU of the server.
+//!
+//! With [`reject_after`](struct.UploadLimit.html#method.reject_after), the clients that keep
+//! uploading faster than the limit get a `429 Too Many Requests` response that asks them to close
+//! the connection, instead of being slowed down forever.
+//!
+//! The limit can be applied to all the requests with
+//! [`Server::upload_limit`](../struct.Server.html#method.upload_limit), or to some routes with
+//! [`UploadLimit::limit`](struct.UploadLimit.html#method.limit) or as a
+//! [`Middleware`](../middleware/trait.Middleware.html).
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #[macro_use] extern crate rouille;
+//! # fn main() {
+//! use std::io::Read;
+//! use std::time::Duration;
+//! use rouille::Response;
+//! use rouille::upload_limit::UploadLimit;
+//!
+//! // 1 MB/s, with bursts of 4 MB, and the clients that are slowed down for more than 30 seconds
+//! // are rejected.
+//! let limit = UploadLimit::new(1_000_000)
+//!     .burst(4_000_000)
+//!     .reject_after(Duration::from_secs(30));
+//!
+//! rouille::start_server("0.0.0.0:80", move |request| {
+//!     router!(request,
+//!         (POST) (/upload) => {
+//!             limit.limit(request, || {
+//!                 let mut body = Vec::new();
+//!                 try_or_400!(request.data().unwrap().read_to_end(&mut body));
+//!                 Response::text(format!("received {} bytes", body.len()))
+//!             })
+//!         },
+//!         _ => Response::empty_404()
+//!     )
+//! });
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use middleware::Middleware;
+use Request;
+use Response;
+
+/// Limits the number of bytes per second read from the bodies of the requests.
+///
+/// Cloning an `UploadLimit` gives a limit that shares the budgets of the connections with the
+/// original.
+#[derive(Clone)]
+pub struct UploadLimit {
+    rate: u64,
+    burst: u64,
+    reject_after: Option<Duration>,
+    per_request: bool,
+    connections: Arc<Mutex<HashMap<SocketAddr, Bucket>>>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    // Number of bytes that can be read without waiting. Negative if the reader is in debt.
+    available: f64,
+    last_update: Instant,
+}
+
+// Number of tracked connections above which we remove the ones whose budget is full.
+const PRUNE_THRESHOLD: usize = 1024;
+
+impl UploadLimit {
+    /// Builds a new `UploadLimit` of `bytes_per_second` for each connection, with a burst
+    /// allowance of one second.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `bytes_per_second` is 0.
+    pub fn new(bytes_per_second: u64) -> UploadLimit {
+        assert!(bytes_per_second > 0, "the upload rate must not be zero");
+        UploadLimit {
+            rate: bytes_per_second,
+            burst: bytes_per_second,
+            reject_after: None,
+            per_request: false,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the number of bytes that can be read at full speed before the limit kicks in. The
+    /// allowance refills at the rate of the limit when the connection is idle.
+    #[inline]
+    pub fn burst(mut self, bytes: u64) -> UploadLimit {
+        self.burst = bytes.max(1);
+        self
+    }
+
+    /// Rejects the requests whose body has been slowed down for more than `duration` in total,
+    /// with a `429 Too Many Requests` response and a `Connection: close` header.
+    ///
+    /// Reading the body then returns an error, which the handler usually turns into an error
+    /// response that is replaced with the 429 response.
+    #[inline]
+    pub fn reject_after(mut self, duration: Duration) -> UploadLimit {
+        self.reject_after = Some(duration);
+        self
+    }
+
+    /// If true, each request gets its own budget instead of sharing the budget of its
+    /// connection.
+    #[inline]
+    pub fn per_request(mut self, per_request: bool) -> UploadLimit {
+        self.per_request = per_request;
+        self
+    }
+
+    /// Returns the number of bytes per second.
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Limits the rate at which the body of `request` is read.
+    ///
+    /// Does nothing if the body has already been extracted, or if a limit has already been
+    /// applied to the request.
+    pub fn apply(&self, request: &Request) {
+        if request.extensions().contains::<Exceeded>() {
+            return;
+        }
+
+        let mut data = request.data.lock().unwrap();
+        let inner = match data.take() {
+            Some(inner) => inner,
+            None => return,
+        };
+
+        let exceeded = Exceeded(Arc::new(Mutex::new(None)));
+        let bucket = if self.per_request {
+            BucketRef::Own(self.full_bucket())
+        } else {
+            BucketRef::Connection(request.remote_addr)
+        };
+        *data = Some(Box::new(LimitedBody {
+            inner,
+            limit: self.clone(),
+            bucket,
+            waited: Duration::new(0, 0),
+            exceeded: exceeded.clone(),
+        }));
+        request.extensions().insert(exceeded);
+    }
+
+    /// Limits the rate at which the body of `request` is read, then calls `handler`.
+    ///
+    /// If the client was rejected because of `reject_after`, returns a `429 Too Many Requests`
+    /// response instead of the one of the handler.
+    pub fn limit<F>(&self, request: &Request, handler: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        self.apply(request);
+        let response = handler();
+        self.check(request, response)
+    }
+
+    // Replaces the response with a 429 response if the client has been rejected.
+    pub(crate) fn check(&self, request: &Request, response: Response) -> Response {
+        let retry_after = match request.extensions().get::<Exceeded>() {
+            Some(exceeded) => *exceeded.0.lock().unwrap(),
+            None => None,
+        };
+
+        match retry_after {
+            Some(retry_after) => Response::too_many_requests(retry_after).with_connection_close(),
+            None => response,
+        }
+    }
+
+    fn full_bucket(&self) -> Bucket {
+        Bucket {
+            available: self.burst as f64,
+            last_update: Instant::now(),
+        }
+    }
+
+    // Spends `bytes` from a bucket, and returns how long to wait until it is out of debt.
+    fn spend(&self, bucket: &mut Bucket, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let rate = self.rate as f64;
+        let elapsed = duration_to_secs(now.duration_since(bucket.last_update));
+        bucket.available = (bucket.available + elapsed * rate).min(self.burst as f64);
+        bucket.last_update = now;
+
+        bucket.available -= bytes as f64;
+        if bucket.available >= 0.0 {
+            Duration::new(0, 0)
+        } else {
+            secs_to_duration(-bucket.available / rate)
+        }
+    }
+
+    // Same as `spend`, for the bucket of a connection.
+    fn spend_connection(&self, addr: SocketAddr, bytes: usize) -> Duration {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.len() > PRUNE_THRESHOLD {
+            let now = Instant::now();
+            let (rate, burst) = (self.rate as f64, self.burst as f64);
+            connections.retain(|_, bucket| {
+                let elapsed = duration_to_secs(now.duration_since(bucket.last_update));
+                bucket.available + elapsed * rate < burst
+            });
+        }
+
+        let full = self.full_bucket();
+        let bucket = connections.entry(addr).or_insert(full);
+        self.spend(bucket, bytes)
+    }
+}
+
+impl fmt::Debug for UploadLimit {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("UploadLimit")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .field("reject_after", &self.reject_after)
+            .field("per_request", &self.per_request)
+            .finish()
+    }
+}
+
+impl Middleware for UploadLimit {
+    #[inline]
+    fn before(&self, request: &Request) -> Option<Response> {
+        self.apply(request);
+        None
+    }
+
+    #[inline]
+    fn after(&self, request: &Request, response: Response) -> Response {
+        self.check(request, response)
+    }
+}
+
+/// Error returned when reading a body whose client has been rejected by
+/// [`UploadLimit::reject_after`](struct.UploadLimit.html#method.reject_after).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UploadRateExceeded;
+
+impl error::Error for UploadRateExceeded {}
+
+impl fmt::Display for UploadRateExceeded {
+    #[inline]
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "The client exceeded the upload rate limit")
+    }
+}
+
+// Inserted in the extensions of the requests whose body is limited. Contains the time after which
+// the client can retry if it has been rejected.
+#[derive(Clone)]
+struct Exceeded(Arc<Mutex<Option<Duration>>>);
+
+enum BucketRef {
+    Connection(SocketAddr),
+    Own(Bucket),
+}
+
+struct LimitedBody {
+    inner: Box<dyn Read + Send>,
+    limit: UploadLimit,
+    bucket: BucketRef,
+    // Total time spent waiting.
+    waited: Duration,
+    exceeded: Exceeded,
+}
+
+impl Read for LimitedBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.exceeded.0.lock().unwrap().is_some() {
+            return Err(io::Error::new(io::ErrorKind::Other, UploadRateExceeded));
+        }
+
+        // Reading at most the burst allowance keeps the waits short.
+        let len = buf.len().min(self.limit.burst as usize);
+        let num = self.inner.read(&mut buf[..len])?;
+        if num == 0 {
+            return Ok(0);
+        }
+
+        let wait = match self.bucket {
+            BucketRef::Connection(addr) => self.limit.spend_connection(addr, num),
+            BucketRef::Own(ref mut bucket) => self.limit.spend(bucket, num),
+        };
+
+        if let Some(reject_after) = self.limit.reject_after {
+            if self.waited + wait > reject_after {
+                *self.exceeded.0.lock().unwrap() = Some(wait);
+                return Err(io::Error::new(io::ErrorKind::Other, UploadRateExceeded));
+            }
+        }
+
+        self.waited += wait;
+        thread::sleep(wait);
+        Ok(num)
+    }
+}
+
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+fn secs_to_duration(secs: f64) -> Duration {
+    let whole = secs.trunc();
+    Duration::new(whole as u64, ((secs - whole) * 1_000_000_000.0) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::UploadLimit;
+    use Request;
+    use Response;
+
+    fn upload(limit: &UploadLimit, size: usize) -> (Response, Duration) {
+        let request = Request::fake_http("POST", "/", vec![], vec![0; size]);
+        let start = Instant::now();
+        let response = limit.limit(&request, || {
+            let mut body = Vec::new();
+            match request.data().unwrap().read_to_end(&mut body) {
+                Ok(_) => Response::text(body.len().to_string()),
+                Err(_) => Response::empty_400(),
+            }
+        });
+        (response, start.elapsed())
+    }
+
+    #[test]
+    fn throttles_after_burst() {
+        let limit = UploadLimit::new(10_000).burst(1_000).per_request(true);
+        let (response, elapsed) = upload(&limit, 1_000);
+        assert_eq!(response.status_code, 200);
+        assert!(elapsed < Duration::from_millis(50));
+
+        // 2000 bytes beyond the burst take 200 ms.
+        let (response, elapsed) = upload(&limit, 3_000);
+        assert_eq!(response.status_code, 200);
+        assert!(elapsed >= Duration::from_millis(190));
+    }
+
+    #[test]
+    fn shared_by_connection() {
+        let limit = UploadLimit::new(10_000).burst(1_000);
+        let (_, elapsed) = upload(&limit, 1_000);
+        assert!(elapsed < Duration::from_millis(50));
+
+        // The fake requests all come from the same address.
+        let (_, elapsed) = upload(&limit, 1_000);
+        assert!(elapsed >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn rejects() {
+        let limit = UploadLimit::new(1_000)
+            .burst(100)
+            .per_request(true)
+            .reject_after(Duration::from_millis(100));
+        let (response, elapsed) = upload(&limit, 10_000);
+        assert_eq!(response.status_code, 429);
+        assert_eq!(response.header("Connection"), Some("close"));
+        assert!(response.header("Retry-After").is_some());
+        assert!(elapsed < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::fmt;
+
+use percent_encoding;
+use url::form_urlencoded;
+
+// Characters that are encoded in a segment of the path.
+const SEGMENT_ENCODE_SET: &percent_encoding::AsciiSet = &::DEFAULT_ENCODE_SET.add(b'%').add(b'/');
+// Same, but for `{*rest}` parameters, which can contain multiple segments.
+const REST_ENCODE_SET: &percent_encoding::AsciiSet = &::DEFAULT_ENCODE_SET.add(b'%');
+
+/// Builds a URL from a route pattern of the string-style syntax of `router!`.
+///
+/// Each `{name}` in the pattern is replaced with the percent-encoded value of the parameter of
+/// the same name. A `{*name}` parameter can contain slashes, which are kept as they are. The
+/// parameters that don't appear in the pattern are added to the query string.
+///
+/// By storing the patterns of your routes in constants and using them both in the `router!` and
+/// when generating links, the links stay in sync with the routes.
+///
+/// See also the [`url_for!`](macro.url_for.html) macro, which is easier to use.
+///
+/// # Panic
+///
+/// Panics if the pattern contains a parameter that isn't passed.
+///
+/// # Example
+///
+/// ```
+/// let url = rouille::url_for("/users/{id}/posts", &[("id", &"a b"), ("page", &2)]);
+/// assert_eq!(url, "/users/a%20b/posts?page=2");
+/// ```
+pub fn url_for(pattern: &str, params: &[(&str, &dyn fmt::Display)]) -> String {
+    let mut used = vec![false; params.len()];
+    let mut url = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        url.push_str(&rest[..start]);
+        let name = &rest[start + 1..end];
+        let (name, encode_set) = if name.starts_with('*') {
+            (&name[1..], REST_ENCODE_SET)
+        } else {
+            (name, SEGMENT_ENCODE_SET)
+        };
+
+        let index = match params.iter().position(|p| p.0 == name) {
+            Some(i) => i,
+            None => panic!(
+                "Missing url parameter `{}` to build url: {:?}",
+                name, pattern
+            ),
+        };
+        used[index] = true;
+        let value = params[index].1.to_string();
+        url.extend(percent_encoding::utf8_percent_encode(&value, encode_set));
+
+        rest = &rest[end + 1..];
+    }
+    url.push_str(rest);
+
+    if used.iter().any(|u| !u) {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        for (param, _) in params.iter().zip(used).filter(|p| !p.1) {
+            query.append_pair(param.0, &param.1.to_string());
+        }
+        url.push(if url.contains('?') { '&' } else { '?' });
+        url.push_str(&query.finish());
+    }
+
+    url
+}
+
+/// Builds a URL from a route pattern of the string-style syntax of `router!`.
+///
+/// This is a more convenient way to call [`url_for`](fn.url_for.html). The first parameter is the
+/// pattern, and the other parameters are of the form `name = value`, where `value` is anything
+/// that implements `Display`.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use rouille::{Request, Response};
+///
+/// const USER: &str = "/users/{id}";
+///
+/// fn handle(request: &Request) -> Response {
+///     router!(request,
+///         (GET) [USER, id: u32] => { Response::text(format!("user {}", id)) },
+///         (POST) ["/users"] => {
+///             // ... create the user ...
+///             Response::redirect_303(url_for!(USER, id = 5))
+///         },
+///         _ => Response::empty_404()
+///     )
+/// }
+/// # fn main() {
+/// # assert_eq!(url_for!(USER, id = 5, tab = "posts"), "/users/5?tab=posts");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! url_for {
+    ($pattern:expr $(, $name:ident = $value:expr)* $(,)*) => {
+        $crate::url_for($pattern, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),*])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::url_for;
+
+    #[test]
+    fn build() {
+        assert_eq!(url_for("/", &[]), "/");
+        assert_eq!(
+            url_for!("/users/{id}/{name}", id = 5, name = "a/b%"),
+            "/users/5/a%2Fb%25"
+        );
+        assert_eq!(
+            url_for!("/files/{*path}", path = "docs/a b.txt"),
+            "/files/docs/a%20b.txt"
+        );
+        assert_eq!(
+            url_for!("/search?lang=en", q = "a&b", page = 1),
+            "/search?lang=en&q=a%26b&page=1"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        use Request;
+
+        let url = url_for!("/users/{id}/{name}", id = 42, name = "Jane Doe?");
+        let request = Request::fake_http("GET", url, vec![], vec![]);
+        assert_eq!(request.path_segments(), vec!["users", "42", "Jane Doe?"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing url parameter `id`")]
+    fn missing_parameter() {
+        url_for("/users/{id}", &[]);
+    }
+}
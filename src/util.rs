@@ -0,0 +1,56 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+// Helpers shared by the modules that deal with tokens, hashes and signatures.
+
+// Encodes `bytes` in lowercase hexadecimal.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Decodes hexadecimal digits, in lowercase or uppercase.
+pub(crate) fn from_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() % 2 != 0 || !input.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&input[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Compares two secrets in a time that doesn't depend on the number of bytes that match.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, from_hex, to_hex};
+
+    #[test]
+    fn hex() {
+        assert_eq!(to_hex(&[0x00, 0x9f, 0xff]), "009fff");
+        assert_eq!(from_hex("009fFF"), Some(vec![0x00, 0x9f, 0xff]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("+1"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+}
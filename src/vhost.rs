@@ -0,0 +1,199 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+/// Dispatches the request to sub-handlers depending on its host.
+///
+/// Each parameter after the request must be of the form `pattern => handler`, where `handler` is
+/// something that can be called with a `&Request`, such as a function or a closure. The first
+/// handler whose pattern matches the host of the request, as returned by
+/// [`Request::host`](struct.Request.html#method.host), is called. If no pattern matches, or if
+/// the request has no valid host, the default expression after `_` is returned.
+///
+/// See [`match_host`](fn.match_host.html) for the syntax of the patterns. The values captured
+/// by the pattern are available with
+/// [`Request::host_param`](struct.Request.html#method.host_param).
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// # fn main() {
+/// use rouille::{Request, Response};
+///
+/// fn handle(request: &Request) -> Response {
+///     vhosts!(request,
+///         "api.example.com" => |request: &Request| Response::text("api"),
+///         "www.example.com" => |request: &Request| Response::text("website"),
+///         "{tenant}.example.com" => |request: &Request| {
+///             let tenant = request.host_param("tenant").unwrap();
+///             Response::text(format!("website of {}", tenant))
+///         },
+///         _ => Response::empty_404()
+///     )
+/// }
+/// # }
+/// ```
+#[macro_export]
+macro_rules! vhosts {
+    ($request:expr, $($pattern:expr => $handler:expr,)* _ => $default:expr $(,)*) => ({
+        let request = &$request;
+        let host = request.host();
+        let mut response = None;
+        $(
+            if response.is_none() {
+                if let Some(params) = host.as_ref().and_then(|h| $crate::match_host($pattern, h)) {
+                    request.extensions().insert(params);
+                    response = Some(($handler)(request));
+                }
+            }
+        )*
+        match response {
+            Some(response) => response,
+            None => $default,
+        }
+    });
+}
+
+/// The values captured from the host of the request by the pattern that matched it.
+///
+/// The [`vhosts!`](macro.vhosts.html) macro inserts this in the
+/// [extensions](struct.Request.html#method.extensions) of the request before calling the
+/// handler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostParams {
+    params: Vec<(String, String)>,
+}
+
+impl HostParams {
+    /// Returns the value captured by `{name}`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|p| p.0 == name)
+            .map(|p| p.1.as_str())
+    }
+
+    /// Returns the number of captured values.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    /// Returns true if no value was captured.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+}
+
+/// Matches a host against a pattern, and returns the captured values if it matches.
+///
+/// The pattern is a list of labels separated with dots, and must have the same number of labels
+/// as the host. Each label of the pattern is either:
+///
+/// - `{name}`, which matches any label and captures it under `name`.
+/// - `*`, which matches any label without capturing it.
+/// - A literal label, which is compared to the label of the host case-insensitively.
+///
+/// The host shouldn't contain a port. The captured values are in lowercase.
+///
+/// # Example
+///
+/// ```
+/// use rouille::match_host;
+///
+/// let params = match_host("{tenant}.example.com", "Acme.example.com").unwrap();
+/// assert_eq!(params.get("tenant"), Some("acme"));
+///
+/// assert!(match_host("*.example.com", "www.example.com").is_some());
+/// assert!(match_host("{tenant}.example.com", "a.b.example.com").is_none());
+/// assert!(match_host("api.example.com", "www.example.com").is_none());
+/// ```
+pub fn match_host(pattern: &str, host: &str) -> Option<HostParams> {
+    // A trailing dot refers to the same host.
+    let pattern = pattern.trim_end_matches('.');
+    let host = host.trim_end_matches('.');
+
+    let mut pattern_labels = pattern.split('.');
+    let mut host_labels = host.split('.');
+    let mut params = HostParams::default();
+
+    loop {
+        match (pattern_labels.next(), host_labels.next()) {
+            (None, None) => return Some(params),
+            (Some(_), None) | (None, Some(_)) => return None,
+            (Some(_), Some("")) => return None,
+            (Some("*"), Some(_)) => {}
+            (Some(expected), Some(label)) => {
+                if expected.starts_with('{') && expected.ends_with('}') && expected.len() >= 2 {
+                    let name = &expected[1..expected.len() - 1];
+                    params
+                        .params
+                        .push((name.to_owned(), label.to_ascii_lowercase()));
+                } else if !expected.eq_ignore_ascii_case(label) {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::match_host;
+    use Request;
+    use Response;
+
+    #[test]
+    fn patterns() {
+        assert!(match_host("example.com", "EXAMPLE.com.")
+            .unwrap()
+            .is_empty());
+        assert!(match_host("example.com", "www.example.com").is_none());
+        assert!(match_host("*.example.com", "example.com").is_none());
+
+        let params = match_host("{a}.{b}.example.com", "x.y.example.com").unwrap();
+        assert_eq!(params.len(), 2);
+        assert_eq!(params.get("a"), Some("x"));
+        assert_eq!(params.get("b"), Some("y"));
+        assert_eq!(params.get("c"), None);
+    }
+
+    #[test]
+    fn vhosts() {
+        let handle = |host: &str| {
+            let request = Request::fake_http(
+                "GET",
+                "/",
+                vec![("Host".to_owned(), host.to_owned())],
+                vec![],
+            );
+            let response = vhosts!(request,
+                "api.example.com" => |_: &Request| Response::text("api"),
+                "{tenant}.example.com" => |r: &Request| {
+                    Response::text(r.host_param("tenant").unwrap())
+                },
+                _ => Response::text("default")
+            );
+            let mut body = String::new();
+            response
+                .data
+                .into_reader_and_size()
+                .0
+                .read_to_string(&mut body)
+                .unwrap();
+            body
+        };
+
+        assert_eq!(handle("api.example.com:8080"), "api");
+        assert_eq!(handle("acme.example.com"), "acme");
+        assert_eq!(handle("example.com"), "default");
+        assert_eq!(handle("a/b"), "default");
+    }
+}
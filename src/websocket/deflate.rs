@@ -0,0 +1,351 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! The `permessage-deflate` extension of RFC 7692.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io;
+
+use Request;
+
+/// Configuration of the `permessage-deflate` extension, which compresses the messages. See
+/// [`WebsocketOptions::deflate`](struct.WebsocketOptions.html#structfield.deflate).
+///
+/// The messages sent by the server always use a window of 32 kiB, so the offers of the clients
+/// that require a smaller one are declined.
+///
+/// # Example
+///
+/// ```
+/// use rouille::websocket::{Deflate, WebsocketOptions};
+///
+/// let options = WebsocketOptions {
+///     deflate: Some(Deflate {
+///         server_no_context_takeover: true,
+///         ..Deflate::default()
+///     }),
+///     ..WebsocketOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deflate {
+    /// Compression level of the messages sent by the server, from 0 to 9. Defaults to 6.
+    pub level: u32,
+
+    /// If true, the server compresses each message on its own instead of referring to the
+    /// previous ones, which uses less memory between the messages but compresses repetitive
+    /// messages less. Defaults to `false`.
+    ///
+    /// The server also does it when the client asks for it.
+    pub server_no_context_takeover: bool,
+
+    /// If true, asks the client to compress each message on its own. Defaults to `false`.
+    pub client_no_context_takeover: bool,
+
+    /// If `Some`, asks the client to use a window of at most `2^n` bytes, with `n` between 8 and
+    /// 15, if the client supports it. Defaults to `None`.
+    pub client_max_window_bits: Option<u8>,
+}
+
+impl Default for Deflate {
+    #[inline]
+    fn default() -> Deflate {
+        Deflate {
+            level: 6,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            client_max_window_bits: None,
+        }
+    }
+}
+
+// Parameters agreed with the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Negotiated {
+    // Value of the `Sec-WebSocket-Extensions` header of the response.
+    pub(crate) header: String,
+    pub(crate) level: u32,
+    pub(crate) server_no_context_takeover: bool,
+    pub(crate) client_no_context_takeover: bool,
+}
+
+// Returns the parameters of the first offer of the client that the server accepts, if any.
+pub(crate) fn negotiate(request: &Request, config: &Deflate) -> Option<Negotiated> {
+    let offers = request
+        .headers()
+        .filter(|&(name, _)| name.eq_ignore_ascii_case("Sec-WebSocket-Extensions"))
+        .flat_map(|(_, value)| value.split(','));
+
+    'offers: for offer in offers {
+        let mut params = offer.split(';').map(|param| param.trim());
+        if !params.next().map_or(false, |name| {
+            name.eq_ignore_ascii_case("permessage-deflate")
+        }) {
+            continue;
+        }
+
+        let mut server_no_context_takeover = config.server_no_context_takeover;
+        // `None` if the client doesn't support the parameter, `Some(15)` if it has no value.
+        let mut client_max_window_bits = None;
+        let mut seen = Vec::new();
+        for param in params {
+            let mut split = param.splitn(2, '=');
+            let name = split.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = split.next().map(|value| value.trim().trim_matches('"'));
+            if seen.contains(&name) {
+                continue 'offers;
+            }
+
+            let window_bits = value.map(|value| match value.parse::<u8>() {
+                Ok(bits) if (8..=15).contains(&bits) && !value.starts_with('0') => Some(bits),
+                _ => None,
+            });
+            match (&name[..], window_bits) {
+                ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => (),
+                // The compressor of the server can only use the largest window.
+                ("server_max_window_bits", Some(Some(15))) => (),
+                ("client_max_window_bits", None) => client_max_window_bits = Some(15),
+                ("client_max_window_bits", Some(Some(bits))) => client_max_window_bits = Some(bits),
+                _ => continue 'offers,
+            }
+            seen.push(name);
+        }
+
+        let mut header = "permessage-deflate".to_owned();
+        if server_no_context_takeover {
+            header.push_str("; server_no_context_takeover");
+        }
+        if config.client_no_context_takeover {
+            header.push_str("; client_no_context_takeover");
+        }
+        if let (Some(offered), Some(wanted)) =
+            (client_max_window_bits, config.client_max_window_bits)
+        {
+            let bits = offered.min(wanted.max(8).min(15));
+            header.push_str(&format!("; client_max_window_bits={}", bits));
+        }
+
+        return Some(Negotiated {
+            header,
+            level: config.level.min(9),
+            server_no_context_takeover,
+            client_no_context_takeover: config.client_no_context_takeover,
+        });
+    }
+
+    None
+}
+
+// The trailer that ends each compressed message, which is left out of the frames.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Compression state of a connection.
+pub(crate) struct Context {
+    compress: Compress,
+    decompress: Decompress,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+}
+
+impl Context {
+    pub(crate) fn new(negotiated: &Negotiated) -> Context {
+        Context {
+            compress: Compress::new(Compression::new(negotiated.level), false),
+            decompress: Decompress::new(false),
+            server_no_context_takeover: negotiated.server_no_context_takeover,
+            client_no_context_takeover: negotiated.client_no_context_takeover,
+        }
+    }
+
+    // Compresses the payload of a message sent by the server.
+    pub(crate) fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            // The flush is complete once the compressor doesn't fill the buffer anymore.
+            if (self.compress.total_in() - start) as usize == data.len()
+                && output.len() < output.capacity()
+            {
+                break;
+            }
+        }
+
+        if output.ends_with(&TRAILER) {
+            output.truncate(output.len() - TRAILER.len());
+        }
+        if output.is_empty() {
+            // An empty stored block, as recommended for empty messages.
+            output.push(0x00);
+        }
+        if self.server_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(output)
+    }
+
+    // Decompresses the payload of a message received from the client. Returns `None` if the
+    // message is larger than `max_size`, in which case the context can't be used anymore.
+    pub(crate) fn decompress(
+        &mut self,
+        mut data: Vec<u8>,
+        max_size: Option<usize>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        data.extend_from_slice(&TRAILER);
+        let max_size = max_size.unwrap_or(std::usize::MAX);
+        let mut output = Vec::with_capacity(data.len().saturating_mul(4).min(max_size));
+        let start = self.decompress.total_in();
+        loop {
+            if output.len() > max_size {
+                return Ok(None);
+            }
+            if output.len() == output.capacity() {
+                // Growing the buffer to one byte more than the limit at most, which is enough to
+                // know that it's exceeded.
+                let additional = output.capacity().max(64).min(max_size - output.len() + 1);
+                output.reserve_exact(additional);
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let produced = output.len();
+            let status = self
+                .decompress
+                .decompress_vec(&data[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let consumed_now = (self.decompress.total_in() - start) as usize;
+
+            if status == Status::StreamEnd {
+                // The client ended the stream with a final block, so the next message starts a
+                // new one.
+                self.decompress.reset(false);
+                break;
+            }
+            if consumed_now == data.len() && output.len() < output.capacity() {
+                break;
+            }
+            if consumed_now == consumed && output.len() == produced {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated compressed message",
+                ));
+            }
+        }
+
+        if output.len() > max_size {
+            return Ok(None);
+        }
+        if self.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::negotiate;
+    use super::Context;
+    use super::Deflate;
+    use Request;
+
+    fn offer(extensions: &str) -> Request {
+        let headers = vec![("Sec-WebSocket-Extensions".to_owned(), extensions.to_owned())];
+        Request::fake_http("GET", "/", headers, vec![])
+    }
+
+    #[test]
+    fn negotiation() {
+        let config = Deflate::default();
+        let header = |extensions, config: &Deflate| {
+            negotiate(&offer(extensions), config).map(|negotiated| negotiated.header)
+        };
+
+        assert_eq!(
+            header("permessage-deflate; client_max_window_bits", &config).unwrap(),
+            "permessage-deflate"
+        );
+        assert_eq!(
+            header(
+                "x-webkit-deflate-frame, permessage-deflate; server_no_context_takeover",
+                &config
+            )
+            .unwrap(),
+            "permessage-deflate; server_no_context_takeover"
+        );
+        // The first offer requires a smaller window for the server.
+        assert_eq!(
+            header(
+                "permessage-deflate; server_max_window_bits=10, permessage-deflate",
+                &config
+            )
+            .unwrap(),
+            "permessage-deflate"
+        );
+        assert_eq!(header("permessage-deflate; unknown", &config), None);
+        assert_eq!(
+            header("permessage-deflate; client_max_window_bits=16", &config),
+            None
+        );
+        assert_eq!(header("x-webkit-deflate-frame", &config), None);
+
+        let config = Deflate {
+            client_no_context_takeover: true,
+            client_max_window_bits: Some(10),
+            ..Deflate::default()
+        };
+        assert_eq!(
+            header("permessage-deflate; client_max_window_bits=12", &config).unwrap(),
+            "permessage-deflate; client_no_context_takeover; client_max_window_bits=10"
+        );
+        assert_eq!(
+            header("permessage-deflate", &config).unwrap(),
+            "permessage-deflate; client_no_context_takeover"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let negotiated = negotiate(&offer("permessage-deflate"), &Deflate::default()).unwrap();
+        let mut server = Context::new(&negotiated);
+        let mut client = Context::new(&negotiated);
+
+        let message =
+            br#"{"values": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]}"#.repeat(50);
+        let first = server.compress(&message).unwrap();
+        assert!(first.len() < message.len() / 10);
+        let decompressed = client.decompress(first.clone(), Some(message.len()));
+        assert_eq!(decompressed.unwrap().unwrap(), message);
+
+        // The second message refers to the first one.
+        let second = server.compress(&message).unwrap();
+        assert!(second.len() < first.len());
+        assert_eq!(client.decompress(second, None).unwrap().unwrap(), message);
+
+        let empty = server.compress(b"").unwrap();
+        assert_eq!(empty, vec![0x00]);
+        assert_eq!(client.decompress(empty, Some(0)).unwrap().unwrap(), b"");
+
+        let mut client = Context::new(&negotiated);
+        let bomb = server.compress(&vec![0; 1 << 20]).unwrap();
+        assert!(bomb.len() < 2048);
+        assert_eq!(client.decompress(bomb, Some(1 << 16)).unwrap(), None);
+
+        // The example of section 7.2.3.1 of RFC 7692.
+        let mut context = Context::new(&negotiated);
+        let hello = vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert_eq!(context.decompress(hello, None).unwrap().unwrap(), b"Hello");
+        assert!(context.decompress(vec![0xff, 0xff], None).is_err());
+    }
+}
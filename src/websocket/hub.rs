@@ -0,0 +1,299 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use websocket::SendError;
+use websocket::Websocket;
+
+/// Groups websockets into named channels, and sends messages to all the websockets of a channel
+/// at once.
+///
+/// The hub takes the ownership of the websockets that join it, and only sends messages to them.
+/// Since reading from a websocket blocks, the messages of the clients aren't read. A websocket
+/// is removed from its channel when a message can't be sent to it, which happens when the client
+/// has closed the connection. Empty channels are removed as well.
+///
+/// The methods of `Hub` take `&self`, so it can be shared between threads with an `Arc` or a
+/// `static`.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// use rouille::Request;
+/// use rouille::Response;
+/// use rouille::websocket;
+/// use rouille::websocket::Hub;
+/// # fn main() {}
+///
+/// fn handle_request(request: &Request, hub: &Arc<Hub>) -> Response {
+///     let (response, websocket) = try_or_400!(websocket::start(request, None::<&str>));
+///
+///     // The websocket is only available once the response has been sent.
+///     let hub = hub.clone();
+///     thread::spawn(move || {
+///         if let Ok(websocket) = websocket.recv() {
+///             hub.join("notifications", websocket);
+///         }
+///     });
+///
+///     response
+/// }
+///
+/// fn notify(hub: &Hub, text: &str) {
+///     let delivered = hub.broadcast_text("notifications", text);
+///     println!("sent to {} clients", delivered);
+/// }
+/// ```
+#[derive(Default)]
+pub struct Hub {
+    channels: Mutex<HashMap<String, Vec<Member>>>,
+    next_id: AtomicUsize,
+}
+
+/// Identifies a websocket that joined a `Hub`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MemberId(usize);
+
+struct Member {
+    id: MemberId,
+    // Each websocket has its own lock, so that the broadcasts to different channels can happen
+    // at the same time.
+    websocket: Arc<Mutex<Websocket>>,
+}
+
+impl Hub {
+    /// Builds a hub without any channel.
+    #[inline]
+    pub fn new() -> Hub {
+        Hub::default()
+    }
+
+    /// Adds a websocket to a channel, which is created if it doesn't exist.
+    pub fn join<C>(&self, channel: C, websocket: Websocket) -> MemberId
+    where
+        C: Into<String>,
+    {
+        let id = MemberId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let member = Member {
+            id,
+            websocket: Arc::new(Mutex::new(websocket)),
+        };
+        let mut channels = self.channels.lock().unwrap();
+        channels.entry(channel.into()).or_default().push(member);
+        id
+    }
+
+    /// Removes a websocket from its channel, which closes the connection once no broadcast is
+    /// using it anymore.
+    ///
+    /// Returns false if the websocket wasn't in the hub anymore.
+    pub fn leave(&self, member: MemberId) -> bool {
+        let mut channels = self.channels.lock().unwrap();
+        let mut found = false;
+        for members in channels.values_mut() {
+            let len = members.len();
+            members.retain(|m| m.id != member);
+            found |= members.len() != len;
+        }
+        channels.retain(|_, members| !members.is_empty());
+        found
+    }
+
+    /// Sends a text message to all the websockets of a channel. Returns the number of websockets
+    /// that the message was sent to.
+    #[inline]
+    pub fn broadcast_text(&self, channel: &str, text: &str) -> usize {
+        self.broadcast(channel, |websocket| websocket.send_text(text))
+    }
+
+    /// Sends a binary message to all the websockets of a channel. Returns the number of
+    /// websockets that the message was sent to.
+    #[inline]
+    pub fn broadcast_binary(&self, channel: &str, data: &[u8]) -> usize {
+        self.broadcast(channel, |websocket| websocket.send_binary(data))
+    }
+
+    /// Sends a text message to one websocket of the hub.
+    ///
+    /// Returns `SendError::Closed` if the websocket isn't in the hub anymore. It is removed from
+    /// the hub if the message can't be sent.
+    pub fn send_text(&self, member: MemberId, text: &str) -> Result<(), SendError> {
+        let websocket = {
+            let channels = self.channels.lock().unwrap();
+            channels
+                .values()
+                .flat_map(|members| members.iter())
+                .find(|m| m.id == member)
+                .map(|m| m.websocket.clone())
+        };
+        let websocket = websocket.ok_or(SendError::Closed)?;
+
+        let result = websocket.lock().unwrap().send_text(text);
+        if result.is_err() {
+            self.leave(member);
+        }
+        result
+    }
+
+    /// Returns the number of websockets in a channel.
+    pub fn len(&self, channel: &str) -> usize {
+        let channels = self.channels.lock().unwrap();
+        channels.get(channel).map_or(0, |members| members.len())
+    }
+
+    /// Returns true if the hub doesn't have any websocket.
+    pub fn is_empty(&self) -> bool {
+        self.channels.lock().unwrap().is_empty()
+    }
+
+    /// Returns the names of the channels that have at least one websocket.
+    pub fn channels(&self) -> Vec<String> {
+        let channels = self.channels.lock().unwrap();
+        channels.keys().cloned().collect()
+    }
+
+    fn broadcast<F>(&self, channel: &str, send: F) -> usize
+    where
+        F: Fn(&mut Websocket) -> Result<(), SendError>,
+    {
+        // The list is copied so that the websockets aren't written to while the hub is locked.
+        let members: Vec<_> = {
+            let channels = self.channels.lock().unwrap();
+            match channels.get(channel) {
+                Some(members) => members
+                    .iter()
+                    .map(|m| (m.id, m.websocket.clone()))
+                    .collect(),
+                None => return 0,
+            }
+        };
+
+        let mut failed = Vec::new();
+        for &(id, ref websocket) in &members {
+            if send(&mut websocket.lock().unwrap()).is_err() {
+                failed.push(id);
+            }
+        }
+
+        if !failed.is_empty() {
+            let mut channels = self.channels.lock().unwrap();
+            let now_empty = match channels.get_mut(channel) {
+                Some(members) => {
+                    members.retain(|m| !failed.contains(&m.id));
+                    members.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                channels.remove(channel);
+            }
+        }
+
+        members.len() - failed.len()
+    }
+}
+
+impl fmt::Debug for Hub {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let channels = self.channels.lock().unwrap();
+        let mut map = f.debug_map();
+        for (name, members) in channels.iter() {
+            map.entry(name, &members.len());
+        }
+        map.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    use super::Hub;
+    use websocket::Websocket;
+    use Upgrade;
+
+    // A connection whose writes fail once `closed` is set.
+    struct FakeSocket {
+        output: Arc<Mutex<Vec<u8>>>,
+        closed: Arc<AtomicBool>,
+    }
+
+    impl Read for FakeSocket {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for FakeSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn websocket() -> (Websocket, Arc<Mutex<Vec<u8>>>, Arc<AtomicBool>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let socket = FakeSocket {
+            output: output.clone(),
+            closed: closed.clone(),
+        };
+        let (mut tx, rx) = mpsc::channel();
+        tx.build(Box::new(socket));
+        (rx.recv().unwrap(), output, closed)
+    }
+
+    #[test]
+    fn broadcast() {
+        let hub = Hub::new();
+        let (first, first_output, first_closed) = websocket();
+        let (second, second_output, _) = websocket();
+        let (other, other_output, _) = websocket();
+        hub.join("room", first);
+        let second = hub.join("room", second);
+        hub.join("other", other);
+        assert_eq!(hub.len("room"), 2);
+
+        assert_eq!(hub.broadcast_text("room", "hi"), 2);
+        assert_eq!(&first_output.lock().unwrap()[..], &[0x81, 2, b'h', b'i']);
+        assert_eq!(&second_output.lock().unwrap()[..], &[0x81, 2, b'h', b'i']);
+        assert!(other_output.lock().unwrap().is_empty());
+        assert_eq!(hub.broadcast_binary("missing", b"hi"), 0);
+
+        // The closed connection is removed from the channel.
+        first_closed.store(true, Ordering::SeqCst);
+        assert_eq!(hub.broadcast_text("room", "hi"), 1);
+        assert_eq!(hub.len("room"), 1);
+
+        assert!(hub.send_text(second, "you").is_ok());
+        assert!(hub.leave(second));
+        assert!(!hub.leave(second));
+        assert!(hub.send_text(second, "you").is_err());
+        assert_eq!(hub.channels(), vec!["other".to_owned()]);
+    }
+}
@@ -35,6 +35,9 @@ pub enum Element<'a> {
         length: u64,
         /// Opcode. See https://tools.ietf.org/html/rfc6455#section-5.2.
         opcode: u8,
+        /// The RSV1 bit, which the `permessage-deflate` extension sets on the first frame of
+        /// the compressed messages.
+        compressed: bool,
     },
 
     /// Data was received as part of the current frame.
@@ -137,8 +140,9 @@ impl<'a> Iterator for ElementsIter<'a> {
                     (first_byte, second_byte)
                 };
 
-                // Reserved bits must be zero, otherwise error.
-                if (first_byte & 0x70) != 0 {
+                // Reserved bits must be zero, otherwise error. RSV1 is checked by the caller,
+                // depending on the negotiated extensions.
+                if (first_byte & 0x30) != 0 {
                     return Some(Element::Error {
                         desc: "Reserved bits must be zero",
                     });
@@ -266,6 +270,7 @@ impl<'a> Iterator for ElementsIter<'a> {
                     fin: (first_byte & 0x80) != 0,
                     length,
                     opcode: first_byte & 0xf,
+                    compressed: (first_byte & 0x40) != 0,
                 })
             }
 
@@ -368,7 +373,8 @@ mod tests {
             Element::FrameStart {
                 fin: true,
                 length: 5,
-                opcode: 1
+                opcode: 1,
+                compressed: false,
             }
         );
 
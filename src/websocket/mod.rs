@@ -44,6 +44,20 @@
 //! - Your route supports multiple subprotocols. This is the most complex situation as you will
 //!   have to enumerate the protocols with `requested_protocols()` and choose one.
 //!
+//! # Compression
+//!
+//! With the `websocket-deflate` feature, the messages can be compressed with the
+//! `permessage-deflate` extension if the client supports it, which all the major browsers do.
+//! Call `start_with_options()` with a `deflate` configuration instead of `start()` to enable it.
+//! The compression is transparent: the `Websocket` object sends and produces uncompressed
+//! messages.
+//!
+//! # Broadcasting
+//!
+//! A `Hub` groups the websockets into named channels and sends a message to all the websockets
+//! of a channel at once, from any thread. The websockets that are closed are removed
+//! automatically.
+//!
 //! # Example
 //!
 //! ```
@@ -65,6 +79,9 @@
 //! }
 //! ```
 
+#[cfg(feature = "websocket-deflate")]
+pub use self::deflate::Deflate;
+pub use self::hub::{Hub, MemberId};
 pub use self::websocket::Message;
 pub use self::websocket::SendError;
 pub use self::websocket::Websocket;
@@ -80,6 +97,9 @@ use std::vec::IntoIter as VecIntoIter;
 use Request;
 use Response;
 
+#[cfg(feature = "websocket-deflate")]
+mod deflate;
+mod hub;
 mod low_level;
 #[allow(clippy::module_inception)]
 mod websocket;
@@ -120,11 +140,81 @@ impl fmt::Display for WebsocketError {
     }
 }
 
+/// Options of a websocket connection, given to `start_with_options()`.
+///
+/// # Example
+///
+/// ```
+/// use rouille::websocket::WebsocketOptions;
+///
+/// let options = WebsocketOptions {
+///     max_message_size: Some(16 * 1024 * 1024),
+///     max_fragments: Some(64),
+///     ..WebsocketOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WebsocketOptions {
+    /// If `Some`, the messages are compressed with the `permessage-deflate` extension when the
+    /// client supports it. Defaults to `None`.
+    ///
+    /// Requires the `websocket-deflate` feature.
+    #[cfg(feature = "websocket-deflate")]
+    pub deflate: Option<Deflate>,
+
+    /// Maximum size in bytes of a message received from the client, after decompression.
+    /// Defaults to `None`, meaning no limit.
+    ///
+    /// The connection is closed with the status code 1009 if the client sends a larger message,
+    /// before it is buffered.
+    pub max_message_size: Option<usize>,
+
+    /// Maximum size in bytes of a frame received from the client. Defaults to `None`, meaning no
+    /// limit.
+    ///
+    /// The connection is closed with the status code 1009 if the client sends a larger frame.
+    pub max_frame_size: Option<u64>,
+
+    /// Maximum number of frames that a message received from the client can be split into.
+    /// Defaults to `None`, meaning no limit.
+    ///
+    /// The connection is closed with the status code 1009 if the client sends more fragments.
+    pub max_fragments: Option<usize>,
+}
+
 /// Builds a `Response` that initiates the websocket protocol.
+#[inline]
 pub fn start<S>(
     request: &Request,
     subprotocol: Option<S>,
 ) -> Result<(Response, mpsc::Receiver<Websocket>), WebsocketError>
+where
+    S: Into<Cow<'static, str>>,
+{
+    start_with_options(request, subprotocol, WebsocketOptions::default())
+}
+
+/// Same as `start()`, but with options for the connection.
+///
+/// # Example
+///
+/// ```
+/// # #[macro_use] extern crate rouille;
+/// use rouille::websocket::{self, WebsocketOptions};
+/// # fn main() {}
+///
+/// # fn handle(request: &rouille::Request) -> rouille::Response {
+/// let options = WebsocketOptions::default();
+/// let (response, websocket) = try_or_400!(websocket::start_with_options(request, None::<&str>,
+///                                                                        options));
+/// # response
+/// # }
+/// ```
+pub fn start_with_options<S>(
+    request: &Request,
+    subprotocol: Option<S>,
+    options: WebsocketOptions,
+) -> Result<(Response, mpsc::Receiver<Websocket>), WebsocketError>
 where
     S: Into<Cow<'static, str>>,
 {
@@ -184,7 +274,33 @@ where
     response
         .headers
         .push(("Sec-Websocket-Accept".into(), key.into()));
-    response.upgrade = Some(Box::new(tx) as Box<_>);
+
+    #[cfg(feature = "websocket-deflate")]
+    let deflate = options
+        .deflate
+        .as_ref()
+        .and_then(|config| deflate::negotiate(request, config));
+    #[cfg(feature = "websocket-deflate")]
+    {
+        if let Some(ref negotiated) = deflate {
+            response.headers.push((
+                "Sec-Websocket-Extensions".into(),
+                negotiated.header.clone().into(),
+            ));
+        }
+    }
+
+    let upgrade = websocket::WebsocketUpgrade {
+        sender: tx,
+        limits: websocket::Limits {
+            message_size: options.max_message_size,
+            frame_size: options.max_frame_size,
+            fragments: options.max_fragments,
+        },
+        #[cfg(feature = "websocket-deflate")]
+        deflate,
+    };
+    response.upgrade = Some(Box::new(upgrade) as Box<_>);
     Ok((response, rx))
 }
 
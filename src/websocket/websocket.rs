@@ -14,6 +14,8 @@ use std::sync::mpsc::Sender;
 use ReadWrite;
 use Upgrade;
 
+#[cfg(feature = "websocket-deflate")]
+use websocket::deflate;
 use websocket::low_level;
 
 /// A successful websocket. An open channel of communication. Implements `Read` and `Write`.
@@ -25,16 +27,35 @@ pub struct Websocket {
     // True if the fragmented message currently being processed is binary. False if string. Pings
     // are excluded.
     current_message_binary: bool,
+    // True if the fragmented message currently being processed is compressed.
+    current_message_compressed: bool,
+    // Number of frames received so far for the fragmented message currently being processed.
+    current_message_fragments: usize,
     // Buffer for the fragmented message currently being processed. Pings are excluded.
     current_message_payload: Vec<u8>,
     // Opcode of the fragment currently being processed.
     current_frame_opcode: u8,
     // Fin flag of the fragment currently being processed.
     current_frame_fin: bool,
+    // RSV1 flag of the fragment currently being processed.
+    current_frame_compressed: bool,
     // Data of the fragment currently being processed.
     current_frame_payload: Vec<u8>,
     // Queue of the messages that are going to be returned by `next()`.
     messages_in_queue: Vec<Message>,
+    // Limits on the messages received from the client.
+    limits: Limits,
+    // State of the `permessage-deflate` extension, if it was negotiated.
+    #[cfg(feature = "websocket-deflate")]
+    deflate: Option<deflate::Context>,
+}
+
+// Limits from `WebsocketOptions`. `None` means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Limits {
+    pub(crate) message_size: Option<usize>,
+    pub(crate) frame_size: Option<u64>,
+    pub(crate) fragments: Option<usize>,
 }
 
 /// A message produced by a websocket connection.
@@ -74,13 +95,7 @@ impl Websocket {
     /// If the client is in javascript, the message will contain a string.
     #[inline]
     pub fn send_text(&mut self, data: &str) -> Result<(), SendError> {
-        let socket = match self.socket {
-            Some(ref mut s) => s,
-            None => return Err(SendError::Closed),
-        };
-
-        send(data.as_bytes(), Write::by_ref(socket), 0x1)?;
-        Ok(())
+        self.send_message(data.as_bytes(), 0x1)
     }
 
     /// Sends binary data over the websocket.
@@ -90,13 +105,7 @@ impl Websocket {
     /// If the client is in javascript, the message will contain a blob or an arraybuffer.
     #[inline]
     pub fn send_binary(&mut self, data: &[u8]) -> Result<(), SendError> {
-        let socket = match self.socket {
-            Some(ref mut s) => s,
-            None => return Err(SendError::Closed),
-        };
-
-        send(data, Write::by_ref(socket), 0x2)?;
-        Ok(())
+        self.send_message(data, 0x2)
     }
 
     /// Returns `true` if the websocket has been closed by either the client (voluntarily or not)
@@ -107,22 +116,151 @@ impl Websocket {
     }
 
     // TODO: give access to close reason
-}
 
-impl Upgrade for Sender<Websocket> {
-    fn build(&mut self, socket: Box<dyn ReadWrite + Send>) {
-        let websocket = Websocket {
+    fn new(socket: Box<dyn ReadWrite + Send>) -> Websocket {
+        Websocket {
             socket: Some(socket),
             state_machine: low_level::StateMachine::new(),
             current_message_binary: false,
+            current_message_compressed: false,
+            current_message_fragments: 0,
             current_message_payload: Vec::new(),
             current_frame_opcode: 0,
             current_frame_fin: false,
+            current_frame_compressed: false,
             current_frame_payload: Vec::new(),
             messages_in_queue: Vec::new(),
+            limits: Limits::default(),
+            #[cfg(feature = "websocket-deflate")]
+            deflate: None,
+        }
+    }
+
+    fn send_message(&mut self, data: &[u8], opcode: u8) -> Result<(), SendError> {
+        let socket = match self.socket {
+            Some(ref mut s) => s,
+            None => return Err(SendError::Closed),
         };
 
-        let _ = self.send(websocket);
+        #[cfg(feature = "websocket-deflate")]
+        {
+            if let Some(ref mut deflate) = self.deflate {
+                let compressed = deflate.compress(data)?;
+                send_frame(&compressed, Write::by_ref(socket), opcode, true)?;
+                return Ok(());
+            }
+        }
+
+        send(data, Write::by_ref(socket), opcode)?;
+        Ok(())
+    }
+
+    // Returns true if the client is allowed to send compressed messages.
+    #[cfg(feature = "websocket-deflate")]
+    fn compression_enabled(&self) -> bool {
+        self.deflate.is_some()
+    }
+
+    #[cfg(not(feature = "websocket-deflate"))]
+    fn compression_enabled(&self) -> bool {
+        false
+    }
+
+    // Sends a close frame with a status code and a reason, then drops the connection.
+    fn close(&mut self, code: u16, reason: &str) {
+        if let Some(mut socket) = self.socket.take() {
+            let mut payload = Vec::with_capacity(2 + reason.len());
+            payload.push((code >> 8) as u8);
+            payload.push(code as u8);
+            payload.extend_from_slice(reason.as_bytes());
+            let _ = send(&payload, Write::by_ref(&mut socket), 0x8);
+        }
+    }
+
+    #[cfg(feature = "websocket-deflate")]
+    fn decompress(&mut self, payload: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let max_size = self.limits.message_size;
+        match self.deflate {
+            Some(ref mut deflate) => deflate.decompress(payload, max_size),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "compression wasn't negotiated",
+            )),
+        }
+    }
+
+    #[cfg(not(feature = "websocket-deflate"))]
+    fn decompress(&mut self, _: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "compression wasn't negotiated",
+        ))
+    }
+
+    // Adds a complete message to `messages_in_queue`. Returns false if the message is invalid, in
+    // which case the connection has been closed.
+    fn queue_message(&mut self, binary: bool, compressed: bool, payload: Vec<u8>) -> bool {
+        let payload = if compressed {
+            match self.decompress(payload) {
+                Ok(Some(payload)) => payload,
+                Ok(None) => {
+                    self.close(1009, "Message too big");
+                    return false;
+                }
+                Err(_) => {
+                    // Closing connection because the message couldn't be decompressed
+                    self.close(1007, "Invalid compressed data");
+                    return false;
+                }
+            }
+        } else {
+            payload
+        };
+
+        if binary {
+            self.messages_in_queue.push(Message::Binary(payload));
+            return true;
+        }
+
+        match String::from_utf8(payload) {
+            Ok(s) => {
+                self.messages_in_queue.push(Message::Text(s));
+                true
+            }
+            Err(_) => {
+                // Closing connection because text wasn't UTF-8
+                self.close(1007, "Invalid UTF-8 encoding");
+                false
+            }
+        }
+    }
+}
+
+// Produces the `Websocket` of a connection started with `start_with_options`.
+pub(crate) struct WebsocketUpgrade {
+    pub(crate) sender: Sender<Websocket>,
+    pub(crate) limits: Limits,
+    // The parameters of `permessage-deflate` accepted in the response, if any.
+    #[cfg(feature = "websocket-deflate")]
+    pub(crate) deflate: Option<deflate::Negotiated>,
+}
+
+impl Upgrade for WebsocketUpgrade {
+    fn build(&mut self, socket: Box<dyn ReadWrite + Send>) {
+        let mut websocket = Websocket::new(socket);
+        websocket.limits = self.limits;
+        #[cfg(feature = "websocket-deflate")]
+        {
+            websocket.deflate = self.deflate.as_ref().map(deflate::Context::new);
+        }
+
+        let _ = self.sender.send(websocket);
+    }
+}
+
+impl Upgrade for Sender<Websocket> {
+    fn build(&mut self, socket: Box<dyn ReadWrite + Send>) {
+        let _ = self.send(Websocket::new(socket));
     }
 }
 
@@ -155,13 +293,62 @@ impl Iterator for Websocket {
                 }
             };
 
-            // Fill `messages_in_queue` by analyzing the packets.
-            for element in self.state_machine.feed(&buf[0..n]) {
+            // Fill `messages_in_queue` by analyzing the packets. The state machine is moved out
+            // while its elements are processed, and only put back if the connection is still open.
+            let mut state_machine =
+                mem::replace(&mut self.state_machine, low_level::StateMachine::new());
+            for element in state_machine.feed(&buf[0..n]) {
                 match element {
-                    low_level::Element::FrameStart { fin, opcode, .. } => {
+                    low_level::Element::FrameStart {
+                        fin,
+                        length,
+                        opcode,
+                        compressed,
+                    } => {
                         debug_assert!(self.current_frame_payload.is_empty());
+
+                        // Only the first frame of a data message can be compressed.
+                        if compressed
+                            && !(self.compression_enabled() && (opcode == 0x1 || opcode == 0x2))
+                        {
+                            self.close(1002, "Reserved bits must be zero");
+                            return None;
+                        }
+
+                        // The limits are checked before buffering the data of the frame.
+                        if self.limits.frame_size.map_or(false, |max| length > max) {
+                            self.close(1009, "Frame too big");
+                            return None;
+                        }
+                        if opcode <= 0x2 {
+                            let buffered = if opcode == 0x0 {
+                                self.current_message_fragments += 1;
+                                self.current_message_payload.len() as u64
+                            } else {
+                                self.current_message_fragments = 1;
+                                0
+                            };
+                            if self
+                                .limits
+                                .message_size
+                                .map_or(false, |max| buffered + length > max as u64)
+                            {
+                                self.close(1009, "Message too big");
+                                return None;
+                            }
+                            if self
+                                .limits
+                                .fragments
+                                .map_or(false, |max| self.current_message_fragments > max)
+                            {
+                                self.close(1009, "Too many fragments");
+                                return None;
+                            }
+                        }
+
                         self.current_frame_fin = fin;
                         self.current_frame_opcode = opcode;
+                        self.current_frame_compressed = compressed;
                     }
 
                     low_level::Element::Data {
@@ -182,31 +369,14 @@ impl Iterator for Websocket {
 
                                     // If the message is finished, dispatch it.
                                     if self.current_frame_fin {
-                                        let binary = mem::replace(
+                                        let payload = mem::replace(
                                             &mut self.current_message_payload,
                                             Vec::new(),
                                         );
-
-                                        if self.current_message_binary {
-                                            self.messages_in_queue.push(Message::Binary(binary));
-                                        } else {
-                                            let string = match String::from_utf8(binary) {
-                                                Ok(s) => s,
-                                                Err(_) => {
-                                                    // Closing connection because text wasn't UTF-8
-                                                    let _ = send(
-                                                        b"1007 Invalid UTF-8 encoding",
-                                                        Write::by_ref(
-                                                            self.socket.as_mut().unwrap(),
-                                                        ),
-                                                        0x8,
-                                                    );
-                                                    self.socket = None;
-                                                    return None;
-                                                }
-                                            };
-
-                                            self.messages_in_queue.push(Message::Text(string));
+                                        let binary = self.current_message_binary;
+                                        let compressed = self.current_message_compressed;
+                                        if !self.queue_message(binary, compressed, payload) {
+                                            return None;
                                         }
                                     }
                                 }
@@ -216,39 +386,25 @@ impl Iterator for Websocket {
                                     // If we're in the middle of a message, this frame is invalid
                                     // and we need to close.
                                     if !self.current_message_payload.is_empty() {
-                                        let _ = send(
-                                            b"1002 Expected continuation frame",
-                                            Write::by_ref(self.socket.as_mut().unwrap()),
-                                            0x8,
-                                        );
-                                        self.socket = None;
+                                        self.close(1002, "Expected continuation frame");
                                         return None;
                                     }
 
                                     if self.current_frame_fin {
                                         // There's only one frame in this message.
-                                        let binary = mem::replace(
+                                        let payload = mem::replace(
                                             &mut self.current_frame_payload,
                                             Vec::new(),
                                         );
-                                        let string = match String::from_utf8(binary) {
-                                            Ok(s) => s,
-                                            Err(_err) => {
-                                                // Closing connection because text wasn't UTF-8
-                                                let _ = send(
-                                                    b"1007 Invalid UTF-8 encoding",
-                                                    Write::by_ref(self.socket.as_mut().unwrap()),
-                                                    0x8,
-                                                );
-                                                self.socket = None;
-                                                return None;
-                                            }
-                                        };
-
-                                        self.messages_in_queue.push(Message::Text(string));
+                                        let compressed = self.current_frame_compressed;
+                                        if !self.queue_message(false, compressed, payload) {
+                                            return None;
+                                        }
                                     } else {
                                         // Start of a fragmented message.
                                         self.current_message_binary = false;
+                                        self.current_message_compressed =
+                                            self.current_frame_compressed;
                                         self.current_message_payload
                                             .append(&mut self.current_frame_payload);
                                     }
@@ -259,24 +415,24 @@ impl Iterator for Websocket {
                                     // If we're in the middle of a message, this frame is invalid
                                     // and we need to close.
                                     if !self.current_message_payload.is_empty() {
-                                        let _ = send(
-                                            b"1002 Expected continuation frame",
-                                            Write::by_ref(self.socket.as_mut().unwrap()),
-                                            0x8,
-                                        );
-                                        self.socket = None;
+                                        self.close(1002, "Expected continuation frame");
                                         return None;
                                     }
 
                                     if self.current_frame_fin {
-                                        let binary = mem::replace(
+                                        let payload = mem::replace(
                                             &mut self.current_frame_payload,
                                             Vec::new(),
                                         );
-                                        self.messages_in_queue.push(Message::Binary(binary));
+                                        let compressed = self.current_frame_compressed;
+                                        if !self.queue_message(true, compressed, payload) {
+                                            return None;
+                                        }
                                     } else {
                                         // Start of a fragmented message.
                                         self.current_message_binary = true;
+                                        self.current_message_compressed =
+                                            self.current_frame_compressed;
                                         self.current_message_payload
                                             .append(&mut self.current_frame_payload);
                                     }
@@ -312,12 +468,7 @@ impl Iterator for Websocket {
 
                                 // Unknown opcode means error and close.
                                 _ => {
-                                    let _ = send(
-                                        b"Unknown opcode",
-                                        Write::by_ref(self.socket.as_mut().unwrap()),
-                                        0x8,
-                                    );
-                                    self.socket = None;
+                                    self.close(1002, "Unknown opcode");
                                     return None;
                                 }
                             }
@@ -328,26 +479,27 @@ impl Iterator for Websocket {
 
                     low_level::Element::Error { desc } => {
                         // The low level layer signaled an error. Sending it to client and closing.
-                        let _ = send(
-                            desc.as_bytes(),
-                            Write::by_ref(self.socket.as_mut().unwrap()),
-                            0x8,
-                        );
-                        self.socket = None;
+                        self.close(1002, desc);
                         return None;
                     }
                 }
             }
+            self.state_machine = state_machine;
         }
     }
 }
 
 // Sends a mesage to a websocket.
 // TODO: message fragmentation?
-fn send<W: Write>(data: &[u8], mut dest: W, opcode: u8) -> io::Result<()> {
+fn send<W: Write>(data: &[u8], dest: W, opcode: u8) -> io::Result<()> {
+    send_frame(data, dest, opcode, false)
+}
+
+// Same as `send`, but `rsv1` indicates whether the data is compressed.
+fn send_frame<W: Write>(data: &[u8], mut dest: W, opcode: u8, rsv1: bool) -> io::Result<()> {
     // Write the opcode
     assert!(opcode <= 0xf);
-    let first_byte = 0x80 | opcode;
+    let first_byte = if rsv1 { 0xc0 | opcode } else { 0x80 | opcode };
     dest.write_all(&[first_byte])?;
 
     // Write the length
@@ -379,3 +531,188 @@ fn send<W: Write>(data: &[u8], mut dest: W, opcode: u8) -> io::Result<()> {
     dest.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Read;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use super::Limits;
+    use super::Message;
+    use super::Websocket;
+    #[cfg(feature = "websocket-deflate")]
+    use websocket::deflate;
+    #[cfg(feature = "websocket-deflate")]
+    use websocket::Deflate;
+    #[cfg(feature = "websocket-deflate")]
+    use Request;
+
+    struct FakeSocket {
+        input: io::Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for FakeSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Builds a websocket that reads `input`, and returns what it writes.
+    fn connect(input: Vec<u8>, limits: Limits) -> (Websocket, Arc<Mutex<Vec<u8>>>) {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let socket = FakeSocket {
+            input: io::Cursor::new(input),
+            output: output.clone(),
+        };
+        let mut websocket = Websocket::new(Box::new(socket));
+        websocket.limits = limits;
+        (websocket, output)
+    }
+
+    // Builds an unmasked frame from the client. A mask of zero leaves the payload unchanged.
+    fn frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() < 126);
+        let mut frame = vec![first_byte, 0x80 | payload.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    // Returns the status code of the close frame sent by the server.
+    fn close_code(output: &Arc<Mutex<Vec<u8>>>) -> u16 {
+        let output = output.lock().unwrap();
+        assert_eq!(output[0], 0x88);
+        (u16::from(output[2]) << 8) | u16::from(output[3])
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            message_size: Some(8),
+            frame_size: Some(6),
+            fragments: Some(3),
+        };
+
+        let mut input = frame(0x01, b"abc");
+        input.extend(frame(0x00, b"def"));
+        input.extend(frame(0x80, b"gh"));
+        let (mut websocket, _) = connect(input, limits);
+        assert_eq!(websocket.next(), Some(Message::Text("abcdefgh".to_owned())));
+
+        let (mut websocket, output) = connect(frame(0x82, b"abcdefg"), limits);
+        assert_eq!(websocket.next(), None);
+        assert_eq!(close_code(&output), 1009);
+
+        let mut input = frame(0x02, b"abcdef");
+        input.extend(frame(0x80, b"ghi"));
+        let (mut websocket, output) = connect(input, limits);
+        assert_eq!(websocket.next(), None);
+        assert_eq!(close_code(&output), 1009);
+
+        let mut input = frame(0x01, b"a");
+        input.extend(frame(0x00, b"b"));
+        input.extend(frame(0x00, b"c"));
+        input.extend(frame(0x80, b"d"));
+        let (mut websocket, output) = connect(input, limits);
+        assert_eq!(websocket.next(), None);
+        assert_eq!(close_code(&output), 1009);
+
+        // Control frames don't count.
+        let mut input = frame(0x01, b"a");
+        input.extend(frame(0x89, b""));
+        input.extend(frame(0x80, b"b"));
+        let (mut websocket, _) = connect(input, limits);
+        assert_eq!(websocket.next(), Some(Message::Text("ab".to_owned())));
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let (mut websocket, output) = connect(frame(0x81, &[0xff]), Limits::default());
+        assert_eq!(websocket.next(), None);
+        assert!(websocket.is_closed());
+        assert_eq!(close_code(&output), 1007);
+
+        let (mut websocket, output) = connect(frame(0x83, b"x"), Limits::default());
+        assert_eq!(websocket.next(), None);
+        assert_eq!(close_code(&output), 1002);
+        assert_eq!(&output.lock().unwrap()[4..], b"Unknown opcode");
+    }
+
+    #[cfg(feature = "websocket-deflate")]
+    fn negotiated() -> deflate::Negotiated {
+        let headers = vec![(
+            "Sec-WebSocket-Extensions".to_owned(),
+            "permessage-deflate".to_owned(),
+        )];
+        let request = Request::fake_http("GET", "/", headers, vec![]);
+        deflate::negotiate(&request, &Deflate::default()).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "websocket-deflate")]
+    fn compressed_messages() {
+        // A compressed "Hello", then an uncompressed "Hi".
+        let mut input = frame(0xc1, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        input.extend(frame(0x81, b"Hi"));
+        let (mut websocket, output) = connect(input, Limits::default());
+        websocket.deflate = Some(deflate::Context::new(&negotiated()));
+
+        websocket.send_text("Hello").unwrap();
+        {
+            let output = output.lock().unwrap();
+            assert_eq!(output[0], 0xc1);
+            assert_eq!(output.len(), 2 + output[1] as usize);
+            let mut client = deflate::Context::new(&negotiated());
+            let decompressed = client.decompress(output[2..].to_vec(), None);
+            assert_eq!(decompressed.unwrap().unwrap(), b"Hello");
+        }
+
+        assert_eq!(websocket.next(), Some(Message::Text("Hello".to_owned())));
+        assert_eq!(websocket.next(), Some(Message::Text("Hi".to_owned())));
+        assert_eq!(websocket.next(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "websocket-deflate")]
+    fn compressed_limits() {
+        let limits = Limits {
+            message_size: Some(4),
+            ..Limits::default()
+        };
+        let input = frame(0xc1, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        let (mut websocket, output) = connect(input, limits);
+        websocket.deflate = Some(deflate::Context::new(&negotiated()));
+        assert_eq!(websocket.next(), None);
+        assert_eq!(close_code(&output), 1009);
+    }
+
+    #[test]
+    fn compression_not_negotiated() {
+        let input = frame(0xc1, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+        let (mut websocket, output) = connect(input, Limits::default());
+        assert_eq!(websocket.next(), None);
+        assert!(websocket.is_closed());
+        assert_eq!(close_code(&output), 1002);
+
+        // A ping can never be compressed.
+        #[cfg(feature = "websocket-deflate")]
+        {
+            let (mut websocket, output) = connect(frame(0xc9, b""), Limits::default());
+            websocket.deflate = Some(deflate::Context::new(&negotiated()));
+            assert_eq!(websocket.next(), None);
+            assert_eq!(close_code(&output), 1002);
+        }
+    }
+}
@@ -0,0 +1,404 @@
+// Copyright (c) 2016 The Rouille developers
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>,
+// at your option. All files in the project carrying such
+// notice may not be copied, modified, or distributed except
+// according to those terms.
+
+//! Running a server in multiple processes that share the same port.
+//!
+//! A [`Supervisor`](struct.Supervisor.html) starts the current executable again a given number
+//! of times. In each of these worker processes, [`worker`](fn.worker.html) returns a `Worker`
+//! that builds a server bound with `SO_REUSEPORT`, so that the kernel balances the connections
+//! between the workers. This uses all the cores of the machine even if the handler contends on
+//! a lock, and a crash only takes down one worker, which the supervisor restarts.
+//!
+//! The processes don't share any memory. Each worker periodically reports the number of
+//! requests it has processed to the supervisor, which aggregates them in
+//! [`Supervisor::metrics`](struct.Supervisor.html#method.metrics).
+//!
+//! Only available on Unix platforms.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rouille::Response;
+//! use rouille::workers::{self, Supervisor};
+//!
+//! match workers::worker() {
+//!     Some(worker) => {
+//!         let server = worker.server("0.0.0.0:80", |request| {
+//!             Response::text("hello world")
+//!         }).unwrap();
+//!         server.run();
+//!     }
+//!     None => {
+//!         let supervisor = Supervisor::spawn(4).unwrap();
+//!         supervisor.run().unwrap();
+//!     }
+//! }
+//! ```
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::net::ToSocketAddrs;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::process;
+use std::process::Child;
+use std::process::Command;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use handoff;
+use socket2::SockRef;
+use Request;
+use Response;
+use Server;
+use SocketOptions;
+
+// Index of the worker, set by the supervisor.
+const WORKER: &str = "ROUILLE_WORKER";
+// File descriptor of the socket that the worker reports its metrics to.
+const METRICS_FD: &str = "ROUILLE_METRICS_FD";
+
+// Interval between two reports of a worker.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Metrics aggregated by the supervisor.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Number of requests processed by the workers.
+    pub requests: u64,
+    /// Number of responses with a `5xx` status code.
+    pub server_errors: u64,
+    /// Number of times a worker was restarted after exiting.
+    pub restarts: u64,
+}
+
+/// Returns the worker that the current process is, or `None` if it wasn't started by a
+/// `Supervisor`.
+///
+/// Only the first call returns the worker.
+pub fn worker() -> Option<Worker> {
+    let index = env::var(WORKER).ok()?.parse().ok()?;
+    env::remove_var(WORKER);
+
+    let report = env::var(METRICS_FD)
+        .ok()
+        .and_then(|fd| handoff::parse_fds(&fd).ok())
+        .map(|fd| handoff::from_inherited_fd::<UnixStream>(fd[0]));
+    env::remove_var(METRICS_FD);
+
+    Some(Worker { index, report })
+}
+
+/// One of the processes started by a `Supervisor`.
+pub struct Worker {
+    index: usize,
+    report: Option<UnixStream>,
+}
+
+impl Worker {
+    /// Returns the index of the worker, between 0 and the number of workers. A restarted worker
+    /// keeps the same index.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Builds a server that listens on `addr` with `SO_REUSEPORT`, and whose requests are
+    /// reported to the supervisor.
+    ///
+    /// The process exits if the supervisor stops receiving the reports, so that no worker
+    /// outlives its supervisor.
+    pub fn server<A, F>(
+        self,
+        addr: A,
+        handler: F,
+    ) -> Result<
+        Server<impl Fn(&Request) -> Response + Send + Sync + 'static>,
+        Box<dyn Error + Send + Sync + 'static>,
+    >
+    where
+        A: ToSocketAddrs,
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let options = SocketOptions {
+            reuse_port: true,
+            ..SocketOptions::default()
+        };
+        let listener = options.bind(addr)?;
+
+        let counters = Arc::new((AtomicU64::new(0), AtomicU64::new(0)));
+        if let Some(mut report) = self.report {
+            let counters = counters.clone();
+            thread::spawn(move || loop {
+                thread::sleep(REPORT_INTERVAL);
+                let line = format!(
+                    "{} {}\n",
+                    counters.0.load(Ordering::Relaxed),
+                    counters.1.load(Ordering::Relaxed)
+                );
+                if report.write_all(line.as_bytes()).is_err() {
+                    process::exit(1);
+                }
+            });
+        }
+
+        Server::from_listener(listener, move |request| {
+            let response = handler(request);
+            counters.0.fetch_add(1, Ordering::Relaxed);
+            if response.status_code >= 500 {
+                counters.1.fetch_add(1, Ordering::Relaxed);
+            }
+            response
+        })
+    }
+}
+
+impl fmt::Debug for Worker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Worker")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+/// Starts and supervises worker processes.
+pub struct Supervisor {
+    command: Box<dyn Fn(usize) -> Command>,
+    workers: Vec<WorkerProcess>,
+    // Metrics of the processes that have exited.
+    retired: Metrics,
+}
+
+struct WorkerProcess {
+    child: Child,
+    report: UnixStream,
+    // Incomplete line of the report.
+    buffer: Vec<u8>,
+    last: Metrics,
+}
+
+impl Supervisor {
+    /// Starts `count` workers by running the current executable again with the same
+    /// arguments.
+    pub fn spawn(count: usize) -> io::Result<Supervisor> {
+        let exe = env::current_exe()?;
+        Supervisor::spawn_command(count, move |_| {
+            let mut command = Command::new(&exe);
+            command.args(env::args_os().skip(1));
+            command
+        })
+    }
+
+    /// Same as `spawn`, but runs the command returned by `command` for the worker with the
+    /// given index.
+    pub fn spawn_command<C>(count: usize, command: C) -> io::Result<Supervisor>
+    where
+        C: Fn(usize) -> Command + 'static,
+    {
+        let mut supervisor = Supervisor {
+            command: Box::new(command),
+            workers: Vec::with_capacity(count),
+            retired: Metrics::default(),
+        };
+
+        for index in 0..count {
+            let worker = supervisor.start(index)?;
+            supervisor.workers.push(worker);
+        }
+
+        Ok(supervisor)
+    }
+
+    fn start(&self, index: usize) -> io::Result<WorkerProcess> {
+        let (report, child_report) = UnixStream::pair()?;
+        SockRef::from(&child_report).set_cloexec(false)?;
+        report.set_nonblocking(true)?;
+
+        let mut command = (self.command)(index);
+        command.env(WORKER, index.to_string());
+        command.env(METRICS_FD, child_report.as_raw_fd().to_string());
+        let child = command.spawn()?;
+
+        Ok(WorkerProcess {
+            child,
+            report,
+            buffer: Vec::new(),
+            last: Metrics::default(),
+        })
+    }
+
+    /// Collects the reports of the workers, and restarts the workers that have exited.
+    ///
+    /// Returns an error if a worker couldn't be restarted.
+    pub fn supervise(&mut self) -> io::Result<()> {
+        for index in 0..self.workers.len() {
+            self.workers[index].read_reports();
+
+            if self.workers[index].child.try_wait()?.is_some() {
+                let worker = self.start(index)?;
+                let exited = mem::replace(&mut self.workers[index], worker);
+                self.retired.requests += exited.last.requests;
+                self.retired.server_errors += exited.last.server_errors;
+                self.retired.restarts += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Supervises the workers forever.
+    ///
+    /// Only returns if a worker couldn't be restarted.
+    pub fn run(mut self) -> io::Result<()> {
+        loop {
+            self.supervise()?;
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Returns the metrics of all the workers, as of their last report.
+    pub fn metrics(&self) -> Metrics {
+        self.workers
+            .iter()
+            .fold(self.retired, |total, worker| Metrics {
+                requests: total.requests + worker.last.requests,
+                server_errors: total.server_errors + worker.last.server_errors,
+                restarts: total.restarts,
+            })
+    }
+
+    /// Kills all the workers and waits for them to exit.
+    pub fn shutdown(self) -> io::Result<()> {
+        for mut worker in self.workers {
+            // The worker may have exited already.
+            let _ = worker.child.kill();
+            worker.child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<_> = self.workers.iter().map(|w| w.child.id()).collect();
+        f.debug_struct("Supervisor")
+            .field("workers", &ids)
+            .field("metrics", &self.metrics())
+            .finish()
+    }
+}
+
+impl WorkerProcess {
+    fn read_reports(&mut self) {
+        let mut buf = [0; 1024];
+        while let Ok(num) = self.report.read(&mut buf) {
+            if num == 0 {
+                break;
+            }
+            self.buffer.extend_from_slice(&buf[..num]);
+        }
+
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            if let Some(metrics) = parse_report(&String::from_utf8_lossy(&line)) {
+                self.last = metrics;
+            }
+        }
+    }
+}
+
+// Parses a line written by a worker. The counters are the totals since the worker started.
+fn parse_report(line: &str) -> Option<Metrics> {
+    let mut counters = line.split_whitespace().map(|n| n.parse().ok());
+    Some(Metrics {
+        requests: counters.next()??,
+        server_errors: counters.next()??,
+        restarts: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_report;
+    use super::Metrics;
+    use super::Supervisor;
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    fn shell(script: &'static str) -> impl Fn(usize) -> Command {
+        move |_| {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(script);
+            command
+        }
+    }
+
+    #[test]
+    fn reports() {
+        assert_eq!(
+            parse_report("12 3\n"),
+            Some(Metrics {
+                requests: 12,
+                server_errors: 3,
+                restarts: 0
+            })
+        );
+        assert_eq!(parse_report("12\n"), None);
+        assert_eq!(parse_report("a b\n"), None);
+    }
+
+    #[test]
+    fn aggregates_metrics() {
+        let script = "printf '1 0\\n3 1\\n' >&$ROUILLE_METRICS_FD; sleep 10";
+        let mut supervisor = Supervisor::spawn_command(2, shell(script)).unwrap();
+
+        for _ in 0..100 {
+            supervisor.supervise().unwrap();
+            if supervisor.metrics().requests == 6 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        assert_eq!(
+            supervisor.metrics(),
+            Metrics {
+                requests: 6,
+                server_errors: 2,
+                restarts: 0
+            }
+        );
+        supervisor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn restarts_workers() {
+        let mut supervisor = Supervisor::spawn_command(1, shell("exit 1")).unwrap();
+
+        for _ in 0..100 {
+            supervisor.supervise().unwrap();
+            if supervisor.metrics().restarts >= 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        assert!(supervisor.metrics().restarts >= 2);
+        supervisor.shutdown().unwrap();
+    }
+}